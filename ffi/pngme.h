@@ -0,0 +1,100 @@
+#ifndef PNGME_H
+#define PNGME_H
+
+/* Generated by cbindgen from ffi/src/lib.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// What a call came to. The values are those the CLI exits with.
+typedef enum {
+  PNGME_STATUS_OK = 0,
+  PNGME_STATUS_FAILURE = 1,
+  // A null pointer, or a chunk type that isn't one.
+  PNGME_STATUS_BAD_ARGUMENT = 2,
+  PNGME_STATUS_NOT_FOUND = 3,
+  PNGME_STATUS_PARSE_ERROR = 4,
+  PNGME_STATUS_CRC_FAILURE = 5,
+} PngmeStatus;
+
+// A parsed PNG file.
+typedef struct PngmePng PngmePng;
+
+// Bytes owned by the library; free with `pngme_buffer_free`.
+typedef struct {
+  uint8_t *data;
+  uintptr_t len;
+} PngmeBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Parses the `len` bytes at `data` into `*out`. Free it with
+// `pngme_png_free`.
+//
+// # Safety
+//
+// `data` must point to `len` readable bytes and `out` to a writable
+// pointer.
+PngmeStatus pngme_parse(const uint8_t *data, uintptr_t len, PngmePng **out);
+
+// Frees a file from `pngme_parse`. Null is ignored.
+//
+// # Safety
+//
+// `png` must come from `pngme_parse` and not be used again.
+void pngme_png_free(PngmePng *png);
+
+// The number of chunks in `png`, `IEND` included.
+//
+// # Safety
+//
+// `png` must be a live file from `pngme_parse`.
+uintptr_t pngme_png_chunk_count(const PngmePng *png);
+
+// Points `*data` and `*len` at the data of the first chunk of
+// `chunk_type` (four ASCII letters), borrowed from `png`.
+//
+// # Safety
+//
+// `png` must be a live file from `pngme_parse`, `chunk_type` a
+// NUL-terminated string, and `data` and `len` writable.
+PngmeStatus pngme_png_get_chunk(const PngmePng *png,
+                                const char *chunk_type,
+                                const uint8_t **data,
+                                uintptr_t *len);
+
+// Adds a chunk of `chunk_type` holding the `len` bytes at `message` to
+// `png`, before `IEND`, as `pngme encode` stores a plain message.
+//
+// # Safety
+//
+// `png` must be a live file from `pngme_parse`, `chunk_type` a
+// NUL-terminated string and `message` point to `len` readable bytes.
+PngmeStatus pngme_png_encode_message(PngmePng *png,
+                                     const char *chunk_type,
+                                     const uint8_t *message,
+                                     uintptr_t len);
+
+// Serializes `png` into `*out`. Free it with `pngme_buffer_free`.
+//
+// # Safety
+//
+// `png` must be a live file from `pngme_parse` and `out` writable.
+PngmeStatus pngme_png_to_bytes(const PngmePng *png, PngmeBuffer *out);
+
+// Frees a buffer from `pngme_png_to_bytes`. An empty one is ignored.
+//
+// # Safety
+//
+// `buffer` must come from `pngme_png_to_bytes` and not be used again.
+void pngme_buffer_free(PngmeBuffer buffer);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* PNGME_H */
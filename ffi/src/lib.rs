@@ -0,0 +1,292 @@
+//! C bindings for pngme, so C and C++ programs (game engines, image
+//! tools) can link the crate as `libpngme_ffi` and include `pngme.h`.
+//!
+//! A file is parsed into an opaque `PngmePng`, read and changed through it
+//! and serialized back into a `PngmeBuffer`. Each function returns a
+//! `PngmeStatus`, whose values are the exit codes of the CLI. Whatever a
+//! function hands out is freed with the matching `_free` function; data
+//! borrowed from a `PngmePng` lives until it is changed or freed.
+
+use std::ffi::{c_char, CStr};
+use std::ptr;
+use std::slice;
+use std::str::FromStr;
+
+use pngme::chunk::{Chunk, ChunkError};
+use pngme::chunk_type::ChunkType;
+use pngme::png::{Png, PngError};
+
+/// What a call came to. The values are those the CLI exits with.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PngmeStatus {
+    Ok = 0,
+    Failure = 1,
+    /// A null pointer, or a chunk type that isn't one.
+    BadArgument = 2,
+    NotFound = 3,
+    ParseError = 4,
+    CrcFailure = 5,
+}
+
+impl From<PngError> for PngmeStatus {
+    fn from(err: PngError) -> Self {
+        match err {
+            PngError::InvalidHeader
+            | PngError::LimitExceeded(_)
+            | PngError::UnknownCritical { .. }
+            | PngError::Nonconforming { .. } => Self::ParseError,
+            PngError::BadChunk {
+                source: ChunkError::ChecksumError,
+                ..
+            } => Self::CrcFailure,
+            PngError::BadChunk { .. } => Self::ParseError,
+            PngError::BadChunkType(_) => Self::BadArgument,
+            PngError::ChunkNotFound(_) => Self::NotFound,
+            PngError::TooLarge => Self::Failure,
+        }
+    }
+}
+
+/// A parsed PNG file.
+pub struct PngmePng(Png);
+
+/// Bytes owned by the library; free with `pngme_buffer_free`.
+#[repr(C)]
+pub struct PngmeBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+/// Parses the `len` bytes at `data` into `*out`. Free it with
+/// `pngme_png_free`.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `out` to a writable
+/// pointer.
+#[no_mangle]
+pub unsafe extern "C" fn pngme_parse(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut PngmePng,
+) -> PngmeStatus {
+    if data.is_null() || out.is_null() {
+        return PngmeStatus::BadArgument;
+    }
+    let bytes = slice::from_raw_parts(data, len);
+    match Png::try_from(bytes) {
+        Ok(png) => {
+            *out = Box::into_raw(Box::new(PngmePng(png)));
+            PngmeStatus::Ok
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Frees a file from `pngme_parse`. Null is ignored.
+///
+/// # Safety
+///
+/// `png` must come from `pngme_parse` and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn pngme_png_free(png: *mut PngmePng) {
+    if !png.is_null() {
+        drop(Box::from_raw(png));
+    }
+}
+
+/// The number of chunks in `png`, `IEND` included.
+///
+/// # Safety
+///
+/// `png` must be a live file from `pngme_parse`.
+#[no_mangle]
+pub unsafe extern "C" fn pngme_png_chunk_count(png: *const PngmePng) -> usize {
+    png.as_ref().map_or(0, |png| png.0.chunks().len())
+}
+
+/// Points `*data` and `*len` at the data of the first chunk of
+/// `chunk_type` (four ASCII letters), borrowed from `png`.
+///
+/// # Safety
+///
+/// `png` must be a live file from `pngme_parse`, `chunk_type` a
+/// NUL-terminated string, and `data` and `len` writable.
+#[no_mangle]
+pub unsafe extern "C" fn pngme_png_get_chunk(
+    png: *const PngmePng,
+    chunk_type: *const c_char,
+    data: *mut *const u8,
+    len: *mut usize,
+) -> PngmeStatus {
+    let (Some(png), Some(chunk_type)) = (png.as_ref(), parse_chunk_type(chunk_type)) else {
+        return PngmeStatus::BadArgument;
+    };
+    if data.is_null() || len.is_null() {
+        return PngmeStatus::BadArgument;
+    }
+    let Some(chunk) = png
+        .0
+        .chunks()
+        .iter()
+        .find(|c| *c.chunk_type() == chunk_type)
+    else {
+        return PngmeStatus::NotFound;
+    };
+    *data = chunk.data().as_ptr();
+    *len = chunk.data().len();
+    PngmeStatus::Ok
+}
+
+/// Adds a chunk of `chunk_type` holding the `len` bytes at `message` to
+/// `png`, before `IEND`, as `pngme encode` stores a plain message.
+///
+/// # Safety
+///
+/// `png` must be a live file from `pngme_parse`, `chunk_type` a
+/// NUL-terminated string and `message` point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pngme_png_encode_message(
+    png: *mut PngmePng,
+    chunk_type: *const c_char,
+    message: *const u8,
+    len: usize,
+) -> PngmeStatus {
+    let (Some(png), Some(chunk_type)) = (png.as_mut(), parse_chunk_type(chunk_type)) else {
+        return PngmeStatus::BadArgument;
+    };
+    if message.is_null() && len > 0 {
+        return PngmeStatus::BadArgument;
+    }
+    let message = match len {
+        0 => &[][..],
+        _ => slice::from_raw_parts(message, len),
+    };
+    match Chunk::try_new(chunk_type, message) {
+        Ok(chunk) => {
+            png.0.append_chunk(chunk);
+            PngmeStatus::Ok
+        }
+        Err(_) => PngmeStatus::Failure,
+    }
+}
+
+/// Serializes `png` into `*out`. Free it with `pngme_buffer_free`.
+///
+/// # Safety
+///
+/// `png` must be a live file from `pngme_parse` and `out` writable.
+#[no_mangle]
+pub unsafe extern "C" fn pngme_png_to_bytes(
+    png: *const PngmePng,
+    out: *mut PngmeBuffer,
+) -> PngmeStatus {
+    let Some(png) = png.as_ref() else {
+        return PngmeStatus::BadArgument;
+    };
+    if out.is_null() {
+        return PngmeStatus::BadArgument;
+    }
+    let bytes = Box::into_raw(png.0.as_bytes().into_boxed_slice());
+    *out = PngmeBuffer {
+        data: bytes.cast(),
+        len: bytes.len(),
+    };
+    PngmeStatus::Ok
+}
+
+/// Frees a buffer from `pngme_png_to_bytes`. An empty one is ignored.
+///
+/// # Safety
+///
+/// `buffer` must come from `pngme_png_to_bytes` and not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn pngme_buffer_free(buffer: PngmeBuffer) {
+    if !buffer.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buffer.data,
+            buffer.len,
+        )));
+    }
+}
+
+unsafe fn parse_chunk_type(chunk_type: *const c_char) -> Option<ChunkType> {
+    if chunk_type.is_null() {
+        return None;
+    }
+    let chunk_type = CStr::from_ptr(chunk_type).to_str().ok()?;
+    ChunkType::from_str(chunk_type).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = ["IHDR", "IDAT", "IEND"]
+            .iter()
+            .map(|t| Chunk::new(ChunkType::from_str(t).unwrap(), &[0; 4]))
+            .collect();
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = testing_bytes();
+        unsafe {
+            let mut png = ptr::null_mut();
+            assert_eq!(
+                pngme_parse(bytes.as_ptr(), bytes.len(), &mut png),
+                PngmeStatus::Ok
+            );
+            let message = b"hidden";
+            let status =
+                pngme_png_encode_message(png, c"ruSt".as_ptr(), message.as_ptr(), message.len());
+            assert_eq!(status, PngmeStatus::Ok);
+            assert_eq!(pngme_png_chunk_count(png), 4);
+
+            let (mut data, mut len) = (ptr::null(), 0);
+            let status = pngme_png_get_chunk(png, c"ruSt".as_ptr(), &mut data, &mut len);
+            assert_eq!(status, PngmeStatus::Ok);
+            assert_eq!(slice::from_raw_parts(data, len), message);
+            let status = pngme_png_get_chunk(png, c"teSt".as_ptr(), &mut data, &mut len);
+            assert_eq!(status, PngmeStatus::NotFound);
+
+            let mut out = PngmeBuffer {
+                data: ptr::null_mut(),
+                len: 0,
+            };
+            assert_eq!(pngme_png_to_bytes(png, &mut out), PngmeStatus::Ok);
+            let written = Png::try_from(slice::from_raw_parts(out.data, out.len)).unwrap();
+            assert_eq!(written.chunks()[2].data(), message);
+            pngme_buffer_free(out);
+            pngme_png_free(png);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut bytes = testing_bytes();
+        unsafe {
+            let mut png = ptr::null_mut();
+            assert_eq!(
+                pngme_parse(b"GIF89a".as_ptr(), 6, &mut png),
+                PngmeStatus::ParseError
+            );
+            bytes[8 + 8] ^= 1;
+            assert_eq!(
+                pngme_parse(bytes.as_ptr(), bytes.len(), &mut png),
+                PngmeStatus::CrcFailure
+            );
+            assert!(png.is_null());
+            assert_eq!(
+                pngme_parse(ptr::null(), 0, &mut png),
+                PngmeStatus::BadArgument
+            );
+            let status = pngme_png_encode_message(png, c"bad!".as_ptr(), ptr::null(), 0);
+            assert_eq!(status, PngmeStatus::BadArgument);
+            pngme_png_free(png);
+        }
+    }
+}
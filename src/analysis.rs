@@ -0,0 +1,521 @@
+//! LSB steganalysis: statistical tests that tell how likely it is that the
+//! least significant bits of an image's samples carry a message.
+//!
+//! - The chi-square attack (Westfeld and Pfitzmann) checks whether the
+//!   counts of each pair of values 2k and 2k + 1 have been evened out, as
+//!   overwriting low bits with random data does. It is run on growing
+//!   prefixes of each channel, which reveals sequential embeds that only
+//!   cover part of the image.
+//! - RS analysis (Fridrich, Goljan and Du) compares how flipping low bits
+//!   changes the smoothness of small groups of samples, and estimates the
+//!   share of samples that carry a message.
+//!
+//! [`chunk_stats`] looks at chunks instead: the Shannon entropy of each
+//! one's data and how well it deflates. Encrypted or compressed data is
+//! close to random on both counts, which is expected of `IDAT` but, in an
+//! ancillary chunk that isn't compressed by design, hints at hidden data.
+
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use std::io::Write;
+use thiserror::Error;
+
+use crate::chunk_type::ChunkType;
+use crate::image::{ColorType, ImageData};
+use crate::png::Png;
+
+#[derive(Debug, Error)]
+pub enum AnalysisError {
+    #[error("Can't analyze {0}-bit grayscale images")]
+    LowBitDepth(u8),
+    #[error("Can't analyze indexed images")]
+    Indexed,
+}
+
+/// Results of every test on one channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChannelReport {
+    /// The channel's index within a pixel.
+    pub channel: usize,
+    /// Chi-square probability that the whole channel carries a message.
+    pub chi_square: f64,
+    /// The share of the channel, counted from the start, over which the
+    /// chi-square probability stays above one half.
+    pub chi_square_extent: f64,
+    /// RS estimate of the share of samples that carry a message. It is
+    /// noisy, so it can be slightly negative, and unreliable near full
+    /// embedding, where it can land well above 1.
+    pub rs: f64,
+}
+
+impl ChannelReport {
+    /// The likelihood of an embed, from 0 to 1: the geometric mean of the
+    /// chi-square evidence and the RS estimate. Either test alone misfires
+    /// on natural photos, whose histograms can pass for an embed under the
+    /// chi-square attack, so a channel only scores high when both agree.
+    pub fn score(&self) -> f64 {
+        let chi_square = self.chi_square.max(self.chi_square_extent);
+        (chi_square * self.rs.clamp(0.0, 1.0)).sqrt()
+    }
+}
+
+/// Results of every test on the color channels of an image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub channels: Vec<ChannelReport>,
+}
+
+impl Report {
+    pub fn score(&self) -> f64 {
+        self.channels
+            .iter()
+            .map(ChannelReport::score)
+            .fold(0.0, f64::max)
+    }
+}
+
+/// Names of the color channels of `color_type`, which [`analyze`] covers.
+pub fn channel_names(color_type: ColorType) -> &'static [&'static str] {
+    match color_type {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => &["gray"],
+        ColorType::Rgb | ColorType::Rgba => &["red", "green", "blue"],
+        ColorType::Indexed => &["index"],
+    }
+}
+
+/// Runs every test on each color channel of `image` (alpha is skipped, and
+/// 16-bit samples are judged by their low byte).
+pub fn analyze(image: &ImageData) -> Result<Report, AnalysisError> {
+    let header = image.header();
+    if header.color_type == ColorType::Indexed {
+        return Err(AnalysisError::Indexed);
+    }
+    if header.bit_depth < 8 {
+        return Err(AnalysisError::LowBitDepth(header.bit_depth));
+    }
+    let sample_len = header.bit_depth as usize / 8;
+    let channels = header.color_type.channels();
+    let samples = |channel: usize| -> Vec<u8> {
+        image
+            .as_bytes()
+            .chunks(sample_len)
+            .skip(channel)
+            .step_by(channels)
+            .map(|sample| sample[sample_len - 1])
+            .collect()
+    };
+    let channels = (0..channel_names(header.color_type).len())
+        .map(|channel| {
+            let samples = samples(channel);
+            let (chi_square, chi_square_extent) = chi_square(&samples);
+            ChannelReport {
+                channel,
+                chi_square,
+                chi_square_extent,
+                rs: rs(&samples),
+            }
+        })
+        .collect();
+    Ok(Report { channels })
+}
+
+/// Prefixes the chi-square test is run on, in twentieths of the channel.
+const STEPS: usize = 20;
+
+/// The chi-square probability of an embed over all of `samples`, and the
+/// share of them over which it stays above one half.
+fn chi_square(samples: &[u8]) -> (f64, f64) {
+    let mut histogram = [0u64; 256];
+    let mut extent = 0.0;
+    let mut whole = 0.0;
+    let mut embedded = true;
+    for step in 1..=STEPS {
+        let (start, end) = (
+            samples.len() * (step - 1) / STEPS,
+            samples.len() * step / STEPS,
+        );
+        for &sample in &samples[start..end] {
+            histogram[sample as usize] += 1;
+        }
+        let p = chi_square_probability(&histogram);
+        embedded &= p > 0.5;
+        if embedded {
+            extent = step as f64 / STEPS as f64;
+        }
+        whole = p;
+    }
+    (whole, extent)
+}
+
+fn chi_square_probability(histogram: &[u64; 256]) -> f64 {
+    let mut statistic = 0.0;
+    let mut categories = 0;
+    for pair in histogram.chunks(2) {
+        let expected = (pair[0] + pair[1]) as f64 / 2.0;
+        // Sparse categories make the statistic unreliable.
+        if expected < 5.0 {
+            continue;
+        }
+        statistic += (pair[0] as f64 - expected).powi(2) / expected;
+        categories += 1;
+    }
+    if categories < 2 {
+        return 0.0;
+    }
+    1.0 - regularized_gamma((categories - 1) as f64 / 2.0, statistic / 2.0)
+}
+
+/// The lower regularized gamma function P(a, x), which gives the
+/// chi-square distribution with 2a degrees of freedom at 2x.
+fn regularized_gamma(a: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let prefix = (a * x.ln() - x - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        // Series expansion.
+        let (mut term, mut sum, mut n) = (1.0 / a, 1.0 / a, a);
+        while term.abs() > sum.abs() * 1e-15 {
+            n += 1.0;
+            term *= x / n;
+            sum += term;
+        }
+        (sum * prefix).min(1.0)
+    } else {
+        // Continued fraction for Q(a, x), by the modified Lentz method.
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            d = if d.abs() < tiny { tiny } else { d };
+            c = b + an / c;
+            c = if c.abs() < tiny { tiny } else { c };
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < 1e-15 {
+                break;
+            }
+        }
+        (1.0 - prefix * h).max(0.0)
+    }
+}
+
+/// ln Γ(x) for x > 0, by the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.180_091_729_471_46,
+        -86.505_320_329_416_77,
+        24.014_098_240_830_91,
+        -1.231_739_572_450_155,
+        0.001_208_650_973_866_179,
+        -0.000_005_395_239_384_953,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000_000_000_190_015, |sum, (i, c)| {
+            sum + c / (x + 1.0 + i as f64)
+        });
+    -tmp + (2.506_628_274_631_000_5 * series / x).ln()
+}
+
+/// Samples per RS group, and the mask of those that get flipped.
+const MASK: [bool; 4] = [false, true, true, false];
+
+/// The RS estimate of the share of `samples` that carry a message.
+fn rs(samples: &[u8]) -> f64 {
+    let flipped: Vec<u8> = samples.iter().map(|s| s ^ 1).collect();
+    let (r, s, r_neg, s_neg) = rs_counts(samples);
+    let (r1, s1, r1_neg, s1_neg) = rs_counts(&flipped);
+    let (d0, d1) = (r - s, r1 - s1);
+    let (d0_neg, d1_neg) = (r_neg - s_neg, r1_neg - s1_neg);
+    let a = 2.0 * (d1 + d0);
+    let b = d0_neg - d1_neg - d1 - 3.0 * d0;
+    let c = d0 - d0_neg;
+    let z = if a.abs() < 1e-12 {
+        if b.abs() < 1e-12 {
+            return 0.0;
+        }
+        -c / b
+    } else {
+        // Near full embedding the curves barely cross; take the closest
+        // real value then.
+        let discriminant = (b * b - 4.0 * a * c).max(0.0);
+        let roots = [
+            (-b + discriminant.sqrt()) / (2.0 * a),
+            (-b - discriminant.sqrt()) / (2.0 * a),
+        ];
+        if roots[0].abs() <= roots[1].abs() {
+            roots[0]
+        } else {
+            roots[1]
+        }
+    };
+    z / (z - 0.5)
+}
+
+/// Shares of regular and singular groups under the mask, then under its
+/// negation.
+fn rs_counts(samples: &[u8]) -> (f64, f64, f64, f64) {
+    let smoothness = |group: &[i16]| -> i16 { group.windows(2).map(|w| (w[1] - w[0]).abs()).sum() };
+    let (mut r, mut s, mut r_neg, mut s_neg) = (0u64, 0u64, 0u64, 0u64);
+    let groups = samples.chunks_exact(MASK.len());
+    let count = groups.len();
+    for group in groups {
+        let group: Vec<i16> = group.iter().map(|&v| v as i16).collect();
+        let before = smoothness(&group);
+        let flip = |f: fn(i16) -> i16| -> i16 {
+            let flipped: Vec<i16> = group
+                .iter()
+                .zip(MASK)
+                .map(|(&v, masked)| if masked { f(v) } else { v })
+                .collect();
+            smoothness(&flipped)
+        };
+        match flip(|v| v ^ 1).cmp(&before) {
+            std::cmp::Ordering::Greater => r += 1,
+            std::cmp::Ordering::Less => s += 1,
+            std::cmp::Ordering::Equal => {}
+        }
+        match flip(|v| ((v + 1) ^ 1) - 1).cmp(&before) {
+            std::cmp::Ordering::Greater => r_neg += 1,
+            std::cmp::Ordering::Less => s_neg += 1,
+            std::cmp::Ordering::Equal => {}
+        }
+    }
+    let share = |n: u64| n as f64 / count.max(1) as f64;
+    (share(r), share(s), share(r_neg), share(s_neg))
+}
+
+/// Bytes of a chunk's data that [`chunk_stats`] measures, from its start.
+pub const SAMPLE_LEN: usize = 64 * 1024;
+/// Chunks shorter than this aren't flagged: too few bytes to tell.
+pub const MIN_FLAGGED_LEN: usize = 64;
+/// Ancillary chunks whose data is compressed by design.
+const COMPRESSED: &[&[u8; 4]] = &[b"iCCP", b"zTXt", b"iTXt", b"fdAT"];
+
+/// Entropy and compressibility of one chunk's data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkStats {
+    pub index: usize,
+    pub chunk_type: ChunkType,
+    pub len: usize,
+    /// Shannon entropy, in bits per byte (0 to 8).
+    pub entropy: f64,
+    /// Deflated size over size: near or above 1 for data that doesn't
+    /// compress.
+    pub deflate_ratio: f64,
+}
+
+impl ChunkStats {
+    /// Whether the data looks encrypted or compressed where it shouldn't
+    /// be: an ancillary chunk, not one compressed by design, whose entropy
+    /// is within 15% of the most its length allows and that deflate can't
+    /// shrink by 5%.
+    pub fn is_suspicious(&self) -> bool {
+        let code = self.chunk_type.bytes();
+        let most = (self.len.min(256) as f64).log2();
+        !self.chunk_type.is_critical()
+            && !COMPRESSED.contains(&&code)
+            && self.len >= MIN_FLAGGED_LEN
+            && self.entropy >= 0.85 * most
+            && self.deflate_ratio >= 0.95
+    }
+}
+
+/// Shannon entropy of `data`, in bits per byte.
+pub fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut histogram = [0u64; 256];
+    for &byte in data {
+        histogram[byte as usize] += 1;
+    }
+    let len = data.len() as f64;
+    histogram
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Size of `data` deflated at the default level over its own size.
+pub fn deflate_ratio(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 1.0;
+    }
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    let deflated = encoder
+        .write_all(data)
+        .and_then(|()| encoder.finish())
+        .expect("deflating into memory");
+    deflated.len() as f64 / data.len() as f64
+}
+
+/// Measures the `data` of chunk `index`, of type `chunk_type`, over its
+/// first [`SAMPLE_LEN`] bytes.
+pub fn measure(index: usize, chunk_type: &ChunkType, data: &[u8]) -> ChunkStats {
+    let sample = &data[..data.len().min(SAMPLE_LEN)];
+    ChunkStats {
+        index,
+        chunk_type: chunk_type.clone(),
+        len: data.len(),
+        entropy: entropy(sample),
+        deflate_ratio: deflate_ratio(sample),
+    }
+}
+
+/// Measures every chunk of `png`.
+pub fn chunk_stats(png: &Png) -> Vec<ChunkStats> {
+    png.chunks()
+        .iter()
+        .enumerate()
+        .map(|(index, chunk)| measure(index, chunk.chunk_type(), chunk.data()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Header;
+
+    /// A smooth, slightly noisy image. With `parity_bias`, its values
+    /// lean towards even numbers, like the uneven histograms of real photos
+    /// that the chi-square attack relies on.
+    fn testing_image(parity_bias: bool) -> ImageData {
+        let header = Header {
+            width: 128,
+            height: 128,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        };
+        let mut seed = 7u32;
+        let mut random = move |n: u32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (seed >> 16) % n
+        };
+        let pixels = (0..128 * 128 * 3)
+            .map(|i| {
+                let (x, y) = ((i / 3 % 128) as f64, (i / 3 / 128) as f64);
+                let smooth = 128.0 + 50.0 * (x / 11.0).sin() * (y / 17.0).cos();
+                if parity_bias {
+                    (smooth as u8 & !1) + u8::from(random(4) == 0)
+                } else {
+                    (smooth + random(4) as f64 - random(4) as f64) as u8
+                }
+            })
+            .collect();
+        ImageData::new(header, pixels).unwrap()
+    }
+
+    fn embed_random(image: &mut ImageData, share: f64) {
+        let mut seed = 99u32;
+        let len = (image.as_bytes().len() as f64 * share) as usize;
+        for byte in &mut image.as_bytes_mut()[..len] {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            *byte = (*byte & !1) | (seed >> 16) as u8 & 1;
+        }
+    }
+
+    #[test]
+    fn test_chi_square_attack() {
+        let clean = analyze(&testing_image(true)).unwrap();
+        assert_eq!(clean.channels.len(), 3);
+        for channel in &clean.channels {
+            assert!(channel.chi_square < 0.01, "{:?}", channel);
+            assert_eq!(channel.chi_square_extent, 0.0);
+        }
+        let mut image = testing_image(true);
+        embed_random(&mut image, 0.3);
+        for channel in analyze(&image).unwrap().channels {
+            assert!(channel.chi_square < 0.01, "{:?}", channel);
+            assert!((0.25..=0.35).contains(&channel.chi_square_extent));
+        }
+        embed_random(&mut image, 1.0);
+        let report = analyze(&image).unwrap();
+        for channel in &report.channels {
+            assert!(channel.chi_square > 0.9, "{:?}", channel);
+            assert_eq!(channel.chi_square_extent, 1.0);
+        }
+        assert!(report.score() > 0.9);
+    }
+
+    /// A 128x128 crop of the flower photo in the examples of the
+    /// rust-embed crate (MIT or Apache-2.0): its chi-square probability
+    /// alone passes for a full embed in two channels.
+    #[test]
+    fn test_clean_photo() {
+        let png = Png::try_from(&include_bytes!("testdata/flower.png")[..]).unwrap();
+        let mut image = ImageData::decode(&png).unwrap();
+        let clean = analyze(&image).unwrap();
+        assert!(clean
+            .channels
+            .iter()
+            .any(|channel| channel.chi_square > 0.9));
+        assert!(clean.score() < 0.5, "{:?}", clean);
+        embed_random(&mut image, 1.0);
+        assert!(analyze(&image).unwrap().score() > 0.9);
+    }
+
+    #[test]
+    fn test_rs_analysis() {
+        for share in [0.0, 0.3, 0.6] {
+            let mut image = testing_image(false);
+            embed_random(&mut image, share);
+            for channel in analyze(&image).unwrap().channels {
+                assert!((channel.rs - share).abs() < 0.15, "{} {:?}", share, channel);
+            }
+        }
+    }
+
+    #[test]
+    fn test_chi_square_distribution() {
+        // P(chi² <= 3.84 | 1 degree of freedom) = 0.95
+        assert!((regularized_gamma(0.5, 3.841 / 2.0) - 0.95).abs() < 1e-3);
+        // P(chi² <= 124.34 | 100 degrees of freedom) = 0.95
+        assert!((regularized_gamma(50.0, 124.342 / 2.0) - 0.95).abs() < 1e-3);
+        assert!((ln_gamma(5.0) - 24f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_chunk_stats() {
+        use crate::chunk::Chunk;
+        use std::str::FromStr;
+
+        let mut seed = 7u32;
+        let random: Vec<u8> = (0..1000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect();
+        let text = "The quick brown fox jumps over the lazy dog. ".repeat(20);
+        let chunk = |code: &str, data: &[u8]| Chunk::new(ChunkType::from_str(code).unwrap(), data);
+        let png = Png::from_chunks(vec![
+            chunk("IDAT", &random),
+            chunk("ruSt", &random),
+            chunk("tEXt", text.as_bytes()),
+            chunk("zTXt", &random),
+            chunk("prVt", &random[..32]),
+        ]);
+        let stats = chunk_stats(&png);
+        assert!(stats[1].entropy > 7.5 && stats[1].deflate_ratio > 1.0);
+        assert!(stats[2].entropy < 5.0 && stats[2].deflate_ratio < 0.2);
+        let suspicious: Vec<_> = stats.iter().map(ChunkStats::is_suspicious).collect();
+        assert_eq!(suspicious, [false, true, false, false, false]);
+        assert_eq!(entropy(&[]), 0.0);
+        assert_eq!(entropy(&[0, 1]), 1.0);
+    }
+}
@@ -0,0 +1,165 @@
+//! What the programmatic front ends, `serve` and `rpc`, do with an image,
+//! kept in one place so they answer alike.
+
+use anyhow::{Context, Result};
+use thiserror::Error;
+
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::crypto::Encryption;
+use pngme::envelope::{Envelope, EnvelopeError, PayloadKind};
+use pngme::index;
+use pngme::limits::Limits;
+use pngme::messages;
+use pngme::png::{Png, PngError};
+
+use crate::json::Value;
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("Missing field {0:?}")]
+    MissingField(&'static str),
+    #[error("{0}")]
+    Invalid(String),
+    #[error("Decode the payload with the pngme command: {0}")]
+    Unsupported(&'static str),
+}
+
+/// Chunks in a file from a client: millions of empty ones would cost far
+/// more memory than the file does.
+const MAX_CHUNKS: usize = 100_000;
+/// How many times the size of the largest file from a client a payload may
+/// decompress to.
+const MAX_EXPANSION: usize = 16;
+
+/// Argon2id memory, in KiB, a payload from a client may ask for: a file may
+/// ask for gigabytes, once for each connection. The default takes 19 MiB.
+const MAX_KDF_MEMORY_KIB: usize = 64 * 1024;
+/// Key derivation work, about a microsecond a unit, a payload from a client
+/// may ask for: enough for the defaults and the PBKDF2 iterations of old
+/// payloads, not for minutes of one core.
+const MAX_KDF_WORK: usize = 1_000_000;
+
+/// The limits `serve` and `rpc` read files of at most `max_len` bytes
+/// under, so a small upload can't decompress or derive a key without bound.
+pub fn limits_for(max_len: usize) -> Limits {
+    Limits {
+        max_file_len: max_len,
+        max_chunks: MAX_CHUNKS,
+        max_decompressed_len: max_len.saturating_mul(MAX_EXPANSION),
+        max_kdf_memory_kib: MAX_KDF_MEMORY_KIB,
+        max_kdf_work: MAX_KDF_WORK,
+    }
+}
+
+/// The chunks of `png` and how many bytes follow `IEND`.
+pub fn inspect(png: &Png) -> Value {
+    let chunks = png
+        .chunks()
+        .iter()
+        .map(|chunk| {
+            Value::object([
+                ("type", Value::string(chunk.chunk_type().to_string())),
+                ("length", Value::number(chunk.length())),
+                ("crc", Value::number(chunk.crc())),
+            ])
+        })
+        .collect();
+    Value::object([
+        ("chunks", Value::Array(chunks)),
+        ("trailer_length", Value::number(png.trailer().len() as u64)),
+    ])
+}
+
+/// Adds `message` to `png` in a `chunk_type` chunk, as `encode` does:
+/// under `name` if given, replacing the message of that name, and
+/// encrypted with the default settings under `password` if given.
+pub fn encode(
+    png: &mut Png,
+    chunk_type: ChunkType,
+    message: &str,
+    name: Option<&str>,
+    password: Option<&[u8]>,
+) -> Result<()> {
+    let data = match (name, password) {
+        (None, None) if !Envelope::is_envelope(message.as_bytes()) => message.as_bytes().to_vec(),
+        _ => {
+            let mut envelope = Envelope::text(message);
+            if let Some(name) = name {
+                messages::remove_named(png, name, None);
+                envelope = envelope.with_name(name);
+            }
+            if let Some(password) = password {
+                envelope = envelope.encrypt(password, &Encryption::default())?;
+            }
+            envelope.as_bytes()
+        }
+    };
+    let indexed = index::has_index(png);
+    png.append_chunk(
+        Chunk::try_new(chunk_type, &data).context("The message doesn't fit in one chunk")?,
+    );
+    if indexed {
+        index::refresh(png);
+    }
+    Ok(())
+}
+
+/// A decoded payload.
+pub enum Decoded {
+    /// A text message, or the data of a chunk that holds no envelope.
+    Text(Vec<u8>),
+    File {
+        name: String,
+        data: Vec<u8>,
+    },
+}
+
+/// The payload in the `chunk_type` chunk of `png`, or the one stored under
+/// `name`, as `decode` gives it; `password` opens protected payloads.
+pub fn decode(
+    png: &Png,
+    chunk_type: Option<&ChunkType>,
+    name: Option<&str>,
+    password: Option<&[u8]>,
+) -> Result<Decoded> {
+    let envelope = match (name, chunk_type) {
+        (Some(name), chunk_type) => messages::find_named(png, name, chunk_type)?.1,
+        (None, Some(chunk_type)) => {
+            let chunk = png
+                .chunk_by_type(&chunk_type.to_string())
+                .ok_or_else(|| PngError::ChunkNotFound(chunk_type.to_string()))?;
+            if !Envelope::is_envelope(chunk.data()) {
+                return Ok(Decoded::Text(chunk.data().to_vec()));
+            }
+            Envelope::try_from(chunk.data())?
+        }
+        (None, None) => return Err(ApiError::MissingField("chunk_type").into()),
+    };
+    let password = || password.ok_or(EnvelopeError::PasswordRequired);
+    let envelope = if envelope.has_integrity_tag() {
+        envelope.verify(password()?)?;
+        envelope
+    } else if envelope.is_encrypted_to_recipients() {
+        return Err(ApiError::Unsupported("it is encrypted to public keys").into());
+    } else if envelope.is_encrypted() {
+        envelope.decrypt(password()?)?
+    } else {
+        envelope
+    };
+    match envelope.kind() {
+        PayloadKind::Text => Ok(Decoded::Text(envelope.payload().to_vec())),
+        PayloadKind::File => {
+            let meta = envelope.file_meta().expect("file envelopes carry metadata");
+            Ok(Decoded::File {
+                name: meta.name.clone(),
+                data: envelope.payload().to_vec(),
+            })
+        }
+        PayloadKind::Encrypted => Err(EnvelopeError::PasswordRequired.into()),
+        PayloadKind::Log => Err(ApiError::Unsupported("it is a log").into()),
+        PayloadKind::Share | PayloadKind::Part => {
+            Err(ApiError::Unsupported("it is split across several files").into())
+        }
+    }
+}
@@ -0,0 +1,996 @@
+use std::path::PathBuf;
+
+use clap::{value_parser, ArgGroup, Args, Parser, Subcommand};
+
+use pngme::compression::Compression;
+use pngme::crypto::{Cipher, Kdf};
+use pngme::decoder::CriticalPolicy;
+use pngme::fec::Fec;
+use pngme::generate::{Size, Style};
+use pngme::jpeg::Segment;
+use pngme::lint::{Profile, Rule};
+use pngme::lsb::{self, Strategy};
+use pngme::perceptual::{Method, PerceptualHash};
+use pngme::report::Format;
+
+use crate::budget::GrowthBudget;
+use crate::git_filter::Text;
+#[cfg(feature = "keychain")]
+use crate::keychain::KeychainArgs;
+use crate::keys::IdentityArgs;
+use crate::password::PasswordArgs;
+#[cfg(feature = "openpgp")]
+use crate::pgp::{PgpDecodeArgs, PgpEncodeArgs};
+
+const EXIT_CODES_HELP: &str = "\
+Exit codes:
+  0  success
+  1  other failure
+  2  bad arguments
+  3  chunk or payload not found
+  4  malformed PNG
+  5  CRC check failed
+  6  IO error
+  7  password or identity required, wrong key or tampered payload";
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "pngme",
+    version,
+    about = "Hide secret messages in PNG files",
+    after_help = EXIT_CODES_HELP
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: PngMeArgs,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PngMeArgs {
+    /// Encode a message into a PNG file
+    Encode(EncodeArgs),
+    /// Decode a message stored in a PNG file
+    Decode(DecodeArgs),
+    /// Remove a chunk from a PNG file
+    Remove(RemoveArgs),
+    /// Decrypt an encrypted payload and encrypt it again under a new
+    /// password or new recipients, rewriting the file atomically
+    Reencrypt(ReencryptArgs),
+    /// Print every chunk of a PNG file
+    Print(PrintArgs),
+    /// Extract raw chunk data from one or more PNG files
+    Extract(ExtractArgs),
+    /// Hash only the image-defining chunks (IHDR, PLTE, IDAT, tRNS)
+    Hash(HashArgs),
+    /// List, rebuild or drop the payload index of a PNG file
+    Index(IndexArgs),
+    /// Split a payload into shares hidden in several PNG files, any
+    /// THRESHOLD of which recover it
+    Split(SplitArgs),
+    /// Recover a payload split with `split` from enough of its carriers
+    Reassemble(ReassembleArgs),
+    /// Hide a payload in a chunk whose type and position are derived from a
+    /// password, so it can't be told apart without it
+    Hide(HideArgs),
+    /// Recover a payload hidden with `hide`
+    Reveal(RevealArgs),
+    /// Embed a payload in the least significant bits of the pixels, where
+    /// it survives tools that strip chunks
+    Embed(EmbedArgs),
+    /// Recover a payload embedded with `embed`
+    Recover(RecoverArgs),
+    /// Store a payload after the IEND chunk, where tools that strip unknown
+    /// chunks often leave it (read it back with decode --trailer)
+    Attach(AttachArgs),
+    /// Append a ZIP archive after the IEND chunk, so the file is both a PNG
+    /// and a ZIP archive that unzip extracts
+    Polyglot(PolyglotArgs),
+    /// Report what PNG files hide: pngme payloads, data after IEND and
+    /// PNG/ZIP polyglots
+    Scan(ScanArgs),
+    /// Find the PNG files under directories that carry pngme payloads, with
+    /// their sizes and whether they are encrypted, reading only the chunks
+    /// that hold them
+    Detect(DetectArgs),
+    /// Count the chunk types of PNG files across directory trees, with the
+    /// files, chunks and bytes of each and example files, registered types
+    /// first
+    Census(CensusArgs),
+    /// Find near-duplicate images across directory trees by perceptual
+    /// hashes of their pixels, which survive re-encoding and slight resizing
+    Similar(SimilarArgs),
+    /// Find PNG files in raw data such as disk images and memory dumps,
+    /// report where they are and whether they are whole, and extract them
+    Carve(CarveArgs),
+    /// Check the CRC of every chunk of PNG files, or of the PNG files under
+    /// directories, streaming them rather than loading them
+    Verify(VerifyArgs),
+    /// Write a JSON manifest of the content hash, chunks and payloads of
+    /// PNG files, optionally signed, for verify --manifest
+    Manifest(ManifestArgs),
+    /// Check PNG files against the specification, as pngcheck does, and
+    /// report each problem under a stable lint ID
+    Lint(LintArgs),
+    /// Compare two PNG files chunk by chunk, or with --pixels by their
+    /// decoded pixels, failing if they differ
+    Diff(DiffArgs),
+    /// Put the chunks of a PNG file in a canonical order and drop exact
+    /// duplicates, leaving the image data as it is
+    Normalize(NormalizeArgs),
+    /// Rebuild a PNG file whose chunk lengths are corrupt, finding where
+    /// chunks really start by their CRCs and skipping what can't be saved
+    Repair(RepairArgs),
+    /// Remove chunks of some types from PNG files, rewriting only the files
+    /// that had any
+    Strip(StripArgs),
+    /// Drop exact copies of chunks PNG files may hold only once, and of
+    /// text chunks, reporting how many bytes each file saves
+    Dedupe(DedupeArgs),
+    /// Rebuild a PNG file from untrusted sources from its decoded pixels,
+    /// keeping only the chunks needed to show it (and any --keep types)
+    Sanitize(SanitizeArgs),
+    /// Run as a Git clean or smudge filter from standard input to standard
+    /// output, so repositories store PNG files without volatile metadata
+    GitFilter(GitFilterArgs),
+    /// Report how much a PNG file can hold in chunks and in its pixels, and
+    /// whether a given payload fits
+    Capacity(CapacityArgs),
+    /// Run LSB steganalysis on the pixels of PNG files and score how likely
+    /// each is to hide an embedded payload, or with --chunks measure the
+    /// entropy of each chunk
+    Analyze(AnalyzeArgs),
+    /// List every timestamp PNG files carry, from tIME, text chunks, EXIF,
+    /// XMP and envelopes, earliest first
+    Timeline(TimelineArgs),
+    /// Write a self-contained forensic report on a PNG file, in HTML or
+    /// Markdown: chunk map, findings, metadata, entropy and hex previews
+    Report(ReportArgs),
+    /// Mark the pixels with a watermark derived from a key, which survives
+    /// re-encoding, stripped chunks and cropping
+    Watermark(WatermarkArgs),
+    /// Check whether a PNG file carries the watermark of a key
+    VerifyWatermark(VerifyWatermarkArgs),
+    /// Generate a synthetic cover image to carry a payload
+    Generate(GenerateArgs),
+    /// Generate an age identity for encode --recipient
+    Keygen(KeygenArgs),
+    /// Store or delete passwords and identities in the OS keychain
+    #[cfg(feature = "keychain")]
+    Keychain(KeychainArgs),
+    /// Mount the ZIP archive of a PNG/ZIP polyglot as a read-only file
+    /// system, until unmounted or interrupted
+    #[cfg(all(feature = "fuse", target_os = "linux"))]
+    Mount(MountArgs),
+    /// Serve inspect, encode, decode and strip as an HTTP API, taking
+    /// images in multipart/form-data forms
+    #[cfg(feature = "serve")]
+    Serve(ServeArgs),
+    /// Answer JSON-RPC 2.0 requests to inspect, encode, decode and validate
+    /// images, one per line of standard input, for editors and other tools
+    Rpc(RpcArgs),
+    /// Keep summaries of the files print --daemon and verify --daemon ask
+    /// about, answering again from memory until the files change
+    #[cfg(all(feature = "daemon", unix))]
+    Daemon(DaemonArgs),
+}
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("password_protection").args(["encrypt", "hmac"])))]
+pub struct EncodeArgs {
+    pub file_path: PathBuf,
+    pub chunk_type: String,
+    #[arg(required_unless_present = "payload_file")]
+    pub message: Option<String>,
+    /// Embed this file (name, size and modification time are preserved)
+    /// instead of a text message
+    #[arg(long = "file", value_name = "PATH", conflicts_with = "message")]
+    pub payload_file: Option<PathBuf>,
+    /// Label the payload so several can be stored in one file; an existing
+    /// payload with the same name is replaced
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Compress the payload: deflate, zstd or brotli, with an optional level
+    /// (e.g. zstd:19)
+    #[arg(long, value_name = "ALGO[:LEVEL]")]
+    pub compress: Option<Compression>,
+    /// Store a BLAKE3 checksum of the payload, which decode verifies
+    /// independently of chunk CRCs
+    #[arg(long)]
+    pub checksum: bool,
+    /// Add Reed–Solomon error correction with this many parity bytes per
+    /// 255-byte block (an even number up to 128), so decode repairs up to
+    /// half as many damaged bytes per block
+    #[arg(long, value_name = "PARITY")]
+    pub fec: Option<Fec>,
+    /// Encrypt the payload with a password-derived key
+    #[arg(long)]
+    pub encrypt: bool,
+    /// Add an HMAC-SHA256 tag keyed by a password, so decode detects any
+    /// change to the payload (which is not encrypted)
+    #[arg(long, conflicts_with_all = ["encrypt", "recipients"])]
+    pub hmac: bool,
+    /// Also encrypt this innocuous message under a second password: that
+    /// password only ever reveals the decoy, and nothing shows whether the
+    /// payload holds one
+    #[arg(
+        long,
+        value_name = "MESSAGE",
+        requires = "encrypt",
+        conflicts_with = "append"
+    )]
+    pub decoy: Option<String>,
+    /// Use this file as the decoy instead of a message
+    #[arg(
+        long,
+        value_name = "PATH",
+        requires = "encrypt",
+        conflicts_with_all = ["decoy", "append"]
+    )]
+    pub decoy_file: Option<PathBuf>,
+    /// Read the decoy password from the first line of this file (default:
+    /// $PNGME_DECOY_PASSWORD or a prompt)
+    #[arg(long, value_name = "PATH")]
+    pub decoy_password_file: Option<PathBuf>,
+    /// Encrypt in independent 64 KiB frames, so that decode --salvage can
+    /// recover the rest of a large payload when part of it is damaged
+    #[arg(long, requires = "encrypt", conflicts_with_all = ["decoy", "decoy_file"])]
+    pub stream: bool,
+    /// Cipher used by --encrypt
+    #[arg(long, default_value = "aes-256-gcm", requires = "encrypt")]
+    pub cipher: Cipher,
+    /// Argon2id memory cost used by --encrypt and --hmac, in MiB
+    #[arg(
+        long,
+        value_name = "MIB",
+        default_value_t = Kdf::DEFAULT_MEMORY_KIB / 1024,
+        value_parser = value_parser!(u32).range(1..=(Kdf::MAX_MEMORY_KIB / 1024) as i64),
+        requires = "password_protection"
+    )]
+    pub kdf_memory: u32,
+    /// Argon2id time cost (passes over memory) used by --encrypt and --hmac
+    #[arg(
+        long,
+        value_name = "PASSES",
+        default_value_t = Kdf::DEFAULT_ITERATIONS,
+        value_parser = value_parser!(u32).range(1..=Kdf::MAX_ITERATIONS as i64),
+        requires = "password_protection"
+    )]
+    pub kdf_time: u32,
+    /// Argon2id parallelism (lanes) used by --encrypt and --hmac
+    #[arg(
+        long,
+        value_name = "LANES",
+        default_value_t = Kdf::DEFAULT_PARALLELISM,
+        value_parser = value_parser!(u32).range(1..=Kdf::MAX_PARALLELISM as i64),
+        requires = "password_protection"
+    )]
+    pub kdf_parallelism: u32,
+    #[command(flatten)]
+    pub password: PasswordArgs,
+    /// Encrypt the payload to this age public key (age1...) instead of a
+    /// password; repeat for several recipients
+    #[arg(
+        short = 'r',
+        long = "recipient",
+        value_name = "PUBKEY",
+        conflicts_with = "encrypt"
+    )]
+    pub recipients: Vec<age::x25519::Recipient>,
+    #[command(flatten)]
+    pub identity: IdentityArgs,
+    #[cfg(feature = "openpgp")]
+    #[command(flatten)]
+    pub pgp: PgpEncodeArgs,
+    /// Add MESSAGE as a new timestamped entry to the existing payload (the
+    /// one named --name, or the first CHUNK_TYPE chunk) instead of replacing it
+    #[arg(long, conflicts_with = "payload_file")]
+    pub append: bool,
+    /// Add a payload index chunk for fast lookups by name (an existing index
+    /// is always kept up to date)
+    #[arg(long)]
+    pub index: bool,
+    /// Stripe the payload across FILE_PATH and these PNG files (repeatable),
+    /// one part in each, for payloads too large for one carrier
+    #[arg(long, value_name = "PNG", conflicts_with_all = ["append", "output"])]
+    pub span: Vec<PathBuf>,
+    /// Write the result here instead of overwriting the input file
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Fail if the file would grow by more than this many bytes (e.g. 4096,
+    /// 16K, 2M) or this percentage of its original size (e.g. 5%)
+    #[arg(long, value_name = "SIZE")]
+    pub max_growth: Option<GrowthBudget>,
+    /// Compress, encrypt (in frames, as with --stream) and write the --file
+    /// payload as it is read, in chunks of at most this many MiB, so files
+    /// larger than memory can be embedded
+    #[arg(
+        long,
+        value_name = "MIB",
+        value_parser = value_parser!(u32).range(1..=2047),
+        requires = "payload_file",
+        conflicts_with_all = [
+            "checksum", "fec", "hmac", "decoy", "decoy_file", "recipients", "index", "span",
+            "max_growth"
+        ]
+    )]
+    pub part_size: Option<u32>,
+    /// In a JPEG file, the segments to store the payload in: app11, or com
+    /// for comments
+    #[arg(long, value_name = "SEGMENT", default_value = "app11")]
+    pub jpeg_segment: Segment,
+}
+
+#[derive(Debug, Args)]
+pub struct DecodeArgs {
+    pub file_path: PathBuf,
+    #[arg(required_unless_present_any = ["name", "trailer"])]
+    pub chunk_type: Option<String>,
+    /// Decode the payload with this name (optionally only searching chunks
+    /// of CHUNK_TYPE)
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Write the payload to this path instead of printing it; if it is a
+    /// directory, an embedded file is restored inside it under its original name
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Other files of a payload striped with encode --span (repeatable, in
+    /// any order)
+    #[arg(long, value_name = "PNG")]
+    pub span: Vec<PathBuf>,
+    /// Decode the payload stored after IEND with `attach`
+    #[arg(long, conflicts_with_all = ["chunk_type", "name", "span"])]
+    pub trailer: bool,
+    /// Decode what is intact of a payload encrypted with encode --stream,
+    /// zero-filling damaged frames instead of failing
+    #[arg(long)]
+    pub salvage: bool,
+    /// Only verify that the payload is intact (CRCs, error correction,
+    /// decryption and checksum) without writing it out
+    #[arg(long, conflicts_with_all = ["output", "salvage"])]
+    pub check_only: bool,
+    #[command(flatten)]
+    pub password: PasswordArgs,
+    #[command(flatten)]
+    pub identity: IdentityArgs,
+    #[cfg(feature = "openpgp")]
+    #[command(flatten)]
+    pub pgp: PgpDecodeArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct RemoveArgs {
+    pub file_path: PathBuf,
+    #[arg(required_unless_present_any = ["name", "trailer"])]
+    pub chunk_type: Option<String>,
+    /// Remove the payload with this name (optionally only searching chunks
+    /// of CHUNK_TYPE)
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Remove the payload stored after IEND with `attach`
+    #[arg(long, conflicts_with_all = ["chunk_type", "name"])]
+    pub trailer: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ReencryptArgs {
+    pub file_path: PathBuf,
+    #[arg(required_unless_present = "name")]
+    pub chunk_type: Option<String>,
+    /// Re-encrypt the payload with this name (optionally only searching
+    /// chunks of CHUNK_TYPE)
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Read the new password from the first line of this file (default:
+    /// $PNGME_NEW_PASSWORD or a prompt)
+    #[arg(long, value_name = "PATH")]
+    pub new_password_file: Option<PathBuf>,
+    /// Encrypt to this age public key (age1...) instead of a new password;
+    /// repeat for several recipients
+    #[arg(short = 'r', long = "recipient", value_name = "PUBKEY")]
+    pub recipients: Vec<age::x25519::Recipient>,
+    /// Switch to this cipher (default: keep the payload's cipher)
+    #[arg(long, conflicts_with = "recipients")]
+    pub cipher: Option<Cipher>,
+    /// The current password
+    #[command(flatten)]
+    pub password: PasswordArgs,
+    /// The current identity, for payloads encrypted to public keys
+    #[command(flatten)]
+    pub identity: IdentityArgs,
+    /// Write the result here instead of overwriting the input file
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct PrintArgs {
+    pub file_path: PathBuf,
+    /// What to do with critical chunks this tool doesn't know: error, keep
+    /// them, or quarantine them (listed apart from the image's chunks)
+    #[arg(long, value_name = "POLICY", default_value = "keep")]
+    pub unknown_critical: CriticalPolicy,
+    /// Ask the running daemon (at $PNGME_SOCKET or its default socket)
+    #[cfg(all(feature = "daemon", unix))]
+    #[arg(long)]
+    pub daemon: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ExtractArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Only extract chunks of this type (default: every chunk)
+    #[arg(short = 't', long)]
+    pub chunk_type: Option<String>,
+    /// Output filename template; placeholders: {dir}, {file}, {stem}, {ext},
+    /// {chunk}, {index} (match number within the file) and {n} (match number
+    /// across the whole batch). Use {{ and }} for literal braces.
+    #[arg(short, long, default_value = "{stem}_{chunk}_{index}.bin")]
+    pub output: String,
+    /// Overwrite existing output files
+    #[arg(short, long)]
+    pub force: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct HashArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Print a perceptual hash of the pixels instead, dct (pHash) or
+    /// difference (dHash), to compare with `similar`
+    #[arg(long, value_name = "METHOD")]
+    pub perceptual: Option<Method>,
+}
+
+#[derive(Debug, Args)]
+pub struct IndexArgs {
+    pub file_path: PathBuf,
+    /// Write a fresh index chunk to the file
+    #[arg(long, conflicts_with = "drop")]
+    pub rebuild: bool,
+    /// Remove the index chunk from the file
+    #[arg(long)]
+    pub drop: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SplitArgs {
+    pub chunk_type: String,
+    /// PNG files to hide one share in each (modified in place)
+    #[arg(required = true, num_args = 2..=255)]
+    pub carriers: Vec<PathBuf>,
+    /// The message to split
+    #[arg(short, long, required_unless_present = "payload_file")]
+    pub message: Option<String>,
+    /// Split this file (name, size and modification time are preserved)
+    /// instead of a text message
+    #[arg(long = "file", value_name = "PATH", conflicts_with = "message")]
+    pub payload_file: Option<PathBuf>,
+    /// Number of carriers needed to recover the payload
+    #[arg(
+        short = 'k',
+        long,
+        value_name = "K",
+        value_parser = value_parser!(u8).range(2..)
+    )]
+    pub threshold: u8,
+    /// Label the shares so several payloads can be stored in the carriers;
+    /// existing payloads with the same name are replaced
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Add a payload index chunk to every carrier (an existing index is
+    /// always kept up to date)
+    #[arg(long)]
+    pub index: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ReassembleArgs {
+    /// Carriers to collect shares from, in any order
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Only look at chunks of this type
+    #[arg(short = 't', long)]
+    pub chunk_type: Option<String>,
+    /// Only use shares of the payload with this name
+    #[arg(long)]
+    pub name: Option<String>,
+    /// Write the payload to this path instead of printing it; if it is a
+    /// directory, an embedded file is restored inside it under its original name
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct HideArgs {
+    pub file_path: PathBuf,
+    #[arg(required_unless_present = "payload_file")]
+    pub message: Option<String>,
+    /// Hide this file (name, size and modification time are preserved)
+    /// instead of a text message
+    #[arg(long = "file", value_name = "PATH", conflicts_with = "message")]
+    pub payload_file: Option<PathBuf>,
+    /// Compress the payload: deflate, zstd or brotli, with an optional level
+    /// (e.g. zstd:19)
+    #[arg(long, value_name = "ALGO[:LEVEL]")]
+    pub compress: Option<Compression>,
+    #[command(flatten)]
+    pub password: PasswordArgs,
+    /// Write the result here instead of overwriting the input file
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct RevealArgs {
+    pub file_path: PathBuf,
+    /// Write the payload to this path instead of printing it; if it is a
+    /// directory, an embedded file is restored inside it under its original name
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    #[command(flatten)]
+    pub password: PasswordArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct EmbedArgs {
+    pub file_path: PathBuf,
+    #[arg(required_unless_present = "payload_file")]
+    pub message: Option<String>,
+    /// Embed this file (name, size and modification time are preserved)
+    /// instead of a text message
+    #[arg(long = "file", value_name = "PATH", conflicts_with = "message")]
+    pub payload_file: Option<PathBuf>,
+    /// Compress the payload: deflate, zstd or brotli, with an optional level
+    /// (e.g. zstd:19)
+    #[arg(long, value_name = "ALGO[:LEVEL]")]
+    pub compress: Option<Compression>,
+    /// Low bits of each sample (or palette rank) to use: more hold more but
+    /// show more
+    #[arg(long, default_value_t = 1, value_parser = value_parser!(u8).range(1..=lsb::MAX_BITS as i64))]
+    pub bits: u8,
+    /// Which samples to change: every color sample (color), only alpha
+    /// samples (alpha), or only the color of fully transparent pixels
+    /// (transparent)
+    #[arg(long, default_value = "color")]
+    pub strategy: Strategy,
+    /// Encrypt the payload with a password-derived key
+    #[arg(long)]
+    pub encrypt: bool,
+    /// Scatter the bits over the image in an order derived from the
+    /// password, so they can't be found without it
+    #[arg(long)]
+    pub scatter: bool,
+    #[command(flatten)]
+    pub password: PasswordArgs,
+    /// Write the result here instead of overwriting the input file
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct RecoverArgs {
+    pub file_path: PathBuf,
+    /// Write the payload to this path instead of printing it; if it is a
+    /// directory, an embedded file is restored inside it under its original name
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// The payload was embedded with --scatter
+    #[arg(long)]
+    pub scatter: bool,
+    #[command(flatten)]
+    pub password: PasswordArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct AttachArgs {
+    pub file_path: PathBuf,
+    #[arg(required_unless_present = "payload_file")]
+    pub message: Option<String>,
+    /// Attach this file (name, size and modification time are preserved)
+    /// instead of a text message
+    #[arg(long = "file", value_name = "PATH", conflicts_with = "message")]
+    pub payload_file: Option<PathBuf>,
+    /// Compress the payload: deflate, zstd or brotli, with an optional level
+    /// (e.g. zstd:19)
+    #[arg(long, value_name = "ALGO[:LEVEL]")]
+    pub compress: Option<Compression>,
+    /// Encrypt the payload with a password-derived key
+    #[arg(long)]
+    pub encrypt: bool,
+    #[command(flatten)]
+    pub password: PasswordArgs,
+    /// Write the result here instead of overwriting the input file
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct PolyglotArgs {
+    pub file_path: PathBuf,
+    /// The ZIP archive to append
+    pub archive: PathBuf,
+    /// Write the result here instead of overwriting the input file
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+#[derive(Debug, Args)]
+pub struct MountArgs {
+    pub file_path: PathBuf,
+    /// An existing directory to mount the archive on
+    pub mountpoint: PathBuf,
+}
+
+#[cfg(feature = "serve")]
+#[derive(Debug, Args)]
+pub struct ServeArgs {
+    /// Address and port to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: std::net::SocketAddr,
+    /// Read the API key clients must send from the first line of this file
+    /// (default: $PNGME_API_KEY)
+    #[arg(long, value_name = "FILE")]
+    pub api_key_file: Option<PathBuf>,
+    /// Serve without an API key, e.g. behind a proxy that authenticates
+    #[arg(long, conflicts_with = "api_key_file")]
+    pub no_auth: bool,
+    /// Largest request accepted, in MiB
+    #[arg(long, value_name = "MIB", default_value_t = 16, value_parser = value_parser!(u32).range(1..=1024))]
+    pub max_upload: u32,
+}
+
+#[cfg(all(feature = "daemon", unix))]
+#[derive(Debug, Args)]
+pub struct DaemonArgs {
+    /// Socket to listen on (default: $PNGME_SOCKET, or pngme.sock in
+    /// $XDG_RUNTIME_DIR or a private directory of the temporary directory)
+    #[arg(long, value_name = "PATH")]
+    pub socket: Option<PathBuf>,
+    /// Files to keep summaries of, dropping the least recently asked about
+    #[arg(long, default_value_t = 1024, value_parser = value_parser!(u32).range(1..))]
+    pub capacity: u32,
+}
+
+#[derive(Debug, Args)]
+pub struct RpcArgs {
+    /// Largest image accepted, in MiB, whether read from a path or sent
+    #[arg(long, value_name = "MIB", default_value_t = 16, value_parser = value_parser!(u32).range(1..=1024))]
+    pub max_image: u32,
+}
+
+#[derive(Debug, Args)]
+pub struct ScanArgs {
+    /// Files, or (built with the s3 feature) s3://BUCKET/PREFIX and
+    /// gs://BUCKET/PREFIX, whose objects ending in .png are scanned
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Map the files into memory instead of reading them, for files too
+    /// large to load (only map files nothing else is writing to)
+    #[cfg(all(feature = "mmap", unix))]
+    #[arg(long)]
+    pub mmap: bool,
+    /// Read the files in batches through io_uring, with a few system calls
+    /// per batch rather than several per file
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    #[arg(long)]
+    pub io_uring: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DetectArgs {
+    /// Files, or directories to search for files ending in .png
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    /// Search this many files at a time (default: one per core)
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub threads: Option<u16>,
+}
+
+#[derive(Debug, Args)]
+pub struct CensusArgs {
+    /// Files, or directories to search for files ending in .png
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    /// List this many example files per chunk type
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    pub examples: usize,
+    /// Read this many files at a time (default: one per core)
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub threads: Option<u16>,
+}
+
+#[derive(Debug, Args)]
+pub struct SimilarArgs {
+    /// Files, or directories to search for files ending in .png
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    /// The perceptual hash: dct (pHash) or difference (dHash)
+    #[arg(long, default_value = "dct")]
+    pub method: Method,
+    /// Report pairs whose hashes differ in at most this many of 64 bits
+    #[arg(long, value_name = "BITS", default_value_t = PerceptualHash::SIMILAR)]
+    pub max_distance: u32,
+    /// Decode this many files at a time (default: one per core)
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub threads: Option<u16>,
+}
+
+#[derive(Debug, Args)]
+pub struct CarveArgs {
+    /// The data to search
+    pub blob: PathBuf,
+    /// Write each PNG found, damaged or cut-off ones too, to this
+    /// directory, named after its offset in hex
+    #[arg(short, long, value_name = "DIR")]
+    pub output: Option<PathBuf>,
+    /// Map the data into memory instead of reading it, for blobs too large
+    /// to load (only map files nothing else is writing to)
+    #[cfg(all(feature = "mmap", unix))]
+    #[arg(long)]
+    pub mmap: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyArgs {
+    /// Files, directories to search for files ending in .png, or (built
+    /// with the s3 feature) s3://BUCKET/PREFIX and gs://BUCKET/PREFIX
+    #[arg(required_unless_present = "manifest")]
+    pub paths: Vec<PathBuf>,
+    /// Check the files a JSON manifest lists against the content hash,
+    /// chunks and payloads it gives for each, rather than CRCs under PATHS
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["paths", "threads"])]
+    pub manifest: Option<PathBuf>,
+    /// Require the manifest to be signed by the OpenPGP certificate in this
+    /// key file (repeatable: any one of them is enough)
+    #[cfg(feature = "openpgp")]
+    #[arg(long = "manifest-signer", value_name = "CERT", requires = "manifest")]
+    pub manifest_signers: Vec<PathBuf>,
+    /// Verify this many files at a time (default: one per core)
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    pub threads: Option<u16>,
+    /// What to do with critical chunks this tool doesn't know: error fails
+    /// the file, keep and quarantine count them
+    #[arg(long, value_name = "POLICY", default_value = "keep")]
+    pub unknown_critical: CriticalPolicy,
+    /// Ask the running daemon (at $PNGME_SOCKET or its default socket) about
+    /// files and directories
+    #[cfg(all(feature = "daemon", unix))]
+    #[arg(long, conflicts_with_all = ["threads", "manifest"])]
+    pub daemon: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct ManifestArgs {
+    /// Files, or directories to search for files ending in .png
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    /// Write the manifest to this file rather than to standard output;
+    /// paths in it are relative to the file's directory
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Sign the manifest with the OpenPGP secret key in this key file
+    #[cfg(feature = "openpgp")]
+    #[arg(long, value_name = "KEY")]
+    pub sign: Option<PathBuf>,
+    #[cfg(feature = "openpgp")]
+    #[command(flatten)]
+    pub password: PasswordArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct DiffArgs {
+    pub first: PathBuf,
+    pub second: PathBuf,
+    /// Compare the images, decoded to RGBA, rather than the chunks: files
+    /// that only differ in metadata or encoding show the same pixels
+    #[arg(long)]
+    pub pixels: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct LintArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Don't report this lint, by ID, e.g. P013 for data after IEND
+    /// (repeatable)
+    #[arg(long = "allow", value_name = "ID")]
+    pub allowed: Vec<Rule>,
+    /// The specification to check against: 1.2, third (the third edition,
+    /// with eXIf, cICP and APNG) or apng (1.2 and APNG, animation required)
+    #[arg(long, default_value = "third")]
+    pub profile: Profile,
+    /// Fail on warnings too
+    #[arg(long)]
+    pub strict: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct NormalizeArgs {
+    pub file_path: PathBuf,
+    /// Write the result here instead of overwriting the input file
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Report what would change without writing anything
+    #[arg(long, conflicts_with = "output")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct SanitizeArgs {
+    pub file_path: PathBuf,
+    /// Write the result here instead of overwriting the input file
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Also keep the chunks of this type, e.g. gAMA or iCCP (repeatable)
+    #[arg(long = "keep", value_name = "CHUNK_TYPE")]
+    pub keep: Vec<String>,
+    /// Report what would be dropped without writing anything
+    #[arg(long, conflicts_with = "output")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RepairArgs {
+    pub file_path: PathBuf,
+    /// Write the result here instead of overwriting the input file
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Also fix chunks that fail their CRC by one or two flipped bits,
+    /// when only one such fix matches, and list the candidates otherwise
+    #[arg(long)]
+    pub bit_flips: bool,
+    /// Report what would be repaired without writing anything
+    #[arg(long, conflicts_with = "output")]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct StripArgs {
+    /// Files, directories to search for files ending in .png, or (built
+    /// with the s3 feature) s3://BUCKET/PREFIX and gs://BUCKET/PREFIX
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    /// Remove the chunks of this type (repeatable)
+    #[arg(
+        short = 't',
+        long = "chunk-type",
+        value_name = "CHUNK_TYPE",
+        required = true
+    )]
+    pub chunk_types: Vec<String>,
+    /// Report what would be removed without rewriting anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct DedupeArgs {
+    /// Files, directories to search for files ending in .png, or (built
+    /// with the s3 feature) s3://BUCKET/PREFIX and gs://BUCKET/PREFIX
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+    /// Report what would be dropped without rewriting anything
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("direction").args(["clean", "smudge"]).required(true)))]
+pub struct GitFilterArgs {
+    /// Strip tIME chunks, creation and modification dates in text chunks
+    /// and the --text keywords, for the repository
+    #[arg(long)]
+    pub clean: bool,
+    /// Add the --text chunks, for the working tree
+    #[arg(long)]
+    pub smudge: bool,
+    /// A tEXt chunk: added (or replaced) on smudge as KEYWORD=VALUE,
+    /// stripped on clean by KEYWORD (repeatable)
+    #[arg(long = "text", value_name = "KEYWORD[=VALUE]")]
+    pub texts: Vec<Text>,
+    /// Also strip chunks of this type on clean (repeatable)
+    #[arg(long = "strip", value_name = "CHUNK_TYPE", requires = "clean")]
+    pub strip: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct CapacityArgs {
+    pub file_path: PathBuf,
+    /// Check where this message fits
+    pub message: Option<String>,
+    /// Check where this file fits
+    #[arg(long = "file", value_name = "PATH", conflicts_with = "message")]
+    pub payload_file: Option<PathBuf>,
+    /// Estimate the payload's size compressed: deflate, zstd or brotli, with
+    /// an optional level (e.g. zstd:19)
+    #[arg(long, value_name = "ALGO[:LEVEL]")]
+    pub compress: Option<Compression>,
+}
+
+#[derive(Debug, Args)]
+pub struct AnalyzeArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+    /// Measure the entropy and compressibility of each chunk instead, and
+    /// flag ancillary chunks whose data looks encrypted
+    #[arg(long)]
+    pub chunks: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct TimelineArgs {
+    #[arg(required = true)]
+    pub files: Vec<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReportArgs {
+    pub file_path: PathBuf,
+    /// Write the report here instead of to standard output
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// html or markdown (default: by the extension of --output, else html)
+    #[arg(long)]
+    pub format: Option<Format>,
+}
+
+#[derive(Debug, Args)]
+pub struct WatermarkArgs {
+    pub file_path: PathBuf,
+    /// The key the watermark is derived from, e.g. the owner's name
+    pub mark: String,
+    /// Write the result here instead of overwriting the input file
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyWatermarkArgs {
+    pub file_path: PathBuf,
+    /// The key the watermark was derived from
+    pub mark: String,
+}
+
+#[derive(Debug, Args)]
+#[command(group(ArgGroup::new("dimensions").args(["size", "capacity"]).required(true)))]
+pub struct GenerateArgs {
+    /// Image size in pixels (e.g. 1024x768)
+    #[arg(long, value_name = "WxH")]
+    pub size: Option<Size>,
+    /// Pick the smallest square image whose pixels hold this many bytes
+    /// with `embed --bits BITS`
+    #[arg(long, value_name = "BYTES")]
+    pub capacity: Option<usize>,
+    /// Low bits per sample the --capacity estimate assumes
+    #[arg(long, default_value_t = 1, requires = "capacity", value_parser = value_parser!(u8).range(1..=lsb::MAX_BITS as i64))]
+    pub bits: u8,
+    /// What the image looks like: noise, gradient or photo-like
+    #[arg(long, default_value = "photo-like")]
+    pub style: Style,
+    /// Generate the same image again from this seed (random by default)
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Write the image here
+    #[arg(short, long)]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct KeygenArgs {
+    /// Write the identity to this file instead of standard output
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn test_cli_definition() {
+        Cli::command().debug_assert();
+    }
+}
@@ -0,0 +1,184 @@
+//! Reading and writing PNG files without blocking, for servers that handle
+//! uploads on an async executor.
+//!
+//! Bytes go through the same [`Decoder`] as the blocking readers, so parsing
+//! is identical. The traits are those of `futures-io`, which every executor
+//! can use: tokio's types adapt to them through `tokio_util::compat`.
+
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use std::io;
+
+use crate::chunk::Chunk;
+use crate::decoder::{Decoder, Event};
+use crate::png::{Png, PngError};
+
+const READ_LEN: usize = 64 * 1024;
+
+impl Png {
+    /// Reads a whole PNG file from `reader`, trailer included.
+    pub async fn from_async_reader<R: AsyncRead + Unpin>(mut reader: R) -> Result<Self, PngError> {
+        let mut decoder = Decoder::new();
+        let mut buffer = vec![0; READ_LEN];
+        let mut chunks = Vec::new();
+        let mut trailer = Vec::new();
+        loop {
+            let read = read(&mut reader, &mut buffer, &decoder).await?;
+            if read == 0 {
+                break;
+            }
+            for event in decoder.feed(&buffer[..read])? {
+                match event {
+                    Event::Chunk { chunk, .. } => chunks.push(chunk),
+                    Event::Trailer(bytes) => trailer.extend(bytes),
+                    Event::Quarantined { .. } => {
+                        unreachable!("the decoder keeps unknown critical chunks")
+                    }
+                }
+            }
+        }
+        decoder.finish()?;
+        let mut png = Png::from_chunks(chunks);
+        png.set_trailer(trailer);
+        Ok(png)
+    }
+    pub async fn write_to_async<W: AsyncWrite + Unpin>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.as_bytes()).await?;
+        writer.flush().await
+    }
+}
+
+/// Reads into `buffer`, retrying when interrupted; errors carry the offset
+/// `decoder` has reached.
+async fn read<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buffer: &mut [u8],
+    decoder: &Decoder,
+) -> Result<usize, PngError> {
+    loop {
+        match reader.read(buffer).await {
+            Ok(read) => return Ok(read),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                return Err(PngError::BadChunk {
+                    offset: decoder.position(),
+                    source: e.into(),
+                })
+            }
+        }
+    }
+}
+
+/// The chunks of a PNG file read from `reader`, parsed as they arrive, like
+/// [`crate::decoder::Chunks`]. Reading stops after `IEND`.
+pub struct AsyncChunks<R> {
+    reader: R,
+    decoder: Option<Decoder>,
+    pending: std::vec::IntoIter<Event>,
+    buffer: Box<[u8]>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncChunks<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_decoder(reader, Decoder::new())
+    }
+    pub fn with_decoder(reader: R, decoder: Decoder) -> Self {
+        Self {
+            reader,
+            decoder: Some(decoder),
+            pending: Vec::new().into_iter(),
+            buffer: vec![0; READ_LEN].into_boxed_slice(),
+        }
+    }
+    /// The next chunk, or `None` once `IEND` or the end of the input has been
+    /// reached or an error returned.
+    pub async fn next_chunk(&mut self) -> Option<Result<Chunk, PngError>> {
+        loop {
+            for event in self.pending.by_ref() {
+                if let Event::Chunk { chunk, .. } = event {
+                    return Some(Ok(chunk));
+                }
+            }
+            let decoder = self.decoder.as_ref()?;
+            if decoder.is_done() {
+                self.decoder = None;
+                return None;
+            }
+            let read = match read(&mut self.reader, &mut self.buffer, decoder).await {
+                Ok(read) => read,
+                Err(e) => {
+                    self.decoder = None;
+                    return Some(Err(e));
+                }
+            };
+            if read == 0 {
+                let decoder = self.decoder.take().expect("checked above");
+                return decoder.finish().err().map(Err);
+            }
+            let decoder = self.decoder.as_mut().expect("checked above");
+            match decoder.feed(&self.buffer[..read]) {
+                Ok(events) => self.pending = events.into_iter(),
+                Err(e) => {
+                    self.decoder = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use futures_lite::future::block_on;
+    use futures_lite::io::Cursor;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), &[0; 13]),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), &[7; 100_000]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello"),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), &[]),
+        ]);
+        png.set_trailer(b"after".to_vec());
+        png
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let png = testing_png();
+        let mut written = Cursor::new(Vec::new());
+        block_on(png.write_to_async(&mut written)).unwrap();
+        assert_eq!(written.get_ref(), &png.as_bytes());
+
+        let read = block_on(Png::from_async_reader(Cursor::new(written.into_inner()))).unwrap();
+        assert_eq!(read.as_bytes(), png.as_bytes());
+        assert_eq!(read.trailer(), b"after");
+
+        let mut cut = png.as_bytes();
+        cut.truncate(1000);
+        assert!(block_on(Png::from_async_reader(Cursor::new(cut))).is_err());
+    }
+
+    #[test]
+    fn test_chunks_stop_after_iend() {
+        let bytes = testing_png().as_bytes();
+        let mut chunks = AsyncChunks::new(Cursor::new(bytes.clone()));
+        let types = block_on(async {
+            let mut types = Vec::new();
+            while let Some(chunk) = chunks.next_chunk().await {
+                types.push(chunk.unwrap().chunk_type().to_string());
+            }
+            types
+        });
+        assert_eq!(types, ["IHDR", "IDAT", "ruSt", "IEND"]);
+
+        let mut chunks = AsyncChunks::new(Cursor::new(bytes[..1000].to_vec()));
+        block_on(async {
+            assert!(chunks.next_chunk().await.unwrap().is_ok());
+            assert!(chunks.next_chunk().await.unwrap().is_err());
+            assert!(chunks.next_chunk().await.is_none());
+        });
+    }
+}
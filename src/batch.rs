@@ -0,0 +1,144 @@
+//! Parsing and writing many PNG files in a row without allocating afresh
+//! for each.
+//!
+//! A [`Parser`] keeps the decoder's buffer, the bytes of the last file read
+//! and the scratch space used to inflate pixels; a [`Serializer`] keeps its
+//! output buffer. After the first few files they rarely need to grow, so a
+//! scan over thousands of files mostly reuses the same memory.
+
+use std::io::Read;
+
+use crate::decoder::{Decoder, Event};
+use crate::image::{DecodeBuffers, ImageData, ImageError};
+use crate::limits::{self, Limits};
+use crate::png::{Png, PngError};
+
+pub struct Parser {
+    decoder: Decoder,
+    /// The file being parsed, for [`Parser::read`].
+    file: Vec<u8>,
+    image: DecodeBuffers,
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self::with_limits(limits::get())
+    }
+    /// A parser that enforces `limits` rather than the process-wide ones.
+    pub fn with_limits(limits: Limits) -> Self {
+        Self {
+            decoder: Decoder::new().with_limits(limits),
+            file: Vec::new(),
+            image: DecodeBuffers::new(),
+        }
+    }
+    /// Parses the PNG file in `bytes`, like `Png::try_from`.
+    pub fn parse(&mut self, bytes: &[u8]) -> Result<Png, PngError> {
+        parse(&mut self.decoder, bytes)
+    }
+    /// Reads a whole PNG file from `reader` and parses it.
+    pub fn read(&mut self, reader: impl Read) -> Result<Png, PngError> {
+        self.file.clear();
+        // One byte past the limit is enough for the decoder to refuse it.
+        let max = self.decoder.limits().max_file_len as u64;
+        reader
+            .take(max.saturating_add(1))
+            .read_to_end(&mut self.file)
+            .map_err(|e| PngError::BadChunk {
+                offset: self.file.len(),
+                source: e.into(),
+            })?;
+        parse(&mut self.decoder, &self.file)
+    }
+    /// Decodes the pixels of `png`, like [`ImageData::decode`].
+    pub fn decode_image(&mut self, png: &Png) -> Result<ImageData, ImageError> {
+        ImageData::decode_with(png, &mut self.image, &self.decoder.limits())
+    }
+}
+
+fn parse(decoder: &mut Decoder, bytes: &[u8]) -> Result<Png, PngError> {
+    decoder.reset();
+    let mut chunks = Vec::new();
+    let mut trailer = Vec::new();
+    for event in decoder.feed(bytes)? {
+        match event {
+            Event::Chunk { chunk, .. } => chunks.push(chunk),
+            Event::Trailer(bytes) => trailer = bytes,
+            Event::Quarantined { .. } => unreachable!("the decoder keeps unknown critical chunks"),
+        }
+    }
+    decoder.finish()?;
+    let mut png = Png::from_chunks(chunks);
+    png.set_trailer(trailer);
+    Ok(png)
+}
+
+#[derive(Default)]
+pub struct Serializer {
+    bytes: Vec<u8>,
+}
+
+impl Serializer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// The bytes of `png`, in a buffer the next call reuses.
+    pub fn serialize(&mut self, png: &Png) -> &[u8] {
+        self.bytes.clear();
+        png.extend_bytes(&mut self.bytes);
+        &self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{ColorType, EncodeOptions, Header};
+
+    fn testing_png(width: u32, shade: u8) -> Png {
+        let header = Header {
+            width,
+            height: 4,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        };
+        let pixels = vec![shade; width as usize * 4 * 3];
+        let mut png = ImageData::new(header, pixels)
+            .unwrap()
+            .to_png(&EncodeOptions::default())
+            .unwrap();
+        png.set_trailer(vec![shade]);
+        png
+    }
+
+    #[test]
+    fn test_reuse_across_files() {
+        let mut parser = Parser::new();
+        let mut serializer = Serializer::new();
+        // Large then small, so leftovers of the first would show in the second.
+        for (width, shade) in [(64, 9), (3, 200), (17, 0)] {
+            let png = testing_png(width, shade);
+            let bytes = serializer.serialize(&png).to_vec();
+            assert_eq!(bytes, png.as_bytes());
+            let parsed = parser.read(bytes.as_slice()).unwrap();
+            assert_eq!(parsed.as_bytes(), bytes);
+            assert_eq!(parsed.trailer(), [shade]);
+            let image = parser.decode_image(&parsed).unwrap();
+            assert_eq!(image, ImageData::decode(&png).unwrap());
+        }
+        assert!(matches!(
+            parser.parse(b"GIF89a"),
+            Err(PngError::InvalidHeader)
+        ));
+        let bytes = testing_png(3, 1).as_bytes();
+        assert!(parser.parse(&bytes[..bytes.len() - 10]).is_err());
+        assert_eq!(parser.parse(&bytes).unwrap().as_bytes(), bytes);
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum BudgetError {
+    #[error(
+        "Invalid size budget {0:?}: expected bytes (e.g. 4096, 16K, 2M) or a percentage (e.g. 5%)"
+    )]
+    InvalidBudget(String),
+    #[error(
+        "Output would grow by {growth} bytes ({percent:.2}%), exceeding the budget of {budget}"
+    )]
+    Exceeded {
+        growth: u64,
+        percent: f64,
+        budget: GrowthBudget,
+    },
+}
+
+/// Upper bound on how much an operation may grow a file, either as an
+/// absolute number of bytes or relative to the original size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GrowthBudget {
+    Bytes(u64),
+    Percent(f64),
+}
+
+impl FromStr for GrowthBudget {
+    type Err = BudgetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BudgetError::InvalidBudget(s.to_string());
+        let trimmed = s.trim();
+        if let Some(percent) = trimmed.strip_suffix('%') {
+            let percent: f64 = percent.trim().parse().map_err(|_| invalid())?;
+            if !percent.is_finite() || percent < 0.0 {
+                return Err(invalid());
+            }
+            return Ok(Self::Percent(percent));
+        }
+        let split = trimmed
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(trimmed.len());
+        let (digits, unit) = trimmed.split_at(split);
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        let multiplier = match unit.trim() {
+            "" | "B" => 1,
+            "K" | "k" | "KiB" => 1 << 10,
+            "M" | "MiB" => 1 << 20,
+            "G" | "GiB" => 1 << 30,
+            _ => return Err(invalid()),
+        };
+        value
+            .checked_mul(multiplier)
+            .map(Self::Bytes)
+            .ok_or_else(invalid)
+    }
+}
+
+impl Display for GrowthBudget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bytes(bytes) => write!(f, "{} bytes", bytes),
+            Self::Percent(percent) => write!(f, "{}%", percent),
+        }
+    }
+}
+
+impl GrowthBudget {
+    /// Checks that going from `before` to `after` bytes stays within budget.
+    pub fn check(&self, before: u64, after: u64) -> Result<(), BudgetError> {
+        let growth = after.saturating_sub(before);
+        let percent = if before == 0 {
+            0.0
+        } else {
+            growth as f64 * 100.0 / before as f64
+        };
+        let within = match *self {
+            Self::Bytes(max) => growth <= max,
+            Self::Percent(max) => percent <= max,
+        };
+        if within {
+            Ok(())
+        } else {
+            Err(BudgetError::Exceeded {
+                growth,
+                percent,
+                budget: *self,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_budgets() {
+        assert_eq!(
+            GrowthBudget::from_str("4096").unwrap(),
+            GrowthBudget::Bytes(4096)
+        );
+        assert_eq!(
+            GrowthBudget::from_str("16K").unwrap(),
+            GrowthBudget::Bytes(16 * 1024)
+        );
+        assert_eq!(
+            GrowthBudget::from_str("2MiB").unwrap(),
+            GrowthBudget::Bytes(2 * 1024 * 1024)
+        );
+        assert_eq!(
+            GrowthBudget::from_str("12.5%").unwrap(),
+            GrowthBudget::Percent(12.5)
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_budgets() {
+        for s in ["", "K", "10X", "-5%", "abc%", "99999999999999999999G"] {
+            assert!(GrowthBudget::from_str(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_check_bytes() {
+        let budget = GrowthBudget::Bytes(100);
+        assert!(budget.check(1000, 1100).is_ok());
+        assert!(budget.check(1000, 1101).is_err());
+        assert!(budget.check(1000, 900).is_ok());
+    }
+
+    #[test]
+    fn test_check_percent() {
+        let budget = GrowthBudget::Percent(10.0);
+        assert!(budget.check(1000, 1100).is_ok());
+        assert!(budget.check(1000, 1101).is_err());
+    }
+}
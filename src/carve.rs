@@ -0,0 +1,198 @@
+//! Finding PNG files inside raw data with no file system to go by: disk
+//! images, memory dumps, captured network payloads.
+//!
+//! [`carve`] looks for the PNG signature and follows the chunks after it,
+//! checking their CRCs, until `IEND`, the end of the data, or bytes that
+//! can't be a chunk header. What it walked is reported whether it ended
+//! well or not, so damaged and cut-off images can still be extracted.
+//! The search goes on after each find, so a PNG stored inside the data of
+//! another one's chunk isn't reported separately.
+
+use std::fmt::{self, Display, Formatter};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crc;
+use crate::png::Png;
+
+/// How the chunks of a carved PNG ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum End {
+    /// With an `IEND` chunk.
+    Iend,
+    /// With the data, in the middle of a chunk.
+    Cut,
+    /// With bytes that aren't a chunk header: a type that isn't four
+    /// letters, or a length no chunk can have.
+    Garbage,
+}
+
+/// A PNG file found by [`carve`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Carved {
+    /// Where its signature starts.
+    pub offset: usize,
+    /// The bytes from the signature to the end of its last whole chunk.
+    pub len: usize,
+    pub chunks: usize,
+    pub bad_crcs: usize,
+    pub end: End,
+    /// The width and height `IHDR` gives, when it is the first chunk.
+    pub size: Option<(u32, u32)>,
+}
+
+impl Carved {
+    /// Whether it ends with `IEND` and every CRC is right.
+    pub fn is_intact(&self) -> bool {
+        self.end == End::Iend && self.bad_crcs == 0
+    }
+    /// Its bytes in `blob`, the data it was carved from.
+    pub fn bytes<'a>(&self, blob: &'a [u8]) -> &'a [u8] {
+        &blob[self.offset..self.offset + self.len]
+    }
+}
+
+impl Display for Carved {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match (self.end, self.bad_crcs) {
+            (End::Iend, 0) => write!(f, "intact"),
+            (End::Iend, bad) => write!(f, "{} bad CRCs", bad),
+            (End::Cut, _) => write!(f, "cut off"),
+            (End::Garbage, _) => write!(f, "ends in garbage"),
+        }?;
+        if self.end != End::Iend && self.bad_crcs > 0 {
+            write!(f, ", {} bad CRCs", self.bad_crcs)?;
+        }
+        Ok(())
+    }
+}
+
+/// The PNG files in `blob`, in the order they are stored.
+pub fn carve(blob: &[u8]) -> Carver<'_> {
+    Carver { blob, at: 0 }
+}
+
+/// The iterator [`carve`] returns.
+#[derive(Clone, Debug)]
+pub struct Carver<'a> {
+    blob: &'a [u8],
+    at: usize,
+}
+
+impl Iterator for Carver<'_> {
+    type Item = Carved;
+
+    fn next(&mut self) -> Option<Carved> {
+        let offset = find_signature(self.blob, self.at)?;
+        let carved = walk(self.blob, offset);
+        self.at = offset + carved.len;
+        Some(carved)
+    }
+}
+
+fn find_signature(blob: &[u8], from: usize) -> Option<usize> {
+    let signature = Png::STANDARD_HEADER;
+    let mut at = from;
+    while at + signature.len() <= blob.len() {
+        let start = at + blob[at..].iter().position(|&b| b == signature[0])?;
+        if blob[start..].starts_with(&signature) {
+            return Some(start);
+        }
+        at = start + 1;
+    }
+    None
+}
+
+/// Follows the chunks after the signature at `offset`.
+fn walk(blob: &[u8], offset: usize) -> Carved {
+    let mut carved = Carved {
+        offset,
+        len: Png::STANDARD_HEADER.len(),
+        chunks: 0,
+        bad_crcs: 0,
+        end: End::Cut,
+        size: None,
+    };
+    loop {
+        let at = offset + carved.len;
+        let Some(header) = blob.get(at..at + 8) else {
+            return carved;
+        };
+        let length = u32::from_be_bytes(header[..4].try_into().expect("4 bytes"));
+        let code: [u8; 4] = header[4..].try_into().expect("4 bytes");
+        if length > Chunk::MAX_LENGTH || ChunkType::try_from(code).is_err() {
+            carved.end = End::Garbage;
+            return carved;
+        }
+        let end = at + Chunk::OVERHEAD + length as usize;
+        let Some(chunk) = blob.get(at..end) else {
+            carved.end = End::Cut;
+            return carved;
+        };
+        let stored = u32::from_be_bytes(chunk[end - at - 4..].try_into().expect("4 bytes"));
+        if crc::hash(&chunk[4..end - at - 4]) != stored {
+            carved.bad_crcs += 1;
+        }
+        if carved.chunks == 0 && code == *b"IHDR" && length == 13 {
+            let field =
+                |at: usize| u32::from_be_bytes(chunk[at..at + 4].try_into().expect("4 bytes"));
+            carved.size = Some((field(8), field(12)));
+        }
+        carved.chunks += 1;
+        carved.len = end - offset;
+        if code == *b"IEND" {
+            carved.end = End::Iend;
+            return carved;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_png() -> Vec<u8> {
+        let chunk = |code: &str, data: &[u8]| Chunk::new(ChunkType::from_str(code).unwrap(), data);
+        let mut ihdr = [0; 13];
+        ihdr[..8].copy_from_slice(&[0, 0, 0, 3, 0, 0, 0, 2]);
+        Png::from_chunks(vec![
+            chunk("IHDR", &ihdr),
+            chunk("IDAT", &[1; 40]),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_carve() {
+        let png = testing_png();
+        let mut blob = vec![0x89; 100];
+        blob.extend(&png);
+        let mut damaged = png.clone();
+        // A byte of the IDAT data.
+        damaged[8 + 25 + 10] ^= 1;
+        blob.extend(&damaged);
+        blob.extend(b"\x89PNG\r\n\x1a\n\0\0\0\r!!!!");
+        blob.extend(&png[..png.len() - 20]);
+
+        let found: Vec<Carved> = carve(&blob).collect();
+        assert_eq!(found.len(), 4);
+        assert_eq!(found[0].offset, 100);
+        assert_eq!(found[0].bytes(&blob), png);
+        assert!(found[0].is_intact());
+        assert_eq!(found[0].size, Some((3, 2)));
+        assert_eq!((found[1].end, found[1].bad_crcs), (End::Iend, 1));
+        assert_eq!(found[1].to_string(), "1 bad CRCs");
+        assert_eq!(
+            (found[2].end, found[2].chunks, found[2].len),
+            (End::Garbage, 0, 8)
+        );
+        assert_eq!((found[3].end, found[3].chunks), (End::Cut, 1));
+        assert_eq!(
+            found[3].offset + found[3].len,
+            blob.len() - (png.len() - 20 - 33)
+        );
+        assert!(carve(&blob[..107]).next().is_none());
+    }
+}
@@ -0,0 +1,196 @@
+//! Counting the chunk types of a whole corpus, so what is normal in it is
+//! known and anything else stands out.
+//!
+//! [`chunk_headers`] reads only the eight-byte header of each chunk and
+//! seeks past the data, and [`Census`] adds up what many files hold: per
+//! chunk type, how many files have it, how many chunks and bytes there
+//! are, and a few of the files, to look at.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, Seek};
+use std::path::{Path, PathBuf};
+
+use crate::chunk_type::ChunkType;
+use crate::lint::Profile;
+use crate::png::PngError;
+use crate::walk::{self, Walker};
+
+/// Registered extensions that are part of no edition of the specification.
+const EXTENSIONS: &[&[u8; 4]] = &[b"dSIG", b"fRAc", b"gIFg", b"gIFt", b"gIFx"];
+
+/// Whether `code` is in the specification (the third edition, APNG
+/// included) or registered as an extension to it.
+pub fn is_registered(code: &[u8; 4]) -> bool {
+    Profile::Third.defines(code) || EXTENSIONS.contains(&code)
+}
+
+/// The type and data length of each chunk of the PNG file in `reader`, up
+/// to `IEND` or the end of the file. CRCs aren't checked.
+pub fn chunk_headers(reader: impl BufRead + Seek) -> Result<Vec<(ChunkType, u32)>, PngError> {
+    let mut walker = Walker::new(reader)?;
+    let mut headers = Vec::new();
+    while let Some(header) = walker.next_header()? {
+        walk::skip(walker.reader(), header.length as u64 + 4).map_err(|e| header.bad(e))?;
+        let end = header.is_end();
+        headers.push((header.chunk_type, header.length));
+        if end {
+            break;
+        }
+    }
+    Ok(headers)
+}
+
+/// What a [`Census`] found of one chunk type.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub chunk_type: ChunkType,
+    /// Files with at least one chunk of the type.
+    pub files: usize,
+    pub chunks: usize,
+    /// Data bytes, headers and CRCs left out.
+    pub bytes: u64,
+    /// The first files with the type, in path order.
+    pub examples: Vec<PathBuf>,
+}
+
+/// The chunk types of many files, added up.
+#[derive(Clone, Debug)]
+pub struct Census {
+    entries: BTreeMap<[u8; 4], Entry>,
+    files: usize,
+    examples: usize,
+}
+
+impl Default for Census {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Census {
+    /// Examples kept per chunk type by [`Census::new`].
+    pub const EXAMPLES: usize = 3;
+
+    pub fn new() -> Self {
+        Self::with_examples(Self::EXAMPLES)
+    }
+    /// A census keeping up to `examples` files per chunk type.
+    pub fn with_examples(examples: usize) -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            files: 0,
+            examples,
+        }
+    }
+    /// Counts the chunks, as [`chunk_headers`] gives them, of the file at
+    /// `path`.
+    pub fn add(&mut self, path: &Path, headers: &[(ChunkType, u32)]) {
+        self.files += 1;
+        let mut seen: Vec<[u8; 4]> = Vec::new();
+        for (chunk_type, length) in headers {
+            let code = chunk_type.bytes();
+            let entry = self.entries.entry(code).or_insert_with(|| Entry {
+                chunk_type: chunk_type.clone(),
+                files: 0,
+                chunks: 0,
+                bytes: 0,
+                examples: Vec::new(),
+            });
+            entry.chunks += 1;
+            entry.bytes += *length as u64;
+            if seen.contains(&code) {
+                continue;
+            }
+            seen.push(code);
+            entry.files += 1;
+            let at = entry
+                .examples
+                .partition_point(|example| example.as_path() < path);
+            if at < self.examples {
+                entry.examples.insert(at, path.to_path_buf());
+                entry.examples.truncate(self.examples);
+            }
+        }
+    }
+    /// Files counted.
+    pub fn files(&self) -> usize {
+        self.files
+    }
+    /// Every chunk type seen, those in the most files first.
+    pub fn entries(&self) -> Vec<&Entry> {
+        let mut entries: Vec<&Entry> = self.entries.values().collect();
+        entries.sort_by(|a, b| b.files.cmp(&a.files).then(b.chunks.cmp(&a.chunks)));
+        entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::png::Png;
+    use std::io::{BufReader, Cursor};
+    use std::str::FromStr;
+
+    fn headers(types: &[(&str, u32)]) -> Vec<(ChunkType, u32)> {
+        types
+            .iter()
+            .map(|&(code, len)| (ChunkType::from_str(code).unwrap(), len))
+            .collect()
+    }
+
+    #[test]
+    fn test_chunk_headers() {
+        let chunk = |code: &str, data: &[u8]| Chunk::new(ChunkType::from_str(code).unwrap(), data);
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("IDAT", &[0; 100_000]),
+            chunk("ruSt", b"hi"),
+            chunk("IEND", &[]),
+        ]);
+        png.set_trailer(b"after".to_vec());
+        let bytes = png.as_bytes();
+        assert_eq!(
+            chunk_headers(BufReader::with_capacity(64, Cursor::new(&bytes))).unwrap(),
+            headers(&[("IHDR", 13), ("IDAT", 100_000), ("ruSt", 2), ("IEND", 0)])
+        );
+        assert!(matches!(
+            chunk_headers(Cursor::new(&bytes[..37])),
+            Err(PngError::BadChunk { offset: 33, .. })
+        ));
+        // Truncated in the middle of the image data, past the buffer.
+        let truncated = BufReader::with_capacity(64, Cursor::new(&bytes[..50_000]));
+        assert!(matches!(
+            chunk_headers(truncated),
+            Err(PngError::BadChunk { offset: 33, .. })
+        ));
+    }
+
+    #[test]
+    fn test_census() {
+        let mut census = Census::with_examples(2);
+        census.add(
+            Path::new("c.png"),
+            &headers(&[("IHDR", 13), ("IDAT", 10), ("IDAT", 5), ("IEND", 0)]),
+        );
+        census.add(
+            Path::new("b.png"),
+            &headers(&[("IHDR", 13), ("ruSt", 7), ("IEND", 0)]),
+        );
+        census.add(
+            Path::new("a.png"),
+            &headers(&[("IHDR", 13), ("IDAT", 1), ("IEND", 0)]),
+        );
+        assert_eq!(census.files(), 3);
+        let entries = census.entries();
+        let idat = entries
+            .iter()
+            .find(|entry| entry.chunk_type.to_string() == "IDAT")
+            .unwrap();
+        assert_eq!((idat.files, idat.chunks, idat.bytes), (2, 3, 16));
+        assert_eq!(idat.examples, [Path::new("a.png"), Path::new("c.png")]);
+        assert_eq!(entries.last().unwrap().chunk_type.to_string(), "ruSt");
+        assert!(is_registered(b"IDAT") && is_registered(b"gIFg"));
+        assert!(!is_registered(b"ruSt"));
+    }
+}
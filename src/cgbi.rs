@@ -0,0 +1,137 @@
+//! Apple's CgBI variant of PNG, which Xcode writes into iOS app bundles and
+//! asset catalogs.
+//!
+//! A `CgBI` chunk before `IHDR` marks such a file. Its image data is a raw
+//! deflate stream, without the zlib header and checksum, of pixels stored
+//! blue first and, with alpha, premultiplied by it. Other decoders fail to
+//! inflate it, so [`convert`] rewrites it as a standard PNG.
+
+use flate2::read::DeflateDecoder;
+use std::io::Read;
+
+use crate::image::{ColorType, EncodeOptions, Header, ImageData, ImageError};
+use crate::limits;
+use crate::png::Png;
+
+pub const CHUNK_TYPE: &[u8; 4] = b"CgBI";
+
+/// Whether `png` is in Apple's CgBI format.
+pub fn is_cgbi(png: &Png) -> bool {
+    png.chunks()
+        .iter()
+        .any(|chunk| chunk.chunk_type().bytes() == *CHUNK_TYPE)
+}
+
+/// Restores a standard PNG from a CgBI one: drops the `CgBI` chunk, puts
+/// the color samples back in order, undoes the premultiplication and
+/// stores the image data as a zlib stream again. Returns false, changing
+/// nothing, when `png` isn't a CgBI file.
+pub fn convert(png: &mut Png) -> Result<bool, ImageError> {
+    if !is_cgbi(png) {
+        return Ok(false);
+    }
+    let header = Header::from_png(png)?;
+    let mut stream = Vec::new();
+    for chunk in png.chunks() {
+        if chunk.chunk_type().bytes() == *b"IDAT" {
+            stream.extend_from_slice(chunk.data());
+        }
+    }
+    let expected = header.stream_len().ok_or(ImageError::TooLarge)?;
+    limits::get().check(limits::Limit::DecompressedLen, expected)?;
+    let mut filtered = Vec::new();
+    DeflateDecoder::new(stream.as_slice())
+        .take(expected as u64 + 1)
+        .read_to_end(&mut filtered)
+        .map_err(ImageError::Inflate)?;
+    if filtered.len() != expected {
+        return Err(ImageError::WrongLength {
+            expected,
+            actual: filtered.len(),
+        });
+    }
+    let mut image = ImageData::from_filtered(header, &filtered)?;
+    if header.bit_depth == 8 {
+        match header.color_type {
+            ColorType::Rgba => image.as_bytes_mut().chunks_exact_mut(4).for_each(restore),
+            ColorType::Rgb => image
+                .as_bytes_mut()
+                .chunks_exact_mut(3)
+                .for_each(|pixel| pixel.swap(0, 2)),
+            _ => {}
+        }
+    }
+    while let Some(at) = png
+        .chunks()
+        .iter()
+        .position(|chunk| chunk.chunk_type().bytes() == *CHUNK_TYPE)
+    {
+        png.remove_chunk_at(at);
+    }
+    image.write_to(png, &EncodeOptions::default())?;
+    Ok(true)
+}
+
+/// Turns a premultiplied BGRA pixel into a straight RGBA one.
+fn restore(pixel: &mut [u8]) {
+    pixel.swap(0, 2);
+    let alpha = pixel[3] as u32;
+    if alpha == 0 || alpha == 255 {
+        return;
+    }
+    for sample in &mut pixel[..3] {
+        *sample = ((*sample as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use flate2::write::DeflateEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    use std::str::FromStr;
+
+    fn chunk(code: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(code).unwrap(), data)
+    }
+
+    /// A 2x1 CgBI image: opaque blue, then red at half opacity.
+    fn testing_cgbi() -> Png {
+        let header = Header {
+            width: 2,
+            height: 1,
+            bit_depth: 8,
+            color_type: ColorType::Rgba,
+            interlaced: false,
+        };
+        let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+        deflate
+            .write_all(&[0, 255, 0, 0, 255, 0, 0, 64, 128])
+            .unwrap();
+        Png::from_chunks(vec![
+            chunk("CgBI", &[0x50, 0, 0x20, 0x06]),
+            chunk("IHDR", &header.as_bytes()),
+            chunk("IDAT", &deflate.finish().unwrap()),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    #[test]
+    fn test_convert() {
+        let mut png = testing_cgbi();
+        assert!(is_cgbi(&png));
+        assert!(matches!(
+            ImageData::decode(&png),
+            Err(ImageError::AppleCgbi)
+        ));
+
+        assert!(convert(&mut png).unwrap());
+        assert!(!is_cgbi(&png));
+        let image = ImageData::decode(&png).unwrap();
+        assert_eq!(image.as_bytes(), [0, 0, 255, 255, 128, 0, 0, 128]);
+        assert!(!convert(&mut png).unwrap());
+    }
+}
@@ -1,28 +1,76 @@
-use crc::{Crc, CRC_32_ISO_HDLC};
-use std::convert::TryFrom;
-use std::fmt::{Display, Formatter};
-use std::io;
-use std::io::Read;
-use thiserror::Error;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt::{self, Display, Formatter};
+#[cfg(feature = "std")]
+use std::io::{self, Write};
 
 use crate::chunk_type::{ChunkType, ChunkTypeError};
+use crate::crc;
+use crate::parse::{self, Span};
 
-#[derive(Debug, Error)]
+#[derive(Debug)]
 pub enum ChunkError {
-    #[error("IO Error converting from bytes: {0}")]
-    InvalidChunkData(#[from] io::Error),
-    #[error("Non UTf-8 characters found: {0}")]
+    #[cfg(feature = "std")]
+    InvalidChunkData(io::Error),
     NonUTf8Characters(String),
-    #[error("Bad ChunkType: {0}")]
-    BadChunkType(#[from] ChunkTypeError),
-    #[error("Checksum error")]
+    BadChunkType(ChunkTypeError),
     ChecksumError,
+    Truncated(Span),
+    TooLong(usize),
 }
 
+impl Display for ChunkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "std")]
+            Self::InvalidChunkData(e) => write!(f, "IO Error converting from bytes: {}", e),
+            Self::NonUTf8Characters(e) => write!(f, "Non UTf-8 characters found: {}", e),
+            Self::BadChunkType(e) => write!(f, "Bad ChunkType: {}", e),
+            Self::ChecksumError => write!(f, "Checksum error"),
+            Self::Truncated(span) => write!(f, "Chunk is truncated: {}", span),
+            Self::TooLong(len) => write!(
+                f,
+                "Chunk length {} exceeds the PNG maximum of {}",
+                len,
+                Chunk::MAX_LENGTH
+            ),
+        }
+    }
+}
+
+impl core::error::Error for ChunkError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            Self::InvalidChunkData(e) => Some(e),
+            Self::BadChunkType(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for ChunkError {
+    fn from(e: io::Error) -> Self {
+        Self::InvalidChunkData(e)
+    }
+}
+
+impl From<ChunkTypeError> for ChunkError {
+    fn from(e: ChunkTypeError) -> Self {
+        Self::BadChunkType(e)
+    }
+}
+
+/// A chunk of a PNG file. Its data is shared between clones and copied
+/// only when one of them changes it, so cloning is cheap however large the
+/// chunk is.
 #[derive(Clone, Debug)]
 pub struct Chunk {
     chunk_type: ChunkType,
-    data: Vec<u8>,
+    data: Arc<Vec<u8>>,
     length: u32,
     crc: u32,
 }
@@ -31,22 +79,23 @@ impl TryFrom<&[u8]> for Chunk {
     type Error = ChunkError;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let input_stream = &mut &*value;
-        let mut buf: [u8; 4] = [0, 0, 0, 0];
-        input_stream.read_exact(&mut buf)?;
-        let length = u32::from_be_bytes(buf);
-        input_stream.read_exact(&mut buf)?;
-        let chunk_type = ChunkType::try_from(buf)?;
-        let mut data = vec![0u8; length as usize];
-        input_stream.read_exact(&mut data)?;
-        input_stream.read_exact(&mut buf)?;
-        let crc = u32::from_be_bytes(buf);
-        if crc != Self::CRC.checksum(&[&chunk_type.bytes()[..], &data.clone()].concat()) {
+        Self::parse(value, true)
+    }
+}
+
+impl Chunk {
+    fn parse(value: &[u8], check_crc: bool) -> Result<Self, ChunkError> {
+        let (_, raw) =
+            parse::chunk(value).map_err(|e| ChunkError::Truncated(parse::span(value, e)))?;
+        let chunk_type = ChunkType::try_from(raw.chunk_type)?;
+        let length = Self::check_length(raw.data.len())?;
+        let (data, crc) = (raw.data.to_vec(), raw.crc);
+        if check_crc && crc != Self::checksum(&chunk_type, &data) {
             return Err(ChunkError::ChecksumError);
         }
         Ok(Self {
             chunk_type,
-            data,
+            data: Arc::new(data),
             length,
             crc,
         })
@@ -57,30 +106,54 @@ impl TryFrom<Chunk> for String {
     type Error = ChunkError;
 
     fn try_from(value: Chunk) -> Result<Self, Self::Error> {
-        match std::str::from_utf8(value.data()) {
-            Ok(s) => Ok(s.to_string()),
-            Err(e) => Err(ChunkError::NonUTf8Characters(e.to_string())),
-        }
+        value.data_as_string()
     }
 }
 
 impl Display for Chunk {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(f, "{:?}", self)
     }
 }
 
 impl Chunk {
-    pub const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    /// Bytes of length, type and CRC around the data.
+    pub const OVERHEAD: usize = 12;
+    /// The largest data length PNG allows.
+    pub const MAX_LENGTH: u32 = i32::MAX as u32;
 
+    /// The CRC PNG stores for a chunk: CRC-32 over its type and data, by
+    /// the backend [`crc::set`] chose.
+    pub fn checksum(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+        let mut hasher = crc::Hasher::new();
+        hasher.update(&chunk_type.bytes());
+        hasher.update(data);
+        hasher.finalize()
+    }
+    /// `len` as a chunk length, if PNG allows it.
+    pub(crate) fn check_length(len: usize) -> Result<u32, ChunkError> {
+        u32::try_from(len)
+            .ok()
+            .filter(|&length| length <= Self::MAX_LENGTH)
+            .ok_or(ChunkError::TooLong(len))
+    }
+    /// # Panics
+    ///
+    /// If `data` is longer than [`Chunk::MAX_LENGTH`]; see
+    /// [`Chunk::try_new`].
     pub fn new(chunk_type: ChunkType, data: &[u8]) -> Self {
-        let crc = Self::CRC.checksum(&[&chunk_type.bytes()[..], data].concat());
-        Self {
+        Self::try_new(chunk_type, data).expect("chunk data too long")
+    }
+    /// Like [`Chunk::new`], but refuses data longer than PNG allows.
+    pub fn try_new(chunk_type: ChunkType, data: &[u8]) -> Result<Self, ChunkError> {
+        let length = Self::check_length(data.len())?;
+        let crc = Self::checksum(&chunk_type, data);
+        Ok(Self {
             chunk_type,
-            data: data.to_vec(),
-            length: data.len() as u32,
+            data: Arc::new(data.to_vec()),
+            length,
             crc,
-        }
+        })
     }
     pub fn length(&self) -> u32 {
         self.length
@@ -94,15 +167,115 @@ impl Chunk {
     pub fn crc(&self) -> u32 {
         self.crc
     }
+    /// The data, as a handle that can outlive the chunk (or the `Png` it
+    /// belongs to, say behind an `Arc` on another thread) without copying.
+    pub fn shared_data(&self) -> Arc<Vec<u8>> {
+        Arc::clone(&self.data)
+    }
+    /// Changes the data through `f`, copying it first if a clone shares it,
+    /// and updates the length and CRC to match.
+    ///
+    /// # Panics
+    ///
+    /// If `f` leaves the data longer than [`Chunk::MAX_LENGTH`].
+    pub fn modify_data(&mut self, f: impl FnOnce(&mut Vec<u8>)) {
+        let data = Arc::make_mut(&mut self.data);
+        f(data);
+        self.length = Self::check_length(data.len()).expect("chunk data too long");
+        self.crc = Self::checksum(&self.chunk_type, data);
+    }
+    /// Like `try_from`, but keeps a chunk whose CRC doesn't match, for
+    /// callers that can repair the data themselves.
+    pub fn from_bytes_unchecked(value: &[u8]) -> Result<Self, ChunkError> {
+        Self::parse(value, false)
+    }
+    pub fn has_valid_crc(&self) -> bool {
+        self.crc == Self::checksum(&self.chunk_type, &self.data)
+    }
+    pub fn data_as_string(&self) -> Result<String, ChunkError> {
+        match core::str::from_utf8(&self.data) {
+            Ok(s) => Ok(s.to_string()),
+            Err(e) => Err(ChunkError::NonUTf8Characters(e.to_string())),
+        }
+    }
     pub fn as_bytes(&self) -> Vec<u8> {
-        self.length
-            .to_be_bytes()
-            .iter()
-            .chain(self.chunk_type.bytes().iter())
-            .chain(self.data.iter())
-            .chain(self.crc.to_be_bytes().iter())
-            .copied()
-            .collect()
+        let mut bytes = Vec::with_capacity(Self::OVERHEAD + self.data.len());
+        self.extend_bytes(&mut bytes);
+        bytes
+    }
+    /// Appends the chunk, as stored in a file, to `bytes`.
+    pub fn extend_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.reserve(Self::OVERHEAD + self.data.len());
+        bytes.extend_from_slice(&self.length.to_be_bytes());
+        bytes.extend_from_slice(&self.chunk_type.bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes.extend_from_slice(&self.crc.to_be_bytes());
+    }
+    /// Writes the chunk, as stored in a file, without copying it first.
+    #[cfg(feature = "std")]
+    pub fn write_into(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.length.to_be_bytes())?;
+        writer.write_all(&self.chunk_type.bytes())?;
+        writer.write_all(&self.data)?;
+        writer.write_all(&self.crc.to_be_bytes())
+    }
+}
+
+/// A chunk borrowed from the bytes of a file (see [`crate::png::chunk_refs`]),
+/// so that walking a large file copies nothing but what is asked for.
+#[derive(Clone, Debug)]
+pub struct ChunkRef<'a> {
+    offset: usize,
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+}
+
+impl<'a> ChunkRef<'a> {
+    /// Reads the chunk at `offset` in `bytes`, without checking its CRC.
+    pub fn parse(bytes: &'a [u8], offset: usize) -> Result<Self, ChunkError> {
+        let rest = bytes.get(offset..).unwrap_or_default();
+        let (_, raw) =
+            parse::chunk(rest).map_err(|e| ChunkError::Truncated(parse::span(bytes, e)))?;
+        Chunk::check_length(raw.data.len())?;
+        Ok(Self {
+            offset,
+            chunk_type: ChunkType::try_from(raw.chunk_type)?,
+            data: raw.data,
+            crc: raw.crc,
+        })
+    }
+    /// Where the chunk starts in the file.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+    /// The offset just past the chunk.
+    pub fn end(&self) -> usize {
+        self.offset + Chunk::OVERHEAD + self.data.len()
+    }
+    pub fn has_valid_crc(&self) -> bool {
+        self.crc == Chunk::checksum(&self.chunk_type, self.data)
+    }
+    /// Copies the chunk out of the file.
+    pub fn to_chunk(&self) -> Chunk {
+        Chunk {
+            chunk_type: self.chunk_type.clone(),
+            data: Arc::new(self.data.to_vec()),
+            length: self.length(),
+            crc: self.crc,
+        }
     }
 }
 
@@ -128,6 +301,33 @@ mod tests {
             .collect()
     }
 
+    #[test]
+    fn test_checksum() {
+        // Bit by bit, as in the PNG specification.
+        fn reference(bytes: &[u8]) -> u32 {
+            let mut crc = !0u32;
+            for byte in bytes {
+                crc ^= *byte as u32;
+                for _ in 0..8 {
+                    crc = if crc & 1 == 1 {
+                        (crc >> 1) ^ 0xedb8_8320
+                    } else {
+                        crc >> 1
+                    };
+                }
+            }
+            !crc
+        }
+        let iend = ChunkType::from_str("IEND").unwrap();
+        assert_eq!(Chunk::checksum(&iend, &[]), 0xae42_6082);
+        let idat = ChunkType::from_str("IDAT").unwrap();
+        let data: Vec<u8> = (0..100_003u32).map(|i| (i * 31 % 256) as u8).collect();
+        assert_eq!(
+            Chunk::checksum(&idat, &data),
+            reference(&[&b"IDAT"[..], &data].concat())
+        );
+    }
+
     #[test]
     fn test_new_chunk() {
         let chunk_type = ChunkType::from_str("RuSt").unwrap();
@@ -137,12 +337,35 @@ mod tests {
         assert_eq!(chunk.crc(), 2882656334);
     }
 
+    #[test]
+    fn test_chunk_ref() {
+        let mut bytes = vec![0; 3];
+        bytes.extend(testing_chunk_data());
+        let chunk = ChunkRef::parse(&bytes, 3).unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "RuSt");
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.end(), bytes.len());
+        assert!(chunk.has_valid_crc());
+        assert_eq!(chunk.to_chunk().as_bytes(), &bytes[3..]);
+        assert!(ChunkRef::parse(&bytes[..bytes.len() - 1], 3).is_err());
+    }
+
     #[test]
     fn test_chunk_length() {
         let chunk = Chunk::try_from(testing_chunk_data().as_ref()).unwrap();
         assert_eq!(chunk.length(), 42);
     }
 
+    #[test]
+    fn test_check_length() {
+        let max = Chunk::MAX_LENGTH as usize;
+        assert_eq!(Chunk::check_length(max).unwrap(), Chunk::MAX_LENGTH);
+        assert!(matches!(
+            Chunk::check_length(max + 1),
+            Err(ChunkError::TooLong(len)) if len == max + 1
+        ));
+    }
+
     #[test]
     fn test_chunk_type() {
         let chunk = Chunk::try_from(testing_chunk_data().as_ref()).unwrap();
@@ -193,6 +416,40 @@ mod tests {
 
         let chunk = Chunk::try_from(chunk_data.as_ref());
         assert!(chunk.is_err());
+
+        let chunk = Chunk::from_bytes_unchecked(chunk_data.as_ref()).unwrap();
+        assert_eq!(chunk.crc(), 2882656333);
+        assert!(!chunk.has_valid_crc());
+    }
+
+    #[test]
+    fn test_serialize_in_place() {
+        let chunk = Chunk::try_from(testing_chunk_data().as_ref()).unwrap();
+        let mut bytes = b"before".to_vec();
+        chunk.extend_bytes(&mut bytes);
+        assert_eq!(&bytes[6..], testing_chunk_data());
+        #[cfg(feature = "std")]
+        {
+            let mut written = Vec::new();
+            chunk.write_into(&mut written).unwrap();
+            assert_eq!(written, testing_chunk_data());
+        }
+    }
+
+    #[test]
+    fn test_modify_data() {
+        let mut chunk = Chunk::try_from(testing_chunk_data().as_ref()).unwrap();
+        let original = chunk.clone();
+        chunk.modify_data(|data| data.extend_from_slice(b"!"));
+        assert_eq!(chunk.length(), original.length() + 1);
+        assert!(chunk.has_valid_crc());
+        assert!(original.has_valid_crc());
+        assert_eq!(original.as_bytes(), testing_chunk_data());
+        // No longer shared, so changed in place.
+        let data = chunk.data().as_ptr();
+        chunk.modify_data(|data| data[0] = b'T');
+        assert_eq!(chunk.data().as_ptr(), data);
+        assert!(chunk.data_as_string().unwrap().starts_with("This is where"));
     }
 
     #[test]
@@ -1,16 +1,29 @@
-use std::convert::TryFrom;
-use std::fmt::Formatter;
-use std::str::FromStr;
-use thiserror::Error;
+use core::convert::TryFrom;
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
 
-#[derive(Debug, Error)]
+#[derive(Debug)]
 pub enum ChunkTypeError {
-    #[error("Characters can only be alphabetic (A-Z, a-z)")]
     NonAlphabeticCharacters,
-    #[error("String should be exactly 4 characters, found: {0}")]
     InvalidStringLength(usize),
 }
 
+// Written out rather than derived, since thiserror needs `std`.
+impl Display for ChunkTypeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NonAlphabeticCharacters => {
+                write!(f, "Characters can only be alphabetic (A-Z, a-z)")
+            }
+            Self::InvalidStringLength(len) => {
+                write!(f, "String should be exactly 4 characters, found: {}", len)
+            }
+        }
+    }
+}
+
+impl core::error::Error for ChunkTypeError {}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct ChunkType {
     code: [u8; 4],
@@ -64,9 +77,9 @@ impl FromStr for ChunkType {
     }
 }
 
-impl std::fmt::Display for ChunkType {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", std::str::from_utf8(&self.code).unwrap())
+impl Display for ChunkType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", core::str::from_utf8(&self.code).unwrap())
     }
 }
 
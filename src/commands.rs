@@ -0,0 +1,2753 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use anyhow::{bail, Context, Result};
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use pngme::analysis;
+use pngme::batch::Parser;
+use pngme::carve;
+use pngme::census::{self, Census};
+use pngme::cgbi;
+use pngme::chunk::{Chunk, ChunkError};
+use pngme::chunk_type::ChunkType;
+use pngme::container::{Container, ContainerError, Format, Gif, Jpeg, Segment};
+use pngme::crypto::{Encryption, Kdf, StreamParams};
+use pngme::decoder::CriticalPolicy;
+use pngme::detect::Location;
+use pngme::envelope::{self, Envelope, EnvelopeError, FileMeta, LogEntry, PartWriter, PayloadKind};
+use pngme::fec::Fec;
+use pngme::generate::{self, Size};
+use pngme::hash;
+use pngme::image::EncodeOptions;
+use pngme::index::{self, IndexEntry, PayloadIndex};
+use pngme::lazy;
+use pngme::lint::{self, Severity};
+use pngme::lsb::{self, LsbError, ScatterKey, Strategy};
+use pngme::messages::{self, MessageError};
+use pngme::normalize;
+use pngme::perceptual;
+use pngme::png::{Png, PngError};
+use pngme::polyglot;
+#[cfg(feature = "http")]
+use pngme::remote;
+use pngme::report;
+use pngme::resync::{self, Repair};
+use pngme::sanitize;
+use pngme::scan::{self, Finding, ScanError, Scanner};
+use pngme::shamir::ShamirError;
+use pngme::stealth::StealthKey;
+use pngme::storage::{self, Directory, Object, Storage};
+use pngme::timeline;
+use pngme::timestamp;
+use pngme::trailer;
+use pngme::vendor;
+use pngme::verify;
+use pngme::watermark::Watermark;
+use pngme::writer::PngWriter;
+
+use crate::args::{
+    AnalyzeArgs, AttachArgs, CapacityArgs, CarveArgs, CensusArgs, DecodeArgs, DedupeArgs,
+    DetectArgs, DiffArgs, EmbedArgs, EncodeArgs, ExtractArgs, GenerateArgs, GitFilterArgs,
+    HashArgs, HideArgs, IndexArgs, KeygenArgs, LintArgs, ManifestArgs, NormalizeArgs, PngMeArgs,
+    PolyglotArgs, PrintArgs, ReassembleArgs, RecoverArgs, ReencryptArgs, RemoveArgs, RepairArgs,
+    ReportArgs, RevealArgs, SanitizeArgs, ScanArgs, SimilarArgs, SplitArgs, StripArgs,
+    TimelineArgs, VerifyArgs, VerifyWatermarkArgs, WatermarkArgs,
+};
+use crate::git_filter;
+use crate::keys::IdentityArgs;
+use crate::manifest::{self, Discrepancy, Entry, Manifest};
+use crate::password::{self, PasswordArgs};
+use crate::template::{Template, TemplateContext};
+
+pub fn run(args: PngMeArgs) -> Result<()> {
+    match args {
+        PngMeArgs::Encode(args) => encode(args),
+        PngMeArgs::Decode(args) => decode(args),
+        PngMeArgs::Remove(args) => remove(args),
+        PngMeArgs::Reencrypt(args) => reencrypt(args),
+        PngMeArgs::Print(args) => print(args),
+        PngMeArgs::Extract(args) => extract(args),
+        PngMeArgs::Hash(args) => hash(args),
+        PngMeArgs::Index(args) => index(args),
+        PngMeArgs::Split(args) => split(args),
+        PngMeArgs::Reassemble(args) => reassemble(args),
+        PngMeArgs::Hide(args) => hide(args),
+        PngMeArgs::Reveal(args) => reveal(args),
+        PngMeArgs::Embed(args) => embed(args),
+        PngMeArgs::Recover(args) => recover(args),
+        PngMeArgs::Attach(args) => attach(args),
+        PngMeArgs::Polyglot(args) => make_polyglot(args),
+        PngMeArgs::Scan(args) => scan(args),
+        PngMeArgs::Detect(args) => detect(args),
+        PngMeArgs::Census(args) => census(args),
+        PngMeArgs::Similar(args) => similar(args),
+        PngMeArgs::Carve(args) => carve(args),
+        PngMeArgs::Verify(args) => verify(args),
+        PngMeArgs::Manifest(args) => manifest(args),
+        PngMeArgs::Lint(args) => lint(args),
+        PngMeArgs::Diff(args) => diff(args),
+        PngMeArgs::Normalize(args) => normalize(args),
+        PngMeArgs::Repair(args) => repair(args),
+        PngMeArgs::Strip(args) => strip(args),
+        PngMeArgs::Dedupe(args) => dedupe(args),
+        PngMeArgs::Sanitize(args) => sanitize(args),
+        PngMeArgs::GitFilter(args) => git_filter(args),
+        PngMeArgs::Capacity(args) => capacity(args),
+        PngMeArgs::Analyze(args) => analyze(args),
+        PngMeArgs::Timeline(args) => timeline(args),
+        PngMeArgs::Report(args) => report(args),
+        PngMeArgs::Watermark(args) => watermark(args),
+        PngMeArgs::VerifyWatermark(args) => verify_watermark(args),
+        PngMeArgs::Generate(args) => generate_cover(args),
+        PngMeArgs::Keygen(args) => keygen(args),
+        #[cfg(feature = "keychain")]
+        PngMeArgs::Keychain(args) => crate::keychain::run(args),
+        #[cfg(all(feature = "fuse", target_os = "linux"))]
+        PngMeArgs::Mount(args) => mount(args),
+        #[cfg(feature = "serve")]
+        PngMeArgs::Serve(args) => crate::serve::run(args),
+        PngMeArgs::Rpc(args) => crate::rpc::run(args),
+        #[cfg(all(feature = "daemon", unix))]
+        PngMeArgs::Daemon(args) => crate::daemon::run(args),
+    }
+}
+
+/// The bytes of `path`, downloaded if it is an `https://` URL and pngme
+/// was built with `http`.
+pub(crate) fn read_input(path: &Path) -> Result<Vec<u8>> {
+    #[cfg(feature = "http")]
+    if let Some(url) = path.to_str().filter(|path| remote::is_url(path)) {
+        return Ok(remote::fetch(url, pngme::limits::get().max_file_len)?);
+    }
+    fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+/// Fails on an input that can't be written back, a URL.
+fn check_writable(path: &Path) -> Result<()> {
+    if path
+        .to_str()
+        .is_some_and(|path| path.starts_with("https://"))
+    {
+        bail!("Can't write to {}; name an output file", path.display());
+    }
+    Ok(())
+}
+
+fn read_png(path: &Path) -> Result<Png> {
+    let bytes = read_input(path)?;
+    Png::try_from(bytes.as_slice()).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+/// Reads a PNG like [`read_png`], but lets chunks that fail their CRC check
+/// through when they hold error-corrected envelopes (or parts of a striped
+/// payload, checked once joined), which repair themselves when parsed, or
+/// stream-encrypted ones, whose frames are each authenticated and can be
+/// salvaged.
+fn read_damaged_png(path: &Path) -> Result<Png> {
+    let bytes = read_input(path)?;
+    let err = match Png::try_from(bytes.as_slice()) {
+        Ok(png) => return Ok(png),
+        Err(
+            err @ PngError::BadChunk {
+                source: ChunkError::ChecksumError,
+                ..
+            },
+        ) => err,
+        Err(err) => return Err(err).with_context(|| format!("Failed to parse {}", path.display())),
+    };
+    let png = Png::from_bytes_unchecked(&bytes)?;
+    let repairable = png.chunks().iter().filter(|c| !c.has_valid_crc()).all(|c| {
+        match Envelope::try_from(c.data()) {
+            Ok(envelope) => {
+                envelope.fec().is_some()
+                    || envelope.part_info().is_some()
+                    || envelope.stream_params().is_some()
+            }
+            Err(_) => false,
+        }
+    });
+    if !repairable {
+        return Err(err).with_context(|| format!("Failed to parse {}", path.display()));
+    }
+    Ok(png)
+}
+
+fn write_png(path: &Path, png: &Png) -> Result<()> {
+    write_file(path, &png.as_bytes())
+}
+
+/// The file at `path` when it is in a carrier format other than PNG, with
+/// new JPEG payloads going in `segment` segments, or `None` for anything
+/// else, which the PNG code paths read and report on.
+fn read_container(path: &Path, segment: Segment) -> Result<Option<Box<dyn Container>>> {
+    let mut magic = [0; 6];
+    let format = fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .ok()
+        .and_then(|()| Format::sniff(&magic));
+    let context = || format!("Failed to parse {}", path.display());
+    Ok(Some(match format {
+        Some(Format::Gif) => {
+            Box::new(Gif::try_from(read_input(path)?.as_slice()).with_context(context)?)
+        }
+        Some(Format::Jpeg) => Box::new(
+            Jpeg::try_from(read_input(path)?.as_slice())
+                .with_context(context)?
+                .with_segment(segment),
+        ),
+        Some(Format::Png) | None => return Ok(None),
+    }))
+}
+
+fn encode(args: EncodeArgs) -> Result<()> {
+    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+    if let Some(mut container) = read_container(&args.file_path, args.jpeg_segment)? {
+        return encode_container(container.as_mut(), chunk_type, &args);
+    }
+    if !args.span.is_empty() {
+        return encode_striped(chunk_type, &args);
+    }
+    if let Some(part_size) = args.part_size {
+        return encode_streaming(chunk_type, part_size, &args);
+    }
+    let mut png = read_png(&args.file_path)?;
+    let original_size = png.encoded_len();
+    let indexed = args.index || index::has_index(&png);
+    if args.append {
+        append_entry(&mut png, chunk_type, &args)?;
+    } else {
+        if let Some(name) = &args.name {
+            messages::remove_named(&mut png, name, None);
+        }
+        let chunk = Chunk::try_new(chunk_type, &encode_payload(&args)?)
+            .context("The payload doesn't fit in one chunk; split it with --part-size")?;
+        png.append_chunk(chunk);
+    }
+    if indexed {
+        index::refresh(&mut png);
+    }
+    if let Some(budget) = args.max_growth {
+        budget
+            .check(original_size, png.encoded_len())
+            .with_context(|| format!("Refusing to encode into {}", args.file_path.display()))?;
+    }
+    write_png(args.output.as_ref().unwrap_or(&args.file_path), &png)
+}
+
+/// Encodes into a file in another carrier format than PNG, where only the
+/// payload itself can go.
+fn encode_container(
+    container: &mut dyn Container,
+    chunk_type: ChunkType,
+    args: &EncodeArgs,
+) -> Result<()> {
+    if !args.span.is_empty()
+        || args.part_size.is_some()
+        || args.append
+        || args.index
+        || args.name.is_some()
+    {
+        bail!("--span, --part-size, --append, --index and --name need a PNG file");
+    }
+    let original_size = container.to_bytes().len() as u64;
+    container.insert_payload(chunk_type, &encode_payload(args)?)?;
+    let bytes = container.to_bytes();
+    if let Some(budget) = args.max_growth {
+        budget
+            .check(original_size, bytes.len() as u64)
+            .with_context(|| format!("Refusing to encode into {}", args.file_path.display()))?;
+    }
+    write_file(args.output.as_ref().unwrap_or(&args.file_path), &bytes)
+}
+
+/// Stores one part of the payload in each of FILE_PATH and the `--span`
+/// files, in that order.
+fn encode_striped(chunk_type: ChunkType, args: &EncodeArgs) -> Result<()> {
+    let paths: Vec<&PathBuf> = std::iter::once(&args.file_path).chain(&args.span).collect();
+    let count = u16::try_from(paths.len()).context("Too many files to stripe across")?;
+    let mut carriers = Vec::new();
+    for path in &paths {
+        carriers.push(read_png(path)?);
+    }
+    let parts = Envelope::stripe(&encode_payload(args)?, args.name.as_deref(), count);
+    for ((path, mut png), part) in paths.into_iter().zip(carriers).zip(parts) {
+        let original_size = png.encoded_len();
+        let indexed = args.index || index::has_index(&png);
+        if let Some(name) = &args.name {
+            messages::remove_named(&mut png, name, None);
+        }
+        png.append_chunk(Chunk::new(chunk_type.clone(), &part.as_bytes()));
+        if indexed {
+            index::refresh(&mut png);
+        }
+        if let Some(budget) = args.max_growth {
+            budget
+                .check(original_size, png.encoded_len())
+                .with_context(|| format!("Refusing to encode into {}", path.display()))?;
+        }
+        write_png(path, &png)?;
+    }
+    Ok(())
+}
+
+/// Writes the carrier's chunks, then the `--file` payload as part envelopes
+/// of at most `part_size` MiB, compressed and encrypted as it is read, then
+/// `IEND`: only the carrier and one part are ever in memory.
+fn encode_streaming(chunk_type: ChunkType, part_size: u32, args: &EncodeArgs) -> Result<()> {
+    let path = args.payload_file.as_ref().expect("clap requires --file");
+    if openpgp_requested(args) {
+        bail!("OpenPGP payloads can't be written with --part-size");
+    }
+    let mut png = read_png(&args.file_path)?;
+    if index::has_index(&png) {
+        bail!(
+            "{} has a payload index, which --part-size can't keep up to date",
+            args.file_path.display()
+        );
+    }
+    if let Some(name) = &args.name {
+        messages::remove_named(&mut png, name, None);
+    }
+    let payload =
+        fs::File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let meta = payload
+        .metadata()
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let file_meta = FileMeta {
+        name: path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        size: meta.len(),
+        modified: meta.modified().ok(),
+    };
+    let envelope = apply_options(Envelope::file(file_meta, Vec::new()), args);
+    let part_len = part_size as usize * 1024 * 1024;
+    let total = envelope::parts_needed(meta.len(), part_len)
+        .with_context(|| format!("{} is too large even in parts", path.display()))?;
+    let password = match args.encrypt {
+        true => Some(args.password.get(true)?),
+        false => None,
+    };
+    replace_file_with(args.output.as_ref().unwrap_or(&args.file_path), |file| {
+        let mut writer = PngWriter::new(io::BufWriter::new(file))?;
+        for chunk in png.chunks() {
+            if chunk.chunk_type().bytes() != *b"IEND" {
+                writer.write_chunk(chunk)?;
+            }
+        }
+        let mut parts = PartWriter::new(args.name.as_deref(), total, part_len, |part| {
+            writer.write_chunk(&Chunk::new(chunk_type.clone(), &part.as_bytes()))
+        });
+        let payload = io::BufReader::new(payload);
+        match &password {
+            Some(password) => {
+                let encryption = Encryption {
+                    cipher: args.cipher,
+                    kdf: kdf(args),
+                };
+                envelope.write_encrypted_streaming(
+                    payload,
+                    password.as_bytes(),
+                    &encryption,
+                    StreamParams::DEFAULT_FRAME_LEN,
+                    &mut parts,
+                )?
+            }
+            None => envelope.write_streaming(payload, &mut parts)?,
+        }
+        parts.finish()?;
+        let mut out = writer.finish()?;
+        out.write_all(png.trailer())?;
+        out.flush()?;
+        Ok(())
+    })
+}
+
+/// Chunk data for the requested payload. Plain messages are stored as-is,
+/// as they always have been; anything else is wrapped in an [`Envelope`].
+fn encode_payload(args: &EncodeArgs) -> Result<Vec<u8>> {
+    let envelope = match (&args.payload_file, &args.message) {
+        (Some(path), _) => Envelope::from_path(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+        (None, message) => {
+            let message = message.as_deref().unwrap_or_default();
+            if !needs_envelope(args) && !Envelope::is_envelope(message.as_bytes()) {
+                return Ok(message.into());
+            }
+            Envelope::text(message)
+        }
+    };
+    let envelope = apply_options(envelope, args);
+    #[cfg(feature = "openpgp")]
+    let envelope = crate::pgp::protect(envelope, &args.pgp, &args.password)?;
+    let envelope = with_fec(seal(envelope, args, None)?, args.fec);
+    serialize(&envelope, args, None)
+}
+
+fn with_fec(envelope: Envelope, fec: Option<Fec>) -> Envelope {
+    match fec {
+        Some(fec) => envelope.with_fec(fec),
+        None => envelope,
+    }
+}
+
+/// Whether the requested options can only be stored in an envelope.
+fn needs_envelope(args: &EncodeArgs) -> bool {
+    args.name.is_some()
+        || args.compress.is_some()
+        || args.checksum
+        || args.encrypt
+        || args.hmac
+        || !args.recipients.is_empty()
+        || args.fec.is_some()
+        || !args.span.is_empty()
+        || openpgp_requested(args)
+}
+
+#[cfg(feature = "openpgp")]
+fn openpgp_requested(args: &EncodeArgs) -> bool {
+    args.pgp.is_requested()
+}
+
+#[cfg(not(feature = "openpgp"))]
+fn openpgp_requested(_args: &EncodeArgs) -> bool {
+    false
+}
+
+fn kdf(args: &EncodeArgs) -> Kdf {
+    Kdf::Argon2id {
+        memory_kib: args.kdf_memory * 1024,
+        iterations: args.kdf_time,
+        parallelism: args.kdf_parallelism,
+    }
+}
+
+/// How an existing payload was encrypted, so that appending to it keeps it
+/// encrypted, and re-encrypting it keeps its settings.
+enum Sealing {
+    Password(Encryption),
+    /// Password encryption in frames of this many bytes.
+    Stream(Encryption, u32),
+    Recipients,
+    Deniable,
+}
+
+impl Sealing {
+    fn of(envelope: &Envelope) -> Option<Self> {
+        if envelope.is_encrypted_to_recipients() {
+            return Some(Self::Recipients);
+        }
+        if envelope.is_deniable() {
+            return Some(Self::Deniable);
+        }
+        if let Some(p) = envelope.stream_params() {
+            let encryption = Encryption {
+                cipher: p.cipher,
+                kdf: p.kdf,
+            };
+            return Some(Self::Stream(encryption, p.frame_len));
+        }
+        envelope.encryption_params().map(|p| {
+            Self::Password(Encryption {
+                cipher: p.cipher,
+                kdf: p.kdf,
+            })
+        })
+    }
+}
+
+/// Encrypts the envelope when `--encrypt` or `--recipient` is given, or when
+/// it replaces a payload that was encrypted as `previous`.
+fn seal(envelope: Envelope, args: &EncodeArgs, previous: Option<Sealing>) -> Result<Envelope> {
+    if !args.recipients.is_empty() {
+        return Ok(envelope.encrypt_to(&args.recipients)?);
+    }
+    let (encryption, frame_len) = match previous {
+        // Only one of the slots could be opened, so the other would be lost.
+        Some(Sealing::Deniable) => bail!(
+            "The payload may hold a decoy, which appending can't keep; \
+             encode it again without --append"
+        ),
+        _ if args.encrypt => {
+            let encryption = Encryption {
+                cipher: args.cipher,
+                kdf: kdf(args),
+            };
+            (
+                encryption,
+                args.stream.then_some(StreamParams::DEFAULT_FRAME_LEN),
+            )
+        }
+        Some(Sealing::Password(previous)) => (previous, None),
+        Some(Sealing::Stream(previous, frame_len)) => (previous, Some(frame_len)),
+        Some(Sealing::Recipients) => bail!(
+            "The payload is encrypted to public keys, which aren't stored in the file; \
+             pass them again with --recipient"
+        ),
+        None => return Ok(envelope),
+    };
+    let password = args.password.get(true)?;
+    if let Some(decoy) = decoy(args)? {
+        let decoy_password = password::decoy_password(args.decoy_password_file.as_deref())?;
+        if *decoy_password == *password {
+            bail!("The decoy password must differ from the password");
+        }
+        let decoy = (&decoy, decoy_password.as_bytes());
+        return Ok(envelope.encrypt_deniable(password.as_bytes(), Some(decoy), &encryption)?);
+    }
+    if let Some(frame_len) = frame_len {
+        return Ok(envelope.encrypt_stream(password.as_bytes(), &encryption, frame_len)?);
+    }
+    Ok(envelope.encrypt(password.as_bytes(), &encryption)?)
+}
+
+/// The `--decoy` or `--decoy-file` payload, compressed like the real one.
+fn decoy(args: &EncodeArgs) -> Result<Option<Envelope>> {
+    let decoy = match (&args.decoy_file, &args.decoy) {
+        (Some(path), _) => Envelope::from_path(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+        (None, Some(message)) => Envelope::text(message),
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(match args.compress {
+        Some(compression) => decoy.with_compression(compression),
+        None => decoy,
+    }))
+}
+
+/// Serializes the envelope, with an integrity tag when `--hmac` is given or
+/// when it replaces a payload that had one derived with `previous`.
+fn serialize(envelope: &Envelope, args: &EncodeArgs, previous: Option<Kdf>) -> Result<Vec<u8>> {
+    let kdf = match previous {
+        _ if args.hmac => kdf(args),
+        Some(previous) => previous,
+        None => return Ok(envelope.as_bytes()),
+    };
+    let password = args.password.get(true)?;
+    Ok(envelope.as_bytes_with_integrity(password.as_bytes(), kdf)?)
+}
+
+/// Decrypts `envelope` or checks its integrity tag if needed, asking for
+/// the password or loading identities only then.
+fn unlock(
+    envelope: Envelope,
+    password: &PasswordArgs,
+    identity: &IdentityArgs,
+) -> Result<Envelope> {
+    if envelope.has_integrity_tag() {
+        envelope.verify(password.get(false)?.as_bytes())?;
+        return Ok(envelope);
+    }
+    if !envelope.is_encrypted() {
+        return Ok(envelope);
+    }
+    if envelope.is_encrypted_to_recipients() {
+        return Ok(envelope.decrypt_with(&identity.load(password.profile())?)?);
+    }
+    let password = password.get(false)?;
+    Ok(envelope.decrypt(password.as_bytes())?)
+}
+
+fn apply_options(envelope: Envelope, args: &EncodeArgs) -> Envelope {
+    let envelope = match &args.name {
+        Some(name) => envelope.with_name(name),
+        None => envelope,
+    };
+    let envelope = match args.compress {
+        Some(compression) => envelope.with_compression(compression),
+        None => envelope,
+    };
+    if args.checksum {
+        envelope.with_checksum()
+    } else {
+        envelope
+    }
+}
+
+/// Adds the message as a new timestamped entry to the payload named
+/// `--name`, or else to the first chunk of `chunk_type`, starting a new log
+/// if there is none yet.
+fn append_entry(png: &mut Png, chunk_type: ChunkType, args: &EncodeArgs) -> Result<()> {
+    let entry = LogEntry::now(args.message.as_deref().unwrap_or_default());
+    let existing = match &args.name {
+        Some(name) => messages::find_named(png, name, None).ok(),
+        None => match png
+            .chunks()
+            .iter()
+            .position(|c| *c.chunk_type() == chunk_type)
+        {
+            Some(i) => Some((i, envelope_at(png, i)?)),
+            None => None,
+        },
+    };
+    match existing {
+        Some((i, envelope)) => {
+            let previous = Sealing::of(&envelope);
+            let previous_integrity = envelope.integrity_params().map(|p| p.kdf);
+            let fec = args.fec.or(envelope.fec());
+            let mut envelope = unlock(envelope, &args.password, &args.identity)?;
+            envelope.append_entry(&entry)?;
+            let envelope = seal(apply_options(envelope, args), args, previous)?;
+            let envelope = with_fec(envelope, fec);
+            let data = serialize(&envelope, args, previous_integrity)?;
+            let chunk_type = png.chunks()[i].chunk_type().clone();
+            png.replace_chunk_at(i, Chunk::new(chunk_type, &data));
+        }
+        None => {
+            let envelope = seal(apply_options(Envelope::log(&[entry]), args), args, None)?;
+            let envelope = with_fec(envelope, args.fec);
+            png.append_chunk(Chunk::new(chunk_type, &serialize(&envelope, args, None)?));
+        }
+    }
+    Ok(())
+}
+
+/// The envelope stored in chunk `index`, treating a bare message as text.
+fn envelope_at(png: &Png, index: usize) -> Result<Envelope> {
+    let chunk = &png.chunks()[index];
+    if Envelope::is_envelope(chunk.data()) {
+        Ok(Envelope::try_from(chunk.data())?)
+    } else {
+        Ok(Envelope::text(&chunk.data_as_string()?))
+    }
+}
+
+fn parse_chunk_type(chunk_type: Option<&str>) -> Result<Option<ChunkType>> {
+    Ok(chunk_type.map(ChunkType::from_str).transpose()?)
+}
+
+fn decode(args: DecodeArgs) -> Result<()> {
+    let chunk_type = parse_chunk_type(args.chunk_type.as_deref())?;
+    if let Some(container) = read_container(&args.file_path, Segment::default())? {
+        if args.name.is_some() || args.trailer || !args.span.is_empty() {
+            bail!("--name, --trailer and --span need a PNG file");
+        }
+        let chunk_type = chunk_type.expect("clap requires a chunk type without --name");
+        let data = container
+            .payload(&chunk_type)
+            .ok_or_else(|| ContainerError::NotFound(chunk_type.to_string()))?;
+        let chunk = Chunk::try_new(chunk_type.clone(), data)?;
+        return decode_chunk(&chunk, &chunk_type, &args);
+    }
+    if let (None, false, Some(chunk_type)) = (&args.name, args.trailer, &chunk_type) {
+        // Seek straight to the chunk; anything unusual, such as a damaged
+        // chunk that error correction may repair, takes the full read below.
+        if let Ok(Some(chunk)) = fs::File::open(&args.file_path)
+            .map(io::BufReader::new)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| Ok(lazy::find_chunk_in_reader(file, &chunk_type.to_string())?))
+        {
+            return decode_chunk(&chunk, chunk_type, &args);
+        }
+    }
+    let png = read_damaged_png(&args.file_path)?;
+    if args.trailer {
+        let envelope = Envelope::try_from(trailer::find(&png)?)
+            .context("The bytes after IEND don't hold a payload stored with `attach`")?;
+        return output_decoded(envelope, &args);
+    }
+    if let Some(name) = &args.name {
+        let (_, envelope) = messages::find_named(&png, name, chunk_type.as_ref())?;
+        let envelope = join_parts(envelope, chunk_type.as_ref(), &args.file_path, &args.span)?;
+        return output_decoded(envelope, &args);
+    }
+    let chunk_type = chunk_type.expect("clap requires a chunk type without --name");
+    let chunk = png
+        .chunk_by_type(&chunk_type.to_string())
+        .ok_or_else(|| PngError::ChunkNotFound(chunk_type.to_string()))?;
+    decode_chunk(chunk, &chunk_type, &args)
+}
+
+/// Decodes the payload in `chunk`, the first chunk of `chunk_type`.
+fn decode_chunk(chunk: &Chunk, chunk_type: &ChunkType, args: &DecodeArgs) -> Result<()> {
+    if !Envelope::is_envelope(chunk.data()) {
+        if args.check_only {
+            report_intact(None);
+            return Ok(());
+        }
+        return match &args.output {
+            Some(path) => write_file(path, chunk.data()),
+            None => {
+                println!("{}", chunk.data_as_string()?);
+                Ok(())
+            }
+        };
+    }
+    let envelope = Envelope::try_from(chunk.data())?;
+    let envelope = join_parts(envelope, Some(chunk_type), &args.file_path, &args.span)?;
+    output_decoded(envelope, args)
+}
+
+/// Opens the envelope and writes out its payload, or with `--check-only`
+/// only reports that it is intact.
+fn output_decoded(envelope: Envelope, args: &DecodeArgs) -> Result<()> {
+    let envelope = open_envelope(envelope, args)?;
+    if args.check_only {
+        report_intact(envelope.checksum());
+        return Ok(());
+    }
+    output_envelope(args.output.as_deref(), &envelope)
+}
+
+fn report_intact(checksum: Option<[u8; 32]>) {
+    match checksum {
+        Some(checksum) => println!("ok\tblake3 {}", hash::to_hex(&checksum)),
+        None => println!("ok\tno checksum stored"),
+    }
+}
+
+/// The whole envelope when `envelope` is one part of a striped payload,
+/// collecting the other parts from `file` (where encode --part-size leaves
+/// them all) and the `span` files; other envelopes are returned unchanged.
+fn join_parts(
+    envelope: Envelope,
+    chunk_type: Option<&ChunkType>,
+    file: &Path,
+    span: &[PathBuf],
+) -> Result<Envelope> {
+    if envelope.part_info().is_none() {
+        return Ok(envelope);
+    }
+    let mut parts = Vec::new();
+    for file in std::iter::once(file).chain(span.iter().map(PathBuf::as_path)) {
+        let png = read_damaged_png(file)?;
+        parts.extend(
+            messages::envelopes(&png, chunk_type)
+                .map(|(_, e)| e)
+                .filter(|e| e.part_info().is_some()),
+        );
+    }
+    let data = Envelope::join(&envelope, &parts)
+        .context("Pass every file of the striped payload with --span")?;
+    Ok(Envelope::try_from(data.as_slice())?)
+}
+
+/// Removes every protection layer of `envelope` that decode was given the
+/// keys for.
+fn open_envelope(envelope: Envelope, args: &DecodeArgs) -> Result<Envelope> {
+    if envelope.repaired() > 0 {
+        eprintln!(
+            "Repaired {} damaged bytes of the payload",
+            envelope.repaired()
+        );
+    }
+    let envelope = if args.salvage && envelope.stream_params().is_some() {
+        let password = args.password.get(false)?;
+        let (envelope, damaged) = envelope.decrypt_salvaging(password.as_bytes())?;
+        if !damaged.is_empty() {
+            let frames: Vec<_> = damaged.iter().map(u32::to_string).collect();
+            eprintln!(
+                "Warning: zero-filled the damaged frames of the payload: {}",
+                frames.join(", ")
+            );
+        }
+        envelope
+    } else {
+        unlock(envelope, &args.password, &args.identity)?
+    };
+    #[cfg(feature = "openpgp")]
+    let envelope = crate::pgp::open(envelope, &args.pgp, &args.password)?;
+    Ok(envelope)
+}
+
+fn output_envelope(output: Option<&Path>, envelope: &Envelope) -> Result<()> {
+    if envelope.is_openpgp() {
+        let Some(path) = output else {
+            bail!(
+                "The payload is an OpenPGP message; write it out with --output to open it with gpg"
+            );
+        };
+        write_file(path, envelope.payload())?;
+        eprintln!("Wrote the OpenPGP message to {}", path.display());
+        return Ok(());
+    }
+    match (output, envelope.kind()) {
+        (output, PayloadKind::Log) => {
+            let rendered = render_log(envelope)?;
+            match output {
+                Some(path) => write_file(path, rendered.as_bytes()),
+                None => {
+                    print!("{}", rendered);
+                    Ok(())
+                }
+            }
+        }
+        (_, PayloadKind::Encrypted) => Err(EnvelopeError::PasswordRequired.into()),
+        (_, PayloadKind::Part) => {
+            let info = envelope
+                .part_info()
+                .expect("part envelopes carry part info");
+            bail!(
+                "Payload is part {} of {} of a striped payload",
+                info.index,
+                info.total
+            )
+        }
+        (_, PayloadKind::Share) => {
+            let info = envelope
+                .share_info()
+                .expect("share envelopes carry share info");
+            bail!(
+                "Payload is share {} of {}; use reassemble with at least {} carriers",
+                info.x,
+                info.total,
+                info.threshold
+            )
+        }
+        (Some(path), _) => restore_payload(path, envelope),
+        (None, PayloadKind::Text) => {
+            println!("{}", std::str::from_utf8(envelope.payload())?);
+            Ok(())
+        }
+        (None, PayloadKind::File) => {
+            let meta = envelope.file_meta().expect("file envelopes carry metadata");
+            bail!(
+                "Payload is the file {:?} ({} bytes); use --output to extract it",
+                meta.name,
+                meta.size
+            )
+        }
+    }
+}
+
+/// One line per entry: the UTC timestamp (or `-`), a tab, then the text.
+fn render_log(envelope: &Envelope) -> Result<String> {
+    let mut out = String::new();
+    for entry in envelope.log_entries()? {
+        let time = entry
+            .time
+            .map(timestamp::format_utc)
+            .unwrap_or_else(|| "-".to_string());
+        out.push_str(&format!("{}\t{}\n", time, entry.text));
+    }
+    Ok(out)
+}
+
+pub(crate) fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    check_writable(path)?;
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Writes `contents` to a temporary file next to `path`, then renames it
+/// over `path`, so that a crash leaves either the old file or the new one.
+fn replace_file(path: &Path, contents: &[u8]) -> Result<()> {
+    replace_file_with(path, |file| Ok(file.write_all(contents)?))
+}
+
+/// Like [`replace_file`], with the contents written by `write`.
+fn replace_file_with(path: &Path, write: impl FnOnce(&mut fs::File) -> Result<()>) -> Result<()> {
+    check_writable(path)?;
+    let name = path
+        .file_name()
+        .with_context(|| format!("{} is not a file path", path.display()))?;
+    let temp = path.with_file_name(format!(".{}.pngme-tmp", name.to_string_lossy()));
+    let result = (|| -> Result<()> {
+        let mut file = fs::File::create(&temp)?;
+        if let Ok(meta) = fs::metadata(path) {
+            file.set_permissions(meta.permissions())?;
+        }
+        write(&mut file)?;
+        file.sync_all()?;
+        Ok(fs::rename(&temp, path)?)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result.with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Writes the payload to `path`, restoring an embedded file's original name
+/// (when `path` is a directory) and modification time.
+fn restore_payload(path: &Path, envelope: &Envelope) -> Result<()> {
+    let meta = envelope.file_meta();
+    let path = match meta {
+        Some(meta) if path.is_dir() => {
+            let name = Path::new(&meta.name)
+                .file_name()
+                .with_context(|| format!("Embedded file name {:?} is not usable", meta.name))?;
+            path.join(name)
+        }
+        _ => path.to_path_buf(),
+    };
+    write_file(&path, envelope.payload())?;
+    if let Some(modified) = meta.and_then(|m| m.modified) {
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(modified))
+            .with_context(|| format!("Failed to set modification time of {}", path.display()))?;
+    }
+    println!("{}", path.display());
+    Ok(())
+}
+
+fn remove(args: RemoveArgs) -> Result<()> {
+    let chunk_type = parse_chunk_type(args.chunk_type.as_deref())?;
+    if let Some(mut container) = read_container(&args.file_path, Segment::default())? {
+        if args.name.is_some() || args.trailer {
+            bail!("--name and --trailer need a PNG file");
+        }
+        let chunk_type = chunk_type.expect("clap requires a chunk type without --name");
+        container.remove_payload(&chunk_type)?;
+        return write_file(&args.file_path, &container.to_bytes());
+    }
+    let mut png = read_png(&args.file_path)?;
+    let indexed = index::has_index(&png);
+    match (&args.name, chunk_type) {
+        _ if args.trailer => {
+            trailer::detach(&mut png)?;
+        }
+        (Some(name), chunk_type) => {
+            if messages::remove_named(&mut png, name, chunk_type.as_ref()) == 0 {
+                return Err(MessageError::NameNotFound(name.clone()).into());
+            }
+        }
+        (None, Some(chunk_type)) => {
+            png.remove_chunk(&chunk_type.to_string())?;
+        }
+        (None, None) => unreachable!("clap requires a chunk type without --name or --trailer"),
+    }
+    if indexed && index::has_index(&png) {
+        index::refresh(&mut png);
+    }
+    write_png(&args.file_path, &png)
+}
+
+/// Decrypts the payload in memory and encrypts it again under the new
+/// password or recipients, keeping its cipher, key derivation, framing and
+/// error correction unless told otherwise.
+fn reencrypt(args: ReencryptArgs) -> Result<()> {
+    let chunk_type = parse_chunk_type(args.chunk_type.as_deref())?;
+    let mut png = read_png(&args.file_path)?;
+    let (index, envelope) = match (&args.name, chunk_type) {
+        (Some(name), chunk_type) => messages::find_named(&png, name, chunk_type.as_ref())?,
+        (None, Some(chunk_type)) => {
+            let index = png
+                .chunks()
+                .iter()
+                .position(|c| *c.chunk_type() == chunk_type)
+                .ok_or_else(|| PngError::ChunkNotFound(chunk_type.to_string()))?;
+            let data = png.chunks()[index].data();
+            if !Envelope::is_envelope(data) {
+                bail!("The payload is not encrypted");
+            }
+            (index, Envelope::try_from(data)?)
+        }
+        (None, None) => unreachable!("clap requires a chunk type without --name"),
+    };
+    if !envelope.is_encrypted() {
+        bail!("The payload is not encrypted");
+    }
+    let previous = match Sealing::of(&envelope) {
+        Some(Sealing::Deniable) => bail!(
+            "The payload may hold a decoy, which re-encrypting can't keep; \
+             encode it again instead"
+        ),
+        previous => previous,
+    };
+    let fec = envelope.fec();
+    let plain = unlock(envelope, &args.password, &args.identity)?;
+    let sealed = if !args.recipients.is_empty() {
+        plain.encrypt_to(&args.recipients)?
+    } else {
+        let (mut encryption, frame_len) = match previous {
+            Some(Sealing::Password(encryption)) => (encryption, None),
+            Some(Sealing::Stream(encryption, frame_len)) => (encryption, Some(frame_len)),
+            _ => (Encryption::default(), None),
+        };
+        if let Some(cipher) = args.cipher {
+            encryption.cipher = cipher;
+        }
+        let password = password::new_password(args.new_password_file.as_deref())?;
+        match frame_len {
+            Some(frame_len) => plain.encrypt_stream(password.as_bytes(), &encryption, frame_len)?,
+            None => plain.encrypt(password.as_bytes(), &encryption)?,
+        }
+    };
+    let chunk_type = png.chunks()[index].chunk_type().clone();
+    let data = with_fec(sealed, fec).as_bytes();
+    png.replace_chunk_at(index, Chunk::new(chunk_type, &data));
+    if index::has_index(&png) {
+        index::refresh(&mut png);
+    }
+    replace_file(
+        args.output.as_ref().unwrap_or(&args.file_path),
+        &png.as_bytes(),
+    )
+}
+
+fn print(args: PrintArgs) -> Result<()> {
+    // Written rather than printed, so a closed pipe is an error `main` can
+    // tell apart instead of a panic.
+    let mut stdout = io::stdout().lock();
+    #[cfg(all(feature = "daemon", unix))]
+    if args.daemon {
+        write!(
+            stdout,
+            "{}",
+            crate::daemon::query("print", &args.file_path)?
+        )?;
+        stdout.flush()?;
+        return Ok(());
+    }
+    if let Some(container) = read_container(&args.file_path, Segment::default())? {
+        for block in container.blocks() {
+            writeln!(stdout, "{} (length: {})", block.kind, block.len)?;
+        }
+        stdout.flush()?;
+        return Ok(());
+    }
+    let bytes = read_input(&args.file_path)?;
+    let png = Png::from_bytes_with_policy(&bytes, args.unknown_critical)
+        .with_context(|| format!("Failed to parse {}", args.file_path.display()))?;
+    write!(stdout, "{}", png)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn extract(args: ExtractArgs) -> Result<()> {
+    let template = Template::from_str(&args.output)?;
+    let wanted = args
+        .chunk_type
+        .as_deref()
+        .map(ChunkType::from_str)
+        .transpose()?;
+    let mut written: HashSet<PathBuf> = HashSet::new();
+    let mut count = 0;
+    for file in &args.files {
+        let png = read_png(file)?;
+        let matches = png
+            .chunks()
+            .iter()
+            .filter(|c| wanted.as_ref().is_none_or(|t| c.chunk_type() == t));
+        for (index, chunk) in matches.enumerate() {
+            let chunk_type = chunk.chunk_type().to_string();
+            let path = PathBuf::from(template.render(&TemplateContext {
+                input: file,
+                chunk_type: &chunk_type,
+                index,
+                count,
+            }));
+            if !written.insert(path.clone()) {
+                bail!(
+                    "Output template produced {} twice; add {{index}} or {{n}} to make names unique",
+                    path.display()
+                );
+            }
+            if path.exists() && !args.force {
+                bail!(
+                    "{} already exists (use --force to overwrite)",
+                    path.display()
+                );
+            }
+            write_file(&path, chunk.data())?;
+            println!("{}", path.display());
+            count += 1;
+        }
+    }
+    Ok(())
+}
+
+fn hash(args: HashArgs) -> Result<()> {
+    for file in &args.files {
+        let png = read_png(file)?;
+        let hash = match args.perceptual {
+            Some(method) => perceptual::perceptual_hash(&png, method)
+                .with_context(|| format!("Failed to decode {}", file.display()))?
+                .to_string(),
+            None => hash::to_hex(&hash::content_hash(&png)),
+        };
+        println!("{}  {}", hash, file.display());
+    }
+    Ok(())
+}
+
+fn index(args: IndexArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    if args.drop {
+        if !index::remove(&mut png) {
+            return Err(PngError::ChunkNotFound(PayloadIndex::CHUNK_TYPE.to_string()).into());
+        }
+        return write_png(&args.file_path, &png);
+    }
+    if args.rebuild {
+        index::refresh(&mut png);
+        write_png(&args.file_path, &png)?;
+    }
+    let index = index::read(&png)
+        .ok_or_else(|| PngError::ChunkNotFound(PayloadIndex::CHUNK_TYPE.to_string()))??;
+    for entry in index.entries() {
+        let kind = if entry.flags & IndexEntry::FLAG_PART == IndexEntry::FLAG_PART {
+            "part"
+        } else if entry.flags & IndexEntry::FLAG_FILE != 0 {
+            "file"
+        } else if entry.flags & IndexEntry::FLAG_LOG != 0 {
+            "log"
+        } else if entry.flags & IndexEntry::FLAG_ENCRYPTED != 0 {
+            "encrypted"
+        } else if entry.flags & IndexEntry::FLAG_SHARE != 0 {
+            "share"
+        } else {
+            "text"
+        };
+        println!(
+            "{}\t{}\t#{} @{}\t{} bytes\t{}",
+            entry.name.as_deref().unwrap_or("-"),
+            entry.chunk_type,
+            entry.chunk_index,
+            entry.offset,
+            entry.size,
+            kind
+        );
+    }
+    Ok(())
+}
+
+fn split(args: SplitArgs) -> Result<()> {
+    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+    let envelope = match (&args.payload_file, &args.message) {
+        (Some(path), _) => Envelope::from_path(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+        (None, message) => Envelope::text(message.as_deref().unwrap_or_default()),
+    };
+    let envelope = match &args.name {
+        Some(name) => envelope.with_name(name),
+        None => envelope,
+    };
+    let total = u8::try_from(args.carriers.len()).expect("clap limits the number of carriers");
+    let mut carriers = Vec::new();
+    for path in &args.carriers {
+        carriers.push(read_png(path)?);
+    }
+    let shares = envelope.split(args.threshold, total)?;
+    for ((path, mut png), share) in args.carriers.iter().zip(carriers).zip(shares) {
+        let indexed = args.index || index::has_index(&png);
+        if let Some(name) = &args.name {
+            messages::remove_named(&mut png, name, None);
+        }
+        png.append_chunk(Chunk::new(chunk_type.clone(), &share.as_bytes()));
+        if indexed {
+            index::refresh(&mut png);
+        }
+        write_png(path, &png)?;
+    }
+    Ok(())
+}
+
+fn reassemble(args: ReassembleArgs) -> Result<()> {
+    let chunk_type = parse_chunk_type(args.chunk_type.as_deref())?;
+    // Shares grouped by the secret they belong to, in order of discovery.
+    let mut sets: Vec<Vec<Envelope>> = Vec::new();
+    for file in &args.files {
+        let png = read_png(file)?;
+        let candidates = png
+            .chunks()
+            .iter()
+            .filter(|c| chunk_type.as_ref().is_none_or(|t| c.chunk_type() == t))
+            .filter(|c| Envelope::is_envelope(c.data()));
+        for chunk in candidates {
+            let envelope = Envelope::try_from(chunk.data())
+                .with_context(|| format!("Failed to parse a payload of {}", file.display()))?;
+            let Some(info) = envelope.share_info() else {
+                continue;
+            };
+            if args.name.is_some() && envelope.name() != args.name.as_deref() {
+                continue;
+            }
+            match sets
+                .iter_mut()
+                .find(|set| set[0].share_info().map(|i| i.set_id) == Some(info.set_id))
+            {
+                Some(set) => set.push(envelope),
+                None => sets.push(vec![envelope]),
+            }
+        }
+    }
+    let distinct = |set: &Vec<Envelope>| {
+        set.iter()
+            .filter_map(|s| s.share_info().map(|i| i.x))
+            .collect::<HashSet<_>>()
+            .len()
+    };
+    let Some(largest) = sets.iter().max_by_key(|set| distinct(set)) else {
+        return Err(match &args.name {
+            Some(name) => MessageError::NameNotFound(name.clone()).into(),
+            None => anyhow::anyhow!("No shares found in the given files"),
+        });
+    };
+    let complete = sets
+        .iter()
+        .find(|set| distinct(set) >= set[0].share_info().map_or(0, |i| i.threshold as usize));
+    let envelope = match complete {
+        Some(set) => Envelope::combine(set)?,
+        None => {
+            let info = largest[0].share_info().expect("only shares are collected");
+            return Err(ShamirError::NotEnoughShares {
+                needed: info.threshold,
+                got: distinct(largest),
+            })
+            .context("Not enough carriers to reassemble the payload");
+        }
+    };
+    output_envelope(args.output.as_deref(), &envelope)
+}
+
+fn hide(args: HideArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let envelope = match (&args.payload_file, &args.message) {
+        (Some(path), _) => Envelope::from_path(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+        (None, message) => Envelope::text(message.as_deref().unwrap_or_default()),
+    };
+    let envelope = match args.compress {
+        Some(compression) => envelope.with_compression(compression),
+        None => envelope,
+    };
+    let key = StealthKey::derive(args.password.get(true)?.as_bytes(), Kdf::default())?;
+    key.insert(&mut png, &key.seal(&envelope.as_bytes()));
+    if index::has_index(&png) {
+        index::refresh(&mut png);
+    }
+    write_png(args.output.as_ref().unwrap_or(&args.file_path), &png)
+}
+
+fn reveal(args: RevealArgs) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let key = StealthKey::derive(args.password.get(false)?.as_bytes(), Kdf::default())?;
+    let chunk = key
+        .find(&png)
+        .ok_or_else(|| PngError::ChunkNotFound(key.chunk_type().to_string()))
+        .context("Nothing is hidden in this file with this password")?;
+    let envelope = Envelope::try_from(key.open(chunk.data())?.as_slice())?;
+    output_envelope(args.output.as_deref(), &envelope)
+}
+
+fn embed(args: EmbedArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let envelope = match (&args.payload_file, &args.message) {
+        (Some(path), _) => Envelope::from_path(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+        (None, message) => Envelope::text(message.as_deref().unwrap_or_default()),
+    };
+    let envelope = match args.compress {
+        Some(compression) => envelope.with_compression(compression),
+        None => envelope,
+    };
+    let password = if args.encrypt || args.scatter {
+        Some(args.password.get(true)?)
+    } else {
+        None
+    };
+    let envelope = match &password {
+        Some(password) if args.encrypt => {
+            envelope.encrypt(password.as_bytes(), &Encryption::default())?
+        }
+        _ => envelope,
+    };
+    let options = EncodeOptions::default();
+    match &password {
+        Some(password) if args.scatter => {
+            let key = ScatterKey::derive(password.as_bytes(), Kdf::default())?;
+            lsb::embed_scattered(
+                &mut png,
+                &envelope.as_bytes(),
+                args.bits,
+                args.strategy,
+                &key,
+                &options,
+            )
+        }
+        _ => lsb::embed(
+            &mut png,
+            &envelope.as_bytes(),
+            args.bits,
+            args.strategy,
+            &options,
+        ),
+    }
+    .with_context(|| {
+        format!(
+            "Failed to embed the payload in {}",
+            args.file_path.display()
+        )
+    })?;
+    if index::has_index(&png) {
+        index::refresh(&mut png);
+    }
+    write_png(args.output.as_ref().unwrap_or(&args.file_path), &png)
+}
+
+fn recover(args: RecoverArgs) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let password = if args.scatter {
+        Some(args.password.get(false)?)
+    } else {
+        None
+    };
+    let data = match &password {
+        Some(password) => {
+            let key = ScatterKey::derive(password.as_bytes(), Kdf::default())?;
+            lsb::extract_scattered(&png, &key)?
+        }
+        None => lsb::extract(&png)?,
+    };
+    let envelope = Envelope::try_from(data.as_slice())
+        .context("The pixels don't hold a payload embedded with `embed`")?;
+    let envelope = match (envelope.is_encrypted(), password) {
+        (true, Some(password)) => envelope.decrypt(password.as_bytes())?,
+        (true, None) => envelope.decrypt(args.password.get(false)?.as_bytes())?,
+        (false, _) => envelope,
+    };
+    output_envelope(args.output.as_deref(), &envelope)
+}
+
+fn attach(args: AttachArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let envelope = match (&args.payload_file, &args.message) {
+        (Some(path), _) => Envelope::from_path(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+        (None, message) => Envelope::text(message.as_deref().unwrap_or_default()),
+    };
+    let envelope = match args.compress {
+        Some(compression) => envelope.with_compression(compression),
+        None => envelope,
+    };
+    let envelope = if args.encrypt {
+        envelope.encrypt(args.password.get(true)?.as_bytes(), &Encryption::default())?
+    } else {
+        envelope
+    };
+    trailer::attach(&mut png, &envelope.as_bytes())?;
+    eprintln!(
+        "Warning: the payload is stored after IEND; editors that re-save the image drop \
+         it, and some tools and decoders truncate the file at IEND"
+    );
+    write_png(args.output.as_ref().unwrap_or(&args.file_path), &png)
+}
+
+fn make_polyglot(args: PolyglotArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let zip = fs::read(&args.archive)
+        .with_context(|| format!("Failed to read {}", args.archive.display()))?;
+    polyglot::create(&mut png, &zip)
+        .with_context(|| format!("Failed to append {}", args.archive.display()))?;
+    write_png(args.output.as_ref().unwrap_or(&args.file_path), &png)
+}
+
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+fn mount(args: crate::args::MountArgs) -> Result<()> {
+    use pngme::fuse::{self, Tree};
+
+    let bytes = read_input(&args.file_path)?;
+    let png = Png::try_from(bytes.as_slice())
+        .with_context(|| format!("Failed to parse {}", args.file_path.display()))?;
+    let Some(archive) = polyglot::detect_in(&bytes, bytes.len() - png.trailer().len()) else {
+        bail!(
+            "{} holds no ZIP archive after IEND (`pngme polyglot` adds one)",
+            args.file_path.display()
+        );
+    };
+    let mut tree = Tree::new();
+    if let Ok(modified) = fs::metadata(&args.file_path).and_then(|meta| meta.modified()) {
+        tree.add_directory("", modified);
+    }
+    for (index, name) in archive.entries.iter().enumerate() {
+        let modified = archive.modified(index);
+        match name.ends_with('/') {
+            true => tree.add_directory(name, modified),
+            false => tree.add_file(name, index, archive.entry_len(index), modified),
+        }
+    }
+    eprintln!(
+        "Mounted {} on {}; unmount it or press Ctrl-C to stop",
+        args.file_path.display(),
+        args.mountpoint.display()
+    );
+    fuse::mount(&tree, &args.mountpoint, |index| {
+        archive.extract(&bytes, index).map_err(|e| {
+            eprintln!("Failed to extract {}: {}", archive.entries[index], e);
+            io::Error::other(e)
+        })
+    })?;
+    Ok(())
+}
+
+fn scan(args: ScanArgs) -> Result<()> {
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    if args.io_uring {
+        #[cfg(all(feature = "mmap", unix))]
+        if args.mmap {
+            bail!("--io-uring reads the files, so it can't be combined with --mmap");
+        }
+        return scan_batched(&args.files);
+    }
+    let mut sources = Vec::new();
+    for file in &args.files {
+        match is_bucket(file) {
+            true => sources.extend(list_pngs(file)?),
+            false => sources.push(Source::File(file.clone())),
+        }
+    }
+    // Files are scanned concurrently with the parallel feature, but reported
+    // in order, up to the first that fails.
+    let reports = pngme::parallel::map(&sources, |source| {
+        #[cfg(all(feature = "mmap", unix))]
+        if let (true, Source::File(file)) = (args.mmap, source) {
+            // SAFETY: --mmap is documented as only for files nothing else
+            // is writing to; asking for it is the caller's promise.
+            let map = unsafe { pngme::mmap::Mmap::open(file) }
+                .with_context(|| format!("Failed to map {}", file.display()))?;
+            return scan_bytes(file, &map);
+        }
+        let bytes = source.read()?;
+        scan_bytes(&source.name(), &bytes)
+    });
+    for report in reports {
+        for line in report? {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+/// Like `scan`, reading a batch of files at a time through io_uring and
+/// scanning each batch once it is in.
+#[cfg(all(feature = "uring", target_os = "linux"))]
+fn scan_batched(files: &[PathBuf]) -> Result<()> {
+    use pngme::uring::Reader;
+
+    let mut reader = Reader::default_depth().context("Failed to set up io_uring")?;
+    let limit = pngme::limits::get().max_file_len.saturating_add(1);
+    for batch in files.chunks(Reader::DEFAULT_DEPTH as usize) {
+        let mut read = Vec::new();
+        let mut failed = None;
+        for (file, bytes) in batch.iter().zip(reader.read_files(batch, limit)?) {
+            match bytes {
+                Ok(bytes) => read.push((file, bytes)),
+                Err(e) => {
+                    failed = Some(
+                        anyhow::Error::new(e).context(format!("Failed to read {}", file.display())),
+                    );
+                    break;
+                }
+            }
+        }
+        let reports = pngme::parallel::map(&read, |(file, bytes)| scan_bytes(file, bytes));
+        for report in reports {
+            for line in report? {
+                println!("{}", line);
+            }
+        }
+        if let Some(e) = failed {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+/// Reports what the PNG file in `bytes` hides, one line per finding.
+fn scan_bytes(file: &Path, bytes: &[u8]) -> Result<Vec<String>> {
+    let (_, findings) =
+        scan::inspect(bytes).with_context(|| format!("Failed to parse {}", file.display()))?;
+    let mut lines: Vec<String> = findings
+        .iter()
+        .map(|finding| match finding {
+            Finding::Payload {
+                chunk_type,
+                name: Some(name),
+            } => format!(
+                "{}\t{}\tpngme payload {:?}",
+                file.display(),
+                chunk_type,
+                name
+            ),
+            Finding::Payload {
+                chunk_type,
+                name: None,
+            } => format!("{}\t{}\tpngme payload", file.display(), chunk_type),
+            Finding::TrailerPayload { len } => format!(
+                "{}\tafter IEND\tpngme payload ({} bytes)",
+                file.display(),
+                len
+            ),
+            Finding::Polyglot { entries, aligned } => format!(
+                "{}\tafter IEND\tZIP archive of {} entries: PNG/ZIP polyglot{}",
+                file.display(),
+                entries,
+                if *aligned {
+                    ""
+                } else {
+                    " (offsets not adjusted)"
+                }
+            ),
+            Finding::UnknownTrailer { len } => {
+                format!("{}\tafter IEND\t{} unknown bytes", file.display(), len)
+            }
+            Finding::HighEntropy(stats) => format!(
+                "{}\t{}\thigh entropy: {:.2} bits/byte, deflates to {:.0}%",
+                file.display(),
+                stats.chunk_type,
+                stats.entropy,
+                stats.deflate_ratio * 100.0
+            ),
+            Finding::Vendor {
+                chunk_type,
+                vendor,
+                description,
+            } => format!(
+                "{}\t{}\t{} chunk: {}",
+                file.display(),
+                chunk_type,
+                vendor,
+                description
+            ),
+        })
+        .collect();
+    if lines.is_empty() {
+        lines.push(format!("{}\tnothing found", file.display()));
+    }
+    Ok(lines)
+}
+
+/// Reports every payload of every file as each file is searched, then how
+/// many files carry any, and fails with the first file that couldn't be
+/// searched.
+fn detect(args: DetectArgs) -> Result<()> {
+    let mut scanner = Scanner::new();
+    if let Some(threads) = args.threads {
+        scanner = scanner.with_threads(threads.into());
+    }
+    let (mut files, mut carriers, mut payloads, mut bytes, mut encrypted) = (0, 0, 0, 0, 0);
+    let mut failures = Vec::new();
+    for result in scanner.detect(&args.paths) {
+        let (path, detected) = match result {
+            Ok(found) => found,
+            Err(e) => {
+                let (path, reason) = match &e {
+                    ScanError::Io { path, source } => (path, source.to_string()),
+                    ScanError::Parse { path, source } => (path, source.to_string()),
+                    ScanError::Decode { path, source } => (path, source.to_string()),
+                };
+                println!("{}	failed	{}", path.display(), reason);
+                failures.push(e.into());
+                continue;
+            }
+        };
+        files += 1;
+        carriers += !detected.is_empty() as usize;
+        for payload in &detected {
+            let location = match &payload.location {
+                Location::Chunk(chunk_type) => chunk_type.to_string(),
+                Location::Trailer => "after IEND".to_string(),
+            };
+            let kind = match payload.kind {
+                Some(PayloadKind::Text) => "text",
+                Some(PayloadKind::File) => "file",
+                Some(PayloadKind::Log) => "log",
+                Some(PayloadKind::Encrypted) => "encrypted",
+                Some(PayloadKind::Share) => "share",
+                Some(PayloadKind::Part) => "part",
+                None => "damaged",
+            };
+            println!(
+                "{}	{}	{}	{} bytes	{}	{}",
+                path.display(),
+                location,
+                payload.name.as_deref().unwrap_or("-"),
+                payload.len,
+                kind,
+                if payload.encrypted {
+                    "encrypted"
+                } else {
+                    "plain"
+                }
+            );
+            payloads += 1;
+            bytes += payload.len;
+            encrypted += payload.encrypted as usize;
+        }
+    }
+    println!(
+        "{} of {} files carry payloads: {} payloads, {} bytes, {} encrypted",
+        carriers, files, payloads, bytes, encrypted
+    );
+    fail_if_any(failures, "couldn't be searched")
+}
+
+/// Prints a line per chunk type, registered types first and vendor chunks
+/// named, once every file has been read.
+fn similar(args: SimilarArgs) -> Result<()> {
+    let mut scanner = Scanner::new();
+    if let Some(threads) = args.threads {
+        scanner = scanner.with_threads(threads.into());
+    }
+    let mut hashes = Vec::new();
+    let mut failures = Vec::new();
+    for result in scanner.perceptual_hashes(&args.paths, args.method) {
+        match result {
+            Ok(hashed) => hashes.push(hashed),
+            Err(e) => {
+                let (path, reason) = match &e {
+                    ScanError::Io { path, source } => (path, source.to_string()),
+                    ScanError::Parse { path, source } => (path, source.to_string()),
+                    ScanError::Decode { path, source } => (path, source.to_string()),
+                };
+                println!("{}\tfailed\t{}", path.display(), reason);
+                failures.push(e.into());
+            }
+        }
+    }
+    // Every pair: comparing two hashes is one XOR, so even tens of
+    // thousands of files take no time next to decoding them.
+    hashes.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut pairs = Vec::new();
+    for (i, (path, hash)) in hashes.iter().enumerate() {
+        for (other_path, other_hash) in &hashes[i + 1..] {
+            let distance = hash.distance(*other_hash);
+            if distance <= args.max_distance {
+                pairs.push((distance, path, other_path));
+            }
+        }
+    }
+    pairs.sort();
+    for (distance, path, other_path) in &pairs {
+        println!("{}\t{}\t{}", distance, path.display(), other_path.display());
+    }
+    println!(
+        "{} similar pair{} among {} files",
+        pairs.len(),
+        if pairs.len() == 1 { "" } else { "s" },
+        hashes.len()
+    );
+    fail_if_any(failures, "failed to be hashed")
+}
+
+fn census(args: CensusArgs) -> Result<()> {
+    let mut scanner = Scanner::new();
+    if let Some(threads) = args.threads {
+        scanner = scanner.with_threads(threads.into());
+    }
+    let mut census = Census::with_examples(args.examples);
+    let mut failures = Vec::new();
+    for result in scanner.census(&args.paths) {
+        match result {
+            Ok((path, headers)) => census.add(&path, &headers),
+            Err(e) => {
+                let (path, reason) = match &e {
+                    ScanError::Io { path, source } => (path, source.to_string()),
+                    ScanError::Parse { path, source } => (path, source.to_string()),
+                    ScanError::Decode { path, source } => (path, source.to_string()),
+                };
+                println!("{}\tfailed\t{}", path.display(), reason);
+                failures.push(e.into());
+            }
+        }
+    }
+    let (registered, unregistered): (Vec<_>, Vec<_>) = census
+        .entries()
+        .into_iter()
+        .partition(|entry| census::is_registered(&entry.chunk_type.bytes()));
+    for entry in registered.iter().chain(&unregistered) {
+        let code = entry.chunk_type.bytes();
+        let class = if census::is_registered(&code) {
+            "registered".to_string()
+        } else if let Some(known) = vendor::lookup(&code) {
+            format!("unregistered ({})", known.vendor)
+        } else {
+            "unregistered".to_string()
+        };
+        let examples: Vec<String> = entry
+            .examples
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        println!(
+            "{}\t{}\t{} files\t{} chunks\t{} bytes\t{}",
+            entry.chunk_type,
+            class,
+            entry.files,
+            entry.chunks,
+            entry.bytes,
+            examples.join(", ")
+        );
+    }
+    println!(
+        "{} files: {} chunk types, {} registered and {} not",
+        census.files(),
+        registered.len() + unregistered.len(),
+        registered.len(),
+        unregistered.len()
+    );
+    fail_if_any(failures, "couldn't be read")
+}
+
+/// Prints a line per PNG found, writing it out with `--output`, then how
+/// many were whole.
+fn carve(args: CarveArgs) -> Result<()> {
+    #[cfg(all(feature = "mmap", unix))]
+    if args.mmap {
+        // SAFETY: as in `scan`, --mmap is the caller's promise that nothing
+        // else writes to the blob while it is carved.
+        let map = unsafe { pngme::mmap::Mmap::open(&args.blob) }
+            .with_context(|| format!("Failed to map {}", args.blob.display()))?;
+        return carve_bytes(&map, args.output.as_deref());
+    }
+    let blob = read_input(&args.blob)?;
+    carve_bytes(&blob, args.output.as_deref())
+}
+
+fn carve_bytes(blob: &[u8], output: Option<&Path>) -> Result<()> {
+    if let Some(dir) = output {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let (mut found, mut intact) = (0, 0);
+    for carved in carve::carve(blob) {
+        let size = match carved.size {
+            Some((width, height)) => format!("{}x{}", width, height),
+            None => "-".to_string(),
+        };
+        let mut line = format!(
+            "{:#x}\t{} bytes\t{}\t{} chunks\t{}",
+            carved.offset, carved.len, size, carved.chunks, carved
+        );
+        if let Some(dir) = output {
+            let path = dir.join(format!("{:010x}.png", carved.offset));
+            fs::write(&path, carved.bytes(blob))
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            line.push_str(&format!("\t{}", path.display()));
+        }
+        println!("{}", line);
+        found += 1;
+        intact += carved.is_intact() as usize;
+    }
+    println!("{} PNG files found, {} intact", found, intact);
+    Ok(())
+}
+
+/// Reports each file as it is verified, and fails with the first damaged
+/// one found once all have been checked.
+fn verify(args: VerifyArgs) -> Result<()> {
+    #[cfg(all(feature = "daemon", unix))]
+    if args.daemon {
+        return verify_with_daemon(&args.paths);
+    }
+    if let Some(manifest) = &args.manifest {
+        #[cfg(feature = "openpgp")]
+        let signers = args.manifest_signers.as_slice();
+        #[cfg(not(feature = "openpgp"))]
+        let signers = &[];
+        return verify_manifest(manifest, signers, args.unknown_critical);
+    }
+    let mut scanner = Scanner::new().with_critical_policy(args.unknown_critical);
+    if let Some(threads) = args.threads {
+        scanner = scanner.with_threads(threads.into());
+    }
+    let (buckets, paths): (Vec<&PathBuf>, Vec<&PathBuf>) =
+        args.paths.iter().partition(|path| is_bucket(path));
+    let mut failures = Vec::new();
+    for result in scanner.verify(paths) {
+        match result {
+            Ok((path, verified)) => print_verified(&path, &verified),
+            Err(e) => {
+                let (path, reason) = match &e {
+                    ScanError::Io { path, source } => (path, source.to_string()),
+                    ScanError::Parse { path, source } => (path, source.to_string()),
+                    ScanError::Decode { path, source } => (path, source.to_string()),
+                };
+                println!("{}\tfailed\t{}", path.display(), reason);
+                failures.push(e.into());
+            }
+        }
+    }
+    for bucket in buckets {
+        let sources = list_pngs(bucket)?;
+        let results = pngme::parallel::map(&sources, |source| -> Result<verify::Verified> {
+            let bytes = source.read()?;
+            Ok(verify::verify_with_policy(
+                bytes.as_slice(),
+                args.unknown_critical,
+            )?)
+        });
+        for (source, result) in sources.iter().zip(results) {
+            match result {
+                Ok(verified) => print_verified(&source.name(), &verified),
+                Err(e) => {
+                    println!("{}\tfailed\t{}", source.name().display(), e);
+                    failures
+                        .push(e.context(format!("Failed to verify {}", source.name().display())));
+                }
+            }
+        }
+    }
+    fail_if_any(failures, "failed verification")
+}
+
+/// Checks each file `manifest` lists, with a line per discrepancy, and
+/// fails if any file has one. With `signers`, the manifest must be signed
+/// by one of them.
+fn verify_manifest(manifest: &Path, signers: &[PathBuf], policy: CriticalPolicy) -> Result<()> {
+    let text = fs::read_to_string(manifest)
+        .with_context(|| format!("Failed to read {}", manifest.display()))?;
+    let base = manifest.parent().unwrap_or(Path::new(""));
+    let manifest = Manifest::parse(&text, base)?;
+    #[cfg(feature = "openpgp")]
+    if !signers.is_empty() {
+        crate::pgp::check_manifest(&manifest, signers)?;
+    }
+    #[cfg(not(feature = "openpgp"))]
+    let _ = signers;
+    let results = pngme::parallel::map(&manifest.entries, |entry| -> Result<Vec<Discrepancy>> {
+        let bytes = read_input(&entry.path)?;
+        Ok(entry.check(&bytes, policy)?)
+    });
+    let mut failures = Vec::new();
+    for (entry, result) in manifest.entries.iter().zip(results) {
+        let path = entry.path.display();
+        match result {
+            Ok(discrepancies) if discrepancies.is_empty() => println!("{}\tok", path),
+            Ok(discrepancies) => {
+                for discrepancy in &discrepancies {
+                    println!("{}\tmismatch\t{}", path, discrepancy);
+                }
+                failures.push(anyhow::anyhow!(
+                    "{} doesn't match the manifest: {}",
+                    path,
+                    discrepancies[0]
+                ));
+            }
+            Err(e) => {
+                println!("{}\tfailed\t{:#}", path, e);
+                failures.push(e.context(format!("Failed to verify {}", path)));
+            }
+        }
+    }
+    fail_if_any(failures, "don't match the manifest")
+}
+
+/// Writes the manifest `verify --manifest` checks files against, of every
+/// PNG file under the paths given.
+fn manifest(args: ManifestArgs) -> Result<()> {
+    let mut sources = Vec::new();
+    for path in &args.paths {
+        match path.is_dir() {
+            true => sources.extend(list_pngs(path)?),
+            false => sources.push(Source::File(path.clone())),
+        }
+    }
+    let base = args
+        .output
+        .as_deref()
+        .and_then(Path::parent)
+        .unwrap_or(Path::new(""));
+    let results = pngme::parallel::map(&sources, |source| -> Result<Entry> {
+        let path = source.name();
+        let name = manifest::name_from(&path, base)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let bytes = source.read()?;
+        Ok(Entry::of(name, &bytes, CriticalPolicy::default())?)
+    });
+    let entries = sources
+        .iter()
+        .zip(results)
+        .map(|(source, result)| {
+            result.with_context(|| format!("Failed to parse {}", source.name().display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let manifest = Manifest::new(entries);
+    #[cfg(feature = "openpgp")]
+    let manifest = match &args.sign {
+        Some(key) => crate::pgp::sign_manifest(manifest, key, &args.password)?,
+        None => manifest,
+    };
+    let text = format!("{}\n", manifest);
+    match &args.output {
+        Some(output) => {
+            fs::write(output, text).with_context(|| format!("Failed to write {}", output.display()))
+        }
+        None => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(text.as_bytes())?;
+            stdout.flush()?;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(feature = "daemon", unix))]
+fn verify_with_daemon(paths: &[PathBuf]) -> Result<()> {
+    use crate::daemon::{self, DaemonError};
+
+    let mut failures = Vec::new();
+    for path in paths {
+        let body = daemon::query("verify", path)?;
+        for (code, line) in daemon::verify_lines(&body)? {
+            println!("{}", line);
+            if code != 0 {
+                failures.push(
+                    DaemonError::Failed {
+                        code,
+                        message: line.replacen('\t', ": ", 2),
+                    }
+                    .into(),
+                );
+            }
+        }
+    }
+    fail_if_any(failures, "failed verification")
+}
+
+fn print_verified(path: &Path, verified: &verify::Verified) {
+    println!("{}", verified_line(path, verified));
+}
+
+/// What `verify` shows for a file that passed.
+pub(crate) fn verified_line(path: &Path, verified: &verify::Verified) -> String {
+    let mut line = format!(
+        "{}\tok\t{} chunks, {} bytes",
+        path.display(),
+        verified.chunks,
+        verified.len
+    );
+    if verified.unknown_critical > 0 {
+        line.push_str(&format!(
+            ", {} unknown critical chunks",
+            verified.unknown_critical
+        ));
+    }
+    line
+}
+
+/// Reports every lint of every file, and fails with the first error (or
+/// warning, with `--strict`) once all have been checked.
+fn lint(args: LintArgs) -> Result<()> {
+    let mut failures = Vec::new();
+    for file in &args.files {
+        let bytes = read_input(file)?;
+        let png = Png::from_bytes_unchecked(&bytes)
+            .with_context(|| format!("Failed to parse {}", file.display()))?;
+        let lints: Vec<_> = lint::lint_with_profile(&png, args.profile)
+            .into_iter()
+            .filter(|lint| !args.allowed.contains(&lint.rule))
+            .collect();
+        for lint in &lints {
+            let chunk = match lint.chunk {
+                Some(index) => format!("chunk {} ({})", index, png.chunks()[index].chunk_type()),
+                None => "file".to_string(),
+            };
+            println!(
+                "{}\t{}\t{}\t{}\t{}",
+                file.display(),
+                lint.rule.id(),
+                lint.severity().as_str(),
+                chunk,
+                lint.message
+            );
+        }
+        let failed = lints
+            .into_iter()
+            .find(|lint| args.strict || lint.severity() == Severity::Error);
+        match failed {
+            Some(lint) => failures.push(
+                anyhow::Error::new(lint)
+                    .context(format!("{} doesn't pass linting", file.display())),
+            ),
+            None => println!("{}\tok", file.display()),
+        }
+    }
+    fail_if_any(failures, "failed linting")
+}
+
+fn diff(args: DiffArgs) -> Result<()> {
+    let (first, second) = (read_png(&args.first)?, read_png(&args.second)?);
+    let names = format!("{} and {}", args.first.display(), args.second.display());
+    if args.pixels {
+        let equal = first
+            .pixels_equal(&second)
+            .with_context(|| format!("Failed to decode {}", names))?;
+        if !equal {
+            bail!("{} show different pixels", names);
+        }
+        println!("same pixels");
+        return Ok(());
+    }
+    // Chunks are matched as they are, wherever they are, so reordered files
+    // only differ in their order.
+    let mut matched = vec![false; second.chunks().len()];
+    let mut differences = 0;
+    for (index, chunk) in first.chunks().iter().enumerate() {
+        let found = second.chunks().iter().zip(&matched).position(|(c, &used)| {
+            !used && c.chunk_type() == chunk.chunk_type() && c.data() == chunk.data()
+        });
+        match found {
+            Some(other) => matched[other] = true,
+            None => {
+                println!(
+                    "-\t#{}\t{}\t{} bytes",
+                    index,
+                    chunk.chunk_type(),
+                    chunk.length()
+                );
+                differences += 1;
+            }
+        }
+    }
+    for (index, chunk) in second.chunks().iter().enumerate() {
+        if !matched[index] {
+            println!(
+                "+\t#{}\t{}\t{} bytes",
+                index,
+                chunk.chunk_type(),
+                chunk.length()
+            );
+            differences += 1;
+        }
+    }
+    if first.trailer() != second.trailer() {
+        for (sign, png) in [("-", &first), ("+", &second)] {
+            if !png.trailer().is_empty() {
+                println!("{}\tafter IEND\t{} bytes", sign, png.trailer().len());
+            }
+        }
+        differences += 1;
+    }
+    let moved = differences == 0
+        && first
+            .chunks()
+            .iter()
+            .zip(second.chunks())
+            .any(|(a, b)| a.chunk_type() != b.chunk_type() || a.data() != b.data());
+    match (differences, moved) {
+        (0, false) => {
+            println!("same chunks");
+            Ok(())
+        }
+        (0, true) => bail!("{} hold the same chunks in a different order", names),
+        (differences, _) => bail!(
+            "{} differ in {} place{}",
+            names,
+            differences,
+            if differences == 1 { "" } else { "s" }
+        ),
+    }
+}
+
+fn normalize(args: NormalizeArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let indexed = index::has_index(&png);
+    let converted = cgbi::convert(&mut png)
+        .with_context(|| format!("Failed to convert {} from CgBI", args.file_path.display()))?;
+    if converted {
+        println!("converted from Apple CgBI");
+    }
+    let normalized = normalize::normalize(&mut png);
+    let changed = converted || normalized.changed();
+    if changed && indexed {
+        index::refresh(&mut png);
+    }
+    println!(
+        "moved {} chunk{}, removed {} duplicate{}",
+        normalized.moved,
+        if normalized.moved == 1 { "" } else { "s" },
+        normalized.removed,
+        if normalized.removed == 1 { "" } else { "s" }
+    );
+    match (&args.output, args.dry_run) {
+        (_, true) => Ok(()),
+        (Some(output), false) => write_png(output, &png),
+        (None, false) if changed => write_png(&args.file_path, &png),
+        (None, false) => Ok(()),
+    }
+}
+
+fn repair(args: RepairArgs) -> Result<()> {
+    let bytes = read_input(&args.file_path)?;
+    let mut resynced = resync::resync(&bytes)
+        .with_context(|| format!("Failed to read {}", args.file_path.display()))?;
+    if args.bit_flips {
+        resynced.fix_bit_flips();
+    }
+    for repair in &resynced.repairs {
+        println!("{}", repair);
+    }
+    let fixed = resynced
+        .repairs
+        .iter()
+        .filter(|repair| matches!(repair, Repair::Length { .. }))
+        .count();
+    let flipped = resynced
+        .repairs
+        .iter()
+        .filter(|repair| matches!(repair, Repair::BitFlips { .. }))
+        .count();
+    let skipped: usize = resynced
+        .repairs
+        .iter()
+        .map(|repair| match repair {
+            Repair::Skipped { len, .. } => *len,
+            _ => 0,
+        })
+        .sum();
+    println!(
+        "fixed {} length{} and {} chunk{} with flipped bits, skipped {} byte{}",
+        fixed,
+        if fixed == 1 { "" } else { "s" },
+        flipped,
+        if flipped == 1 { "" } else { "s" },
+        skipped,
+        if skipped == 1 { "" } else { "s" }
+    );
+    let changed = !resynced.repairs.is_empty();
+    match (&args.output, args.dry_run) {
+        (_, true) => Ok(()),
+        (Some(output), false) => write_png(output, &resynced.png),
+        (None, false) if changed => write_png(&args.file_path, &resynced.png),
+        (None, false) => Ok(()),
+    }
+}
+
+/// Fails with the first of `failures`, counting them all.
+fn fail_if_any(failures: Vec<anyhow::Error>, what: &str) -> Result<()> {
+    let count = failures.len();
+    match failures.into_iter().next() {
+        Some(first) => Err(first.context(format!(
+            "{} file{} {}",
+            count,
+            if count == 1 { "" } else { "s" },
+            what
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn strip(args: StripArgs) -> Result<()> {
+    let chunk_types = strippable(&args.chunk_types)?;
+    let mut sources = Vec::new();
+    for path in &args.paths {
+        match is_bucket(path) || path.is_dir() {
+            true => sources.extend(list_pngs(path)?),
+            false => sources.push(Source::File(path.clone())),
+        }
+    }
+    let results = pngme::parallel::map(&sources, |source| {
+        strip_source(source, &chunk_types, args.dry_run)
+    });
+    let mut failures = Vec::new();
+    for (source, result) in sources.iter().zip(results) {
+        let name = source.name();
+        match result {
+            Ok(0) => println!("{}\tunchanged", name.display()),
+            Ok(removed) => println!(
+                "{}\t{}\t{} chunk{}",
+                name.display(),
+                if args.dry_run {
+                    "would strip"
+                } else {
+                    "stripped"
+                },
+                removed,
+                if removed == 1 { "" } else { "s" }
+            ),
+            Err(e) => {
+                println!("{}\tfailed\t{}", name.display(), e);
+                failures.push(e.context(format!("Failed to strip {}", name.display())));
+            }
+        }
+    }
+    fail_if_any(failures, "failed to be stripped")
+}
+
+/// The chunk types to strip, refusing critical ones.
+pub(crate) fn strippable(chunk_types: &[String]) -> Result<Vec<ChunkType>> {
+    let chunk_types = chunk_types
+        .iter()
+        .map(|chunk_type| ChunkType::from_str(chunk_type))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(critical) = chunk_types
+        .iter()
+        .find(|chunk_type| chunk_type.is_critical())
+    {
+        bail!(
+            "{} is a critical chunk: images can't do without it",
+            critical
+        );
+    }
+    Ok(chunk_types)
+}
+
+/// Removes the chunks of `chunk_types` from `png`, keeping its index up to
+/// date, and gives how many there were.
+pub(crate) fn strip_chunks(png: &mut Png, chunk_types: &[ChunkType]) -> usize {
+    let indexed = index::has_index(png);
+    let before = png.chunks().len();
+    for chunk_type in chunk_types {
+        while png.remove_chunk(&chunk_type.to_string()).is_ok() {}
+    }
+    let removed = before - png.chunks().len();
+    if removed > 0 && indexed && index::has_index(png) {
+        index::refresh(png);
+    }
+    removed
+}
+
+/// Removes the chunks of `chunk_types` from `source`, giving how many
+/// there were. Rewrites it only if there were some and not `dry_run`: an
+/// object only if nothing else changed it since it was read.
+fn strip_source(source: &Source, chunk_types: &[ChunkType], dry_run: bool) -> Result<usize> {
+    let mut png = Png::try_from(source.read()?.as_slice())?;
+    let removed = strip_chunks(&mut png, chunk_types);
+    if removed == 0 || dry_run {
+        return Ok(removed);
+    }
+    match source {
+        Source::File(path) => replace_file(path, &png.as_bytes())?,
+        Source::Object(store, object) => {
+            store.put(&object.key, &png.as_bytes(), Some(&object.etag))?
+        }
+    }
+    Ok(removed)
+}
+
+fn dedupe(args: DedupeArgs) -> Result<()> {
+    let mut sources = Vec::new();
+    for path in &args.paths {
+        match is_bucket(path) || path.is_dir() {
+            true => sources.extend(list_pngs(path)?),
+            false => sources.push(Source::File(path.clone())),
+        }
+    }
+    let results = pngme::parallel::map(&sources, |source| dedupe_source(source, args.dry_run));
+    let (mut files, mut saved) = (0, 0);
+    let mut failures = Vec::new();
+    for (source, result) in sources.iter().zip(results) {
+        let name = source.name();
+        match result {
+            Ok(deduplicated) if deduplicated.removed.is_empty() => {
+                println!("{}\tunchanged", name.display())
+            }
+            Ok(deduplicated) => {
+                let copies: Vec<String> = deduplicated
+                    .removed
+                    .iter()
+                    .map(|(chunk_type, count)| match count {
+                        1 => chunk_type.to_string(),
+                        count => format!("{} x{}", chunk_type, count),
+                    })
+                    .collect();
+                let count = deduplicated.count();
+                println!(
+                    "{}\t{}\t{} cop{} ({})\t{} bytes",
+                    name.display(),
+                    if args.dry_run {
+                        "would drop"
+                    } else {
+                        "dropped"
+                    },
+                    count,
+                    if count == 1 { "y" } else { "ies" },
+                    copies.join(", "),
+                    deduplicated.saved
+                );
+                files += 1;
+                saved += deduplicated.saved;
+            }
+            Err(e) => {
+                println!("{}\tfailed\t{}", name.display(), e);
+                failures.push(e.context(format!("Failed to deduplicate {}", name.display())));
+            }
+        }
+    }
+    println!(
+        "{} {} bytes in {} file{}",
+        if args.dry_run { "would save" } else { "saved" },
+        saved,
+        files,
+        if files == 1 { "" } else { "s" }
+    );
+    fail_if_any(failures, "failed to be deduplicated")
+}
+
+/// Drops the duplicate chunks of `source`, keeping its index up to date.
+/// Rewrites it only if there were some and not `dry_run`: an object only
+/// if nothing else changed it since it was read.
+fn dedupe_source(source: &Source, dry_run: bool) -> Result<normalize::Deduplicated> {
+    let mut png = Png::try_from(source.read()?.as_slice())?;
+    let indexed = index::has_index(&png);
+    let deduplicated = normalize::deduplicate(&mut png);
+    if deduplicated.removed.is_empty() || dry_run {
+        return Ok(deduplicated);
+    }
+    if indexed {
+        index::refresh(&mut png);
+    }
+    match source {
+        Source::File(path) => replace_file(path, &png.as_bytes())?,
+        Source::Object(store, object) => {
+            store.put(&object.key, &png.as_bytes(), Some(&object.etag))?
+        }
+    }
+    Ok(deduplicated)
+}
+
+fn sanitize(args: SanitizeArgs) -> Result<()> {
+    let mut keep = sanitize::default_keep();
+    for chunk_type in &args.keep {
+        keep.push(ChunkType::from_str(chunk_type)?);
+    }
+    let bytes = read_input(&args.file_path)?;
+    let png = Png::try_from(bytes.as_slice())
+        .with_context(|| format!("Failed to parse {}", args.file_path.display()))?;
+    let sanitized = sanitize::sanitize(&png, &keep)
+        .with_context(|| format!("Failed to sanitize {}", args.file_path.display()))?;
+    for (chunk_type, count) in &sanitized.dropped {
+        println!(
+            "dropped\t{}\t{} chunk{}",
+            chunk_type,
+            count,
+            if *count == 1 { "" } else { "s" }
+        );
+    }
+    if sanitized.trailer > 0 {
+        println!("dropped\tafter IEND\t{} bytes", sanitized.trailer);
+    }
+    let kept = sanitized.png.chunks().len();
+    println!(
+        "kept {} chunk{}, dropped {}, {} bytes -> {} bytes",
+        kept,
+        if kept == 1 { "" } else { "s" },
+        sanitized.count(),
+        bytes.len(),
+        sanitized.png.as_bytes().len()
+    );
+    match (&args.output, args.dry_run) {
+        (_, true) => Ok(()),
+        (Some(output), false) => write_png(output, &sanitized.png),
+        (None, false) => write_png(&args.file_path, &sanitized.png),
+    }
+}
+
+fn git_filter(args: GitFilterArgs) -> Result<()> {
+    let strip = args
+        .strip
+        .iter()
+        .map(|chunk_type| ChunkType::from_str(chunk_type))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut input = Vec::new();
+    io::stdin()
+        .read_to_end(&mut input)
+        .context("Failed to read standard input")?;
+    // Whatever isn't a PNG file passes through as it is: a filter that
+    // fails or mangles a file would keep Git from storing it.
+    let output = match Png::try_from(input.as_slice()) {
+        Ok(mut png) => {
+            let indexed = index::has_index(&png);
+            if args.clean {
+                git_filter::clean(&mut png, &args.texts, &strip);
+            } else {
+                git_filter::smudge(&mut png, &args.texts)?;
+            }
+            if indexed {
+                index::refresh(&mut png);
+            }
+            png.as_bytes()
+        }
+        Err(_) => input,
+    };
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&output)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// A PNG file for a batch command: a path, or an object in a directory or
+/// bucket.
+enum Source {
+    File(PathBuf),
+    Object(Arc<dyn Storage>, Object),
+}
+
+impl Source {
+    fn name(&self) -> PathBuf {
+        match self {
+            Self::File(path) => path.clone(),
+            Self::Object(store, object) => PathBuf::from(store.name(&object.key)),
+        }
+    }
+    fn read(&self) -> Result<Vec<u8>> {
+        match self {
+            Self::File(path) => read_input(path),
+            Self::Object(store, object) => {
+                Ok(store.get(&object.key, pngme::limits::get().max_file_len)?)
+            }
+        }
+    }
+}
+
+/// Whether `path` names a bucket (`s3://` or `gs://`) rather than a file.
+fn is_bucket(path: &Path) -> bool {
+    path.to_str().is_some_and(storage::is_location)
+}
+
+/// The objects ending in `.png` under `location`, a bucket or a directory.
+fn list_pngs(location: &Path) -> Result<Vec<Source>> {
+    let (store, prefix) = match location.to_str() {
+        Some(location) if storage::is_location(location) => storage::open(location)?,
+        _ => (
+            Box::new(Directory::new(location)) as Box<dyn Storage>,
+            String::new(),
+        ),
+    };
+    let store: Arc<dyn Storage> = Arc::from(store);
+    Ok(store
+        .list(&prefix)?
+        .into_iter()
+        .filter(|object| object.key.ends_with(".png"))
+        .map(|object| Source::Object(Arc::clone(&store), object))
+        .collect())
+}
+
+fn capacity(args: CapacityArgs) -> Result<()> {
+    let bytes = read_input(&args.file_path)?;
+    let png = Png::try_from(bytes.as_slice())
+        .with_context(|| format!("Failed to parse {}", args.file_path.display()))?;
+    let payload = match (&args.payload_file, &args.message) {
+        (Some(path), _) => Some(
+            Envelope::from_path(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?,
+        ),
+        (None, Some(message)) => Some(Envelope::text(message)),
+        (None, None) => None,
+    };
+    // What embed would store, compressed as it would.
+    let stored = payload.map(|envelope| {
+        let raw = envelope.payload().len();
+        let envelope = match args.compress {
+            Some(compression) => envelope.with_compression(compression),
+            None => envelope,
+        };
+        (raw, envelope.as_bytes().len())
+    });
+    let fits = |capacity: usize| match stored {
+        Some((_, len)) if len <= capacity => "\tfits",
+        Some(_) => "\ttoo small",
+        None => "",
+    };
+
+    if let Some((raw, len)) = stored {
+        println!("payload\t{} bytes\t{} bytes stored", raw, len);
+    }
+    match stored {
+        Some((raw, len)) => {
+            // encode stores plain text messages as they are.
+            let plain = args.message.is_some() && args.compress.is_none();
+            let len = if plain { raw } else { len };
+            let chunks = len.div_ceil(Chunk::MAX_LENGTH as usize).max(1);
+            let growth = len + chunks * Chunk::OVERHEAD;
+            println!(
+                "chunk\t-\tunlimited\tgrows the file by {} bytes ({:.2}%)",
+                growth,
+                growth as f64 * 100.0 / bytes.len() as f64
+            );
+        }
+        None => println!(
+            "chunk\t-\tunlimited\tgrows the file by the payload plus {} bytes",
+            Chunk::OVERHEAD
+        ),
+    }
+    match lsb::capacities(&png) {
+        Ok(capacities) => {
+            for capacity in capacities {
+                println!(
+                    "{}\t{}\t{} bytes{}",
+                    capacity.strategy,
+                    capacity.bits,
+                    capacity.bytes,
+                    fits(capacity.bytes)
+                );
+            }
+        }
+        Err(err @ (LsbError::Unsupported(_) | LsbError::MissingPalette)) => {
+            eprintln!("No room in the pixels: {}", err);
+        }
+        Err(err) => {
+            return Err(err).with_context(|| {
+                format!(
+                    "Failed to decode the pixels of {}",
+                    args.file_path.display()
+                )
+            })
+        }
+    }
+    Ok(())
+}
+
+fn analyze(args: AnalyzeArgs) -> Result<()> {
+    let mut parser = Parser::new();
+    for file in &args.files {
+        let bytes = read_input(file)?;
+        let png = parser
+            .parse(&bytes)
+            .with_context(|| format!("Failed to parse {}", file.display()))?;
+        if args.chunks {
+            for stats in analysis::chunk_stats(&png) {
+                println!(
+                    "{}\t#{} {}\t{} bytes\tentropy {:.2}\tdeflates to {:.0}%{}",
+                    file.display(),
+                    stats.index,
+                    stats.chunk_type,
+                    stats.len,
+                    stats.entropy,
+                    stats.deflate_ratio * 100.0,
+                    if stats.is_suspicious() {
+                        "\thigh entropy"
+                    } else {
+                        ""
+                    }
+                );
+            }
+            continue;
+        }
+        let image = parser
+            .decode_image(&png)
+            .with_context(|| format!("Failed to decode the pixels of {}", file.display()))?;
+        let report = analysis::analyze(&image)
+            .with_context(|| format!("Failed to analyze {}", file.display()))?;
+        let names = analysis::channel_names(image.header().color_type);
+        for channel in &report.channels {
+            println!(
+                "{}\t{}\tchi-square {:.3} over {:.0}%\tRS {:.3}",
+                file.display(),
+                names[channel.channel],
+                channel.chi_square,
+                channel.chi_square_extent * 100.0,
+                channel.rs
+            );
+        }
+        println!("{}\tscore {:.2}", file.display(), report.score());
+    }
+    Ok(())
+}
+
+/// Prints a line per timestamp: the time in UTC, or as stored when it has
+/// no zone, or `-` when it doesn't parse, then where it is and its value.
+fn timeline(args: TimelineArgs) -> Result<()> {
+    let mut parser = Parser::new();
+    for file in &args.files {
+        let bytes = read_input(file)?;
+        let png = parser
+            .parse(&bytes)
+            .with_context(|| format!("Failed to parse {}", file.display()))?;
+        for event in timeline::timeline(&png) {
+            let time = match event.time {
+                Some(time) if event.zoned => timestamp::format_utc(time),
+                Some(time) => timestamp::format_utc(time)
+                    .trim_end_matches('Z')
+                    .to_string(),
+                None => "-".to_string(),
+            };
+            let chunk = match event.chunk {
+                Some(index) => format!("#{}", index),
+                None => "after IEND".to_string(),
+            };
+            println!(
+                "{}\t{}\t{} ({})\t{}",
+                file.display(),
+                time,
+                event.source,
+                chunk,
+                event.detail
+            );
+        }
+    }
+    Ok(())
+}
+
+fn report(args: ReportArgs) -> Result<()> {
+    let bytes = read_input(&args.file_path)?;
+    let format = match (args.format, &args.output) {
+        (Some(format), _) => format,
+        (None, Some(output)) => report::Format::for_path(output),
+        (None, None) => report::Format::Html,
+    };
+    let name = args.file_path.display().to_string();
+    let report = report::report(&name, &bytes, format)
+        .with_context(|| format!("Failed to parse {}", name))?;
+    match &args.output {
+        Some(output) => fs::write(output, report)
+            .with_context(|| format!("Failed to write {}", output.display())),
+        None => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(report.as_bytes())?;
+            stdout.flush()?;
+            Ok(())
+        }
+    }
+}
+
+fn watermark(args: WatermarkArgs) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    Watermark::new(args.mark.as_bytes())
+        .apply(&mut png, &EncodeOptions::default())
+        .with_context(|| format!("Failed to watermark {}", args.file_path.display()))?;
+    if index::has_index(&png) {
+        index::refresh(&mut png);
+    }
+    write_png(args.output.as_ref().unwrap_or(&args.file_path), &png)
+}
+
+fn verify_watermark(args: VerifyWatermarkArgs) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let detection = Watermark::new(args.mark.as_bytes()).verify(&png)?;
+    println!(
+        "strength {:.3}\toffset {},{}",
+        detection.strength, detection.offset.0, detection.offset.1
+    );
+    Ok(())
+}
+
+fn generate_cover(args: GenerateArgs) -> Result<()> {
+    let size = match (args.size, args.capacity) {
+        (Some(size), _) => size,
+        (None, Some(bytes)) => Size::for_capacity(bytes, args.bits),
+        (None, None) => unreachable!("clap requires --size or --capacity"),
+    };
+    let seed = args.seed.unwrap_or_else(|| OsRng.next_u64());
+    let png = generate::generate(size, args.style, seed).to_png(&EncodeOptions::default())?;
+    if let Some(bytes) = args.capacity {
+        let capacity = lsb::capacity(&png, args.bits, Strategy::Color)?;
+        if capacity < bytes {
+            bail!(
+                "The largest cover ({}) holds only {} bytes at {} bits",
+                size,
+                capacity,
+                args.bits
+            );
+        }
+    }
+    write_png(&args.output, &png)?;
+    eprintln!("Generated a {} {} image (seed {})", size, args.style, seed);
+    Ok(())
+}
+
+fn keygen(args: KeygenArgs) -> Result<()> {
+    use age::secrecy::ExposeSecret;
+
+    let identity = age::x25519::Identity::generate();
+    let recipient = identity.to_public();
+    let contents = format!(
+        "# created: {}\n# public key: {}\n{}\n",
+        timestamp::format_utc(std::time::SystemTime::now()),
+        recipient,
+        identity.to_string().expose_secret()
+    );
+    match &args.output {
+        Some(path) => {
+            write_secret_file(path, contents.as_bytes())?;
+            eprintln!("Public key: {}", recipient);
+        }
+        None => print!("{}", contents),
+    }
+    Ok(())
+}
+
+/// Creates `path` readable only by its owner, refusing to replace an
+/// existing file.
+fn write_secret_file(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    file.write_all(contents)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Cli;
+    use clap::Parser as _;
+    use std::ffi::OsStr;
+
+    fn reencrypt_in(dir: &Path, extra: &[&str]) -> Result<()> {
+        let file = dir.join("image.png");
+        let new_password = dir.join("new-password");
+        let mut argv = vec![
+            OsStr::new("pngme"),
+            OsStr::new("reencrypt"),
+            file.as_os_str(),
+            OsStr::new("ruSt"),
+            OsStr::new("--new-password-file"),
+            new_password.as_os_str(),
+        ];
+        argv.extend(extra.iter().map(OsStr::new));
+        run(Cli::try_parse_from(argv).unwrap().command)
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_reencrypt_replaces_atomically() {
+        let dir = std::env::temp_dir().join(format!("pngme-reencrypt-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let encryption = Encryption {
+            kdf: Kdf::Pbkdf2Sha256 { iterations: 1000 },
+            ..Encryption::default()
+        };
+        let sealed = Envelope::text("secret")
+            .encrypt(b"old", &encryption)
+            .unwrap();
+        let chunk = |t: &str, data: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+        let original = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("ruSt", &sealed.as_bytes()),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes();
+        fs::write(dir.join("image.png"), &original).unwrap();
+        fs::write(dir.join("new-password"), "new\n").unwrap();
+        // A directory can't be renamed over, so writing there fails after
+        // the temporary file is made.
+        let taken = dir.join("taken");
+        fs::create_dir_all(&taken).unwrap();
+
+        let wrong_password = reencrypt_in(&dir, &["--password", "wrong"]);
+        let unwritable = reencrypt_in(&dir, &["--password", "old", "-o", taken.to_str().unwrap()]);
+        let failed = (
+            fs::read(dir.join("image.png")).unwrap(),
+            names(&dir),
+            names(&taken),
+        );
+        let replaced = reencrypt_in(&dir, &["--password", "old"]);
+        let written = fs::read(dir.join("image.png")).unwrap();
+        let after = names(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(wrong_password.is_err());
+        assert!(unwritable.is_err());
+        assert_eq!(failed.0, original);
+        assert_eq!(failed.1, ["image.png", "new-password", "taken"]);
+        assert!(failed.2.is_empty());
+        replaced.unwrap();
+        assert_eq!(after, failed.1);
+        let png = Png::try_from(written.as_slice()).unwrap();
+        let envelope = Envelope::try_from(png.chunk_by_type("ruSt").unwrap().data()).unwrap();
+        assert!(envelope.decrypt(b"old").is_err());
+        assert_eq!(envelope.decrypt(b"new").unwrap().payload(), b"secret");
+    }
+}
@@ -0,0 +1,566 @@
+use std::fmt::{Display, Formatter};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+use std::sync::RwLock;
+use thiserror::Error;
+
+use crate::trace;
+
+#[derive(Debug, Error)]
+pub enum CompressionError {
+    #[error("Unknown compression algorithm {0:?} (expected deflate, zstd or brotli)")]
+    UnknownAlgorithm(String),
+    #[error("Invalid {algorithm} level {level:?} (expected {min}-{max})")]
+    InvalidLevel {
+        algorithm: Algorithm,
+        level: String,
+        min: i32,
+        max: i32,
+    },
+    #[error("Unknown compression algorithm id: {0}")]
+    UnknownAlgorithmId(u8),
+    #[error("pngme was built without {0} (enable its feature)")]
+    NotBuilt(Algorithm),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Algorithm {
+    Deflate,
+    Zstd,
+    Brotli,
+}
+
+impl Algorithm {
+    pub const ALL: [Self; 3] = [Self::Deflate, Self::Zstd, Self::Brotli];
+
+    /// The id stored in envelopes; never reuse a value.
+    pub fn id(self) -> u8 {
+        match self {
+            Self::Deflate => 1,
+            Self::Zstd => 2,
+            Self::Brotli => 3,
+        }
+    }
+    pub fn from_id(id: u8) -> Result<Self, CompressionError> {
+        match id {
+            1 => Ok(Self::Deflate),
+            2 => Ok(Self::Zstd),
+            3 => Ok(Self::Brotli),
+            _ => Err(CompressionError::UnknownAlgorithmId(id)),
+        }
+    }
+    /// Whether this build can compress and decompress with the algorithm:
+    /// zstd and brotli each have a feature of their own, unless a
+    /// [`Codec`] is set for them.
+    pub fn is_built(self) -> bool {
+        codec(self).is_some()
+            || match self {
+                Self::Deflate => true,
+                Self::Zstd => cfg!(feature = "zstd"),
+                Self::Brotli => cfg!(feature = "brotli"),
+            }
+    }
+    fn levels(self) -> (i32, i32, i32) {
+        // (min, max, default)
+        match self {
+            Self::Deflate => (0, 9, 6),
+            Self::Zstd => (1, 22, 3),
+            Self::Brotli => (0, 11, 9),
+        }
+    }
+    pub fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.decompress_at_most(data, usize::MAX)
+    }
+    /// Decompresses no more than `max` bytes and one past, so callers can
+    /// tell output that would exceed `max` without producing all of it.
+    pub fn decompress_at_most(self, data: &[u8], max: usize) -> io::Result<Vec<u8>> {
+        trace::span!("decompress", algorithm = %self, len = data.len());
+        let limit = (max as u64).saturating_add(1);
+        let mut out = Vec::new();
+        trace::failed(
+            self.decompressor(data)
+                .and_then(|mut reader| (&mut reader).take(limit).read_to_end(&mut out)),
+        )?;
+        trace::event!(debug, out_len = out.len(), "decompressed");
+        Ok(out)
+    }
+    /// Decompresses what `reader` holds as it is read.
+    pub fn decompressor<'a, R: Read + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        if let Some(codec) = codec(self) {
+            return Ok(Box::new(Decoding {
+                decoder: codec.decoder()?,
+                reader: BufReader::new(reader),
+                end: false,
+            }));
+        }
+        Ok(match self {
+            Self::Deflate => Box::new(flate2::read::ZlibDecoder::new(reader)),
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            #[cfg(feature = "brotli")]
+            Self::Brotli => Box::new(brotli::Decompressor::new(reader, 4096)),
+            #[allow(unreachable_patterns)]
+            _ => return Err(not_built(self)),
+        })
+    }
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Deflate => "deflate",
+            Self::Zstd => "zstd",
+            Self::Brotli => "brotli",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A compression algorithm and level, parsed from `<algo>[:<level>]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Compression {
+    algorithm: Algorithm,
+    level: i32,
+}
+
+impl FromStr for Compression {
+    type Err = CompressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, level) = match s.split_once(':') {
+            Some((name, level)) => (name, Some(level)),
+            None => (s, None),
+        };
+        let algorithm = match name {
+            "deflate" | "zlib" => Algorithm::Deflate,
+            "zstd" => Algorithm::Zstd,
+            "brotli" => Algorithm::Brotli,
+            _ => return Err(CompressionError::UnknownAlgorithm(name.to_string())),
+        };
+        if !algorithm.is_built() {
+            return Err(CompressionError::NotBuilt(algorithm));
+        }
+        let (min, max, default) = algorithm.levels();
+        let level = match level {
+            None => default,
+            Some(level) => level
+                .parse()
+                .ok()
+                .filter(|l| (min..=max).contains(l))
+                .ok_or_else(|| CompressionError::InvalidLevel {
+                    algorithm,
+                    level: level.to_string(),
+                    min,
+                    max,
+                })?,
+        };
+        Ok(Self { algorithm, level })
+    }
+}
+
+impl Compression {
+    pub fn new(algorithm: Algorithm) -> Self {
+        Self {
+            algorithm,
+            level: algorithm.levels().2,
+        }
+    }
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+    /// # Panics
+    ///
+    /// If the algorithm isn't built, see [`Algorithm::is_built`]; parsing
+    /// a `Compression` checks that.
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        let write = || -> io::Result<Vec<u8>> {
+            let mut compressor = self.compressor(Vec::new())?;
+            compressor.write_all(data)?;
+            compressor.finish()
+        };
+        trace::span!("compress", algorithm = %self.algorithm, level = self.level, len = data.len());
+        let out = write().expect("compressing into memory cannot fail");
+        trace::event!(debug, out_len = out.len(), "compressed");
+        out
+    }
+    /// Compresses everything written to the result into `writer`; call
+    /// [`Compressor::finish`] to end the stream.
+    pub fn compressor<W: Write>(&self, writer: W) -> io::Result<Compressor<W>> {
+        if let Some(codec) = codec(self.algorithm) {
+            return Ok(Compressor::Codec {
+                encoder: codec.encoder(self.level)?,
+                writer,
+                out: Vec::new(),
+            });
+        }
+        Ok(match self.algorithm {
+            Algorithm::Deflate => {
+                let level = flate2::Compression::new(self.level as u32);
+                Compressor::Deflate(flate2::write::ZlibEncoder::new(writer, level))
+            }
+            #[cfg(feature = "zstd")]
+            Algorithm::Zstd => {
+                Compressor::Zstd(zstd::stream::write::Encoder::new(writer, self.level)?)
+            }
+            #[cfg(feature = "brotli")]
+            Algorithm::Brotli => {
+                let latch = Latch {
+                    writer,
+                    error: None,
+                };
+                Compressor::Brotli(Box::new(brotli::CompressorWriter::new(
+                    latch,
+                    4096,
+                    self.level as u32,
+                    22,
+                )))
+            }
+            #[allow(unreachable_patterns)]
+            algorithm => return Err(not_built(algorithm)),
+        })
+    }
+}
+
+/// Compresses a stream, see [`Compression::compressor`].
+pub enum Compressor<W: Write> {
+    Deflate(flate2::write::ZlibEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    #[cfg(feature = "brotli")]
+    Brotli(Box<brotli::CompressorWriter<Latch<W>>>),
+    /// A [`Codec`] set for the algorithm, with the output it made.
+    Codec {
+        encoder: Box<dyn Encode>,
+        writer: W,
+        out: Vec<u8>,
+    },
+}
+
+impl<W: Write> Compressor<W> {
+    /// Ends the compressed stream and returns the inner writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            Self::Deflate(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.finish(),
+            #[cfg(feature = "brotli")]
+            Self::Brotli(encoder) => {
+                let latch = encoder.into_inner();
+                match latch.error {
+                    Some(error) => Err(error),
+                    None => Ok(latch.writer),
+                }
+            }
+            Self::Codec {
+                mut encoder,
+                mut writer,
+                mut out,
+            } => {
+                encoder.finish(&mut out)?;
+                writer.write_all(&out)?;
+                Ok(writer)
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Compressor<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Deflate(encoder) => encoder.write(data),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.write(data),
+            #[cfg(feature = "brotli")]
+            Self::Brotli(encoder) => encoder.write(data),
+            Self::Codec {
+                encoder,
+                writer,
+                out,
+            } => {
+                encoder.encode(data, out)?;
+                writer.write_all(out)?;
+                out.clear();
+                Ok(data.len())
+            }
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Deflate(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(encoder) => encoder.flush(),
+            #[cfg(feature = "brotli")]
+            Self::Brotli(encoder) => encoder.flush(),
+            Self::Codec { writer, .. } => writer.flush(),
+        }
+    }
+}
+
+/// A compressor and decompressor for one [`Algorithm`], to use instead of
+/// the built-in one: a hardware offload, say, or the system zlib. See
+/// [`set_codec`]. Deflate streams are zlib streams, header and Adler-32
+/// included, as PNG stores them.
+pub trait Codec: Send + Sync {
+    /// Starts a stream compressed at `level`, which is in the algorithm's
+    /// range.
+    fn encoder(&self, level: i32) -> io::Result<Box<dyn Encode>>;
+    fn decoder(&self) -> io::Result<Box<dyn Decode>>;
+}
+
+/// One stream being compressed by a [`Codec`].
+pub trait Encode: Send {
+    /// Compresses all of `input`, appending the output that is ready to
+    /// `out`.
+    fn encode(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()>;
+    /// Ends the stream, appending the rest of the output to `out`.
+    fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// How far one call to [`Decode::decode`] got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub read: usize,
+    pub written: usize,
+    /// The stream has ended; nothing more will be read or written.
+    pub end: bool,
+}
+
+/// One stream being decompressed by a [`Codec`].
+pub trait Decode: Send {
+    /// Decompresses from the front of `input` into `out`, which is never
+    /// empty. Must read or write something, or end, unless `input` is.
+    fn decode(&mut self, input: &[u8], out: &mut [u8]) -> io::Result<Step>;
+}
+
+/// Reads what a [`Decode`] makes of a reader.
+struct Decoding<R> {
+    decoder: Box<dyn Decode>,
+    reader: BufReader<R>,
+    end: bool,
+}
+
+impl<R: Read> Read for Decoding<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.end && !buf.is_empty() {
+            let input = self.reader.fill_buf()?;
+            let eof = input.is_empty();
+            let step = self.decoder.decode(input, buf)?;
+            self.reader.consume(step.read);
+            self.end = step.end;
+            if step.written > 0 {
+                return Ok(step.written);
+            }
+            if step.read == 0 && !step.end {
+                return Err(match eof {
+                    true => io::ErrorKind::UnexpectedEof.into(),
+                    false => io::Error::new(io::ErrorKind::InvalidData, "codec made no progress"),
+                });
+            }
+        }
+        Ok(0)
+    }
+}
+
+static CODECS: RwLock<[Option<&'static dyn Codec>; 3]> = RwLock::new([None; 3]);
+
+/// Makes `codec` compress and decompress `algorithm` for the whole process,
+/// in payloads and, for deflate, in PNG image data. `None` goes back to the
+/// built-in one.
+pub fn set_codec(algorithm: Algorithm, codec: Option<&'static dyn Codec>) {
+    let mut codecs = CODECS.write().expect("never held across a panic");
+    codecs[algorithm.id() as usize - 1] = codec;
+}
+
+/// The codec last set for `algorithm`, if any.
+pub fn codec(algorithm: Algorithm) -> Option<&'static dyn Codec> {
+    let codecs = CODECS.read().expect("never held across a panic");
+    codecs[algorithm.id() as usize - 1]
+}
+
+fn not_built(algorithm: Algorithm) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        CompressionError::NotBuilt(algorithm),
+    )
+}
+
+/// Keeps the first error of the writer under brotli, which finishing the
+/// stream would otherwise swallow.
+pub struct Latch<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Write for Latch<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.writer.write(data).inspect_err(|e| {
+            self.error
+                .get_or_insert_with(|| io::Error::new(e.kind(), e.to_string()));
+        })
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{FlushCompress, FlushDecompress, Status};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn testing_data() -> Vec<u8> {
+        "This is where your secret message will be! "
+            .repeat(50)
+            .into_bytes()
+    }
+
+    #[test]
+    fn test_parse() {
+        let c = Compression::from_str("deflate:9").unwrap();
+        assert_eq!(c.algorithm(), Algorithm::Deflate);
+        assert_eq!(c.level(), 9);
+        assert_eq!(Compression::from_str("zlib").unwrap().level(), 6);
+        if Algorithm::Brotli.is_built() {
+            assert_eq!(Compression::from_str("brotli").unwrap().level(), 9);
+        } else {
+            assert!(matches!(
+                Compression::from_str("brotli"),
+                Err(CompressionError::NotBuilt(Algorithm::Brotli))
+            ));
+        }
+        assert!(Compression::from_str("lzma").is_err());
+        assert!(Compression::from_str("deflate:10").is_err());
+        assert!(Compression::from_str("deflate:x").is_err());
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let data = testing_data();
+        for algorithm in Algorithm::ALL.into_iter().filter(|a| a.is_built()) {
+            let compressed = Compression::new(algorithm).compress(&data);
+            assert!(compressed.len() < data.len(), "{}", algorithm);
+            let id = Algorithm::from_id(algorithm.id()).unwrap();
+            assert_eq!(id.decompress(&compressed).unwrap(), data);
+            let capped = algorithm.decompress_at_most(&compressed, 100).unwrap();
+            assert_eq!(capped, data[..101]);
+        }
+    }
+
+    #[test]
+    fn test_streaming() {
+        let data = testing_data();
+        for algorithm in Algorithm::ALL.into_iter().filter(|a| a.is_built()) {
+            let compression = Compression::new(algorithm);
+            let mut compressor = compression.compressor(Vec::new()).unwrap();
+            for piece in data.chunks(7) {
+                compressor.write_all(piece).unwrap();
+            }
+            let compressed = compressor.finish().unwrap();
+            assert_eq!(compressed, compression.compress(&data), "{}", algorithm);
+            let mut decompressed = Vec::new();
+            algorithm
+                .decompressor(compressed.as_slice())
+                .unwrap()
+                .read_to_end(&mut decompressed)
+                .unwrap();
+            assert_eq!(decompressed, data);
+        }
+    }
+
+    /// flate2's deflate through its low-level API, counting streams.
+    struct Flate2(AtomicUsize);
+    struct Flate2Encode(flate2::Compress);
+    struct Flate2Decode(flate2::Decompress);
+
+    impl Codec for Flate2 {
+        fn encoder(&self, level: i32) -> io::Result<Box<dyn Encode>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            let level = flate2::Compression::new(level as u32);
+            Ok(Box::new(Flate2Encode(flate2::Compress::new(level, true))))
+        }
+        fn decoder(&self) -> io::Result<Box<dyn Decode>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Ok(Box::new(Flate2Decode(flate2::Decompress::new(true))))
+        }
+    }
+
+    impl Flate2Encode {
+        fn run(&mut self, input: &[u8], out: &mut Vec<u8>, flush: FlushCompress) -> io::Result<()> {
+            let start = self.0.total_in();
+            loop {
+                out.reserve(4096);
+                let read = (self.0.total_in() - start) as usize;
+                let status = self
+                    .0
+                    .compress_vec(&input[read..], out, flush)
+                    .map_err(io::Error::other)?;
+                let done = match flush {
+                    FlushCompress::Finish => status == Status::StreamEnd,
+                    _ => (self.0.total_in() - start) as usize == input.len(),
+                };
+                if done && out.len() < out.capacity() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    impl Encode for Flate2Encode {
+        fn encode(&mut self, input: &[u8], out: &mut Vec<u8>) -> io::Result<()> {
+            self.run(input, out, FlushCompress::None)
+        }
+        fn finish(&mut self, out: &mut Vec<u8>) -> io::Result<()> {
+            self.run(&[], out, FlushCompress::Finish)
+        }
+    }
+
+    impl Decode for Flate2Decode {
+        fn decode(&mut self, input: &[u8], out: &mut [u8]) -> io::Result<Step> {
+            let (read, written) = (self.0.total_in(), self.0.total_out());
+            let status = self
+                .0
+                .decompress(input, out, FlushDecompress::None)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            Ok(Step {
+                read: (self.0.total_in() - read) as usize,
+                written: (self.0.total_out() - written) as usize,
+                end: status == Status::StreamEnd,
+            })
+        }
+    }
+
+    static FLATE2: Flate2 = Flate2(AtomicUsize::new(0));
+
+    #[test]
+    fn test_codec() {
+        let data = testing_data();
+        let builtin = Compression::new(Algorithm::Deflate).compress(&data);
+        // The codec makes the same stream, so other tests running meanwhile
+        // don't notice.
+        set_codec(Algorithm::Deflate, Some(&FLATE2));
+        let compressed = Compression::new(Algorithm::Deflate).compress(&data);
+        assert_eq!(compressed, builtin);
+        assert_eq!(Algorithm::Deflate.decompress(&compressed).unwrap(), data);
+        let capped = Algorithm::Deflate
+            .decompress_at_most(&compressed, 100)
+            .unwrap();
+        assert_eq!(capped, data[..101]);
+        let cut = &compressed[..compressed.len() / 2];
+        let err = Algorithm::Deflate.decompress(cut).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(FLATE2.0.load(Ordering::Relaxed) >= 4);
+        set_codec(Algorithm::Deflate, None);
+        assert!(codec(Algorithm::Deflate).is_none());
+    }
+
+    #[test]
+    fn test_corrupt_input() {
+        assert!(Algorithm::Zstd.decompress(b"not zstd").is_err());
+        assert!(Algorithm::Brotli.decompress(b"not brotli").is_err());
+        assert!(Algorithm::Deflate.decompress(b"not zlib").is_err());
+    }
+}
@@ -0,0 +1,140 @@
+//! Carrier formats: what commands need from a file to hide payloads in it,
+//! whatever its format.
+//!
+//! A [`Container`] lists the blocks of a file and stores, finds and removes
+//! payloads tagged with a [`ChunkType`]. PNG files keep each payload in a
+//! chunk of that type; GIF files ([`Gif`]) in an application extension
+//! named after it, which decoders skip; JPEG files ([`Jpeg`]) in APP11 or
+//! COM segments.
+
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+pub use crate::gif::Gif;
+pub use crate::jpeg::{Jpeg, Segment};
+use crate::png::Png;
+
+#[derive(Debug, Error)]
+pub enum ContainerError {
+    #[error("No {0} payload in the file")]
+    NotFound(String),
+    #[error("A payload of {0} bytes is too large for the file format")]
+    TooLarge(usize),
+}
+
+/// A carrier format, told apart by the first bytes of a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Png,
+    Gif,
+    Jpeg,
+}
+
+impl Format {
+    /// The format `bytes` start like, from their first 6 bytes.
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&Png::STANDARD_HEADER[..6]) {
+            Some(Self::Png)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(Self::Gif)
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(Self::Jpeg)
+        } else {
+            None
+        }
+    }
+}
+
+/// A block of a file as `print` lists it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    /// What the block is, e.g. a chunk type.
+    pub kind: String,
+    /// Bytes of data in it.
+    pub len: usize,
+}
+
+pub trait Container {
+    /// The blocks of the file, in order.
+    fn blocks(&self) -> Vec<Block>;
+    /// The first payload tagged `tag`.
+    fn payload(&self, tag: &ChunkType) -> Option<&[u8]>;
+    /// Stores `data` tagged `tag` after the payloads already there.
+    fn insert_payload(&mut self, tag: ChunkType, data: &[u8]) -> Result<(), ContainerError>;
+    /// Removes the first payload tagged `tag`.
+    fn remove_payload(&mut self, tag: &ChunkType) -> Result<(), ContainerError>;
+    /// The file, with its payloads.
+    fn to_bytes(&self) -> Vec<u8>;
+}
+
+impl Container for Png {
+    fn blocks(&self) -> Vec<Block> {
+        self.chunks()
+            .iter()
+            .map(|chunk| Block {
+                kind: chunk.chunk_type().to_string(),
+                len: chunk.data().len(),
+            })
+            .collect()
+    }
+    fn payload(&self, tag: &ChunkType) -> Option<&[u8]> {
+        self.chunk_by_type(&tag.to_string()).map(Chunk::data)
+    }
+    fn insert_payload(&mut self, tag: ChunkType, data: &[u8]) -> Result<(), ContainerError> {
+        let chunk = Chunk::try_new(tag, data).map_err(|_| ContainerError::TooLarge(data.len()))?;
+        self.append_chunk(chunk);
+        Ok(())
+    }
+    fn remove_payload(&mut self, tag: &ChunkType) -> Result<(), ContainerError> {
+        self.remove_chunk(&tag.to_string())
+            .map(drop)
+            .map_err(|_| ContainerError::NotFound(tag.to_string()))
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let chunk = |t: &str, data: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("IDAT", &[1; 8]),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(Format::sniff(&testing_png().as_bytes()), Some(Format::Png));
+        assert_eq!(Format::sniff(b"GIF89a\x01\x00"), Some(Format::Gif));
+        assert_eq!(Format::sniff(b"GIF87a"), Some(Format::Gif));
+        assert_eq!(Format::sniff(b"GIF8"), None);
+        assert_eq!(Format::sniff(b"\xff\xd8\xff\xe0"), Some(Format::Jpeg));
+        assert_eq!(Format::sniff(b"\xff\xd8"), None);
+    }
+
+    #[test]
+    fn test_png_container() {
+        let tag = ChunkType::from_str("ruSt").unwrap();
+        let mut png = testing_png();
+        let container: &mut dyn Container = &mut png;
+        assert!(container.payload(&tag).is_none());
+        container.insert_payload(tag.clone(), b"hello").unwrap();
+        assert_eq!(container.payload(&tag), Some(&b"hello"[..]));
+        let kinds: Vec<_> = container.blocks().into_iter().map(|b| b.kind).collect();
+        assert_eq!(kinds, ["IHDR", "IDAT", "ruSt", "IEND"]);
+        container.remove_payload(&tag).unwrap();
+        assert!(matches!(
+            container.remove_payload(&tag),
+            Err(ContainerError::NotFound(_))
+        ));
+        assert_eq!(container.to_bytes(), testing_png().as_bytes());
+    }
+}
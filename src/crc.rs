@@ -0,0 +1,133 @@
+//! The CRC-32 that chunks are checked with, and a way to swap it out.
+//!
+//! Everything in the crate computes chunk CRCs through [`Hasher`], which
+//! runs the backend last passed to [`set`]: by default [`Crc32Fast`], so a
+//! service with a CRC offload engine can plug it in without forking.
+
+use alloc::boxed::Box;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// CRC-32 as PNG and ZIP use it (the IEEE polynomial, reflected, with the
+/// usual initial value and final XOR).
+pub trait Crc32: Send + Sync {
+    /// The CRC of some bytes followed by `data`, given `crc`, the CRC of
+    /// those bytes (0 for none).
+    fn update(&self, crc: u32, data: &[u8]) -> u32;
+}
+
+/// The built-in backend. crc32fast picks a carry-less multiply (x86
+/// PCLMULQDQ) or the ARM CRC instructions at runtime, and slicing-by-16 on
+/// other CPUs.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Crc32Fast;
+
+impl Crc32 for Crc32Fast {
+    fn update(&self, crc: u32, data: &[u8]) -> u32 {
+        let mut hasher = crc32fast::Hasher::new_with_initial(crc);
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+static BACKEND: AtomicPtr<&'static dyn Crc32> = AtomicPtr::new(ptr::null_mut());
+
+/// Makes `backend` compute every CRC in the process from now on. Meant to
+/// be called once at startup: each call keeps a few bytes for good.
+pub fn set(backend: &'static dyn Crc32) {
+    BACKEND.store(Box::leak(Box::new(backend)), Ordering::Release);
+}
+
+/// The backend last passed to [`set`], or [`Crc32Fast`].
+pub fn get() -> &'static dyn Crc32 {
+    let backend = BACKEND.load(Ordering::Acquire);
+    if backend.is_null() {
+        return &Crc32Fast;
+    }
+    // SAFETY: only `set` stores, a pointer it leaked, so never freed.
+    unsafe { *backend }
+}
+
+/// A CRC computed over several pieces with the current backend.
+#[derive(Clone, Copy)]
+pub struct Hasher {
+    backend: &'static dyn Crc32,
+    crc: u32,
+}
+
+impl Hasher {
+    pub fn new() -> Self {
+        Self {
+            backend: get(),
+            crc: 0,
+        }
+    }
+    pub fn update(&mut self, data: &[u8]) {
+        self.crc = self.backend.update(self.crc, data);
+    }
+    pub fn finalize(self) -> u32 {
+        self.crc
+    }
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The CRC of `data` with the current backend.
+pub fn hash(data: &[u8]) -> u32 {
+    get().update(0, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::AtomicUsize;
+
+    /// Bitwise CRC-32, counting the bytes it is given.
+    struct Counting(AtomicUsize);
+
+    impl Crc32 for Counting {
+        fn update(&self, crc: u32, data: &[u8]) -> u32 {
+            self.0.fetch_add(data.len(), Ordering::Relaxed);
+            let mut crc = !crc;
+            for &byte in data {
+                crc ^= byte as u32;
+                for _ in 0..8 {
+                    crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+                }
+            }
+            !crc
+        }
+    }
+
+    static COUNTING: Counting = Counting(AtomicUsize::new(0));
+
+    #[test]
+    fn test_backends_agree() {
+        let data = b"IENDwhatever follows";
+        assert_eq!(Crc32Fast.update(0, data), 0x98ce_bdfe);
+        assert_eq!(
+            Crc32Fast.update(Crc32Fast.update(0, b"IEND"), &data[4..]),
+            0x98ce_bdfe
+        );
+        assert_eq!(COUNTING.update(0, data), Crc32Fast.update(0, data));
+        assert_eq!(Crc32Fast.update(0, b"IEND"), 0xae42_6082);
+    }
+
+    #[test]
+    fn test_set() {
+        // The counting backend gives the same CRCs, so other tests running
+        // meanwhile don't notice.
+        set(&COUNTING);
+        let mut hasher = Hasher::new();
+        hasher.update(b"IE");
+        hasher.update(b"ND");
+        assert_eq!(hasher.finalize(), 0xae42_6082);
+        assert!(COUNTING.0.load(Ordering::Relaxed) >= 4);
+        set(&Crc32Fast);
+        assert_eq!(hash(b"IEND"), 0xae42_6082);
+    }
+}
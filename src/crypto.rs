@@ -0,0 +1,1250 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::ChaCha20Poly1305;
+use hmac::{Hmac, Mac};
+use std::convert::TryFrom;
+use std::fmt::{Display, Formatter};
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::limits::{self, Limit, LimitExceeded, Limits};
+use crate::trace;
+
+#[derive(Debug, Error)]
+pub enum CryptoError {
+    #[error("Unknown cipher {0:?} (expected aes-256-gcm or chacha20-poly1305)")]
+    UnknownCipher(String),
+    #[error("Unknown cipher id: {0}")]
+    UnknownCipherId(u8),
+    #[error("Unknown key derivation function id: {0}")]
+    UnknownKdfId(u8),
+    #[error("Encryption parameters are truncated or malformed")]
+    BadParameters,
+    #[error("Decryption failed: wrong password or tampered payload")]
+    DecryptionFailed,
+    #[error("At least one recipient is required")]
+    NoRecipients,
+    #[error("None of the given identities can decrypt this payload")]
+    NoMatchingIdentity,
+    #[error("Invalid identity file: {0}")]
+    InvalidIdentityFile(String),
+    #[error("Integrity check failed: wrong password or tampered payload")]
+    IntegrityCheckFailed,
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitExceeded),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Cipher {
+    #[default]
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub const NONCE_LEN: usize = 12;
+
+    pub fn id(self) -> u8 {
+        match self {
+            Self::Aes256Gcm => 1,
+            Self::ChaCha20Poly1305 => 2,
+        }
+    }
+    pub fn from_id(id: u8) -> Result<Self, CryptoError> {
+        match id {
+            1 => Ok(Self::Aes256Gcm),
+            2 => Ok(Self::ChaCha20Poly1305),
+            _ => Err(CryptoError::UnknownCipherId(id)),
+        }
+    }
+    pub(crate) fn encrypt(
+        self,
+        key: &[u8; 32],
+        nonce: &[u8],
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> Vec<u8> {
+        let payload = Payload {
+            msg: plaintext,
+            aad,
+        };
+        let ciphertext = match self {
+            Self::Aes256Gcm => Aes256Gcm::new(key.into()).encrypt(nonce.into(), payload),
+            Self::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new(key.into()).encrypt(nonce.into(), payload)
+            }
+        };
+        ciphertext.expect("in-memory AEAD encryption cannot fail")
+    }
+    pub(crate) fn decrypt(
+        self,
+        key: &[u8; 32],
+        nonce: &[u8],
+        aad: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let payload = Payload {
+            msg: ciphertext,
+            aad,
+        };
+        let plaintext = match self {
+            Self::Aes256Gcm => Aes256Gcm::new(key.into()).decrypt(nonce.into(), payload),
+            Self::ChaCha20Poly1305 => {
+                ChaCha20Poly1305::new(key.into()).decrypt(nonce.into(), payload)
+            }
+        };
+        plaintext.map_err(|_| CryptoError::DecryptionFailed)
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "aes-256-gcm" | "aes" => Ok(Self::Aes256Gcm),
+            "chacha20-poly1305" | "chacha20" => Ok(Self::ChaCha20Poly1305),
+            _ => Err(CryptoError::UnknownCipher(s.to_string())),
+        }
+    }
+}
+
+impl Display for Cipher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Aes256Gcm => "aes-256-gcm",
+            Self::ChaCha20Poly1305 => "chacha20-poly1305",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How the encryption key is derived from the password.
+///
+/// Argon2id is used for new payloads; PBKDF2 is only kept so payloads
+/// encrypted before Argon2id support can still be opened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kdf {
+    Pbkdf2Sha256 {
+        iterations: u32,
+    },
+    Argon2id {
+        /// Memory cost in KiB.
+        memory_kib: u32,
+        /// Number of passes over the memory.
+        iterations: u32,
+        /// Number of lanes.
+        parallelism: u32,
+    },
+}
+
+impl Default for Kdf {
+    fn default() -> Self {
+        Self::Argon2id {
+            memory_kib: Self::DEFAULT_MEMORY_KIB,
+            iterations: Self::DEFAULT_ITERATIONS,
+            parallelism: Self::DEFAULT_PARALLELISM,
+        }
+    }
+}
+
+impl Kdf {
+    pub const SALT_LEN: usize = 16;
+    /// Argon2id defaults, following the OWASP recommendation.
+    pub const DEFAULT_MEMORY_KIB: u32 = 19 * 1024;
+    pub const DEFAULT_ITERATIONS: u32 = 2;
+    pub const DEFAULT_PARALLELISM: u32 = 1;
+    /// Largest Argon2id memory cost accepted from a file, so a crafted
+    /// payload can't make decoding allocate without bound.
+    pub const MAX_MEMORY_KIB: u32 = 4 * 1024 * 1024;
+    /// Largest Argon2id time cost and parallelism accepted from a file, so
+    /// it can't make decoding run without end either.
+    pub const MAX_ITERATIONS: u32 = 1000;
+    pub const MAX_PARALLELISM: u32 = 64;
+    /// Largest PBKDF2 iteration count accepted from a file; payloads of
+    /// before Argon2id used 600,000.
+    pub const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+
+    fn argon2_params(
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    ) -> Result<argon2::Params, CryptoError> {
+        if memory_kib > Self::MAX_MEMORY_KIB
+            || iterations > Self::MAX_ITERATIONS
+            || parallelism > Self::MAX_PARALLELISM
+        {
+            return Err(CryptoError::BadParameters);
+        }
+        argon2::Params::new(memory_kib, iterations, parallelism, Some(32))
+            .map_err(|_| CryptoError::BadParameters)
+    }
+    /// Checks that the parameters are usable, e.g. before spending time
+    /// building a payload with them.
+    pub fn validate(&self) -> Result<(), CryptoError> {
+        match *self {
+            Self::Pbkdf2Sha256 { iterations }
+                if (1..=Self::MAX_PBKDF2_ITERATIONS).contains(&iterations) =>
+            {
+                Ok(())
+            }
+            Self::Pbkdf2Sha256 { .. } => Err(CryptoError::BadParameters),
+            Self::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => Self::argon2_params(memory_kib, iterations, parallelism).map(|_| ()),
+        }
+    }
+    /// Checks the cost against the key derivation limits of `limits`.
+    pub fn check_limits(&self, limits: &Limits) -> Result<(), LimitExceeded> {
+        match *self {
+            Self::Pbkdf2Sha256 { iterations } => limits.check(Limit::KdfWork, iterations as usize),
+            Self::Argon2id {
+                memory_kib,
+                iterations,
+                ..
+            } => {
+                limits.check(Limit::KdfMemory, memory_kib as usize)?;
+                let work = (memory_kib as usize).saturating_mul(iterations as usize);
+                limits.check(Limit::KdfWork, work)
+            }
+        }
+    }
+    pub(crate) fn derive(
+        &self,
+        password: &[u8],
+        salt: &[u8],
+    ) -> Result<Zeroizing<[u8; 32]>, CryptoError> {
+        trace::span!("kdf.derive", kdf = ?self);
+        self.check_limits(&limits::get())?;
+        let mut key = Zeroizing::new([0u8; 32]);
+        match *self {
+            Self::Pbkdf2Sha256 { iterations } => {
+                self.validate()?;
+                pbkdf2::pbkdf2_hmac::<sha2::Sha256>(password, salt, iterations, key.as_mut());
+            }
+            Self::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params = Self::argon2_params(memory_kib, iterations, parallelism)?;
+                argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                    .hash_password_into(password, salt, key.as_mut())
+                    .map_err(|_| CryptoError::BadParameters)?;
+            }
+        }
+        Ok(key)
+    }
+    fn write_to(&self, bytes: &mut Vec<u8>) {
+        match *self {
+            Self::Pbkdf2Sha256 { iterations } => {
+                bytes.push(1);
+                bytes.extend_from_slice(&iterations.to_be_bytes());
+            }
+            Self::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                bytes.push(2);
+                bytes.extend_from_slice(&memory_kib.to_be_bytes());
+                bytes.extend_from_slice(&iterations.to_be_bytes());
+                bytes.extend_from_slice(&parallelism.to_be_bytes());
+            }
+        }
+    }
+    fn read_from(input: &mut &[u8]) -> Result<Self, CryptoError> {
+        let read_u32 =
+            |input: &mut &[u8]| take(input, 4).map(|b| u32::from_be_bytes(b.try_into().unwrap()));
+        let kdf = match take(input, 1)?[0] {
+            1 => Self::Pbkdf2Sha256 {
+                iterations: read_u32(input)?,
+            },
+            2 => Self::Argon2id {
+                memory_kib: read_u32(input)?,
+                iterations: read_u32(input)?,
+                parallelism: read_u32(input)?,
+            },
+            id => return Err(CryptoError::UnknownKdfId(id)),
+        };
+        kdf.validate()?;
+        Ok(kdf)
+    }
+}
+
+/// The choices made when encrypting: cipher and key derivation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Encryption {
+    pub cipher: Cipher,
+    pub kdf: Kdf,
+}
+
+/// Everything besides the password needed to decrypt: stored in the clear
+/// next to the ciphertext.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncryptionParams {
+    pub cipher: Cipher,
+    pub kdf: Kdf,
+    pub salt: Vec<u8>,
+    pub nonce: Vec<u8>,
+}
+
+impl EncryptionParams {
+    /// Fresh parameters with a random salt and nonce.
+    pub fn generate(encryption: &Encryption) -> Self {
+        let mut salt = vec![0u8; Kdf::SALT_LEN];
+        let mut nonce = vec![0u8; Cipher::NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        Self {
+            cipher: encryption.cipher,
+            kdf: encryption.kdf,
+            salt,
+            nonce,
+        }
+    }
+    pub fn encrypt(
+        &self,
+        password: &[u8],
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        trace::span!("encrypt", cipher = %self.cipher, len = plaintext.len());
+        let key = trace::failed(self.kdf.derive(password, &self.salt))?;
+        Ok(self.cipher.encrypt(&key, &self.nonce, aad, plaintext))
+    }
+    pub fn decrypt(
+        &self,
+        password: &[u8],
+        aad: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        trace::span!("decrypt", cipher = %self.cipher, len = ciphertext.len());
+        trace::failed(
+            self.kdf
+                .derive(password, &self.salt)
+                .and_then(|key| self.cipher.decrypt(&key, &self.nonce, aad, ciphertext)),
+        )
+    }
+    /// `cipher | kdf | u8 salt length | salt | nonce`
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.cipher.id()];
+        self.kdf.write_to(&mut bytes);
+        bytes.push(self.salt.len() as u8);
+        bytes.extend_from_slice(&self.salt);
+        bytes.extend_from_slice(&self.nonce);
+        bytes
+    }
+}
+
+fn take<'a>(input: &mut &'a [u8], n: usize) -> Result<&'a [u8], CryptoError> {
+    if input.len() < n {
+        return Err(CryptoError::BadParameters);
+    }
+    let (head, tail) = input.split_at(n);
+    *input = tail;
+    Ok(head)
+}
+
+impl TryFrom<&[u8]> for EncryptionParams {
+    type Error = CryptoError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let input = &mut &*value;
+        let cipher = Cipher::from_id(take(input, 1)?[0])?;
+        let kdf = Kdf::read_from(input)?;
+        let salt_len = take(input, 1)?[0] as usize;
+        let salt = take(input, salt_len)?.to_vec();
+        if input.len() != Cipher::NONCE_LEN {
+            return Err(CryptoError::BadParameters);
+        }
+        Ok(Self {
+            cipher,
+            kdf,
+            salt,
+            nonce: input.to_vec(),
+        })
+    }
+}
+
+/// Key derivation for an HMAC-SHA256 integrity tag: like
+/// [`EncryptionParams`] without a cipher, for payloads that should be
+/// tamper-evident but stay readable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IntegrityParams {
+    pub kdf: Kdf,
+    pub salt: Vec<u8>,
+}
+
+impl IntegrityParams {
+    pub const TAG_LEN: usize = 32;
+
+    /// Fresh parameters with a random salt.
+    pub fn generate(kdf: Kdf) -> Self {
+        let mut salt = vec![0u8; Kdf::SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self { kdf, salt }
+    }
+    fn mac(&self, password: &[u8], data: &[u8]) -> Result<Hmac<sha2::Sha256>, CryptoError> {
+        let key = self.kdf.derive(password, &self.salt)?;
+        let mut mac = <Hmac<sha2::Sha256> as Mac>::new_from_slice(key.as_ref())
+            .expect("HMAC accepts keys of any length");
+        mac.update(data);
+        Ok(mac)
+    }
+    pub fn tag(&self, password: &[u8], data: &[u8]) -> Result<[u8; Self::TAG_LEN], CryptoError> {
+        Ok(self.mac(password, data)?.finalize().into_bytes().into())
+    }
+    /// Checks `tag` in constant time.
+    pub fn verify(&self, password: &[u8], data: &[u8], tag: &[u8]) -> Result<(), CryptoError> {
+        self.mac(password, data)?
+            .verify_slice(tag)
+            .map_err(|_| CryptoError::IntegrityCheckFailed)
+    }
+    /// `kdf | u8 salt length | salt`
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.kdf.write_to(&mut bytes);
+        bytes.push(self.salt.len() as u8);
+        bytes.extend_from_slice(&self.salt);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for IntegrityParams {
+    type Error = CryptoError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let input = &mut &*value;
+        let kdf = Kdf::read_from(input)?;
+        let salt_len = take(input, 1)?[0] as usize;
+        let salt = take(input, salt_len)?.to_vec();
+        if !input.is_empty() {
+            return Err(CryptoError::BadParameters);
+        }
+        Ok(Self { kdf, salt })
+    }
+}
+
+/// Password encryption in independent frames (the STREAM construction), for
+/// payloads too large to seal in one piece.
+///
+/// The plaintext is cut into frames of `frame_len` bytes, each sealed on its
+/// own with a nonce made of a random prefix, the frame number and a flag
+/// set on the last frame only. Frames can't be reordered, dropped or cut
+/// off without detection, encrypting and decrypting only ever hold one
+/// frame (see [`StreamWriter`] and [`StreamReader`]), and a damaged frame
+/// only loses its own contents (see [`StreamParams::salvage`]).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamParams {
+    pub cipher: Cipher,
+    pub kdf: Kdf,
+    pub salt: Vec<u8>,
+    pub nonce_prefix: [u8; Self::PREFIX_LEN],
+    pub frame_len: u32,
+}
+
+/// What [`StreamParams::salvage`] could decrypt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Salvaged {
+    /// The plaintext, with the frames that failed to decrypt zero-filled.
+    pub plaintext: Vec<u8>,
+    /// The numbers of the frames that failed, from 0.
+    pub damaged: Vec<u32>,
+}
+
+impl StreamParams {
+    pub const DEFAULT_FRAME_LEN: u32 = 64 * 1024;
+    const PREFIX_LEN: usize = Cipher::NONCE_LEN - 5;
+    const TAG_LEN: usize = 16;
+
+    /// Fresh parameters with a random salt and nonce prefix.
+    pub fn generate(encryption: &Encryption, frame_len: u32) -> Self {
+        let mut salt = vec![0u8; Kdf::SALT_LEN];
+        let mut nonce_prefix = [0u8; Self::PREFIX_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce_prefix);
+        Self {
+            cipher: encryption.cipher,
+            kdf: encryption.kdf,
+            salt,
+            nonce_prefix,
+            frame_len: frame_len.max(1),
+        }
+    }
+    fn sealed_frame_len(&self) -> usize {
+        self.frame_len as usize + Self::TAG_LEN
+    }
+    fn frames(&self, password: &[u8], aad: &[u8]) -> Result<Frames, CryptoError> {
+        Ok(Frames {
+            cipher: self.cipher,
+            key: self.kdf.derive(password, &self.salt)?,
+            nonce_prefix: self.nonce_prefix,
+            aad: aad.to_vec(),
+            counter: 0,
+        })
+    }
+    /// Encrypts everything written to the result into `writer`; call
+    /// [`StreamWriter::finish`] to seal the last frame.
+    pub fn encryptor<W: Write>(
+        &self,
+        password: &[u8],
+        aad: &[u8],
+        writer: W,
+    ) -> Result<StreamWriter<W>, CryptoError> {
+        Ok(StreamWriter {
+            frames: self.frames(password, aad)?,
+            frame_len: self.frame_len as usize,
+            buffer: Vec::new(),
+            writer,
+        })
+    }
+    /// Decrypts what `reader` holds as it is read; a damaged, reordered or
+    /// truncated stream fails with an [`std::io::ErrorKind::InvalidData`]
+    /// error wrapping [`CryptoError::DecryptionFailed`].
+    pub fn decryptor<R: Read>(
+        &self,
+        password: &[u8],
+        aad: &[u8],
+        reader: R,
+    ) -> Result<StreamReader<R>, CryptoError> {
+        Ok(StreamReader {
+            frames: self.frames(password, aad)?,
+            sealed_frame_len: self.sealed_frame_len(),
+            pending: Vec::new(),
+            plaintext: Vec::new(),
+            position: 0,
+            done: false,
+            reader,
+        })
+    }
+    pub fn encrypt(
+        &self,
+        password: &[u8],
+        aad: &[u8],
+        plaintext: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        trace::span!("encrypt", cipher = %self.cipher, len = plaintext.len(), frame_len = self.frame_len);
+        let mut encryptor = trace::failed(self.encryptor(password, aad, Vec::new()))?;
+        encryptor
+            .write_all(plaintext)
+            .expect("writing to memory cannot fail");
+        Ok(encryptor.finish().expect("writing to memory cannot fail"))
+    }
+    pub fn decrypt(
+        &self,
+        password: &[u8],
+        aad: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        trace::span!("decrypt", cipher = %self.cipher, len = ciphertext.len(), frame_len = self.frame_len);
+        let mut plaintext = Vec::new();
+        trace::failed(
+            self.decryptor(password, aad, ciphertext)
+                .and_then(|mut reader| {
+                    reader
+                        .read_to_end(&mut plaintext)
+                        .map_err(|_| CryptoError::DecryptionFailed)
+                }),
+        )?;
+        Ok(plaintext)
+    }
+    /// Decrypts every frame that is intact, zero-filling the others so the
+    /// rest stays in place. Fails only if no frame decrypts, which means a
+    /// wrong password rather than damage.
+    pub fn salvage(
+        &self,
+        password: &[u8],
+        aad: &[u8],
+        ciphertext: &[u8],
+    ) -> Result<Salvaged, CryptoError> {
+        let mut frames = self.frames(password, aad)?;
+        let sealed: Vec<&[u8]> = ciphertext.chunks(self.sealed_frame_len()).collect();
+        // Empty plaintext is still sealed in one (empty) frame.
+        let count = sealed.len().max(1);
+        let mut salvaged = Salvaged {
+            plaintext: Vec::with_capacity(ciphertext.len()),
+            damaged: Vec::new(),
+        };
+        for n in 0..count {
+            let frame = sealed.get(n).copied().unwrap_or_default();
+            match frames.open(frame, n + 1 == count) {
+                Ok(plaintext) => salvaged.plaintext.extend_from_slice(&plaintext),
+                Err(_) => {
+                    let len = frame.len().saturating_sub(Self::TAG_LEN);
+                    salvaged.plaintext.resize(salvaged.plaintext.len() + len, 0);
+                    salvaged.damaged.push(n as u32);
+                }
+            }
+        }
+        if salvaged.damaged.len() == count {
+            return Err(CryptoError::DecryptionFailed);
+        }
+        Ok(salvaged)
+    }
+    /// `cipher | kdf | u8 salt length | salt | nonce prefix | u32 frame length`
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.cipher.id()];
+        self.kdf.write_to(&mut bytes);
+        bytes.push(self.salt.len() as u8);
+        bytes.extend_from_slice(&self.salt);
+        bytes.extend_from_slice(&self.nonce_prefix);
+        bytes.extend_from_slice(&self.frame_len.to_be_bytes());
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for StreamParams {
+    type Error = CryptoError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let input = &mut &*value;
+        let cipher = Cipher::from_id(take(input, 1)?[0])?;
+        let kdf = Kdf::read_from(input)?;
+        let salt_len = take(input, 1)?[0] as usize;
+        let salt = take(input, salt_len)?.to_vec();
+        let nonce_prefix = take(input, Self::PREFIX_LEN)?
+            .try_into()
+            .expect("PREFIX_LEN bytes");
+        let frame_len = u32::from_be_bytes(take(input, 4)?.try_into().expect("4 bytes"));
+        if !input.is_empty() || frame_len == 0 {
+            return Err(CryptoError::BadParameters);
+        }
+        Ok(Self {
+            cipher,
+            kdf,
+            salt,
+            nonce_prefix,
+            frame_len,
+        })
+    }
+}
+
+/// The key and position within a stream, shared by both directions.
+struct Frames {
+    cipher: Cipher,
+    key: Zeroizing<[u8; 32]>,
+    nonce_prefix: [u8; StreamParams::PREFIX_LEN],
+    aad: Vec<u8>,
+    counter: u32,
+}
+
+impl Frames {
+    /// `prefix | u32 frame number | u8 last flag`
+    fn nonce(&self, last: bool) -> [u8; Cipher::NONCE_LEN] {
+        let mut nonce = [0u8; Cipher::NONCE_LEN];
+        nonce[..StreamParams::PREFIX_LEN].copy_from_slice(&self.nonce_prefix);
+        nonce[StreamParams::PREFIX_LEN..][..4].copy_from_slice(&self.counter.to_be_bytes());
+        nonce[Cipher::NONCE_LEN - 1] = last as u8;
+        nonce
+    }
+    fn advance(&mut self) -> io::Result<()> {
+        self.counter = self.counter.checked_add(1).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "too many frames in one stream")
+        })?;
+        Ok(())
+    }
+    fn seal(&mut self, frame: &[u8], last: bool) -> io::Result<Vec<u8>> {
+        let sealed = self
+            .cipher
+            .encrypt(&self.key, &self.nonce(last), &self.aad, frame);
+        self.advance()?;
+        Ok(sealed)
+    }
+    fn open(&mut self, frame: &[u8], last: bool) -> Result<Vec<u8>, CryptoError> {
+        let opened = self
+            .cipher
+            .decrypt(&self.key, &self.nonce(last), &self.aad, frame);
+        // Damaged frames still take up their number.
+        self.counter = self.counter.wrapping_add(1);
+        opened
+    }
+}
+
+/// Encrypts a stream frame by frame, see [`StreamParams::encryptor`].
+pub struct StreamWriter<W: Write> {
+    frames: Frames,
+    frame_len: usize,
+    buffer: Vec<u8>,
+    writer: W,
+}
+
+impl<W: Write> StreamWriter<W> {
+    /// Seals the last frame and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        let sealed = self.frames.seal(&self.buffer, true)?;
+        self.writer.write_all(&sealed)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Write for StreamWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // A full frame is only sealed once more data follows it, since the
+        // last one must be flagged.
+        let room = self.frame_len + 1 - self.buffer.len();
+        let taken = data.len().min(room);
+        self.buffer.extend_from_slice(&data[..taken]);
+        if self.buffer.len() > self.frame_len {
+            let sealed = self.frames.seal(&self.buffer[..self.frame_len], false)?;
+            self.writer.write_all(&sealed)?;
+            self.buffer.drain(..self.frame_len);
+        }
+        Ok(taken)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Decrypts a stream frame by frame, see [`StreamParams::decryptor`].
+pub struct StreamReader<R: Read> {
+    frames: Frames,
+    sealed_frame_len: usize,
+    pending: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+    done: bool,
+    reader: R,
+}
+
+impl<R: Read> StreamReader<R> {
+    /// Decrypts the next frame into `plaintext`.
+    fn next_frame(&mut self) -> io::Result<()> {
+        // Read one byte past the frame to tell whether it is the last.
+        let mut eof = false;
+        while self.pending.len() <= self.sealed_frame_len {
+            let start = self.pending.len();
+            self.pending.resize(self.sealed_frame_len + 1, 0);
+            let read = self.reader.read(&mut self.pending[start..])?;
+            self.pending.truncate(start + read);
+            if read == 0 {
+                eof = true;
+                break;
+            }
+        }
+        let len = self.pending.len().min(self.sealed_frame_len);
+        let frame: Vec<u8> = self.pending.drain(..len).collect();
+        self.plaintext = self
+            .frames
+            .open(&frame, eof)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        self.position = 0;
+        self.done = eof;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for StreamReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.next_frame()?;
+        }
+        let len = buf.len().min(self.plaintext.len() - self.position);
+        buf[..len].copy_from_slice(&self.plaintext[self.position..][..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+/// Two password-encrypted slots of the same size, for payloads with a
+/// decoy: each password opens only its own slot, and an unused slot is
+/// random filler, so nothing shows whether a second payload exists.
+///
+/// Each stored slot is `salt | nonce | ciphertext`, where the plaintext is
+/// `u32 length | contents | zero padding` up to `slot_len` bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeniableParams {
+    pub cipher: Cipher,
+    pub kdf: Kdf,
+    pub slot_len: u32,
+}
+
+impl DeniableParams {
+    pub const SLOTS: usize = 2;
+    /// Slot sizes are rounded up to this, so they don't give away the exact
+    /// length of either payload.
+    const PADDING: usize = 256;
+    const TAG_LEN: usize = 16;
+
+    /// Parameters whose slots fit `contents` of every length in `lens`.
+    pub fn for_contents(encryption: &Encryption, lens: &[usize]) -> Self {
+        let longest = lens.iter().copied().max().unwrap_or_default() + 4;
+        Self {
+            cipher: encryption.cipher,
+            kdf: encryption.kdf,
+            slot_len: longest.next_multiple_of(Self::PADDING) as u32,
+        }
+    }
+    /// Bytes one slot takes in the stored body.
+    pub fn stored_slot_len(&self) -> usize {
+        Kdf::SALT_LEN + Cipher::NONCE_LEN + self.slot_len as usize + Self::TAG_LEN
+    }
+    /// Encrypts `contents` into one stored slot.
+    pub fn seal(
+        &self,
+        password: &[u8],
+        aad: &[u8],
+        contents: &[u8],
+    ) -> Result<Vec<u8>, CryptoError> {
+        let mut plaintext = Zeroizing::new((contents.len() as u32).to_be_bytes().to_vec());
+        plaintext.extend_from_slice(contents);
+        if plaintext.len() > self.slot_len as usize {
+            return Err(CryptoError::BadParameters);
+        }
+        plaintext.resize(self.slot_len as usize, 0);
+        let params = EncryptionParams::generate(&Encryption {
+            cipher: self.cipher,
+            kdf: self.kdf,
+        });
+        let mut slot = params.salt.clone();
+        slot.extend_from_slice(&params.nonce);
+        slot.extend_from_slice(&params.encrypt(password, aad, &plaintext)?);
+        Ok(slot)
+    }
+    /// A stored slot that no password opens, indistinguishable from one
+    /// made by [`DeniableParams::seal`].
+    pub fn filler(&self) -> Vec<u8> {
+        let mut slot = vec![0u8; self.stored_slot_len()];
+        OsRng.fill_bytes(&mut slot);
+        slot
+    }
+    /// Decrypts whichever slot of `body` `password` opens.
+    pub fn open(&self, password: &[u8], aad: &[u8], body: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if body.len() != Self::SLOTS * self.stored_slot_len() {
+            return Err(CryptoError::BadParameters);
+        }
+        for slot in body.chunks(self.stored_slot_len()) {
+            let (salt, rest) = slot.split_at(Kdf::SALT_LEN);
+            let (nonce, ciphertext) = rest.split_at(Cipher::NONCE_LEN);
+            let params = EncryptionParams {
+                cipher: self.cipher,
+                kdf: self.kdf,
+                salt: salt.to_vec(),
+                nonce: nonce.to_vec(),
+            };
+            let Ok(plaintext) = params.decrypt(password, aad, ciphertext) else {
+                continue;
+            };
+            let plaintext = Zeroizing::new(plaintext);
+            let len = u32::from_be_bytes(plaintext[..4].try_into().expect("4 bytes")) as usize;
+            return plaintext
+                .get(4..4 + len)
+                .map(<[u8]>::to_vec)
+                .ok_or(CryptoError::BadParameters);
+        }
+        Err(CryptoError::DecryptionFailed)
+    }
+    /// `cipher | kdf | u32 slot length`
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.cipher.id()];
+        self.kdf.write_to(&mut bytes);
+        bytes.extend_from_slice(&self.slot_len.to_be_bytes());
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for DeniableParams {
+    type Error = CryptoError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let input = &mut &*value;
+        let cipher = Cipher::from_id(take(input, 1)?[0])?;
+        let kdf = Kdf::read_from(input)?;
+        let slot_len: [u8; 4] = (*input)
+            .try_into()
+            .map_err(|_| CryptoError::BadParameters)?;
+        let slot_len = u32::from_be_bytes(slot_len);
+        if slot_len < 4 {
+            return Err(CryptoError::BadParameters);
+        }
+        Ok(Self {
+            cipher,
+            kdf,
+            slot_len,
+        })
+    }
+}
+
+/// Encrypts `plaintext` to every recipient in the age format, so that any
+/// one of their identities can decrypt it.
+pub fn encrypt_to(
+    recipients: &[age::x25519::Recipient],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    trace::span!(
+        "encrypt",
+        recipients = recipients.len(),
+        len = plaintext.len()
+    );
+    let recipients = recipients.iter().map(|r| r as &dyn age::Recipient);
+    let encryptor = trace::failed(
+        age::Encryptor::with_recipients(recipients).map_err(|_| CryptoError::NoRecipients),
+    )?;
+    let mut ciphertext = Vec::new();
+    let write = || -> std::io::Result<()> {
+        let mut writer = encryptor.wrap_output(&mut ciphertext)?;
+        writer.write_all(plaintext)?;
+        writer.finish()?;
+        Ok(())
+    };
+    write().expect("encrypting into memory cannot fail");
+    Ok(ciphertext)
+}
+
+/// Decrypts an age ciphertext made by [`encrypt_to`] with the first matching
+/// identity.
+pub fn decrypt_with(
+    identities: &[Box<dyn age::Identity>],
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    trace::span!(
+        "decrypt",
+        identities = identities.len(),
+        len = ciphertext.len()
+    );
+    let read = || -> Result<Vec<u8>, CryptoError> {
+        let decryptor = age::Decryptor::new_buffered(ciphertext).map_err(decrypt_error)?;
+        let mut reader = decryptor
+            .decrypt(identities.iter().map(|i| i.as_ref()))
+            .map_err(decrypt_error)?;
+        let mut plaintext = Vec::new();
+        reader
+            .read_to_end(&mut plaintext)
+            .map_err(|_| CryptoError::DecryptionFailed)?;
+        Ok(plaintext)
+    };
+    trace::failed(read())
+}
+
+fn decrypt_error(err: age::DecryptError) -> CryptoError {
+    match err {
+        age::DecryptError::NoMatchingKeys => CryptoError::NoMatchingIdentity,
+        age::DecryptError::InvalidHeader | age::DecryptError::UnknownFormat => {
+            CryptoError::BadParameters
+        }
+        _ => CryptoError::DecryptionFailed,
+    }
+}
+
+/// Parses an age identity file: one `AGE-SECRET-KEY-1...` key per line,
+/// blank lines and `#` comments ignored.
+pub fn read_identities(data: &[u8]) -> Result<Vec<Box<dyn age::Identity>>, CryptoError> {
+    let invalid = |e: &dyn std::fmt::Display| CryptoError::InvalidIdentityFile(e.to_string());
+    let identities = age::IdentityFile::from_buffer(data)
+        .map_err(|e| invalid(&e))?
+        .into_identities()
+        .map_err(|e| invalid(&e))?;
+    if identities.is_empty() {
+        return Err(CryptoError::InvalidIdentityFile(
+            "no identities found".to_string(),
+        ));
+    }
+    Ok(identities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_encryption(cipher: Cipher) -> Encryption {
+        Encryption {
+            cipher,
+            kdf: Kdf::Pbkdf2Sha256 { iterations: 10 },
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for cipher in [Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let params = EncryptionParams::generate(&testing_encryption(cipher));
+            let ciphertext = params.encrypt(b"hunter2", b"aad", b"secret").unwrap();
+            assert_ne!(&ciphertext[..6], b"secret");
+            let plaintext = params.decrypt(b"hunter2", b"aad", &ciphertext).unwrap();
+            assert_eq!(plaintext, b"secret");
+        }
+    }
+
+    #[test]
+    fn test_wrong_password_or_aad() {
+        let params = EncryptionParams::generate(&testing_encryption(Cipher::Aes256Gcm));
+        let ciphertext = params.encrypt(b"hunter2", b"aad", b"secret").unwrap();
+        assert!(matches!(
+            params.decrypt(b"hunter3", b"aad", &ciphertext),
+            Err(CryptoError::DecryptionFailed)
+        ));
+        assert!(params.decrypt(b"hunter2", b"other", &ciphertext).is_err());
+    }
+
+    #[test]
+    fn test_params_round_trip() {
+        let params = EncryptionParams::generate(&testing_encryption(Cipher::ChaCha20Poly1305));
+        let decoded = EncryptionParams::try_from(params.as_bytes().as_ref()).unwrap();
+        assert_eq!(decoded, params);
+        assert!(EncryptionParams::try_from(&params.as_bytes()[..10]).is_err());
+    }
+
+    #[test]
+    fn test_argon2id() {
+        let encryption = Encryption {
+            cipher: Cipher::Aes256Gcm,
+            kdf: Kdf::Argon2id {
+                memory_kib: 64,
+                iterations: 1,
+                parallelism: 1,
+            },
+        };
+        let params = EncryptionParams::generate(&encryption);
+        let decoded = EncryptionParams::try_from(params.as_bytes().as_ref()).unwrap();
+        assert_eq!(decoded, params);
+        let ciphertext = params.encrypt(b"hunter2", b"aad", b"secret").unwrap();
+        assert_eq!(
+            decoded.decrypt(b"hunter2", b"aad", &ciphertext).unwrap(),
+            b"secret"
+        );
+    }
+
+    #[test]
+    fn test_excessive_argon2id_memory() {
+        let kdf = Kdf::Argon2id {
+            memory_kib: Kdf::MAX_MEMORY_KIB + 1,
+            iterations: 1,
+            parallelism: 1,
+        };
+        assert!(matches!(kdf.validate(), Err(CryptoError::BadParameters)));
+        let mut bytes = vec![Cipher::Aes256Gcm.id()];
+        kdf.write_to(&mut bytes);
+        bytes.push(0);
+        bytes.extend_from_slice(&[0; Cipher::NONCE_LEN]);
+        assert!(EncryptionParams::try_from(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_kdf_limits() {
+        let limits = Limits {
+            max_kdf_memory_kib: 64 * 1024,
+            max_kdf_work: 1_000_000,
+            ..Limits::UNLIMITED
+        };
+        let argon2id = |memory_kib, iterations| Kdf::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism: 1,
+        };
+        assert!(Kdf::default().check_limits(&limits).is_ok());
+        assert!(Kdf::Pbkdf2Sha256 {
+            iterations: 600_000
+        }
+        .check_limits(&limits)
+        .is_ok());
+        let exceeded = |kdf: Kdf| kdf.check_limits(&limits).unwrap_err().limit;
+        assert_eq!(exceeded(argon2id(64 * 1024 + 1, 1)), Limit::KdfMemory);
+        assert_eq!(exceeded(argon2id(64 * 1024, 16)), Limit::KdfWork);
+        assert_eq!(
+            exceeded(Kdf::Pbkdf2Sha256 {
+                iterations: 1_000_001
+            }),
+            Limit::KdfWork
+        );
+    }
+
+    #[test]
+    fn test_excessive_kdf_costs() {
+        let argon2id = |iterations, parallelism| Kdf::Argon2id {
+            memory_kib: 8 * Kdf::MAX_PARALLELISM,
+            iterations,
+            parallelism,
+        };
+        for kdf in [
+            argon2id(Kdf::MAX_ITERATIONS + 1, 1),
+            argon2id(u32::MAX, 1),
+            argon2id(1, Kdf::MAX_PARALLELISM + 1),
+            Kdf::Pbkdf2Sha256 {
+                iterations: Kdf::MAX_PBKDF2_ITERATIONS + 1,
+            },
+            Kdf::Pbkdf2Sha256 {
+                iterations: u32::MAX,
+            },
+        ] {
+            assert!(matches!(kdf.validate(), Err(CryptoError::BadParameters)));
+            assert!(matches!(
+                kdf.derive(b"hunter2", &[0; Kdf::SALT_LEN]),
+                Err(CryptoError::BadParameters)
+            ));
+            let mut bytes = vec![Cipher::Aes256Gcm.id()];
+            kdf.write_to(&mut bytes);
+            bytes.push(0);
+            bytes.extend_from_slice(&[0; Cipher::NONCE_LEN]);
+            assert!(EncryptionParams::try_from(bytes.as_slice()).is_err());
+        }
+        assert!(argon2id(Kdf::MAX_ITERATIONS, Kdf::MAX_PARALLELISM)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_integrity_tag() {
+        let params = IntegrityParams::generate(Kdf::Pbkdf2Sha256 { iterations: 10 });
+        let tag = params.tag(b"hunter2", b"payload").unwrap();
+        let decoded = IntegrityParams::try_from(params.as_bytes().as_ref()).unwrap();
+        assert!(decoded.verify(b"hunter2", b"payload", &tag).is_ok());
+        assert!(matches!(
+            decoded.verify(b"hunter2", b"Payload", &tag),
+            Err(CryptoError::IntegrityCheckFailed)
+        ));
+        assert!(decoded.verify(b"letmein", b"payload", &tag).is_err());
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let encryption = testing_encryption(Cipher::ChaCha20Poly1305);
+        let params = StreamParams::generate(&encryption, 10);
+        assert_eq!(
+            StreamParams::try_from(params.as_bytes().as_slice()).unwrap(),
+            params
+        );
+        for len in [0usize, 1, 10, 25, 30] {
+            let plaintext: Vec<u8> = (0..len as u8).collect();
+            let ciphertext = params.encrypt(b"hunter2", b"aad", &plaintext).unwrap();
+            assert_eq!(
+                ciphertext.len(),
+                len + len.div_ceil(10).max(1) * StreamParams::TAG_LEN
+            );
+            assert_eq!(
+                params.decrypt(b"hunter2", b"aad", &ciphertext).unwrap(),
+                plaintext
+            );
+        }
+
+        // Written in odd pieces and read back a byte at a time.
+        let mut encryptor = params.encryptor(b"hunter2", b"aad", Vec::new()).unwrap();
+        for piece in [&b"stream"[..], b"ing in ", b"", b"frames of ten bytes"] {
+            encryptor.write_all(piece).unwrap();
+        }
+        let ciphertext = encryptor.finish().unwrap();
+        let mut decryptor = params
+            .decryptor(b"hunter2", b"aad", &ciphertext[..])
+            .unwrap();
+        let mut plaintext = Vec::new();
+        let mut byte = [0];
+        while decryptor.read(&mut byte).unwrap() == 1 {
+            plaintext.push(byte[0]);
+        }
+        assert_eq!(plaintext, b"streaming in frames of ten bytes");
+    }
+
+    #[test]
+    fn test_stream_tampering_and_salvage() {
+        let params = StreamParams::generate(&testing_encryption(Cipher::Aes256Gcm), 10);
+        let plaintext: Vec<u8> = (1..=35).collect();
+        let ciphertext = params.encrypt(b"hunter2", b"aad", &plaintext).unwrap();
+        let sealed = 10 + StreamParams::TAG_LEN;
+        // Cut at a frame boundary, frames swapped, a wrong password.
+        let truncated = &ciphertext[..sealed * 2];
+        let swapped = [
+            &ciphertext[sealed..sealed * 2],
+            &ciphertext[..sealed],
+            &ciphertext[sealed * 2..],
+        ]
+        .concat();
+        for (password, ciphertext) in [
+            (&b"hunter2"[..], truncated),
+            (b"hunter2", &swapped),
+            (b"hunter3", &ciphertext),
+        ] {
+            assert!(matches!(
+                params.decrypt(password, b"aad", ciphertext),
+                Err(CryptoError::DecryptionFailed)
+            ));
+        }
+
+        let mut damaged = ciphertext.clone();
+        damaged[sealed + 3] ^= 1;
+        assert!(params.decrypt(b"hunter2", b"aad", &damaged).is_err());
+        let salvaged = params.salvage(b"hunter2", b"aad", &damaged).unwrap();
+        assert_eq!(salvaged.damaged, [1]);
+        let mut expected = plaintext.clone();
+        expected[10..20].fill(0);
+        assert_eq!(salvaged.plaintext, expected);
+        assert!(matches!(
+            params.salvage(b"hunter3", b"aad", &damaged),
+            Err(CryptoError::DecryptionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_deniable_slots() {
+        let params =
+            DeniableParams::for_contents(&testing_encryption(Cipher::Aes256Gcm), &[6, 300]);
+        assert_eq!(params.slot_len, 512);
+        let decoded = DeniableParams::try_from(params.as_bytes().as_ref()).unwrap();
+        assert_eq!(decoded, params);
+
+        let decoy = params.seal(b"decoy", b"aad", b"boring").unwrap();
+        let real = params.seal(b"hunter2", b"aad", &[7; 300]).unwrap();
+        assert_eq!(decoy.len(), params.stored_slot_len());
+        assert_eq!(params.filler().len(), decoy.len());
+        let body = [real, decoy].concat();
+        assert_eq!(params.open(b"decoy", b"aad", &body).unwrap(), b"boring");
+        assert_eq!(params.open(b"hunter2", b"aad", &body).unwrap(), [7; 300]);
+        assert!(matches!(
+            params.open(b"letmein", b"aad", &body),
+            Err(CryptoError::DecryptionFailed)
+        ));
+        assert!(params.open(b"decoy", b"other", &body).is_err());
+        assert!(params.seal(b"decoy", b"aad", &[0; 510]).is_err());
+    }
+
+    #[test]
+    fn test_recipients() {
+        let alice = age::x25519::Identity::generate();
+        let bob = age::x25519::Identity::generate();
+        let mallory = age::x25519::Identity::generate();
+        let ciphertext = encrypt_to(&[alice.to_public(), bob.to_public()], b"secret").unwrap();
+        for identity in [alice, bob] {
+            let identities: Vec<Box<dyn age::Identity>> = vec![Box::new(identity)];
+            assert_eq!(decrypt_with(&identities, &ciphertext).unwrap(), b"secret");
+        }
+        let identities: Vec<Box<dyn age::Identity>> = vec![Box::new(mallory)];
+        assert!(matches!(
+            decrypt_with(&identities, &ciphertext),
+            Err(CryptoError::NoMatchingIdentity)
+        ));
+        assert!(matches!(
+            encrypt_to(&[], b"secret"),
+            Err(CryptoError::NoRecipients)
+        ));
+    }
+
+    #[test]
+    fn test_read_identities() {
+        use age::secrecy::ExposeSecret;
+        let identity = age::x25519::Identity::generate();
+        let file = format!(
+            "# public key: {}\n{}\n",
+            identity.to_public(),
+            identity.to_string().expose_secret()
+        );
+        assert_eq!(read_identities(file.as_bytes()).unwrap().len(), 1);
+        assert!(read_identities(b"# nothing here\n").is_err());
+        assert!(read_identities(b"not a key\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_cipher() {
+        assert_eq!(
+            Cipher::from_str("chacha20-poly1305").unwrap(),
+            Cipher::ChaCha20Poly1305
+        );
+        assert!(Cipher::from_str("des").is_err());
+    }
+}
@@ -0,0 +1,569 @@
+//! `pngme daemon`: `print` and `verify` answered from memory.
+//!
+//! The daemon keeps a summary of the files it has read, keyed by path and
+//! checked against their modification time and length on every query, so
+//! asking again about a large file that hasn't changed costs a `stat`.
+//! Clients (`print --daemon`, `verify --daemon`, or anything that can open
+//! a Unix socket) send one request per connection:
+//!
+//! ```text
+//! COMMAND \t ABSOLUTE PATH \n
+//! ```
+//!
+//! with `print` or `verify` as the command. The answer starts with `ok` or
+//! `error \t EXIT CODE \t MESSAGE` on a line of its own; after `ok`, `print`
+//! sends what `pngme print` shows and `verify` one `EXIT CODE \t LINE` per
+//! file, the line being what `pngme verify` shows and the code 0 for files
+//! that passed.
+
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, DirBuilder, Metadata};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+use pngme::png::Png;
+use pngme::storage::{Directory, Storage};
+use pngme::verify::{self, Verified};
+
+use crate::args::DaemonArgs;
+use crate::commands::verified_line;
+use crate::exit;
+
+#[derive(Debug, Error)]
+pub enum DaemonError {
+    #[error("A daemon is already listening on {0}")]
+    Running(PathBuf),
+    #[error("Failed to reach the daemon on {socket}")]
+    Unreachable { socket: PathBuf, source: io::Error },
+    #[error("Paths with newlines can't be sent to the daemon: {0}")]
+    BadPath(PathBuf),
+    #[error("Unexpected answer from the daemon")]
+    BadResponse,
+    #[error("{0} must be a directory of yours that only you can enter (mode 0700)")]
+    NotPrivate(PathBuf),
+    /// A failure the daemon reported, with the exit code the command would
+    /// have had.
+    #[error("{message}")]
+    Failed { code: u8, message: String },
+}
+
+/// Connections served at once; more are turned away.
+const MAX_CONNECTIONS: usize = 16;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `$PNGME_SOCKET`, or `pngme.sock` in `$XDG_RUNTIME_DIR`, or in a
+/// directory of the temporary directory only the user can enter.
+pub fn socket_path() -> PathBuf {
+    if let Some(socket) = std::env::var_os("PNGME_SOCKET") {
+        return PathBuf::from(socket);
+    }
+    if let Some(runtime) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return Path::new(&runtime).join("pngme.sock");
+    }
+    private_dir().join("pngme.sock")
+}
+
+/// The directory of the temporary directory the socket goes in when
+/// neither variable is set. Anyone may have made it first, so it is only
+/// used once [`check_private`] passes.
+fn private_dir() -> PathBuf {
+    let user = std::env::var("USER").unwrap_or_else(|_| "default".to_string());
+    std::env::temp_dir().join(format!("pngme-{}", user))
+}
+
+/// Whether the directory of `socket` is [`private_dir`].
+fn in_private_dir(socket: &Path) -> bool {
+    socket.parent() == Some(private_dir().as_path())
+}
+
+/// Fails unless `dir` itself, not a link to it, is a directory owned by
+/// this user with mode 0700.
+fn check_private(dir: &Path) -> Result<(), DaemonError> {
+    let not_private = || DaemonError::NotPrivate(dir.to_path_buf());
+    let meta = fs::symlink_metadata(dir).map_err(|_| not_private())?;
+    // SAFETY: getuid has no preconditions and can't fail.
+    let uid = unsafe { libc::getuid() };
+    if !meta.is_dir() || meta.uid() != uid || meta.mode() & 0o777 != 0o700 {
+        return Err(not_private());
+    }
+    Ok(())
+}
+
+pub fn run(args: DaemonArgs) -> Result<()> {
+    let socket = args.socket.unwrap_or_else(socket_path);
+    let listener = bind(&socket, in_private_dir(&socket))?;
+    eprintln!("Listening on {}; press Ctrl-C to stop", socket.display());
+    let cache = Arc::new(Mutex::new(Cache::new(args.capacity as usize)));
+    let connections = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else { continue };
+        if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            connections.fetch_sub(1, Ordering::SeqCst);
+            let _ = stream.write_all(error_line(exit::FAILURE, "The daemon is busy").as_bytes());
+            continue;
+        }
+        let cache = Arc::clone(&cache);
+        let connections = Arc::clone(&connections);
+        thread::spawn(move || {
+            let _ = serve(stream, &cache);
+            connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+/// Listens on `socket`, taking over from a daemon that died without
+/// removing it. The directory of `socket` must pass [`check_private`] if
+/// `private` is set or it has to be created.
+fn bind(socket: &Path, private: bool) -> Result<UnixListener> {
+    if let Some(dir) = socket.parent() {
+        let created = !dir.exists();
+        if created {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        if created || private {
+            check_private(dir)?;
+        }
+    }
+    if UnixStream::connect(socket).is_ok() {
+        return Err(DaemonError::Running(socket.to_path_buf()).into());
+    }
+    match fs::remove_file(socket) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(e).with_context(|| format!("Failed to remove {}", socket.display()))
+        }
+        _ => {}
+    }
+    // The socket is made under a umask that keeps everyone else out, rather
+    // than being opened to them until its permissions are set.
+    // SAFETY: umask has no preconditions and can't fail; the daemon binds
+    // before it starts any thread that creates files.
+    let umask = unsafe { libc::umask(0o077) };
+    let listener = UnixListener::bind(socket);
+    // SAFETY: as above.
+    unsafe { libc::umask(umask) };
+    let listener = listener.with_context(|| format!("Failed to listen on {}", socket.display()))?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+fn serve(stream: UnixStream, cache: &Mutex<Cache>) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut request = Vec::new();
+    BufReader::new(&stream)
+        .take(64 * 1024)
+        .read_until(b'\n', &mut request)?;
+    let answer = answer(cache, &request);
+    (&stream).write_all(answer.as_bytes())
+}
+
+/// What to send back for `request`.
+fn answer(cache: &Mutex<Cache>, request: &[u8]) -> String {
+    let request = request.strip_suffix(b"\n").unwrap_or(request);
+    let (command, path) = match request.iter().position(|&b| b == b'\t') {
+        Some(tab) => (
+            &request[..tab],
+            Path::new(OsStr::from_bytes(&request[tab + 1..])),
+        ),
+        None => return error_line(exit::BAD_ARGUMENTS, "Expected COMMAND\\tPATH"),
+    };
+    if !path.is_absolute() {
+        return error_line(exit::BAD_ARGUMENTS, "Expected an absolute path");
+    }
+    match command {
+        b"print" => match summary(cache, path) {
+            Ok(summary) => match &summary.listing {
+                Ok(listing) => format!("ok\n{}", listing),
+                Err(failure) => error_line(failure.code, &failure.message),
+            },
+            Err(failure) => error_line(failure.code, &failure.message),
+        },
+        b"verify" => {
+            let files = match fs::metadata(path) {
+                Ok(meta) if meta.is_dir() => match pngs_under(path) {
+                    Ok(files) => files,
+                    Err(e) => return error_line(exit::IO_ERROR, &format!("{:#}", e)),
+                },
+                _ => vec![path.to_path_buf()],
+            };
+            let mut answer = "ok\n".to_string();
+            for file in files {
+                let verified = summary(cache, &file)
+                    .and_then(|summary| summary.verified.clone())
+                    .map(|verified| verified_line(&file, &verified));
+                let (code, line) = match verified {
+                    Ok(line) => (0, line),
+                    Err(failure) => (
+                        failure.code,
+                        format!("{}\tfailed\t{}", file.display(), failure.message),
+                    ),
+                };
+                answer.push_str(&format!("{}\t{}\n", code, line));
+            }
+            answer
+        }
+        _ => error_line(
+            exit::BAD_ARGUMENTS,
+            "Unknown command: expected print or verify",
+        ),
+    }
+}
+
+fn error_line(code: u8, message: &str) -> String {
+    // Messages stay on their line.
+    format!("error\t{}\t{}\n", code, message.replace('\n', " "))
+}
+
+/// The files ending in .png under `dir`, as `verify` finds them.
+fn pngs_under(dir: &Path) -> Result<Vec<PathBuf>> {
+    Ok(Directory::new(dir)
+        .list("")?
+        .into_iter()
+        .filter(|object| object.key.ends_with(".png"))
+        .map(|object| dir.join(object.key))
+        .collect())
+}
+
+/// The summary of `path` from the cache, reading the file if it changed
+/// or was never read.
+fn summary(cache: &Mutex<Cache>, path: &Path) -> Result<Arc<Summary>, Failure> {
+    let io_failure = |e: io::Error| {
+        Failure::of(&anyhow::Error::new(e).context(format!("Failed to read {}", path.display())))
+    };
+    let stamp = fs::metadata(path)
+        .map(|meta| Stamp::of(&meta))
+        .map_err(io_failure)?;
+    if let Some(summary) = lock(cache).get(path, stamp) {
+        return Ok(summary);
+    }
+    // Read outside the lock, so other clients aren't kept waiting.
+    let bytes = fs::read(path).map_err(io_failure)?;
+    let summary = Arc::new(Summary::new(path, &bytes));
+    lock(cache).insert(path.to_path_buf(), stamp, Arc::clone(&summary));
+    Ok(summary)
+}
+
+fn lock(cache: &Mutex<Cache>) -> std::sync::MutexGuard<'_, Cache> {
+    // A client thread that panicked leaves nothing half-written.
+    cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Failure {
+    code: u8,
+    message: String,
+}
+
+impl Failure {
+    fn of(e: &anyhow::Error) -> Self {
+        Self {
+            code: exit::code_for(e),
+            message: format!("{:#}", e),
+        }
+    }
+}
+
+/// What the daemon remembers of a file.
+#[derive(Debug)]
+struct Summary {
+    /// What `print` shows.
+    listing: Result<String, Failure>,
+    verified: Result<Verified, Failure>,
+}
+
+impl Summary {
+    fn new(path: &Path, bytes: &[u8]) -> Self {
+        let listing = Png::try_from(bytes)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+            .map(|png| png.to_string())
+            .map_err(|e| Failure::of(&e));
+        // verify shows the reason alone, after the path.
+        let verified = verify::verify(bytes).map_err(|e| Failure {
+            message: e.to_string(),
+            code: exit::code_for(&e.into()),
+        });
+        Self { listing, verified }
+    }
+}
+
+/// What tells whether a file changed since it was read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl Stamp {
+    fn of(meta: &Metadata) -> Self {
+        Self {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+        }
+    }
+}
+
+struct Entry {
+    stamp: Stamp,
+    summary: Arc<Summary>,
+    /// When it was last asked for, on the cache's clock.
+    used: u64,
+}
+
+/// Summaries of at most `capacity` files, dropping the one asked for least
+/// recently to make room.
+struct Cache {
+    capacity: usize,
+    clock: u64,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            clock: 0,
+            entries: HashMap::new(),
+        }
+    }
+    fn get(&mut self, path: &Path, stamp: Stamp) -> Option<Arc<Summary>> {
+        self.clock += 1;
+        let entry = self
+            .entries
+            .get_mut(path)
+            .filter(|entry| entry.stamp == stamp)?;
+        entry.used = self.clock;
+        Some(Arc::clone(&entry.summary))
+    }
+    fn insert(&mut self, path: PathBuf, stamp: Stamp, summary: Arc<Summary>) {
+        self.clock += 1;
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&path) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(path, _)| path.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        let used = self.clock;
+        self.entries.insert(
+            path,
+            Entry {
+                stamp,
+                summary,
+                used,
+            },
+        );
+    }
+}
+
+/// Asks the daemon at [`socket_path`] to run `command` on `path`, giving
+/// the body of its answer.
+pub fn query(command: &str, path: &Path) -> Result<String, DaemonError> {
+    let socket = socket_path();
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    if path.as_os_str().as_bytes().contains(&b'\n') {
+        return Err(DaemonError::BadPath(path));
+    }
+    if in_private_dir(&socket) {
+        check_private(socket.parent().expect("the socket is in a directory"))?;
+    }
+    let unreachable = |source| DaemonError::Unreachable {
+        socket: socket.clone(),
+        source,
+    };
+    let mut stream = UnixStream::connect(&socket).map_err(unreachable)?;
+    let request = [
+        command.as_bytes(),
+        b"\t",
+        path.as_os_str().as_bytes(),
+        b"\n",
+    ]
+    .concat();
+    stream.write_all(&request).map_err(unreachable)?;
+    let mut answer = String::new();
+    stream.read_to_string(&mut answer).map_err(unreachable)?;
+    parse_answer(&answer).map(str::to_string)
+}
+
+fn parse_answer(answer: &str) -> Result<&str, DaemonError> {
+    let (status, body) = answer.split_once('\n').ok_or(DaemonError::BadResponse)?;
+    if status == "ok" {
+        return Ok(body);
+    }
+    let mut fields = status.splitn(3, '\t');
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some("error"), Some(code), Some(message)) => Err(DaemonError::Failed {
+            code: code.parse().map_err(|_| DaemonError::BadResponse)?,
+            message: message.to_string(),
+        }),
+        _ => Err(DaemonError::BadResponse),
+    }
+}
+
+/// The `verify` lines of an answer body, with the exit code of each file.
+pub fn verify_lines(body: &str) -> Result<Vec<(u8, &str)>, DaemonError> {
+    body.lines()
+        .map(|line| {
+            let (code, line) = line.split_once('\t').ok_or(DaemonError::BadResponse)?;
+            let code = code.parse().map_err(|_| DaemonError::BadResponse)?;
+            Ok((code, line))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pngme::chunk::Chunk;
+    use pngme::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn testing_png() -> Vec<u8> {
+        let chunk = |t: &str, data: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("ruSt", b"hello"),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes()
+    }
+
+    fn stamp(len: u64) -> Stamp {
+        Stamp {
+            modified: Some(SystemTime::UNIX_EPOCH),
+            len,
+        }
+    }
+
+    #[test]
+    fn test_cache() {
+        let summary = Arc::new(Summary::new(Path::new("a.png"), &testing_png()));
+        let mut cache = Cache::new(2);
+        cache.insert("a".into(), stamp(1), Arc::clone(&summary));
+        cache.insert("b".into(), stamp(1), Arc::clone(&summary));
+        assert!(cache.get(Path::new("a"), stamp(1)).is_some());
+        assert!(cache.get(Path::new("a"), stamp(2)).is_none());
+        cache.insert("c".into(), stamp(1), Arc::clone(&summary));
+        assert!(cache.get(Path::new("b"), stamp(1)).is_none());
+        assert!(cache.get(Path::new("a"), stamp(1)).is_some());
+        assert!(cache.get(Path::new("c"), stamp(1)).is_some());
+        assert_eq!(cache.entries.len(), 2);
+    }
+
+    #[test]
+    fn test_bind() {
+        let root = std::env::temp_dir().join(format!("pngme-daemon-bind-{}", std::process::id()));
+        let fresh = root.join("fresh");
+        let socket = fresh.join("pngme.sock");
+        let bound = bind(&socket, false).is_ok();
+        let mode = |path: &Path| fs::symlink_metadata(path).map(|m| m.mode() & 0o777).ok();
+        let modes = (mode(&fresh), mode(&socket));
+
+        // Made by someone else first, and open to them.
+        let taken = root.join("taken");
+        DirBuilder::new().mode(0o755).create(&taken).unwrap();
+        fs::set_permissions(&taken, fs::Permissions::from_mode(0o755)).unwrap();
+        let refused = bind(&taken.join("pngme.sock"), true).err();
+        let left = fs::read_dir(&taken).unwrap().count();
+        let linked = root.join("linked");
+        std::os::unix::fs::symlink(&fresh, &linked).unwrap();
+        let through_link = check_private(&linked);
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(bound);
+        assert_eq!(modes, (Some(0o700), Some(0o600)));
+        let refused = refused.unwrap();
+        assert!(matches!(
+            refused.downcast_ref::<DaemonError>(),
+            Some(DaemonError::NotPrivate(dir)) if *dir == taken
+        ));
+        assert_eq!(left, 0);
+        assert!(matches!(through_link, Err(DaemonError::NotPrivate(_))));
+    }
+
+    #[test]
+    fn test_answer() {
+        let dir = std::env::temp_dir().join(format!("pngme-daemon-{}", std::process::id()));
+        fs::create_dir_all(dir.join("cats")).unwrap();
+        let good = dir.join("cats/good.png");
+        let bad = dir.join("bad.png");
+        fs::write(&good, testing_png()).unwrap();
+        let mut corrupt = testing_png();
+        let last = corrupt.len() - 13;
+        corrupt[last] ^= 1;
+        fs::write(&bad, &corrupt).unwrap();
+        let cache = Mutex::new(Cache::new(16));
+        let ask = |command: &str, path: &Path| {
+            let request = [
+                command.as_bytes(),
+                b"\t",
+                path.as_os_str().as_bytes(),
+                b"\n",
+            ]
+            .concat();
+            answer(&cache, &request)
+        };
+
+        let printed = ask("print", &good);
+        let again = ask("print", &good);
+        let verified = ask("verify", &dir);
+        let missing = ask("print", &dir.join("missing.png"));
+        let not_png = ask("print", &dir.join("cats"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let expected = Png::try_from(testing_png().as_slice()).unwrap().to_string();
+        assert_eq!(parse_answer(&printed).unwrap(), expected);
+        assert_eq!(again, printed);
+        assert_eq!(cache.lock().unwrap().entries.len(), 2);
+        let lines = verify_lines(parse_answer(&verified).unwrap()).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].0, exit::CRC_FAILURE);
+        assert!(lines[0]
+            .1
+            .starts_with(&format!("{}\tfailed\t", bad.display())));
+        assert_eq!(
+            lines[1],
+            (
+                0,
+                verified_line(&good, &verify::verify(testing_png().as_slice()).unwrap()).as_str()
+            )
+        );
+        assert!(matches!(
+            parse_answer(&missing),
+            Err(DaemonError::Failed {
+                code: exit::IO_ERROR,
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_answer(&not_png),
+            Err(DaemonError::Failed { .. })
+        ));
+        assert!(matches!(
+            parse_answer(&answer(&cache, b"print\trelative.png\n")),
+            Err(DaemonError::Failed {
+                code: exit::BAD_ARGUMENTS,
+                ..
+            })
+        ));
+    }
+}
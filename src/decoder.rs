@@ -0,0 +1,517 @@
+//! A push parser for PNG files that does no IO of its own.
+//!
+//! [`Decoder::feed`] takes bytes as they arrive, from a file, a socket or a
+//! browser stream, in pieces of any size, and returns what they completed:
+//! chunks, then whatever follows `IEND`. Callers looking for one chunk can
+//! stop feeding as soon as it shows up. [`Decoder::finish`] tells a file
+//! that just ended from one that was cut short. A [`CriticalPolicy`] picks
+//! what happens to critical chunks the decoder doesn't know. With `std`, [`Chunks`]
+//! drives a decoder from an `io::Read`; with the `async` feature,
+//! `asynchronous::AsyncChunks` does the same from an `AsyncRead`.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::io::{self, Read};
+
+use crate::chunk::{Chunk, ChunkError};
+use crate::chunk_type::ChunkType;
+use crate::limits::{self, Limit, Limits};
+use crate::options::{CrcPolicy, ParseOptions};
+use crate::parse::{self, Span};
+use crate::png::{Png, PngError};
+use crate::trace;
+
+/// Something [`Decoder::feed`] completed.
+#[derive(Clone, Debug)]
+pub enum Event {
+    /// A whole chunk, with the offset of its first byte in the file.
+    Chunk { offset: usize, chunk: Chunk },
+    /// Bytes after the `IEND` chunk, which can come in several pieces.
+    Trailer(Vec<u8>),
+    /// An unknown critical chunk taken out of the file under
+    /// [`CriticalPolicy::Quarantine`].
+    Quarantined { offset: usize, chunk: Chunk },
+}
+
+/// What a [`Decoder`] does with a critical chunk other than `IHDR`, `PLTE`,
+/// `IDAT` and `IEND`. Most decoders refuse files that have one, since the
+/// image can't be shown correctly without understanding it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CriticalPolicy {
+    /// Fail with [`PngError::UnknownCritical`].
+    Error,
+    /// Keep it like any other chunk. [`Decoder::unknown_critical`] lists it.
+    #[default]
+    Keep,
+    /// Set it aside as an [`Event::Quarantined`] instead of a chunk of the
+    /// file. [`Decoder::unknown_critical`] lists it too.
+    Quarantine,
+}
+
+#[derive(Debug)]
+pub struct UnknownPolicy(pub String);
+
+impl core::fmt::Display for UnknownPolicy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "Unknown policy {:?} (expected error, keep or quarantine)",
+            self.0
+        )
+    }
+}
+
+impl core::error::Error for UnknownPolicy {}
+
+impl core::str::FromStr for CriticalPolicy {
+    type Err = UnknownPolicy;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "keep" => Ok(Self::Keep),
+            "quarantine" => Ok(Self::Quarantine),
+            _ => Err(UnknownPolicy(s.into())),
+        }
+    }
+}
+
+/// Whether decoders are expected to know `chunk_type`, if it is critical.
+pub fn is_unknown_critical(chunk_type: &ChunkType) -> bool {
+    chunk_type.is_critical()
+        && !matches!(&chunk_type.bytes(), b"IHDR" | b"PLTE" | b"IDAT" | b"IEND")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Signature,
+    Chunks,
+    /// `IEND` has been seen.
+    Trailer,
+}
+
+#[derive(Clone, Debug)]
+pub struct Decoder {
+    state: State,
+    check_crc: bool,
+    /// Bytes fed but not yet part of an event.
+    buffer: Vec<u8>,
+    /// Offset in the file of the first byte of `buffer`.
+    offset: usize,
+    limits: Limits,
+    /// Chunks parsed so far.
+    chunks: usize,
+    policy: CriticalPolicy,
+    /// Offsets and types of the unknown critical chunks seen so far.
+    unknown_critical: Vec<(usize, ChunkType)>,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            state: State::Signature,
+            check_crc: true,
+            buffer: Vec::new(),
+            offset: 0,
+            limits: limits::get(),
+            chunks: 0,
+            policy: CriticalPolicy::default(),
+            unknown_critical: Vec::new(),
+        }
+    }
+    /// Replaces the process-wide limits (see [`crate::limits`]) for this
+    /// decoder.
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+    pub fn with_critical_policy(self, policy: CriticalPolicy) -> Self {
+        Self { policy, ..self }
+    }
+    /// A decoder following `options`' CRC policy, limits and critical
+    /// policy.
+    pub fn with_options(options: &ParseOptions) -> Self {
+        Self {
+            check_crc: options.crc_policy() == CrcPolicy::Error,
+            limits: options.limits(),
+            policy: options.critical_policy(),
+            ..Self::new()
+        }
+    }
+    /// Like [`Decoder::new`], but keeps chunks whose CRC doesn't match (see
+    /// [`Png::from_bytes_unchecked`]).
+    pub fn unchecked() -> Self {
+        Self {
+            check_crc: false,
+            ..Self::new()
+        }
+    }
+    /// Starts over on a new file, keeping the buffer's memory.
+    pub fn reset(&mut self) {
+        self.state = State::Signature;
+        self.buffer.clear();
+        self.offset = 0;
+        self.chunks = 0;
+        self.unknown_critical.clear();
+    }
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+    /// The offsets and types of the unknown critical chunks kept or
+    /// quarantined so far.
+    pub fn unknown_critical(&self) -> &[(usize, ChunkType)] {
+        &self.unknown_critical
+    }
+    /// Whether `IEND` has been seen: everything fed from now on is trailer.
+    pub fn is_done(&self) -> bool {
+        self.state == State::Trailer
+    }
+    /// How many bytes have been fed so far.
+    pub fn position(&self) -> usize {
+        self.offset + self.buffer.len()
+    }
+    /// Parses as much of the input fed so far as possible.
+    pub fn feed(&mut self, input: &[u8]) -> Result<Vec<Event>, PngError> {
+        let mut events = Vec::new();
+        // Every offset below is at most this, so none of them can overflow.
+        let Some(end) = self.position().checked_add(input.len()) else {
+            return Err(PngError::TooLarge);
+        };
+        self.limits.check(Limit::FileLen, end)?;
+        if self.state == State::Trailer {
+            if !input.is_empty() {
+                self.offset += input.len();
+                events.push(Event::Trailer(input.to_vec()));
+            }
+            return Ok(events);
+        }
+        self.buffer.extend_from_slice(input);
+        let mut consumed = 0;
+        loop {
+            let rest = &self.buffer[consumed..];
+            match self.state {
+                State::Signature => {
+                    let len = Png::STANDARD_HEADER.len();
+                    if rest[..rest.len().min(len)] != Png::STANDARD_HEADER[..rest.len().min(len)] {
+                        return Err(PngError::InvalidHeader);
+                    }
+                    if rest.len() < len {
+                        break;
+                    }
+                    consumed += len;
+                    self.state = State::Chunks;
+                }
+                State::Chunks => {
+                    let offset = self.offset + consumed;
+                    let bad = |source| PngError::BadChunk { offset, source };
+                    if rest.len() < 8 {
+                        break;
+                    }
+                    // Refuse a bad type before waiting for data that may
+                    // never come.
+                    let chunk_type: [u8; 4] = rest[4..8].try_into().expect("4 bytes");
+                    let chunk_type = ChunkType::try_from(chunk_type).map_err(|e| bad(e.into()))?;
+                    let unknown = is_unknown_critical(&chunk_type);
+                    if unknown && self.policy == CriticalPolicy::Error {
+                        return Err(PngError::UnknownCritical { offset, chunk_type });
+                    }
+                    let length = u32::from_be_bytes(rest[..4].try_into().expect("4 bytes"));
+                    if length > Chunk::MAX_LENGTH {
+                        return Err(bad(ChunkError::TooLong(length as usize)));
+                    }
+                    let total = length as usize + Chunk::OVERHEAD;
+                    // Refuse a chunk that can't fit before buffering it.
+                    self.limits
+                        .check(Limit::FileLen, offset.saturating_add(total))?;
+                    self.limits.check(Limit::Chunks, self.chunks + 1)?;
+                    if rest.len() < total {
+                        break;
+                    }
+                    let chunk = if self.check_crc {
+                        Chunk::try_from(&rest[..total])
+                    } else {
+                        Chunk::from_bytes_unchecked(&rest[..total])
+                    }
+                    .map_err(bad)?;
+                    trace::event!(trace, offset, chunk_type = %chunk_type, length, "chunk");
+                    consumed += total;
+                    self.chunks += 1;
+                    if chunk.chunk_type().bytes() == *b"IEND" {
+                        self.state = State::Trailer;
+                    }
+                    if !unknown {
+                        events.push(Event::Chunk { offset, chunk });
+                        continue;
+                    }
+                    self.unknown_critical.push((offset, chunk_type));
+                    events.push(match self.policy {
+                        CriticalPolicy::Quarantine => Event::Quarantined { offset, chunk },
+                        _ => Event::Chunk { offset, chunk },
+                    });
+                }
+                State::Trailer => {
+                    if !rest.is_empty() {
+                        events.push(Event::Trailer(rest.to_vec()));
+                        consumed = self.buffer.len();
+                    }
+                    break;
+                }
+            }
+        }
+        self.buffer.drain(..consumed);
+        self.offset += consumed;
+        Ok(events)
+    }
+    /// Checks that the input didn't stop in the middle of the signature or
+    /// of a chunk. A file may end without `IEND`.
+    pub fn finish(&self) -> Result<(), PngError> {
+        match self.state {
+            State::Signature => Err(PngError::InvalidHeader),
+            State::Chunks if !self.buffer.is_empty() => {
+                // Whole chunks never stay buffered, so this one is cut short.
+                let err = parse::chunk(&self.buffer)
+                    .err()
+                    .expect("an incomplete chunk");
+                let span = parse::span(&self.buffer, err);
+                Err(PngError::BadChunk {
+                    offset: self.offset,
+                    source: ChunkError::Truncated(Span {
+                        offset: self.offset + span.offset,
+                        ..span
+                    }),
+                })
+            }
+            State::Chunks | State::Trailer => Ok(()),
+        }
+    }
+}
+
+/// The chunks of a PNG file read from `reader`, parsed as they are read.
+/// Iteration stops after `IEND`, without reading the rest of the input.
+#[cfg(feature = "std")]
+pub struct Chunks<R> {
+    reader: R,
+    decoder: Option<Decoder>,
+    pending: std::vec::IntoIter<Event>,
+    buffer: Box<[u8]>,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Chunks<R> {
+    pub fn new(reader: R) -> Self {
+        Self::with_decoder(reader, Decoder::new())
+    }
+    pub fn with_decoder(reader: R, decoder: Decoder) -> Self {
+        Self {
+            reader,
+            decoder: Some(decoder),
+            pending: Vec::new().into_iter(),
+            buffer: vec![0; 64 * 1024].into_boxed_slice(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Iterator for Chunks<R> {
+    type Item = Result<Chunk, PngError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for event in self.pending.by_ref() {
+                if let Event::Chunk { chunk, .. } = event {
+                    return Some(Ok(chunk));
+                }
+            }
+            let decoder = self.decoder.as_mut()?;
+            if decoder.is_done() {
+                self.decoder = None;
+                return None;
+            }
+            let read = match self.reader.read(&mut self.buffer) {
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let offset = decoder.position();
+                    self.decoder = None;
+                    return Some(Err(PngError::BadChunk {
+                        offset,
+                        source: e.into(),
+                    }));
+                }
+            };
+            if read == 0 {
+                let decoder = self.decoder.take().expect("checked above");
+                return decoder.finish().err().map(Err);
+            }
+            match decoder.feed(&self.buffer[..read]) {
+                Ok(events) => self.pending = events.into_iter(),
+                Err(e) => {
+                    self.decoder = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limits::LimitExceeded;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), &[0; 13]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello"),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), &[]),
+        ]);
+        png.set_trailer(b"after".to_vec());
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_feed_byte_by_byte() {
+        let bytes = testing_bytes();
+        let mut decoder = Decoder::new();
+        let mut chunks = Vec::new();
+        let mut trailer = Vec::new();
+        for byte in &bytes {
+            for event in decoder.feed(&[*byte]).unwrap() {
+                match event {
+                    Event::Chunk { offset, chunk } => chunks.push((offset, chunk)),
+                    Event::Trailer(bytes) => trailer.extend(bytes),
+                    Event::Quarantined { .. } => unreachable!("kept by default"),
+                }
+            }
+        }
+        decoder.finish().unwrap();
+        let offsets: Vec<usize> = chunks.iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, [8, 8 + 25, 8 + 25 + 17]);
+        assert_eq!(chunks[1].1.data(), b"hello");
+        assert_eq!(trailer, b"after");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(
+            Decoder::new().feed(b"GIF89a"),
+            Err(PngError::InvalidHeader)
+        ));
+        assert!(matches!(
+            Decoder::new().finish(),
+            Err(PngError::InvalidHeader)
+        ));
+
+        let bytes = testing_bytes();
+        let mut decoder = Decoder::new();
+        decoder.feed(&bytes[..40]).unwrap();
+        assert!(matches!(
+            decoder.finish(),
+            Err(PngError::BadChunk { offset: 33, .. })
+        ));
+
+        let mut damaged = bytes.clone();
+        damaged[8 + 25 + 8] ^= 1;
+        assert!(matches!(
+            Decoder::new().feed(&damaged),
+            Err(PngError::BadChunk {
+                offset: 33,
+                source: ChunkError::ChecksumError
+            })
+        ));
+        assert_eq!(Decoder::unchecked().feed(&damaged).unwrap().len(), 4);
+
+        // Refused from the length alone, before any data is buffered.
+        let mut oversized = bytes[..33].to_vec();
+        oversized.extend_from_slice(&(Chunk::MAX_LENGTH + 1).to_be_bytes());
+        oversized.extend_from_slice(b"IDAT");
+        assert!(matches!(
+            Decoder::unchecked().feed(&oversized),
+            Err(PngError::BadChunk {
+                offset: 33,
+                source: ChunkError::TooLong(0x8000_0000)
+            })
+        ));
+    }
+
+    #[test]
+    fn test_limits() {
+        let bytes = testing_bytes();
+        let limited = |limits| Decoder::new().with_limits(limits);
+        let exceeded = |result: Result<Vec<Event>, PngError>, expected| match result {
+            Err(PngError::LimitExceeded(LimitExceeded { limit, .. })) => limit == expected,
+            _ => false,
+        };
+        let chunks = |max_chunks| Limits {
+            max_chunks,
+            ..Limits::UNLIMITED
+        };
+        assert!(exceeded(limited(chunks(2)).feed(&bytes), Limit::Chunks));
+        assert_eq!(limited(chunks(3)).feed(&bytes).unwrap().len(), 4);
+
+        let file_len = |max_file_len| Limits {
+            max_file_len,
+            ..Limits::UNLIMITED
+        };
+        let mut decoder = limited(file_len(bytes.len()));
+        decoder.feed(&bytes).unwrap();
+        assert!(exceeded(decoder.feed(b"!"), Limit::FileLen));
+        // A chunk too large to fit is refused from its header alone.
+        let mut header = Png::STANDARD_HEADER.to_vec();
+        header.extend(5000u32.to_be_bytes());
+        header.extend(b"IDAT");
+        assert!(exceeded(
+            limited(file_len(1000)).feed(&header),
+            Limit::FileLen
+        ));
+    }
+
+    #[test]
+    fn test_critical_policy() {
+        let mut png = Png::try_from(testing_bytes().as_slice()).unwrap();
+        png.insert_chunk_at(1, Chunk::new(ChunkType::from_str("ABCD").unwrap(), b"?"));
+        let bytes = png.as_bytes();
+        let with = |policy| Decoder::new().with_critical_policy(policy);
+
+        let mut keep = with(CriticalPolicy::Keep);
+        assert_eq!(keep.feed(&bytes).unwrap().len(), 5);
+        assert_eq!(keep.unknown_critical().len(), 1);
+        assert_eq!(keep.unknown_critical()[0].0, 33);
+
+        let mut quarantine = with(CriticalPolicy::Quarantine);
+        let events = quarantine.feed(&bytes).unwrap();
+        assert!(matches!(
+            &events[1],
+            Event::Quarantined { offset: 33, chunk } if chunk.data() == b"?"
+        ));
+        quarantine.reset();
+        assert!(quarantine.unknown_critical().is_empty());
+
+        assert!(matches!(
+            with(CriticalPolicy::Error).feed(&bytes),
+            Err(PngError::UnknownCritical { offset: 33, .. })
+        ));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_chunks_stop_after_iend() {
+        let bytes = testing_bytes();
+        let mut reader = bytes.as_slice();
+        let types: Vec<String> = Chunks::new(&mut reader)
+            .map(|chunk| chunk.unwrap().chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "ruSt", "IEND"]);
+
+        let first = Chunks::new(&bytes[..40]).next().unwrap().unwrap();
+        assert_eq!(first.chunk_type().to_string(), "IHDR");
+        assert!(Chunks::new(&bytes[..40]).nth(1).unwrap().is_err());
+    }
+}
@@ -0,0 +1,179 @@
+//! Finding pngme payloads across many files, for audits and cleanup
+//! sweeps.
+//!
+//! [`detect`] reads a chunk's data only when it starts with the envelope
+//! magic, and seeks past everything else, image data included; a payload
+//! after `IEND` is found from its footer at the end of the file. CRCs
+//! aren't checked, so damaged carriers are still reported, and envelopes
+//! are parsed but never opened, so no password is needed.
+
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+use crate::chunk_type::ChunkType;
+use crate::envelope::{Envelope, PayloadKind};
+use crate::png::PngError;
+use crate::trailer;
+use crate::walk::{self, bad, Walker};
+
+/// Where a payload is stored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Location {
+    Chunk(ChunkType),
+    Trailer,
+}
+
+/// A pngme payload found by [`detect`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Detected {
+    pub location: Location,
+    /// Bytes the payload takes in the file, its envelope included.
+    pub len: usize,
+    /// `None` when the data starts like an envelope but doesn't parse.
+    pub kind: Option<PayloadKind>,
+    pub name: Option<String>,
+    /// Whether opening it takes a password or an identity.
+    pub encrypted: bool,
+}
+
+impl Detected {
+    fn new(location: Location, data: &[u8]) -> Self {
+        let envelope = Envelope::try_from(data).ok();
+        Self {
+            location,
+            len: data.len(),
+            kind: envelope.as_ref().map(Envelope::kind),
+            name: envelope
+                .as_ref()
+                .and_then(|envelope| envelope.name().map(str::to_string)),
+            encrypted: envelope.as_ref().is_some_and(Envelope::is_encrypted),
+        }
+    }
+}
+
+/// The pngme payloads in the PNG file in `reader`, in the order they are
+/// stored. Accepts a file that ends without `IEND`.
+pub fn detect(reader: impl BufRead + Seek) -> Result<Vec<Detected>, PngError> {
+    let mut walker = Walker::new(reader)?;
+    let mut found = Vec::new();
+    while let Some(header) = walker.next_header()? {
+        let reader = walker.reader();
+        let mut magic = [0; Envelope::MAGIC.len()];
+        let peeked = magic.len().min(header.length as usize);
+        reader
+            .read_exact(&mut magic[..peeked])
+            .map_err(|e| header.bad(e))?;
+        let rest = if Envelope::is_envelope(&magic[..peeked]) {
+            let mut data = magic.to_vec();
+            data.resize(header.length as usize, 0);
+            reader
+                .read_exact(&mut data[peeked..])
+                .map_err(|e| header.bad(e))?;
+            found.push(Detected::new(
+                Location::Chunk(header.chunk_type.clone()),
+                &data,
+            ));
+            0
+        } else {
+            (header.length as usize - peeked) as u64
+        };
+        // The data not read, and the CRC.
+        walk::skip(reader, rest + 4).map_err(|e| header.bad(e))?;
+        if header.is_end() {
+            let end = walker.offset();
+            if let Some(data) =
+                trailer_payload(walker.reader(), end).map_err(|e| bad(end, e.into()))?
+            {
+                found.push(Detected::new(Location::Trailer, &data));
+            }
+            break;
+        }
+    }
+    Ok(found)
+}
+
+/// The framed payload after `IEND`, which ends at `end`, found by reading
+/// the footer at the end of the file and then only the payload.
+fn trailer_payload(reader: &mut (impl Read + Seek), end: u64) -> io::Result<Option<Vec<u8>>> {
+    let footer_len = 4 + trailer::FOOTER.len() as u64;
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    if file_len < end + footer_len {
+        return Ok(None);
+    }
+    let mut footer = [0; 8];
+    reader.seek(SeekFrom::Start(file_len - footer_len))?;
+    reader.read_exact(&mut footer)?;
+    if footer[4..] != *trailer::FOOTER {
+        return Ok(None);
+    }
+    let len = u32::from_be_bytes(footer[..4].try_into().expect("4 bytes")) as u64;
+    if end + footer_len + len > file_len {
+        return Ok(None);
+    }
+    let mut data = vec![0; len as usize];
+    reader.seek(SeekFrom::Start(file_len - footer_len - len))?;
+    reader.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::crypto::Encryption;
+    use crate::png::Png;
+    use std::io::{BufReader, Cursor};
+    use std::str::FromStr;
+
+    fn chunk(code: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(code).unwrap(), data)
+    }
+
+    #[test]
+    fn test_detect() {
+        let named = Envelope::text("hello").with_name("greeting").as_bytes();
+        let locked = Envelope::text("secret")
+            .encrypt(b"hunter2", &Encryption::default())
+            .unwrap()
+            .as_bytes();
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("IDAT", &[7; 300_000]),
+            chunk("ruSt", &named),
+            chunk("teXt", b"PNG"),
+            chunk("IEND", &[]),
+        ]);
+        trailer::attach(&mut png, &locked).unwrap();
+        let bytes = png.as_bytes();
+
+        let found = detect(BufReader::with_capacity(64, Cursor::new(&bytes))).unwrap();
+        assert_eq!(
+            found,
+            [
+                Detected {
+                    location: Location::Chunk(ChunkType::from_str("ruSt").unwrap()),
+                    len: named.len(),
+                    kind: Some(PayloadKind::Text),
+                    name: Some("greeting".to_string()),
+                    encrypted: false,
+                },
+                Detected {
+                    location: Location::Trailer,
+                    len: locked.len(),
+                    kind: Some(PayloadKind::Encrypted),
+                    name: None,
+                    encrypted: true,
+                },
+            ]
+        );
+
+        let mut damaged = bytes.clone();
+        // The envelope's version.
+        damaged[8 + 25 + 300_012 + 8 + 5] = 99;
+        let found = detect(Cursor::new(&damaged)).unwrap();
+        assert_eq!(found[0].kind, None);
+        assert!(matches!(
+            detect(Cursor::new(b"GIF89a")),
+            Err(PngError::InvalidHeader)
+        ));
+    }
+}
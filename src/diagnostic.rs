@@ -0,0 +1,334 @@
+//! Parse errors as diagnostics, for tools that show them to people.
+//!
+//! [`ChunkTypeError`], [`ChunkError`], [`PngError`] and [`LimitExceeded`]
+//! each convert into a [`Diagnostic`]: a stable code naming what went
+//! wrong, whichever of them reported it, the message, labels pointing at
+//! the bytes of the input at fault, and help on what to do about it.
+//! [`Diagnostic::render`] draws the labels under a hex dump of the input,
+//! in the manner of compilers and miette.
+//!
+//! Offsets in labels count from the start of what was parsed: the whole
+//! file for a [`PngError`], the chunk for a [`ChunkError`] on its own.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter, Write};
+
+use crate::chunk::ChunkError;
+use crate::chunk_type::ChunkTypeError;
+use crate::limits::LimitExceeded;
+use crate::png::PngError;
+
+/// Bytes a row of [`Diagnostic::render`]'s hex dump shows.
+const ROW_LEN: usize = 16;
+
+/// Bytes of the input a [`Diagnostic`] is about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    pub offset: usize,
+    /// 0 to point between bytes, at the end of an input that stopped short.
+    pub len: usize,
+    pub message: String,
+}
+
+impl Label {
+    fn new(offset: usize, len: usize, message: impl Into<String>) -> Self {
+        Self {
+            offset,
+            len,
+            message: message.into(),
+        }
+    }
+}
+
+/// An error with what a person needs to act on it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Stable across releases, like `pngme::chunk::checksum`, for matching
+    /// and for looking up.
+    pub code: &'static str,
+    /// The error's own message, as its `Display` gives it.
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub help: Option<&'static str>,
+}
+
+impl Diagnostic {
+    fn new(code: &'static str, message: impl ToString, help: Option<&'static str>) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+            labels: Vec::new(),
+            help,
+        }
+    }
+    fn with_label(mut self, label: Label) -> Self {
+        self.labels.push(label);
+        self
+    }
+
+    /// The diagnostic with each label under the rows of `input` it points
+    /// at, `input` being what failed to parse and `name` what to call it.
+    pub fn render(&self, name: &str, input: &[u8]) -> String {
+        let mut out = format!("error[{}]: {}\n", self.code, self.message);
+        for label in &self.labels {
+            let _ = writeln!(
+                out,
+                "  --> {} at byte {} ({:#x})",
+                name, label.offset, label.offset
+            );
+            let first = label.offset / ROW_LEN * ROW_LEN;
+            let end = label.offset + label.len.max(1);
+            // Two rows at most, enough to show where a label starts.
+            let last = end.div_ceil(ROW_LEN).min(first / ROW_LEN + 2) * ROW_LEN;
+            for row in (first..last).step_by(ROW_LEN) {
+                let bytes = input.get(row..input.len().min(row + ROW_LEN));
+                let hex: Vec<String> = bytes
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect();
+                let _ = writeln!(out, "  {:08x} | {}", row, hex.join(" "));
+                let marks: String = (row..row + ROW_LEN)
+                    .take_while(|&i| i < end)
+                    .map(|i| match (i < label.offset, label.len) {
+                        (true, _) => "   ",
+                        (false, 0) => "^",
+                        (false, _) => "^^ ",
+                    })
+                    .collect();
+                if !marks.trim().is_empty() {
+                    let _ = writeln!(out, "           | {}", marks.trim_end());
+                }
+            }
+            let _ = writeln!(out, "           = {}", label.message);
+        }
+        if let Some(help) = self.help {
+            let _ = writeln!(out, "  help: {}", help);
+        }
+        out
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "error[{}]: {}", self.code, self.message)?;
+        for label in &self.labels {
+            write!(f, "\n  at byte {}: {}", label.offset, label.message)?;
+        }
+        if let Some(help) = self.help {
+            write!(f, "\n  help: {}", help)?;
+        }
+        Ok(())
+    }
+}
+
+impl core::error::Error for Diagnostic {}
+
+impl From<&ChunkTypeError> for Diagnostic {
+    fn from(err: &ChunkTypeError) -> Self {
+        let help = Some("Chunk types are four ASCII letters, like IHDR or tEXt");
+        match err {
+            ChunkTypeError::NonAlphabeticCharacters => {
+                Self::new("pngme::chunk_type::not_alphabetic", err, help)
+            }
+            ChunkTypeError::InvalidStringLength(_) => {
+                Self::new("pngme::chunk_type::length", err, help)
+            }
+        }
+    }
+}
+
+impl From<&ChunkError> for Diagnostic {
+    fn from(err: &ChunkError) -> Self {
+        chunk_diagnostic(err, 0, err)
+    }
+}
+
+impl From<&LimitExceeded> for Diagnostic {
+    fn from(err: &LimitExceeded) -> Self {
+        Self::new(
+            "pngme::limits::exceeded",
+            err,
+            Some(
+                "Limits guard against hostile files; raise them with limits::set if the \
+                 input is trusted",
+            ),
+        )
+    }
+}
+
+impl From<&PngError> for Diagnostic {
+    fn from(err: &PngError) -> Self {
+        match err {
+            PngError::InvalidHeader => Self::new(
+                "pngme::png::signature",
+                err,
+                Some("The input isn't a PNG file, or its first bytes were damaged"),
+            )
+            .with_label(Label::new(0, 8, "expected 89 50 4e 47 0d 0a 1a 0a")),
+            PngError::BadChunk { offset, source } => chunk_diagnostic(source, *offset, err),
+            PngError::BadChunkType(e) => Self {
+                message: err.to_string(),
+                ..Self::from(e)
+            },
+            PngError::ChunkNotFound(_) => Self::new(
+                "pngme::png::chunk_not_found",
+                err,
+                Some("`pngme print` lists the chunks the file has"),
+            ),
+            PngError::UnknownCritical { offset, .. } => Self::new(
+                "pngme::png::unknown_critical",
+                err,
+                Some(
+                    "Decoders can't show the image without understanding this chunk; keep or \
+                     quarantine it with CriticalPolicy",
+                ),
+            )
+            .with_label(Label::new(
+                offset + 4,
+                4,
+                "an uppercase first letter marks it critical",
+            )),
+            PngError::LimitExceeded(e) => Self::from(e),
+            PngError::TooLarge => Self::new("pngme::png::too_large", err, None),
+            #[cfg(feature = "std")]
+            PngError::Nonconforming { .. } => Self::new(
+                "pngme::png::nonconforming",
+                err,
+                Some("`pngme lint` lists everything the file gets wrong, by rule"),
+            ),
+        }
+    }
+}
+
+macro_rules! from_owned {
+    ($($error:ty),*) => {$(
+        impl From<$error> for Diagnostic {
+            fn from(err: $error) -> Self {
+                Self::from(&err)
+            }
+        }
+    )*};
+}
+
+from_owned!(ChunkTypeError, ChunkError, LimitExceeded, PngError);
+
+/// The diagnostic of `err`, met in a chunk starting at `offset`, with the
+/// message of `outer`, the error that carried it.
+fn chunk_diagnostic(err: &ChunkError, offset: usize, outer: &dyn Display) -> Diagnostic {
+    match err {
+        #[cfg(feature = "std")]
+        ChunkError::InvalidChunkData(_) => Diagnostic::new("pngme::chunk::io", outer, None),
+        ChunkError::NonUTf8Characters(_) => Diagnostic::new(
+            "pngme::chunk::not_utf8",
+            outer,
+            Some("The chunk holds binary data; read it as bytes rather than as a string"),
+        ),
+        ChunkError::BadChunkType(_) => Diagnostic::new(
+            "pngme::chunk::chunk_type",
+            outer,
+            Some(
+                "Chunk types are four ASCII letters; anything else means the chunk lengths \
+                 before it are off, which `pngme repair` can work out",
+            ),
+        )
+        .with_label(Label::new(offset + 4, 4, "not a chunk type")),
+        ChunkError::ChecksumError => Diagnostic::new(
+            "pngme::chunk::checksum",
+            outer,
+            Some(
+                "The chunk was changed after it was written, or the file was damaged; \
+                 `pngme repair` salvages the chunks whose CRCs match",
+            ),
+        )
+        .with_label(Label::new(
+            offset,
+            8,
+            "this chunk's CRC doesn't match its type and data",
+        )),
+        ChunkError::Truncated(span) => Diagnostic::new(
+            "pngme::chunk::truncated",
+            outer,
+            Some("The input ends in the middle of a chunk; it may have been cut short in transfer"),
+        )
+        .with_label(Label::new(
+            span.offset,
+            0,
+            format!("the {} runs past the end", span.what),
+        )),
+        ChunkError::TooLong(_) => Diagnostic::new(
+            "pngme::chunk::too_long",
+            outer,
+            Some(
+                "A length this large usually means the file was damaged here; `pngme repair` \
+                 can find where chunks really start",
+            ),
+        )
+        .with_label(Label::new(offset, 4, "the chunk's length")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::png::Png;
+    use core::str::FromStr;
+
+    fn testing_png() -> Vec<u8> {
+        let ihdr = [0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0];
+        let chunks = [("IHDR", &ihdr[..]), ("tEXt", b"Comment\0hi"), ("IEND", b"")];
+        Png::from_chunks(
+            chunks
+                .iter()
+                .map(|(code, data)| Chunk::new(ChunkType::from_str(code).unwrap(), data))
+                .collect(),
+        )
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_diagnostic() {
+        let mut bytes = testing_png();
+        // The last byte of the tEXt CRC, at 33 + 12 + 10 - 1.
+        bytes[54] ^= 1;
+        let err = Png::try_from(bytes.as_slice()).unwrap_err();
+        let diagnostic = Diagnostic::from(&err);
+        assert_eq!(diagnostic.code, "pngme::chunk::checksum");
+        assert_eq!(diagnostic.message, err.to_string());
+        assert_eq!(diagnostic.labels[0].offset, 33);
+        assert_eq!(
+            diagnostic.render("image.png", &bytes),
+            format!(
+                "error[pngme::chunk::checksum]: {}\n\
+                 \x20 --> image.png at byte 33 (0x21)\n\
+                 \x20 00000020 | 55 00 00 00 0a 74 45 58 74 43 6f 6d 6d 65 6e 74\n\
+                 \x20          |    ^^ ^^ ^^ ^^ ^^ ^^ ^^ ^^\n\
+                 \x20          = this chunk's CRC doesn't match its type and data\n\
+                 \x20 help: {}\n",
+                err,
+                diagnostic.help.unwrap()
+            )
+        );
+
+        let cut = &testing_png()[..40];
+        let err = Png::try_from(cut).unwrap_err();
+        let diagnostic = Diagnostic::from(err);
+        assert_eq!(diagnostic.code, "pngme::chunk::truncated");
+        assert_eq!(diagnostic.labels[0].len, 0);
+        assert!(diagnostic
+            .render("cut.png", cut)
+            .contains(&format!("\n           | {}^\n", " ".repeat(15))));
+
+        let err = ChunkType::from_str("IH1R").unwrap_err();
+        let diagnostic = Diagnostic::from(&PngError::BadChunkType(err));
+        assert_eq!(diagnostic.code, "pngme::chunk_type::not_alphabetic");
+        assert!(diagnostic.labels.is_empty());
+        assert!(diagnostic.to_string().starts_with(
+            "error[pngme::chunk_type::not_alphabetic]: Bad ChunkType: Characters can only"
+        ));
+    }
+}
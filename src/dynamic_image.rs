@@ -0,0 +1,238 @@
+//! Conversions to and from the image crate's [`DynamicImage`], so programs
+//! that decode, resize or draw with it can hand the result to pngme for
+//! its chunks and payloads, and take the pixels of a file back.
+//!
+//! A `DynamicImage` becomes a PNG file of the closest color type and bit
+//! depth; floating-point images, which PNG can't hold, become 16-bit. The
+//! other way, 8- and 16-bit images without `tRNS` keep their layout, gray
+//! images of fewer bits are scaled to 8, and indexed images or those with
+//! `tRNS` become RGBA, of 16 bits if their samples are.
+
+use ::image::{DynamicImage, ImageBuffer, Luma, Pixel, Rgba, RgbaImage};
+
+use crate::image::{ColorType, Colors, EncodeOptions, Header, ImageData, ImageError};
+use crate::png::Png;
+
+impl TryFrom<&DynamicImage> for ImageData {
+    type Error = ImageError;
+
+    /// Fails on an empty image, which PNG can't hold.
+    fn try_from(image: &DynamicImage) -> Result<Self, Self::Error> {
+        let (color_type, bit_depth, pixels) = match image {
+            DynamicImage::ImageLuma8(buffer) => (ColorType::Grayscale, 8, buffer.to_vec()),
+            DynamicImage::ImageLumaA8(buffer) => (ColorType::GrayscaleAlpha, 8, buffer.to_vec()),
+            DynamicImage::ImageRgb8(buffer) => (ColorType::Rgb, 8, buffer.to_vec()),
+            DynamicImage::ImageRgba8(buffer) => (ColorType::Rgba, 8, buffer.to_vec()),
+            DynamicImage::ImageLuma16(buffer) => (ColorType::Grayscale, 16, big_endian(buffer)),
+            DynamicImage::ImageLumaA16(buffer) => {
+                (ColorType::GrayscaleAlpha, 16, big_endian(buffer))
+            }
+            DynamicImage::ImageRgb16(buffer) => (ColorType::Rgb, 16, big_endian(buffer)),
+            DynamicImage::ImageRgba16(buffer) => (ColorType::Rgba, 16, big_endian(buffer)),
+            DynamicImage::ImageRgb32F(_) => (ColorType::Rgb, 16, big_endian(&image.to_rgb16())),
+            _ => (ColorType::Rgba, 16, big_endian(&image.to_rgba16())),
+        };
+        let header = Header {
+            width: image.width(),
+            height: image.height(),
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+        Self::new(header, pixels)
+    }
+}
+
+impl TryFrom<DynamicImage> for Png {
+    type Error = ImageError;
+
+    fn try_from(image: DynamicImage) -> Result<Self, Self::Error> {
+        ImageData::try_from(&image)?.to_png(&EncodeOptions::default())
+    }
+}
+
+impl TryFrom<&Png> for DynamicImage {
+    type Error = ImageError;
+
+    fn try_from(png: &Png) -> Result<Self, Self::Error> {
+        let image = ImageData::decode(png)?;
+        let header = *image.header();
+        let (width, height) = (header.width, header.height);
+        let colors = Colors::of(png, &header);
+        let keyed = header.color_type == ColorType::Indexed || png.chunk_by_type("tRNS").is_some();
+        Ok(match (header.color_type, header.bit_depth) {
+            _ if keyed && header.bit_depth == 16 => {
+                Self::ImageRgba16(ImageBuffer::from_fn(width, height, |x, y| {
+                    Rgba(colors.rgba(&image, x, y))
+                }))
+            }
+            _ if keyed => Self::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+                Rgba(colors.rgba(&image, x, y).map(|sample| (sample >> 8) as u8))
+            })),
+            (ColorType::Grayscale, 8) => {
+                Self::ImageLuma8(from_raw(width, height, image.as_bytes().to_vec()))
+            }
+            (ColorType::GrayscaleAlpha, 8) => {
+                Self::ImageLumaA8(from_raw(width, height, image.as_bytes().to_vec()))
+            }
+            (ColorType::Rgb, 8) => {
+                Self::ImageRgb8(from_raw(width, height, image.as_bytes().to_vec()))
+            }
+            (ColorType::Rgba, 8) => {
+                Self::ImageRgba8(from_raw(width, height, image.as_bytes().to_vec()))
+            }
+            (ColorType::Grayscale, 16) => {
+                Self::ImageLuma16(from_raw(width, height, native_endian(image.as_bytes())))
+            }
+            (ColorType::GrayscaleAlpha, 16) => {
+                Self::ImageLumaA16(from_raw(width, height, native_endian(image.as_bytes())))
+            }
+            (ColorType::Rgb, 16) => {
+                Self::ImageRgb16(from_raw(width, height, native_endian(image.as_bytes())))
+            }
+            (ColorType::Rgba, 16) => {
+                Self::ImageRgba16(from_raw(width, height, native_endian(image.as_bytes())))
+            }
+            // Gray of 1, 2 or 4 bits.
+            _ => {
+                let max = (1u16 << header.bit_depth) - 1;
+                Self::ImageLuma8(ImageBuffer::from_fn(width, height, |x, y| {
+                    Luma([(image.pixel(x, y)[0] * 255 / max) as u8])
+                }))
+            }
+        })
+    }
+}
+
+fn from_raw<P: Pixel>(
+    width: u32,
+    height: u32,
+    samples: Vec<P::Subpixel>,
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    ImageBuffer::from_raw(width, height, samples).expect("the buffer holds a whole image")
+}
+
+/// 16-bit samples as PNG stores them.
+fn big_endian<P: Pixel<Subpixel = u16>>(buffer: &ImageBuffer<P, Vec<u16>>) -> Vec<u8> {
+    buffer
+        .iter()
+        .flat_map(|sample| sample.to_be_bytes())
+        .collect()
+}
+
+fn native_endian(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use ::image::{GrayAlphaImage, LumaA, Rgb, Rgb32FImage, RgbImage};
+    use std::str::FromStr;
+
+    fn round_trip(image: DynamicImage) -> DynamicImage {
+        let bytes = Png::try_from(image).unwrap().as_bytes();
+        DynamicImage::try_from(&Png::try_from(bytes.as_slice()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let rgb = RgbImage::from_fn(7, 5, |x, y| Rgb([x as u8 * 30, y as u8 * 40, 200]));
+        let gray_alpha = GrayAlphaImage::from_fn(3, 9, |x, y| LumaA([x as u8, y as u8]));
+        let rgba16 = ImageBuffer::from_fn(4, 4, |x, y| {
+            Rgba([x as u16 * 0x1234, y as u16 * 0x2345, 0xfedc, 0x0102])
+        });
+        let luma16 = ImageBuffer::from_fn(2, 3, |x, y| Luma([(x * 1000 + y) as u16]));
+        for image in [
+            DynamicImage::ImageRgb8(rgb),
+            DynamicImage::ImageLumaA8(gray_alpha),
+            DynamicImage::ImageRgba16(rgba16),
+            DynamicImage::ImageLuma16(luma16),
+        ] {
+            assert_eq!(round_trip(image.clone()), image);
+        }
+
+        let float = Rgb32FImage::from_fn(2, 2, |x, _| Rgb([x as f32, 0.5, 1.0]));
+        let float = DynamicImage::ImageRgb32F(float);
+        let data = ImageData::try_from(&float).unwrap();
+        assert_eq!(
+            (data.header().color_type, data.header().bit_depth),
+            (ColorType::Rgb, 16)
+        );
+        assert_eq!(
+            round_trip(float.clone()),
+            DynamicImage::ImageRgb16(float.to_rgb16())
+        );
+
+        let empty = DynamicImage::ImageRgb8(RgbImage::new(0, 0));
+        assert!(matches!(
+            Png::try_from(empty),
+            Err(ImageError::BadHeader("dimensions"))
+        ));
+    }
+
+    #[test]
+    fn test_from_png() {
+        let chunk = |code: &str, data: &[u8]| Chunk::new(ChunkType::from_str(code).unwrap(), data);
+        let png = |header: Header, pixels: &[u8], extra: Vec<Chunk>| {
+            let mut png = Png::from_chunks(vec![chunk("IHDR", &header.as_bytes())]);
+            for extra in extra {
+                png.append_chunk(extra);
+            }
+            png.append_chunk(chunk("IEND", &[]));
+            let image = ImageData::new(header, pixels.to_vec()).unwrap();
+            image.write_to(&mut png, &EncodeOptions::default()).unwrap();
+            png
+        };
+        let header = |color_type, bit_depth| Header {
+            width: 4,
+            height: 1,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+
+        // Indices 0, 1, 2 and 3 into a palette of two colors, the first
+        // half transparent.
+        let indexed = png(
+            header(ColorType::Indexed, 2),
+            &[0b0001_1011],
+            vec![
+                chunk("PLTE", &[10, 20, 30, 40, 50, 60]),
+                chunk("tRNS", &[128]),
+            ],
+        );
+        let image = DynamicImage::try_from(&indexed).unwrap();
+        let expected = RgbaImage::from_raw(
+            4,
+            1,
+            vec![10, 20, 30, 128, 40, 50, 60, 255, 0, 0, 0, 255, 0, 0, 0, 255],
+        )
+        .unwrap();
+        assert_eq!(image, DynamicImage::ImageRgba8(expected));
+
+        let gray = png(header(ColorType::Grayscale, 2), &[0b0001_1011], vec![]);
+        let image = DynamicImage::try_from(&gray).unwrap();
+        assert_eq!(image.as_bytes(), [0, 85, 170, 255]);
+
+        let keyed = png(
+            header(ColorType::Grayscale, 8),
+            &[1, 2, 3, 4],
+            vec![chunk("tRNS", &[0, 3])],
+        );
+        let image = DynamicImage::try_from(&keyed).unwrap();
+        assert_eq!(image.to_rgba8().get_pixel(2, 0), &Rgba([3, 3, 3, 0]));
+        assert_eq!(image.to_rgba8().get_pixel(3, 0), &Rgba([4, 4, 4, 255]));
+
+        let no_header = Png::from_chunks(vec![chunk("IEND", &[])]);
+        assert!(matches!(
+            DynamicImage::try_from(&no_header),
+            Err(ImageError::MissingHeader)
+        ));
+    }
+}
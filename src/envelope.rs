@@ -0,0 +1,1842 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use std::convert::TryFrom;
+#[cfg(feature = "fs")]
+use std::fs;
+use std::io::{self, Read, Write};
+#[cfg(feature = "fs")]
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+use crate::blake3;
+use crate::compression::{Algorithm, Compression, CompressionError};
+use crate::crypto::{
+    self, CryptoError, DeniableParams, Encryption, EncryptionParams, IntegrityParams, Kdf,
+    StreamParams,
+};
+use crate::fec::{Fec, FecError};
+use crate::limits::{self, Limit, LimitExceeded};
+use crate::parse::{self, Span};
+use crate::shamir::{self, ShamirError};
+
+#[derive(Debug, Error)]
+pub enum EnvelopeError {
+    #[error("Envelope is truncated: {0}")]
+    Truncated(Span),
+    #[error("Unsupported envelope version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("Unknown critical envelope field: {0:#04x}")]
+    UnknownCriticalField(u8),
+    #[error("Invalid envelope field: {0}")]
+    InvalidField(&'static str),
+    #[error("Only text and log payloads can be appended to")]
+    NotAppendable,
+    #[error("Bad payload compression: {0}")]
+    Compression(#[from] CompressionError),
+    #[error("Failed to decompress payload: {0}")]
+    Decompression(io::Error),
+    #[error("Password required: the payload is encrypted")]
+    PasswordRequired,
+    #[error("Identity required: the payload is encrypted to public keys")]
+    IdentityRequired,
+    #[error(transparent)]
+    Crypto(#[from] CryptoError),
+    #[error(transparent)]
+    Shamir(#[from] ShamirError),
+    #[error("Payload is damaged: {0}")]
+    Fec(#[from] FecError),
+    #[error("Part {index} of {total} of the striped payload is missing")]
+    MissingPart { index: u16, total: u16 },
+    #[error("Payload checksum mismatch: the payload is corrupted")]
+    ChecksumMismatch,
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitExceeded),
+    #[error("The {0} needs the whole payload up front, so it can't be streamed")]
+    NotStreamable(&'static str),
+    #[error("Failed to stream the payload: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// What the payload of an [`Envelope`] represents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayloadKind {
+    Text,
+    File,
+    /// A list of timestamped text entries, see [`LogEntry`].
+    Log,
+    /// Ciphertext of a whole inner envelope, see [`Envelope::decrypt`].
+    Encrypted,
+    /// One Shamir share of a whole inner envelope, see [`ShareInfo`].
+    Share,
+    /// A slice of a serialized envelope striped across files, see
+    /// [`Envelope::stripe`].
+    Part,
+}
+
+/// One entry of a log payload. Entries converted from a plain message have
+/// no timestamp.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub time: Option<SystemTime>,
+    pub text: String,
+}
+
+impl LogEntry {
+    /// An entry stamped with the system clock, which wasm32-unknown-unknown
+    /// doesn't have: there, build the entry with a time from the host.
+    pub fn now(text: &str) -> Self {
+        Self {
+            time: Some(SystemTime::now()),
+            text: text.to_string(),
+        }
+    }
+    /// `flags | u64 secs | u32 nanos | u32 length | text`, where bit 0 of
+    /// `flags` tells whether the entry has a timestamp.
+    fn write_to(&self, bytes: &mut Vec<u8>) {
+        let since_epoch = self.time.and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+        let since_epoch = since_epoch.unwrap_or_default();
+        bytes.push(u8::from(self.time.is_some()));
+        bytes.extend_from_slice(&since_epoch.as_secs().to_be_bytes());
+        bytes.extend_from_slice(&since_epoch.subsec_nanos().to_be_bytes());
+        bytes.extend_from_slice(&(self.text.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(self.text.as_bytes());
+    }
+    /// Reads the entry at the start of `input`, which is part of `payload`.
+    fn read_from(payload: &[u8], input: &mut &[u8]) -> Result<Self, EnvelopeError> {
+        let (rest, (flags, secs, nanos, text)) = parse::log_entry(input)
+            .map_err(|e| EnvelopeError::Truncated(parse::span(payload, e)))?;
+        *input = rest;
+        let text =
+            std::str::from_utf8(text).map_err(|_| EnvelopeError::InvalidField("log entry text"))?;
+        let time = if flags & 1 != 0 {
+            if nanos >= 1_000_000_000 {
+                return Err(EnvelopeError::InvalidField("log entry time"));
+            }
+            UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+        } else {
+            None
+        };
+        Ok(Self {
+            time,
+            text: text.to_string(),
+        })
+    }
+}
+
+/// Metadata of an embedded file, used to reconstruct it on decode.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileMeta {
+    pub name: String,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+/// Which secret a share belongs to and how many are needed to recover it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShareInfo {
+    /// Random id shared by every share of one secret.
+    pub set_id: [u8; 16],
+    pub threshold: u8,
+    pub total: u8,
+    /// The share's x coordinate, 1 to `total`.
+    pub x: u8,
+}
+
+impl ShareInfo {
+    /// `set id | threshold | total | x`
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.set_id.to_vec();
+        bytes.extend_from_slice(&[self.threshold, self.total, self.x]);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for ShareInfo {
+    type Error = EnvelopeError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let value: [u8; 19] = fixed(value, "share")?;
+        let (set_id, rest) = value.split_at(16);
+        let info = Self {
+            set_id: set_id.try_into().expect("split at 16"),
+            threshold: rest[0],
+            total: rest[1],
+            x: rest[2],
+        };
+        if info.threshold < 2 || info.threshold > info.total || info.x == 0 || info.x > info.total {
+            return Err(EnvelopeError::InvalidField("share"));
+        }
+        Ok(info)
+    }
+}
+
+/// Where a part of a striped payload goes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartInfo {
+    /// Random id shared by every part of one payload.
+    pub set_id: [u8; 16],
+    /// 1 to `total`.
+    pub index: u16,
+    pub total: u16,
+}
+
+impl PartInfo {
+    /// `set id | index u16 | total u16`
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.set_id.to_vec();
+        bytes.extend_from_slice(&self.index.to_be_bytes());
+        bytes.extend_from_slice(&self.total.to_be_bytes());
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for PartInfo {
+    type Error = EnvelopeError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let value: [u8; 20] = fixed(value, "part")?;
+        let info = Self {
+            set_id: fixed(&value[..16], "part")?,
+            index: u16::from_be_bytes([value[16], value[17]]),
+            total: u16::from_be_bytes([value[18], value[19]]),
+        };
+        if info.index == 0 || info.index > info.total {
+            return Err(EnvelopeError::InvalidField("part"));
+        }
+        Ok(info)
+    }
+}
+
+/// Self-describing container for an embedded payload.
+///
+/// Layout: `MAGIC`, a version byte, a list of `tag | u32 length | value`
+/// fields terminated by tag `0`, then the payload bytes. Tags with the high
+/// bit set are critical: readers that don't understand them must refuse the
+/// envelope, while other unknown tags are skipped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Envelope {
+    kind: PayloadKind,
+    name: Option<String>,
+    file: Option<FileMeta>,
+    compression: Option<Compression>,
+    encryption: Option<EncryptionParams>,
+    deniable: Option<DeniableParams>,
+    stream: Option<StreamParams>,
+    /// Encrypted to age recipients rather than with a password.
+    recipients: bool,
+    integrity: Option<Integrity>,
+    /// The payload is an OpenPGP message wrapping the actual contents.
+    openpgp: bool,
+    share: Option<ShareInfo>,
+    part: Option<PartInfo>,
+    /// Error correction applied to the stored body.
+    fec: Option<Fec>,
+    /// Bytes that error correction repaired when the envelope was read.
+    repaired: usize,
+    /// A BLAKE3 digest of the payload is stored, and was checked on reading.
+    checksum: bool,
+    payload: Vec<u8>,
+}
+
+/// An HMAC tag read from a serialized envelope, with the bytes it covers:
+/// everything but the tag field itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Integrity {
+    params: IntegrityParams,
+    tag: Vec<u8>,
+    signed: Vec<u8>,
+}
+
+mod tag {
+    pub const END: u8 = 0x00;
+    pub const FILE_NAME: u8 = 0x01;
+    pub const FILE_SIZE: u8 = 0x02;
+    pub const FILE_MODIFIED: u8 = 0x03;
+    pub const NAME: u8 = 0x04;
+    pub const CHECKSUM: u8 = 0x05;
+    pub const KIND: u8 = 0x80;
+    pub const COMPRESSION: u8 = 0x81;
+    pub const ENCRYPTION: u8 = 0x82;
+    pub const RECIPIENTS: u8 = 0x83;
+    /// Must be the last field, so the tag can cover all the others.
+    pub const INTEGRITY: u8 = 0x84;
+    pub const OPENPGP: u8 = 0x85;
+    pub const SHARE: u8 = 0x86;
+    pub const PART: u8 = 0x87;
+    pub const FEC: u8 = 0x88;
+    pub const DENIABLE: u8 = 0x89;
+    pub const STREAM: u8 = 0x8a;
+}
+
+impl Envelope {
+    pub const MAGIC: [u8; 5] = *b"PNGME";
+    pub const VERSION: u8 = 1;
+
+    pub fn text(message: &str) -> Self {
+        Self {
+            kind: PayloadKind::Text,
+            name: None,
+            file: None,
+            compression: None,
+            encryption: None,
+            deniable: None,
+            stream: None,
+            recipients: false,
+            integrity: None,
+            openpgp: false,
+            share: None,
+            part: None,
+            fec: None,
+            repaired: 0,
+            checksum: false,
+            payload: message.as_bytes().to_vec(),
+        }
+    }
+    pub fn file(meta: FileMeta, contents: Vec<u8>) -> Self {
+        Self {
+            kind: PayloadKind::File,
+            name: None,
+            file: Some(meta),
+            compression: None,
+            encryption: None,
+            deniable: None,
+            stream: None,
+            recipients: false,
+            integrity: None,
+            openpgp: false,
+            share: None,
+            part: None,
+            fec: None,
+            repaired: 0,
+            checksum: false,
+            payload: contents,
+        }
+    }
+    pub fn log(entries: &[LogEntry]) -> Self {
+        let mut payload = Vec::new();
+        for entry in entries {
+            entry.write_to(&mut payload);
+        }
+        Self {
+            kind: PayloadKind::Log,
+            name: None,
+            file: None,
+            compression: None,
+            encryption: None,
+            deniable: None,
+            stream: None,
+            recipients: false,
+            integrity: None,
+            openpgp: false,
+            share: None,
+            part: None,
+            fec: None,
+            repaired: 0,
+            checksum: false,
+            payload,
+        }
+    }
+    /// Splits the whole envelope into `total` share envelopes, any
+    /// `threshold` of which recover it with [`Envelope::combine`]. Each
+    /// share keeps the readable name.
+    pub fn split(&self, threshold: u8, total: u8) -> Result<Vec<Self>, EnvelopeError> {
+        let mut set_id = [0u8; 16];
+        OsRng.fill_bytes(&mut set_id);
+        let shares = shamir::split(&self.as_bytes(), threshold, total)?;
+        Ok(shares
+            .into_iter()
+            .map(|share| Self {
+                kind: PayloadKind::Share,
+                name: self.name.clone(),
+                file: None,
+                compression: None,
+                encryption: None,
+                deniable: None,
+                stream: None,
+                recipients: false,
+                integrity: None,
+                openpgp: false,
+                share: Some(ShareInfo {
+                    set_id,
+                    threshold,
+                    total,
+                    x: share.x,
+                }),
+                part: None,
+                fec: None,
+                repaired: 0,
+                checksum: false,
+                payload: share.y,
+            })
+            .collect())
+    }
+    /// Recovers the envelope split by [`Envelope::split`] from shares of a
+    /// single set; duplicates of the same share are ignored.
+    pub fn combine(shares: &[Self]) -> Result<Self, EnvelopeError> {
+        let first = shares
+            .first()
+            .and_then(|s| s.share.as_ref())
+            .ok_or(ShamirError::NotEnoughShares { needed: 2, got: 0 })?;
+        let mut points: Vec<shamir::Share> = Vec::new();
+        for envelope in shares {
+            let info = envelope
+                .share
+                .as_ref()
+                .filter(|info| info.set_id == first.set_id && info.threshold == first.threshold)
+                .ok_or(EnvelopeError::InvalidField("share"))?;
+            if !points.iter().any(|p| p.x == info.x) {
+                points.push(shamir::Share {
+                    x: info.x,
+                    y: envelope.payload.clone(),
+                });
+            }
+        }
+        let secret = shamir::combine(&points, first.threshold)?;
+        Self::try_from(secret.as_slice())
+    }
+    pub fn share_info(&self) -> Option<&ShareInfo> {
+        self.share.as_ref()
+    }
+    /// Cuts serialized envelope `data` into `count` part envelopes of about
+    /// the same size, to be stored in different files and put back together
+    /// with [`Envelope::join`]. Each part carries `name` readable.
+    pub fn stripe(data: &[u8], name: Option<&str>, count: u16) -> Vec<Self> {
+        let mut set_id = [0u8; 16];
+        OsRng.fill_bytes(&mut set_id);
+        let count = count.max(1);
+        let size = data.len().div_ceil(count as usize);
+        (0..count)
+            .map(|i| {
+                let start = (i as usize * size).min(data.len());
+                let end = (start + size).min(data.len());
+                let info = PartInfo {
+                    set_id,
+                    index: i + 1,
+                    total: count,
+                };
+                Self::part(name, info, data[start..end].to_vec())
+            })
+            .collect()
+    }
+    fn part(name: Option<&str>, info: PartInfo, payload: Vec<u8>) -> Self {
+        Self {
+            kind: PayloadKind::Part,
+            name: name.map(str::to_string),
+            file: None,
+            compression: None,
+            encryption: None,
+            deniable: None,
+            stream: None,
+            recipients: false,
+            integrity: None,
+            openpgp: false,
+            share: None,
+            part: Some(info),
+            fec: None,
+            repaired: 0,
+            checksum: false,
+            payload,
+        }
+    }
+    /// Puts the envelope data cut by [`Envelope::stripe`] back together from
+    /// its parts, in any order. Parts of other payloads are ignored.
+    pub fn join(first: &Self, parts: &[Self]) -> Result<Vec<u8>, EnvelopeError> {
+        let info = first
+            .part
+            .as_ref()
+            .ok_or(EnvelopeError::InvalidField("part"))?;
+        let mut ordered: Vec<Option<&[u8]>> = vec![None; info.total as usize];
+        for envelope in std::iter::once(first).chain(parts) {
+            match &envelope.part {
+                Some(part) if part.set_id == info.set_id && part.total == info.total => {
+                    ordered[part.index as usize - 1] = Some(&envelope.payload);
+                }
+                _ => {}
+            }
+        }
+        let mut data = Vec::new();
+        for (i, part) in ordered.into_iter().enumerate() {
+            let part = part.ok_or(EnvelopeError::MissingPart {
+                index: i as u16 + 1,
+                total: info.total,
+            })?;
+            data.extend_from_slice(part);
+        }
+        Ok(data)
+    }
+    pub fn part_info(&self) -> Option<&PartInfo> {
+        self.part.as_ref()
+    }
+    /// Adds an entry to a log payload, turning a text payload into a log
+    /// whose first entry is the original message.
+    pub fn append_entry(&mut self, entry: &LogEntry) -> Result<(), EnvelopeError> {
+        if self.openpgp {
+            return Err(EnvelopeError::NotAppendable);
+        }
+        match self.kind {
+            PayloadKind::Log => {}
+            PayloadKind::Text => {
+                let text =
+                    std::str::from_utf8(&self.payload).map_err(|_| EnvelopeError::NotAppendable)?;
+                let first = LogEntry {
+                    time: None,
+                    text: text.to_string(),
+                };
+                self.payload.clear();
+                first.write_to(&mut self.payload);
+                self.kind = PayloadKind::Log;
+            }
+            PayloadKind::File | PayloadKind::Encrypted | PayloadKind::Share | PayloadKind::Part => {
+                return Err(EnvelopeError::NotAppendable)
+            }
+        }
+        entry.write_to(&mut self.payload);
+        Ok(())
+    }
+    /// Entries of a log payload; empty for other kinds.
+    pub fn log_entries(&self) -> Result<Vec<LogEntry>, EnvelopeError> {
+        if self.kind != PayloadKind::Log {
+            return Ok(Vec::new());
+        }
+        let input = &mut self.payload.as_slice();
+        let mut entries = Vec::new();
+        while !input.is_empty() {
+            entries.push(LogEntry::read_from(&self.payload, input)?);
+        }
+        Ok(entries)
+    }
+    /// Labels the payload so several can live in one file.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+    /// Compresses the payload when serialized; decoding is transparent.
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+    /// Seals the whole envelope (kind, file metadata, compression and
+    /// payload) with a key derived from `password`. Only the name stays
+    /// readable, so payloads can still be looked up before decrypting.
+    pub fn encrypt(&self, password: &[u8], encryption: &Encryption) -> Result<Self, EnvelopeError> {
+        let params = EncryptionParams::generate(encryption);
+        let mut sealed = Self {
+            kind: PayloadKind::Encrypted,
+            name: self.name.clone(),
+            file: None,
+            compression: None,
+            encryption: Some(params),
+            deniable: None,
+            stream: None,
+            recipients: false,
+            integrity: None,
+            openpgp: false,
+            share: None,
+            part: None,
+            fec: None,
+            repaired: 0,
+            checksum: false,
+            payload: Vec::new(),
+        };
+        let params = sealed.encryption.as_ref().expect("just set");
+        sealed.payload = params.encrypt(password, &sealed.aad(), &self.as_bytes())?;
+        Ok(sealed)
+    }
+    /// Opens a password-encrypted envelope; unencrypted envelopes are
+    /// returned unchanged.
+    pub fn decrypt(&self, password: &[u8]) -> Result<Self, EnvelopeError> {
+        if self.recipients {
+            return Err(EnvelopeError::IdentityRequired);
+        }
+        let plaintext = match (&self.encryption, &self.deniable, &self.stream) {
+            (Some(params), _, _) => params.decrypt(password, &self.aad(), &self.payload)?,
+            (None, Some(params), _) => params.open(password, &self.aad(), &self.payload)?,
+            (None, None, Some(params)) => params.decrypt(password, &self.aad(), &self.payload)?,
+            (None, None, None) => return Ok(self.clone()),
+        };
+        self.open_inner(&plaintext, true)
+    }
+    /// Like [`Envelope::decrypt`], but opens what it can of a payload
+    /// encrypted with [`Envelope::encrypt_stream`] when some frames are
+    /// damaged, returning the numbers of those frames, whose contents are
+    /// zero-filled. The header of the inner envelope is in the first frame,
+    /// so the payload can only be salvaged if that frame is intact.
+    pub fn decrypt_salvaging(&self, password: &[u8]) -> Result<(Self, Vec<u32>), EnvelopeError> {
+        let Some(params) = &self.stream else {
+            return Ok((self.decrypt(password)?, Vec::new()));
+        };
+        let salvaged = params.salvage(password, &self.aad(), &self.payload)?;
+        // Zero-filled frames can't match the checksum.
+        let inner = self.open_inner(&salvaged.plaintext, salvaged.damaged.is_empty())?;
+        Ok((inner, salvaged.damaged))
+    }
+    fn open_inner(&self, plaintext: &[u8], verify_checksum: bool) -> Result<Self, EnvelopeError> {
+        let mut inner = Self::parse(plaintext, verify_checksum)?;
+        if inner.name.is_none() {
+            inner.name = self.name.clone();
+        }
+        Ok(inner)
+    }
+    /// Seals the whole envelope like [`Envelope::encrypt`], in independent
+    /// frames of `frame_len` bytes (see [`StreamParams`]), so damage to
+    /// one frame doesn't take the rest of a large payload with it.
+    pub fn encrypt_stream(
+        &self,
+        password: &[u8],
+        encryption: &Encryption,
+        frame_len: u32,
+    ) -> Result<Self, EnvelopeError> {
+        let mut sealed = self.stream_sealed(encryption, frame_len);
+        let params = sealed.stream.as_ref().expect("just set");
+        sealed.payload = params.encrypt(password, &sealed.aad(), &self.as_bytes())?;
+        Ok(sealed)
+    }
+    /// The outer envelope of [`Envelope::encrypt_stream`], without its
+    /// ciphertext.
+    fn stream_sealed(&self, encryption: &Encryption, frame_len: u32) -> Self {
+        Self {
+            kind: PayloadKind::Encrypted,
+            name: self.name.clone(),
+            file: None,
+            compression: None,
+            encryption: None,
+            deniable: None,
+            stream: Some(StreamParams::generate(encryption, frame_len)),
+            recipients: false,
+            integrity: None,
+            openpgp: false,
+            share: None,
+            part: None,
+            fec: None,
+            repaired: 0,
+            checksum: false,
+            payload: Vec::new(),
+        }
+    }
+    /// Seals the whole envelope like [`Envelope::encrypt`], next to `decoy`
+    /// sealed under its own password: each password only ever opens its own
+    /// envelope, and without a decoy the second slot is random filler, so
+    /// the result doesn't show whether there is one. The decoy takes the
+    /// readable name of this envelope.
+    pub fn encrypt_deniable(
+        &self,
+        password: &[u8],
+        decoy: Option<(&Envelope, &[u8])>,
+        encryption: &Encryption,
+    ) -> Result<Self, EnvelopeError> {
+        let contents = self.as_bytes();
+        let decoy = decoy.map(|(envelope, password)| {
+            let mut envelope = envelope.clone();
+            envelope.name = self.name.clone();
+            (envelope.as_bytes(), password)
+        });
+        let lens: Vec<usize> = std::iter::once(contents.len())
+            .chain(decoy.iter().map(|(bytes, _)| bytes.len()))
+            .collect();
+        let params = DeniableParams::for_contents(encryption, &lens);
+        let mut sealed = Self {
+            kind: PayloadKind::Encrypted,
+            name: self.name.clone(),
+            file: None,
+            compression: None,
+            encryption: None,
+            deniable: Some(params),
+            stream: None,
+            recipients: false,
+            integrity: None,
+            openpgp: false,
+            share: None,
+            part: None,
+            fec: None,
+            repaired: 0,
+            checksum: false,
+            payload: Vec::new(),
+        };
+        let params = sealed.deniable.as_ref().expect("just set");
+        let aad = sealed.aad();
+        let mut slots = vec![params.seal(password, &aad, &contents)?];
+        slots.push(match &decoy {
+            Some((bytes, password)) => params.seal(password, &aad, bytes)?,
+            None => params.filler(),
+        });
+        if OsRng.next_u32() & 1 == 1 {
+            slots.reverse();
+        }
+        sealed.payload = slots.concat();
+        Ok(sealed)
+    }
+    /// Seals the whole envelope like [`Envelope::encrypt`], but to age
+    /// public keys: any of the matching identities can open it. age has no
+    /// associated data, so the readable name is instead checked against the
+    /// copy sealed inside on decryption.
+    pub fn encrypt_to(&self, recipients: &[age::x25519::Recipient]) -> Result<Self, EnvelopeError> {
+        Ok(Self {
+            kind: PayloadKind::Encrypted,
+            name: self.name.clone(),
+            file: None,
+            compression: None,
+            encryption: None,
+            deniable: None,
+            stream: None,
+            recipients: true,
+            integrity: None,
+            openpgp: false,
+            share: None,
+            part: None,
+            fec: None,
+            repaired: 0,
+            checksum: false,
+            payload: crypto::encrypt_to(recipients, &self.as_bytes())?,
+        })
+    }
+    /// Opens an envelope made by [`Envelope::encrypt_to`]; unencrypted
+    /// envelopes are returned unchanged.
+    pub fn decrypt_with(
+        &self,
+        identities: &[Box<dyn age::Identity>],
+    ) -> Result<Self, EnvelopeError> {
+        if self.encryption.is_some() || self.deniable.is_some() || self.stream.is_some() {
+            return Err(EnvelopeError::PasswordRequired);
+        }
+        if !self.recipients {
+            return Ok(self.clone());
+        }
+        let plaintext = crypto::decrypt_with(identities, &self.payload)?;
+        let inner = Self::try_from(plaintext.as_slice())?;
+        if inner.name != self.name {
+            return Err(CryptoError::DecryptionFailed.into());
+        }
+        Ok(inner)
+    }
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+            || self.deniable.is_some()
+            || self.stream.is_some()
+            || self.recipients
+    }
+    /// Whether the envelope may hold a decoy, see [`Envelope::encrypt_deniable`].
+    pub fn is_deniable(&self) -> bool {
+        self.deniable.is_some()
+    }
+    /// Replaces the payload with `message`, an OpenPGP message made from it
+    /// (see the `openpgp` module). Kind, name and file metadata stay
+    /// readable; `decode --output` then writes the message itself, ready for
+    /// `gpg --decrypt`.
+    pub fn with_openpgp_message(mut self, message: Vec<u8>) -> Self {
+        self.payload = message;
+        self.compression = None;
+        self.openpgp = true;
+        self
+    }
+    /// Restores the contents unwrapped from the OpenPGP message.
+    pub fn with_openpgp_contents(mut self, contents: Vec<u8>) -> Result<Self, EnvelopeError> {
+        if let Some(meta) = &self.file {
+            if meta.size != contents.len() as u64 {
+                return Err(EnvelopeError::InvalidField("file size"));
+            }
+        }
+        self.payload = contents;
+        self.openpgp = false;
+        Ok(self)
+    }
+    pub fn is_openpgp(&self) -> bool {
+        self.openpgp
+    }
+    /// Whether the envelope needs an identity rather than a password.
+    pub fn is_encrypted_to_recipients(&self) -> bool {
+        self.recipients
+    }
+    pub fn encryption_params(&self) -> Option<&EncryptionParams> {
+        self.encryption.as_ref()
+    }
+    pub fn stream_params(&self) -> Option<&StreamParams> {
+        self.stream.as_ref()
+    }
+    /// Data authenticated alongside the ciphertext: the fields readable
+    /// without the password.
+    fn aad(&self) -> Vec<u8> {
+        let mut aad = Self::MAGIC.to_vec();
+        aad.push(Self::VERSION);
+        if let Some(params) = &self.encryption {
+            put_field(&mut aad, tag::ENCRYPTION, &params.as_bytes());
+        }
+        if let Some(params) = &self.deniable {
+            put_field(&mut aad, tag::DENIABLE, &params.as_bytes());
+        }
+        if let Some(params) = &self.stream {
+            put_field(&mut aad, tag::STREAM, &params.as_bytes());
+        }
+        if let Some(name) = &self.name {
+            put_field(&mut aad, tag::NAME, name.as_bytes());
+        }
+        aad
+    }
+    /// Reads `path` into a file envelope, keeping its name and mtime.
+    #[cfg(feature = "fs")]
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let contents = fs::read(path)?;
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let modified = fs::metadata(path)?.modified().ok();
+        let meta = FileMeta {
+            name,
+            size: contents.len() as u64,
+            modified,
+        };
+        Ok(Self::file(meta, contents))
+    }
+    /// Whether `bytes` start like an envelope rather than a bare message.
+    pub fn is_envelope(bytes: &[u8]) -> bool {
+        bytes.starts_with(&Self::MAGIC)
+    }
+    pub fn kind(&self) -> PayloadKind {
+        self.kind
+    }
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    pub fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+    pub fn file_meta(&self) -> Option<&FileMeta> {
+        self.file.as_ref()
+    }
+    /// Stores the envelope with error correction: up to `fec.parity() / 2`
+    /// damaged bytes per block are repaired on reading.
+    pub fn with_fec(mut self, fec: Fec) -> Self {
+        self.fec = Some(fec);
+        self
+    }
+    pub fn fec(&self) -> Option<Fec> {
+        self.fec
+    }
+    /// Stores a BLAKE3 digest of the payload, checked when the envelope is
+    /// read back, independently of chunk CRCs and of any encryption.
+    pub fn with_checksum(mut self) -> Self {
+        self.checksum = true;
+        self
+    }
+    /// The BLAKE3 digest of the payload, if the envelope stores one (read
+    /// envelopes have been checked against it, unless salvaged).
+    pub fn checksum(&self) -> Option<[u8; 32]> {
+        self.checksum.then(|| blake3::hash(&self.payload))
+    }
+    /// How many damaged bytes error correction repaired when reading.
+    pub fn repaired(&self) -> usize {
+        self.repaired
+    }
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+    pub fn into_payload(self) -> Vec<u8> {
+        self.payload
+    }
+    /// Serializes the envelope. A tag read from an integrity-protected
+    /// envelope is not carried over: use [`Envelope::as_bytes_with_integrity`]
+    /// to sign it again.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let (bytes, body) = self.parts();
+        self.finish(bytes, &body)
+    }
+    /// Serializes the envelope with an HMAC-SHA256 tag over every field and
+    /// the payload, keyed by `password`: decoders can detect any change, but
+    /// the payload itself is not encrypted.
+    pub fn as_bytes_with_integrity(
+        &self,
+        password: &[u8],
+        kdf: Kdf,
+    ) -> Result<Vec<u8>, EnvelopeError> {
+        let (mut bytes, body) = self.parts();
+        let params = IntegrityParams::generate(kdf);
+        let mut signed = bytes.clone();
+        signed.extend_from_slice(&body);
+        let mut value = params.as_bytes();
+        value.extend_from_slice(&params.tag(password, &signed)?);
+        put_field(&mut bytes, tag::INTEGRITY, &value);
+        Ok(self.finish(bytes, &body))
+    }
+    /// Whether the envelope was read with an integrity tag, which
+    /// [`Envelope::verify`] checks.
+    pub fn has_integrity_tag(&self) -> bool {
+        self.integrity.is_some()
+    }
+    pub fn integrity_params(&self) -> Option<&IntegrityParams> {
+        self.integrity.as_ref().map(|i| &i.params)
+    }
+    /// Checks the integrity tag against `password`; envelopes without one
+    /// pass.
+    pub fn verify(&self, password: &[u8]) -> Result<(), EnvelopeError> {
+        match &self.integrity {
+            Some(integrity) => {
+                Ok(integrity
+                    .params
+                    .verify(password, &integrity.signed, &integrity.tag)?)
+            }
+            None => Ok(()),
+        }
+    }
+    /// The header fields (without the end tag) and the stored body.
+    fn parts(&self) -> (Vec<u8>, Vec<u8>) {
+        let mut bytes = self.header();
+        let body = match &self.compression {
+            Some(compression) => compression.compress(&self.payload),
+            None => self.payload.clone(),
+        };
+        if let Some(fec) = &self.fec {
+            let mut value = vec![fec.parity()];
+            value.extend_from_slice(&(body.len() as u64).to_be_bytes());
+            put_field(&mut bytes, tag::FEC, &value);
+        }
+        (bytes, body)
+    }
+    /// The header fields that don't depend on the stored body.
+    fn header(&self) -> Vec<u8> {
+        let mut bytes = Self::MAGIC.to_vec();
+        bytes.push(Self::VERSION);
+        let kind = match self.kind {
+            PayloadKind::Text => 0,
+            PayloadKind::File => 1,
+            PayloadKind::Log => 2,
+            PayloadKind::Encrypted => 3,
+            PayloadKind::Share => 4,
+            PayloadKind::Part => 5,
+        };
+        put_field(&mut bytes, tag::KIND, &[kind]);
+        if let Some(name) = &self.name {
+            put_field(&mut bytes, tag::NAME, name.as_bytes());
+        }
+        if let Some(meta) = &self.file {
+            put_field(&mut bytes, tag::FILE_NAME, meta.name.as_bytes());
+            put_field(&mut bytes, tag::FILE_SIZE, &meta.size.to_be_bytes());
+            if let Some(since_epoch) = meta
+                .modified
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            {
+                let mut value = since_epoch.as_secs().to_be_bytes().to_vec();
+                value.extend_from_slice(&since_epoch.subsec_nanos().to_be_bytes());
+                put_field(&mut bytes, tag::FILE_MODIFIED, &value);
+            }
+        }
+        if let Some(params) = &self.encryption {
+            put_field(&mut bytes, tag::ENCRYPTION, &params.as_bytes());
+        }
+        if let Some(params) = &self.deniable {
+            put_field(&mut bytes, tag::DENIABLE, &params.as_bytes());
+        }
+        if let Some(params) = &self.stream {
+            put_field(&mut bytes, tag::STREAM, &params.as_bytes());
+        }
+        if self.recipients {
+            put_field(&mut bytes, tag::RECIPIENTS, &[]);
+        }
+        if self.openpgp {
+            put_field(&mut bytes, tag::OPENPGP, &[]);
+        }
+        if let Some(share) = &self.share {
+            put_field(&mut bytes, tag::SHARE, &share.as_bytes());
+        }
+        if let Some(part) = &self.part {
+            put_field(&mut bytes, tag::PART, &part.as_bytes());
+        }
+        if self.checksum {
+            put_field(&mut bytes, tag::CHECKSUM, &blake3::hash(&self.payload));
+        }
+        if let Some(compression) = &self.compression {
+            put_field(
+                &mut bytes,
+                tag::COMPRESSION,
+                &[compression.algorithm().id()],
+            );
+        }
+        bytes
+    }
+    /// Ends the header and appends the body, error-corrected if requested.
+    /// Integrity tags cover the body before error correction, so that a
+    /// repaired payload still verifies.
+    fn finish(&self, mut header: Vec<u8>, body: &[u8]) -> Vec<u8> {
+        header.push(tag::END);
+        match &self.fec {
+            Some(fec) => header.extend_from_slice(&fec.encode(body)),
+            None => header.extend_from_slice(body),
+        }
+        header
+    }
+}
+
+impl Envelope {
+    /// Serializes the envelope into `writer` like [`Envelope::as_bytes`],
+    /// but reads the payload from `payload` (ignoring the envelope's own),
+    /// compressing it as it goes, so only a buffer of it is held at a time.
+    /// A checksum or error correction needs the whole payload before it is
+    /// written, and is refused.
+    pub fn write_streaming(
+        &self,
+        mut payload: impl Read,
+        mut writer: impl Write,
+    ) -> Result<(), EnvelopeError> {
+        if self.checksum {
+            return Err(EnvelopeError::NotStreamable("checksum"));
+        }
+        if self.fec.is_some() {
+            return Err(EnvelopeError::NotStreamable("error correction"));
+        }
+        let mut header = self.header();
+        header.push(tag::END);
+        writer.write_all(&header)?;
+        match &self.compression {
+            Some(compression) => {
+                let mut compressor = compression.compressor(writer)?;
+                io::copy(&mut payload, &mut compressor)?;
+                compressor.finish()?;
+            }
+            None => {
+                io::copy(&mut payload, &mut writer)?;
+            }
+        }
+        Ok(())
+    }
+    /// Like [`Envelope::write_streaming`], sealing the envelope as
+    /// [`Envelope::encrypt_stream`] does, one frame at a time.
+    pub fn write_encrypted_streaming(
+        &self,
+        payload: impl Read,
+        password: &[u8],
+        encryption: &Encryption,
+        frame_len: u32,
+        mut writer: impl Write,
+    ) -> Result<(), EnvelopeError> {
+        let sealed = self.stream_sealed(encryption, frame_len);
+        let mut header = sealed.header();
+        header.push(tag::END);
+        writer.write_all(&header)?;
+        let params = sealed.stream.as_ref().expect("just set");
+        let mut encryptor = params.encryptor(password, &sealed.aad(), writer)?;
+        self.write_streaming(payload, &mut encryptor)?;
+        encryptor.finish()?;
+        Ok(())
+    }
+}
+
+/// Cuts a serialized envelope written to it into parts like those of
+/// [`Envelope::stripe`], handing each to `emit` once it is full, so that a
+/// payload larger than memory (or than a chunk may be) can be stored in
+/// several chunks. Only one part is held at a time.
+///
+/// Every part records how many there are before the stream's length is
+/// known, so the count is planned from an upper bound with
+/// [`parts_needed`], and parts past the end of the data are
+/// emitted empty.
+pub struct PartWriter<F: FnMut(Envelope) -> io::Result<()>> {
+    name: Option<String>,
+    set_id: [u8; 16],
+    total: u16,
+    part_len: usize,
+    /// Parts emitted so far.
+    emitted: u16,
+    buffer: Vec<u8>,
+    emit: F,
+}
+
+impl<F: FnMut(Envelope) -> io::Result<()>> PartWriter<F> {
+    /// A writer of `total` parts of at most `part_len` bytes, named `name`.
+    pub fn new(name: Option<&str>, total: u16, part_len: usize, emit: F) -> Self {
+        let mut set_id = [0u8; 16];
+        OsRng.fill_bytes(&mut set_id);
+        Self {
+            name: name.map(str::to_string),
+            set_id,
+            total: total.max(1),
+            part_len: part_len.max(1),
+            emitted: 0,
+            buffer: Vec::new(),
+            emit,
+        }
+    }
+    fn emit_part(&mut self) -> io::Result<()> {
+        if self.emitted == self.total {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the envelope outgrew the parts planned for it",
+            ));
+        }
+        self.emitted += 1;
+        let info = PartInfo {
+            set_id: self.set_id,
+            index: self.emitted,
+            total: self.total,
+        };
+        let data = std::mem::take(&mut self.buffer);
+        (self.emit)(Envelope::part(self.name.as_deref(), info, data))
+    }
+    /// Emits the last part with data and the empty ones after it.
+    pub fn finish(mut self) -> io::Result<()> {
+        while self.emitted < self.total {
+            self.emit_part()?;
+        }
+        if !self.buffer.is_empty() {
+            return self.emit_part();
+        }
+        Ok(())
+    }
+}
+
+impl<F: FnMut(Envelope) -> io::Result<()>> Write for PartWriter<F> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buffer.len() == self.part_len {
+            self.emit_part()?;
+        }
+        let taken = data.len().min(self.part_len - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..taken]);
+        Ok(taken)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How many parts of `part_len` bytes surely hold an envelope streamed
+/// from a `payload_len`-byte payload, allowing for the header, for data that
+/// doesn't compress and for the tags of encrypted frames.
+pub fn parts_needed(payload_len: u64, part_len: usize) -> Option<u16> {
+    let bound = payload_len
+        .checked_add(payload_len / 64)?
+        .checked_add(64 * 1024)?;
+    u16::try_from(bound.div_ceil(part_len.max(1) as u64)).ok()
+}
+
+fn put_field(bytes: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    bytes.push(tag);
+    bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(value);
+}
+
+fn fixed<const N: usize>(value: &[u8], field: &'static str) -> Result<[u8; N], EnvelopeError> {
+    value
+        .try_into()
+        .map_err(|_| EnvelopeError::InvalidField(field))
+}
+
+impl TryFrom<&[u8]> for Envelope {
+    type Error = EnvelopeError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Self::parse(value, true)
+    }
+}
+
+impl Envelope {
+    /// Parses a serialized envelope, checking the payload against its
+    /// checksum if `verify_checksum` is set and it has one.
+    fn parse(value: &[u8], verify_checksum: bool) -> Result<Self, EnvelopeError> {
+        let all = value;
+        let truncated = |e| EnvelopeError::Truncated(parse::span(all, e));
+        let (mut input, (magic, version)) = parse::envelope_header(value).map_err(truncated)?;
+        if magic != Self::MAGIC {
+            return Err(EnvelopeError::InvalidField("magic"));
+        }
+        if version != Self::VERSION {
+            return Err(EnvelopeError::UnsupportedVersion(version));
+        }
+        let mut kind = None;
+        let mut name = None;
+        let mut file_name = None;
+        let mut size = None;
+        let mut modified = None;
+        let mut compression = None;
+        let mut encryption = None;
+        let mut deniable = None;
+        let mut stream = None;
+        let mut recipients = false;
+        let mut openpgp = false;
+        let mut share = None;
+        let mut part = None;
+        let mut fec = None;
+        let mut checksum = None;
+        let mut integrity_field = None;
+        loop {
+            let field_start = all.len() - input.len();
+            let (rest, (tag, value)) = parse::field(tag::END)(input).map_err(truncated)?;
+            input = rest;
+            if tag == tag::END {
+                break;
+            }
+            if integrity_field.is_some() {
+                return Err(EnvelopeError::InvalidField("integrity tag"));
+            }
+            match tag {
+                tag::KIND => {
+                    kind = Some(match value {
+                        [0] => PayloadKind::Text,
+                        [1] => PayloadKind::File,
+                        [2] => PayloadKind::Log,
+                        [3] => PayloadKind::Encrypted,
+                        [4] => PayloadKind::Share,
+                        [5] => PayloadKind::Part,
+                        _ => return Err(EnvelopeError::InvalidField("kind")),
+                    })
+                }
+                tag::NAME => {
+                    let s = std::str::from_utf8(value)
+                        .map_err(|_| EnvelopeError::InvalidField("name"))?;
+                    name = Some(s.to_string());
+                }
+                tag::FILE_NAME => {
+                    let s = std::str::from_utf8(value)
+                        .map_err(|_| EnvelopeError::InvalidField("file name"))?;
+                    file_name = Some(s.to_string());
+                }
+                tag::CHECKSUM => checksum = Some(fixed::<32>(value, "checksum")?),
+                tag::FILE_SIZE => size = Some(u64::from_be_bytes(fixed(value, "file size")?)),
+                tag::FILE_MODIFIED => {
+                    let value: [u8; 12] = fixed(value, "file modified time")?;
+                    let secs = u64::from_be_bytes(fixed(&value[..8], "file modified time")?);
+                    let nanos = u32::from_be_bytes(fixed(&value[8..], "file modified time")?);
+                    if nanos >= 1_000_000_000 {
+                        return Err(EnvelopeError::InvalidField("file modified time"));
+                    }
+                    modified = UNIX_EPOCH.checked_add(Duration::new(secs, nanos));
+                }
+                tag::ENCRYPTION => {
+                    encryption = Some(EncryptionParams::try_from(value)?);
+                }
+                tag::DENIABLE => deniable = Some(DeniableParams::try_from(value)?),
+                tag::STREAM => stream = Some(StreamParams::try_from(value)?),
+                tag::RECIPIENTS => recipients = true,
+                tag::OPENPGP => openpgp = true,
+                tag::SHARE => share = Some(ShareInfo::try_from(value)?),
+                tag::PART => part = Some(PartInfo::try_from(value)?),
+                tag::FEC => {
+                    let value: [u8; 9] = fixed(value, "error correction")?;
+                    let len = u64::from_be_bytes(fixed(&value[1..], "error correction")?);
+                    let len = usize::try_from(len)
+                        .map_err(|_| EnvelopeError::InvalidField("error correction"))?;
+                    fec = Some((Fec::new(value[0])?, len));
+                }
+                tag::INTEGRITY => integrity_field = Some((field_start, value)),
+                tag::COMPRESSION => {
+                    let [id] = fixed(value, "compression")?;
+                    compression = Some(Compression::new(Algorithm::from_id(id)?));
+                }
+                t if t & 0x80 != 0 => return Err(EnvelopeError::UnknownCriticalField(t)),
+                _ => {}
+            }
+        }
+        let (body, repaired) = match fec {
+            Some((fec, len)) => fec.decode(input, len)?,
+            None => (input.to_vec(), 0),
+        };
+        let fec = fec.map(|(fec, _)| fec);
+        let integrity = match integrity_field {
+            Some((start, field)) => {
+                let Some(split) = field.len().checked_sub(IntegrityParams::TAG_LEN) else {
+                    return Err(EnvelopeError::InvalidField("integrity tag"));
+                };
+                let (params, tag) = field.split_at(split);
+                let mut signed = all[..start].to_vec();
+                signed.extend_from_slice(&body);
+                Some(Integrity {
+                    params: IntegrityParams::try_from(params)?,
+                    tag: tag.to_vec(),
+                    signed,
+                })
+            }
+            None => None,
+        };
+        let kind = kind.ok_or(EnvelopeError::InvalidField("kind"))?;
+        // An encrypted envelope is sealed in exactly one way.
+        let sealings = [
+            encryption.is_some(),
+            deniable.is_some(),
+            stream.is_some(),
+            recipients,
+        ]
+        .into_iter()
+        .filter(|&s| s)
+        .count();
+        if sealings > 1 || (kind == PayloadKind::Encrypted) != (sealings == 1) {
+            return Err(EnvelopeError::InvalidField("encryption"));
+        }
+        if (kind == PayloadKind::Share) != share.is_some() {
+            return Err(EnvelopeError::InvalidField("share"));
+        }
+        if (kind == PayloadKind::Part) != part.is_some() {
+            return Err(EnvelopeError::InvalidField("part"));
+        }
+        if openpgp && (kind == PayloadKind::Encrypted || compression.is_some()) {
+            return Err(EnvelopeError::InvalidField("openpgp"));
+        }
+        let payload = match &compression {
+            Some(c) => {
+                let limits = limits::get();
+                let payload = c
+                    .algorithm()
+                    .decompress_at_most(&body, limits.max_decompressed_len)
+                    .map_err(EnvelopeError::Decompression)?;
+                limits.check(Limit::DecompressedLen, payload.len())?;
+                payload
+            }
+            None => body,
+        };
+        if verify_checksum && checksum.is_some_and(|c| c != blake3::hash(&payload)) {
+            return Err(EnvelopeError::ChecksumMismatch);
+        }
+        let file = match kind {
+            PayloadKind::Text
+            | PayloadKind::Log
+            | PayloadKind::Encrypted
+            | PayloadKind::Share
+            | PayloadKind::Part => None,
+            PayloadKind::File => {
+                let size = size.ok_or(EnvelopeError::InvalidField("file size"))?;
+                if !openpgp && size != payload.len() as u64 {
+                    return Err(EnvelopeError::InvalidField("file size"));
+                }
+                Some(FileMeta {
+                    name: file_name.ok_or(EnvelopeError::InvalidField("file name"))?,
+                    size,
+                    modified,
+                })
+            }
+        };
+        Ok(Self {
+            kind,
+            name,
+            file,
+            compression,
+            encryption,
+            deniable,
+            stream,
+            recipients,
+            integrity,
+            openpgp,
+            share,
+            part,
+            fec,
+            repaired,
+            checksum: checksum.is_some(),
+            payload,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_file_envelope() -> Envelope {
+        let meta = FileMeta {
+            name: "notes.bin".to_string(),
+            size: 4,
+            modified: Some(UNIX_EPOCH + Duration::new(1_650_000_000, 123)),
+        };
+        Envelope::file(meta, vec![0, 159, 146, 150])
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let envelope = Envelope::text("This is a secret");
+        let bytes = envelope.as_bytes();
+        assert!(Envelope::is_envelope(&bytes));
+        assert_eq!(Envelope::try_from(bytes.as_ref()).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let envelope = testing_file_envelope();
+        let decoded = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert_eq!(decoded.kind(), PayloadKind::File);
+        assert_eq!(decoded.file_meta(), envelope.file_meta());
+        assert_eq!(decoded.payload(), &[0, 159, 146, 150]);
+    }
+
+    #[test]
+    fn test_named_round_trip() {
+        let envelope = Envelope::text("hunter2").with_name("wifi");
+        let decoded = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert_eq!(decoded.name(), Some("wifi"));
+        assert_eq!(decoded, envelope);
+    }
+
+    #[test]
+    fn test_append_to_text() {
+        let mut envelope = Envelope::text("first").with_name("journal");
+        let entry = LogEntry {
+            time: Some(UNIX_EPOCH + Duration::new(1_650_000_000, 5)),
+            text: "second".to_string(),
+        };
+        envelope.append_entry(&entry).unwrap();
+        let decoded = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert_eq!(decoded.kind(), PayloadKind::Log);
+        assert_eq!(decoded.name(), Some("journal"));
+        let entries = decoded.log_entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].time, None);
+        assert_eq!(entries[0].text, "first");
+        assert_eq!(entries[1], entry);
+    }
+
+    #[test]
+    fn test_append_to_file_fails() {
+        let mut envelope = testing_file_envelope();
+        assert!(matches!(
+            envelope.append_entry(&LogEntry::now("nope")),
+            Err(EnvelopeError::NotAppendable)
+        ));
+    }
+
+    #[test]
+    fn test_truncated_log() {
+        let mut envelope = Envelope::log(&[LogEntry::now("entry")]);
+        envelope.payload.pop();
+        assert!(envelope.log_entries().is_err());
+    }
+
+    #[test]
+    fn test_compressed_round_trip() {
+        let message = "compress me ".repeat(100);
+        for algorithm in Algorithm::ALL.into_iter().filter(|a| a.is_built()) {
+            let envelope = Envelope::text(&message).with_compression(Compression::new(algorithm));
+            let bytes = envelope.as_bytes();
+            assert!(bytes.len() < message.len());
+            let decoded = Envelope::try_from(bytes.as_ref()).unwrap();
+            assert_eq!(decoded.payload(), message.as_bytes());
+            assert_eq!(decoded.compression().unwrap().algorithm(), algorithm);
+        }
+    }
+
+    #[test]
+    fn test_compressed_file_size_is_checked_after_decompression() {
+        let envelope =
+            testing_file_envelope().with_compression(Compression::new(Algorithm::Deflate));
+        let decoded = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert_eq!(decoded.file_meta().unwrap().size, 4);
+    }
+
+    #[test]
+    fn test_checksum() {
+        let envelope = Envelope::text("pay alice")
+            .with_compression(Compression::new(Algorithm::Deflate))
+            .with_checksum();
+        let decoded = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert_eq!(decoded, envelope);
+        assert_eq!(decoded.checksum(), Some(blake3::hash(b"pay alice")));
+        assert_eq!(Envelope::text("pay alice").checksum(), None);
+
+        let mut tampered = Envelope::text("pay alice").with_checksum().as_bytes();
+        let at = tampered.windows(5).position(|w| w == b"alice").unwrap();
+        tampered[at] = b'X';
+        assert!(matches!(
+            Envelope::try_from(tampered.as_ref()),
+            Err(EnvelopeError::ChecksumMismatch)
+        ));
+    }
+
+    fn testing_encryption() -> Encryption {
+        Encryption {
+            kdf: crate::crypto::Kdf::Argon2id {
+                memory_kib: 64,
+                iterations: 1,
+                parallelism: 1,
+            },
+            ..Encryption::default()
+        }
+    }
+
+    #[test]
+    fn test_encrypted_round_trip() {
+        let envelope = testing_file_envelope()
+            .with_name("backup")
+            .with_compression(Compression::new(Algorithm::Deflate));
+        let sealed = envelope.encrypt(b"hunter2", &testing_encryption()).unwrap();
+        let bytes = sealed.as_bytes();
+        assert!(!bytes.windows(9).any(|w| w == b"notes.bin"));
+
+        let parsed = Envelope::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(parsed.kind(), PayloadKind::Encrypted);
+        assert_eq!(parsed.name(), Some("backup"));
+        let opened = parsed.decrypt(b"hunter2").unwrap();
+        assert_eq!(opened.kind(), PayloadKind::File);
+        assert_eq!(opened.file_meta(), envelope.file_meta());
+        assert_eq!(opened.payload(), envelope.payload());
+    }
+
+    #[test]
+    fn test_encrypted_wrong_password() {
+        let sealed = Envelope::text("secret")
+            .encrypt(b"hunter2", &testing_encryption())
+            .unwrap();
+        let parsed = Envelope::try_from(sealed.as_bytes().as_ref()).unwrap();
+        assert!(matches!(
+            parsed.decrypt(b"letmein"),
+            Err(EnvelopeError::Crypto(CryptoError::DecryptionFailed))
+        ));
+    }
+
+    #[test]
+    fn test_stream_encrypted_salvage() {
+        let envelope = Envelope::text(&"frame ".repeat(20)).with_name("big");
+        let sealed = envelope
+            .encrypt_stream(b"hunter2", &testing_encryption(), 32)
+            .unwrap();
+        let mut bytes = sealed.as_bytes();
+        let parsed = Envelope::try_from(bytes.as_ref()).unwrap();
+        assert!(parsed.is_encrypted());
+        assert_eq!(parsed.name(), Some("big"));
+        assert_eq!(parsed.decrypt(b"hunter2").unwrap(), envelope);
+
+        // Damage the third frame of the body.
+        let len = bytes.len();
+        bytes[len - sealed.payload.len() + 48 * 2 + 5] ^= 0x20;
+        let damaged = Envelope::try_from(bytes.as_ref()).unwrap();
+        assert!(damaged.decrypt(b"hunter2").is_err());
+        let (salvaged, frames) = damaged.decrypt_salvaging(b"hunter2").unwrap();
+        assert_eq!(frames, [2]);
+        let text = salvaged.payload();
+        assert_eq!(text.len(), envelope.payload().len());
+        assert_eq!(text.iter().filter(|&&b| b == 0).count(), 32);
+    }
+
+    fn testing_kdf() -> Kdf {
+        Kdf::Pbkdf2Sha256 { iterations: 10 }
+    }
+
+    #[test]
+    fn test_integrity_round_trip() {
+        let envelope = testing_file_envelope()
+            .with_name("signed")
+            .with_compression(Compression::new(Algorithm::Deflate));
+        let bytes = envelope
+            .as_bytes_with_integrity(b"hunter2", testing_kdf())
+            .unwrap();
+        let parsed = Envelope::try_from(bytes.as_ref()).unwrap();
+        assert!(parsed.has_integrity_tag());
+        assert_eq!(parsed.payload(), envelope.payload());
+        assert!(parsed.verify(b"hunter2").is_ok());
+        assert!(matches!(
+            parsed.verify(b"letmein"),
+            Err(EnvelopeError::Crypto(CryptoError::IntegrityCheckFailed))
+        ));
+        assert!(!Envelope::try_from(parsed.as_bytes().as_ref())
+            .unwrap()
+            .has_integrity_tag());
+    }
+
+    #[test]
+    fn test_integrity_detects_tampering() {
+        let bytes = Envelope::text("pay alice")
+            .with_name("order")
+            .as_bytes_with_integrity(b"hunter2", testing_kdf())
+            .unwrap();
+        for target in [&b"alice"[..], b"order"] {
+            let mut tampered = bytes.clone();
+            let at = tampered.windows(5).position(|w| w == target).unwrap();
+            tampered[at] = b'X';
+            let parsed = Envelope::try_from(tampered.as_ref()).unwrap();
+            assert!(parsed.verify(b"hunter2").is_err());
+        }
+    }
+
+    #[test]
+    fn test_integrity_tag_must_be_last() {
+        let mut bytes = Envelope::text("x")
+            .as_bytes_with_integrity(b"hunter2", testing_kdf())
+            .unwrap();
+        let end = bytes.len() - 2;
+        assert_eq!(bytes[end], tag::END);
+        bytes.splice(end..end, [tag::NAME, 0, 0, 0, 0]);
+        assert!(matches!(
+            Envelope::try_from(bytes.as_ref()),
+            Err(EnvelopeError::InvalidField("integrity tag"))
+        ));
+    }
+
+    #[test]
+    fn test_openpgp_wrapping() {
+        let envelope = testing_file_envelope().with_name("pgp");
+        let wrapped = envelope
+            .clone()
+            .with_openpgp_message(b"-----BEGIN PGP MESSAGE-----".to_vec());
+        let parsed = Envelope::try_from(wrapped.as_bytes().as_ref()).unwrap();
+        assert!(parsed.is_openpgp());
+        assert_eq!(parsed.file_meta(), envelope.file_meta());
+        assert!(parsed.clone().with_openpgp_contents(vec![0; 3]).is_err());
+        let opened = parsed
+            .with_openpgp_contents(envelope.payload().to_vec())
+            .unwrap();
+        assert_eq!(opened, envelope);
+    }
+
+    #[test]
+    fn test_split_and_combine() {
+        let envelope = Envelope::text("secret").with_name("part");
+        let shares = envelope.split(2, 3).unwrap();
+        assert_eq!(shares.len(), 3);
+        let parsed: Vec<Envelope> = shares
+            .iter()
+            .map(|s| Envelope::try_from(s.as_bytes().as_ref()).unwrap())
+            .collect();
+        assert_eq!(parsed, shares);
+        assert_eq!(parsed[1].kind(), PayloadKind::Share);
+        assert_eq!(parsed[1].name(), Some("part"));
+        assert_eq!(parsed[1].share_info().unwrap().x, 2);
+
+        let picked = [parsed[2].clone(), parsed[2].clone(), parsed[0].clone()];
+        assert_eq!(Envelope::combine(&picked).unwrap(), envelope);
+        assert!(matches!(
+            Envelope::combine(&parsed[..1]),
+            Err(EnvelopeError::Shamir(ShamirError::NotEnoughShares { .. }))
+        ));
+        let other = envelope.split(2, 3).unwrap();
+        assert!(Envelope::combine(&[parsed[0].clone(), other[1].clone()]).is_err());
+    }
+
+    #[test]
+    fn test_stripe_and_join() {
+        let data = Envelope::text("a payload too large for one file")
+            .with_name("big")
+            .as_bytes();
+        let parts = Envelope::stripe(&data, Some("big"), 3);
+        assert_eq!(parts.len(), 3);
+        let parsed: Vec<Envelope> = parts
+            .iter()
+            .map(|p| Envelope::try_from(p.as_bytes().as_ref()).unwrap())
+            .collect();
+        assert_eq!(parsed, parts);
+        assert_eq!(parsed[0].kind(), PayloadKind::Part);
+        assert_eq!(parsed[2].name(), Some("big"));
+
+        let rest = [parsed[0].clone(), parsed[1].clone()];
+        assert_eq!(Envelope::join(&parsed[2], &rest).unwrap(), data);
+        assert!(matches!(
+            Envelope::join(&parsed[0], &parsed[2..]),
+            Err(EnvelopeError::MissingPart { index: 2, total: 3 })
+        ));
+        let other = Envelope::stripe(&data, None, 3);
+        assert!(Envelope::join(&parsed[0], &other[1..]).is_err());
+    }
+
+    #[test]
+    fn test_write_streaming() {
+        let meta = FileMeta {
+            name: "big.bin".to_string(),
+            size: 5000,
+            modified: None,
+        };
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 7) as u8).collect();
+        let envelope = Envelope::file(meta.clone(), Vec::new())
+            .with_name("big")
+            .with_compression(Compression::new(Algorithm::Deflate));
+        let mut streamed = Vec::new();
+        envelope
+            .write_streaming(data.as_slice(), &mut streamed)
+            .unwrap();
+        let whole = Envelope::file(meta, data.clone())
+            .with_name("big")
+            .with_compression(Compression::new(Algorithm::Deflate));
+        assert_eq!(streamed, whole.as_bytes());
+
+        let mut sealed = Vec::new();
+        envelope
+            .write_encrypted_streaming(
+                data.as_slice(),
+                b"hunter2",
+                &testing_encryption(),
+                256,
+                &mut sealed,
+            )
+            .unwrap();
+        let parsed = Envelope::try_from(sealed.as_slice()).unwrap();
+        assert_eq!(parsed.name(), Some("big"));
+        assert_eq!(parsed.stream_params().unwrap().frame_len, 256);
+        assert_eq!(parsed.decrypt(b"hunter2").unwrap(), whole);
+
+        assert!(matches!(
+            envelope
+                .with_checksum()
+                .write_streaming(data.as_slice(), Vec::new()),
+            Err(EnvelopeError::NotStreamable("checksum"))
+        ));
+    }
+
+    #[test]
+    fn test_part_writer() {
+        let data = Envelope::text(&"streamed ".repeat(100)).as_bytes();
+        let total = parts_needed(900, 300).unwrap();
+        assert_eq!(total, 222);
+        let mut parts = Vec::new();
+        let mut writer = PartWriter::new(Some("big"), 5, 300, |part| {
+            parts.push(part);
+            Ok(())
+        });
+        writer.write_all(&data).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(parts.len(), 5);
+        assert_eq!(parts[0].payload().len(), 300);
+        assert!(parts[4].payload().is_empty());
+        assert_eq!(parts[4].part_info().unwrap().index, 5);
+        assert_eq!(Envelope::join(&parts[3], &parts).unwrap(), data);
+
+        let mut writer = PartWriter::new(None, 2, 300, |_| Ok(()));
+        assert!(writer.write_all(&data).is_err());
+    }
+
+    #[cfg(feature = "fec")]
+    #[test]
+    fn test_error_correction_repairs_damage() {
+        let envelope = Envelope::text(&"x".repeat(1000))
+            .with_name("fec")
+            .with_compression(Compression::new(Algorithm::Deflate))
+            .with_fec(Fec::new(32).unwrap());
+        let mut bytes = envelope
+            .as_bytes_with_integrity(b"password", testing_kdf())
+            .unwrap();
+        let parsed = Envelope::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(parsed.repaired(), 0);
+        assert_eq!(parsed.fec(), Some(Fec::new(32).unwrap()));
+
+        let end = bytes.len();
+        for byte in &mut bytes[end - 20..end - 4] {
+            *byte = 0;
+        }
+        let repaired = Envelope::try_from(bytes.as_ref()).unwrap();
+        assert!(repaired.repaired() > 0);
+        assert_eq!(repaired.payload(), envelope.payload());
+        repaired.verify(b"password").unwrap();
+    }
+
+    #[test]
+    fn test_deniable_decoy() {
+        let secret = Envelope::text("the real plans").with_name("notes");
+        let decoy = Envelope::text("groceries: milk, eggs");
+        let sealed = secret
+            .encrypt_deniable(b"hunter2", Some((&decoy, b"decoy")), &testing_encryption())
+            .unwrap();
+        let bytes = sealed.as_bytes();
+        assert!(!bytes.windows(5).any(|w| w == b"plans" || w == b"groce"));
+        let parsed = Envelope::try_from(bytes.as_ref()).unwrap();
+        assert!(parsed.is_deniable());
+        assert_eq!(parsed.kind(), PayloadKind::Encrypted);
+
+        assert_eq!(parsed.decrypt(b"hunter2").unwrap(), secret);
+        let opened = parsed.decrypt(b"decoy").unwrap();
+        assert_eq!(opened.payload(), b"groceries: milk, eggs");
+        assert_eq!(opened.name(), Some("notes"));
+        assert!(parsed.decrypt(b"letmein").is_err());
+
+        let alone = secret
+            .encrypt_deniable(b"hunter2", None, &testing_encryption())
+            .unwrap();
+        assert_eq!(alone.payload().len(), sealed.payload().len());
+        assert_eq!(alone.decrypt(b"hunter2").unwrap(), secret);
+    }
+
+    #[test]
+    fn test_encrypted_to_recipients() {
+        let identity = age::x25519::Identity::generate();
+        let sealed = testing_file_envelope()
+            .with_name("backup")
+            .encrypt_to(&[identity.to_public()])
+            .unwrap();
+        let parsed = Envelope::try_from(sealed.as_bytes().as_ref()).unwrap();
+        assert!(parsed.is_encrypted_to_recipients());
+        assert_eq!(parsed.name(), Some("backup"));
+        assert!(matches!(
+            parsed.decrypt(b"hunter2"),
+            Err(EnvelopeError::IdentityRequired)
+        ));
+        let identities: Vec<Box<dyn age::Identity>> = vec![Box::new(identity)];
+        let opened = parsed.decrypt_with(&identities).unwrap();
+        assert_eq!(opened.kind(), PayloadKind::File);
+        assert_eq!(opened.payload(), &[0, 159, 146, 150]);
+    }
+
+    #[test]
+    fn test_recipients_name_is_checked() {
+        let identity = age::x25519::Identity::generate();
+        let sealed = Envelope::text("secret")
+            .with_name("label")
+            .encrypt_to(&[identity.to_public()])
+            .unwrap();
+        let mut bytes = sealed.as_bytes();
+        let at = bytes.windows(5).position(|w| w == b"label").unwrap();
+        bytes[at] = b'L';
+        let parsed = Envelope::try_from(bytes.as_ref()).unwrap();
+        let identities: Vec<Box<dyn age::Identity>> = vec![Box::new(identity)];
+        assert!(parsed.decrypt_with(&identities).is_err());
+    }
+
+    #[test]
+    fn test_encrypted_name_is_authenticated() {
+        let sealed = Envelope::text("secret")
+            .with_name("label")
+            .encrypt(b"hunter2", &testing_encryption())
+            .unwrap();
+        let mut bytes = sealed.as_bytes();
+        let at = bytes.windows(5).position(|w| w == b"label").unwrap();
+        bytes[at] = b'L';
+        let parsed = Envelope::try_from(bytes.as_ref()).unwrap();
+        assert!(parsed.decrypt(b"hunter2").is_err());
+    }
+
+    #[test]
+    fn test_plain_message_is_not_envelope() {
+        assert!(!Envelope::is_envelope(b"This is a secret"));
+    }
+
+    #[test]
+    fn test_truncated_envelope() {
+        let bytes = testing_file_envelope().as_bytes();
+        assert!(matches!(
+            Envelope::try_from(&bytes[..10]),
+            Err(EnvelopeError::Truncated(Span {
+                what: "field value",
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_size_mismatch() {
+        let mut bytes = testing_file_envelope().as_bytes();
+        bytes.push(0);
+        assert!(Envelope::try_from(bytes.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_unknown_fields() {
+        let mut bytes = Envelope::MAGIC.to_vec();
+        bytes.push(Envelope::VERSION);
+        put_field(&mut bytes, tag::KIND, &[0]);
+        put_field(&mut bytes, 0x7f, b"ignored");
+        bytes.push(tag::END);
+        bytes.extend_from_slice(b"hi");
+        let envelope = Envelope::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(envelope.payload(), b"hi");
+
+        let mut bytes = Envelope::MAGIC.to_vec();
+        bytes.push(Envelope::VERSION);
+        put_field(&mut bytes, 0xfe, b"");
+        bytes.push(tag::END);
+        assert!(matches!(
+            Envelope::try_from(bytes.as_ref()),
+            Err(EnvelopeError::UnknownCriticalField(0xfe))
+        ));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_from_path() {
+        let path = std::env::temp_dir().join("pngme_envelope_from_path.bin");
+        fs::write(&path, [1, 2, 3]).unwrap();
+        let envelope = Envelope::from_path(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let meta = envelope.file_meta().unwrap();
+        assert_eq!(meta.name, "pngme_envelope_from_path.bin");
+        assert_eq!(meta.size, 3);
+        assert!(meta.modified.is_some());
+        assert_eq!(envelope.payload(), &[1, 2, 3]);
+    }
+}
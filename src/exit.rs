@@ -0,0 +1,348 @@
+use std::io;
+
+use pngme::chunk::ChunkError;
+use pngme::chunk_type::ChunkTypeError;
+use pngme::container::ContainerError;
+use pngme::crypto::CryptoError;
+use pngme::envelope::EnvelopeError;
+use pngme::fec::FecError;
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+use pngme::fuse::FuseError;
+use pngme::gif::GifError;
+use pngme::image::ImageError;
+use pngme::index::IndexError;
+use pngme::jpeg::JpegError;
+use pngme::lint::{Lint, Rule};
+use pngme::lsb::LsbError;
+use pngme::messages::MessageError;
+#[cfg(feature = "openpgp")]
+use pngme::openpgp::OpenPgpError;
+use pngme::png::PngError;
+use pngme::polyglot::PolyglotError;
+#[cfg(feature = "http")]
+use pngme::remote::RemoteError;
+use pngme::sanitize::SanitizeError;
+use pngme::shamir::ShamirError;
+use pngme::storage::StorageError;
+use pngme::trailer::TrailerError;
+use pngme::watermark::WatermarkError;
+
+use crate::api::ApiError;
+#[cfg(all(feature = "daemon", unix))]
+use crate::daemon::DaemonError;
+use crate::git_filter::GitFilterError;
+use crate::manifest::ManifestError;
+use crate::rpc::RpcError;
+#[cfg(feature = "serve")]
+use crate::serve::ServeError;
+use crate::template::TemplateError;
+
+/// Any failure that doesn't fall in one of the classes below.
+pub const FAILURE: u8 = 1;
+/// Invalid command line arguments (also used by clap for usage errors).
+pub const BAD_ARGUMENTS: u8 = 2;
+/// The requested chunk or payload does not exist in the file.
+pub const NOT_FOUND: u8 = 3;
+/// The file is not a well-formed PNG.
+pub const PARSE_ERROR: u8 = 4;
+/// A chunk failed its CRC check.
+pub const CRC_FAILURE: u8 = 5;
+/// Reading or writing a file failed.
+pub const IO_ERROR: u8 = 6;
+/// A password is needed but wasn't given, or it is wrong (or the payload
+/// was tampered with).
+pub const AUTH_FAILURE: u8 = 7;
+
+/// Whether `err` comes of writing to a pipe whose reader has gone, as
+/// `pngme print a.png | head` does once it has its lines. Rust ignores
+/// `SIGPIPE`, so writes fail with `EPIPE` instead; that ends the command
+/// cleanly, since nobody is left to read the rest.
+pub fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<io::Error>()
+            .is_some_and(|e| e.kind() == io::ErrorKind::BrokenPipe)
+    })
+}
+
+/// Maps an error to its exit code by looking at the outermost error in the
+/// chain that belongs to a known class.
+pub fn code_for(err: &anyhow::Error) -> u8 {
+    for cause in err.chain() {
+        if let Some(e) = cause.downcast_ref::<PngError>() {
+            return match e {
+                PngError::InvalidHeader => PARSE_ERROR,
+                PngError::BadChunk { source, .. } => chunk_error_code(source),
+                PngError::BadChunkType(_) => BAD_ARGUMENTS,
+                PngError::ChunkNotFound(_) => NOT_FOUND,
+                PngError::UnknownCritical { .. } => PARSE_ERROR,
+                PngError::LimitExceeded(_) => PARSE_ERROR,
+                PngError::TooLarge => FAILURE,
+                PngError::Nonconforming { .. } => PARSE_ERROR,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<ChunkError>() {
+            return chunk_error_code(e);
+        }
+        if let Some(MessageError::NameNotFound(_)) = cause.downcast_ref::<MessageError>() {
+            return NOT_FOUND;
+        }
+        match cause.downcast_ref::<EnvelopeError>() {
+            Some(EnvelopeError::PasswordRequired | EnvelopeError::IdentityRequired) => {
+                return AUTH_FAILURE
+            }
+            Some(EnvelopeError::Crypto(e)) => return crypto_error_code(e),
+            Some(EnvelopeError::Shamir(e)) => return shamir_error_code(e),
+            Some(EnvelopeError::MissingPart { .. }) => return NOT_FOUND,
+            Some(EnvelopeError::ChecksumMismatch) => return CRC_FAILURE,
+            Some(EnvelopeError::NotStreamable(_)) => return BAD_ARGUMENTS,
+            Some(EnvelopeError::Io(_)) => return IO_ERROR,
+            Some(EnvelopeError::LimitExceeded(_)) => return PARSE_ERROR,
+            Some(EnvelopeError::Fec(FecError::InvalidParity(_) | FecError::BadParity(_))) => {
+                return BAD_ARGUMENTS
+            }
+            _ => {}
+        }
+        if let Some(e) = cause.downcast_ref::<ShamirError>() {
+            return shamir_error_code(e);
+        }
+        if let Some(e) = cause.downcast_ref::<LsbError>() {
+            return match e {
+                LsbError::NoPayload => NOT_FOUND,
+                LsbError::InvalidBits(_) | LsbError::NoAlpha(_) | LsbError::UnknownStrategy(_) => {
+                    BAD_ARGUMENTS
+                }
+                LsbError::Image(_) | LsbError::MissingPalette => PARSE_ERROR,
+                LsbError::Unsupported(_) | LsbError::TooLarge { .. } => FAILURE,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<TrailerError>() {
+            return match e {
+                TrailerError::NotFound => NOT_FOUND,
+                TrailerError::Truncated(_) => PARSE_ERROR,
+                TrailerError::TooLong(_) => FAILURE,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<PolyglotError>() {
+            return match e {
+                PolyglotError::Trailer(_) => FAILURE,
+                PolyglotError::LimitExceeded(_) => PARSE_ERROR,
+                _ => BAD_ARGUMENTS,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<ContainerError>() {
+            return match e {
+                ContainerError::NotFound(_) => NOT_FOUND,
+                ContainerError::TooLarge(_) => FAILURE,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<JpegError>() {
+            return match e {
+                JpegError::UnknownSegment(_) => BAD_ARGUMENTS,
+                _ => PARSE_ERROR,
+            };
+        }
+        if let Some(lint) = cause.downcast_ref::<Lint>() {
+            return match lint.rule {
+                Rule::BadCrc => CRC_FAILURE,
+                _ => PARSE_ERROR,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<WatermarkError>() {
+            return match e {
+                WatermarkError::NotFound(_) => NOT_FOUND,
+                WatermarkError::Image(_) => PARSE_ERROR,
+                WatermarkError::Unsupported(_) => FAILURE,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<CryptoError>() {
+            return crypto_error_code(e);
+        }
+        #[cfg(feature = "openpgp")]
+        if let Some(e) = cause.downcast_ref::<OpenPgpError>() {
+            return match e {
+                OpenPgpError::NoMatchingKey | OpenPgpError::BadSignature(_) => AUTH_FAILURE,
+                OpenPgpError::NoKeys
+                | OpenPgpError::NoEncryptionKey(_)
+                | OpenPgpError::NoSigningKey(_) => BAD_ARGUMENTS,
+                OpenPgpError::Sequoia(_) => FAILURE,
+            };
+        }
+        #[cfg(feature = "http")]
+        if let Some(e) = cause.downcast_ref::<RemoteError>() {
+            return remote_error_code(e);
+        }
+        if let Some(e) = cause.downcast_ref::<StorageError>() {
+            return match e {
+                StorageError::BadLocation(_) | StorageError::MissingCredentials(_) => BAD_ARGUMENTS,
+                StorageError::NotFound(_) => NOT_FOUND,
+                StorageError::Changed(_) => FAILURE,
+                StorageError::TooLarge { .. } => PARSE_ERROR,
+                StorageError::Status { .. }
+                | StorageError::BadResponse(_)
+                | StorageError::Io(_) => IO_ERROR,
+                #[cfg(feature = "s3")]
+                StorageError::Remote(e) => remote_error_code(e),
+            };
+        }
+        #[cfg(all(feature = "fuse", target_os = "linux"))]
+        if cause.is::<FuseError>() {
+            return IO_ERROR;
+        }
+        #[cfg(all(feature = "daemon", unix))]
+        if let Some(e) = cause.downcast_ref::<DaemonError>() {
+            return match e {
+                DaemonError::Running(_) | DaemonError::NotPrivate(_) => FAILURE,
+                DaemonError::Unreachable { .. } | DaemonError::BadResponse => IO_ERROR,
+                DaemonError::BadPath(_) => BAD_ARGUMENTS,
+                DaemonError::Failed { code, .. } => *code,
+            };
+        }
+        #[cfg(feature = "serve")]
+        if cause.is::<ServeError>() {
+            return BAD_ARGUMENTS;
+        }
+        if cause.is::<EnvelopeError>()
+            || cause.is::<IndexError>()
+            || cause.is::<ImageError>()
+            || cause.is::<SanitizeError>()
+            || cause.is::<GifError>()
+        {
+            return PARSE_ERROR;
+        }
+        if cause.is::<ChunkTypeError>()
+            || cause.is::<TemplateError>()
+            || cause.is::<GitFilterError>()
+            || cause.is::<ManifestError>()
+            || cause.is::<ApiError>()
+            || cause.is::<RpcError>()
+        {
+            return BAD_ARGUMENTS;
+        }
+        if cause.is::<io::Error>() {
+            return IO_ERROR;
+        }
+    }
+    FAILURE
+}
+
+fn chunk_error_code(err: &ChunkError) -> u8 {
+    match err {
+        ChunkError::ChecksumError => CRC_FAILURE,
+        _ => PARSE_ERROR,
+    }
+}
+
+fn crypto_error_code(err: &CryptoError) -> u8 {
+    match err {
+        CryptoError::DecryptionFailed
+        | CryptoError::NoMatchingIdentity
+        | CryptoError::IntegrityCheckFailed => AUTH_FAILURE,
+        CryptoError::InvalidIdentityFile(_) | CryptoError::NoRecipients => BAD_ARGUMENTS,
+        _ => PARSE_ERROR,
+    }
+}
+
+#[cfg(feature = "http")]
+fn remote_error_code(err: &RemoteError) -> u8 {
+    match err {
+        RemoteError::NotHttps(_) => BAD_ARGUMENTS,
+        RemoteError::TooLarge { .. } => PARSE_ERROR,
+        RemoteError::Spawn(_) | RemoteError::Failed { .. } | RemoteError::Io(_) => IO_ERROR,
+    }
+}
+
+fn shamir_error_code(err: &ShamirError) -> u8 {
+    match err {
+        ShamirError::InvalidThreshold { .. } => BAD_ARGUMENTS,
+        ShamirError::NotEnoughShares { .. } => NOT_FOUND,
+        _ => PARSE_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_not_found() {
+        let err = anyhow::Error::from(PngError::ChunkNotFound("ruSt".to_string()));
+        assert_eq!(code_for(&err), NOT_FOUND);
+        let err = anyhow::Error::from(EnvelopeError::MissingPart { index: 2, total: 3 });
+        assert_eq!(code_for(&err), NOT_FOUND);
+        let err = anyhow::Error::from(LsbError::NoPayload);
+        assert_eq!(code_for(&err), NOT_FOUND);
+    }
+
+    #[test]
+    fn test_crc_failure_inside_context() {
+        let err: anyhow::Result<()> = Err(PngError::BadChunk {
+            offset: 8,
+            source: ChunkError::ChecksumError,
+        })
+        .context("Failed to parse a.png");
+        assert_eq!(code_for(&err.unwrap_err()), CRC_FAILURE);
+    }
+
+    #[test]
+    fn test_truncated_chunk_is_parse_error() {
+        let io = io::Error::from(io::ErrorKind::UnexpectedEof);
+        let err = anyhow::Error::from(PngError::BadChunk {
+            offset: 8,
+            source: ChunkError::InvalidChunkData(io),
+        });
+        assert_eq!(code_for(&err), PARSE_ERROR);
+    }
+
+    #[test]
+    fn test_io_error() {
+        let err: anyhow::Result<()> =
+            Err(io::Error::from(io::ErrorKind::NotFound)).context("Failed to read a.png");
+        assert_eq!(code_for(&err.unwrap_err()), IO_ERROR);
+    }
+
+    #[test]
+    fn test_broken_pipe() {
+        let err: anyhow::Result<()> =
+            Err(io::Error::from(io::ErrorKind::BrokenPipe)).context("Failed to write");
+        assert!(is_broken_pipe(&err.unwrap_err()));
+        let err = anyhow::Error::from(io::Error::from(io::ErrorKind::NotFound));
+        assert!(!is_broken_pipe(&err));
+    }
+
+    #[test]
+    fn test_bad_arguments() {
+        let err = anyhow::Error::from(ChunkTypeError::InvalidStringLength(5));
+        assert_eq!(code_for(&err), BAD_ARGUMENTS);
+    }
+
+    #[test]
+    fn test_auth_failure() {
+        let err = anyhow::Error::from(EnvelopeError::PasswordRequired);
+        assert_eq!(code_for(&err), AUTH_FAILURE);
+        let err = anyhow::Error::from(EnvelopeError::Crypto(CryptoError::DecryptionFailed));
+        assert_eq!(code_for(&err), AUTH_FAILURE);
+        let err = anyhow::Error::from(EnvelopeError::Crypto(CryptoError::NoMatchingIdentity));
+        assert_eq!(code_for(&err), AUTH_FAILURE);
+        let err = anyhow::Error::from(EnvelopeError::Crypto(CryptoError::IntegrityCheckFailed));
+        assert_eq!(code_for(&err), AUTH_FAILURE);
+    }
+
+    #[test]
+    fn test_shamir_errors() {
+        let err = anyhow::Error::from(EnvelopeError::Shamir(ShamirError::InvalidThreshold {
+            threshold: 4,
+            shares: 3,
+        }));
+        assert_eq!(code_for(&err), BAD_ARGUMENTS);
+        let err: anyhow::Result<()> =
+            Err(ShamirError::NotEnoughShares { needed: 3, got: 2 }).context("Not enough carriers");
+        assert_eq!(code_for(&err.unwrap_err()), NOT_FOUND);
+    }
+
+    #[test]
+    fn test_other_failure() {
+        assert_eq!(code_for(&anyhow::anyhow!("something else")), FAILURE);
+    }
+}
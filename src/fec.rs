@@ -0,0 +1,179 @@
+//! Reed–Solomon forward error correction, so a payload survives some damage
+//! to the bytes it is stored in.
+//!
+//! Data is cut into blocks of `255 - parity` bytes, each extended with
+//! `parity` check bytes so that up to `parity / 2` damaged bytes per block
+//! can be repaired. The blocks are interleaved byte by byte, which spreads a
+//! burst of damage (e.g. an overwritten range) over all of them.
+//!
+//! The codec needs the `fec` feature; without it [`Fec::new`] fails, so
+//! envelopes stored with error correction can't be read back.
+
+#[cfg(feature = "fec")]
+use reed_solomon::{Decoder, Encoder};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FecError {
+    #[error("Invalid error correction parity {0} (expected an even number from 2 to 128)")]
+    InvalidParity(u8),
+    #[error("Invalid error correction parity: {0}")]
+    BadParity(String),
+    #[error("Error-corrected data is truncated")]
+    Truncated,
+    #[error("Too much damage to repair")]
+    TooManyErrors,
+    #[error("pngme was built without error correction (enable the fec feature)")]
+    NotBuilt,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Fec {
+    parity: u8,
+}
+
+impl Fec {
+    /// Length of an encoded block, the largest a GF(256) code allows.
+    pub const BLOCK_LEN: usize = 255;
+    pub const MAX_PARITY: u8 = 128;
+
+    pub fn new(parity: u8) -> Result<Self, FecError> {
+        if !(2..=Self::MAX_PARITY).contains(&parity) || !parity.is_multiple_of(2) {
+            return Err(FecError::InvalidParity(parity));
+        }
+        if !cfg!(feature = "fec") {
+            return Err(FecError::NotBuilt);
+        }
+        Ok(Self { parity })
+    }
+    /// Check bytes per block.
+    pub fn parity(&self) -> u8 {
+        self.parity
+    }
+    #[cfg(feature = "fec")]
+    fn data_len(&self) -> usize {
+        Self::BLOCK_LEN - self.parity as usize
+    }
+    /// Encodes `data`; the result is a whole number of blocks, so
+    /// [`Fec::decode`] needs the original length back.
+    #[cfg(feature = "fec")]
+    pub fn encode(&self, data: &[u8]) -> Vec<u8> {
+        let encoder = Encoder::new(self.parity as usize);
+        let blocks: Vec<Vec<u8>> = data
+            .chunks(self.data_len())
+            .map(|chunk| {
+                let mut block = chunk.to_vec();
+                block.resize(self.data_len(), 0);
+                encoder.encode(&block).to_vec()
+            })
+            .collect();
+        let mut encoded = Vec::with_capacity(blocks.len() * Self::BLOCK_LEN);
+        for i in 0..Self::BLOCK_LEN {
+            encoded.extend(blocks.iter().map(|block| block[i]));
+        }
+        encoded
+    }
+    /// Repairs and decodes data made by [`Fec::encode`], returning it with
+    /// the number of bytes that had to be repaired.
+    #[cfg(feature = "fec")]
+    pub fn decode(&self, encoded: &[u8], len: usize) -> Result<(Vec<u8>, usize), FecError> {
+        let count = len.div_ceil(self.data_len());
+        count
+            .checked_mul(Self::BLOCK_LEN)
+            .filter(|&n| n == encoded.len())
+            .ok_or(FecError::Truncated)?;
+        let decoder = Decoder::new(self.parity as usize);
+        let mut data = Vec::with_capacity(count * self.data_len());
+        let mut repaired = 0;
+        for i in 0..count {
+            let block: Vec<u8> = encoded.iter().skip(i).step_by(count).copied().collect();
+            let (corrected, errors) = decoder
+                .correct_err_count(&block, None)
+                .map_err(|_| FecError::TooManyErrors)?;
+            data.extend_from_slice(corrected.data());
+            repaired += errors;
+        }
+        data.truncate(len);
+        Ok((data, repaired))
+    }
+    #[cfg(not(feature = "fec"))]
+    pub fn encode(&self, _: &[u8]) -> Vec<u8> {
+        unreachable!("Fec::new fails without the fec feature")
+    }
+    #[cfg(not(feature = "fec"))]
+    pub fn decode(&self, _: &[u8], _: usize) -> Result<(Vec<u8>, usize), FecError> {
+        unreachable!("Fec::new fails without the fec feature")
+    }
+}
+
+impl FromStr for Fec {
+    type Err = FecError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parity = s.parse().map_err(|_| FecError::BadParity(s.to_string()))?;
+        Self::new(parity)
+    }
+}
+
+#[cfg(all(test, feature = "fec"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let fec = Fec::new(16).unwrap();
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let encoded = fec.encode(&data);
+        assert_eq!(encoded.len(), 5 * Fec::BLOCK_LEN);
+        assert_eq!(fec.decode(&encoded, data.len()).unwrap(), (data, 0));
+        assert_eq!(fec.decode(&fec.encode(&[]), 0).unwrap(), (vec![], 0));
+    }
+
+    #[test]
+    fn test_repairs_burst() {
+        let fec = Fec::new(8).unwrap();
+        let data = vec![42u8; 600];
+        let mut encoded = fec.encode(&data);
+        // Three blocks repair up to 4 bytes each, so 12 in a row.
+        for byte in &mut encoded[100..112] {
+            *byte ^= 0xff;
+        }
+        assert_eq!(fec.decode(&encoded, data.len()).unwrap(), (data, 12));
+        for byte in &mut encoded[200..215] {
+            *byte ^= 0xff;
+        }
+        assert!(matches!(
+            fec.decode(&encoded, 600),
+            Err(FecError::TooManyErrors)
+        ));
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        assert!(Fec::new(0).is_err());
+        assert!(Fec::new(7).is_err());
+        assert!(Fec::new(130).is_err());
+        assert_eq!(Fec::from_str("32").unwrap().parity(), 32);
+        assert!(Fec::from_str("many").is_err());
+        let fec = Fec::new(2).unwrap();
+        assert!(matches!(fec.decode(&[0; 10], 5), Err(FecError::Truncated)));
+    }
+
+    #[test]
+    fn test_overflowing_length() {
+        let fec = Fec::new(2).unwrap();
+        // As many blocks as wrap around to 254 bytes.
+        let blocks = usize::MAX / Fec::BLOCK_LEN + 1;
+        assert_eq!(blocks.wrapping_mul(Fec::BLOCK_LEN), 254);
+        let len = blocks * (Fec::BLOCK_LEN - 2);
+        assert!(matches!(
+            fec.decode(&[0; 254], len),
+            Err(FecError::Truncated)
+        ));
+        assert!(matches!(
+            fec.decode(&[0; 254], usize::MAX),
+            Err(FecError::Truncated)
+        ));
+    }
+}
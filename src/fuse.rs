@@ -0,0 +1,840 @@
+//! Read-only FUSE file systems (Linux), spoken straight to `/dev/fuse`.
+//!
+//! The kernel sends requests on the device and reads replies from it; a
+//! read-only tree of files needs about a dozen of them, so this serves
+//! them itself rather than linking libfuse. [`mount`] mounts a [`Tree`]
+//! with `mount(2)` when allowed to, as root, and through `fusermount3` or
+//! `fusermount` otherwise, as libfuse does, then serves it until it is
+//! unmounted.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum FuseError {
+    #[error("Failed to open /dev/fuse: {0}")]
+    Device(io::Error),
+    #[error("Failed to mount {path}: {source}")]
+    Mount { path: PathBuf, source: io::Error },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+// From <linux/fuse.h>.
+const FUSE_KERNEL_VERSION: u32 = 7;
+const FUSE_KERNEL_MINOR_VERSION: u32 = 31;
+const FUSE_ROOT_ID: u64 = 1;
+const FUSE_LOOKUP: u32 = 1;
+const FUSE_FORGET: u32 = 2;
+const FUSE_GETATTR: u32 = 3;
+const FUSE_OPEN: u32 = 14;
+const FUSE_READ: u32 = 15;
+const FUSE_STATFS: u32 = 17;
+const FUSE_RELEASE: u32 = 18;
+const FUSE_INIT: u32 = 26;
+const FUSE_OPENDIR: u32 = 27;
+const FUSE_READDIR: u32 = 28;
+const FUSE_RELEASEDIR: u32 = 29;
+const FUSE_INTERRUPT: u32 = 36;
+const FUSE_DESTROY: u32 = 38;
+const FUSE_BATCH_FORGET: u32 = 42;
+/// `fuse_init_out` before minor version 23.
+const COMPAT_22_INIT_OUT_LEN: usize = 24;
+const IN_HEADER_LEN: usize = 40;
+const OUT_HEADER_LEN: usize = 16;
+const MAX_WRITE: u32 = 128 * 1024;
+const BUFFER_LEN: usize = MAX_WRITE as usize + 4096;
+/// How long the kernel may cache names and attributes, in seconds: the
+/// tree never changes.
+const TTL: u64 = 3600;
+
+/// A read-only tree of directories and files, each file naming the
+/// contents [`mount`] asks for by its id.
+#[derive(Clone, Debug)]
+pub struct Tree {
+    /// Node `i` has inode number `i + 1`; the root comes first.
+    nodes: Vec<Node>,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    parent: u64,
+    modified: SystemTime,
+    kind: Kind,
+}
+
+#[derive(Clone, Debug)]
+enum Kind {
+    Directory(Vec<(Vec<u8>, u64)>),
+    File { id: usize, len: u64 },
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tree {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![Node {
+                parent: FUSE_ROOT_ID,
+                modified: UNIX_EPOCH,
+                kind: Kind::Directory(Vec::new()),
+            }],
+        }
+    }
+    /// Adds file `id` of `len` bytes at the `/`-separated `path`, creating
+    /// the directories above it. Empty, `.` and `..` components are
+    /// skipped; a path that names a file already there is ignored.
+    pub fn add_file(&mut self, path: &str, id: usize, len: u64, modified: SystemTime) {
+        let mut components = components(path);
+        let Some(name) = components.pop() else {
+            return;
+        };
+        let parent = self.directory(&components, modified);
+        self.add(parent, name, modified, Kind::File { id, len });
+    }
+    /// Adds the directory at `path`, and those above it; an empty `path`
+    /// dates the root.
+    pub fn add_directory(&mut self, path: &str, modified: SystemTime) {
+        let inode = self.directory(&components(path), modified);
+        self.node_mut(inode).modified = modified;
+    }
+    fn node(&self, inode: u64) -> Option<&Node> {
+        self.nodes.get(usize::try_from(inode).ok()?.checked_sub(1)?)
+    }
+    fn node_mut(&mut self, inode: u64) -> &mut Node {
+        &mut self.nodes[inode as usize - 1]
+    }
+    fn child(&self, parent: u64, name: &[u8]) -> Option<u64> {
+        match &self.node(parent)?.kind {
+            Kind::Directory(children) => children
+                .iter()
+                .find(|(child, _)| child == name)
+                .map(|&(_, inode)| inode),
+            Kind::File { .. } => None,
+        }
+    }
+    /// The directory at `components`, created as needed.
+    fn directory(&mut self, components: &[&[u8]], modified: SystemTime) -> u64 {
+        let mut inode = FUSE_ROOT_ID;
+        for name in components {
+            inode = match self.child(inode, name) {
+                Some(child)
+                    if matches!(self.nodes[child as usize - 1].kind, Kind::Directory(_)) =>
+                {
+                    child
+                }
+                // A file in the way keeps its name; what is under the
+                // directory goes under the parent instead.
+                Some(_) => continue,
+                None => self.add(inode, name, modified, Kind::Directory(Vec::new())),
+            };
+        }
+        inode
+    }
+    fn add(&mut self, parent: u64, name: &[u8], modified: SystemTime, kind: Kind) -> u64 {
+        if let Some(existing) = self.child(parent, name) {
+            return existing;
+        }
+        self.nodes.push(Node {
+            parent,
+            modified,
+            kind,
+        });
+        let inode = self.nodes.len() as u64;
+        if let Kind::Directory(children) = &mut self.node_mut(parent).kind {
+            children.push((name.to_vec(), inode));
+        }
+        inode
+    }
+}
+
+fn components(path: &str) -> Vec<&[u8]> {
+    path.split('/')
+        .filter(|component| !matches!(*component, "" | "." | ".."))
+        .map(str::as_bytes)
+        .collect()
+}
+
+/// Mounts `tree` read-only at `mountpoint` and serves it until it is
+/// unmounted, or until the process gets `SIGINT`, `SIGTERM` or `SIGHUP`,
+/// which unmount it. `read` gives the contents of the file with an id, when
+/// the file is first opened.
+pub fn mount(
+    tree: &Tree,
+    mountpoint: &Path,
+    read: impl FnMut(usize) -> io::Result<Vec<u8>>,
+) -> Result<(), FuseError> {
+    let mount = Mount::new(mountpoint)?;
+    let _signals = Signals::catch();
+    let mut session = Session::new(tree, read);
+    let mut buffer = vec![0; BUFFER_LEN];
+    let result = loop {
+        if STOP.load(Ordering::Relaxed) {
+            break Ok(());
+        }
+        let read =
+            unsafe { libc::read(mount.fd.as_raw_fd(), buffer.as_mut_ptr().cast(), BUFFER_LEN) };
+        if read < 0 {
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                // Interrupted by a signal, or a request the kernel took back.
+                Some(libc::EINTR | libc::ENOENT | libc::EAGAIN) => continue,
+                // Unmounted.
+                Some(libc::ENODEV) => break Ok(()),
+                _ => break Err(err.into()),
+            }
+        }
+        let Some(reply) = session.handle(&buffer[..read as usize]) else {
+            continue;
+        };
+        let written =
+            unsafe { libc::write(mount.fd.as_raw_fd(), reply.as_ptr().cast(), reply.len()) };
+        // ENOENT: the request was interrupted and nobody waits for it.
+        if written < 0 && io::Error::last_os_error().raw_os_error() != Some(libc::ENOENT) {
+            break Err(io::Error::last_os_error().into());
+        }
+        if session.destroyed {
+            break Ok(());
+        }
+    };
+    drop(mount);
+    result
+}
+
+/// Set by the signal handler to stop serving.
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn stop(_signal: libc::c_int) {
+    STOP.store(true, Ordering::Relaxed);
+}
+
+/// The handlers that stop [`mount`], replacing the previous ones until
+/// dropped. Without `SA_RESTART`, the signal also interrupts the blocking
+/// read on the device.
+struct Signals(Vec<(libc::c_int, libc::sigaction)>);
+
+impl Signals {
+    fn catch() -> Self {
+        STOP.store(false, Ordering::Relaxed);
+        let mut previous = Vec::new();
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = stop as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut old: libc::sigaction = std::mem::zeroed();
+                if libc::sigaction(signal, &action, &mut old) == 0 {
+                    previous.push((signal, old));
+                }
+            }
+        }
+        Self(previous)
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        for (signal, old) in &self.0 {
+            unsafe { libc::sigaction(*signal, old, std::ptr::null_mut()) };
+        }
+    }
+}
+
+/// A mounted file system and the device it is served on; dropping it
+/// unmounts it if it still is.
+struct Mount {
+    fd: OwnedFd,
+    mountpoint: PathBuf,
+    /// Mounted by `fusermount` rather than `mount(2)`.
+    fusermount: Option<&'static str>,
+}
+
+impl Mount {
+    fn new(mountpoint: &Path) -> Result<Self, FuseError> {
+        let mount_error = |source| FuseError::Mount {
+            path: mountpoint.to_path_buf(),
+            source,
+        };
+        let target = CString::new(mountpoint.as_os_str().as_bytes())
+            .map_err(|e| mount_error(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+        let device = c"/dev/fuse";
+        let fd = unsafe { libc::open(device.as_ptr(), libc::O_RDWR | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(FuseError::Device(io::Error::last_os_error()));
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let options = format!(
+            "fd={},rootmode=40000,user_id={},group_id={},default_permissions",
+            fd.as_raw_fd(),
+            uid,
+            gid
+        );
+        let options = CString::new(options).expect("no NUL in the options");
+        let mounted = unsafe {
+            libc::mount(
+                c"pngme".as_ptr(),
+                target.as_ptr(),
+                c"fuse.pngme".as_ptr(),
+                libc::MS_RDONLY | libc::MS_NOSUID | libc::MS_NODEV,
+                options.as_ptr().cast(),
+            )
+        };
+        if mounted == 0 {
+            return Ok(Self {
+                fd,
+                mountpoint: mountpoint.to_path_buf(),
+                fusermount: None,
+            });
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EPERM) {
+            return Err(mount_error(err));
+        }
+        // Not allowed to mount: the setuid helper does it and hands back
+        // its own descriptor of the device.
+        let mut last = err;
+        for helper in ["fusermount3", "fusermount"] {
+            match fusermount(helper, mountpoint) {
+                Ok(fd) => {
+                    return Ok(Self {
+                        fd,
+                        mountpoint: mountpoint.to_path_buf(),
+                        fusermount: Some(helper),
+                    })
+                }
+                Err(e) if e.kind() == io::ErrorKind::NotFound => last = e,
+                Err(e) => return Err(mount_error(e)),
+            }
+        }
+        Err(mount_error(last))
+    }
+}
+
+impl Drop for Mount {
+    fn drop(&mut self) {
+        match self.fusermount {
+            Some(helper) => {
+                let _ = Command::new(helper)
+                    .args(["-u", "-q", "-z", "--"])
+                    .arg(&self.mountpoint)
+                    .status();
+            }
+            None => {
+                if let Ok(target) = CString::new(self.mountpoint.as_os_str().as_bytes()) {
+                    unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) };
+                }
+            }
+        }
+    }
+}
+
+/// Mounts through `helper`, which gets one end of a socket pair in
+/// `_FUSE_COMMFD` and sends the device descriptor over it.
+fn fusermount(helper: &str, mountpoint: &Path) -> io::Result<OwnedFd> {
+    let mut fds = [0; 2];
+    if unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_STREAM, 0, fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (ours, theirs) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    unsafe { libc::fcntl(ours.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+    let status = Command::new(helper)
+        .args([
+            "-o",
+            "ro,nosuid,nodev,default_permissions,fsname=pngme,subtype=pngme",
+            "--",
+        ])
+        .arg(mountpoint)
+        .env("_FUSE_COMMFD", theirs.as_raw_fd().to_string())
+        .status()?;
+    drop(theirs);
+    if !status.success() {
+        return Err(io::Error::other(format!("{} failed: {}", helper, status)));
+    }
+    receive_fd(ours.as_raw_fd())
+}
+
+fn receive_fd(socket: RawFd) -> io::Result<OwnedFd> {
+    let mut byte = 0u8;
+    let mut iov = libc::iovec {
+        iov_base: (&mut byte as *mut u8).cast(),
+        iov_len: 1,
+    };
+    let space = unsafe { libc::CMSG_SPACE(size_of::<RawFd>() as u32) } as usize;
+    let mut control = vec![0u8; space];
+    let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+    message.msg_iov = &mut iov;
+    message.msg_iovlen = 1;
+    message.msg_control = control.as_mut_ptr().cast();
+    message.msg_controllen = space as _;
+    if unsafe { libc::recvmsg(socket, &mut message, 0) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let header = unsafe { libc::CMSG_FIRSTHDR(&message) };
+    if header.is_null()
+        || unsafe { (*header).cmsg_level } != libc::SOL_SOCKET
+        || unsafe { (*header).cmsg_type } != libc::SCM_RIGHTS
+    {
+        return Err(io::Error::other("fusermount sent no file descriptor"));
+    }
+    let fd = unsafe { std::ptr::read_unaligned(libc::CMSG_DATA(header).cast::<RawFd>()) };
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC) };
+    Ok(fd)
+}
+
+/// Answers the kernel's requests about a tree.
+struct Session<'a, F> {
+    tree: &'a Tree,
+    read: F,
+    /// The contents of the open files, by handle.
+    open: HashMap<u64, Arc<Vec<u8>>>,
+    /// The contents of the files with an open handle, by id, so opening a
+    /// file twice reads it once.
+    contents: HashMap<usize, (Arc<Vec<u8>>, usize)>,
+    next_handle: u64,
+    uid: u32,
+    gid: u32,
+    destroyed: bool,
+}
+
+impl<'a, F: FnMut(usize) -> io::Result<Vec<u8>>> Session<'a, F> {
+    fn new(tree: &'a Tree, read: F) -> Self {
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        Self {
+            tree,
+            read,
+            open: HashMap::new(),
+            contents: HashMap::new(),
+            next_handle: 1,
+            uid,
+            gid,
+            destroyed: false,
+        }
+    }
+
+    /// The reply to `request`, if it takes one.
+    fn handle(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let header = request.get(..IN_HEADER_LEN)?;
+        let opcode = u32_at(header, 4);
+        let unique = u64_at(header, 8);
+        let inode = u64_at(header, 16);
+        let body = &request[IN_HEADER_LEN..];
+        let result = match opcode {
+            FUSE_FORGET | FUSE_BATCH_FORGET | FUSE_INTERRUPT => return None,
+            FUSE_DESTROY => {
+                self.destroyed = true;
+                Ok(Vec::new())
+            }
+            FUSE_INIT => self.init(body),
+            FUSE_LOOKUP => self.lookup(inode, body),
+            FUSE_GETATTR => self.getattr(inode),
+            FUSE_OPEN => self.open(inode, body),
+            FUSE_READ => self.read(body),
+            FUSE_RELEASE => self.release(body),
+            FUSE_OPENDIR => self.opendir(inode),
+            FUSE_READDIR => self.readdir(inode, body),
+            FUSE_RELEASEDIR => Ok(Vec::new()),
+            FUSE_STATFS => Ok(self.statfs()),
+            _ => Err(libc::ENOSYS),
+        };
+        let (error, data) = match result {
+            Ok(data) => (0, data),
+            Err(errno) => (-errno, Vec::new()),
+        };
+        let mut reply = Vec::with_capacity(OUT_HEADER_LEN + data.len());
+        reply.extend_from_slice(&((OUT_HEADER_LEN + data.len()) as u32).to_ne_bytes());
+        reply.extend_from_slice(&error.to_ne_bytes());
+        reply.extend_from_slice(&unique.to_ne_bytes());
+        reply.extend_from_slice(&data);
+        Some(reply)
+    }
+
+    fn init(&mut self, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let major = u32_at(body, 0);
+        let minor = u32_at(body, 4);
+        let max_readahead = u32_at(body, 8);
+        let mut out = Vec::new();
+        push_u32(&mut out, FUSE_KERNEL_VERSION);
+        if major != FUSE_KERNEL_VERSION {
+            // The kernel will ask again with this major version, or give up.
+            push_u32(&mut out, FUSE_KERNEL_MINOR_VERSION);
+            out.resize(COMPAT_22_INIT_OUT_LEN, 0);
+            return Ok(out);
+        }
+        let minor = minor.min(FUSE_KERNEL_MINOR_VERSION);
+        push_u32(&mut out, minor);
+        push_u32(&mut out, max_readahead);
+        // No optional capabilities.
+        push_u32(&mut out, 0);
+        // Background requests and congestion threshold.
+        push_u16(&mut out, 16);
+        push_u16(&mut out, 12);
+        push_u32(&mut out, MAX_WRITE);
+        // Time granularity in nanoseconds.
+        push_u32(&mut out, 1);
+        match minor < 23 {
+            true => out.truncate(COMPAT_22_INIT_OUT_LEN),
+            false => out.resize(64, 0),
+        }
+        Ok(out)
+    }
+
+    fn lookup(&mut self, parent: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let name = body.split(|&b| b == 0).next().unwrap_or_default();
+        let inode = self.tree.child(parent, name).ok_or(libc::ENOENT)?;
+        let mut out = Vec::new();
+        push_u64(&mut out, inode);
+        // Generation, entry and attribute validity.
+        push_u64(&mut out, 0);
+        push_u64(&mut out, TTL);
+        push_u64(&mut out, TTL);
+        push_u32(&mut out, 0);
+        push_u32(&mut out, 0);
+        self.push_attr(&mut out, inode)?;
+        Ok(out)
+    }
+
+    fn getattr(&mut self, inode: u64) -> Result<Vec<u8>, i32> {
+        let mut out = Vec::new();
+        push_u64(&mut out, TTL);
+        push_u32(&mut out, 0);
+        push_u32(&mut out, 0);
+        self.push_attr(&mut out, inode)?;
+        Ok(out)
+    }
+
+    /// A `fuse_attr`.
+    fn push_attr(&self, out: &mut Vec<u8>, inode: u64) -> Result<(), i32> {
+        let node = self.tree.node(inode).ok_or(libc::ENOENT)?;
+        let (mode, len, links) = match node.kind {
+            Kind::Directory(_) => (libc::S_IFDIR | 0o555, 0, 2),
+            Kind::File { len, .. } => (libc::S_IFREG | 0o444, len, 1),
+        };
+        let modified = node.modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+        push_u64(out, inode);
+        push_u64(out, len);
+        push_u64(out, len.div_ceil(512));
+        for _ in 0..3 {
+            push_u64(out, modified.as_secs());
+        }
+        for _ in 0..3 {
+            push_u32(out, modified.subsec_nanos());
+        }
+        push_u32(out, mode);
+        push_u32(out, links);
+        push_u32(out, self.uid);
+        push_u32(out, self.gid);
+        // Device, block size and flags.
+        push_u32(out, 0);
+        push_u32(out, 4096);
+        push_u32(out, 0);
+        Ok(())
+    }
+
+    fn open(&mut self, inode: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let node = self.tree.node(inode).ok_or(libc::ENOENT)?;
+        let Kind::File { id, .. } = node.kind else {
+            return Err(libc::EISDIR);
+        };
+        if u32_at(body, 0) as i32 & libc::O_ACCMODE != libc::O_RDONLY {
+            return Err(libc::EROFS);
+        }
+        let contents = match self.contents.get_mut(&id) {
+            Some((contents, handles)) => {
+                *handles += 1;
+                Arc::clone(contents)
+            }
+            None => {
+                let contents =
+                    Arc::new((self.read)(id).map_err(|e| e.raw_os_error().unwrap_or(libc::EIO))?);
+                self.contents.insert(id, (Arc::clone(&contents), 1));
+                contents
+            }
+        };
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        self.open.insert(handle, contents);
+        let mut out = Vec::new();
+        // Handle, FOPEN_KEEP_CACHE (the contents never change), padding.
+        push_u64(&mut out, handle);
+        push_u32(&mut out, 1 << 1);
+        push_u32(&mut out, 0);
+        Ok(out)
+    }
+
+    fn read(&mut self, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let contents = self.open.get(&u64_at(body, 0)).ok_or(libc::EBADF)?;
+        let offset = usize::try_from(u64_at(body, 8)).unwrap_or(usize::MAX);
+        let len = u32_at(body, 16) as usize;
+        let start = offset.min(contents.len());
+        let end = start.saturating_add(len).min(contents.len());
+        Ok(contents[start..end].to_vec())
+    }
+
+    fn release(&mut self, body: &[u8]) -> Result<Vec<u8>, i32> {
+        if let Some(contents) = self.open.remove(&u64_at(body, 0)) {
+            self.contents.retain(|_, (kept, handles)| {
+                if Arc::ptr_eq(kept, &contents) {
+                    *handles -= 1;
+                }
+                *handles > 0
+            });
+        }
+        Ok(Vec::new())
+    }
+
+    fn opendir(&mut self, inode: u64) -> Result<Vec<u8>, i32> {
+        match self.tree.node(inode).ok_or(libc::ENOENT)?.kind {
+            Kind::Directory(_) => Ok(vec![0; 16]),
+            Kind::File { .. } => Err(libc::ENOTDIR),
+        }
+    }
+
+    fn readdir(&mut self, inode: u64, body: &[u8]) -> Result<Vec<u8>, i32> {
+        let node = self.tree.node(inode).ok_or(libc::ENOENT)?;
+        let Kind::Directory(children) = &node.kind else {
+            return Err(libc::ENOTDIR);
+        };
+        let offset = u64_at(body, 8);
+        let len = u32_at(body, 16) as usize;
+        let entries = [(&b"."[..], inode), (&b".."[..], node.parent)]
+            .into_iter()
+            .chain(
+                children
+                    .iter()
+                    .map(|(name, inode)| (name.as_slice(), *inode)),
+            );
+        let mut out = Vec::new();
+        for (index, (name, child)) in entries.enumerate().skip(offset as usize) {
+            let kind = match self.tree.node(child).map(|node| &node.kind) {
+                Some(Kind::File { .. }) => libc::DT_REG,
+                _ => libc::DT_DIR,
+            };
+            let entry_len = (24 + name.len()).next_multiple_of(8);
+            if out.len() + entry_len > len {
+                break;
+            }
+            // Inode, offset of the next entry, name length and type.
+            push_u64(&mut out, child);
+            push_u64(&mut out, index as u64 + 1);
+            push_u32(&mut out, name.len() as u32);
+            push_u32(&mut out, kind.into());
+            out.extend_from_slice(name);
+            out.resize(out.len().next_multiple_of(8), 0);
+        }
+        Ok(out)
+    }
+
+    fn statfs(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        // Blocks, free and available blocks, files and free files.
+        push_u64(&mut out, 0);
+        push_u64(&mut out, 0);
+        push_u64(&mut out, 0);
+        push_u64(&mut out, self.tree.nodes.len() as u64);
+        push_u64(&mut out, 0);
+        // Block size, longest name, fragment size.
+        push_u32(&mut out, 4096);
+        push_u32(&mut out, 255);
+        push_u32(&mut out, 4096);
+        out.resize(80, 0);
+        out
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    bytes
+        .get(at..at + 4)
+        .map_or(0, |b| u32::from_ne_bytes(b.try_into().expect("four bytes")))
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    bytes.get(at..at + 8).map_or(0, |b| {
+        u64::from_ne_bytes(b.try_into().expect("eight bytes"))
+    })
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+fn push_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_ne_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(opcode: u32, inode: u64, body: &[u8]) -> Vec<u8> {
+        let mut request = Vec::new();
+        push_u32(&mut request, (IN_HEADER_LEN + body.len()) as u32);
+        push_u32(&mut request, opcode);
+        push_u64(&mut request, 7);
+        push_u64(&mut request, inode);
+        request.resize(IN_HEADER_LEN, 0);
+        request.extend_from_slice(body);
+        request
+    }
+
+    /// The error and data of a reply.
+    fn reply<F: FnMut(usize) -> io::Result<Vec<u8>>>(
+        session: &mut Session<F>,
+        opcode: u32,
+        inode: u64,
+        body: &[u8],
+    ) -> (i32, Vec<u8>) {
+        let reply = session.handle(&request(opcode, inode, body)).unwrap();
+        assert_eq!(u32_at(&reply, 0) as usize, reply.len());
+        assert_eq!(u64_at(&reply, 8), 7);
+        (u32_at(&reply, 4) as i32, reply[OUT_HEADER_LEN..].to_vec())
+    }
+
+    fn read_in(handle: u64, offset: u64, len: u32) -> Vec<u8> {
+        let mut body = Vec::new();
+        push_u64(&mut body, handle);
+        push_u64(&mut body, offset);
+        push_u32(&mut body, len);
+        body.resize(40, 0);
+        body
+    }
+
+    fn testing_tree() -> Tree {
+        let mut tree = Tree::new();
+        tree.add_file("a.txt", 0, 5, UNIX_EPOCH);
+        tree.add_file("../b//c.txt", 1, 5, UNIX_EPOCH);
+        tree.add_directory("empty/", UNIX_EPOCH);
+        tree.add_file("a.txt", 2, 9, UNIX_EPOCH);
+        tree
+    }
+
+    #[test]
+    fn test_tree() {
+        let tree = testing_tree();
+        let b = tree.child(FUSE_ROOT_ID, b"b").unwrap();
+        let c = tree.child(b, b"c.txt").unwrap();
+        assert!(matches!(
+            tree.node(c).unwrap().kind,
+            Kind::File { id: 1, len: 5 }
+        ));
+        assert_eq!(tree.node(c).unwrap().parent, b);
+        assert!(tree.child(FUSE_ROOT_ID, b"empty").is_some());
+        let a = tree.child(FUSE_ROOT_ID, b"a.txt").unwrap();
+        assert!(matches!(
+            tree.node(a).unwrap().kind,
+            Kind::File { id: 0, .. }
+        ));
+        assert!(tree.node(0).is_none());
+        assert!(tree.node(99).is_none());
+    }
+
+    #[test]
+    fn test_session() {
+        let tree = testing_tree();
+        let mut reads = Vec::new();
+        let mut session = Session::new(&tree, |id| {
+            reads.push(id);
+            Ok([b"hello", b"world"][id].to_vec())
+        });
+
+        let mut init = Vec::new();
+        for value in [7, 38, 65536, 0] {
+            push_u32(&mut init, value);
+        }
+        let (error, out) = reply(&mut session, FUSE_INIT, 0, &init);
+        assert_eq!((error, out.len()), (0, 64));
+        assert_eq!((u32_at(&out, 0), u32_at(&out, 4)), (7, 31));
+
+        let (error, out) = reply(&mut session, FUSE_LOOKUP, FUSE_ROOT_ID, b"b\0");
+        assert_eq!((error, out.len()), (0, 128));
+        let b = u64_at(&out, 0);
+        assert_eq!(u32_at(&out, 40 + 60) & libc::S_IFMT, libc::S_IFDIR);
+        let (error, out) = reply(&mut session, FUSE_LOOKUP, b, b"c.txt\0");
+        assert_eq!(error, 0);
+        let c = u64_at(&out, 0);
+        assert_eq!(u64_at(&out, 40 + 8), 5);
+        let (error, _) = reply(&mut session, FUSE_LOOKUP, b, b"d.txt\0");
+        assert_eq!(error, -libc::ENOENT);
+
+        let (error, out) = reply(&mut session, FUSE_GETATTR, c, &[0; 16]);
+        assert_eq!((error, out.len()), (0, 104));
+        assert_eq!(u32_at(&out, 16 + 60), libc::S_IFREG | 0o444);
+
+        let (error, out) = reply(&mut session, FUSE_OPEN, c, &[0; 8]);
+        assert_eq!(error, 0);
+        let handle = u64_at(&out, 0);
+        let (_, again) = reply(&mut session, FUSE_OPEN, c, &[0; 8]);
+        let (_, out) = reply(&mut session, FUSE_READ, c, &read_in(handle, 1, 3));
+        assert_eq!(out, b"orl");
+        let (_, out) = reply(&mut session, FUSE_READ, c, &read_in(handle, 9, 3));
+        assert!(out.is_empty());
+        reply(&mut session, FUSE_RELEASE, c, &read_in(handle, 0, 0));
+        let (error, _) = reply(&mut session, FUSE_READ, c, &read_in(handle, 0, 3));
+        assert_eq!(error, -libc::EBADF);
+        reply(
+            &mut session,
+            FUSE_RELEASE,
+            c,
+            &read_in(u64_at(&again, 0), 0, 0),
+        );
+        let mut write = Vec::new();
+        push_u32(&mut write, libc::O_WRONLY as u32);
+        push_u32(&mut write, 0);
+        let (error, _) = reply(&mut session, FUSE_OPEN, c, &write);
+        assert_eq!(error, -libc::EROFS);
+        let (error, _) = reply(&mut session, FUSE_OPEN, b, &[0; 8]);
+        assert_eq!(error, -libc::EISDIR);
+
+        let (error, out) = reply(
+            &mut session,
+            FUSE_READDIR,
+            FUSE_ROOT_ID,
+            &read_in(0, 0, 4096),
+        );
+        assert_eq!(error, 0);
+        let mut names = Vec::new();
+        let mut at = 0;
+        while at < out.len() {
+            let len = u32_at(&out, at + 16) as usize;
+            names.push(String::from_utf8(out[at + 24..at + 24 + len].to_vec()).unwrap());
+            at += (24 + len).next_multiple_of(8);
+        }
+        assert_eq!(names, [".", "..", "a.txt", "b", "empty"]);
+        let (_, rest) = reply(
+            &mut session,
+            FUSE_READDIR,
+            FUSE_ROOT_ID,
+            &read_in(0, 3, 4096),
+        );
+        assert_eq!(u32_at(&rest, 16), 1);
+        assert_eq!(&rest[24..25], b"b");
+        let (error, _) = reply(&mut session, FUSE_READDIR, c, &read_in(0, 0, 4096));
+        assert_eq!(error, -libc::ENOTDIR);
+
+        let (error, _) = reply(&mut session, 9999, FUSE_ROOT_ID, &[]);
+        assert_eq!(error, -libc::ENOSYS);
+        assert!(session.handle(&request(FUSE_FORGET, c, &[0; 8])).is_none());
+        drop(session);
+        // Both handles on c.txt shared a single read.
+        assert_eq!(reads, [1]);
+    }
+}
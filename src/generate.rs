@@ -0,0 +1,313 @@
+//! Synthetic cover images, for when there is no fresh photo at hand to
+//! carry a payload and reusing the same one would link the carriers.
+//!
+//! Every image is 8-bit RGB and comes from a seed, so the same seed always
+//! gives the same image. Noise is pure random samples; gradient is a linear
+//! blend between two colors with a little grain; photo-like layers smooth
+//! noise at several scales, the way natural images mix large shapes and fine
+//! texture, and adds sensor-like grain on top.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::image::{ColorType, Header, ImageData};
+
+#[derive(Debug, Error)]
+pub enum GenerateError {
+    #[error("Invalid size {0:?} (expected WIDTHxHEIGHT, each 1-{max})", max = Size::MAX_SIDE)]
+    BadSize(String),
+    #[error("Unknown style {0:?} (expected noise, gradient or photo-like)")]
+    UnknownStyle(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Size {
+    pub const MAX_SIDE: u32 = 16384;
+
+    /// The smallest square image whose color samples hold `bytes` bytes at
+    /// `bits` bits each, besides the length and header `embed` adds.
+    pub fn for_capacity(bytes: usize, bits: u8) -> Self {
+        // One header byte at 1 bit per sample, then the length and payload.
+        let samples = 8 + ((bytes + 4) * 8).div_ceil(bits as usize);
+        let pixels = samples.div_ceil(3);
+        let side = ((pixels as f64).sqrt().ceil() as u32).clamp(1, Self::MAX_SIDE);
+        Self {
+            width: side,
+            height: side,
+        }
+    }
+}
+
+impl FromStr for Size {
+    type Err = GenerateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || GenerateError::BadSize(s.to_string());
+        let (width, height) = s.split_once(['x', 'X']).ok_or_else(bad)?;
+        let side = |side: &str| match side.parse() {
+            Ok(side) if (1..=Self::MAX_SIDE).contains(&side) => Ok(side),
+            _ => Err(bad()),
+        };
+        Ok(Self {
+            width: side(width)?,
+            height: side(height)?,
+        })
+    }
+}
+
+impl Display for Size {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Style {
+    Noise,
+    Gradient,
+    #[default]
+    PhotoLike,
+}
+
+impl FromStr for Style {
+    type Err = GenerateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "noise" => Ok(Self::Noise),
+            "gradient" => Ok(Self::Gradient),
+            "photo-like" => Ok(Self::PhotoLike),
+            _ => Err(GenerateError::UnknownStyle(s.to_string())),
+        }
+    }
+}
+
+impl Display for Style {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Noise => "noise",
+            Self::Gradient => "gradient",
+            Self::PhotoLike => "photo-like",
+        })
+    }
+}
+
+/// Generates an image of `size` in `style` from `seed`. Panics on a size
+/// with no pixels, which [`Size::from_str`] refuses.
+pub fn generate(size: Size, style: Style, seed: u64) -> ImageData {
+    let header = Header {
+        width: size.width,
+        height: size.height,
+        bit_depth: 8,
+        color_type: ColorType::Rgb,
+        interlaced: false,
+    };
+    let mut rng = Rng(seed);
+    let (width, height) = (size.width as usize, size.height as usize);
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    match style {
+        Style::Noise => pixels.extend((0..width * height * 3).map(|_| rng.next() as u8)),
+        Style::Gradient => {
+            let (from, to) = (rng.color(), rng.color());
+            let angle = rng.unit() * std::f64::consts::TAU;
+            let (dx, dy) = (angle.cos(), angle.sin());
+            let project = |x: f64, y: f64| x * dx + y * dy;
+            let (w, h) = (width as f64, height as f64);
+            let corners = [
+                project(0.0, 0.0),
+                project(w, 0.0),
+                project(0.0, h),
+                project(w, h),
+            ];
+            let lowest = corners.into_iter().fold(f64::INFINITY, f64::min);
+            let highest = corners.into_iter().fold(f64::NEG_INFINITY, f64::max);
+            let range = (highest - lowest).max(1.0);
+            for y in 0..height {
+                for x in 0..width {
+                    let t = (project(x as f64, y as f64) - lowest) / range;
+                    for c in 0..3 {
+                        let value = from[c] + (to[c] - from[c]) * t + rng.grain(1.0);
+                        pixels.push(value.round().clamp(0.0, 255.0) as u8);
+                    }
+                }
+            }
+        }
+        Style::PhotoLike => {
+            let shape = Field::fractal(&mut rng, width, height);
+            let tints = [
+                Field::new(&mut rng, width, height, (width.max(height) / 3).max(2)),
+                Field::new(&mut rng, width, height, (width.max(height) / 3).max(2)),
+            ];
+            let (shadow, light) = (rng.color(), rng.color());
+            for y in 0..height {
+                for x in 0..width {
+                    let t = shape.at(x, y);
+                    let tint = [tints[0].at(x, y), tints[1].at(x, y)];
+                    for c in 0..3 {
+                        let shift = match c {
+                            0 => tint[0] - 0.5,
+                            1 => 0.5 - (tint[0] + tint[1]) / 2.0,
+                            _ => tint[1] - 0.5,
+                        };
+                        let value =
+                            shadow[c] + (light[c] - shadow[c]) * t + shift * 60.0 + rng.grain(3.0);
+                        pixels.push(value.round().clamp(0.0, 255.0) as u8);
+                    }
+                }
+            }
+        }
+    }
+    ImageData::new(header, pixels)
+        .expect("a non-empty image with as many bytes as the header needs")
+}
+
+/// SplitMix64: small, fast and good enough for pictures.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    /// Uniform in [0, 1).
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+    fn color(&mut self) -> [f64; 3] {
+        [0; 3].map(|_| self.unit() * 255.0)
+    }
+    /// Roughly normal noise of standard deviation `sigma`.
+    fn grain(&mut self, sigma: f64) -> f64 {
+        // The sum of 3 uniforms has a variance of 1/4.
+        (self.unit() + self.unit() + self.unit() - 1.5) * 2.0 * sigma
+    }
+}
+
+/// Smooth noise in [0, 1]: random values on a grid, interpolated.
+struct Field {
+    values: Vec<f64>,
+    columns: usize,
+    cell: usize,
+}
+
+impl Field {
+    fn new(rng: &mut Rng, width: usize, height: usize, cell: usize) -> Self {
+        let columns = width / cell + 2;
+        let rows = height / cell + 2;
+        Self {
+            values: (0..columns * rows).map(|_| rng.unit()).collect(),
+            columns,
+            cell,
+        }
+    }
+    /// Fields from big shapes down to fine texture, each a bit weaker than
+    /// the one before, summed and stretched back to [0, 1].
+    fn fractal(rng: &mut Rng, width: usize, height: usize) -> Self {
+        const PERSISTENCE: f64 = 0.6;
+        let mut cell = (width.max(height) / 2).max(2);
+        let mut octaves = Vec::new();
+        while cell >= 2 && octaves.len() < 7 {
+            octaves.push(Self::new(rng, width, height, cell));
+            cell /= 2;
+        }
+        let mut values: Vec<f64> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                octaves
+                    .iter()
+                    .enumerate()
+                    .map(|(n, octave)| octave.at(x, y) * PERSISTENCE.powi(n as i32))
+                    .sum()
+            })
+            .collect();
+        let lowest = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let highest = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let range = (highest - lowest).max(f64::EPSILON);
+        for value in &mut values {
+            *value = (*value - lowest) / range;
+        }
+        Self {
+            values,
+            columns: width,
+            cell: 1,
+        }
+    }
+    fn at(&self, x: usize, y: usize) -> f64 {
+        let (column, row) = (x / self.cell, y / self.cell);
+        let value = |column: usize, row: usize| self.values[row * self.columns + column];
+        if self.cell == 1 {
+            return value(column, row);
+        }
+        let smooth = |t: f64| t * t * (3.0 - 2.0 * t);
+        let tx = smooth((x % self.cell) as f64 / self.cell as f64);
+        let ty = smooth((y % self.cell) as f64 / self.cell as f64);
+        let top = value(column, row) * (1.0 - tx) + value(column + 1, row) * tx;
+        let bottom = value(column, row + 1) * (1.0 - tx) + value(column + 1, row + 1) * tx;
+        top * (1.0 - ty) + bottom * ty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::EncodeOptions;
+    use crate::lsb::{self, Strategy};
+
+    /// Mean absolute difference between horizontally adjacent samples.
+    fn roughness(image: &ImageData) -> f64 {
+        let mut total = 0;
+        let mut count = 0;
+        for row in image.rows() {
+            for pair in row.windows(4) {
+                total += (pair[0] as i32 - pair[3] as i32).abs();
+                count += 1;
+            }
+        }
+        total as f64 / count as f64
+    }
+
+    #[test]
+    fn test_size() {
+        let size = Size::from_str("640x480").unwrap();
+        assert_eq!((size.width, size.height), (640, 480));
+        assert_eq!(size.to_string(), "640x480");
+        for bad in ["640", "0x480", "640x", "x", "99999x1", "axb"] {
+            assert!(Size::from_str(bad).is_err(), "{}", bad);
+        }
+        let size = Size::for_capacity(10_000, 2);
+        let png = generate(size, Style::Noise, 1)
+            .to_png(&EncodeOptions::default())
+            .unwrap();
+        let capacity = lsb::capacity(&png, 2, Strategy::Color).unwrap();
+        assert!(capacity >= 10_000, "{}", capacity);
+        assert!(capacity < 10_200, "{}", capacity);
+    }
+
+    #[test]
+    fn test_styles() {
+        let size = Size {
+            width: 120,
+            height: 80,
+        };
+        for style in [Style::Noise, Style::Gradient, Style::PhotoLike] {
+            assert_eq!(Style::from_str(&style.to_string()).unwrap(), style);
+            let image = generate(size, style, 7);
+            assert_eq!(image.as_bytes().len(), 120 * 80 * 3);
+            assert_eq!(image, generate(size, style, 7));
+            assert_ne!(image, generate(size, style, 8));
+        }
+        assert!(roughness(&generate(size, Style::Noise, 7)) > 60.0);
+        assert!(roughness(&generate(size, Style::Gradient, 7)) < 5.0);
+        let photo = roughness(&generate(size, Style::PhotoLike, 7));
+        assert!((2.0..20.0).contains(&photo), "{}", photo);
+    }
+}
@@ -0,0 +1,311 @@
+//! GIF files as a [`Container`]: payloads go in application extensions.
+//!
+//! An application extension names its application with 8 bytes and an
+//! authentication code with 3, then carries data in sub-blocks of at most
+//! 255 bytes. Payloads use `PNGM` and the tag as the name and `1.0` as the
+//! code:
+//!
+//! ```text
+//! 21 FF 0B | "PNGM" tag "1.0" | len data... | ... | 00
+//! ```
+//!
+//! Every other block is kept byte for byte.
+
+use thiserror::Error;
+
+use crate::chunk_type::ChunkType;
+use crate::container::{Block, Container, ContainerError};
+
+#[derive(Debug, Error)]
+pub enum GifError {
+    #[error("Invalid GIF header")]
+    InvalidHeader,
+    #[error("Truncated GIF file: a block at offset {0} runs past the end")]
+    Truncated(usize),
+    #[error("Unknown GIF block 0x{byte:02x} at offset {offset}")]
+    UnknownBlock { offset: usize, byte: u8 },
+}
+
+const EXTENSION: u8 = 0x21;
+const IMAGE: u8 = 0x2c;
+const TRAILER: u8 = 0x3b;
+const APPLICATION: u8 = 0xff;
+const PAYLOAD_NAME: &[u8; 4] = b"PNGM";
+const PAYLOAD_CODE: &[u8; 3] = b"1.0";
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    /// A block other than a payload, as it was read.
+    Raw {
+        kind: String,
+        bytes: Vec<u8>,
+    },
+    Payload {
+        tag: ChunkType,
+        data: Vec<u8>,
+    },
+}
+
+/// A GIF file: its header, logical screen descriptor and global color
+/// table, then its blocks up to the trailer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gif {
+    header: Vec<u8>,
+    parts: Vec<Part>,
+    /// Bytes after the trailer.
+    rest: Vec<u8>,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize, start: usize) -> Result<&'a [u8], GifError> {
+        let taken = self
+            .bytes
+            .get(self.offset..self.offset + len)
+            .ok_or(GifError::Truncated(start))?;
+        self.offset += len;
+        Ok(taken)
+    }
+    fn byte(&mut self, start: usize) -> Result<u8, GifError> {
+        Ok(self.take(1, start)?[0])
+    }
+    /// Sub-blocks up to and including the terminating empty one.
+    fn sub_blocks(&mut self, start: usize) -> Result<Vec<&'a [u8]>, GifError> {
+        let mut blocks = Vec::new();
+        loop {
+            let len = self.byte(start)?;
+            if len == 0 {
+                return Ok(blocks);
+            }
+            blocks.push(self.take(len.into(), start)?);
+        }
+    }
+}
+
+/// Bytes in the color table a packed field announces.
+fn color_table_len(packed: u8) -> usize {
+    if packed & 0x80 == 0 {
+        return 0;
+    }
+    3 << ((packed & 7) + 1)
+}
+
+fn extension_kind(label: u8, first: Option<&[u8]>) -> String {
+    match label {
+        0xf9 => "graphic control".to_string(),
+        0xfe => "comment".to_string(),
+        0x01 => "plain text".to_string(),
+        APPLICATION => match first {
+            Some(name) => format!("application {}", String::from_utf8_lossy(name)),
+            None => "application".to_string(),
+        },
+        _ => format!("extension 0x{:02x}", label),
+    }
+}
+
+/// The tag of an application extension that holds a payload.
+fn payload_tag(first: &[u8]) -> Option<ChunkType> {
+    if first.len() != 11 || &first[..4] != PAYLOAD_NAME || &first[8..] != PAYLOAD_CODE {
+        return None;
+    }
+    let tag: [u8; 4] = first[4..8].try_into().expect("4 bytes");
+    ChunkType::try_from(tag).ok()
+}
+
+impl TryFrom<&[u8]> for Gif {
+    type Error = GifError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if !bytes.starts_with(b"GIF87a") && !bytes.starts_with(b"GIF89a") {
+            return Err(GifError::InvalidHeader);
+        }
+        let mut reader = Reader { bytes, offset: 6 };
+        let screen = reader.take(7, 6)?;
+        reader.take(color_table_len(screen[4]), 6)?;
+        let header = bytes[..reader.offset].to_vec();
+        let mut parts = Vec::new();
+        loop {
+            let start = reader.offset;
+            let part = match reader.byte(start)? {
+                EXTENSION => {
+                    let label = reader.byte(start)?;
+                    let blocks = reader.sub_blocks(start)?;
+                    let first = blocks.first().copied();
+                    match first.filter(|_| label == APPLICATION).and_then(payload_tag) {
+                        Some(tag) => Part::Payload {
+                            tag,
+                            data: blocks[1..].concat(),
+                        },
+                        None => Part::Raw {
+                            kind: extension_kind(label, first),
+                            bytes: bytes[start..reader.offset].to_vec(),
+                        },
+                    }
+                }
+                IMAGE => {
+                    let descriptor = reader.take(9, start)?;
+                    reader.take(color_table_len(descriptor[8]), start)?;
+                    // LZW minimum code size, then the image data.
+                    reader.byte(start)?;
+                    reader.sub_blocks(start)?;
+                    let width = u16::from_le_bytes([descriptor[4], descriptor[5]]);
+                    let height = u16::from_le_bytes([descriptor[6], descriptor[7]]);
+                    Part::Raw {
+                        kind: format!("image {}x{}", width, height),
+                        bytes: bytes[start..reader.offset].to_vec(),
+                    }
+                }
+                TRAILER => break,
+                byte => {
+                    return Err(GifError::UnknownBlock {
+                        offset: start,
+                        byte,
+                    })
+                }
+            };
+            parts.push(part);
+        }
+        Ok(Self {
+            header,
+            parts,
+            rest: bytes[reader.offset..].to_vec(),
+        })
+    }
+}
+
+impl Container for Gif {
+    fn blocks(&self) -> Vec<Block> {
+        self.parts
+            .iter()
+            .map(|part| match part {
+                Part::Raw { kind, bytes } => Block {
+                    kind: kind.clone(),
+                    len: bytes.len(),
+                },
+                Part::Payload { tag, data } => Block {
+                    kind: format!("application PNGM{}", tag),
+                    len: data.len(),
+                },
+            })
+            .collect()
+    }
+    fn payload(&self, tag: &ChunkType) -> Option<&[u8]> {
+        self.parts.iter().find_map(|part| match part {
+            Part::Payload { tag: t, data } if t == tag => Some(data.as_slice()),
+            _ => None,
+        })
+    }
+    fn insert_payload(&mut self, tag: ChunkType, data: &[u8]) -> Result<(), ContainerError> {
+        // Extensions came with GIF89a.
+        self.header[..6].copy_from_slice(b"GIF89a");
+        self.parts.push(Part::Payload {
+            tag,
+            data: data.to_vec(),
+        });
+        Ok(())
+    }
+    fn remove_payload(&mut self, tag: &ChunkType) -> Result<(), ContainerError> {
+        let index = self
+            .parts
+            .iter()
+            .position(|part| matches!(part, Part::Payload { tag: t, .. } if t == tag))
+            .ok_or_else(|| ContainerError::NotFound(tag.to_string()))?;
+        self.parts.remove(index);
+        Ok(())
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.clone();
+        for part in &self.parts {
+            match part {
+                Part::Raw { bytes: raw, .. } => bytes.extend_from_slice(raw),
+                Part::Payload { tag, data } => {
+                    bytes.extend_from_slice(&[EXTENSION, APPLICATION, 11]);
+                    bytes.extend_from_slice(PAYLOAD_NAME);
+                    bytes.extend_from_slice(&tag.bytes());
+                    bytes.extend_from_slice(PAYLOAD_CODE);
+                    for block in data.chunks(255) {
+                        bytes.push(block.len() as u8);
+                        bytes.extend_from_slice(block);
+                    }
+                    bytes.push(0);
+                }
+            }
+        }
+        bytes.push(TRAILER);
+        bytes.extend_from_slice(&self.rest);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// A 1x1 GIF89a with a global color table, a looping NETSCAPE2.0
+    /// extension, a graphic control extension and one image.
+    const TESTING_GIF: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\x00\x00\x00\xff\xff\xff\
+        \x21\xff\x0bNETSCAPE2.0\x03\x01\x00\x00\x00\
+        \x21\xf9\x04\x01\x00\x00\x00\x00\
+        \x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x44\x01\x00\
+        \x3b";
+
+    #[test]
+    fn test_round_trip() {
+        let gif = Gif::try_from(TESTING_GIF).unwrap();
+        assert_eq!(gif.to_bytes(), TESTING_GIF);
+        let kinds: Vec<_> = gif.blocks().into_iter().map(|b| b.kind).collect();
+        assert_eq!(
+            kinds,
+            ["application NETSCAPE2.0", "graphic control", "image 1x1"]
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        let tag = ChunkType::from_str("ruSt").unwrap();
+        let data: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        let mut gif = Gif::try_from(TESTING_GIF).unwrap();
+        gif.insert_payload(tag.clone(), &data).unwrap();
+        let bytes = gif.to_bytes();
+
+        let mut read = Gif::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(read.payload(&tag), Some(data.as_slice()));
+        assert!(read
+            .payload(&ChunkType::from_str("teSt").unwrap())
+            .is_none());
+        assert_eq!(read.blocks()[3].kind, "application PNGMruSt");
+        read.remove_payload(&tag).unwrap();
+        assert!(matches!(
+            read.remove_payload(&tag),
+            Err(ContainerError::NotFound(_))
+        ));
+        assert_eq!(read.to_bytes(), TESTING_GIF);
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(matches!(
+            Gif::try_from(&b"\x89PNG\r\n\x1a\n"[..]),
+            Err(GifError::InvalidHeader)
+        ));
+        let truncated = &TESTING_GIF[..TESTING_GIF.len() - 4];
+        assert!(matches!(
+            Gif::try_from(truncated),
+            Err(GifError::Truncated(_))
+        ));
+        let mut unknown = TESTING_GIF.to_vec();
+        unknown[19] = 0x42;
+        assert!(matches!(
+            Gif::try_from(unknown.as_slice()),
+            Err(GifError::UnknownBlock {
+                offset: 19,
+                byte: 0x42
+            })
+        ));
+    }
+}
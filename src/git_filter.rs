@@ -0,0 +1,235 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::png::Png;
+
+#[derive(Debug, Error)]
+pub enum GitFilterError {
+    #[error(
+        "Invalid text chunk {0:?}: expected KEYWORD or KEYWORD=VALUE, with a keyword of 1 to 79 \
+         Latin-1 characters and a Latin-1 value"
+    )]
+    InvalidText(String),
+    #[error("--text {0} needs a value (KEYWORD=VALUE) on smudge")]
+    MissingValue(String),
+}
+
+/// Text chunk keywords whose values change every time an image is saved.
+const VOLATILE_KEYWORDS: [&[u8]; 5] = [
+    b"Creation Time",
+    b"date:create",
+    b"date:modify",
+    b"date:timestamp",
+    b"Modification Time",
+];
+
+/// A `tEXt` chunk from the command line, `KEYWORD` or `KEYWORD=VALUE`, in
+/// Latin-1 as the PNG specification wants.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Text {
+    keyword: Vec<u8>,
+    value: Option<Vec<u8>>,
+}
+
+impl Text {
+    /// The chunk to add on smudge.
+    fn chunk(&self) -> Result<Chunk, GitFilterError> {
+        let value = self
+            .value
+            .as_ref()
+            .ok_or_else(|| GitFilterError::MissingValue(latin1_string(&self.keyword)))?;
+        let data = [self.keyword.as_slice(), &[0], value].concat();
+        Ok(Chunk::new(text_type(), &data))
+    }
+}
+
+impl FromStr for Text {
+    type Err = GitFilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || GitFilterError::InvalidText(s.to_string());
+        let (keyword, value) = match s.split_once('=') {
+            Some((keyword, value)) => (keyword, Some(value)),
+            None => (s, None),
+        };
+        let keyword = latin1(keyword).ok_or_else(invalid)?;
+        let printable = keyword.iter().all(|&b| matches!(b, 32..=126 | 161..=255));
+        if keyword.is_empty()
+            || keyword.len() > 79
+            || !printable
+            || keyword.starts_with(b" ")
+            || keyword.ends_with(b" ")
+        {
+            return Err(invalid());
+        }
+        let value = value
+            .map(|value| latin1(value).ok_or_else(invalid))
+            .transpose()?;
+        Ok(Self { keyword, value })
+    }
+}
+
+impl Display for Text {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", latin1_string(&self.keyword))?;
+        if let Some(value) = &self.value {
+            write!(f, "={}", latin1_string(value))?;
+        }
+        Ok(())
+    }
+}
+
+fn latin1(s: &str) -> Option<Vec<u8>> {
+    s.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+fn latin1_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+fn text_type() -> ChunkType {
+    ChunkType::try_from(*b"tEXt").expect("tEXt is a valid chunk type")
+}
+
+/// The keyword of a `tEXt`, `zTXt` or `iTXt` chunk, which all start with
+/// it and a NUL byte.
+fn keyword(chunk: &Chunk) -> Option<&[u8]> {
+    match &chunk.chunk_type().bytes() {
+        b"tEXt" | b"zTXt" | b"iTXt" => chunk.data().split(|&b| b == 0).next(),
+        _ => None,
+    }
+}
+
+/// What the repository stores: `png` without `tIME` chunks, text chunks
+/// with volatile dates or one of the keywords of `texts`, or chunks of the
+/// `strip` types. Gives how many chunks it removed.
+pub fn clean(png: &mut Png, texts: &[Text], strip: &[ChunkType]) -> usize {
+    let removable = |chunk: &Chunk| {
+        let chunk_type = chunk.chunk_type();
+        if chunk_type.bytes() == *b"tIME" || strip.contains(chunk_type) {
+            return true;
+        }
+        keyword(chunk).is_some_and(|keyword| {
+            VOLATILE_KEYWORDS.contains(&keyword) || texts.iter().any(|text| text.keyword == keyword)
+        })
+    };
+    let mut removed = 0;
+    // Backwards, so indices stay valid.
+    for index in (0..png.chunks().len()).rev() {
+        if removable(&png.chunks()[index]) {
+            png.remove_chunk_at(index);
+            removed += 1;
+        }
+    }
+    removed
+}
+
+/// What the working tree gets: `png` with the chunks of `texts` before
+/// `IEND`, replacing any text chunks with the same keywords.
+pub fn smudge(png: &mut Png, texts: &[Text]) -> Result<(), GitFilterError> {
+    let chunks = texts
+        .iter()
+        .map(Text::chunk)
+        .collect::<Result<Vec<_>, _>>()?;
+    clean_texts(png, texts);
+    for chunk in chunks {
+        png.append_chunk(chunk);
+    }
+    Ok(())
+}
+
+fn clean_texts(png: &mut Png, texts: &[Text]) {
+    for index in (0..png.chunks().len()).rev() {
+        let chunk = &png.chunks()[index];
+        if keyword(chunk).is_some_and(|keyword| texts.iter().any(|text| text.keyword == keyword)) {
+            png.remove_chunk_at(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tIME", &[7, 234, 10, 14, 12, 0, 0]),
+            chunk("tEXt", b"date:create\x002026-10-14T12:00:00"),
+            chunk("tEXt", b"Title\0Logo"),
+            chunk("iTXt", b"Revision\0\0\0\0\0abc123"),
+            chunk("IDAT", &[1; 30]),
+            chunk("eXIf", b"MM\0*"),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    #[test]
+    fn test_text() {
+        let text = Text::from_str("Revision=abc123").unwrap();
+        assert_eq!(text.to_string(), "Revision=abc123");
+        assert_eq!(
+            Text::from_str("Author=Zoë").unwrap().to_string(),
+            "Author=Zoë"
+        );
+        assert_eq!(Text::from_str("Revision").unwrap().value, None);
+        assert!(Text::from_str("=abc").is_err());
+        assert!(Text::from_str(" Revision=abc").is_err());
+        assert!(Text::from_str(&"k".repeat(80)).is_err());
+        assert!(Text::from_str("Title=日本").is_err());
+    }
+
+    #[test]
+    fn test_clean() {
+        let mut png = testing_png();
+        let texts = [Text::from_str("Revision").unwrap()];
+        let strip = [ChunkType::from_str("eXIf").unwrap()];
+        assert_eq!(clean(&mut png, &texts, &strip), 4);
+        assert_eq!(types(&png), ["IHDR", "tEXt", "IDAT", "IEND"]);
+        assert_eq!(png.chunks()[1].data(), b"Title\0Logo");
+        assert_eq!(clean(&mut png, &texts, &strip), 0);
+    }
+
+    #[test]
+    fn test_smudge_then_clean() {
+        let mut png = testing_png();
+        let texts = [Text::from_str("Revision=def456").unwrap()];
+        smudge(&mut png, &texts).unwrap();
+        let revisions: Vec<_> = png
+            .chunks()
+            .iter()
+            .filter(|c| keyword(c) == Some(b"Revision"))
+            .collect();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].data(), b"Revision\0def456");
+        assert_eq!(
+            png.chunks()[png.chunks().len() - 2].data(),
+            b"Revision\0def456"
+        );
+
+        let mut cleaned = testing_png();
+        clean(&mut cleaned, &texts, &[]);
+        clean(&mut png, &texts, &[]);
+        assert_eq!(png.as_bytes(), cleaned.as_bytes());
+
+        let missing = [Text::from_str("Revision").unwrap()];
+        assert!(matches!(
+            smudge(&mut png, &missing),
+            Err(GitFilterError::MissingValue(_))
+        ));
+    }
+}
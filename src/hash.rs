@@ -0,0 +1,84 @@
+use sha2::{Digest, Sha256};
+
+use crate::png::Png;
+
+/// Chunks that define the decoded image. Everything else is metadata.
+pub const IMAGE_CHUNK_TYPES: [&str; 4] = ["IHDR", "PLTE", "IDAT", "tRNS"];
+
+/// SHA-256 over the image-defining chunks only, so files that differ just in
+/// metadata or embedded messages hash the same.
+///
+/// Consecutive `IDAT` chunks are hashed as one stream, so re-splitting the
+/// image data doesn't change the digest either.
+pub fn content_hash(png: &Png) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for chunk_type in IMAGE_CHUNK_TYPES {
+        let data: Vec<u8> = png
+            .chunks_by_type(chunk_type)
+            .flat_map(|c| c.data().iter().copied())
+            .collect();
+        hasher.update(chunk_type.as_bytes());
+        hasher.update((data.len() as u64).to_be_bytes());
+        hasher.update(&data);
+    }
+    hasher.finalize().into()
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]),
+            chunk("IDAT", b"pixels"),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    #[test]
+    fn test_metadata_does_not_change_hash() {
+        let mut png = testing_png();
+        let expected = content_hash(&png);
+        png.append_chunk(chunk("tEXt", b"Comment\0hello"));
+        png.append_chunk(chunk("ruSt", b"secret"));
+        assert_eq!(content_hash(&png), expected);
+    }
+
+    #[test]
+    fn test_idat_split_does_not_change_hash() {
+        let split = Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]),
+            chunk("IDAT", b"pix"),
+            chunk("IDAT", b"els"),
+            chunk("IEND", &[]),
+        ]);
+        assert_eq!(content_hash(&split), content_hash(&testing_png()));
+    }
+
+    #[test]
+    fn test_pixels_change_hash() {
+        let other = Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]),
+            chunk("IDAT", b"pixelz"),
+            chunk("IEND", &[]),
+        ]);
+        assert_ne!(content_hash(&other), content_hash(&testing_png()));
+    }
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[0, 15, 255]), "000fff");
+    }
+}
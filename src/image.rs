@@ -0,0 +1,1256 @@
+//! Decoded image data: the `IDAT` stream inflated and unfiltered into raw
+//! scanlines, for everything that works on pixels rather than chunks.
+
+use flate2::write::ZlibEncoder;
+use flate2::{Compress, Decompress, FlushCompress, FlushDecompress, Status};
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::cgbi;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::compression::{self, Algorithm};
+use crate::limits::{self, Limit, LimitExceeded, Limits};
+use crate::parallel;
+use crate::png::Png;
+use crate::trace;
+
+#[derive(Debug, Error)]
+pub enum ImageError {
+    #[error("Missing IHDR chunk")]
+    MissingHeader,
+    #[error("Invalid IHDR chunk: {0}")]
+    BadHeader(&'static str),
+    #[error("Image is too large")]
+    TooLarge,
+    #[error("Failed to inflate image data: {0}")]
+    Inflate(io::Error),
+    #[error("Failed to deflate image data: {0}")]
+    Deflate(io::Error),
+    #[error("Invalid compression level {0} (expected 0-9)")]
+    InvalidLevel(u32),
+    #[error("Unknown filter {0:?} (expected none, sub, up, average, paeth or adaptive)")]
+    UnknownFilterStrategy(String),
+    #[error("Image data is {actual} bytes, expected {expected}")]
+    WrongLength { expected: usize, actual: usize },
+    #[error("Unknown filter type {filter} on row {row}")]
+    UnknownFilter { filter: u8, row: u32 },
+    #[error("Image data is in Apple's CgBI format")]
+    AppleCgbi,
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitExceeded),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    pub fn id(self) -> u8 {
+        match self {
+            Self::Grayscale => 0,
+            Self::Rgb => 2,
+            Self::Indexed => 3,
+            Self::GrayscaleAlpha => 4,
+            Self::Rgba => 6,
+        }
+    }
+    pub fn from_id(id: u8) -> Result<Self, ImageError> {
+        match id {
+            0 => Ok(Self::Grayscale),
+            2 => Ok(Self::Rgb),
+            3 => Ok(Self::Indexed),
+            4 => Ok(Self::GrayscaleAlpha),
+            6 => Ok(Self::Rgba),
+            _ => Err(ImageError::BadHeader("color type")),
+        }
+    }
+    /// Samples per pixel.
+    pub fn channels(self) -> usize {
+        match self {
+            Self::Grayscale | Self::Indexed => 1,
+            Self::GrayscaleAlpha => 2,
+            Self::Rgb => 3,
+            Self::Rgba => 4,
+        }
+    }
+    pub(crate) fn allows_bit_depth(self, bit_depth: u8) -> bool {
+        match self {
+            Self::Grayscale => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+            Self::Indexed => matches!(bit_depth, 1 | 2 | 4 | 8),
+            Self::Rgb | Self::GrayscaleAlpha | Self::Rgba => matches!(bit_depth, 8 | 16),
+        }
+    }
+}
+
+/// The fields of `IHDR` that describe the pixel layout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    pub interlaced: bool,
+}
+
+impl Header {
+    pub fn from_png(png: &Png) -> Result<Self, ImageError> {
+        let chunk = png.chunk_by_type("IHDR").ok_or(ImageError::MissingHeader)?;
+        Self::try_from(chunk.data())
+    }
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+    /// Bytes per scanline, without the filter type byte.
+    pub fn row_len(&self) -> usize {
+        self.pass_row_len(self.width)
+    }
+    fn pass_row_len(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+    /// Distance to the corresponding byte of the previous pixel, as used by
+    /// the filters (1 for pixels smaller than a byte).
+    pub fn filter_stride(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+    /// The sub-images the image data is stored as: the whole image, or the
+    /// seven Adam7 passes without the empty ones, which have no scanlines.
+    fn passes(&self) -> Vec<Pass> {
+        let adam7: &[(u32, u32, u32, u32)] = if self.interlaced {
+            &ADAM7
+        } else {
+            &[(0, 0, 1, 1)]
+        };
+        adam7
+            .iter()
+            .map(|&(x, y, dx, dy)| Pass {
+                x,
+                y,
+                dx,
+                dy,
+                width: (self.width + dx - 1 - x) / dx,
+                height: (self.height + dy - 1 - y) / dy,
+            })
+            .filter(|pass| pass.width > 0 && pass.height > 0)
+            .collect()
+    }
+    /// Length of the filtered image data.
+    pub(crate) fn stream_len(&self) -> Option<usize> {
+        self.passes().iter().try_fold(0usize, |len, pass| {
+            (self.pass_row_len(pass.width) + 1)
+                .checked_mul(pass.height as usize)?
+                .checked_add(len)
+        })
+    }
+    /// The length of each scanline of the filtered image data, its filter
+    /// type byte included, in the order they are stored.
+    pub(crate) fn scanline_lens(&self) -> impl Iterator<Item = usize> {
+        let header = *self;
+        self.passes().into_iter().flat_map(move |pass| {
+            std::iter::repeat_n(header.pass_row_len(pass.width) + 1, pass.height as usize)
+        })
+    }
+    /// `IHDR` chunk data.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.width.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&self.height.to_be_bytes());
+        bytes.extend_from_slice(&[
+            self.bit_depth,
+            self.color_type.id(),
+            0,
+            0,
+            self.interlaced as u8,
+        ]);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Header {
+    type Error = ImageError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let value: [u8; 13] = value
+            .try_into()
+            .map_err(|_| ImageError::BadHeader("length"))?;
+        let width = u32::from_be_bytes(value[0..4].try_into().expect("4 bytes"));
+        let height = u32::from_be_bytes(value[4..8].try_into().expect("4 bytes"));
+        if width == 0 || height == 0 {
+            return Err(ImageError::BadHeader("dimensions"));
+        }
+        let color_type = ColorType::from_id(value[9])?;
+        let bit_depth = value[8];
+        if !color_type.allows_bit_depth(bit_depth) {
+            return Err(ImageError::BadHeader("bit depth"));
+        }
+        if value[10] != 0 {
+            return Err(ImageError::BadHeader("compression method"));
+        }
+        if value[11] != 0 {
+            return Err(ImageError::BadHeader("filter method"));
+        }
+        let interlaced = match value[12] {
+            0 => false,
+            1 => true,
+            _ => return Err(ImageError::BadHeader("interlace method")),
+        };
+        Ok(Self {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced,
+        })
+    }
+}
+
+/// Adam7 passes as (first column, first row, column step, row step).
+const ADAM7: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// The pixels of an image at columns `x + i * dx` and rows `y + j * dy`.
+struct Pass {
+    x: u32,
+    y: u32,
+    dx: u32,
+    dy: u32,
+    width: u32,
+    height: u32,
+}
+
+/// A scanline filter: each byte is stored as its difference from a value
+/// predicted from its neighbours to the left and above.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+}
+
+impl Filter {
+    pub const ALL: [Self; 5] = [Self::None, Self::Sub, Self::Up, Self::Average, Self::Paeth];
+
+    /// The filter type byte that starts each row.
+    pub fn id(self) -> u8 {
+        self as u8
+    }
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.get(id as usize).copied()
+    }
+    /// The prediction for byte `x` of `row`, from the unfiltered bytes
+    /// before it and from the unfiltered `previous` row.
+    fn predict(self, row: &[u8], previous: Option<&[u8]>, x: usize, stride: usize) -> u8 {
+        let a = if x >= stride { row[x - stride] } else { 0 };
+        let b = previous.map_or(0, |p| p[x]);
+        let c = match previous {
+            Some(p) if x >= stride => p[x - stride],
+            _ => 0,
+        };
+        match self {
+            Self::None => 0,
+            Self::Sub => a,
+            Self::Up => b,
+            Self::Average => ((a as u16 + b as u16) / 2) as u8,
+            Self::Paeth => paeth(a, b, c),
+        }
+    }
+}
+
+/// How [`ImageData::encode`] chooses the filter of each row.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FilterStrategy {
+    /// The same filter on every row.
+    Fixed(Filter),
+    /// Per row, the filter whose output has the smallest sum of absolute
+    /// values as signed bytes, the heuristic libpng uses.
+    #[default]
+    Adaptive,
+}
+
+impl FromStr for FilterStrategy {
+    type Err = ImageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let filter = match s {
+            "none" => Filter::None,
+            "sub" => Filter::Sub,
+            "up" => Filter::Up,
+            "average" => Filter::Average,
+            "paeth" => Filter::Paeth,
+            "adaptive" => return Ok(Self::Adaptive),
+            _ => return Err(ImageError::UnknownFilterStrategy(s.to_string())),
+        };
+        Ok(Self::Fixed(filter))
+    }
+}
+
+/// Settings for writing image data back into a PNG.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EncodeOptions {
+    pub filter: FilterStrategy,
+    /// zlib compression level, 0-9.
+    pub level: u32,
+    /// Write the image Adam7-interlaced or not; `None` keeps its header's
+    /// choice.
+    pub interlace: Option<bool>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            filter: FilterStrategy::Adaptive,
+            level: 6,
+            interlace: None,
+        }
+    }
+}
+
+/// Scratch space for [`ImageData::decode`], which [`crate::batch::Parser`]
+/// keeps from one image to the next.
+pub(crate) struct DecodeBuffers {
+    /// The `IDAT` chunks, joined.
+    stream: Vec<u8>,
+    /// The inflated stream: filtered scanlines.
+    filtered: Vec<u8>,
+    inflate: Decompress,
+}
+
+impl DecodeBuffers {
+    pub(crate) fn new() -> Self {
+        Self {
+            stream: Vec::new(),
+            filtered: Vec::new(),
+            inflate: Decompress::new(true),
+        }
+    }
+}
+
+/// Inflates the zlib stream `input` onto `output`, stopping once `output`
+/// holds `limit` bytes or more. Uses the deflate codec if one is set.
+fn inflate_into(
+    inflate: &mut Decompress,
+    input: &[u8],
+    output: &mut Vec<u8>,
+    limit: usize,
+) -> io::Result<()> {
+    if compression::codec(Algorithm::Deflate).is_some() {
+        Algorithm::Deflate
+            .decompressor(input)?
+            .take(limit as u64)
+            .read_to_end(output)?;
+        return Ok(());
+    }
+    inflate.reset(true);
+    while output.len() < limit {
+        // Grow as data arrives rather than by what the header claims.
+        output.reserve((limit - output.len()).min(64 * 1024));
+        let (read, written) = (inflate.total_in(), output.len());
+        let status = inflate
+            .decompress_vec(&input[read as usize..], output, FlushDecompress::None)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if status == Status::StreamEnd {
+            break;
+        }
+        if inflate.total_in() == read && output.len() == written {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+    Ok(())
+}
+
+/// Unfiltered scanlines of an image, `row_len` bytes each, samples packed
+/// as in the PNG stream (big-endian, several pixels per byte below 8 bits).
+/// Interlaced images are held deinterlaced, so pixels are always in place.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImageData {
+    header: Header,
+    pixels: Vec<u8>,
+}
+
+impl ImageData {
+    /// Largest `IDAT` chunk written by [`ImageData::write_to`], as libpng.
+    pub const IDAT_LEN: usize = 8192;
+
+    pub fn new(header: Header, pixels: Vec<u8>) -> Result<Self, ImageError> {
+        // As in `IHDR`, which couldn't hold an empty image.
+        if header.width == 0 || header.height == 0 {
+            return Err(ImageError::BadHeader("dimensions"));
+        }
+        let expected = header
+            .row_len()
+            .checked_mul(header.height as usize)
+            .ok_or(ImageError::TooLarge)?;
+        if pixels.len() != expected {
+            return Err(ImageError::WrongLength {
+                expected,
+                actual: pixels.len(),
+            });
+        }
+        Ok(Self { header, pixels })
+    }
+    /// Inflates the concatenated `IDAT` chunks of `png`, reverses the
+    /// per-row filters and deinterlaces the passes of interlaced images.
+    pub fn decode(png: &Png) -> Result<Self, ImageError> {
+        Self::decode_with(png, &mut DecodeBuffers::new(), &limits::get())
+    }
+    /// Like [`ImageData::decode`], with scratch space from an earlier image
+    /// and under `limits`.
+    pub(crate) fn decode_with(
+        png: &Png,
+        buffers: &mut DecodeBuffers,
+        limits: &Limits,
+    ) -> Result<Self, ImageError> {
+        trace::span!("image.decode", chunks = png.chunks().len());
+        let image = trace::failed(Self::read(png, buffers, limits))?;
+        trace::event!(
+            debug,
+            width = image.header.width,
+            height = image.header.height,
+            color_type = ?image.header.color_type,
+            bit_depth = image.header.bit_depth,
+            len = image.pixels.len(),
+            "decoded"
+        );
+        Ok(image)
+    }
+    fn read(png: &Png, buffers: &mut DecodeBuffers, limits: &Limits) -> Result<Self, ImageError> {
+        if cgbi::is_cgbi(png) {
+            return Err(ImageError::AppleCgbi);
+        }
+        let header = Header::from_png(png)?;
+        let DecodeBuffers {
+            stream,
+            filtered,
+            inflate,
+        } = buffers;
+        stream.clear();
+        for chunk in png.chunks() {
+            if chunk.chunk_type().bytes() == *b"IDAT" {
+                stream.extend_from_slice(chunk.data());
+            }
+        }
+        let expected = header.stream_len().ok_or(ImageError::TooLarge)?;
+        limits.check(Limit::DecompressedLen, expected)?;
+        // Never inflate more than the header allows, so a small file can't
+        // expand without bound.
+        filtered.clear();
+        inflate_into(inflate, stream, filtered, expected + 1).map_err(ImageError::Inflate)?;
+        if filtered.len() != expected {
+            return Err(ImageError::WrongLength {
+                expected,
+                actual: filtered.len(),
+            });
+        }
+        Self::from_filtered(header, filtered)
+    }
+    /// Reverses the filters of the inflated image data `filtered`, which
+    /// holds as many bytes as `header` calls for.
+    pub(crate) fn from_filtered(header: Header, filtered: &[u8]) -> Result<Self, ImageError> {
+        // Adam7 passes are filtered independently of each other, so they can
+        // be unfiltered at the same time (the rows within one can't: each is
+        // predicted from the unfiltered row above).
+        let stride = header.filter_stride();
+        let mut passes = Vec::new();
+        let mut rest = filtered;
+        for pass in header.passes() {
+            let pass_row_len = header.pass_row_len(pass.width);
+            let (lines, next) = rest.split_at((pass_row_len + 1) * pass.height as usize);
+            rest = next;
+            passes.push((pass, pass_row_len, lines));
+        }
+        let subs = parallel::map(&passes, |(_, pass_row_len, lines)| {
+            unfilter(lines, *pass_row_len, stride)
+        });
+        if !header.interlaced {
+            let pixels = subs.into_iter().next().expect("one pass")?;
+            return Ok(Self { header, pixels });
+        }
+        let row_len = header.row_len();
+        let len = row_len
+            .checked_mul(header.height as usize)
+            .ok_or(ImageError::TooLarge)?;
+        let mut pixels = vec![0u8; len];
+        for ((pass, pass_row_len, _), sub) in passes.iter().zip(subs) {
+            for (j, line) in sub?.chunks(*pass_row_len).enumerate() {
+                let y = (pass.y + j as u32 * pass.dy) as usize;
+                let row = &mut pixels[y * row_len..][..row_len];
+                for i in 0..pass.width {
+                    let x = pass.x + i * pass.dx;
+                    copy_pixel(line, i as usize, row, x as usize, header.bits_per_pixel());
+                }
+            }
+        }
+        Ok(Self { header, pixels })
+    }
+    /// Filters and deflates the scanlines into a zlib stream for `IDAT`,
+    /// split into Adam7 passes when interlacing.
+    pub fn encode(&self, options: &EncodeOptions) -> Result<Vec<u8>, ImageError> {
+        trace::span!(
+            "image.encode",
+            width = self.header.width,
+            height = self.header.height,
+            filter = ?options.filter,
+            level = options.level,
+        );
+        let stream = trace::failed(self.filter_and_deflate(options))?;
+        trace::event!(debug, out_len = stream.len(), "encoded");
+        Ok(stream)
+    }
+    fn filter_and_deflate(&self, options: &EncodeOptions) -> Result<Vec<u8>, ImageError> {
+        if options.level > 9 {
+            return Err(ImageError::InvalidLevel(options.level));
+        }
+        let header = self.encoded_header(options);
+        let stride = header.filter_stride();
+        let mut filtered = Vec::with_capacity(header.stream_len().unwrap_or(0));
+        if !header.interlaced {
+            filter(
+                &self.pixels,
+                header.row_len(),
+                stride,
+                options.filter,
+                &mut filtered,
+            );
+        }
+        for pass in header.passes().iter().filter(|_| header.interlaced) {
+            let pass_row_len = header.pass_row_len(pass.width);
+            let mut sub = vec![0u8; pass_row_len * pass.height as usize];
+            for (j, line) in sub.chunks_mut(pass_row_len).enumerate() {
+                let row = self.row(pass.y + j as u32 * pass.dy);
+                for i in 0..pass.width {
+                    let x = pass.x + i * pass.dx;
+                    copy_pixel(row, x as usize, line, i as usize, header.bits_per_pixel());
+                }
+            }
+            filter(&sub, pass_row_len, stride, options.filter, &mut filtered);
+        }
+        deflate(&filtered, options.level).map_err(ImageError::Deflate)
+    }
+    fn encoded_header(&self, options: &EncodeOptions) -> Header {
+        Header {
+            interlaced: options.interlace.unwrap_or(self.header.interlaced),
+            ..self.header
+        }
+    }
+    /// Stores the image in `png`: rewrites `IHDR` and replaces every `IDAT`
+    /// chunk with the newly encoded data, where the first one was. Drops
+    /// Apple's `iDOT`, whose offsets into the old data would be wrong.
+    pub fn write_to(&self, png: &mut Png, options: &EncodeOptions) -> Result<(), ImageError> {
+        let stream = self.encode(options)?;
+        let ihdr = png
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type().to_string() == "IHDR")
+            .ok_or(ImageError::MissingHeader)?;
+        png.replace_chunk_at(
+            ihdr,
+            chunk("IHDR", &self.encoded_header(options).as_bytes()),
+        );
+        while png.remove_chunk("iDOT").is_ok() {}
+        let mut position = None;
+        while let Some(i) = png
+            .chunks()
+            .iter()
+            .position(|c| c.chunk_type().to_string() == "IDAT")
+        {
+            position.get_or_insert(i);
+            png.remove_chunk_at(i);
+        }
+        let position = position.unwrap_or_else(|| {
+            png.chunks()
+                .iter()
+                .position(|c| c.chunk_type().to_string() == "IEND")
+                .unwrap_or(png.chunks().len())
+        });
+        for (i, data) in stream.chunks(Self::IDAT_LEN).enumerate() {
+            png.insert_chunk_at(position + i, chunk("IDAT", data));
+        }
+        Ok(())
+    }
+    /// A new PNG file holding just the image.
+    pub fn to_png(&self, options: &EncodeOptions) -> Result<Png, ImageError> {
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &self.header.as_bytes()),
+            chunk("IEND", &[]),
+        ]);
+        self.write_to(&mut png, options)?;
+        Ok(png)
+    }
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    /// Every scanline, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.pixels.chunks(self.header.row_len())
+    }
+    pub fn row(&self, y: u32) -> &[u8] {
+        let row_len = self.header.row_len();
+        &self.pixels[y as usize * row_len..][..row_len]
+    }
+    pub fn row_mut(&mut self, y: u32) -> &mut [u8] {
+        let row_len = self.header.row_len();
+        &mut self.pixels[y as usize * row_len..][..row_len]
+    }
+    /// All scanlines back to back.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.pixels
+    }
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.pixels
+    }
+    /// The samples of the pixel at (`x`, `y`), one per channel, as stored:
+    /// palette indices for indexed images, not scaled for low bit depths.
+    pub fn pixel(&self, x: u32, y: u32) -> Vec<u16> {
+        let row = self.row(y);
+        let depth = self.header.bit_depth as usize;
+        let channels = self.header.color_type.channels();
+        (0..channels)
+            .map(|channel| {
+                let bit = (x as usize * channels + channel) * depth;
+                match depth {
+                    16 => u16::from_be_bytes([row[bit / 8], row[bit / 8 + 1]]),
+                    8 => row[bit / 8] as u16,
+                    _ => {
+                        let shift = 8 - depth - bit % 8;
+                        ((row[bit / 8] >> shift) & ((1 << depth) - 1) as u8) as u16
+                    }
+                }
+            })
+            .collect()
+    }
+    /// Sets the samples of the pixel at (`x`, `y`), given as
+    /// [`ImageData::pixel`] returns them.
+    pub fn set_pixel(&mut self, x: u32, y: u32, samples: &[u16]) {
+        let depth = self.header.bit_depth as usize;
+        let channels = self.header.color_type.channels();
+        let row = self.row_mut(y);
+        for (channel, &sample) in samples.iter().enumerate().take(channels) {
+            let bit = (x as usize * channels + channel) * depth;
+            match depth {
+                16 => row[bit / 8..][..2].copy_from_slice(&sample.to_be_bytes()),
+                8 => row[bit / 8] = sample as u8,
+                _ => {
+                    let mask = ((1 << depth) - 1) as u8;
+                    let shift = 8 - depth - bit % 8;
+                    row[bit / 8] =
+                        (row[bit / 8] & !(mask << shift)) | ((sample as u8 & mask) << shift);
+                }
+            }
+        }
+    }
+}
+
+impl Png {
+    /// Whether `other` shows the same image: both are decoded and compared
+    /// pixel by pixel as 16-bit RGBA, so files that differ only in their
+    /// chunks, compression, filters, interlacing or pixel format are equal.
+    /// Chunks that change how colors are shown, such as `gAMA` and `iCCP`,
+    /// count as metadata here too, and of an animation only the default
+    /// image is compared.
+    pub fn pixels_equal(&self, other: &Png) -> Result<bool, ImageError> {
+        let (image, other_image) = (ImageData::decode(self)?, ImageData::decode(other)?);
+        let (header, other_header) = (image.header(), other_image.header());
+        if (header.width, header.height) != (other_header.width, other_header.height) {
+            return Ok(false);
+        }
+        let (colors, other_colors) = (Colors::of(self, header), Colors::of(other, other_header));
+        // Scanlines can be compared as they are when they hold the same
+        // samples and end on a whole pixel, with no padding bits to differ.
+        if (header.bit_depth, header.color_type)
+            == (other_header.bit_depth, other_header.color_type)
+            && colors == other_colors
+            && (header.width as usize * header.bits_per_pixel()).is_multiple_of(8)
+        {
+            return Ok(image.as_bytes() == other_image.as_bytes());
+        }
+        Ok((0..header.height).all(|y| {
+            (0..header.width)
+                .all(|x| colors.rgba(&image, x, y) == other_colors.rgba(&other_image, x, y))
+        }))
+    }
+}
+
+/// What else decides the color of a pixel: the palette of an indexed
+/// image, and `tRNS`.
+#[derive(PartialEq)]
+pub(crate) struct Colors<'a> {
+    palette: &'a [u8],
+    transparency: &'a [u8],
+}
+
+impl<'a> Colors<'a> {
+    pub(crate) fn of(png: &'a Png, header: &Header) -> Self {
+        let data = |chunk_type| png.chunk_by_type(chunk_type).map_or(&[][..], |c| c.data());
+        Self {
+            palette: match header.color_type {
+                ColorType::Indexed => data("PLTE"),
+                _ => &[],
+            },
+            transparency: data("tRNS"),
+        }
+    }
+    /// The pixel at (`x`, `y`) of `image`, with each sample scaled to 16
+    /// bits. Indices past the end of the palette are opaque black.
+    pub(crate) fn rgba(&self, image: &ImageData, x: u32, y: u32) -> [u16; 4] {
+        let header = image.header();
+        let samples = image.pixel(x, y);
+        let max = (1u32 << header.bit_depth) - 1;
+        let scale = |sample: u16| (sample as u32 * 0xffff / max) as u16;
+        // The single color tRNS makes transparent, for gray and RGB images.
+        let keyed = |len: usize| match self.transparency.len() == len
+            && self
+                .transparency
+                .chunks(2)
+                .zip(&samples)
+                .all(|(key, &sample)| u16::from_be_bytes([key[0], key[1]]) == sample)
+        {
+            true => 0,
+            false => 0xffff,
+        };
+        match header.color_type {
+            ColorType::Grayscale => {
+                let gray = scale(samples[0]);
+                [gray, gray, gray, keyed(2)]
+            }
+            ColorType::Rgb => [
+                scale(samples[0]),
+                scale(samples[1]),
+                scale(samples[2]),
+                keyed(6),
+            ],
+            ColorType::Indexed => {
+                let index = samples[0] as usize;
+                let rgb = self
+                    .palette
+                    .get(index * 3..index * 3 + 3)
+                    .unwrap_or(&[0; 3]);
+                let alpha = self.transparency.get(index).copied().unwrap_or(0xff);
+                [rgb[0], rgb[1], rgb[2], alpha].map(|sample| sample as u16 * 0x101)
+            }
+            ColorType::GrayscaleAlpha => {
+                let gray = scale(samples[0]);
+                [gray, gray, gray, scale(samples[1])]
+            }
+            ColorType::Rgba => [
+                scale(samples[0]),
+                scale(samples[1]),
+                scale(samples[2]),
+                scale(samples[3]),
+            ],
+        }
+    }
+}
+
+/// Reverses the filters of `lines`, each a filter type byte followed by
+/// `row_len` bytes.
+fn unfilter(lines: &[u8], row_len: usize, stride: usize) -> Result<Vec<u8>, ImageError> {
+    let mut pixels = vec![0u8; lines.len() / (row_len + 1) * row_len];
+    for (y, line) in lines.chunks(row_len + 1).enumerate() {
+        let filter = Filter::from_id(line[0]).ok_or(ImageError::UnknownFilter {
+            filter: line[0],
+            row: y as u32,
+        })?;
+        let (done, rest) = pixels.split_at_mut(y * row_len);
+        let previous = y.checked_sub(1).map(|_| &done[done.len() - row_len..]);
+        let row = &mut rest[..row_len];
+        for x in 0..row_len {
+            row[x] = line[x + 1].wrapping_add(filter.predict(row, previous, x, stride));
+        }
+    }
+    Ok(pixels)
+}
+
+/// Filters the rows of `pixels` onto `out`, choosing each row's filter per
+/// `strategy`. Each row is predicted from the raw row above it, so groups
+/// of rows are filtered in parallel with the `parallel` feature.
+fn filter(
+    pixels: &[u8],
+    row_len: usize,
+    stride: usize,
+    strategy: FilterStrategy,
+    out: &mut Vec<u8>,
+) {
+    let rows = pixels.len() / row_len;
+    let group_rows = (FILTER_GROUP_LEN / row_len).max(1);
+    let groups: Vec<usize> = (0..rows).step_by(group_rows).collect();
+    let filtered = parallel::map(&groups, |&start| {
+        let end = (start + group_rows).min(rows);
+        let mut lines = Vec::with_capacity((end - start) * (row_len + 1));
+        for y in start..end {
+            let row = &pixels[y * row_len..][..row_len];
+            let previous = y.checked_sub(1).map(|y| &pixels[y * row_len..][..row_len]);
+            filter_row(row, previous, stride, strategy, &mut lines);
+        }
+        lines
+    });
+    for lines in filtered {
+        out.extend_from_slice(&lines);
+    }
+}
+
+/// Bytes of pixels filtered together, by one thread.
+const FILTER_GROUP_LEN: usize = 64 * 1024;
+
+fn filter_row(
+    row: &[u8],
+    previous: Option<&[u8]>,
+    stride: usize,
+    strategy: FilterStrategy,
+    out: &mut Vec<u8>,
+) {
+    let apply = |filter: Filter| -> Vec<u8> {
+        let mut line = Vec::with_capacity(row.len() + 1);
+        line.push(filter.id());
+        line.extend(
+            (0..row.len()).map(|x| row[x].wrapping_sub(filter.predict(row, previous, x, stride))),
+        );
+        line
+    };
+    let line = match strategy {
+        FilterStrategy::Fixed(filter) => apply(filter),
+        FilterStrategy::Adaptive => Filter::ALL
+            .into_iter()
+            .map(apply)
+            .min_by_key(|line| {
+                line[1..]
+                    .iter()
+                    .map(|&b| (b as i8).unsigned_abs() as u64)
+                    .sum::<u64>()
+            })
+            .expect("at least one filter"),
+    };
+    out.extend_from_slice(&line);
+}
+
+/// Bytes of filtered data deflated together, by one thread.
+const DEFLATE_BLOCK_LEN: usize = 1024 * 1024;
+
+/// Deflates `data` into a zlib stream, with the deflate codec if one is
+/// set. Otherwise with the `parallel` feature, data longer than a block is
+/// deflated block by block on several threads.
+fn deflate(data: &[u8], level: u32) -> io::Result<Vec<u8>> {
+    if let Some(codec) = compression::codec(Algorithm::Deflate) {
+        let mut encoder = codec.encoder(level as i32)?;
+        let mut out = Vec::new();
+        encoder.encode(data, &mut out)?;
+        encoder.finish(&mut out)?;
+        return Ok(out);
+    }
+    if cfg!(feature = "parallel") && data.len() > DEFLATE_BLOCK_LEN {
+        return deflate_blocks(data, level, DEFLATE_BLOCK_LEN);
+    }
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::new(level));
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Deflates each `block_len` bytes of `data` on its own, as pigz does:
+/// every block but the last ends with a sync flush on a byte boundary, so
+/// the raw streams join into one, wrapped in a zlib header and the Adler-32
+/// of the whole. Blocks don't see the data before them, which costs a
+/// little compression.
+fn deflate_blocks(data: &[u8], level: u32, block_len: usize) -> io::Result<Vec<u8>> {
+    let blocks: Vec<&[u8]> = data.chunks(block_len).collect();
+    let deflated = parallel::map(&blocks, |block| -> io::Result<Vec<u8>> {
+        let last = block.as_ptr_range().end == data.as_ptr_range().end;
+        let flush = if last {
+            FlushCompress::Finish
+        } else {
+            FlushCompress::Sync
+        };
+        let mut compress = Compress::new(flate2::Compression::new(level), false);
+        let mut out = Vec::with_capacity(block.len() / 2 + 64);
+        loop {
+            let read = compress.total_in() as usize;
+            let status = compress
+                .compress_vec(&block[read..], &mut out, flush)
+                .map_err(io::Error::other)?;
+            let done = if last {
+                status == Status::StreamEnd
+            } else {
+                compress.total_in() as usize == block.len() && out.len() < out.capacity()
+            };
+            if done {
+                return Ok(out);
+            }
+            out.reserve(out.capacity().max(64));
+        }
+    });
+    // CMF: deflate with a 32K window; FLG: the level class, then check bits.
+    let class = match level {
+        0 | 1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    };
+    let mut flg = class << 6;
+    flg += (31 - (0x7800 | flg) % 31) % 31;
+    let mut stream = vec![0x78, flg as u8];
+    for block in deflated {
+        stream.extend_from_slice(&block?);
+    }
+    stream.extend_from_slice(&adler32(data).to_be_bytes());
+    Ok(stream)
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // The longest run before the sums can overflow.
+    for run in data.chunks(5552) {
+        for &byte in run {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+/// Copies pixel `from` of row `src` to pixel `to` of row `dst`, pixels being
+/// `bits` bits wide.
+fn copy_pixel(src: &[u8], from: usize, dst: &mut [u8], to: usize, bits: usize) {
+    if bits >= 8 {
+        let len = bits / 8;
+        dst[to * len..][..len].copy_from_slice(&src[from * len..][..len]);
+        return;
+    }
+    let mask = ((1u16 << bits) - 1) as u8;
+    let value = (src[from * bits / 8] >> (8 - bits - from * bits % 8)) & mask;
+    let shift = 8 - bits - to * bits % 8;
+    let byte = &mut dst[to * bits / 8];
+    *byte = (*byte & !(mask << shift)) | (value << shift);
+}
+
+fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+    Chunk::new(
+        ChunkType::from_str(chunk_type).expect("valid chunk type"),
+        data,
+    )
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    /// A PNG whose image stream is `filtered`, split over two IDAT chunks.
+    fn testing_png(header: Header, filtered: &[u8]) -> Png {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(filtered).unwrap();
+        let stream = encoder.finish().unwrap();
+        let (first, second) = stream.split_at(stream.len() / 2);
+        Png::from_chunks(vec![
+            chunk("IHDR", &header.as_bytes()),
+            chunk("IDAT", first),
+            chunk("IDAT", second),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    fn header(width: u32, height: u32, bit_depth: u8, color_type: ColorType) -> Header {
+        Header {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        }
+    }
+
+    #[test]
+    fn test_decode_rgba_file() {
+        let png = Png::try_from(
+            [
+                137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 1, 0, 0, 0,
+                1, 8, 6, 0, 0, 0, 31, 21, 196, 137, 0, 0, 0, 13, 73, 68, 65, 84, 120, 218, 99, 248,
+                207, 192, 240, 31, 0, 5, 0, 1, 255, 86, 199, 47, 13, 0, 0, 0, 0, 73, 69, 78, 68,
+                174, 66, 96, 130,
+            ]
+            .as_ref(),
+        )
+        .unwrap();
+        let image = ImageData::decode(&png).unwrap();
+        assert_eq!(image.header().color_type, ColorType::Rgba);
+        assert_eq!(image.pixel(0, 0), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_every_filter() {
+        // 2x5 RGB, one row per filter type, each decoding to the same values.
+        let header = header(2, 5, 8, ColorType::Rgb);
+        let rows: [[u8; 6]; 5] = [[10, 20, 30, 40, 50, 60]; 5];
+        #[rustfmt::skip]
+        let filtered = [
+            0, 10, 20, 30, 40, 50, 60,
+            1, 10, 20, 30, 30, 30, 30,
+            2, 0, 0, 0, 0, 0, 0,
+            // Average: (left + up) / 2
+            3, 5, 10, 15, 15, 15, 15,
+            // Paeth with a = left, b = up, c = up-left picks b here.
+            4, 0, 0, 0, 0, 0, 0,
+        ];
+        let image = ImageData::decode(&testing_png(header, &filtered)).unwrap();
+        assert_eq!(
+            image.rows().collect::<Vec<_>>(),
+            rows.iter().map(|r| &r[..]).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_low_and_high_bit_depths() {
+        let gray = header(3, 1, 2, ColorType::Grayscale);
+        let image = ImageData::decode(&testing_png(gray, &[0, 0b00_01_11_00])).unwrap();
+        assert_eq!(image.row(0), [0b00_01_11_00]);
+        assert_eq!(image.pixel(1, 0), [1]);
+        assert_eq!(image.pixel(2, 0), [3]);
+
+        let alpha = header(1, 1, 16, ColorType::GrayscaleAlpha);
+        let image = ImageData::decode(&testing_png(alpha, &[0, 1, 2, 255, 255])).unwrap();
+        assert_eq!(image.pixel(0, 0), [258, 65535]);
+    }
+
+    #[test]
+    fn test_invalid_data() {
+        let rgb = header(2, 1, 8, ColorType::Rgb);
+        assert!(matches!(
+            ImageData::decode(&testing_png(rgb, &[0, 1, 2, 3])),
+            Err(ImageError::WrongLength {
+                expected: 7,
+                actual: 4
+            })
+        ));
+        assert!(matches!(
+            ImageData::decode(&testing_png(rgb, &[5, 0, 0, 0, 0, 0, 0])),
+            Err(ImageError::UnknownFilter { filter: 5, row: 0 })
+        ));
+        let limits = Limits {
+            max_decompressed_len: 6,
+            ..Limits::UNLIMITED
+        };
+        assert!(matches!(
+            ImageData::decode_with(
+                &testing_png(rgb, &[0; 7]),
+                &mut DecodeBuffers::new(),
+                &limits
+            ),
+            Err(ImageError::LimitExceeded(_))
+        ));
+        let mut cut = testing_png(rgb, &[0; 7]);
+        let idat = cut.chunks().len() - 2;
+        cut.remove_chunk_at(idat);
+        assert!(matches!(
+            ImageData::decode(&cut),
+            Err(ImageError::Inflate(_))
+        ));
+        let mut bytes = rgb.as_bytes();
+        bytes[8] = 4;
+        assert!(Header::try_from(bytes.as_slice()).is_err());
+        for empty in [
+            header(0, 0, 8, ColorType::Rgb),
+            header(2, 0, 8, ColorType::Rgb),
+        ] {
+            assert!(matches!(
+                ImageData::new(empty, Vec::new()),
+                Err(ImageError::BadHeader("dimensions"))
+            ));
+        }
+        let interlaced = Header {
+            interlaced: true,
+            ..rgb
+        };
+        // Only passes 1 and 6 hold a pixel of a 2x1 image.
+        assert!(matches!(
+            ImageData::decode(&testing_png(interlaced, &[0; 7])),
+            Err(ImageError::WrongLength {
+                expected: 8,
+                actual: 7
+            })
+        ));
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let header = header(7, 6, 8, ColorType::Rgb);
+        let pixels: Vec<u8> = (0..7 * 6 * 3u32).map(|i| (i * i / 5) as u8).collect();
+        let mut image = ImageData::new(header, pixels).unwrap();
+        image.row_mut(2)[4] ^= 1;
+        let mut png = testing_png(header, &[0; 22 * 6]);
+        for filter in ["none", "sub", "up", "average", "paeth", "adaptive"] {
+            let options = EncodeOptions {
+                filter: filter.parse().unwrap(),
+                level: 9,
+                ..Default::default()
+            };
+            image.write_to(&mut png, &options).unwrap();
+            assert_eq!(ImageData::decode(&png).unwrap(), image);
+        }
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "IEND"]);
+
+        let options = EncodeOptions {
+            level: 10,
+            ..Default::default()
+        };
+        assert!(matches!(
+            image.encode(&options),
+            Err(ImageError::InvalidLevel(10))
+        ));
+        assert!(FilterStrategy::from_str("best").is_err());
+    }
+
+    #[test]
+    fn test_adaptive_filter_choice() {
+        // A horizontal gradient costs nothing with Sub, a copy of the row
+        // above nothing with Up.
+        let header = header(16, 2, 8, ColorType::Grayscale);
+        let row: Vec<u8> = (0..16).map(|i| i * 10).collect();
+        let image = ImageData::new(header, [row.clone(), row].concat()).unwrap();
+        let mut filtered = Vec::new();
+        flate2::read::ZlibDecoder::new(image.encode(&EncodeOptions::default()).unwrap().as_slice())
+            .read_to_end(&mut filtered)
+            .unwrap();
+        assert_eq!(filtered[0], Filter::Sub.id());
+        assert_eq!(filtered[17], Filter::Up.id());
+        assert!(ImageData::new(header, vec![0; 31]).is_err());
+    }
+
+    #[test]
+    fn test_deflate_blocks() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * i % 251) as u8).collect();
+        for (level, block_len) in [(6, 1000), (9, 4096), (1, 10_000), (0, 3)] {
+            let stream = deflate_blocks(&data, level, block_len).unwrap();
+            assert_eq!(u16::from_be_bytes([stream[0], stream[1]]) % 31, 0);
+            let mut inflated = Vec::new();
+            flate2::read::ZlibDecoder::new(stream.as_slice())
+                .read_to_end(&mut inflated)
+                .unwrap();
+            assert_eq!(inflated, data);
+        }
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn test_pixels_equal() {
+        let rgb = header(3, 2, 8, ColorType::Rgb);
+        let pixels = [[255, 0, 0], [0, 0, 255], [0, 0, 255]].repeat(2).concat();
+        let png = ImageData::new(rgb, pixels.clone())
+            .unwrap()
+            .to_png(&Default::default())
+            .unwrap();
+
+        // Another encoding, with metadata added.
+        let options = EncodeOptions {
+            filter: FilterStrategy::Fixed(Filter::Paeth),
+            interlace: Some(true),
+            level: 1,
+        };
+        let mut other = ImageData::decode(&png).unwrap().to_png(&options).unwrap();
+        other.append_chunk(chunk("tEXt", b"Comment\0hi"));
+        assert!(png.pixels_equal(&other).unwrap());
+
+        // The same colors from a palette, two bits per pixel.
+        let mut indexed = ImageData::new(header(3, 2, 2, ColorType::Indexed), vec![0x14; 2])
+            .unwrap()
+            .to_png(&Default::default())
+            .unwrap();
+        indexed.insert_chunk_at(1, chunk("PLTE", &[255, 0, 0, 0, 0, 255]));
+        assert!(png.pixels_equal(&indexed).unwrap());
+        indexed.insert_chunk_at(2, chunk("tRNS", &[255, 254]));
+        assert!(!png.pixels_equal(&indexed).unwrap());
+
+        let mut changed = pixels;
+        changed[4] = 1;
+        let changed = ImageData::new(rgb, changed)
+            .unwrap()
+            .to_png(&Default::default())
+            .unwrap();
+        assert!(!png.pixels_equal(&changed).unwrap());
+        let smaller = ImageData::new(header(1, 1, 8, ColorType::Rgb), vec![255, 0, 0])
+            .unwrap()
+            .to_png(&Default::default())
+            .unwrap();
+        assert!(!png.pixels_equal(&smaller).unwrap());
+    }
+
+    #[test]
+    fn test_interlaced() {
+        let gray = header(9, 9, 8, ColorType::Grayscale);
+        let pixels: Vec<u8> = (0..81).collect();
+        let image = ImageData::new(gray, pixels).unwrap();
+        let options = EncodeOptions {
+            filter: FilterStrategy::Fixed(Filter::None),
+            interlace: Some(true),
+            ..Default::default()
+        };
+        let mut png = testing_png(gray, &[0; 90]);
+        image.write_to(&mut png, &options).unwrap();
+        assert!(Header::from_png(&png).unwrap().interlaced);
+
+        // Pass 1 is the pixels at columns and rows 0 and 8.
+        let mut filtered = Vec::new();
+        flate2::read::ZlibDecoder::new(image.encode(&options).unwrap().as_slice())
+            .read_to_end(&mut filtered)
+            .unwrap();
+        assert_eq!(filtered[..6], [0, 0, 8, 0, 72, 80]);
+
+        let decoded = ImageData::decode(&png).unwrap();
+        assert_eq!(decoded.as_bytes(), image.as_bytes());
+        assert!(decoded.header().interlaced);
+        let options = EncodeOptions {
+            interlace: Some(false),
+            ..Default::default()
+        };
+        decoded.write_to(&mut png, &options).unwrap();
+        assert_eq!(ImageData::decode(&png).unwrap(), image);
+
+        // Pixels smaller than a byte move bit by bit.
+        let bits = Header {
+            interlaced: true,
+            ..header(11, 5, 1, ColorType::Grayscale)
+        };
+        // Each row is 11 bits and 5 bits of padding.
+        let pixels: Vec<u8> = (0..10u8)
+            .map(|i| i.wrapping_mul(0x5b) & if i % 2 == 1 { 0xe0 } else { 0xff })
+            .collect();
+        let image = ImageData::new(bits, pixels).unwrap();
+        let mut png = testing_png(bits, &[]);
+        image.write_to(&mut png, &EncodeOptions::default()).unwrap();
+        assert_eq!(ImageData::decode(&png).unwrap(), image);
+    }
+}
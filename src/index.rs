@@ -0,0 +1,301 @@
+use std::convert::TryFrom;
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::envelope::{Envelope, PayloadKind};
+use crate::messages;
+use crate::parse::{self, Span};
+use crate::png::Png;
+
+#[derive(Debug, Error)]
+pub enum IndexError {
+    #[error("Payload index is truncated: {0}")]
+    Truncated(Span),
+    #[error("Unsupported payload index version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("Invalid chunk type in payload index")]
+    BadChunkType,
+    #[error("Non UTF-8 payload name in payload index")]
+    BadName,
+}
+
+/// One payload listed in a [`PayloadIndex`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct IndexEntry {
+    pub name: Option<String>,
+    pub chunk_type: ChunkType,
+    /// Position of the chunk in [`Png::chunks`] when the index was written.
+    pub chunk_index: u32,
+    /// Byte offset of the chunk in the file when the index was written.
+    pub offset: u64,
+    /// Length of the (decompressed) payload inside the envelope.
+    pub size: u64,
+    pub flags: u8,
+}
+
+impl IndexEntry {
+    /// The payload is an embedded file rather than a text message.
+    pub const FLAG_FILE: u8 = 0x01;
+    /// The payload is a log of timestamped entries.
+    pub const FLAG_LOG: u8 = 0x02;
+    /// The payload is stored compressed.
+    pub const FLAG_COMPRESSED: u8 = 0x04;
+    /// The payload is encrypted; kind and size are those of the ciphertext.
+    pub const FLAG_ENCRYPTED: u8 = 0x08;
+    /// The payload carries an HMAC integrity tag.
+    pub const FLAG_INTEGRITY: u8 = 0x10;
+    /// The payload is wrapped in an OpenPGP message.
+    pub const FLAG_OPENPGP: u8 = 0x20;
+    /// The payload is one Shamir share of a secret.
+    pub const FLAG_SHARE: u8 = 0x40;
+    /// The payload is one part of a payload striped across files. The kind
+    /// flags are exclusive, so this combination of two of them is free.
+    pub const FLAG_PART: u8 = Self::FLAG_SHARE | Self::FLAG_FILE;
+    /// The entry carries a name (distinguishes an empty name from none).
+    pub const FLAG_NAMED: u8 = 0x80;
+}
+
+/// Directory of every envelope in a PNG, stored in its own chunk so lookups
+/// by name don't have to trial-parse every ancillary chunk.
+///
+/// The index chunk is always written last before `IEND`, so its own size
+/// never shifts the offsets it records. Entries are only hints: readers must
+/// check the chunk they point at and fall back to a scan when it's stale.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PayloadIndex {
+    entries: Vec<IndexEntry>,
+}
+
+impl PayloadIndex {
+    pub const CHUNK_TYPE: &'static str = "pmIX";
+    pub const VERSION: u8 = 1;
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+    pub fn find(&self, name: &str) -> Option<&IndexEntry> {
+        self.entries
+            .iter()
+            .find(|e| e.name.as_deref() == Some(name))
+    }
+    /// Builds an index by scanning every chunk of `png` except an existing
+    /// index chunk.
+    pub fn build(png: &Png) -> Self {
+        let mut offsets = Vec::with_capacity(png.chunks().len());
+        let mut offset = Png::STANDARD_HEADER.len() as u64;
+        for chunk in png.chunks() {
+            offsets.push(offset);
+            if chunk.chunk_type().to_string() != Self::CHUNK_TYPE {
+                offset += (Chunk::OVERHEAD + chunk.length() as usize) as u64;
+            }
+        }
+        let entries = messages::envelopes(png, None)
+            .map(|(i, envelope)| {
+                let mut flags = match envelope.kind() {
+                    PayloadKind::Text => 0,
+                    PayloadKind::File => IndexEntry::FLAG_FILE,
+                    PayloadKind::Log => IndexEntry::FLAG_LOG,
+                    PayloadKind::Encrypted => IndexEntry::FLAG_ENCRYPTED,
+                    PayloadKind::Share => IndexEntry::FLAG_SHARE,
+                    PayloadKind::Part => IndexEntry::FLAG_PART,
+                };
+                if envelope.compression().is_some() {
+                    flags |= IndexEntry::FLAG_COMPRESSED;
+                }
+                if envelope.has_integrity_tag() {
+                    flags |= IndexEntry::FLAG_INTEGRITY;
+                }
+                if envelope.is_openpgp() {
+                    flags |= IndexEntry::FLAG_OPENPGP;
+                }
+                if envelope.name().is_some() {
+                    flags |= IndexEntry::FLAG_NAMED;
+                }
+                IndexEntry {
+                    name: envelope.name().map(str::to_string),
+                    chunk_type: png.chunks()[i].chunk_type().clone(),
+                    chunk_index: i as u32,
+                    offset: offsets[i],
+                    size: envelope.payload().len() as u64,
+                    flags,
+                }
+            })
+            .collect();
+        Self { entries }
+    }
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![Self::VERSION];
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            let name = entry.name.as_deref().unwrap_or_default();
+            bytes.extend_from_slice(&entry.chunk_index.to_be_bytes());
+            bytes.extend_from_slice(&entry.offset.to_be_bytes());
+            bytes.extend_from_slice(&entry.chunk_type.bytes());
+            bytes.push(entry.flags);
+            bytes.extend_from_slice(&entry.size.to_be_bytes());
+            bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            bytes.extend_from_slice(name.as_bytes());
+        }
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for PayloadIndex {
+    type Error = IndexError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let truncated = |e| IndexError::Truncated(parse::span(value, e));
+        let (mut input, (version, count)) = parse::index_header(value).map_err(truncated)?;
+        if version != Self::VERSION {
+            return Err(IndexError::UnsupportedVersion(version));
+        }
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let (rest, raw) = parse::index_entry(input).map_err(truncated)?;
+            input = rest;
+            let chunk_type =
+                ChunkType::try_from(raw.chunk_type).map_err(|_| IndexError::BadChunkType)?;
+            let name = std::str::from_utf8(raw.name).map_err(|_| IndexError::BadName)?;
+            entries.push(IndexEntry {
+                name: (raw.flags & IndexEntry::FLAG_NAMED != 0).then(|| name.to_string()),
+                chunk_type,
+                chunk_index: raw.chunk_index,
+                offset: raw.offset,
+                size: raw.size,
+                flags: raw.flags,
+            });
+        }
+        Ok(Self { entries })
+    }
+}
+
+fn index_chunk_type() -> ChunkType {
+    ChunkType::from_str(PayloadIndex::CHUNK_TYPE).expect("valid index chunk type")
+}
+
+pub fn has_index(png: &Png) -> bool {
+    png.chunk_by_type(PayloadIndex::CHUNK_TYPE).is_some()
+}
+
+/// Reads the index chunk of `png`, if it has one.
+pub fn read(png: &Png) -> Option<Result<PayloadIndex, IndexError>> {
+    png.chunk_by_type(PayloadIndex::CHUNK_TYPE)
+        .map(|c| PayloadIndex::try_from(c.data()))
+}
+
+/// Drops every index chunk from `png`, returning whether there was one.
+pub fn remove(png: &mut Png) -> bool {
+    let mut removed = false;
+    while png.remove_chunk(PayloadIndex::CHUNK_TYPE).is_ok() {
+        removed = true;
+    }
+    removed
+}
+
+/// Rewrites the index chunk of `png` to match its current payloads.
+pub fn refresh(png: &mut Png) {
+    remove(png);
+    let index = PayloadIndex::build(png);
+    png.append_chunk(Chunk::new(index_chunk_type(), &index.as_bytes()));
+}
+
+/// Uses the index of `png` to locate the payload named `name`, checking
+/// that the chunk it points at still holds it. Returns `None` when there is
+/// no index or it is stale, so callers can fall back to a scan.
+pub fn lookup(png: &Png, name: &str) -> Option<(usize, Envelope)> {
+    let index = read(png)?.ok()?;
+    let entry = index.find(name)?;
+    let i = entry.chunk_index as usize;
+    let chunk = png.chunks().get(i)?;
+    if *chunk.chunk_type() != entry.chunk_type {
+        return None;
+    }
+    let envelope = Envelope::try_from(chunk.data()).ok()?;
+    (envelope.name() == Some(name)).then_some((i, envelope))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), &[0; 13]),
+            Chunk::new(
+                chunk_type.clone(),
+                &Envelope::text("one").with_name("first").as_bytes(),
+            ),
+            Chunk::new(chunk_type, &Envelope::text("anonymous").as_bytes()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), &[]),
+        ])
+    }
+
+    #[test]
+    fn test_build() {
+        let index = PayloadIndex::build(&testing_png());
+        assert_eq!(index.entries().len(), 2);
+        let first = index.find("first").unwrap();
+        assert_eq!(first.chunk_index, 1);
+        assert_eq!(first.offset, 8 + 12 + 13);
+        assert_eq!(first.size, 3);
+        assert_eq!(index.entries()[1].name, None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let index = PayloadIndex::build(&testing_png());
+        let decoded = PayloadIndex::try_from(index.as_bytes().as_ref()).unwrap();
+        assert_eq!(decoded, index);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = PayloadIndex::build(&testing_png()).as_bytes();
+        assert!(matches!(
+            PayloadIndex::try_from(&bytes[..bytes.len() - 1]),
+            Err(IndexError::Truncated(Span {
+                what: "payload name",
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_refresh_and_lookup() {
+        let mut png = testing_png();
+        assert!(lookup(&png, "first").is_none());
+        refresh(&mut png);
+        refresh(&mut png);
+        assert_eq!(png.chunks().len(), 5);
+        assert_eq!(
+            png.chunks()[3].chunk_type().to_string(),
+            PayloadIndex::CHUNK_TYPE
+        );
+        let (i, envelope) = lookup(&png, "first").unwrap();
+        assert_eq!(i, 1);
+        assert_eq!(envelope.payload(), b"one");
+    }
+
+    #[test]
+    fn test_stale_lookup() {
+        let mut png = testing_png();
+        refresh(&mut png);
+        png.remove_chunk_at(1);
+        assert!(lookup(&png, "first").is_none());
+    }
+
+    #[test]
+    fn test_offsets_match_serialized_file() {
+        let mut png = testing_png();
+        refresh(&mut png);
+        let bytes = png.as_bytes();
+        let index = read(&png).unwrap().unwrap();
+        for entry in index.entries() {
+            let offset = entry.offset as usize;
+            assert_eq!(&bytes[offset + 4..offset + 8], &entry.chunk_type.bytes());
+        }
+    }
+}
@@ -0,0 +1,402 @@
+//! JPEG files as a [`Container`]: payloads go in APP11 or COM segments.
+//!
+//! A segment holds at most 65533 bytes, so a payload takes as many
+//! segments as it needs, each starting with `PNGM`, the tag and whether
+//! the payload continues in the next one:
+//!
+//! ```text
+//! FF EB | length (u16 BE) | "PNGM" tag | 1 if more follow, else 0 | data
+//! ```
+//!
+//! Payloads go after the application and comment segments already in the
+//! file, before the tables and the frame. Every other byte, from the start
+//! of the first scan to the end of the file included, is kept as it was.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::chunk_type::ChunkType;
+use crate::container::{Block, Container, ContainerError};
+
+#[derive(Debug, Error)]
+pub enum JpegError {
+    #[error("Invalid JPEG header")]
+    InvalidHeader,
+    #[error("Truncated JPEG file: the segment at offset {0} runs past the end")]
+    Truncated(usize),
+    #[error("Expected a JPEG marker at offset {0}")]
+    NoMarker(usize),
+    #[error("Unknown JPEG segment {0:?}: expected app11 or com")]
+    UnknownSegment(String),
+}
+
+const SOI: [u8; 2] = [0xff, 0xd8];
+const SOS: u8 = 0xda;
+const EOI: u8 = 0xd9;
+const APP0: u8 = 0xe0;
+const APP11: u8 = 0xeb;
+const APP15: u8 = 0xef;
+const COM: u8 = 0xfe;
+const PAYLOAD_NAME: &[u8; 4] = b"PNGM";
+/// `PNGM`, the tag and the continuation flag.
+const PAYLOAD_HEADER_LEN: usize = 9;
+/// Payload bytes in one segment: the most a segment holds, less its length
+/// and header.
+const SEGMENT_DATA_LEN: usize = 0xffff - 2 - PAYLOAD_HEADER_LEN;
+
+/// The kind of segment new payloads go in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Segment {
+    /// APP11, an application segment nothing else claims for JPEG files
+    /// (JPEG XT and JUMBF name theirs).
+    #[default]
+    App11,
+    /// COM, a comment, which some tools show or drop.
+    Comment,
+}
+
+impl Segment {
+    fn marker(self) -> u8 {
+        match self {
+            Self::App11 => APP11,
+            Self::Comment => COM,
+        }
+    }
+}
+
+impl FromStr for Segment {
+    type Err = JpegError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "app11" => Ok(Self::App11),
+            "com" => Ok(Self::Comment),
+            _ => Err(JpegError::UnknownSegment(s.to_string())),
+        }
+    }
+}
+
+impl Display for Segment {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::App11 => "app11",
+            Self::Comment => "com",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    /// A segment other than a payload's, as it was read.
+    Raw { marker: u8, bytes: Vec<u8> },
+    Payload {
+        segment: Segment,
+        tag: ChunkType,
+        data: Vec<u8>,
+    },
+}
+
+/// A JPEG file: the segments before its first scan, then the rest of it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Jpeg {
+    parts: Vec<Part>,
+    /// From the first scan (or `EOI` in a file without one) to the end.
+    rest: Vec<u8>,
+    segment: Segment,
+}
+
+impl Jpeg {
+    /// Stores new payloads in `segment` segments.
+    pub fn with_segment(mut self, segment: Segment) -> Self {
+        self.segment = segment;
+        self
+    }
+}
+
+fn marker_name(marker: u8) -> String {
+    match marker {
+        0xc4 => "DHT".to_string(),
+        0xcc => "DAC".to_string(),
+        0xc0..=0xcf => format!("SOF{}", marker - 0xc0),
+        0xdb => "DQT".to_string(),
+        0xdd => "DRI".to_string(),
+        APP0..=APP15 => format!("APP{}", marker - APP0),
+        COM => "COM".to_string(),
+        _ => format!("marker 0x{:02x}", marker),
+    }
+}
+
+/// The tag, continuation flag and data of a payload segment.
+fn payload_segment(marker: u8, data: &[u8]) -> Option<(Segment, ChunkType, bool, &[u8])> {
+    let segment = match marker {
+        APP11 => Segment::App11,
+        COM => Segment::Comment,
+        _ => return None,
+    };
+    if data.len() < PAYLOAD_HEADER_LEN || &data[..4] != PAYLOAD_NAME || data[8] > 1 {
+        return None;
+    }
+    let tag: [u8; 4] = data[4..8].try_into().expect("4 bytes");
+    let tag = ChunkType::try_from(tag).ok()?;
+    Some((segment, tag, data[8] == 1, &data[PAYLOAD_HEADER_LEN..]))
+}
+
+impl TryFrom<&[u8]> for Jpeg {
+    type Error = JpegError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if !bytes.starts_with(&SOI) {
+            return Err(JpegError::InvalidHeader);
+        }
+        let mut parts = Vec::new();
+        // Set while the last payload segment said more follow.
+        let mut continued = false;
+        let mut offset = SOI.len();
+        loop {
+            let start = offset;
+            if bytes.get(offset) != Some(&0xff) {
+                return Err(JpegError::NoMarker(offset));
+            }
+            // Any number of fill bytes may come before a marker.
+            while bytes.get(offset) == Some(&0xff) {
+                offset += 1;
+            }
+            let marker = *bytes.get(offset).ok_or(JpegError::Truncated(start))?;
+            offset += 1;
+            if marker == SOS || marker == EOI {
+                return Ok(Self {
+                    parts,
+                    rest: bytes[start..].to_vec(),
+                    segment: Segment::default(),
+                });
+            }
+            // Markers without a segment: TEM and the restart markers.
+            let data = if marker == 0x01 || (0xd0..=0xd7).contains(&marker) {
+                &[][..]
+            } else {
+                let len = bytes
+                    .get(offset..offset + 2)
+                    .map(|len| usize::from(u16::from_be_bytes([len[0], len[1]])))
+                    .filter(|&len| len >= 2)
+                    .ok_or(JpegError::Truncated(start))?;
+                let data = bytes
+                    .get(offset + 2..offset + len)
+                    .ok_or(JpegError::Truncated(start))?;
+                offset += len;
+                data
+            };
+            // Segments after fill bytes stay raw, so they are written back
+            // as they were.
+            let payload =
+                payload_segment(marker, data).filter(|_| offset - start == data.len() + 4);
+            match (payload, parts.last_mut()) {
+                (
+                    Some((segment, tag, more, chunk)),
+                    Some(Part::Payload {
+                        segment: s,
+                        tag: t,
+                        data,
+                    }),
+                ) if continued && *s == segment && *t == tag => {
+                    data.extend_from_slice(chunk);
+                    continued = more;
+                }
+                (Some((segment, tag, more, chunk)), _) => {
+                    parts.push(Part::Payload {
+                        segment,
+                        tag,
+                        data: chunk.to_vec(),
+                    });
+                    continued = more;
+                }
+                (None, _) => {
+                    parts.push(Part::Raw {
+                        marker,
+                        bytes: bytes[start..offset].to_vec(),
+                    });
+                    continued = false;
+                }
+            }
+        }
+    }
+}
+
+impl Container for Jpeg {
+    fn blocks(&self) -> Vec<Block> {
+        let mut blocks: Vec<_> = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Raw { marker, bytes } => Block {
+                    kind: marker_name(*marker),
+                    len: bytes.len().saturating_sub(4),
+                },
+                Part::Payload { segment, tag, data } => Block {
+                    kind: format!("{} PNGM{}", marker_name(segment.marker()), tag),
+                    len: data.len(),
+                },
+            })
+            .collect();
+        blocks.push(Block {
+            kind: "scan".to_string(),
+            len: self.rest.len(),
+        });
+        blocks
+    }
+    fn payload(&self, tag: &ChunkType) -> Option<&[u8]> {
+        self.parts.iter().find_map(|part| match part {
+            Part::Payload { tag: t, data, .. } if t == tag => Some(data.as_slice()),
+            _ => None,
+        })
+    }
+    fn insert_payload(&mut self, tag: ChunkType, data: &[u8]) -> Result<(), ContainerError> {
+        // After JFIF, Exif and the rest, which readers expect first.
+        let index = self
+            .parts
+            .iter()
+            .rposition(|part| match part {
+                Part::Raw { marker, .. } => matches!(*marker, APP0..=APP15 | COM),
+                Part::Payload { .. } => true,
+            })
+            .map_or(0, |index| index + 1);
+        self.parts.insert(
+            index,
+            Part::Payload {
+                segment: self.segment,
+                tag,
+                data: data.to_vec(),
+            },
+        );
+        Ok(())
+    }
+    fn remove_payload(&mut self, tag: &ChunkType) -> Result<(), ContainerError> {
+        let index = self
+            .parts
+            .iter()
+            .position(|part| matches!(part, Part::Payload { tag: t, .. } if t == tag))
+            .ok_or_else(|| ContainerError::NotFound(tag.to_string()))?;
+        self.parts.remove(index);
+        Ok(())
+    }
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = SOI.to_vec();
+        for part in &self.parts {
+            match part {
+                Part::Raw { bytes: raw, .. } => bytes.extend_from_slice(raw),
+                Part::Payload { segment, tag, data } => {
+                    // An empty payload still takes a segment.
+                    let count = data.len().div_ceil(SEGMENT_DATA_LEN).max(1);
+                    for i in 0..count {
+                        let end = ((i + 1) * SEGMENT_DATA_LEN).min(data.len());
+                        let chunk = &data[i * SEGMENT_DATA_LEN..end];
+                        let len = (2 + PAYLOAD_HEADER_LEN + chunk.len()) as u16;
+                        bytes.extend_from_slice(&[0xff, segment.marker()]);
+                        bytes.extend_from_slice(&len.to_be_bytes());
+                        bytes.extend_from_slice(PAYLOAD_NAME);
+                        bytes.extend_from_slice(&tag.bytes());
+                        bytes.push(u8::from(i + 1 < count));
+                        bytes.extend_from_slice(chunk);
+                    }
+                }
+            }
+        }
+        bytes.extend_from_slice(&self.rest);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let len = (data.len() + 2) as u16;
+        [&[0xff, marker][..], &len.to_be_bytes(), data].concat()
+    }
+
+    /// A JFIF file's structure, with made-up tables and scan data.
+    fn testing_jpeg() -> Vec<u8> {
+        [
+            &SOI[..],
+            &segment(APP0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0"),
+            &segment(COM, b"made by hand"),
+            &segment(0xdb, &[0; 65]),
+            &segment(0xc0, &[8, 0, 1, 0, 1, 1, 1, 0x11, 0]),
+            &segment(0xc4, &[0; 20]),
+            &segment(SOS, &[1, 1, 0, 0, 0x3f, 0]),
+            &[0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56],
+            &[0xff, EOI],
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = testing_jpeg();
+        let jpeg = Jpeg::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(jpeg.to_bytes(), bytes);
+        let kinds: Vec<_> = jpeg.blocks().into_iter().map(|b| b.kind).collect();
+        assert_eq!(kinds, ["APP0", "COM", "DQT", "SOF0", "DHT", "scan"]);
+        assert_eq!(jpeg.blocks()[1].len, 12);
+    }
+
+    #[test]
+    fn test_payload() {
+        let tag = ChunkType::from_str("ruSt").unwrap();
+        let data: Vec<u8> = (0..150_000).map(|i| i as u8).collect();
+        let original = testing_jpeg();
+        for segment in [Segment::App11, Segment::Comment] {
+            let mut jpeg = Jpeg::try_from(original.as_slice())
+                .unwrap()
+                .with_segment(segment);
+            jpeg.insert_payload(tag.clone(), &data).unwrap();
+            jpeg.insert_payload(ChunkType::from_str("teSt").unwrap(), b"")
+                .unwrap();
+            let bytes = jpeg.to_bytes();
+            assert_eq!(bytes.len(), original.len() + data.len() + 4 * (4 + 9));
+
+            let mut read = Jpeg::try_from(bytes.as_slice()).unwrap();
+            assert_eq!(read.payload(&tag), Some(data.as_slice()));
+            let kinds: Vec<_> = read.blocks().into_iter().map(|b| b.kind).collect();
+            let name = marker_name(segment.marker());
+            assert_eq!(kinds[2], format!("{} PNGMruSt", name));
+            assert_eq!(kinds[3], format!("{} PNGMteSt", name));
+            assert_eq!(kinds[4], "DQT");
+            assert_eq!(
+                read.payload(&ChunkType::from_str("teSt").unwrap()),
+                Some(&b""[..])
+            );
+            read.remove_payload(&tag).unwrap();
+            read.remove_payload(&ChunkType::from_str("teSt").unwrap())
+                .unwrap();
+            assert!(matches!(
+                read.remove_payload(&tag),
+                Err(ContainerError::NotFound(_))
+            ));
+            assert_eq!(read.to_bytes(), original);
+        }
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(matches!(
+            Jpeg::try_from(&b"GIF89a"[..]),
+            Err(JpegError::InvalidHeader)
+        ));
+        let bytes = testing_jpeg();
+        assert!(matches!(
+            Jpeg::try_from(&bytes[..30]),
+            Err(JpegError::Truncated(_))
+        ));
+        let mut no_marker = bytes.clone();
+        no_marker[2] = 0x00;
+        assert!(matches!(
+            Jpeg::try_from(no_marker.as_slice()),
+            Err(JpegError::NoMarker(2))
+        ));
+        assert_eq!(Segment::from_str("com").unwrap(), Segment::Comment);
+        assert!(Segment::from_str("app1").is_err());
+    }
+}
@@ -0,0 +1,333 @@
+//! Just enough JSON for the programmatic front ends: a parser for requests
+//! and [`Value`]'s `Display` for answers.
+
+use std::fmt::{self, Display, Formatter};
+
+/// Nesting deeper than this is refused rather than risking the stack.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    /// A number as written, so ids are echoed back exactly.
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    /// Members in order, duplicates included.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn object(members: impl IntoIterator<Item = (&'static str, Value)>) -> Self {
+        Self::Object(
+            members
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+    pub fn string(s: impl Into<String>) -> Self {
+        Self::String(s.into())
+    }
+    pub fn number(n: impl Into<u64>) -> Self {
+        Self::Number(n.into().to_string())
+    }
+    /// The first member named `key`, of an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Self::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+    /// A whole number that fits in a `u64`.
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Self::Number(n) => n.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// Parses one JSON text, with nothing but whitespace around it.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            offset: 0,
+        };
+        let value = parser.value(0)?;
+        parser.whitespace();
+        (parser.offset == parser.bytes.len()).then_some(value)
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Parser<'_> {
+    fn whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.offset += 1;
+        }
+    }
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.offset).copied()
+    }
+    fn eat(&mut self, expected: &[u8]) -> Option<()> {
+        self.bytes[self.offset..].starts_with(expected).then(|| {
+            self.offset += expected.len();
+        })
+    }
+    fn value(&mut self, depth: usize) -> Option<Value> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        self.whitespace();
+        match self.peek()? {
+            b'n' => self.eat(b"null").map(|()| Value::Null),
+            b't' => self.eat(b"true").map(|()| Value::Bool(true)),
+            b'f' => self.eat(b"false").map(|()| Value::Bool(false)),
+            b'"' => self.string().map(Value::String),
+            b'[' => {
+                self.offset += 1;
+                let mut items = Vec::new();
+                self.whitespace();
+                if self.eat(b"]").is_some() {
+                    return Some(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.whitespace();
+                    match self.peek()? {
+                        b',' => self.offset += 1,
+                        b']' => {
+                            self.offset += 1;
+                            return Some(Value::Array(items));
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            b'{' => {
+                self.offset += 1;
+                let mut members = Vec::new();
+                self.whitespace();
+                if self.eat(b"}").is_some() {
+                    return Some(Value::Object(members));
+                }
+                loop {
+                    self.whitespace();
+                    let key = self.string()?;
+                    self.whitespace();
+                    self.eat(b":")?;
+                    members.push((key, self.value(depth + 1)?));
+                    self.whitespace();
+                    match self.peek()? {
+                        b',' => self.offset += 1,
+                        b'}' => {
+                            self.offset += 1;
+                            return Some(Value::Object(members));
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            b'-' | b'0'..=b'9' => self.number().map(Value::Number),
+            _ => None,
+        }
+    }
+    fn number(&mut self) -> Option<String> {
+        let start = self.offset;
+        let digits = |parser: &mut Self| {
+            let start = parser.offset;
+            while parser.peek().is_some_and(|b| b.is_ascii_digit()) {
+                parser.offset += 1;
+            }
+            parser.offset > start
+        };
+        let _ = self.eat(b"-");
+        if self.eat(b"0").is_none() && !digits(self) {
+            return None;
+        }
+        if self.eat(b".").is_some() && !digits(self) {
+            return None;
+        }
+        if matches!(self.peek(), Some(b'e' | b'E')) {
+            self.offset += 1;
+            if matches!(self.peek(), Some(b'+' | b'-')) {
+                self.offset += 1;
+            }
+            if !digits(self) {
+                return None;
+            }
+        }
+        let number = std::str::from_utf8(&self.bytes[start..self.offset]).ok()?;
+        Some(number.to_string())
+    }
+    fn string(&mut self) -> Option<String> {
+        self.eat(b"\"")?;
+        let mut bytes = Vec::new();
+        loop {
+            let byte = self.peek()?;
+            self.offset += 1;
+            match byte {
+                b'"' => return String::from_utf8(bytes).ok(),
+                b'\\' => {
+                    let escaped = self.peek()?;
+                    self.offset += 1;
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return None,
+                    };
+                    bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                0..=0x1f => return None,
+                _ => bytes.push(byte),
+            }
+        }
+    }
+    /// The character of a `\uXXXX` escape, or of two for a surrogate pair.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex4()?;
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high);
+        }
+        self.eat(b"\\u")?;
+        let low = self.hex4()?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return None;
+        }
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+    }
+    fn hex4(&mut self) -> Option<u32> {
+        let hex = self.bytes.get(self.offset..self.offset + 4)?;
+        let value = u32::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?;
+        self.offset += 4;
+        Some(value)
+    }
+}
+
+/// `s` as a JSON string, quotes included.
+pub fn string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => write!(f, "null"),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Number(n) => write!(f, "{}", n),
+            Self::String(s) => write!(f, "{}", string(s)),
+            Self::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Self::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", string(key), value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let value = Value::parse(
+            r#" {"jsonrpc": "2.0", "id": -1.5e3, "params": {"a": [true, false, null, {}]},
+               "s": "tab\there \"q\" \u00e9\ud83d\ude00"} "#,
+        )
+        .unwrap();
+        assert_eq!(value.get("id"), Some(&Value::Number("-1.5e3".to_string())));
+        assert_eq!(
+            value.get("s").and_then(Value::as_str),
+            Some("tab\there \"q\" é😀")
+        );
+        let params = value.get("params").unwrap();
+        assert_eq!(
+            params.get("a"),
+            Some(&Value::Array(vec![
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::Null,
+                Value::Object(Vec::new())
+            ]))
+        );
+        for invalid in [
+            "",
+            "{",
+            "[1,]",
+            "01",
+            "\"\\x\"",
+            "{\"a\" 1}",
+            "1 2",
+            "\"\u{1}\"",
+        ] {
+            assert_eq!(Value::parse(invalid), None, "{:?}", invalid);
+        }
+        assert!(Value::parse(&"[".repeat(100)).is_none());
+    }
+
+    #[test]
+    fn test_display() {
+        let value = Value::object([
+            ("id", Value::number(7u8)),
+            ("text", Value::string("a \"b\"\n\u{1}")),
+            ("list", Value::Array(vec![Value::Null, Value::Bool(true)])),
+        ]);
+        let json = value.to_string();
+        assert_eq!(
+            json,
+            r#"{"id":7,"text":"a \"b\"\n\u0001","list":[null,true]}"#
+        );
+        assert_eq!(Value::parse(&json), Some(value));
+    }
+}
@@ -0,0 +1,283 @@
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+use keyring::Entry;
+use std::fs;
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+use pngme::crypto;
+use pngme::envelope::EnvelopeError;
+
+use crate::password::PasswordArgs;
+
+/// Service name of every entry pngme stores in the OS keychain.
+pub const SERVICE: &str = "pngme";
+
+#[derive(Debug, Args)]
+pub struct KeychainArgs {
+    #[command(subcommand)]
+    pub action: KeychainAction,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum KeychainAction {
+    /// Store the password given with --password, --password-file,
+    /// $PNGME_PASSWORD or the prompt under PROFILE
+    Store {
+        profile: String,
+        /// Store this age identity file under PROFILE instead of a password
+        #[arg(long, value_name = "PATH")]
+        identity: Option<PathBuf>,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Delete the password and identity stored under PROFILE
+    Delete { profile: String },
+}
+
+#[derive(Clone, Copy)]
+enum Secret {
+    Password,
+    Identity,
+}
+
+fn entry(profile: &str, secret: Secret) -> Result<Entry> {
+    let user = match secret {
+        Secret::Password => format!("password:{}", profile),
+        Secret::Identity => format!("identity:{}", profile),
+    };
+    Entry::new(SERVICE, &user).context("Failed to open the OS keychain")
+}
+
+fn get(profile: &str, secret: Secret) -> Result<Option<Zeroizing<String>>> {
+    match entry(profile, secret)?.get_password() {
+        Ok(value) => Ok(Some(Zeroizing::new(value))),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(err) => Err(err).context("Failed to read from the OS keychain"),
+    }
+}
+
+/// The password stored under `profile`.
+pub fn password(profile: &str) -> Result<Zeroizing<String>> {
+    get(profile, Secret::Password)?
+        .ok_or(EnvelopeError::PasswordRequired)
+        .with_context(|| {
+            format!(
+                "No password stored in the keychain for profile {:?}",
+                profile
+            )
+        })
+}
+
+/// The age identities stored under `profile`, if any.
+pub fn identities(profile: &str) -> Result<Vec<Box<dyn age::Identity>>> {
+    match get(profile, Secret::Identity)? {
+        Some(contents) => Ok(crypto::read_identities(contents.as_bytes())
+            .with_context(|| format!("Invalid identity stored for profile {:?}", profile))?),
+        None => Ok(Vec::new()),
+    }
+}
+
+pub fn run(args: KeychainArgs) -> Result<()> {
+    match args.action {
+        KeychainAction::Store {
+            profile,
+            identity: Some(path),
+            ..
+        } => {
+            let contents = Zeroizing::new(
+                fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?,
+            );
+            crypto::read_identities(contents.as_bytes())
+                .with_context(|| format!("Failed to load {}", path.display()))?;
+            entry(&profile, Secret::Identity)?
+                .set_password(&contents)
+                .context("Failed to write to the OS keychain")
+        }
+        KeychainAction::Store {
+            profile, password, ..
+        } => {
+            let password = password.get(true)?;
+            entry(&profile, Secret::Password)?
+                .set_password(&password)
+                .context("Failed to write to the OS keychain")
+        }
+        KeychainAction::Delete { profile } => {
+            let mut deleted = false;
+            for secret in [Secret::Password, Secret::Identity] {
+                match entry(&profile, secret)?.delete_credential() {
+                    Ok(()) => deleted = true,
+                    Err(keyring::Error::NoEntry) => {}
+                    Err(err) => return Err(err).context("Failed to delete from the OS keychain"),
+                }
+            }
+            if !deleted {
+                anyhow::bail!("Nothing stored in the keychain for profile {:?}", profile);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::Cli;
+    use crate::exit;
+    use crate::keys::IdentityArgs;
+    use age::secrecy::ExposeSecret;
+    use clap::Parser as _;
+    use keyring::credential::{Credential, CredentialApi, CredentialBuilderApi};
+    use std::any::Any;
+    use std::collections::HashMap;
+    use std::sync::{Mutex, Once};
+
+    /// Secrets by user name, shared by every entry so that what one stores
+    /// the next one reads, as with a real keychain.
+    static STORE: Mutex<Option<HashMap<String, Vec<u8>>>> = Mutex::new(None);
+
+    #[derive(Debug)]
+    struct StubCredential(String);
+
+    impl CredentialApi for StubCredential {
+        fn set_secret(&self, secret: &[u8]) -> keyring::Result<()> {
+            let mut store = STORE.lock().unwrap();
+            store
+                .get_or_insert_with(HashMap::new)
+                .insert(self.0.clone(), secret.to_vec());
+            Ok(())
+        }
+
+        fn get_secret(&self) -> keyring::Result<Vec<u8>> {
+            let store = STORE.lock().unwrap();
+            store
+                .as_ref()
+                .and_then(|store| store.get(&self.0).cloned())
+                .ok_or(keyring::Error::NoEntry)
+        }
+
+        fn delete_credential(&self) -> keyring::Result<()> {
+            let mut store = STORE.lock().unwrap();
+            store
+                .as_mut()
+                .and_then(|store| store.remove(&self.0))
+                .map(drop)
+                .ok_or(keyring::Error::NoEntry)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    #[derive(Debug)]
+    struct StubBuilder;
+
+    impl CredentialBuilderApi for StubBuilder {
+        fn build(
+            &self,
+            _target: Option<&str>,
+            service: &str,
+            user: &str,
+        ) -> keyring::Result<Box<Credential>> {
+            assert_eq!(service, SERVICE);
+            Ok(Box::new(StubCredential(user.to_string())))
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    /// Puts the stub in place of the OS keychain. Each test uses its own
+    /// profiles, so they can share it.
+    fn stub_keychain() {
+        static STUB: Once = Once::new();
+        STUB.call_once(|| keyring::set_default_credential_builder(Box::new(StubBuilder)));
+    }
+
+    fn keychain(argv: &[&str]) -> Result<()> {
+        let argv = ["pngme", "keychain"].iter().chain(argv);
+        match Cli::try_parse_from(argv).unwrap().command {
+            crate::args::PngMeArgs::Keychain(args) => run(args),
+            _ => unreachable!(),
+        }
+    }
+
+    fn password_args(argv: &[&str]) -> PasswordArgs {
+        let argv = ["pngme", "keychain", "store", "-"].iter().chain(argv);
+        match Cli::try_parse_from(argv).unwrap().command {
+            crate::args::PngMeArgs::Keychain(KeychainArgs {
+                action: KeychainAction::Store { password, .. },
+            }) => password,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_profile_password() {
+        stub_keychain();
+        keychain(&["store", "work", "--password", "hunter2"]).unwrap();
+        assert_eq!(*password("work").unwrap(), "hunter2");
+
+        // The profile wins over $PNGME_PASSWORD, and can't be combined with
+        // a password on the command line.
+        let args = password_args(&["--profile", "work"]);
+        let env = Some("from env".to_string());
+        assert_eq!(*args.get_or(env.clone(), false).unwrap(), "hunter2");
+        assert_eq!(*password_args(&[]).get_or(env, false).unwrap(), "from env");
+        for conflicting in [["--password", "x"], ["--password-file", "x"]] {
+            let argv = ["pngme", "keychain", "store", "-", "--profile", "work"];
+            let err = Cli::try_parse_from(argv.iter().chain(&conflicting)).unwrap_err();
+            assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+
+        keychain(&["delete", "work"]).unwrap();
+        assert!(password("work").is_err());
+        assert!(keychain(&["delete", "work"]).is_err());
+    }
+
+    #[test]
+    fn test_missing_profile() {
+        stub_keychain();
+        let err = password_args(&["--profile", "nobody"])
+            .get_or(Some("from env".to_string()), false)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<EnvelopeError>(),
+            Some(EnvelopeError::PasswordRequired)
+        ));
+        assert_eq!(exit::code_for(&err), exit::AUTH_FAILURE);
+        assert!(err.to_string().contains("\"nobody\""));
+        assert!(identities("nobody").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_profile_identity() {
+        stub_keychain();
+        let identity = age::x25519::Identity::generate();
+        let dir = std::env::temp_dir().join(format!("pngme-keychain-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("key.txt");
+        fs::write(&path, identity.to_string().expose_secret()).unwrap();
+        let stored = keychain(&["store", "home", "--identity", path.to_str().unwrap()]);
+        fs::write(&path, "not an identity").unwrap();
+        let invalid = keychain(&["store", "other", "--identity", path.to_str().unwrap()]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        stored.unwrap();
+        assert!(invalid.is_err());
+        assert!(identities("other").unwrap().is_empty());
+        let args = IdentityArgs { identities: vec![] };
+        assert_eq!(args.load(Some("home")).unwrap().len(), 1);
+        for profile in [None, Some("other")] {
+            let err = args.load(profile).err().unwrap();
+            assert!(matches!(
+                err.downcast_ref::<EnvelopeError>(),
+                Some(EnvelopeError::IdentityRequired)
+            ));
+        }
+        assert!(password("home").is_err());
+    }
+}
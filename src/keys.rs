@@ -0,0 +1,40 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use std::fs;
+use std::path::PathBuf;
+
+use pngme::crypto;
+use pngme::envelope::EnvelopeError;
+
+#[derive(Debug, Args)]
+pub struct IdentityArgs {
+    /// age identity file for payloads encrypted to public keys (repeatable)
+    #[arg(short = 'i', long = "identity", value_name = "PATH")]
+    pub identities: Vec<PathBuf>,
+}
+
+impl IdentityArgs {
+    /// Loads every identity from the given files, and from the keychain
+    /// under `profile`.
+    pub fn load(&self, profile: Option<&str>) -> Result<Vec<Box<dyn age::Identity>>> {
+        let mut identities = Vec::new();
+        #[cfg(feature = "keychain")]
+        if let Some(profile) = profile {
+            identities.extend(crate::keychain::identities(profile)?);
+        }
+        #[cfg(not(feature = "keychain"))]
+        let _ = profile;
+        for path in &self.identities {
+            let data =
+                fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+            identities.extend(
+                crypto::read_identities(&data)
+                    .with_context(|| format!("Failed to load {}", path.display()))?,
+            );
+        }
+        if identities.is_empty() {
+            return Err(EnvelopeError::IdentityRequired.into());
+        }
+        Ok(identities)
+    }
+}
@@ -0,0 +1,371 @@
+//! PNG files whose chunk data is read on demand.
+//!
+//! [`LazyPng::open`] reads only the signature and the header and CRC of
+//! every chunk, seeking over the data in between, so opening a large file
+//! costs a few bytes per chunk. Chunk data is read, and its CRC checked, the
+//! first time it is asked for. [`LazyPng::open_with`] takes the
+//! [`ParseOptions`] of the other parsers instead.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::chunk::{Chunk, ChunkError};
+use crate::chunk_type::ChunkType;
+use crate::decoder::{self, CriticalPolicy};
+use crate::limits::Limit;
+use crate::options::{CrcPolicy, ParseOptions};
+use crate::png::{Png, PngError};
+
+/// Where a chunk is and what it claims to hold, read without its data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChunkHeader {
+    /// Offset of the chunk's length field in the file.
+    pub offset: usize,
+    pub chunk_type: ChunkType,
+    pub length: u32,
+    pub crc: u32,
+}
+
+impl ChunkHeader {
+    /// The offset just past the chunk.
+    pub fn end(&self) -> usize {
+        self.offset + Chunk::OVERHEAD + self.length as usize
+    }
+}
+
+pub struct LazyPng<R> {
+    reader: R,
+    headers: Vec<ChunkHeader>,
+    loaded: Vec<Option<Chunk>>,
+    /// Unknown critical chunks set aside under
+    /// [`CriticalPolicy::Quarantine`].
+    quarantined: Vec<ChunkHeader>,
+    check_crc: bool,
+    /// Length of the whole file.
+    len: usize,
+}
+
+impl<R: Read + Seek> LazyPng<R> {
+    /// Reads the chunk headers of the PNG file in `reader`, up to `IEND`.
+    pub fn open(reader: R) -> Result<Self, PngError> {
+        Self::open_with(reader, &ParseOptions::new().with_lazy(true))
+    }
+    /// Like [`LazyPng::open`], as `options` say. Unless they are lazy, the
+    /// data of every chunk is read and checked before returning.
+    pub fn open_with(mut reader: R, options: &ParseOptions) -> Result<Self, PngError> {
+        let len = stream_len(&mut reader).map_err(|e| bad(0, e.into()))?;
+        check_signature(&mut reader)?;
+        let limits = options.limits();
+        let mut headers = Vec::new();
+        let mut quarantined = Vec::new();
+        let mut offset = Png::STANDARD_HEADER.len();
+        while offset < len {
+            limits.check(Limit::Chunks, headers.len() + quarantined.len() + 1)?;
+            let header = read_header(&mut reader, offset, len)?;
+            offset = header.end();
+            let is_end = header.chunk_type.bytes() == *b"IEND";
+            match options.critical_policy() {
+                _ if !decoder::is_unknown_critical(&header.chunk_type) => headers.push(header),
+                CriticalPolicy::Error => {
+                    return Err(PngError::UnknownCritical {
+                        offset: header.offset,
+                        chunk_type: header.chunk_type,
+                    })
+                }
+                CriticalPolicy::Keep => headers.push(header),
+                CriticalPolicy::Quarantine => quarantined.push(header),
+            }
+            if is_end {
+                break;
+            }
+        }
+        let loaded = vec![None; headers.len()];
+        let mut png = Self {
+            reader,
+            headers,
+            loaded,
+            quarantined,
+            check_crc: options.crc_policy() == CrcPolicy::Error,
+            len,
+        };
+        if !options.lazy() {
+            for index in 0..png.headers.len() {
+                png.chunk(index)?;
+            }
+        }
+        if options.profile().is_some() {
+            let chunks = png.loaded.iter().flatten().cloned().collect();
+            options.check_conformance(&Png::from_chunks(chunks))?;
+        }
+        Ok(png)
+    }
+    pub fn headers(&self) -> &[ChunkHeader] {
+        &self.headers
+    }
+    /// The unknown critical chunks left out of [`LazyPng::headers`] under
+    /// [`CriticalPolicy::Quarantine`].
+    pub fn quarantined(&self) -> &[ChunkHeader] {
+        &self.quarantined
+    }
+    /// The chunk at `index` in [`LazyPng::headers`], read from the file
+    /// the first time.
+    pub fn chunk(&mut self, index: usize) -> Result<&Chunk, PngError> {
+        if self.loaded[index].is_none() {
+            let header = &self.headers[index];
+            let chunk = read_chunk(&mut self.reader, header, self.check_crc)?;
+            self.loaded[index] = Some(chunk);
+        }
+        Ok(self.loaded[index].as_ref().expect("just loaded"))
+    }
+    /// The first chunk of `chunk_type`, reading only its data.
+    pub fn chunk_by_type(&mut self, chunk_type: &str) -> Result<Option<&Chunk>, PngError> {
+        let chunk_type = chunk_type.parse::<ChunkType>()?;
+        match self.headers.iter().position(|h| h.chunk_type == chunk_type) {
+            Some(index) => self.chunk(index).map(Some),
+            None => Ok(None),
+        }
+    }
+    /// How many bytes follow `IEND` (or the last chunk).
+    pub fn trailer_len(&self) -> usize {
+        let end = self
+            .headers
+            .iter()
+            .chain(&self.quarantined)
+            .map(ChunkHeader::end)
+            .max()
+            .unwrap_or(Png::STANDARD_HEADER.len());
+        self.len - end
+    }
+    /// Reads everything that is left into a [`Png`].
+    pub fn into_png(mut self) -> Result<Png, PngError> {
+        let mut chunks = Vec::with_capacity(self.headers.len());
+        for index in 0..self.headers.len() {
+            let chunk = match self.loaded[index].take() {
+                Some(chunk) => chunk,
+                None => read_chunk(&mut self.reader, &self.headers[index], self.check_crc)?,
+            };
+            chunks.push(chunk);
+        }
+        let mut quarantined = Vec::with_capacity(self.quarantined.len());
+        for header in &self.quarantined {
+            quarantined.push(read_chunk(&mut self.reader, header, self.check_crc)?);
+        }
+        let start = self.len - self.trailer_len();
+        let mut trailer = vec![0; self.trailer_len()];
+        self.reader
+            .seek(SeekFrom::Start(start as u64))
+            .and_then(|_| self.reader.read_exact(&mut trailer))
+            .map_err(|e| bad(start, e.into()))?;
+        let mut png = Png::from_chunks(chunks);
+        png.set_trailer(trailer);
+        png.set_quarantined(quarantined);
+        Ok(png)
+    }
+}
+
+/// The first chunk of `chunk_type` in the PNG file in `reader`, seeking
+/// over the data of every chunk before it and reading nothing after it.
+pub fn find_chunk_in_reader<R: Read + Seek>(
+    mut reader: R,
+    chunk_type: &str,
+) -> Result<Option<Chunk>, PngError> {
+    let chunk_type = chunk_type.parse::<ChunkType>()?;
+    let len = stream_len(&mut reader).map_err(|e| bad(0, e.into()))?;
+    check_signature(&mut reader)?;
+    let mut offset = Png::STANDARD_HEADER.len();
+    while offset < len {
+        let header = read_header(&mut reader, offset, len)?;
+        if header.chunk_type == chunk_type {
+            return read_chunk(&mut reader, &header, true).map(Some);
+        }
+        if header.chunk_type.bytes() == *b"IEND" {
+            break;
+        }
+        offset = header.end();
+    }
+    Ok(None)
+}
+
+fn bad(offset: usize, source: ChunkError) -> PngError {
+    PngError::BadChunk { offset, source }
+}
+
+fn truncated() -> ChunkError {
+    ChunkError::InvalidChunkData(io::ErrorKind::UnexpectedEof.into())
+}
+
+fn stream_len(reader: &mut impl Seek) -> io::Result<usize> {
+    let len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    usize::try_from(len).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large"))
+}
+
+/// Reads the signature from the start of `reader`.
+fn check_signature(reader: &mut (impl Read + Seek)) -> Result<(), PngError> {
+    let mut signature = [0; 8];
+    reader
+        .seek(SeekFrom::Start(0))
+        .and_then(|_| reader.read_exact(&mut signature))
+        .map_err(|_| PngError::InvalidHeader)?;
+    if signature != Png::STANDARD_HEADER {
+        return Err(PngError::InvalidHeader);
+    }
+    Ok(())
+}
+
+/// Reads the header of the chunk at `offset` and its CRC, seeking over its
+/// data; `len` is the length of the whole file.
+fn read_header(
+    reader: &mut (impl Read + Seek),
+    offset: usize,
+    len: usize,
+) -> Result<ChunkHeader, PngError> {
+    let mut header = [0; 8];
+    reader
+        .seek(SeekFrom::Start(offset as u64))
+        .and_then(|_| reader.read_exact(&mut header))
+        .map_err(|e| bad(offset, e.into()))?;
+    let length = u32::from_be_bytes(header[..4].try_into().expect("4 bytes"));
+    let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&header[4..]).expect("4 bytes"))
+        .map_err(|e| bad(offset, e.into()))?;
+    if length > Chunk::MAX_LENGTH {
+        return Err(bad(offset, ChunkError::TooLong(length as usize)));
+    }
+    // A chunk whose end overflows is past the end of the file too.
+    let crc_offset = match offset.checked_add(Chunk::OVERHEAD + length as usize) {
+        Some(end) if end <= len => end - 4,
+        _ => return Err(bad(offset, truncated())),
+    };
+    let mut crc = [0; 4];
+    reader
+        .seek(SeekFrom::Start(crc_offset as u64))
+        .and_then(|_| reader.read_exact(&mut crc))
+        .map_err(|e| bad(offset, e.into()))?;
+    Ok(ChunkHeader {
+        offset,
+        chunk_type,
+        length,
+        crc: u32::from_be_bytes(crc),
+    })
+}
+
+/// Reads the whole chunk `header` describes, checking its CRC if
+/// `check_crc`.
+fn read_chunk(
+    reader: &mut (impl Read + Seek),
+    header: &ChunkHeader,
+    check_crc: bool,
+) -> Result<Chunk, PngError> {
+    let mut bytes = vec![0; header.end() - header.offset];
+    reader
+        .seek(SeekFrom::Start(header.offset as u64))
+        .and_then(|_| reader.read_exact(&mut bytes))
+        .map_err(|e| bad(header.offset, e.into()))?;
+    match check_crc {
+        true => Chunk::try_from(bytes.as_slice()),
+        false => Chunk::from_bytes_unchecked(&bytes),
+    }
+    .map_err(|e| bad(header.offset, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::str::FromStr;
+
+    /// Counts the bytes read through it.
+    struct Counting<R> {
+        inner: R,
+        read: usize,
+    }
+
+    impl<R: Read> Read for Counting<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.inner.read(buf)?;
+            self.read += read;
+            Ok(read)
+        }
+    }
+
+    impl<R: Seek> Seek for Counting<R> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    fn testing_png() -> Png {
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), &[0; 13]),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), &[7; 100_000]),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello"),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), &[]),
+        ]);
+        png.set_trailer(b"after".to_vec());
+        png
+    }
+
+    #[test]
+    fn test_reads_only_what_is_asked_for() {
+        let png = testing_png();
+        let mut reader = Counting {
+            inner: Cursor::new(png.as_bytes()),
+            read: 0,
+        };
+        let mut lazy = LazyPng::open(&mut reader).unwrap();
+        assert_eq!(lazy.headers().len(), 4);
+        assert_eq!(lazy.headers()[1].length, 100_000);
+        assert_eq!(lazy.headers()[2].crc, png.chunks()[2].crc());
+        assert_eq!(lazy.trailer_len(), 5);
+        let chunk = lazy.chunk_by_type("ruSt").unwrap().unwrap();
+        assert_eq!(chunk.data(), b"hello");
+        assert!(lazy.chunk_by_type("teSt").unwrap().is_none());
+        drop(lazy);
+        // Headers, CRCs and the ruSt chunk, but none of the IDAT data.
+        assert!(reader.read < 100, "{}", reader.read);
+
+        let whole = LazyPng::open(Cursor::new(png.as_bytes())).unwrap();
+        assert_eq!(whole.into_png().unwrap().as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_find_chunk_in_reader() {
+        let bytes = testing_png().as_bytes();
+        let mut reader = Counting {
+            inner: Cursor::new(bytes.clone()),
+            read: 0,
+        };
+        let chunk = find_chunk_in_reader(&mut reader, "ruSt").unwrap().unwrap();
+        assert_eq!(chunk.data(), b"hello");
+        assert!(reader.read < 100, "{}", reader.read);
+        assert!(find_chunk_in_reader(Cursor::new(&bytes), "teSt")
+            .unwrap()
+            .is_none());
+        assert!(find_chunk_in_reader(Cursor::new(&bytes), "bad!").is_err());
+    }
+
+    #[test]
+    fn test_damage_is_found_on_access() {
+        let mut bytes = testing_png().as_bytes();
+        let idat = 8 + 25;
+        bytes[idat + 8 + 10] ^= 1;
+        let mut lazy = LazyPng::open(Cursor::new(bytes.clone())).unwrap();
+        assert_eq!(lazy.chunk(2).unwrap().data(), b"hello");
+        assert!(matches!(
+            lazy.chunk(1),
+            Err(PngError::BadChunk {
+                offset,
+                source: ChunkError::ChecksumError
+            }) if offset == idat
+        ));
+
+        bytes.truncate(idat + 1000);
+        assert!(matches!(
+            LazyPng::open(Cursor::new(bytes)),
+            Err(PngError::BadChunk { offset, .. }) if offset == idat
+        ));
+        assert!(matches!(
+            LazyPng::open(Cursor::new(b"GIF89a".to_vec())),
+            Err(PngError::InvalidHeader)
+        ));
+    }
+}
@@ -0,0 +1,126 @@
+//! Hiding payloads in PNG files.
+//!
+//! Without the default `std` feature, only [`chunk_type`], [`chunk`],
+//! [`png`] and what they are built on ([`crc`], [`decoder`], [`parse`],
+//! [`limits`], [`options`]) are available, with [`diagnostic`] to show
+//! their errors and [`warning`] what they get past, under `no_std` with
+//! `alloc`, for firmware and kernels that take PNG files apart. Everything else needs `std`, envelopes and
+//! the modules built on them need `crypto`, and only `fs` touches the
+//! filesystem.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "async")]
+pub mod asynchronous;
+#[cfg(feature = "std")]
+pub mod batch;
+#[cfg(feature = "crypto")]
+pub mod blake3;
+#[cfg(feature = "std")]
+pub mod carve;
+#[cfg(feature = "std")]
+pub mod census;
+#[cfg(feature = "std")]
+pub mod cgbi;
+pub mod chunk;
+pub mod chunk_type;
+#[cfg(feature = "std")]
+pub mod compression;
+#[cfg(feature = "std")]
+pub mod container;
+pub mod crc;
+#[cfg(feature = "crypto")]
+pub mod crypto;
+pub mod decoder;
+#[cfg(feature = "crypto")]
+pub mod detect;
+pub mod diagnostic;
+#[cfg(feature = "image")]
+pub mod dynamic_image;
+#[cfg(feature = "crypto")]
+pub mod envelope;
+#[cfg(feature = "std")]
+pub mod fec;
+#[cfg(all(feature = "fuse", target_os = "linux"))]
+pub mod fuse;
+#[cfg(feature = "crypto")]
+pub mod generate;
+#[cfg(feature = "std")]
+pub mod gif;
+#[cfg(feature = "crypto")]
+pub mod hash;
+#[cfg(feature = "std")]
+pub mod image;
+#[cfg(feature = "crypto")]
+pub mod index;
+#[cfg(feature = "std")]
+pub mod jpeg;
+#[cfg(feature = "std")]
+pub mod lazy;
+pub mod limits;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "crypto")]
+pub mod lsb;
+#[cfg(feature = "crypto")]
+pub mod messages;
+#[cfg(all(feature = "mmap", unix))]
+pub mod mmap;
+#[cfg(feature = "std")]
+pub mod normalize;
+#[cfg(feature = "openpgp")]
+pub mod openpgp;
+pub mod options;
+pub mod parallel;
+pub mod parse;
+#[cfg(feature = "std")]
+pub mod perceptual;
+#[cfg(feature = "fs")]
+pub mod pipeline;
+pub mod png;
+#[cfg(feature = "std")]
+pub mod polyglot;
+#[cfg(feature = "http")]
+pub mod remote;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod resync;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "std")]
+pub mod sanitize;
+#[cfg(feature = "std")]
+pub mod scan;
+#[cfg(feature = "crypto")]
+pub mod shamir;
+#[cfg(feature = "crypto")]
+pub mod stealth;
+#[cfg(feature = "fs")]
+pub mod storage;
+#[cfg(feature = "std")]
+pub mod stream;
+#[cfg(feature = "std")]
+pub mod timeline;
+#[cfg(feature = "std")]
+pub mod timestamp;
+mod trace;
+#[cfg(feature = "std")]
+pub mod trailer;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+pub mod uring;
+#[cfg(feature = "std")]
+pub mod vendor;
+#[cfg(feature = "std")]
+pub mod verify;
+#[cfg(feature = "std")]
+mod walk;
+pub mod warning;
+#[cfg(feature = "crypto")]
+pub mod watermark;
+#[cfg(feature = "std")]
+pub mod writer;
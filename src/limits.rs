@@ -0,0 +1,147 @@
+//! Caps on what parsing may allocate, for services that handle untrusted
+//! uploads.
+//!
+//! A crafted file can claim a chunk of 2 GiB, hold millions of empty chunks
+//! or carry a few kilobytes that decompress to gigabytes. The limits set
+//! here apply to every [`crate::decoder::Decoder`] created afterwards (and
+//! so to `Png::try_from` and the readers built on it), to payload
+//! decompression and to inflating pixels. A payload can also ask for a key
+//! derivation of gigabytes and minutes; the key derivation limits apply to
+//! every password-protected payload opened afterwards. By default nothing is
+//! capped.
+
+use core::fmt::{self, Display, Formatter};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    FileLen,
+    Chunks,
+    DecompressedLen,
+    KdfMemory,
+    KdfWork,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::FileLen => "file size",
+            Self::Chunks => "number of chunks",
+            Self::DecompressedLen => "decompressed size",
+            Self::KdfMemory => "key derivation memory",
+            Self::KdfWork => "key derivation work",
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    pub max: usize,
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "The {} exceeds the limit of {}", self.limit, self.max)
+    }
+}
+
+impl core::error::Error for LimitExceeded {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Limits {
+    /// Bytes of a whole file, trailer included.
+    pub max_file_len: usize,
+    /// Chunks in a file, `IEND` included.
+    pub max_chunks: usize,
+    /// Bytes a payload or the pixel data may inflate to.
+    pub max_decompressed_len: usize,
+    /// KiB of memory an Argon2id key derivation may take.
+    pub max_kdf_memory_kib: usize,
+    /// Work a key derivation may take: Argon2id memory in KiB times its
+    /// passes, or PBKDF2 iterations. Either unit is about a microsecond.
+    pub max_kdf_work: usize,
+}
+
+impl Limits {
+    pub const UNLIMITED: Self = Self {
+        max_file_len: usize::MAX,
+        max_chunks: usize::MAX,
+        max_decompressed_len: usize::MAX,
+        max_kdf_memory_kib: usize::MAX,
+        max_kdf_work: usize::MAX,
+    };
+
+    /// Fails if `value` is over the limit of `limit`.
+    pub fn check(&self, limit: Limit, value: usize) -> Result<(), LimitExceeded> {
+        let max = match limit {
+            Limit::FileLen => self.max_file_len,
+            Limit::Chunks => self.max_chunks,
+            Limit::DecompressedLen => self.max_decompressed_len,
+            Limit::KdfMemory => self.max_kdf_memory_kib,
+            Limit::KdfWork => self.max_kdf_work,
+        };
+        if value > max {
+            return Err(LimitExceeded { limit, max });
+        }
+        Ok(())
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self::UNLIMITED
+    }
+}
+
+static MAX_FILE_LEN: AtomicUsize = AtomicUsize::new(usize::MAX);
+static MAX_CHUNKS: AtomicUsize = AtomicUsize::new(usize::MAX);
+static MAX_DECOMPRESSED_LEN: AtomicUsize = AtomicUsize::new(usize::MAX);
+static MAX_KDF_MEMORY_KIB: AtomicUsize = AtomicUsize::new(usize::MAX);
+static MAX_KDF_WORK: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Sets the limits for the whole process.
+pub fn set(limits: Limits) {
+    MAX_FILE_LEN.store(limits.max_file_len, Ordering::Relaxed);
+    MAX_CHUNKS.store(limits.max_chunks, Ordering::Relaxed);
+    MAX_DECOMPRESSED_LEN.store(limits.max_decompressed_len, Ordering::Relaxed);
+    MAX_KDF_MEMORY_KIB.store(limits.max_kdf_memory_kib, Ordering::Relaxed);
+    MAX_KDF_WORK.store(limits.max_kdf_work, Ordering::Relaxed);
+}
+
+/// The limits last passed to [`set`].
+pub fn get() -> Limits {
+    Limits {
+        max_file_len: MAX_FILE_LEN.load(Ordering::Relaxed),
+        max_chunks: MAX_CHUNKS.load(Ordering::Relaxed),
+        max_decompressed_len: MAX_DECOMPRESSED_LEN.load(Ordering::Relaxed),
+        max_kdf_memory_kib: MAX_KDF_MEMORY_KIB.load(Ordering::Relaxed),
+        max_kdf_work: MAX_KDF_WORK.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let limits = Limits {
+            max_chunks: 3,
+            ..Limits::UNLIMITED
+        };
+        assert!(limits.check(Limit::Chunks, 3).is_ok());
+        assert_eq!(
+            limits.check(Limit::Chunks, 4),
+            Err(LimitExceeded {
+                limit: Limit::Chunks,
+                max: 3
+            })
+        );
+        assert!(limits.check(Limit::FileLen, usize::MAX).is_ok());
+        assert_eq!(
+            limits.check(Limit::Chunks, 4).unwrap_err().to_string(),
+            "The number of chunks exceeds the limit of 3"
+        );
+    }
+}
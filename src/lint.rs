@@ -0,0 +1,1502 @@
+//! Checking a PNG file against the specification, as pngcheck does: the
+//! fields of each standard chunk, how chunks agree with each other (the
+//! palette with the bit depth, `tRNS` with the color type), chunks that
+//! may appear only once, and the order chunks must come in.
+//!
+//! Every finding belongs to a [`Rule`] whose ID never changes, so scripts
+//! can match on it and `lint --allow` can silence it. Unknown ancillary
+//! chunks are never reported: decoders are free to skip them, and that is
+//! where pngme payloads live. The vendor chunks of [`crate::vendor`] are
+//! only checked for the layout their tools expect.
+//!
+//! The image data is inflated, under [`crate::limits`], and its scanlines
+//! checked against `IHDR`: how many bytes there are and that each starts
+//! with a known filter type.
+//!
+//! What counts as unknown depends on the [`Profile`] a file is checked
+//! against: an `eXIf` chunk is unknown to PNG 1.2, so only its absence from
+//! that version is reported, while the third edition checks where it is
+//! and what it holds.
+
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use flate2::{Decompress, FlushDecompress, Status};
+use thiserror::Error;
+
+use crate::cgbi;
+use crate::chunk::Chunk;
+use crate::image::{self, ColorType};
+use crate::limits;
+use crate::png::Png;
+use crate::trace;
+use crate::vendor::{self, VendorError};
+
+/// How bad a finding is: errors make decoders reject the file or guess,
+/// warnings are legal but likely mistakes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// What a finding is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Rule {
+    BadCrc,
+    MissingHeader,
+    MissingData,
+    MissingEnd,
+    SplitData,
+    Duplicate,
+    Misordered,
+    UnknownCritical,
+    ReservedBit,
+    MissingPalette,
+    UnexpectedPalette,
+    IccAndSrgb,
+    TrailingData,
+    NotInProfile,
+    BadLength,
+    BadDimensions,
+    BadBitDepth,
+    BadMethod,
+    BadPalette,
+    PaletteTooLarge,
+    UnexpectedTransparency,
+    SampleOutOfRange,
+    BadKeyword,
+    BadValue,
+    BadText,
+    BadCompressedData,
+    BadAnimation,
+}
+
+impl Rule {
+    pub const ALL: [Self; 27] = [
+        Self::BadCrc,
+        Self::MissingHeader,
+        Self::MissingData,
+        Self::MissingEnd,
+        Self::SplitData,
+        Self::Duplicate,
+        Self::Misordered,
+        Self::UnknownCritical,
+        Self::ReservedBit,
+        Self::MissingPalette,
+        Self::UnexpectedPalette,
+        Self::IccAndSrgb,
+        Self::TrailingData,
+        Self::NotInProfile,
+        Self::BadLength,
+        Self::BadDimensions,
+        Self::BadBitDepth,
+        Self::BadMethod,
+        Self::BadPalette,
+        Self::PaletteTooLarge,
+        Self::UnexpectedTransparency,
+        Self::SampleOutOfRange,
+        Self::BadKeyword,
+        Self::BadValue,
+        Self::BadText,
+        Self::BadCompressedData,
+        Self::BadAnimation,
+    ];
+
+    /// The stable ID: `P0xx` for the file's structure, `P1xx` for the
+    /// contents of a chunk.
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::BadCrc => "P001",
+            Self::MissingHeader => "P002",
+            Self::MissingData => "P003",
+            Self::MissingEnd => "P004",
+            Self::SplitData => "P005",
+            Self::Duplicate => "P006",
+            Self::Misordered => "P007",
+            Self::UnknownCritical => "P008",
+            Self::ReservedBit => "P009",
+            Self::MissingPalette => "P010",
+            Self::UnexpectedPalette => "P011",
+            Self::IccAndSrgb => "P012",
+            Self::TrailingData => "P013",
+            Self::NotInProfile => "P014",
+            Self::BadLength => "P101",
+            Self::BadDimensions => "P102",
+            Self::BadBitDepth => "P103",
+            Self::BadMethod => "P104",
+            Self::BadPalette => "P105",
+            Self::PaletteTooLarge => "P106",
+            Self::UnexpectedTransparency => "P107",
+            Self::SampleOutOfRange => "P108",
+            Self::BadKeyword => "P109",
+            Self::BadValue => "P110",
+            Self::BadText => "P111",
+            Self::BadCompressedData => "P112",
+            Self::BadAnimation => "P113",
+        }
+    }
+    pub fn severity(self) -> Severity {
+        match self {
+            Self::IccAndSrgb | Self::TrailingData | Self::NotInProfile | Self::BadText => {
+                Severity::Warning
+            }
+            _ => Severity::Error,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown lint {0:?} (expected an ID such as P006)")]
+pub struct UnknownRule(String);
+
+impl FromStr for Rule {
+    type Err = UnknownRule;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|rule| rule.id().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnknownRule(s.to_string()))
+    }
+}
+
+/// The version of the specification a file is checked against.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// PNG 1.2 and its registered extensions (`oFFs`, `pCAL`, `sCAL`,
+    /// `sTER`).
+    Png12,
+    /// The third edition: PNG 1.2 with `eXIf`, `cICP`, `mDCV`, `cLLI` and
+    /// the APNG chunks, which it makes standard.
+    #[default]
+    Third,
+    /// PNG 1.2 with the APNG extension, for files that must be animated.
+    Apng,
+}
+
+impl Profile {
+    /// Whether this version of the specification defines `code`.
+    pub fn defines(self, code: &[u8; 4]) -> bool {
+        PNG_1_2.contains(&code)
+            || match self {
+                Self::Png12 => false,
+                Self::Third => THIRD_EDITION.contains(&code),
+                Self::Apng => ANIMATION.contains(&code),
+            }
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Png12 => write!(f, "PNG 1.2"),
+            Self::Third => write!(f, "the third edition"),
+            Self::Apng => write!(f, "APNG"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown profile {0:?} (expected 1.2, third or apng)")]
+pub struct UnknownProfile(String);
+
+impl FromStr for Profile {
+    type Err = UnknownProfile;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "1.2" | "png-1.2" => Ok(Self::Png12),
+            "third" | "3" => Ok(Self::Third),
+            "apng" => Ok(Self::Apng),
+            _ => Err(UnknownProfile(s.to_string())),
+        }
+    }
+}
+
+/// One finding.
+#[derive(Clone, Debug, PartialEq, Eq, Error)]
+#[error("{} {message}", .rule.id())]
+pub struct Lint {
+    pub rule: Rule,
+    /// The index of the chunk it is about, if it is about one.
+    pub chunk: Option<usize>,
+    pub message: String,
+}
+
+impl Lint {
+    pub fn severity(&self) -> Severity {
+        self.rule.severity()
+    }
+}
+
+/// Chunks of PNG 1.2 and its registered extensions.
+const PNG_1_2: &[&[u8; 4]] = &[
+    b"IHDR", b"PLTE", b"IDAT", b"IEND", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"bKGD",
+    b"hIST", b"tRNS", b"pHYs", b"sPLT", b"tIME", b"iTXt", b"tEXt", b"zTXt", b"oFFs", b"pCAL",
+    b"sCAL", b"sTER",
+];
+/// Chunks of the APNG extension.
+const ANIMATION: &[&[u8; 4]] = &[b"acTL", b"fcTL", b"fdAT"];
+/// Chunks the third edition adds to PNG 1.2.
+const THIRD_EDITION: &[&[u8; 4]] = &[
+    b"eXIf", b"cICP", b"mDCV", b"cLLI", b"acTL", b"fcTL", b"fdAT",
+];
+/// Chunks that may appear at most once.
+pub(crate) const SINGLE: &[&[u8; 4]] = &[
+    b"IHDR", b"PLTE", b"IEND", b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCV",
+    b"cLLI", b"bKGD", b"hIST", b"tRNS", b"eXIf", b"pHYs", b"tIME", b"oFFs", b"pCAL", b"sCAL",
+    b"sTER", b"acTL",
+];
+/// Chunks that must come before `PLTE` and `IDAT`.
+const BEFORE_PALETTE: &[&[u8; 4]] = &[
+    b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCV", b"cLLI",
+];
+/// Chunks that must come after `PLTE` and before `IDAT`.
+const AFTER_PALETTE: &[&[u8; 4]] = &[b"bKGD", b"hIST", b"tRNS"];
+/// Other chunks that must come before `IDAT`. The third edition adds
+/// `eXIf`, which earlier extensions allowed anywhere and many files still
+/// have after `IDAT`.
+const BEFORE_DATA: &[&[u8; 4]] = &[
+    b"PLTE", b"pHYs", b"sPLT", b"oFFs", b"pCAL", b"sCAL", b"sTER", b"acTL",
+];
+const CRITICAL: &[&[u8; 4]] = &[b"IHDR", b"PLTE", b"IDAT", b"IEND"];
+
+/// What the rest of the file is checked against.
+struct Header {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: ColorType,
+}
+
+impl Header {
+    /// The largest value of a sample, or of an index.
+    fn max_sample(&self) -> u16 {
+        ((1u32 << self.bit_depth) - 1) as u16
+    }
+}
+
+struct Linter {
+    profile: Profile,
+    lints: Vec<Lint>,
+    header: Option<Header>,
+    /// Entries in `PLTE`.
+    palette: Option<usize>,
+}
+
+impl Linter {
+    fn report(&mut self, rule: Rule, chunk: Option<usize>, message: String) {
+        self.lints.push(Lint {
+            rule,
+            chunk,
+            message,
+        });
+    }
+}
+
+/// Lints `png` against the third edition; see [`lint_with_profile`].
+pub fn lint(png: &Png) -> Vec<Lint> {
+    lint_with_profile(png, Profile::default())
+}
+
+/// Lints `png` against `profile`. It should have been read with
+/// [`Png::from_bytes_unchecked`] so damaged chunks are reported rather than
+/// refused. Findings come in the order of the chunks they are about, those
+/// about the whole file last.
+pub fn lint_with_profile(png: &Png, profile: Profile) -> Vec<Lint> {
+    trace::span!("lint", ?profile, chunks = png.chunks().len());
+    let mut linter = Linter {
+        profile,
+        lints: Vec::new(),
+        header: None,
+        palette: None,
+    };
+    let chunks = png.chunks();
+    let types: Vec<[u8; 4]> = chunks.iter().map(|c| c.chunk_type().bytes()).collect();
+    let position = |code: &[u8; 4]| types.iter().position(|t| t == code);
+    let palette_at = position(b"PLTE");
+    let data_at = position(b"IDAT");
+
+    if types.first() != Some(b"IHDR") {
+        let found = match chunks.first() {
+            Some(chunk) => format!("the first chunk is {}", chunk.chunk_type()),
+            None => "there are no chunks".to_string(),
+        };
+        linter.report(
+            Rule::MissingHeader,
+            None,
+            format!("IHDR must be the first chunk, but {}", found),
+        );
+    }
+    // Later checks depend on the header and the palette, wherever they are.
+    if let Some(index) = position(b"IHDR") {
+        linter.header = check_header(&mut linter, index, chunks[index].data());
+    }
+    if let Some(index) = palette_at {
+        linter.palette = check_palette(&mut linter, index, chunks[index].data());
+    }
+
+    let mut idat_ended = false;
+    for (index, chunk) in chunks.iter().enumerate() {
+        let code = &types[index];
+        let name = chunk.chunk_type().to_string();
+        let at = Some(index);
+        if !chunk.has_valid_crc() {
+            linter.report(
+                Rule::BadCrc,
+                at,
+                format!("{} has a CRC of {:#010x}", name, chunk.crc()),
+            );
+        }
+        if !chunk.chunk_type().is_reserved_bit_valid() {
+            linter.report(
+                Rule::ReservedBit,
+                at,
+                format!("{} has the reserved bit (third letter) set", name),
+            );
+        }
+        if code == cgbi::CHUNK_TYPE {
+            linter.report(
+                Rule::UnknownCritical,
+                at,
+                "CgBI marks an Apple CgBI image, which only Apple's decoders read".to_string(),
+            );
+        } else if chunk.chunk_type().is_critical() && !CRITICAL.contains(&code) {
+            linter.report(
+                Rule::UnknownCritical,
+                at,
+                format!("{} is critical, but decoders don't know it", name),
+            );
+        }
+        if code == b"IDAT" {
+            if idat_ended {
+                linter.report(
+                    Rule::SplitData,
+                    at,
+                    "IDAT chunks must be consecutive".to_string(),
+                );
+                idat_ended = false;
+            }
+        } else if index > 0 && types[index - 1] == *b"IDAT" {
+            idat_ended = true;
+        }
+        if !profile.defines(code) {
+            if THIRD_EDITION.contains(&code) {
+                linter.report(
+                    Rule::NotInProfile,
+                    at,
+                    format!("{} isn't part of {}", name, profile),
+                );
+            }
+            check_vendor(&mut linter, png, index, chunk);
+            continue;
+        }
+        if SINGLE.contains(&code) && types[..index].contains(code) {
+            linter.report(Rule::Duplicate, at, format!("{} appears again", name));
+        }
+        order(&mut linter, index, code, &name, palette_at, data_at);
+        check_chunk(&mut linter, index, chunk);
+    }
+
+    match data_at {
+        Some(index) if position(cgbi::CHUNK_TYPE).is_none() => {
+            check_image_data(&mut linter, chunks, index)
+        }
+        Some(_) => {}
+        None => linter.report(Rule::MissingData, None, "No IDAT chunk".to_string()),
+    }
+    if profile.defines(b"acTL") {
+        animation(&mut linter, chunks, data_at);
+    }
+    if types.last() != Some(b"IEND") {
+        linter.report(
+            Rule::MissingEnd,
+            None,
+            "The file ends without an IEND chunk".to_string(),
+        );
+    }
+    if let Some(header) = &linter.header {
+        match (header.color_type, palette_at) {
+            (ColorType::Indexed, None) => linter.report(
+                Rule::MissingPalette,
+                None,
+                "Indexed images need a PLTE chunk".to_string(),
+            ),
+            (ColorType::Grayscale | ColorType::GrayscaleAlpha, Some(index)) => linter.report(
+                Rule::UnexpectedPalette,
+                Some(index),
+                "Grayscale images can't have a PLTE chunk".to_string(),
+            ),
+            _ => {}
+        }
+    }
+    if let (Some(icc), Some(_)) = (position(b"iCCP"), position(b"sRGB")) {
+        linter.report(
+            Rule::IccAndSrgb,
+            Some(icc),
+            "iCCP and sRGB both give the color space".to_string(),
+        );
+    }
+    if !png.trailer().is_empty() {
+        linter.report(
+            Rule::TrailingData,
+            None,
+            format!("{} bytes follow IEND", png.trailer().len()),
+        );
+    }
+    let mut lints = linter.lints;
+    lints.sort_by_key(|lint| lint.chunk.unwrap_or(usize::MAX));
+    trace::event!(debug, lints = lints.len(), "linted");
+    lints
+}
+
+fn order(
+    linter: &mut Linter,
+    index: usize,
+    code: &[u8; 4],
+    name: &str,
+    palette_at: Option<usize>,
+    data_at: Option<usize>,
+) {
+    let at = Some(index);
+    if BEFORE_PALETTE.contains(&code) && palette_at.is_some_and(|p| p < index) {
+        linter.report(
+            Rule::Misordered,
+            at,
+            format!("{} must come before PLTE", name),
+        );
+    } else if code == b"fdAT" && data_at.is_none_or(|d| d > index) {
+        linter.report(
+            Rule::Misordered,
+            at,
+            "fdAT must come after IDAT".to_string(),
+        );
+    } else if AFTER_PALETTE.contains(&code) && palette_at.is_some_and(|p| p > index) {
+        linter.report(
+            Rule::Misordered,
+            at,
+            format!("{} must come after PLTE", name),
+        );
+    } else if (BEFORE_PALETTE.contains(&code)
+        || AFTER_PALETTE.contains(&code)
+        || BEFORE_DATA.contains(&code))
+        && data_at.is_some_and(|d| d < index)
+    {
+        linter.report(
+            Rule::Misordered,
+            at,
+            format!("{} must come before IDAT", name),
+        );
+    }
+}
+
+fn check_header(linter: &mut Linter, index: usize, data: &[u8]) -> Option<Header> {
+    let at = Some(index);
+    let Ok(data) = <&[u8; 13]>::try_from(data) else {
+        linter.report(
+            Rule::BadLength,
+            at,
+            format!("IHDR is {} bytes, expected 13", data.len()),
+        );
+        return None;
+    };
+    let width = u32::from_be_bytes(data[..4].try_into().expect("4 bytes"));
+    let height = u32::from_be_bytes(data[4..8].try_into().expect("4 bytes"));
+    if !(1..=Chunk::MAX_LENGTH).contains(&width) || !(1..=Chunk::MAX_LENGTH).contains(&height) {
+        linter.report(
+            Rule::BadDimensions,
+            at,
+            format!(
+                "The image is {}x{}, dimensions must be 1 to 2^31 - 1",
+                width, height
+            ),
+        );
+    }
+    let (bit_depth, color_type) = (data[8], data[9]);
+    for (field, value, max) in [
+        ("compression", data[10], 0),
+        ("filter", data[11], 0),
+        ("interlace", data[12], 1),
+    ] {
+        if value > max {
+            linter.report(
+                Rule::BadMethod,
+                at,
+                format!("Unknown {} method {} in IHDR", field, value),
+            );
+        }
+    }
+    let header = ColorType::from_id(color_type)
+        .ok()
+        .filter(|color_type| color_type.allows_bit_depth(bit_depth))
+        .map(|color_type| Header {
+            width,
+            height,
+            bit_depth,
+            color_type,
+        });
+    if header.is_none() {
+        linter.report(
+            Rule::BadBitDepth,
+            at,
+            format!(
+                "Color type {} with bit depth {} isn't a valid combination",
+                color_type, bit_depth
+            ),
+        );
+    }
+    header
+}
+
+fn check_palette(linter: &mut Linter, index: usize, data: &[u8]) -> Option<usize> {
+    let at = Some(index);
+    if data.is_empty() || !data.len().is_multiple_of(3) || data.len() > 3 * 256 {
+        linter.report(
+            Rule::BadPalette,
+            at,
+            format!(
+                "PLTE is {} bytes, expected 3 per entry for 1 to 256 entries",
+                data.len()
+            ),
+        );
+        return None;
+    }
+    let entries = data.len() / 3;
+    if let Some(header) = &linter.header {
+        let max = header.max_sample() as usize + 1;
+        if header.color_type == ColorType::Indexed && entries > max {
+            linter.report(
+                Rule::PaletteTooLarge,
+                at,
+                format!(
+                    "PLTE has {} entries, more than {}-bit indices reach",
+                    entries, header.bit_depth
+                ),
+            );
+        }
+    }
+    Some(entries)
+}
+
+/// Checks a vendor chunk against the layout its tool writes, and that an
+/// `iDOT` still points at the image data.
+fn check_vendor(linter: &mut Linter, png: &Png, index: usize, chunk: &Chunk) {
+    let at = Some(index);
+    match vendor::parse(chunk) {
+        Some(Err(e @ VendorError::Length { .. })) => {
+            linter.report(Rule::BadLength, at, e.to_string())
+        }
+        Some(Err(e)) => linter.report(Rule::BadValue, at, e.to_string()),
+        _ => {}
+    }
+    for offset in vendor::stale_idot_offsets(png, index) {
+        linter.report(
+            Rule::BadValue,
+            at,
+            format!(
+                "iDOT points {} bytes on, where no IDAT chunk starts",
+                offset
+            ),
+        );
+    }
+}
+
+/// Checks what depends only on the chunk's own type and data, and the
+/// header and palette.
+fn check_chunk(linter: &mut Linter, index: usize, chunk: &Chunk) {
+    let at = Some(index);
+    let data = chunk.data();
+    let name = chunk.chunk_type().to_string();
+    let fixed = match &chunk.chunk_type().bytes() {
+        b"IEND" => Some(0),
+        b"gAMA" => Some(4),
+        b"cHRM" => Some(32),
+        b"sRGB" => Some(1),
+        b"pHYs" => Some(9),
+        b"tIME" => Some(7),
+        b"cICP" => Some(4),
+        b"acTL" => Some(8),
+        b"fcTL" => Some(26),
+        _ => None,
+    };
+    if let Some(len) = fixed.filter(|&len| len != data.len()) {
+        linter.report(
+            Rule::BadLength,
+            at,
+            format!("{} is {} bytes, expected {}", name, data.len(), len),
+        );
+        return;
+    }
+    let mut bad_value = |message: String| linter.report(Rule::BadValue, at, message);
+    match &chunk.chunk_type().bytes() {
+        b"gAMA" if data == [0; 4] => bad_value("gAMA is 0".to_string()),
+        b"sRGB" if data[0] > 3 => bad_value(format!("Unknown sRGB rendering intent {}", data[0])),
+        b"pHYs" if data[8] > 1 => bad_value(format!("Unknown pHYs unit {}", data[8])),
+        b"tIME" => {
+            let fields = [
+                ("month", data[2], 1, 12),
+                ("day", data[3], 1, 31),
+                ("hour", data[4], 0, 23),
+                ("minute", data[5], 0, 59),
+                ("second", data[6], 0, 60),
+            ];
+            for (field, value, min, max) in fields {
+                if !(min..=max).contains(&value) {
+                    bad_value(format!("tIME has {} {}", field, value));
+                }
+            }
+        }
+        b"eXIf" if !data.starts_with(b"MM\0*") && !data.starts_with(b"II*\0") => {
+            bad_value("eXIf doesn't start with a TIFF header".to_string())
+        }
+        b"tRNS" => check_transparency(linter, index, data),
+        b"bKGD" => check_background(linter, index, data),
+        b"sBIT" => check_significant_bits(linter, index, data),
+        b"hIST" => match linter.palette {
+            Some(entries) if data.len() != 2 * entries => linter.report(
+                Rule::BadLength,
+                at,
+                format!(
+                    "hIST is {} bytes, expected 2 per palette entry ({})",
+                    data.len(),
+                    2 * entries
+                ),
+            ),
+            Some(_) => {}
+            None => linter.report(
+                Rule::MissingPalette,
+                at,
+                "hIST needs a PLTE chunk".to_string(),
+            ),
+        },
+        b"tEXt" => {
+            if let Some(text) = keyword(linter, index, &name, data) {
+                if text.iter().any(|&b| b < b' ' && b != b'\n') {
+                    linter.report(
+                        Rule::BadText,
+                        at,
+                        "tEXt has control characters other than newlines".to_string(),
+                    );
+                }
+            }
+        }
+        b"zTXt" | b"iCCP" => {
+            if let Some(rest) = keyword(linter, index, &name, data) {
+                compressed(linter, index, &name, rest);
+            }
+        }
+        b"iTXt" => {
+            if let Some(rest) = keyword(linter, index, &name, data) {
+                international_text(linter, index, rest);
+            }
+        }
+        b"sPLT" => {
+            if let Some(rest) = keyword(linter, index, &name, data) {
+                let entry_len = match rest.first() {
+                    Some(8) => 6,
+                    Some(16) => 10,
+                    _ => {
+                        linter.report(
+                            Rule::BadValue,
+                            at,
+                            "sPLT sample depth must be 8 or 16".to_string(),
+                        );
+                        return;
+                    }
+                };
+                if !(rest.len() - 1).is_multiple_of(entry_len) {
+                    linter.report(
+                        Rule::BadLength,
+                        at,
+                        format!("sPLT entries aren't {} bytes each", entry_len),
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn check_transparency(linter: &mut Linter, index: usize, data: &[u8]) {
+    let Some(header) = &linter.header else {
+        return;
+    };
+    let at = Some(index);
+    let (color_type, max) = (header.color_type, header.max_sample());
+    let expected = match color_type {
+        ColorType::Grayscale => 2,
+        ColorType::Rgb => 6,
+        ColorType::Indexed => {
+            let entries = linter.palette.unwrap_or(256);
+            if data.is_empty() || data.len() > entries {
+                linter.report(
+                    Rule::BadLength,
+                    at,
+                    format!(
+                        "tRNS has {} entries, expected 1 to {}, one per palette entry",
+                        data.len(),
+                        entries
+                    ),
+                );
+            }
+            return;
+        }
+        ColorType::GrayscaleAlpha | ColorType::Rgba => {
+            linter.report(
+                Rule::UnexpectedTransparency,
+                at,
+                "tRNS isn't allowed in images with an alpha channel".to_string(),
+            );
+            return;
+        }
+    };
+    samples(linter, index, "tRNS", data, expected, max);
+}
+
+fn check_background(linter: &mut Linter, index: usize, data: &[u8]) {
+    let Some(header) = &linter.header else {
+        return;
+    };
+    let (color_type, max) = (header.color_type, header.max_sample());
+    let expected = match color_type {
+        ColorType::Indexed => {
+            if data.len() != 1 {
+                bad_length(linter, index, "bKGD", data, 1);
+            } else if let Some(entries) = linter.palette.filter(|&n| data[0] as usize >= n) {
+                linter.report(
+                    Rule::SampleOutOfRange,
+                    Some(index),
+                    format!(
+                        "bKGD is palette entry {}, but there are {}",
+                        data[0], entries
+                    ),
+                );
+            }
+            return;
+        }
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => 2,
+        ColorType::Rgb | ColorType::Rgba => 6,
+    };
+    samples(linter, index, "bKGD", data, expected, max);
+}
+
+fn check_significant_bits(linter: &mut Linter, index: usize, data: &[u8]) {
+    let Some(header) = &linter.header else {
+        return;
+    };
+    let (expected, depth) = match header.color_type {
+        ColorType::Indexed => (3, 8),
+        color_type => (color_type.channels(), header.bit_depth),
+    };
+    if data.len() != expected {
+        return bad_length(linter, index, "sBIT", data, expected);
+    }
+    if data.iter().any(|&bits| bits == 0 || bits > depth) {
+        linter.report(
+            Rule::SampleOutOfRange,
+            Some(index),
+            format!("sBIT values must be 1 to {}", depth),
+        );
+    }
+}
+
+/// Checks the animation chunks: that `acTL` counts the frames, that
+/// sequence numbers run from 0 across `fcTL` and `fdAT`, and that frames
+/// fit the image.
+fn animation(linter: &mut Linter, chunks: &[Chunk], data_at: Option<usize>) {
+    let control = chunks
+        .iter()
+        .position(|chunk| chunk.chunk_type().bytes() == *b"acTL");
+    let frames: Vec<(usize, &Chunk)> = chunks
+        .iter()
+        .enumerate()
+        .filter(|(_, chunk)| chunk.chunk_type().bytes() == *b"fcTL")
+        .collect();
+    match control {
+        None if linter.profile == Profile::Apng => linter.report(
+            Rule::BadAnimation,
+            None,
+            "APNG files need an acTL chunk".to_string(),
+        ),
+        None => {
+            if let Some(&(index, _)) = frames.first() {
+                linter.report(
+                    Rule::BadAnimation,
+                    Some(index),
+                    "fcTL without an acTL chunk".to_string(),
+                );
+            }
+            return;
+        }
+        Some(index) => {
+            let data = chunks[index].data();
+            if data.len() == 8 {
+                let announced = u32::from_be_bytes(data[..4].try_into().expect("4 bytes"));
+                if announced == 0 || announced as usize != frames.len() {
+                    linter.report(
+                        Rule::BadAnimation,
+                        Some(index),
+                        format!(
+                            "acTL announces {} frames, but there are {} fcTL chunks",
+                            announced,
+                            frames.len()
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    let mut expected = 0;
+    for (index, chunk) in chunks.iter().enumerate() {
+        if !matches!(&chunk.chunk_type().bytes(), b"fcTL" | b"fdAT") {
+            continue;
+        }
+        let Some(number) = chunk.data().get(..4) else {
+            // A short fcTL is reported with the other fixed lengths.
+            if chunk.chunk_type().bytes() == *b"fdAT" {
+                bad_length(linter, index, "fdAT", chunk.data(), 4);
+            }
+            continue;
+        };
+        let number = u32::from_be_bytes(number.try_into().expect("4 bytes"));
+        if number != expected {
+            linter.report(
+                Rule::BadAnimation,
+                Some(index),
+                format!(
+                    "{} has sequence number {}, expected {}",
+                    chunk.chunk_type(),
+                    number,
+                    expected
+                ),
+            );
+        }
+        expected = number.wrapping_add(1);
+    }
+
+    let Some(header) = &linter.header else {
+        return;
+    };
+    let (image_width, image_height) = (header.width, header.height);
+    for (n, &(index, chunk)) in frames.iter().enumerate() {
+        let Ok(data) = <&[u8; 26]>::try_from(chunk.data()) else {
+            continue;
+        };
+        let field = |at: usize| u32::from_be_bytes(data[at..at + 4].try_into().expect("4 bytes"));
+        let (width, height, x, y) = (field(4), field(8), field(12), field(16));
+        let fits = width > 0
+            && height > 0
+            && x.checked_add(width)
+                .is_some_and(|right| right <= image_width)
+            && y.checked_add(height)
+                .is_some_and(|bottom| bottom <= image_height);
+        let at = Some(index);
+        if !fits {
+            linter.report(
+                Rule::BadAnimation,
+                at,
+                format!(
+                    "Frame {} is {}x{} at {},{}, outside the {}x{} image",
+                    n, width, height, x, y, image_width, image_height
+                ),
+            );
+        } else if n == 0
+            && data_at.is_some_and(|d| d > index)
+            && (x, y, width, height) != (0, 0, image_width, image_height)
+        {
+            linter.report(
+                Rule::BadAnimation,
+                at,
+                "The frame made of IDAT must cover the whole image".to_string(),
+            );
+        }
+        if data[24] > 2 || data[25] > 1 {
+            linter.report(
+                Rule::BadValue,
+                at,
+                format!(
+                    "Unknown fcTL dispose or blend operation {}, {}",
+                    data[24], data[25]
+                ),
+            );
+        }
+    }
+}
+
+/// Checks 16-bit samples of at most `max`.
+fn samples(linter: &mut Linter, index: usize, name: &str, data: &[u8], len: usize, max: u16) {
+    if data.len() != len {
+        return bad_length(linter, index, name, data, len);
+    }
+    let over = data
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .find(|&sample| sample > max);
+    if let Some(sample) = over {
+        linter.report(
+            Rule::SampleOutOfRange,
+            Some(index),
+            format!(
+                "{} has sample {}, more than the bit depth allows",
+                name, sample
+            ),
+        );
+    }
+}
+
+fn bad_length(linter: &mut Linter, index: usize, name: &str, data: &[u8], expected: usize) {
+    linter.report(
+        Rule::BadLength,
+        Some(index),
+        format!("{} is {} bytes, expected {}", name, data.len(), expected),
+    );
+}
+
+/// Checks the keyword `data` starts with and returns what follows it.
+fn keyword<'a>(linter: &mut Linter, index: usize, name: &str, data: &'a [u8]) -> Option<&'a [u8]> {
+    let Some(end) = data.iter().position(|&b| b == 0) else {
+        linter.report(
+            Rule::BadKeyword,
+            Some(index),
+            format!("{} has no null byte after its keyword", name),
+        );
+        return None;
+    };
+    let keyword = &data[..end];
+    let problem = if keyword.is_empty() || keyword.len() > 79 {
+        Some("must be 1 to 79 bytes")
+    } else if keyword
+        .iter()
+        .any(|&b| !(b' '..=b'~').contains(&b) && b < 0xa1)
+    {
+        Some("has characters other than printable Latin-1")
+    } else if keyword.starts_with(b" ") || keyword.ends_with(b" ") {
+        Some("starts or ends with a space")
+    } else if keyword.windows(2).any(|pair| pair == b"  ") {
+        Some("has consecutive spaces")
+    } else {
+        None
+    };
+    if let Some(problem) = problem {
+        linter.report(
+            Rule::BadKeyword,
+            Some(index),
+            format!(
+                "{} keyword {:?} {}",
+                name,
+                String::from_utf8_lossy(keyword),
+                problem
+            ),
+        );
+    }
+    Some(&data[end + 1..])
+}
+
+/// Checks a compression method byte and the zlib stream behind it.
+fn compressed(linter: &mut Linter, index: usize, name: &str, data: &[u8]) {
+    match data.first() {
+        Some(0) => {
+            if let Err(e) = inflate(&data[1..]) {
+                linter.report(
+                    Rule::BadCompressedData,
+                    Some(index),
+                    format!("{} doesn't inflate: {}", name, e),
+                );
+            }
+        }
+        method => linter.report(
+            Rule::BadMethod,
+            Some(index),
+            match method {
+                Some(method) => format!("Unknown {} compression method {}", name, method),
+                None => format!("{} has no compression method", name),
+            },
+        ),
+    }
+}
+
+/// Checks what follows the keyword of an `iTXt` chunk.
+fn international_text(linter: &mut Linter, index: usize, data: &[u8]) {
+    let at = Some(index);
+    let fields = data.get(2..).map(|rest| {
+        let mut fields = rest.splitn(3, |&b| b == 0);
+        (fields.next(), fields.next(), fields.next())
+    });
+    let Some((Some(_language), Some(translated), Some(text))) = fields else {
+        linter.report(Rule::BadLength, at, "iTXt ends before its text".to_string());
+        return;
+    };
+    if std::str::from_utf8(translated).is_err() {
+        linter.report(
+            Rule::BadText,
+            at,
+            "iTXt translated keyword isn't UTF-8".to_string(),
+        );
+    }
+    match data[0] {
+        0 if std::str::from_utf8(text).is_err() => {
+            linter.report(Rule::BadText, at, "iTXt text isn't UTF-8".to_string())
+        }
+        0 => {}
+        1 => compressed(linter, index, "iTXt", &[&[data[1]], text].concat()),
+        flag => linter.report(
+            Rule::BadValue,
+            at,
+            format!("Unknown iTXt compression flag {}", flag),
+        ),
+    }
+}
+
+/// Inflates the `IDAT` chunks, the first at `index`, without keeping what
+/// comes out, checking the scanlines against `IHDR`. Files whose `IHDR` is
+/// unusable have been reported already, and CgBI files store raw deflate
+/// data, so neither gets here.
+fn check_image_data(linter: &mut Linter, chunks: &[Chunk], index: usize) {
+    let header = chunks
+        .iter()
+        .find(|c| c.chunk_type().bytes() == *b"IHDR")
+        .and_then(|c| image::Header::try_from(c.data()).ok());
+    let Some((header, expected)) = header.and_then(|h| Some((h, h.stream_len()?))) else {
+        return;
+    };
+    let at = Some(index);
+    // Past the limit the data isn't checked; past the expected length it
+    // is known to be wrong.
+    let cap = expected.min(limits::get().max_decompressed_len);
+    let mut inputs = chunks
+        .iter()
+        .filter(|c| c.chunk_type().bytes() == *b"IDAT")
+        .map(Chunk::data);
+    let mut input: &[u8] = &[];
+    let mut decompress = Decompress::new(true);
+    let mut out = vec![0; 64 * 1024];
+    let mut scanlines = header.scanline_lens();
+    let (mut scanline, mut left, mut bad_filter) = (0, 0, None);
+    let ended = loop {
+        if input.is_empty() {
+            match inputs.next() {
+                Some(data) => input = data,
+                None => break Ok(false),
+            }
+        }
+        let (before_in, before_out) = (decompress.total_in(), decompress.total_out());
+        let status = match decompress.decompress(input, &mut out, FlushDecompress::None) {
+            Ok(status) => status,
+            Err(e) => break Err(e.to_string()),
+        };
+        let consumed = (decompress.total_in() - before_in) as usize;
+        let produced = (decompress.total_out() - before_out) as usize;
+        input = if consumed == 0 && produced == 0 {
+            &[]
+        } else {
+            &input[consumed..]
+        };
+        let mut bytes = &out[..produced];
+        while !bytes.is_empty() {
+            if left > 0 {
+                let skipped = left.min(bytes.len());
+                left -= skipped;
+                bytes = &bytes[skipped..];
+                continue;
+            }
+            let Some(len) = scanlines.next() else {
+                break;
+            };
+            if bytes[0] > 4 && bad_filter.is_none() {
+                bad_filter = Some((scanline, bytes[0]));
+            }
+            (scanline, left, bytes) = (scanline + 1, len - 1, &bytes[1..]);
+        }
+        if status == Status::StreamEnd || decompress.total_out() as usize > cap {
+            break Ok(status == Status::StreamEnd);
+        }
+    };
+    let len = decompress.total_out() as usize;
+    let inflated = ended.is_ok();
+    match ended {
+        Ok(true) => {}
+        Ok(false) if len > cap => {}
+        Ok(false) => linter.report(
+            Rule::BadCompressedData,
+            at,
+            "IDAT doesn't inflate: the stream is truncated".to_string(),
+        ),
+        Err(e) => linter.report(
+            Rule::BadCompressedData,
+            at,
+            format!("IDAT doesn't inflate: {}", e),
+        ),
+    }
+    if let Some((scanline, filter)) = bad_filter {
+        linter.report(
+            Rule::BadValue,
+            at,
+            format!("Scanline {} has unknown filter type {}", scanline, filter),
+        );
+    }
+    // A stream that doesn't inflate is short too; that says nothing more.
+    if len > expected || (inflated && len < expected && len <= cap) {
+        let found = match len > expected {
+            true => format!("more than {}", expected),
+            false => len.to_string(),
+        };
+        linter.report(
+            Rule::BadLength,
+            at,
+            format!(
+                "The image data is {} bytes, expected {} for a {}x{} image",
+                found, expected, header.width, header.height
+            ),
+        );
+    }
+}
+
+/// Inflates `data` without keeping what comes out, and fails unless the
+/// zlib stream is complete.
+fn inflate(mut data: &[u8]) -> Result<(), String> {
+    let mut decompress = Decompress::new(true);
+    let mut out = vec![0; 64 * 1024];
+    loop {
+        let (before_in, before_out) = (decompress.total_in(), decompress.total_out());
+        let status = decompress
+            .decompress(data, &mut out, FlushDecompress::None)
+            .map_err(|e| e.to_string())?;
+        if status == Status::StreamEnd {
+            return Ok(());
+        }
+        let consumed = (decompress.total_in() - before_in) as usize;
+        if consumed == 0 && decompress.total_out() == before_out {
+            return Err("the stream is truncated".to_string());
+        }
+        data = &data[consumed..];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::{Read, Write};
+
+    fn chunk(code: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(code).unwrap(), data)
+    }
+
+    fn header(bit_depth: u8, color_type: u8) -> Chunk {
+        let mut data = [0; 13];
+        data[..4].copy_from_slice(&4u32.to_be_bytes());
+        data[4..8].copy_from_slice(&4u32.to_be_bytes());
+        data[8] = bit_depth;
+        data[9] = color_type;
+        chunk("IHDR", &data)
+    }
+
+    fn ids(chunks: Vec<Chunk>) -> Vec<&'static str> {
+        lint(&Png::from_chunks(chunks))
+            .iter()
+            .map(|lint| lint.rule.id())
+            .collect()
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// Image data for the 4x4 image of [`header`].
+    fn image_data(bit_depth: u8, color_type: u8) -> Vec<u8> {
+        let header = image::Header {
+            width: 4,
+            height: 4,
+            bit_depth,
+            color_type: ColorType::from_id(color_type).unwrap(),
+            interlaced: false,
+        };
+        deflate(&vec![0; header.stream_len().unwrap()])
+    }
+
+    #[test]
+    fn test_clean() {
+        let data = image_data(2, 3);
+        let ztxt = [&b"Comment\0\0"[..], &deflate(b"hello")].concat();
+        let itxt = [&b"Title\0\x01\0en\0Titel\0"[..], &deflate("é".as_bytes())].concat();
+        let chunks = vec![
+            header(2, 3),
+            chunk("gAMA", &45455u32.to_be_bytes()),
+            chunk("PLTE", &[0; 12]),
+            chunk("tRNS", &[0, 255]),
+            chunk("bKGD", &[3]),
+            chunk("IDAT", &data[..5]),
+            chunk("IDAT", &data[5..]),
+            chunk("tEXt", b"Author\0someone\nelse"),
+            chunk("zTXt", &ztxt),
+            chunk("iTXt", &itxt),
+            chunk("ruSt", b"payload"),
+            chunk("IEND", &[]),
+        ];
+        assert_eq!(ids(chunks), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_structure() {
+        let data = image_data(8, 0);
+        let chunks = vec![
+            chunk("gAMA", &[0, 0, 1, 0]),
+            header(8, 0),
+            chunk("PLTE", &[0; 3]),
+            chunk("gAMA", &[0, 0, 1, 0]),
+            chunk("IDAT", &data[..5]),
+            chunk("tEXt", b"a\0b"),
+            chunk("IDAT", &data[5..]),
+            chunk("pHYs", &[0, 0, 0, 1, 0, 0, 0, 1, 0]),
+            chunk("ABCD", &[]),
+            chunk("test", &[]),
+        ];
+        let lints = lint(&Png::from_chunks(chunks));
+        let found: Vec<_> = lints.iter().map(|l| (l.rule.id(), l.chunk)).collect();
+        assert_eq!(
+            found,
+            [
+                ("P011", Some(2)),
+                ("P006", Some(3)),
+                ("P007", Some(3)),
+                ("P005", Some(6)),
+                ("P007", Some(7)),
+                ("P008", Some(8)),
+                ("P009", Some(9)),
+                ("P002", None),
+                ("P004", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fields() {
+        let chunks = vec![
+            header(1, 3),
+            chunk("sBIT", &[8, 9, 0]),
+            chunk("PLTE", &[0; 9]),
+            chunk("tRNS", &[0; 4]),
+            chunk("bKGD", &[7]),
+            chunk("hIST", &[0; 2]),
+            chunk("IDAT", &image_data(1, 3)),
+            chunk("tIME", &[7, 234, 13, 1, 0, 0, 0]),
+            chunk("tEXt", b" bad\0tab\there"),
+            chunk("zTXt", b"Comment\0\0not zlib"),
+            chunk("IEND", &[]),
+        ];
+        assert_eq!(
+            ids(chunks),
+            ["P108", "P106", "P101", "P108", "P101", "P110", "P109", "P111", "P112"]
+        );
+        assert_eq!(
+            ids(vec![
+                chunk("IHDR", &[1; 12]),
+                chunk("IDAT", &[]),
+                chunk("IEND", &[])
+            ]),
+            ["P101"]
+        );
+        assert_eq!(
+            ids(vec![
+                header(16, 3),
+                chunk("tRNS", &[0; 2]),
+                chunk("IDAT", &[]),
+                chunk("IEND", &[])
+            ]),
+            ["P103"]
+        );
+        assert_eq!(
+            ids(vec![
+                header(8, 6),
+                chunk("tRNS", &[0; 6]),
+                chunk("IDAT", &image_data(8, 6)),
+                chunk("IEND", &[])
+            ]),
+            ["P107"]
+        );
+    }
+
+    fn frame(sequence: u32, width: u32, height: u32, x: u32, y: u32) -> Chunk {
+        let mut data = sequence.to_be_bytes().to_vec();
+        for field in [width, height, x, y] {
+            data.extend_from_slice(&field.to_be_bytes());
+        }
+        data.extend_from_slice(&[0; 6]);
+        chunk("fcTL", &data)
+    }
+
+    fn animation_control(frames: u32) -> Chunk {
+        chunk("acTL", &[frames.to_be_bytes(), 0u32.to_be_bytes()].concat())
+    }
+
+    #[test]
+    fn test_profiles() {
+        let chunks = vec![
+            header(8, 2),
+            chunk("eXIf", b"MM\0*"),
+            animation_control(2),
+            frame(0, 4, 4, 0, 0),
+            chunk("IDAT", &image_data(8, 2)),
+            frame(1, 2, 2, 1, 1),
+            chunk("fdAT", &2u32.to_be_bytes()),
+            chunk("IEND", &[]),
+        ];
+        let png = Png::from_chunks(chunks);
+        let ids = |profile| -> Vec<_> {
+            lint_with_profile(&png, profile)
+                .iter()
+                .map(|lint| (lint.rule.id(), lint.chunk))
+                .collect()
+        };
+        assert_eq!(ids(Profile::Third), []);
+        assert_eq!(ids(Profile::Apng), [("P014", Some(1))]);
+        assert_eq!(
+            ids(Profile::Png12),
+            [1, 2, 3, 5, 6].map(|index| ("P014", Some(index)))
+        );
+
+        let still = Png::from_chunks(vec![
+            header(8, 2),
+            chunk("IDAT", &image_data(8, 2)),
+            chunk("IEND", &[]),
+        ]);
+        assert_eq!(lint_with_profile(&still, Profile::Third), []);
+        assert_eq!(
+            lint_with_profile(&still, Profile::Apng)[0].rule,
+            Rule::BadAnimation
+        );
+        assert_eq!(Profile::from_str("1.2").unwrap(), Profile::Png12);
+        assert!(Profile::from_str("2.0").is_err());
+    }
+
+    #[test]
+    fn test_animation() {
+        let chunks = vec![
+            header(8, 2),
+            animation_control(3),
+            frame(0, 2, 2, 0, 0),
+            chunk("fdAT", &1u32.to_be_bytes()),
+            chunk("IDAT", &image_data(8, 2)),
+            frame(3, 4, 4, 1, 0),
+            chunk("IEND", &[]),
+        ];
+        assert_eq!(ids(chunks), ["P113", "P113", "P007", "P113", "P113"]);
+    }
+
+    #[test]
+    fn test_vendor_chunks() {
+        let idot: Vec<u8> = [1u32, 0, 4, 40]
+            .iter()
+            .flat_map(|v| v.to_be_bytes())
+            .collect();
+        let chunks = vec![
+            header(8, 2),
+            chunk("orNT", &[9]),
+            chunk("vpAg", &[0; 4]),
+            chunk("iDOT", &idot),
+            chunk("IDAT", &image_data(8, 2)),
+            chunk("IEND", &[]),
+        ];
+        assert_eq!(ids(chunks.clone()), ["P110", "P101", "P110"]);
+        let mut fixed = chunks;
+        fixed.remove(1);
+        fixed.remove(1);
+        fixed[1] = chunk(
+            "iDOT",
+            &idot[..12]
+                .iter()
+                .chain(&28u32.to_be_bytes())
+                .copied()
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(ids(fixed), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_image_data() {
+        let pixels: Vec<u8> = (0..7 * 5 * 3).map(|i| (i * 37 % 251) as u8).collect();
+        let layout = image::Header {
+            width: 7,
+            height: 5,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        };
+        let image = image::ImageData::new(layout, pixels).unwrap();
+        for interlace in [false, true] {
+            let options = image::EncodeOptions {
+                interlace: Some(interlace),
+                ..image::EncodeOptions::default()
+            };
+            let png = image.to_png(&options).unwrap();
+            let stream: Vec<u8> = png
+                .chunks()
+                .iter()
+                .filter(|c| c.chunk_type().bytes() == *b"IDAT")
+                .flat_map(|c| c.data().to_vec())
+                .collect();
+            let mut filtered = Vec::new();
+            flate2::read::ZlibDecoder::new(stream.as_slice())
+                .read_to_end(&mut filtered)
+                .unwrap();
+            let with_data = |data: &[u8]| {
+                ids(vec![
+                    png.chunks()[0].clone(),
+                    chunk("IDAT", data),
+                    chunk("IEND", &[]),
+                ])
+            };
+            let scanline_len = 1 + 7 * 3;
+
+            assert_eq!(with_data(&stream), Vec::<&str>::new());
+            let mut corrupt = stream.clone();
+            corrupt[stream.len() / 2] ^= 0xff;
+            assert_eq!(with_data(&corrupt), ["P112"]);
+            assert_eq!(with_data(&stream[..stream.len() / 2]), ["P112", "P101"]);
+            let short = &filtered[..filtered.len() - 1];
+            assert_eq!(with_data(&deflate(short)), ["P101"]);
+            let long = [&filtered[..], &[0; 3]].concat();
+            assert_eq!(with_data(&deflate(&long)), ["P101"]);
+            if !interlace {
+                let mut bad_filter = filtered.clone();
+                bad_filter[2 * scanline_len] = 7;
+                let lints = lint(&Png::from_chunks(vec![
+                    png.chunks()[0].clone(),
+                    chunk("IDAT", &deflate(&bad_filter)),
+                    chunk("IEND", &[]),
+                ]));
+                assert_eq!(lints.len(), 1);
+                assert_eq!(lints[0].rule, Rule::BadValue);
+                assert_eq!(lints[0].message, "Scanline 2 has unknown filter type 7");
+            }
+        }
+        assert_eq!(
+            ids(vec![header(8, 2), chunk("IDAT", &[]), chunk("IEND", &[])]),
+            ["P112", "P101"]
+        );
+    }
+
+    #[test]
+    fn test_rule_ids() {
+        for rule in Rule::ALL {
+            assert_eq!(Rule::from_str(rule.id()).unwrap(), rule);
+        }
+        assert_eq!(Rule::from_str("p012").unwrap(), Rule::IccAndSrgb);
+        assert!(Rule::from_str("P999").is_err());
+    }
+}
@@ -0,0 +1,837 @@
+//! Payloads stored in the least significant bits of the pixels, where they
+//! survive tools that drop ancillary chunks (though not lossy re-encoding).
+//!
+//! Truecolor and grayscale images carry `bits` bits in each color sample,
+//! or in the low byte of 16-bit samples, which changes them by at most
+//! 1/4096 of their range; alpha is left alone. Indexed images carry them in
+//! the pixel indices instead: palette entries are ranked by luminance and a
+//! pixel only moves between neighbouring ranks, so its color changes as
+//! little as the palette allows. Opacity from `tRNS` ranks first, so
+//! transparent entries don't trade places with opaque ones.
+//!
+//! Images with alpha can confine the changes instead: to the alpha samples,
+//! or to the color of fully transparent pixels, which nothing displays.
+//!
+//! The first eight slots hold the strategy and the number of bits per slot,
+//! one bit each, so extracting needs no settings; a 32-bit length and the
+//! payload follow.
+//!
+//! With a [`ScatterKey`], the slots are visited in an order derived from a
+//! password and every stored bit is masked with a keystream, so the header,
+//! length and payload are spread over the whole image and read as noise
+//! without the password.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use crate::crypto::{CryptoError, Kdf};
+use crate::image::{ColorType, EncodeOptions, ImageData, ImageError};
+use crate::png::Png;
+
+#[derive(Debug, Error)]
+pub enum LsbError {
+    #[error(transparent)]
+    Image(#[from] ImageError),
+    #[error("Invalid number of bits per sample {0} (expected 1 to 4)")]
+    InvalidBits(u8),
+    #[error("Can't embed in {0}-bit grayscale images")]
+    Unsupported(u8),
+    #[error("Indexed image has no PLTE chunk")]
+    MissingPalette,
+    #[error("The {0} strategy needs an image with an alpha channel")]
+    NoAlpha(Strategy),
+    #[error("Unknown embedding strategy {0:?} (expected color, alpha or transparent)")]
+    UnknownStrategy(String),
+    #[error("Payload is {len} bytes, but the image holds at most {capacity}")]
+    TooLarge { len: usize, capacity: usize },
+    #[error("No payload is embedded in the pixels")]
+    NoPayload,
+}
+
+pub const MAX_BITS: u8 = 4;
+const HEADER_SLOTS: usize = 8;
+const LEN_BYTES: usize = 4;
+
+/// Which samples carry the payload.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Every color sample (or palette index).
+    #[default]
+    Color,
+    /// Only alpha samples.
+    Alpha,
+    /// Only the color samples of fully transparent pixels.
+    Transparent,
+}
+
+impl Strategy {
+    pub const ALL: [Self; 3] = [Self::Color, Self::Alpha, Self::Transparent];
+
+    /// The id stored in the header; never reuse a value.
+    fn id(self) -> u8 {
+        match self {
+            Self::Color => 0,
+            Self::Alpha => 1,
+            Self::Transparent => 2,
+        }
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = LsbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "color" => Ok(Self::Color),
+            "alpha" => Ok(Self::Alpha),
+            "transparent" => Ok(Self::Transparent),
+            _ => Err(LsbError::UnknownStrategy(s.to_string())),
+        }
+    }
+}
+
+impl Display for Strategy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Color => "color",
+            Self::Alpha => "alpha",
+            Self::Transparent => "transparent",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Where the bits go, derived from a password.
+pub struct ScatterKey {
+    key: Zeroizing<[u8; 32]>,
+}
+
+impl ScatterKey {
+    /// Nothing can be stored to find the payload with, so the salt is fixed,
+    /// as for stealth chunks.
+    const SALT: &'static [u8; 16] = b"pngme scatter v1";
+
+    pub fn derive(password: &[u8], kdf: Kdf) -> Result<Self, CryptoError> {
+        Ok(Self {
+            key: kdf.derive(password, Self::SALT)?,
+        })
+    }
+    fn stream(&self, label: &'static [u8]) -> KeyStream<'_> {
+        KeyStream {
+            key: self,
+            label,
+            counter: 0,
+            block: Vec::new(),
+        }
+    }
+}
+
+/// HMAC-SHA256 of a label and a counter, as an endless stream of bytes.
+#[derive(Clone)]
+struct KeyStream<'a> {
+    key: &'a ScatterKey,
+    label: &'static [u8],
+    counter: u64,
+    block: Vec<u8>,
+}
+
+impl KeyStream<'_> {
+    fn byte(&mut self) -> u8 {
+        if self.block.is_empty() {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.key.key.as_ref())
+                .expect("any key length");
+            mac.update(self.label);
+            mac.update(&self.counter.to_be_bytes());
+            self.block = mac.finalize().into_bytes().to_vec();
+            self.counter += 1;
+        }
+        self.block.pop().expect("refilled")
+    }
+    /// Uniform in `0..n`, up to a bias of n / 2^64.
+    fn below(&mut self, n: usize) -> usize {
+        let value = u64::from_be_bytes([0; 8].map(|_| self.byte()));
+        ((value as u128 * n as u128) >> 64) as usize
+    }
+}
+
+/// How much one strategy holds at some number of bits per sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capacity {
+    pub strategy: Strategy,
+    pub bits: u8,
+    /// Largest payload, in bytes.
+    pub bytes: usize,
+}
+
+/// Bytes `png` can hold at `bits` bits per sample, scattered or not.
+pub fn capacity(png: &Png, bits: u8, strategy: Strategy) -> Result<usize, LsbError> {
+    check_bits(bits)?;
+    Ok(Carrier::new(png, strategy, None)?.capacity(bits))
+}
+
+/// The capacity of `png` for every strategy its color type allows, at each
+/// number of bits.
+pub fn capacities(png: &Png) -> Result<Vec<Capacity>, LsbError> {
+    let mut capacities = Vec::new();
+    for strategy in Strategy::ALL {
+        let carrier = match Carrier::new(png, strategy, None) {
+            Ok(carrier) => carrier,
+            Err(LsbError::NoAlpha(_)) => continue,
+            Err(err) => return Err(err),
+        };
+        capacities.extend((1..=MAX_BITS).map(|bits| Capacity {
+            strategy,
+            bits,
+            bytes: carrier.capacity(bits),
+        }));
+    }
+    Ok(capacities)
+}
+
+/// Stores `data` in the samples of `png` chosen by `strategy`, at `bits`
+/// bits per sample, and writes the image back with `options`.
+pub fn embed(
+    png: &mut Png,
+    data: &[u8],
+    bits: u8,
+    strategy: Strategy,
+    options: &EncodeOptions,
+) -> Result<(), LsbError> {
+    embed_with(png, data, bits, strategy, None, options)
+}
+
+/// Like [`embed`], but scatters the bits over the image with `key`.
+pub fn embed_scattered(
+    png: &mut Png,
+    data: &[u8],
+    bits: u8,
+    strategy: Strategy,
+    key: &ScatterKey,
+    options: &EncodeOptions,
+) -> Result<(), LsbError> {
+    embed_with(png, data, bits, strategy, Some(key), options)
+}
+
+fn embed_with(
+    png: &mut Png,
+    data: &[u8],
+    bits: u8,
+    strategy: Strategy,
+    key: Option<&ScatterKey>,
+    options: &EncodeOptions,
+) -> Result<(), LsbError> {
+    check_bits(bits)?;
+    let mut carrier = Carrier::new(png, strategy, key)?;
+    let capacity = carrier.capacity(bits);
+    let Some((header, payload)) = carrier.layout(bits).filter(|_| data.len() <= capacity) else {
+        return Err(LsbError::TooLarge {
+            len: data.len(),
+            capacity,
+        });
+    };
+    let mut masks = Masks::new(key);
+    for (&slot, value) in header.iter().zip(pack(&[strategy.id() << 4 | bits], 1)) {
+        carrier.set(slot, 1, value ^ masks.next(1));
+    }
+    let mut stream = (data.len() as u32).to_be_bytes().to_vec();
+    stream.extend_from_slice(data);
+    for (&slot, value) in payload.iter().zip(pack(&stream, bits)) {
+        carrier.set(slot, bits, value ^ masks.next(bits));
+    }
+    carrier.image.write_to(png, options)?;
+    Ok(())
+}
+
+/// Recovers data stored by [`embed`], with any strategy.
+pub fn extract(png: &Png) -> Result<Vec<u8>, LsbError> {
+    extract_with(png, None)
+}
+
+/// Recovers data stored by [`embed_scattered`] with the same key.
+pub fn extract_scattered(png: &Png, key: &ScatterKey) -> Result<Vec<u8>, LsbError> {
+    extract_with(png, Some(key))
+}
+
+fn extract_with(png: &Png, key: Option<&ScatterKey>) -> Result<Vec<u8>, LsbError> {
+    for strategy in Strategy::ALL {
+        let carrier = match Carrier::new(png, strategy, key) {
+            Ok(carrier) => carrier,
+            Err(LsbError::NoAlpha(_)) => continue,
+            Err(err) => return Err(err),
+        };
+        match extract_from(&carrier, strategy, key) {
+            Err(LsbError::NoPayload) => {}
+            result => return result,
+        }
+    }
+    Err(LsbError::NoPayload)
+}
+
+fn extract_from(
+    carrier: &Carrier,
+    strategy: Strategy,
+    key: Option<&ScatterKey>,
+) -> Result<Vec<u8>, LsbError> {
+    let mut masks = Masks::new(key);
+    let (header, _) = carrier.layout(1).ok_or(LsbError::NoPayload)?;
+    let header = header
+        .iter()
+        .filter_map(|&s| carrier.get(s, 1))
+        .map(|value| value ^ masks.next(1))
+        .collect::<Vec<_>>();
+    let header = unpack(header.into_iter(), 1, 1)[0];
+    let bits = header & 0x0f;
+    if header >> 4 != strategy.id() {
+        return Err(LsbError::NoPayload);
+    }
+    check_bits(bits).map_err(|_| LsbError::NoPayload)?;
+    let (_, payload) = carrier.layout(bits).ok_or(LsbError::NoPayload)?;
+    let len = read(carrier, &payload, bits, &mut masks.clone(), LEN_BYTES);
+    let len = u32::from_be_bytes(len.try_into().expect("4 bytes")) as usize;
+    if len > payload_capacity(&payload, bits) {
+        return Err(LsbError::NoPayload);
+    }
+    Ok(read(carrier, &payload, bits, &mut masks, LEN_BYTES + len).split_off(LEN_BYTES))
+}
+
+/// The first `len` bytes stored in `payload`.
+fn read(carrier: &Carrier, payload: &[Slot], bits: u8, masks: &mut Masks, len: usize) -> Vec<u8> {
+    let values = payload
+        .iter()
+        .filter_map(|&s| carrier.get(s, bits))
+        .take((len * 8).div_ceil(bits as usize))
+        .map(|value| value ^ masks.next(bits));
+    unpack(values, bits, len)
+}
+
+/// What each stored value is XORed with: nothing without a key, the bits
+/// of a keystream with one.
+#[derive(Clone)]
+struct Masks<'a> {
+    stream: Option<KeyStream<'a>>,
+}
+
+impl<'a> Masks<'a> {
+    fn new(key: Option<&'a ScatterKey>) -> Self {
+        Self {
+            stream: key.map(|key| key.stream(b"mask")),
+        }
+    }
+    fn next(&mut self, bits: u8) -> u8 {
+        self.stream
+            .as_mut()
+            .map_or(0, |stream| stream.byte() & ((1 << bits) - 1))
+    }
+}
+
+fn payload_capacity(payload: &[Slot], bits: u8) -> usize {
+    (payload.len() * bits as usize / 8).saturating_sub(LEN_BYTES)
+}
+
+fn check_bits(bits: u8) -> Result<(), LsbError> {
+    if !(1..=MAX_BITS).contains(&bits) {
+        return Err(LsbError::InvalidBits(bits));
+    }
+    Ok(())
+}
+
+/// Splits `data` into values of `bits` bits, most significant first; the
+/// last one is padded with zeros.
+fn pack(data: &[u8], bits: u8) -> impl Iterator<Item = u8> + '_ {
+    let total = data.len() * 8;
+    let bits = bits as usize;
+    (0..total.div_ceil(bits)).map(move |n| {
+        (n * bits..(n + 1) * bits).fold(0, |value, i| {
+            let bit = if i < total {
+                data[i / 8] >> (7 - i % 8) & 1
+            } else {
+                0
+            };
+            value << 1 | bit
+        })
+    })
+}
+
+/// Reverses [`pack`] for the first `len` bytes.
+fn unpack(values: impl Iterator<Item = u8>, bits: u8, len: usize) -> Vec<u8> {
+    let mut data = vec![0u8; len];
+    let mut i = 0;
+    for value in values {
+        for k in (0..bits).rev() {
+            if i == len * 8 {
+                return data;
+            }
+            data[i / 8] |= (value >> k & 1) << (7 - i % 8);
+            i += 1;
+        }
+    }
+    data
+}
+
+/// Somewhere bits can be stored.
+#[derive(Clone, Copy, Debug)]
+enum Slot {
+    /// The low bits of this byte of the scanlines.
+    Byte(usize),
+    /// The low bits of the palette rank of the pixel at (x, y).
+    Index(u32, u32),
+}
+
+/// Palette entries in order of opacity (from `tRNS`), then luminance, so
+/// neighbouring ranks look alike.
+struct Ranking {
+    /// The rank of each palette index.
+    ranks: Vec<u8>,
+    /// The palette index of each rank.
+    order: Vec<u8>,
+}
+
+impl Ranking {
+    fn new(palette: &[u8], alpha: &[u8]) -> Self {
+        let mut order: Vec<u8> = (0..(palette.len() / 3).min(256)).map(|i| i as u8).collect();
+        order.sort_by_key(|&i| {
+            let rgb = &palette[i as usize * 3..][..3];
+            let luminance = 299 * rgb[0] as u32 + 587 * rgb[1] as u32 + 114 * rgb[2] as u32;
+            (alpha.get(i as usize).copied().unwrap_or(255), luminance)
+        });
+        let mut ranks = vec![0; order.len()];
+        for (rank, &i) in order.iter().enumerate() {
+            ranks[i as usize] = rank as u8;
+        }
+        Self { ranks, order }
+    }
+}
+
+struct Carrier {
+    image: ImageData,
+    ranking: Option<Ranking>,
+    slots: Vec<Slot>,
+}
+
+impl Carrier {
+    fn new(png: &Png, strategy: Strategy, key: Option<&ScatterKey>) -> Result<Self, LsbError> {
+        let image = ImageData::decode(png)?;
+        let header = *image.header();
+        let has_alpha = matches!(
+            header.color_type,
+            ColorType::GrayscaleAlpha | ColorType::Rgba
+        );
+        if strategy != Strategy::Color && !has_alpha {
+            return Err(LsbError::NoAlpha(strategy));
+        }
+        let (ranking, mut slots): (_, Vec<Slot>) = match header.color_type {
+            ColorType::Indexed => {
+                let palette = png.chunk_by_type("PLTE").ok_or(LsbError::MissingPalette)?;
+                let slots = (0..header.height)
+                    .flat_map(|y| (0..header.width).map(move |x| Slot::Index(x, y)))
+                    .collect();
+                let alpha = png.chunk_by_type("tRNS").map_or(&[][..], |c| c.data());
+                (Some(Ranking::new(palette.data(), alpha)), slots)
+            }
+            _ if header.bit_depth < 8 => return Err(LsbError::Unsupported(header.bit_depth)),
+            color_type => {
+                let channels = color_type.channels();
+                let sample_len = header.bit_depth as usize / 8;
+                let is_alpha = |sample: usize| has_alpha && sample % channels == channels - 1;
+                let is_transparent = |sample: usize| {
+                    let alpha = sample - sample % channels + channels - 1;
+                    image.as_bytes()[alpha * sample_len..][..sample_len]
+                        .iter()
+                        .all(|&b| b == 0)
+                };
+                let slots = (0..image.as_bytes().len() / sample_len)
+                    .filter(|&sample| match strategy {
+                        Strategy::Color => !is_alpha(sample),
+                        Strategy::Alpha => is_alpha(sample),
+                        Strategy::Transparent => !is_alpha(sample) && is_transparent(sample),
+                    })
+                    .map(|sample| Slot::Byte(sample * sample_len + sample_len - 1))
+                    .collect();
+                (None, slots)
+            }
+        };
+        if let Some(key) = key {
+            // Fisher-Yates, driven by the key.
+            let mut stream = key.stream(b"order");
+            for i in (1..slots.len()).rev() {
+                slots.swap(i, stream.below(i + 1));
+            }
+        }
+        Ok(Self {
+            image,
+            ranking,
+            slots,
+        })
+    }
+    /// The low `bits` bits held by `slot`, or `None` if it can't hold them:
+    /// an index whose group of 2^`bits` ranks is cut short by the end of the
+    /// palette (or that is outside the palette).
+    fn get(&self, slot: Slot, bits: u8) -> Option<u8> {
+        let mask = (1 << bits) - 1;
+        match slot {
+            Slot::Byte(i) => Some(self.image.as_bytes()[i] & mask),
+            Slot::Index(x, y) => {
+                let ranking = self.ranking.as_ref()?;
+                let rank = *ranking.ranks.get(self.image.pixel(x, y)[0] as usize)?;
+                (((rank | mask) as usize) < ranking.order.len()).then_some(rank & mask)
+            }
+        }
+    }
+    /// Stores `value` in a slot [`Carrier::get`] accepts.
+    fn set(&mut self, slot: Slot, bits: u8, value: u8) {
+        let mask = (1 << bits) - 1;
+        match slot {
+            Slot::Byte(i) => {
+                let byte = &mut self.image.as_bytes_mut()[i];
+                *byte = (*byte & !mask) | value;
+            }
+            Slot::Index(x, y) => {
+                let ranking = self.ranking.as_ref().expect("indexed images are ranked");
+                let rank = ranking.ranks[self.image.pixel(x, y)[0] as usize];
+                let index = ranking.order[((rank & !mask) | value) as usize];
+                self.image.set_pixel(x, y, &[index as u16]);
+            }
+        }
+    }
+    fn capacity(&self, bits: u8) -> usize {
+        self.layout(bits)
+            .map_or(0, |(_, payload)| payload_capacity(&payload, bits))
+    }
+    /// The slots of the header and those of the payload at `bits` bits each,
+    /// or `None` if the image is too small for the header.
+    fn layout(&self, bits: u8) -> Option<(Vec<Slot>, Vec<Slot>)> {
+        let mut header = Vec::with_capacity(HEADER_SLOTS);
+        let mut end = 0;
+        while header.len() < HEADER_SLOTS {
+            let slot = *self.slots.get(end)?;
+            if self.get(slot, 1).is_some() {
+                header.push(slot);
+            }
+            end += 1;
+        }
+        let payload = self.slots[end..]
+            .iter()
+            .copied()
+            .filter(|&slot| self.get(slot, bits).is_some())
+            .collect();
+        Some((header, payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::image::Header;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn testing_png(header: Header, pixels: Vec<u8>, palette: Option<&[u8]>) -> Png {
+        let mut png = Png::from_chunks(vec![chunk("IHDR", &header.as_bytes()), chunk("IEND", &[])]);
+        if let Some(palette) = palette {
+            png.insert_chunk_at(1, chunk("PLTE", palette));
+        }
+        let image = ImageData::new(header, pixels).unwrap();
+        image.write_to(&mut png, &EncodeOptions::default()).unwrap();
+        png
+    }
+
+    fn header(width: u32, height: u32, bit_depth: u8, color_type: ColorType) -> Header {
+        Header {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        }
+    }
+
+    #[test]
+    fn test_pack() {
+        let data = [0b1011_0010, 0xff];
+        let values: Vec<u8> = pack(&data, 3).collect();
+        assert_eq!(values, [0b101, 0b100, 0b101, 0b111, 0b111, 0b100]);
+        assert_eq!(unpack(values.into_iter(), 3, 2), data);
+    }
+
+    #[test]
+    fn test_truecolor_round_trip() {
+        let rgba = header(10, 10, 8, ColorType::Rgba);
+        let pixels: Vec<u8> = (0..400u32).map(|i| (i * 37) as u8).collect();
+        let mut png = testing_png(rgba, pixels.clone(), None);
+        // 300 color samples at 2 bits, less the header and length.
+        assert_eq!(
+            capacity(&png, 2, Strategy::Color).unwrap(),
+            (292 * 2) / 8 - 4
+        );
+        embed(
+            &mut png,
+            b"hidden in plain sight",
+            2,
+            Strategy::Color,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(extract(&png).unwrap(), b"hidden in plain sight");
+
+        let image = ImageData::decode(&png).unwrap();
+        for (i, (&before, &after)) in pixels.iter().zip(image.as_bytes()).enumerate() {
+            if i % 4 == 3 {
+                assert_eq!(before, after);
+            } else {
+                assert_eq!(before & !3, after & !3);
+            }
+        }
+        assert!(matches!(
+            embed(
+                &mut png,
+                &[0; 100],
+                2,
+                Strategy::Color,
+                &EncodeOptions::default()
+            ),
+            Err(LsbError::TooLarge { len: 100, .. })
+        ));
+    }
+
+    #[test]
+    fn test_sixteen_bit_low_byte() {
+        let gray = header(8, 8, 16, ColorType::Grayscale);
+        let pixels: Vec<u8> = (0..128u8).collect();
+        let mut png = testing_png(gray, pixels.clone(), None);
+        embed(
+            &mut png,
+            b"deep",
+            4,
+            Strategy::Color,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(extract(&png).unwrap(), b"deep");
+        let image = ImageData::decode(&png).unwrap();
+        for (before, after) in pixels.chunks(2).zip(image.as_bytes().chunks(2)) {
+            assert_eq!(before[0], after[0]);
+        }
+    }
+
+    #[test]
+    fn test_indexed_moves_between_neighbouring_colors() {
+        // Five grays, listed out of order: the last one has no neighbour to
+        // pair with at 1 bit, so pixels using it are skipped.
+        let palette = [40, 40, 40, 0, 0, 0, 30, 30, 30, 10, 10, 10, 20, 20, 20];
+        let indexed = header(16, 8, 4, ColorType::Indexed);
+        let pixels: Vec<u8> = (0..64u8).map(|i| (i % 5) << 4 | ((i + 2) % 5)).collect();
+        let mut png = testing_png(indexed, pixels, Some(&palette));
+        embed(
+            &mut png,
+            b"pal",
+            1,
+            Strategy::Color,
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(extract(&png).unwrap(), b"pal");
+
+        let image = ImageData::decode(&png).unwrap();
+        let luminance = |index: u16| palette[index as usize * 3] as i32;
+        for y in 0..8 {
+            for x in 0..16 {
+                let before = ((y * 16 + x) / 2) as u8;
+                let before = if x % 2 == 0 {
+                    before % 5
+                } else {
+                    (before + 2) % 5
+                };
+                let after = image.pixel(x, y)[0];
+                assert!((luminance(before as u16) - luminance(after)).abs() <= 10);
+                if before == 0 {
+                    assert_eq!(after, 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unsupported_and_empty() {
+        let png = testing_png(header(4, 4, 8, ColorType::Rgb), vec![0; 48], None);
+        // All low bits are zero: read as 0 bits per sample.
+        assert!(matches!(extract(&png), Err(LsbError::NoPayload)));
+        assert!(matches!(
+            capacity(&png, 5, Strategy::Color),
+            Err(LsbError::InvalidBits(5))
+        ));
+        let tiny = testing_png(header(2, 1, 8, ColorType::Rgb), vec![0; 6], None);
+        assert_eq!(capacity(&tiny, 1, Strategy::Color).unwrap(), 0);
+        let bits = testing_png(header(8, 1, 1, ColorType::Grayscale), vec![0], None);
+        assert!(matches!(
+            embed(
+                &mut bits.clone(),
+                b"x",
+                1,
+                Strategy::Color,
+                &EncodeOptions::default()
+            ),
+            Err(LsbError::Unsupported(1))
+        ));
+    }
+
+    #[test]
+    fn test_capacity() {
+        let interlaced = Header {
+            interlaced: true,
+            ..header(6, 5, 8, ColorType::Rgb)
+        };
+        let palette: Vec<u8> = (0..48).collect();
+        let indices: Vec<u8> = (0..64).map(|i| i % 16).collect();
+        let pixels = |len: usize| (0..len).map(|i| (i * 13) as u8).collect::<Vec<u8>>();
+        // Slots, less the 8 of the header, times the bits, less the length.
+        let cases = [
+            (interlaced, None, Strategy::Color, 3, (90 - 8) * 3 / 8 - 4),
+            (
+                header(5, 4, 16, ColorType::Grayscale),
+                None,
+                Strategy::Color,
+                4,
+                (20 - 8) * 4 / 8 - 4,
+            ),
+            (
+                header(8, 4, 8, ColorType::GrayscaleAlpha),
+                None,
+                Strategy::Alpha,
+                4,
+                (32 - 8) * 4 / 8 - 4,
+            ),
+            (
+                header(8, 8, 8, ColorType::Indexed),
+                Some(&palette[..]),
+                Strategy::Color,
+                2,
+                (64 - 8) * 2 / 8 - 4,
+            ),
+        ];
+        let options = EncodeOptions::default();
+        for (header, palette, strategy, bits, expected) in cases {
+            let data = match header.color_type {
+                ColorType::Indexed => indices.clone(),
+                _ => pixels(header.row_len() * header.height as usize),
+            };
+            let png = testing_png(header, data, palette);
+            assert_eq!(capacity(&png, bits, strategy).unwrap(), expected);
+            assert!(capacities(&png).unwrap().contains(&Capacity {
+                strategy,
+                bits,
+                bytes: expected
+            }));
+            let payload: Vec<u8> = (0..=expected as u8).collect();
+            let mut full = png.clone();
+            embed(&mut full, &payload[..expected], bits, strategy, &options).unwrap();
+            assert_eq!(extract(&full).unwrap(), &payload[..expected]);
+            assert!(matches!(
+                embed(&mut png.clone(), &payload, bits, strategy, &options),
+                Err(LsbError::TooLarge { len, capacity }) if len == expected + 1 && capacity == expected
+            ));
+        }
+    }
+
+    #[test]
+    fn test_alpha_strategies() {
+        // Left half fully transparent, right half opaque.
+        let rgba = header(8, 8, 8, ColorType::Rgba);
+        let pixels: Vec<u8> = (0..256u32)
+            .map(|i| match (i % 4, i / 4 % 8 < 4) {
+                (3, true) => 0,
+                (3, false) => 255,
+                _ => (i * 11) as u8,
+            })
+            .collect();
+        let options = EncodeOptions::default();
+        for strategy in [Strategy::Alpha, Strategy::Transparent] {
+            let mut png = testing_png(rgba, pixels.clone(), None);
+            embed(&mut png, b"invisible", 4, strategy, &options).unwrap();
+            assert_eq!(extract(&png).unwrap(), b"invisible");
+            let image = ImageData::decode(&png).unwrap();
+            for (i, (&before, &after)) in pixels.iter().zip(image.as_bytes()).enumerate() {
+                let touchable = match strategy {
+                    Strategy::Alpha => i % 4 == 3,
+                    _ => i % 4 != 3 && i / 4 % 8 < 4,
+                };
+                if !touchable {
+                    assert_eq!(before, after);
+                }
+            }
+        }
+        // 32 transparent pixels of 3 samples at 1 bit, less the header.
+        let png = testing_png(rgba, pixels, None);
+        assert_eq!(
+            capacity(&png, 1, Strategy::Transparent).unwrap(),
+            88 / 8 - 4
+        );
+        let rgb = testing_png(header(4, 4, 8, ColorType::Rgb), vec![0; 48], None);
+        assert!(matches!(
+            capacity(&rgb, 1, Strategy::Alpha),
+            Err(LsbError::NoAlpha(Strategy::Alpha))
+        ));
+        assert!(Strategy::from_str("edges").is_err());
+        let all = capacities(&png).unwrap();
+        assert_eq!(all.len(), 12);
+        assert!(all.contains(&Capacity {
+            strategy: Strategy::Transparent,
+            bits: 1,
+            bytes: 7
+        }));
+        let all = capacities(&rgb).unwrap();
+        assert!(all.iter().all(|c| c.strategy == Strategy::Color));
+    }
+
+    #[test]
+    fn test_scattered() {
+        let key = |password: &[u8]| {
+            ScatterKey::derive(password, Kdf::Pbkdf2Sha256 { iterations: 10 }).unwrap()
+        };
+        let rgb = header(64, 64, 8, ColorType::Rgb);
+        let pixels: Vec<u8> = (0..64 * 64 * 3u32).map(|i| (i * 7 % 256) as u8).collect();
+        let mut png = testing_png(rgb, pixels.clone(), None);
+        let capacity = capacity(&png, 1, Strategy::Color).unwrap();
+        embed_scattered(
+            &mut png,
+            b"nowhere in particular",
+            1,
+            Strategy::Color,
+            &key(b"hunter2"),
+            &EncodeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            extract_scattered(&png, &key(b"hunter2")).unwrap(),
+            b"nowhere in particular"
+        );
+        assert!(matches!(extract(&png), Err(LsbError::NoPayload)));
+        assert!(matches!(
+            extract_scattered(&png, &key(b"hunter3")),
+            Err(LsbError::NoPayload)
+        ));
+
+        // The changes land all over the image rather than at its start.
+        let image = ImageData::decode(&png).unwrap();
+        let changed: Vec<usize> = pixels
+            .iter()
+            .zip(image.as_bytes())
+            .enumerate()
+            .filter(|(_, (before, after))| before != after)
+            .map(|(i, _)| i)
+            .collect();
+        assert!(changed.iter().any(|&i| i > pixels.len() / 2));
+        assert!(changed
+            .iter()
+            .all(|&i| pixels[i] ^ image.as_bytes()[i] == 1));
+        assert_eq!(capacity, (64 * 64 * 3 - 8) / 8 - 4);
+    }
+}
@@ -1,11 +1,69 @@
-use anyhow::Result;
+use clap::Parser;
+use std::process::ExitCode;
 
+use pngme::chunk::ChunkError;
+use pngme::chunk_type::ChunkTypeError;
+use pngme::diagnostic::Diagnostic;
+use pngme::limits::LimitExceeded;
+use pngme::png::PngError;
+
+mod api;
 mod args;
-mod chunk;
-mod chunk_type;
+mod budget;
 mod commands;
-mod png;
+#[cfg(all(feature = "daemon", unix))]
+mod daemon;
+mod exit;
+mod git_filter;
+mod json;
+#[cfg(feature = "keychain")]
+mod keychain;
+mod keys;
+mod manifest;
+mod password;
+#[cfg(feature = "openpgp")]
+mod pgp;
+mod rpc;
+#[cfg(feature = "serve")]
+mod serve;
+mod template;
+
+fn main() -> ExitCode {
+    let cli = args::Cli::parse();
+    match commands::run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) if exit::is_broken_pipe(&err) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:?}", err);
+            if let Some(diagnostic) = diagnostic(&err) {
+                eprintln!("\nCode: {}", diagnostic.code);
+                for label in &diagnostic.labels {
+                    eprintln!(
+                        "At byte {} ({:#x}): {}",
+                        label.offset, label.offset, label.message
+                    );
+                }
+                if let Some(help) = diagnostic.help {
+                    eprintln!("Help: {}", help);
+                }
+            }
+            ExitCode::from(exit::code_for(&err))
+        }
+    }
+}
 
-fn main() -> Result<()> {
-    todo!()
+/// The diagnostic of the outermost parse error in the chain.
+fn diagnostic(err: &anyhow::Error) -> Option<Diagnostic> {
+    err.chain().find_map(|cause| {
+        if let Some(e) = cause.downcast_ref::<PngError>() {
+            return Some(e.into());
+        }
+        if let Some(e) = cause.downcast_ref::<ChunkError>() {
+            return Some(e.into());
+        }
+        if let Some(e) = cause.downcast_ref::<ChunkTypeError>() {
+            return Some(e.into());
+        }
+        cause.downcast_ref::<LimitExceeded>().map(Diagnostic::from)
+    })
 }
@@ -0,0 +1,525 @@
+//! Release manifests for `verify --manifest`: the files a release ships,
+//! with the content hash, chunks and pngme payloads each must have, so a
+//! pipeline can assert nothing was added to its assets or taken from them
+//! since they were approved.
+//!
+//! A manifest is a JSON object whose `files` member lists one object per
+//! file: its `path`, relative to the manifest's directory unless absolute,
+//! and any of `content_hash` (as `hash` prints it), `chunks` (an object
+//! counting the chunks of each type) and `payloads` (an array of
+//! `{"chunk": TYPE, "name": NAME}`, the name left out for unnamed ones, or
+//! `{"trailer": true}` for a payload after `IEND`). What an entry leaves out
+//! isn't checked; what it gives must match exactly, so chunks or payloads
+//! it doesn't list are discrepancies.
+
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use thiserror::Error;
+
+use pngme::chunk_type::ChunkType;
+use pngme::decoder::CriticalPolicy;
+use pngme::hash;
+use pngme::png::{Png, PngError};
+use pngme::scan::{self, Finding};
+
+use crate::json::Value;
+
+#[derive(Debug, Error)]
+pub enum ManifestError {
+    #[error("Manifest isn't valid JSON")]
+    NotJson,
+    #[error("Invalid manifest: {at} should be {expected}")]
+    Invalid { at: String, expected: &'static str },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Manifest {
+    pub entries: Vec<Entry>,
+    /// A detached OpenPGP signature of [`Manifest::signed_data`], armored.
+    pub signature: Option<String>,
+    signed_data: String,
+}
+
+/// What one file of a [`Manifest`] must hold.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    /// Where the file is, as the manifest gives it.
+    pub name: String,
+    /// [`Entry::name`] resolved against the manifest's directory.
+    pub path: PathBuf,
+    content_hash: Option<String>,
+    chunks: Option<Vec<(ChunkType, usize)>>,
+    payloads: Option<Vec<Payload>>,
+}
+
+/// Where a pngme payload is stored.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Payload {
+    Chunk {
+        chunk_type: ChunkType,
+        name: Option<String>,
+    },
+    Trailer,
+}
+
+impl Display for Payload {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Chunk {
+                chunk_type,
+                name: Some(name),
+            } => write!(f, "{:?} in {}", name, chunk_type),
+            Self::Chunk { chunk_type, .. } => write!(f, "in {}", chunk_type),
+            Self::Trailer => write!(f, "after IEND"),
+        }
+    }
+}
+
+/// One way a file differs from its [`Entry`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Discrepancy {
+    ContentHash {
+        actual: String,
+        expected: String,
+    },
+    Chunks {
+        chunk_type: ChunkType,
+        actual: usize,
+        expected: usize,
+    },
+    MissingPayload(Payload),
+    UnexpectedPayload(Payload),
+}
+
+impl Display for Discrepancy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ContentHash { actual, expected } => {
+                write!(f, "content hash {}, expected {}", actual, expected)
+            }
+            Self::Chunks {
+                chunk_type,
+                actual,
+                expected,
+            } => write!(f, "{} {} chunks, expected {}", actual, chunk_type, expected),
+            Self::MissingPayload(payload) => write!(f, "missing payload {}", payload),
+            Self::UnexpectedPayload(payload) => write!(f, "unexpected payload {}", payload),
+        }
+    }
+}
+
+impl Manifest {
+    /// Parses the manifest `text`, read from the directory `base`.
+    pub fn parse(text: &str, base: &Path) -> Result<Self, ManifestError> {
+        let root = Value::parse(text).ok_or(ManifestError::NotJson)?;
+        let files = root
+            .get("files")
+            .ok_or_else(|| invalid("files", "an array"))?;
+        let entries = files
+            .as_array()
+            .ok_or_else(|| invalid("files", "an array"))?
+            .iter()
+            .enumerate()
+            .map(|(i, file)| Entry::parse(file, &format!("files[{}]", i), base))
+            .collect::<Result<_, _>>()?;
+        let signature = match root.get("signature") {
+            Some(signature) => Some(
+                signature
+                    .as_str()
+                    .ok_or_else(|| invalid("signature", "a string"))?
+                    .to_string(),
+            ),
+            None => None,
+        };
+        Ok(Self {
+            entries,
+            signature,
+            signed_data: files.to_string(),
+        })
+    }
+
+    /// An unsigned manifest of `entries`.
+    pub fn new(entries: Vec<Entry>) -> Self {
+        let files = Value::Array(entries.iter().map(Entry::to_value).collect());
+        Self {
+            entries,
+            signature: None,
+            signed_data: files.to_string(),
+        }
+    }
+
+    /// What the signature is made over: the `files` member as serialized
+    /// compactly, so reading a manifest and writing it back out doesn't
+    /// change it.
+    pub fn signed_data(&self) -> &str {
+        &self.signed_data
+    }
+}
+
+impl Display for Manifest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{{\"files\":{}", self.signed_data())?;
+        if let Some(signature) = &self.signature {
+            write!(f, ",\"signature\":{}", Value::string(signature.as_str()))?;
+        }
+        write!(f, "}}")
+    }
+}
+
+impl Entry {
+    fn parse(value: &Value, at: &str, base: &Path) -> Result<Self, ManifestError> {
+        let Value::Object(members) = value else {
+            return Err(invalid(at, "an object"));
+        };
+        let mut entry = Self {
+            name: String::new(),
+            path: PathBuf::new(),
+            content_hash: None,
+            chunks: None,
+            payloads: None,
+        };
+        let mut path = None;
+        for (key, value) in members {
+            let at = format!("{}.{}", at, key);
+            match key.as_str() {
+                "path" => path = Some(value.as_str().ok_or_else(|| invalid(&at, "a string"))?),
+                "content_hash" => {
+                    let hex = value
+                        .as_str()
+                        .filter(|s| s.len() == 64 && s.bytes().all(|b| b.is_ascii_hexdigit()))
+                        .ok_or_else(|| invalid(&at, "64 hexadecimal digits"))?;
+                    entry.content_hash = Some(hex.to_ascii_lowercase());
+                }
+                "chunks" => entry.chunks = Some(parse_chunks(value, &at)?),
+                "payloads" => {
+                    let items = value.as_array().ok_or_else(|| invalid(&at, "an array"))?;
+                    entry.payloads = Some(
+                        items
+                            .iter()
+                            .enumerate()
+                            .map(|(i, item)| parse_payload(item, &format!("{}[{}]", at, i)))
+                            .collect::<Result<_, _>>()?,
+                    );
+                }
+                _ => return Err(invalid(&at, "path, content_hash, chunks or payloads")),
+            }
+        }
+        let path = path.ok_or_else(|| invalid(&format!("{}.path", at), "given"))?;
+        entry.path = base.join(path);
+        entry.name = path.to_string();
+        Ok(entry)
+    }
+
+    /// Everything there is to check of the file `bytes`, found at `name`.
+    pub fn of(name: String, bytes: &[u8], policy: CriticalPolicy) -> Result<Self, PngError> {
+        let png = Png::from_bytes_with_policy(bytes, policy)?;
+        let mut chunks = Vec::new();
+        for chunk in png.chunks().iter().chain(png.quarantined()) {
+            count(&mut chunks, chunk.chunk_type());
+        }
+        let (_, findings) = scan::inspect(bytes)?;
+        Ok(Self {
+            path: PathBuf::from(&name),
+            name,
+            content_hash: Some(hash::to_hex(&hash::content_hash(&png))),
+            chunks: Some(chunks),
+            payloads: Some(payloads(findings)),
+        })
+    }
+
+    fn to_value(&self) -> Value {
+        let mut members = vec![("path".to_string(), Value::string(self.name.as_str()))];
+        if let Some(content_hash) = &self.content_hash {
+            members.push((
+                "content_hash".to_string(),
+                Value::string(content_hash.as_str()),
+            ));
+        }
+        if let Some(chunks) = &self.chunks {
+            let counts = chunks
+                .iter()
+                .map(|(chunk_type, count)| (chunk_type.to_string(), Value::number(*count as u64)))
+                .collect();
+            members.push(("chunks".to_string(), Value::Object(counts)));
+        }
+        if let Some(payloads) = &self.payloads {
+            let payloads = payloads
+                .iter()
+                .map(|payload| match payload {
+                    Payload::Chunk { chunk_type, name } => {
+                        let mut members =
+                            vec![("chunk".to_string(), Value::string(chunk_type.to_string()))];
+                        if let Some(name) = name {
+                            members.push(("name".to_string(), Value::string(name.as_str())));
+                        }
+                        Value::Object(members)
+                    }
+                    Payload::Trailer => Value::object([("trailer", Value::Bool(true))]),
+                })
+                .collect();
+            members.push(("payloads".to_string(), Value::Array(payloads)));
+        }
+        Value::Object(members)
+    }
+
+    /// How the file `bytes` differs from the entry: nothing when it
+    /// matches.
+    pub fn check(
+        &self,
+        bytes: &[u8],
+        policy: CriticalPolicy,
+    ) -> Result<Vec<Discrepancy>, PngError> {
+        let png = Png::from_bytes_with_policy(bytes, policy)?;
+        let mut discrepancies = Vec::new();
+        if let Some(expected) = &self.content_hash {
+            let actual = hash::to_hex(&hash::content_hash(&png));
+            if &actual != expected {
+                discrepancies.push(Discrepancy::ContentHash {
+                    actual,
+                    expected: expected.clone(),
+                });
+            }
+        }
+        if let Some(expected) = &self.chunks {
+            let mut actual = Vec::new();
+            for chunk in png.chunks().iter().chain(png.quarantined()) {
+                count(&mut actual, chunk.chunk_type());
+            }
+            let number = |counts: &[(ChunkType, usize)], chunk_type: &ChunkType| {
+                counts
+                    .iter()
+                    .find(|(t, _)| t == chunk_type)
+                    .map_or(0, |(_, count)| *count)
+            };
+            let extra = actual
+                .iter()
+                .filter(|(t, _)| !expected.iter().any(|(e, _)| e == t));
+            for (chunk_type, _) in expected.iter().chain(extra) {
+                let (actual, expected) =
+                    (number(&actual, chunk_type), number(expected, chunk_type));
+                if actual != expected {
+                    discrepancies.push(Discrepancy::Chunks {
+                        chunk_type: chunk_type.clone(),
+                        actual,
+                        expected,
+                    });
+                }
+            }
+        }
+        if let Some(expected) = &self.payloads {
+            let (_, findings) = scan::inspect(bytes)?;
+            let mut found = payloads(findings);
+            for payload in expected {
+                match found.iter().position(|p| p == payload) {
+                    Some(i) => drop(found.remove(i)),
+                    None => discrepancies.push(Discrepancy::MissingPayload(payload.clone())),
+                }
+            }
+            discrepancies.extend(found.into_iter().map(Discrepancy::UnexpectedPayload));
+        }
+        Ok(discrepancies)
+    }
+}
+
+fn count(counts: &mut Vec<(ChunkType, usize)>, chunk_type: &ChunkType) {
+    match counts.iter_mut().find(|(t, _)| t == chunk_type) {
+        Some((_, count)) => *count += 1,
+        None => counts.push((chunk_type.clone(), 1)),
+    }
+}
+
+fn payloads(findings: Vec<Finding>) -> Vec<Payload> {
+    findings
+        .into_iter()
+        .filter_map(|finding| match finding {
+            Finding::Payload { chunk_type, name } => Some(Payload::Chunk { chunk_type, name }),
+            Finding::TrailerPayload { .. } => Some(Payload::Trailer),
+            _ => None,
+        })
+        .collect()
+}
+
+/// `path` as a manifest in the directory `base` names it: relative to
+/// `base` when it is inside it, absolute otherwise.
+pub fn name_from(path: &Path, base: &Path) -> io::Result<String> {
+    let path = fs::canonicalize(path)?;
+    let base = fs::canonicalize(if base.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        base
+    })?;
+    let name = path.strip_prefix(&base).unwrap_or(&path);
+    Ok(name.to_string_lossy().into_owned())
+}
+
+fn invalid(at: &str, expected: &'static str) -> ManifestError {
+    ManifestError::Invalid {
+        at: at.to_string(),
+        expected,
+    }
+}
+
+fn parse_chunk_type(value: &Value, at: &str) -> Result<ChunkType, ManifestError> {
+    value
+        .as_str()
+        .and_then(|s| ChunkType::from_str(s).ok())
+        .ok_or_else(|| invalid(at, "a chunk type"))
+}
+
+fn parse_chunks(value: &Value, at: &str) -> Result<Vec<(ChunkType, usize)>, ManifestError> {
+    let Value::Object(members) = value else {
+        return Err(invalid(at, "an object"));
+    };
+    members
+        .iter()
+        .map(|(key, count)| {
+            let at = format!("{}.{}", at, key);
+            let chunk_type = parse_chunk_type(&Value::string(key.as_str()), &at)?;
+            let count = count
+                .as_u64()
+                .and_then(|n| usize::try_from(n).ok())
+                .ok_or_else(|| invalid(&at, "a count"))?;
+            Ok((chunk_type, count))
+        })
+        .collect()
+}
+
+fn parse_payload(value: &Value, at: &str) -> Result<Payload, ManifestError> {
+    if value.get("trailer") == Some(&Value::Bool(true)) {
+        return match value {
+            Value::Object(members) if members.len() == 1 => Ok(Payload::Trailer),
+            _ => Err(invalid(at, "{\"trailer\": true} alone")),
+        };
+    }
+    let chunk_type = value
+        .get("chunk")
+        .ok_or_else(|| invalid(at, "an object with a chunk or trailer member"))?;
+    let chunk_type = parse_chunk_type(chunk_type, &format!("{}.chunk", at))?;
+    let name = match value.get("name") {
+        Some(name) => Some(
+            name.as_str()
+                .ok_or_else(|| invalid(&format!("{}.name", at), "a string"))?
+                .to_string(),
+        ),
+        None => None,
+    };
+    Ok(Payload::Chunk { chunk_type, name })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pngme::chunk::Chunk;
+    use pngme::envelope::Envelope;
+
+    fn chunk(code: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(code).unwrap(), data)
+    }
+
+    #[test]
+    fn test_manifest() {
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+            chunk(
+                "IDAT",
+                &[0x78, 0x9c, 0x63, 0x60, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01],
+            ),
+            chunk("IEND", &[]),
+        ]);
+        let payload = Envelope::text("hi").with_name("notes.txt").as_bytes();
+        png.insert_chunk_at(2, chunk("ruSt", &payload));
+        let bytes = png.as_bytes();
+        let hash = hash::to_hex(&hash::content_hash(&png));
+
+        let text = format!(
+            r#"{{"files": [{{"path": "a.png", "content_hash": "{}",
+                "chunks": {{"IHDR": 1, "IDAT": 1, "ruSt": 1, "IEND": 1}},
+                "payloads": [{{"chunk": "ruSt", "name": "notes.txt"}}]}}]}}"#,
+            hash.to_uppercase()
+        );
+        let manifest = Manifest::parse(&text, Path::new("assets")).unwrap();
+        let entry = &manifest.entries[0];
+        assert_eq!(entry.path, Path::new("assets/a.png"));
+        assert!(entry
+            .check(&bytes, CriticalPolicy::Keep)
+            .unwrap()
+            .is_empty());
+
+        let zeros = "0".repeat(64);
+        let text = format!(
+            r#"{{"files": [{{"path": "/a.png", "content_hash": "{}",
+                "chunks": {{"IHDR": 1, "IDAT": 2, "IEND": 1}},
+                "payloads": [{{"trailer": true}}]}}]}}"#,
+            zeros
+        );
+        let manifest = Manifest::parse(&text, Path::new("assets")).unwrap();
+        let entry = &manifest.entries[0];
+        assert_eq!(entry.path, Path::new("/a.png"));
+        let lines: Vec<String> = entry
+            .check(&bytes, CriticalPolicy::Keep)
+            .unwrap()
+            .iter()
+            .map(Discrepancy::to_string)
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                format!("content hash {}, expected {}", hash, zeros),
+                "1 IDAT chunks, expected 2".to_string(),
+                "1 ruSt chunks, expected 0".to_string(),
+                "missing payload after IEND".to_string(),
+                "unexpected payload \"notes.txt\" in ruSt".to_string(),
+            ]
+        );
+
+        let generated = Manifest::new(vec![Entry::of(
+            "a.png".to_string(),
+            &bytes,
+            CriticalPolicy::Keep,
+        )
+        .unwrap()]);
+        let mut parsed = Manifest::parse(&generated.to_string(), Path::new("")).unwrap();
+        assert!(parsed.entries[0]
+            .check(&bytes, CriticalPolicy::Keep)
+            .unwrap()
+            .is_empty());
+        assert_eq!(parsed.signed_data(), generated.signed_data());
+        parsed.signature = Some("sig".to_string());
+        let reparsed = Manifest::parse(&parsed.to_string(), Path::new("")).unwrap();
+        assert_eq!(reparsed, parsed);
+
+        // Entries only check what they give.
+        let manifest = Manifest::parse(r#"{"files": [{"path": "a.png"}]}"#, Path::new("")).unwrap();
+        assert!(manifest.entries[0]
+            .check(&bytes, CriticalPolicy::Keep)
+            .unwrap()
+            .is_empty());
+
+        for (text, error) in [
+            ("[]", "Invalid manifest: files should be an array"),
+            (
+                r#"{"files": [{"path": "a.png", "hash": "00"}]}"#,
+                "Invalid manifest: files[0].hash should be path, content_hash, chunks or payloads",
+            ),
+            (
+                r#"{"files": [{"path": "a.png", "chunks": {"IDAT": -1}}]}"#,
+                "Invalid manifest: files[0].chunks.IDAT should be a count",
+            ),
+            (
+                r#"{"files": [{"payloads": []}]}"#,
+                "Invalid manifest: files[0].path should be given",
+            ),
+            ("{", "Manifest isn't valid JSON"),
+        ] {
+            assert_eq!(
+                Manifest::parse(text, Path::new(""))
+                    .unwrap_err()
+                    .to_string(),
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::convert::TryFrom;
+use thiserror::Error;
+
+use crate::chunk_type::ChunkType;
+use crate::envelope::Envelope;
+use crate::index;
+use crate::png::Png;
+
+#[derive(Debug, Error)]
+pub enum MessageError {
+    #[error("No payload named {0:?}")]
+    NameNotFound(String),
+}
+
+/// Every chunk holding a well-formed envelope, with its index in
+/// [`Png::chunks`]. Chunks of other types can be filtered out with
+/// `chunk_type`.
+pub fn envelopes<'a>(
+    png: &'a Png,
+    chunk_type: Option<&'a ChunkType>,
+) -> impl Iterator<Item = (usize, Envelope)> + 'a {
+    png.chunks()
+        .iter()
+        .enumerate()
+        .filter(move |(_, c)| chunk_type.is_none_or(|t| c.chunk_type() == t))
+        .filter(|(_, c)| Envelope::is_envelope(c.data()))
+        .filter_map(|(i, c)| Envelope::try_from(c.data()).ok().map(|e| (i, e)))
+}
+
+/// Finds the payload labelled `name`, through the payload index when the
+/// file has an up-to-date one.
+pub fn find_named(
+    png: &Png,
+    name: &str,
+    chunk_type: Option<&ChunkType>,
+) -> Result<(usize, Envelope), MessageError> {
+    if let Some((i, envelope)) = index::lookup(png, name) {
+        if chunk_type.is_none_or(|t| png.chunks()[i].chunk_type() == t) {
+            return Ok((i, envelope));
+        }
+    }
+    envelopes(png, chunk_type)
+        .find(|(_, e)| e.name() == Some(name))
+        .ok_or_else(|| MessageError::NameNotFound(name.to_string()))
+}
+
+/// Removes every payload labelled `name`, returning how many were removed.
+pub fn remove_named(png: &mut Png, name: &str, chunk_type: Option<&ChunkType>) -> usize {
+    let indices: Vec<usize> = envelopes(png, chunk_type)
+        .filter(|(_, e)| e.name() == Some(name))
+        .map(|(i, _)| i)
+        .collect();
+    for &index in indices.iter().rev() {
+        png.remove_chunk_at(index);
+    }
+    indices.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let chunk_type = ChunkType::from_str("ruSt").unwrap();
+        Png::from_chunks(vec![
+            Chunk::new(chunk_type.clone(), b"plain message"),
+            Chunk::new(
+                chunk_type.clone(),
+                &Envelope::text("one").with_name("first").as_bytes(),
+            ),
+            Chunk::new(
+                ChunkType::from_str("teSt").unwrap(),
+                &Envelope::text("two").with_name("second").as_bytes(),
+            ),
+            Chunk::new(chunk_type, &Envelope::text("anonymous").as_bytes()),
+        ])
+    }
+
+    #[test]
+    fn test_envelopes() {
+        let png = testing_png();
+        assert_eq!(envelopes(&png, None).count(), 3);
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        assert_eq!(envelopes(&png, Some(&rust)).count(), 2);
+    }
+
+    #[test]
+    fn test_find_named() {
+        let png = testing_png();
+        let (index, envelope) = find_named(&png, "second", None).unwrap();
+        assert_eq!(index, 2);
+        assert_eq!(envelope.payload(), b"two");
+
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        assert!(matches!(
+            find_named(&png, "second", Some(&rust)),
+            Err(MessageError::NameNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_remove_named() {
+        let mut png = testing_png();
+        assert_eq!(remove_named(&mut png, "first", None), 1);
+        assert_eq!(png.chunks().len(), 3);
+        assert!(find_named(&png, "first", None).is_err());
+        assert!(find_named(&png, "second", None).is_ok());
+        assert_eq!(remove_named(&mut png, "first", None), 0);
+    }
+}
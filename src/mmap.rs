@@ -0,0 +1,104 @@
+//! Read-only memory maps of files, so that large files can be walked with
+//! [`crate::png::chunk_refs`] without reading them into memory: pages are
+//! loaded as they are touched and can be dropped again under pressure.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+use std::ptr::NonNull;
+
+/// The contents of a file, mapped into memory.
+///
+/// The map shows the file as it is on disk: if another process changes it
+/// while mapped, the bytes change too, and truncating it makes reading past
+/// the new end crash the process. That is why [`Mmap::open`] is unsafe.
+pub struct Mmap {
+    ptr: NonNull<libc::c_void>,
+    len: usize,
+}
+
+// The map is read-only and owned by this value.
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+impl Mmap {
+    /// Maps the file at `path`.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may write to or truncate the file while the map lives: the
+    /// slice it derefs to would change under its borrowers, or reading it
+    /// would fault.
+    pub unsafe fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large to map"))?;
+        if len == 0 {
+            // Empty mappings are invalid; there is nothing to read anyway.
+            return Ok(Self {
+                ptr: NonNull::dangling(),
+                len,
+            });
+        }
+        // SAFETY: a fresh private read-only mapping of a file we opened;
+        // the kernel picks the address.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: NonNull::new(ptr).expect("mmap never maps at address 0"),
+            len,
+        })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the mapping is `len` readable bytes for as long as `self`
+        // lives (or `len` is 0 and the pointer is dangling but aligned).
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr().cast(), self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: unmapping exactly what `open` mapped, once.
+            unsafe {
+                libc::munmap(self.ptr.as_ptr(), self.len);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_file() {
+        let path = std::env::temp_dir().join("pngme_mmap.bin");
+        // SAFETY: the file is only rewritten once its map is dropped.
+        let open = |path: &Path| unsafe { Mmap::open(path) };
+        std::fs::write(&path, b"mapped bytes").unwrap();
+        assert_eq!(&*open(&path).unwrap(), b"mapped bytes");
+        std::fs::write(&path, b"").unwrap();
+        assert!(open(&path).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+        assert!(open(&path).is_err());
+    }
+}
@@ -0,0 +1,258 @@
+//! Putting chunks in one canonical order, so files that hold the same
+//! chunks are byte for byte the same and tools that write chunks in odd
+//! places produce valid files.
+//!
+//! Standard chunks go where [`CANONICAL`] puts them, all before `IDAT`.
+//! Other chunks keep their order and stay on their side of `PLTE` and of
+//! the image data, since a chunk that is unsafe to copy may depend on the
+//! critical chunks around it. `IDAT`, `fcTL` and `fdAT` keep their order,
+//! so the pixels and the frames of an animation are untouched, and Apple's
+//! `iDOT`, which points into the image data, stays just before it. Exact
+//! copies of a chunk that may only appear once, or of a text chunk, are
+//! dropped; differing copies are left for [`crate::lint`] to report.
+//! [`deduplicate`] does only that, leaving the order alone, for files that
+//! collected copies over repeated round trips through tools.
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::lint::SINGLE;
+use crate::png::Png;
+
+/// Standard ancillary chunks before `IDAT`, in the order they are put in.
+pub const CANONICAL: &[&[u8; 4]] = &[
+    b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCV", b"cLLI", b"PLTE", b"tRNS",
+    b"bKGD", b"hIST", b"pHYs", b"sPLT", b"eXIf", b"oFFs", b"pCAL", b"sCAL", b"sTER", b"acTL",
+    b"tIME", b"tEXt", b"zTXt", b"iTXt",
+];
+const TEXT: &[&[u8; 4]] = &[b"tEXt", b"zTXt", b"iTXt"];
+
+/// What [`normalize`] changed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Normalized {
+    /// Chunks now at a different position among those kept.
+    pub moved: usize,
+    /// Exact duplicates dropped.
+    pub removed: usize,
+}
+
+impl Normalized {
+    pub fn changed(&self) -> bool {
+        self.moved > 0 || self.removed > 0
+    }
+}
+
+/// What [`deduplicate`] dropped.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Deduplicated {
+    /// How many copies of each type were dropped, in the order of the file.
+    pub removed: Vec<(ChunkType, usize)>,
+    /// How much smaller the file is, headers and CRCs included.
+    pub saved: u64,
+}
+
+impl Deduplicated {
+    /// Copies dropped, of all types.
+    pub fn count(&self) -> usize {
+        self.removed.iter().map(|(_, count)| count).sum()
+    }
+}
+
+/// Drops the exact copies of chunks the specification allows only once,
+/// and of text chunks, keeping the first of each; the rest of the file is
+/// kept as it is.
+pub fn deduplicate(png: &mut Png) -> Deduplicated {
+    let mut deduplicated = Deduplicated::default();
+    let mut kept: Vec<&Chunk> = Vec::new();
+    let mut copies = Vec::new();
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let code = chunk.chunk_type().bytes();
+        let same = |c: &&Chunk| c.chunk_type() == chunk.chunk_type() && c.data() == chunk.data();
+        if (SINGLE.contains(&&code) || TEXT.contains(&&code)) && kept.iter().any(same) {
+            copies.push(index);
+            deduplicated.saved += (Chunk::OVERHEAD + chunk.data().len()) as u64;
+            match deduplicated
+                .removed
+                .iter_mut()
+                .find(|(chunk_type, _)| chunk_type == chunk.chunk_type())
+            {
+                Some((_, count)) => *count += 1,
+                None => deduplicated.removed.push((chunk.chunk_type().clone(), 1)),
+            }
+            continue;
+        }
+        kept.push(chunk);
+    }
+    // Backwards, so indices stay valid.
+    for index in copies.into_iter().rev() {
+        png.remove_chunk_at(index);
+    }
+    deduplicated
+}
+
+/// Where a chunk goes: sorted by slot, then by rank within it, and then
+/// kept in its original order.
+fn slot(code: &[u8; 4], palette_seen: bool, data_seen: bool) -> (u8, usize) {
+    if let Some(rank) = CANONICAL.iter().position(|c| *c == code) {
+        let palette_at = CANONICAL.iter().position(|c| *c == b"PLTE").expect("PLTE");
+        return match rank < palette_at {
+            true => (1, rank),
+            false => (3, rank),
+        };
+    }
+    match code {
+        // Apple's CgBI marker comes first, before IHDR.
+        b"CgBI" | b"IHDR" => (0, 0),
+        b"IEND" => (7, 0),
+        // The first frame's control, and Apple's iDOT, whose offsets count
+        // from it, which must stay just before IDAT.
+        b"fcTL" | b"iDOT" if !data_seen => (5, 0),
+        b"IDAT" => (5, 1),
+        _ if data_seen => (6, 0),
+        _ if palette_seen => (4, 0),
+        _ => (2, 0),
+    }
+}
+
+/// Reorders the chunks of `png` into the canonical order and drops exact
+/// duplicates. The trailer is kept as it is.
+pub fn normalize(png: &mut Png) -> Normalized {
+    let removed = deduplicate(png).count();
+    let moved = reorder(png);
+    Normalized { moved, removed }
+}
+
+/// Puts the chunks of `png` in the canonical order, dropping nothing, and
+/// counts those that moved.
+pub(crate) fn reorder(png: &mut Png) -> usize {
+    let mut kept: Vec<(u8, usize, usize, &Chunk)> = Vec::new();
+    let (mut palette_seen, mut data_seen) = (false, false);
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let code = chunk.chunk_type().bytes();
+        let (slot, rank) = slot(&code, palette_seen, data_seen);
+        kept.push((slot, rank, index, chunk));
+        palette_seen |= code == *b"PLTE";
+        // Every IDAT, even one after other chunks, joins the first.
+        data_seen |= code == *b"IDAT";
+    }
+    let mut order: Vec<(u8, usize, usize, &Chunk)> = kept.clone();
+    order.sort_by_key(|&(slot, rank, index, _)| (slot, rank, index));
+    let moved = kept
+        .iter()
+        .zip(&order)
+        .filter(|(before, after)| before.2 != after.2)
+        .count();
+    let chunks: Vec<Chunk> = order.into_iter().map(|(.., chunk)| chunk.clone()).collect();
+    if moved > 0 {
+        let trailer = png.trailer().to_vec();
+        *png = Png::from_chunks(chunks);
+        png.set_trailer(trailer);
+    }
+    moved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(code: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(code).unwrap(), data)
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_normalize() {
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"a\0b"),
+            chunk("prVt", b"before PLTE"),
+            chunk("PLTE", &[0; 3]),
+            chunk("gAMA", &[0, 0, 1, 0]),
+            chunk("ruSt", b"payload"),
+            chunk("pHYs", &[0; 9]),
+            chunk("tRNS", &[0]),
+            chunk("IDAT", &[1]),
+            chunk("tIME", &[0; 7]),
+            chunk("IDAT", &[2]),
+            chunk("tEXt", b"a\0b"),
+            chunk("tEXt", b"a\0c"),
+            chunk("teSt", b"after IDAT"),
+            chunk("gAMA", &[0, 0, 1, 0]),
+            chunk("IEND", &[]),
+        ]);
+        png.set_trailer(b"trailer".to_vec());
+        let normalized = normalize(&mut png);
+        assert_eq!(
+            types(&png),
+            [
+                "IHDR", "gAMA", "prVt", "PLTE", "tRNS", "pHYs", "tIME", "tEXt", "tEXt", "ruSt",
+                "IDAT", "IDAT", "teSt", "IEND"
+            ]
+        );
+        assert_eq!(normalized.removed, 2);
+        assert!(normalized.moved > 0);
+        assert_eq!(png.chunks()[8].data(), b"a\0c");
+        assert_eq!(png.chunks()[11].data(), [2]);
+        assert_eq!(png.trailer(), b"trailer");
+
+        let bytes = png.as_bytes();
+        assert_eq!(normalize(&mut png), Normalized::default());
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_deduplicate() {
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("gAMA", &[0, 0, 1, 0]),
+            chunk("iTXt", b"XML:com.adobe.xmp\0\0\0\0\0<x/>"),
+            chunk("IDAT", &[1]),
+            chunk("iTXt", b"XML:com.adobe.xmp\0\0\0\0\0<x/>"),
+            chunk("gAMA", &[0, 0, 1, 0]),
+            chunk("gAMA", &[0, 0, 2, 0]),
+            chunk("ruSt", b"payload"),
+            chunk("ruSt", b"payload"),
+            chunk("iTXt", b"XML:com.adobe.xmp\0\0\0\0\0<x/>"),
+            chunk("IEND", &[]),
+        ]);
+        let deduplicated = deduplicate(&mut png);
+        assert_eq!(
+            types(&png),
+            ["IHDR", "gAMA", "iTXt", "IDAT", "gAMA", "ruSt", "ruSt", "IEND"]
+        );
+        assert_eq!(
+            deduplicated.removed,
+            [
+                (ChunkType::from_str("iTXt").unwrap(), 2),
+                (ChunkType::from_str("gAMA").unwrap(), 1)
+            ]
+        );
+        assert_eq!(deduplicated.saved, 2 * (12 + 26) + 16);
+        assert_eq!(deduplicate(&mut png), Deduplicated::default());
+    }
+
+    #[test]
+    fn test_animation_order() {
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("fcTL", &[0; 26]),
+            chunk("acTL", &[0; 8]),
+            chunk("IDAT", &[]),
+            chunk("fcTL", &[1; 26]),
+            chunk("fdAT", &[2; 4]),
+            chunk("IEND", &[]),
+        ]);
+        normalize(&mut png);
+        assert_eq!(
+            types(&png),
+            ["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"]
+        );
+    }
+}
@@ -0,0 +1,382 @@
+//! Payloads as standard OpenPGP messages, so an extracted payload can be
+//! handled by GnuPG and other OpenPGP tools without pngme.
+
+use sequoia_openpgp as openpgp;
+use std::io::{self, Write};
+use thiserror::Error;
+
+use openpgp::armor;
+use openpgp::cert::{Cert, CertParser};
+use openpgp::crypto::{KeyPair, Password, SessionKey};
+use openpgp::packet::{PKESK, SKESK};
+use openpgp::parse::stream::{
+    DecryptionHelper, DecryptorBuilder, DetachedVerifierBuilder, MessageLayer, MessageStructure,
+    VerificationHelper,
+};
+use openpgp::parse::Parse;
+use openpgp::policy::{Policy, StandardPolicy};
+use openpgp::serialize::stream::{Armorer, Encryptor2, LiteralWriter, Message, Signer};
+use openpgp::types::{KeyFlags, SymmetricAlgorithm};
+use openpgp::{Fingerprint, KeyHandle};
+
+#[derive(Debug, Error)]
+pub enum OpenPgpError {
+    #[error("No usable OpenPGP keys found")]
+    NoKeys,
+    #[error("OpenPGP key {0} has no usable encryption subkey")]
+    NoEncryptionKey(Fingerprint),
+    #[error("OpenPGP key {0} has no usable signing key with secret material")]
+    NoSigningKey(Fingerprint),
+    #[error("None of the given OpenPGP keys can decrypt this payload")]
+    NoMatchingKey,
+    #[error("OpenPGP signature verification failed: {0}")]
+    BadSignature(String),
+    #[error("OpenPGP error")]
+    Sequoia(#[from] anyhow::Error),
+}
+
+/// Reads every certificate (public or secret key) from an armored or binary
+/// key file.
+pub fn read_certs(data: &[u8]) -> Result<Vec<Cert>, OpenPgpError> {
+    let certs = CertParser::from_bytes(data)?.collect::<anyhow::Result<Vec<_>>>()?;
+    if certs.is_empty() {
+        return Err(OpenPgpError::NoKeys);
+    }
+    Ok(certs)
+}
+
+/// Whether any secret key in `certs` is protected by a password.
+pub fn needs_password(certs: &[Cert]) -> bool {
+    certs
+        .iter()
+        .flat_map(|c| c.keys().secret())
+        .any(|ka| ka.key().secret().is_encrypted())
+}
+
+/// Wraps `payload` in an OpenPGP message: encrypted to every recipient when
+/// there are any, and signed by `signer` when given. `file_name` ends up in
+/// the literal data packet, like `gpg` does for files.
+pub fn protect(
+    payload: &[u8],
+    file_name: Option<&str>,
+    recipients: &[Cert],
+    signer: Option<(&Cert, Option<&str>)>,
+) -> Result<Vec<u8>, OpenPgpError> {
+    let policy = StandardPolicy::new();
+    let mut encryption_keys = Vec::new();
+    for cert in recipients {
+        let before = encryption_keys.len();
+        encryption_keys.extend(
+            cert.keys()
+                .with_policy(&policy, None)
+                .supported()
+                .alive()
+                .revoked(false)
+                .key_flags(encryption_flags()),
+        );
+        if encryption_keys.len() == before {
+            return Err(OpenPgpError::NoEncryptionKey(cert.fingerprint()));
+        }
+    }
+    let signing_pair = match signer {
+        Some((cert, password)) => Some(signing_pair(&policy, cert, password)?),
+        None => None,
+    };
+
+    let mut sink = Vec::new();
+    let mut message = Message::new(&mut sink);
+    if !encryption_keys.is_empty() {
+        message = Encryptor2::for_recipients(message, encryption_keys).build()?;
+    }
+    if let Some(pair) = signing_pair {
+        message = Signer::new(message, pair).build()?;
+    }
+    let mut literal = LiteralWriter::new(message);
+    if let Some(name) = file_name {
+        literal = literal.filename(name)?;
+    }
+    let mut message = literal.build()?;
+    message.write_all(payload).map_err(anyhow::Error::from)?;
+    message.finalize()?;
+    Ok(sink)
+}
+
+/// An ASCII-armored detached signature of `data` by `signer`, as
+/// `gpg --detach-sign --armor` makes.
+pub fn sign_detached(data: &[u8], signer: (&Cert, Option<&str>)) -> Result<String, OpenPgpError> {
+    let policy = StandardPolicy::new();
+    let (cert, password) = signer;
+    let pair = signing_pair(&policy, cert, password)?;
+    let mut sink = Vec::new();
+    let message = Message::new(&mut sink);
+    let message = Armorer::new(message).kind(armor::Kind::Signature).build()?;
+    let mut message = Signer::new(message, pair).detached().build()?;
+    message.write_all(data).map_err(anyhow::Error::from)?;
+    message.finalize()?;
+    Ok(String::from_utf8(sink).expect("armor is ASCII"))
+}
+
+/// Checks a detached signature of `data`, armored or not, which must be by
+/// one of `signers`. Returns the certificate of the one that made it.
+pub fn verify_detached(
+    data: &[u8],
+    signature: &[u8],
+    signers: &[Cert],
+) -> Result<Fingerprint, OpenPgpError> {
+    if signers.is_empty() {
+        return Err(OpenPgpError::NoKeys);
+    }
+    let policy = StandardPolicy::new();
+    let helper = Helper {
+        policy: &policy,
+        keys: &[],
+        password: None,
+        signers,
+        signed_by: None,
+    };
+    let mut verifier = DetachedVerifierBuilder::from_bytes(signature)?
+        .with_policy(&policy, None, helper)
+        .map_err(classify)?;
+    verifier.verify_bytes(data).map_err(classify)?;
+    let helper = verifier.into_helper();
+    helper
+        .signed_by
+        .ok_or_else(|| OpenPgpError::BadSignature("no valid signature".to_string()))
+}
+
+/// The contents of an OpenPGP message opened by [`open`].
+#[derive(Debug)]
+pub struct Opened {
+    pub payload: Vec<u8>,
+    /// The certificate of the verified signer, when `signers` were given.
+    pub signed_by: Option<Fingerprint>,
+}
+
+/// Decrypts and/or verifies an OpenPGP message. Encrypted messages need a
+/// matching secret key in `keys`. When `signers` is not empty, the message
+/// must carry a valid signature by one of them.
+pub fn open(
+    message: &[u8],
+    keys: &[Cert],
+    password: Option<&str>,
+    signers: &[Cert],
+) -> Result<Opened, OpenPgpError> {
+    let policy = StandardPolicy::new();
+    let helper = Helper {
+        policy: &policy,
+        keys,
+        password,
+        signers,
+        signed_by: None,
+    };
+    let mut decryptor =
+        match DecryptorBuilder::from_bytes(message)?.with_policy(&policy, None, helper) {
+            Ok(decryptor) => decryptor,
+            Err(err) => return Err(classify(err)),
+        };
+    let mut payload = Vec::new();
+    io::copy(&mut decryptor, &mut payload).map_err(anyhow::Error::from)?;
+    let helper = decryptor.into_helper();
+    Ok(Opened {
+        payload,
+        signed_by: helper.signed_by,
+    })
+}
+
+fn classify(err: anyhow::Error) -> OpenPgpError {
+    match err.downcast::<OpenPgpError>() {
+        Ok(err) => err,
+        Err(err) => OpenPgpError::Sequoia(err),
+    }
+}
+
+/// The signing key of `cert`, unlocked with `password` if it needs one.
+fn signing_pair(
+    policy: &dyn Policy,
+    cert: &Cert,
+    password: Option<&str>,
+) -> Result<KeyPair, OpenPgpError> {
+    let ka = cert
+        .keys()
+        .secret()
+        .with_policy(policy, None)
+        .supported()
+        .alive()
+        .revoked(false)
+        .for_signing()
+        .next()
+        .ok_or_else(|| OpenPgpError::NoSigningKey(cert.fingerprint()))?;
+    let mut key = ka.key().clone();
+    if key.secret().is_encrypted() {
+        let password = password.ok_or(OpenPgpError::NoSigningKey(cert.fingerprint()))?;
+        key = key.decrypt_secret(&Password::from(password))?;
+    }
+    Ok(key.into_keypair()?)
+}
+
+fn encryption_flags() -> KeyFlags {
+    KeyFlags::empty()
+        .set_transport_encryption()
+        .set_storage_encryption()
+}
+
+struct Helper<'a> {
+    policy: &'a dyn Policy,
+    keys: &'a [Cert],
+    password: Option<&'a str>,
+    signers: &'a [Cert],
+    signed_by: Option<Fingerprint>,
+}
+
+impl VerificationHelper for Helper<'_> {
+    fn get_certs(&mut self, _ids: &[KeyHandle]) -> openpgp::Result<Vec<Cert>> {
+        Ok(self.signers.to_vec())
+    }
+
+    fn check(&mut self, structure: MessageStructure) -> openpgp::Result<()> {
+        if self.signers.is_empty() {
+            return Ok(());
+        }
+        let mut errors = Vec::new();
+        for layer in structure {
+            if let MessageLayer::SignatureGroup { results } = layer {
+                for result in results {
+                    match result {
+                        Ok(good) => {
+                            self.signed_by = Some(good.ka.cert().fingerprint());
+                            return Ok(());
+                        }
+                        Err(err) => errors.push(err.to_string()),
+                    }
+                }
+            }
+        }
+        let reason = if errors.is_empty() {
+            "the payload is not signed".to_string()
+        } else {
+            errors.join("; ")
+        };
+        Err(OpenPgpError::BadSignature(reason).into())
+    }
+}
+
+impl DecryptionHelper for Helper<'_> {
+    fn decrypt<D>(
+        &mut self,
+        pkesks: &[PKESK],
+        _skesks: &[SKESK],
+        sym_algo: Option<SymmetricAlgorithm>,
+        mut decrypt: D,
+    ) -> openpgp::Result<Option<Fingerprint>>
+    where
+        D: FnMut(SymmetricAlgorithm, &SessionKey) -> bool,
+    {
+        for cert in self.keys {
+            let candidates = cert
+                .keys()
+                .secret()
+                .with_policy(self.policy, None)
+                .supported()
+                .key_flags(encryption_flags());
+            for ka in candidates {
+                let mut key = ka.key().clone();
+                if key.secret().is_encrypted() {
+                    let Some(password) = self.password else {
+                        continue;
+                    };
+                    key = key.decrypt_secret(&Password::from(password))?;
+                }
+                let mut pair = key.into_keypair()?;
+                for pkesk in pkesks {
+                    if let Some((algo, session_key)) = pkesk.decrypt(&mut pair, sym_algo) {
+                        if decrypt(algo, &session_key) {
+                            return Ok(Some(cert.fingerprint()));
+                        }
+                    }
+                }
+            }
+        }
+        Err(OpenPgpError::NoMatchingKey.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openpgp::cert::CertBuilder;
+
+    fn testing_cert(userid: &str) -> Cert {
+        let (cert, _) = CertBuilder::general_purpose(None, Some(userid))
+            .generate()
+            .unwrap();
+        cert
+    }
+
+    #[test]
+    fn test_encrypt_and_sign_round_trip() {
+        let alice = testing_cert("alice@example.org");
+        let bob = testing_cert("bob@example.org");
+        let message = protect(
+            b"secret",
+            Some("notes.txt"),
+            std::slice::from_ref(&bob),
+            Some((&alice, None)),
+        )
+        .unwrap();
+        let opened = open(&message, &[bob], None, std::slice::from_ref(&alice)).unwrap();
+        assert_eq!(opened.payload, b"secret");
+        assert_eq!(opened.signed_by, Some(alice.fingerprint()));
+    }
+
+    #[test]
+    fn test_wrong_key() {
+        let bob = testing_cert("bob@example.org");
+        let mallory = testing_cert("mallory@example.org");
+        let message = protect(b"secret", None, &[bob], None).unwrap();
+        assert!(matches!(
+            open(&message, &[mallory], None, &[]),
+            Err(OpenPgpError::NoMatchingKey)
+        ));
+    }
+
+    #[test]
+    fn test_signature_required() {
+        let alice = testing_cert("alice@example.org");
+        let mallory = testing_cert("mallory@example.org");
+        let unsigned = protect(b"hello", None, &[], None).unwrap();
+        assert!(matches!(
+            open(&unsigned, &[], None, std::slice::from_ref(&alice)),
+            Err(OpenPgpError::BadSignature(_))
+        ));
+        let signed = protect(b"hello", None, &[], Some((&mallory, None))).unwrap();
+        assert!(open(&signed, &[], None, &[alice]).is_err());
+        assert_eq!(open(&signed, &[], None, &[]).unwrap().payload, b"hello");
+    }
+
+    #[test]
+    fn test_detached_signature() {
+        let alice = testing_cert("alice@example.org");
+        let mallory = testing_cert("mallory@example.org");
+        let signature = sign_detached(b"manifest", (&alice, None)).unwrap();
+        assert!(signature.starts_with("-----BEGIN PGP SIGNATURE-----"));
+        let signers = [mallory.clone(), alice.clone()];
+        assert_eq!(
+            verify_detached(b"manifest", signature.as_bytes(), &signers).unwrap(),
+            alice.fingerprint()
+        );
+        assert!(matches!(
+            verify_detached(b"manifest!", signature.as_bytes(), &signers),
+            Err(OpenPgpError::BadSignature(_))
+        ));
+        assert!(verify_detached(b"manifest", signature.as_bytes(), &[mallory]).is_err());
+    }
+
+    #[test]
+    fn test_read_certs() {
+        use openpgp::serialize::SerializeInto;
+        let cert = testing_cert("alice@example.org");
+        let armored = cert.armored().to_vec().unwrap();
+        assert_eq!(read_certs(&armored).unwrap()[0], cert);
+        assert!(read_certs(b"").is_err());
+    }
+}
@@ -0,0 +1,619 @@
+//! Every choice about how a PNG file is parsed, and with `std` written, in
+//! one place.
+//!
+//! A [`ParseOptions`] is built once, with the defaults of `Png::try_from`
+//! changed where a caller needs to, and handed to whichever entry point
+//! reads the file: [`Png::parse_with`], [`Decoder::with_options`] (and so
+//! [`crate::decoder::Chunks`]) and, with `std`, `LazyPng::open_with`.
+//! `WriteOptions` does the same for `Png::as_bytes_with` and
+//! `Png::write_into_with`, so one [`Png`] can be written the way each
+//! consumer downstream expects: `IDAT` cut to the size it reads, metadata
+//! where it looks for it, no trailer, text chunks of the kind it decodes.
+//!
+//! [`Png::parse_with`]: crate::png::Png::parse_with
+//! [`Decoder::with_options`]: crate::decoder::Decoder::with_options
+//! [`Png`]: crate::png::Png
+
+#[cfg(feature = "std")]
+use std::str::FromStr;
+
+#[cfg(feature = "std")]
+use crate::chunk::Chunk;
+#[cfg(feature = "std")]
+use crate::chunk_type::ChunkType;
+#[cfg(feature = "std")]
+use crate::compression::{Algorithm, Compression};
+use crate::decoder::CriticalPolicy;
+use crate::limits::{self, Limits};
+#[cfg(feature = "std")]
+use crate::lint::{self, Profile, Rule, Severity};
+#[cfg(feature = "std")]
+use crate::normalize;
+#[cfg(feature = "std")]
+use crate::png::{Png, PngError};
+#[cfg(feature = "std")]
+use crate::warning::{WarningKind, WithWarnings};
+
+/// What happens to a chunk whose CRC doesn't match its type and data.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CrcPolicy {
+    /// Fail with [`crate::chunk::ChunkError::ChecksumError`].
+    #[default]
+    Error,
+    /// Keep it, as [`crate::png::Png::from_bytes_unchecked`] does, for
+    /// tools that report or repair damage.
+    Keep,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParseOptions {
+    crc_policy: CrcPolicy,
+    limits: Limits,
+    critical_policy: CriticalPolicy,
+    #[cfg(feature = "std")]
+    lazy: bool,
+    #[cfg(feature = "std")]
+    profile: Option<Profile>,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ParseOptions {
+    /// The options of `Png::try_from`: CRCs checked, the process-wide
+    /// limits (see [`crate::limits`]), unknown critical chunks kept, every
+    /// chunk read up front and no conformance check.
+    pub fn new() -> Self {
+        Self {
+            crc_policy: CrcPolicy::default(),
+            limits: limits::get(),
+            critical_policy: CriticalPolicy::default(),
+            #[cfg(feature = "std")]
+            lazy: false,
+            #[cfg(feature = "std")]
+            profile: None,
+        }
+    }
+    pub fn with_crc_policy(self, crc_policy: CrcPolicy) -> Self {
+        Self { crc_policy, ..self }
+    }
+    pub fn with_limits(self, limits: Limits) -> Self {
+        Self { limits, ..self }
+    }
+    pub fn with_critical_policy(self, critical_policy: CriticalPolicy) -> Self {
+        Self {
+            critical_policy,
+            ..self
+        }
+    }
+    /// Leaves chunk data in the file until it is asked for, checking its
+    /// CRC then. Only readers that can seek have anything to defer, so
+    /// this applies to `LazyPng::open_with` alone; bytes already in memory
+    /// are parsed whole.
+    #[cfg(feature = "std")]
+    pub fn with_lazy(self, lazy: bool) -> Self {
+        Self { lazy, ..self }
+    }
+    /// Lints the file against `profile` once it is parsed and refuses it
+    /// with [`crate::png::PngError::Nonconforming`] on the first error,
+    /// leaving out the CRC and unknown critical checks the policies
+    /// already decide. Linting needs every chunk's data, so it overrides
+    /// [`ParseOptions::with_lazy`].
+    #[cfg(feature = "std")]
+    pub fn with_profile(self, profile: Profile) -> Self {
+        Self {
+            profile: Some(profile),
+            ..self
+        }
+    }
+    pub fn crc_policy(&self) -> CrcPolicy {
+        self.crc_policy
+    }
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+    pub fn critical_policy(&self) -> CriticalPolicy {
+        self.critical_policy
+    }
+    /// Whether to defer reading chunk data, which a conformance profile
+    /// rules out.
+    #[cfg(feature = "std")]
+    pub fn lazy(&self) -> bool {
+        self.lazy && self.profile.is_none()
+    }
+    #[cfg(feature = "std")]
+    pub fn profile(&self) -> Option<Profile> {
+        self.profile
+    }
+    /// Refuses `png` on the first error it has under the profile, if any.
+    /// Damaged and unknown critical chunks got this far because the
+    /// policies let them.
+    #[cfg(feature = "std")]
+    pub(crate) fn check_conformance(&self, png: &Png) -> Result<(), PngError> {
+        let Some(profile) = self.profile else {
+            return Ok(());
+        };
+        let decided = |rule| matches!(rule, Rule::BadCrc | Rule::UnknownCritical);
+        match lint::lint_with_profile(png, profile)
+            .into_iter()
+            .find(|lint| lint.severity() == Severity::Error && !decided(lint.rule))
+        {
+            Some(lint) => Err(PngError::Nonconforming { profile, lint }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Where [`WriteOptions`] puts ancillary chunks.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChunkOrder {
+    /// Where they are in the [`Png`].
+    #[default]
+    Keep,
+    /// The order of [`normalize::normalize`], without dropping duplicates.
+    Canonical,
+    /// Text and `tIME` chunks after the image data moved just before it,
+    /// for consumers that read a file only up to its pixels.
+    MetadataFirst,
+}
+
+/// Which kind of chunk [`WriteOptions`] stores text in. Text that the
+/// kind can't hold as it is (anything but Latin-1 in `tEXt` and `zTXt`,
+/// or an `iTXt` language tag or translated keyword, which they have no
+/// room for) stays in the chunk it came in, as does text that doesn't
+/// decode.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextChunks {
+    /// As they are in the [`Png`].
+    #[default]
+    Keep,
+    /// `tEXt`, uncompressed Latin-1.
+    Plain,
+    /// `zTXt`, deflated Latin-1.
+    Compressed,
+    /// `iTXt`, UTF-8, compressed where it was.
+    International,
+}
+
+/// How a [`Png`] is written; see [`Png::as_bytes_with`].
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteOptions {
+    idat_len: Option<usize>,
+    chunk_order: ChunkOrder,
+    trailer: bool,
+    text_chunks: TextChunks,
+}
+
+#[cfg(feature = "std")]
+impl Default for WriteOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl WriteOptions {
+    /// The file as [`Png::as_bytes`] writes it: chunks as they are, and
+    /// the trailer kept.
+    pub fn new() -> Self {
+        Self {
+            idat_len: None,
+            chunk_order: ChunkOrder::default(),
+            trailer: true,
+            text_chunks: TextChunks::default(),
+        }
+    }
+    /// Joins the image data and cuts it into `IDAT` chunks of `len` bytes,
+    /// where the first one was. Apple's `iDOT`, whose offsets would be
+    /// wrong, is dropped.
+    ///
+    /// # Panics
+    ///
+    /// If `len` is 0 or more than [`Chunk::MAX_LENGTH`].
+    pub fn with_idat_len(self, len: usize) -> Self {
+        assert!(len > 0 && len <= Chunk::MAX_LENGTH as usize);
+        Self {
+            idat_len: Some(len),
+            ..self
+        }
+    }
+    pub fn with_chunk_order(self, chunk_order: ChunkOrder) -> Self {
+        Self {
+            chunk_order,
+            ..self
+        }
+    }
+    /// Whether to write what follows `IEND`.
+    pub fn with_trailer(self, trailer: bool) -> Self {
+        Self { trailer, ..self }
+    }
+    pub fn with_text_chunks(self, text_chunks: TextChunks) -> Self {
+        Self {
+            text_chunks,
+            ..self
+        }
+    }
+    pub fn idat_len(&self) -> Option<usize> {
+        self.idat_len
+    }
+    pub fn chunk_order(&self) -> ChunkOrder {
+        self.chunk_order
+    }
+    pub fn trailer(&self) -> bool {
+        self.trailer
+    }
+    pub fn text_chunks(&self) -> TextChunks {
+        self.text_chunks
+    }
+    /// `png` as these options write it, leaving `png` alone. Clones share
+    /// chunk data, so only the chunks that change are copied.
+    pub fn apply(&self, png: &Png) -> Png {
+        self.apply_with_warnings(png).value
+    }
+    /// Like [`WriteOptions::apply`], with a [`WarningKind::TextKept`] for
+    /// each text chunk left as it was.
+    pub fn apply_with_warnings(&self, png: &Png) -> WithWarnings<Png> {
+        let mut png = WithWarnings::new(png.clone());
+        if self.text_chunks != TextChunks::Keep {
+            for index in 0..png.value.chunks().len() {
+                let chunk = &png.value.chunks()[index];
+                match convert_text(chunk, self.text_chunks) {
+                    Ok(Some(converted)) => {
+                        png.value.replace_chunk_at(index, converted);
+                    }
+                    Ok(None) => {}
+                    Err(why) => {
+                        let message = format!("{} stays as it is: {}", chunk.chunk_type(), why);
+                        png.warn(WarningKind::TextKept, Some(index), message);
+                    }
+                }
+            }
+        }
+        let warnings = core::mem::take(&mut png.warnings);
+        let mut png = png.value;
+        match self.chunk_order {
+            ChunkOrder::Keep => {}
+            ChunkOrder::Canonical => {
+                normalize::reorder(&mut png);
+            }
+            ChunkOrder::MetadataFirst => metadata_first(&mut png),
+        }
+        if let Some(len) = self.idat_len {
+            resplit_data(&mut png, len);
+        }
+        if !self.trailer {
+            png.set_trailer(Vec::new());
+        }
+        WithWarnings {
+            value: png,
+            warnings,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+fn chunk(code: &str, data: &[u8]) -> Chunk {
+    Chunk::new(ChunkType::from_str(code).expect("valid chunk type"), data)
+}
+
+/// Moves text and `tIME` chunks after the first `IDAT` to just before it
+/// (and before the `fcTL` or `iDOT` that must stay next to it).
+#[cfg(feature = "std")]
+fn metadata_first(png: &mut Png) {
+    let code = |png: &Png, index: usize| png.chunks()[index].chunk_type().bytes();
+    let Some(data_at) = (0..png.chunks().len()).find(|&i| code(png, i) == *b"IDAT") else {
+        return;
+    };
+    let mut at = data_at;
+    while at > 0 && matches!(&code(png, at - 1), b"fcTL" | b"iDOT") {
+        at -= 1;
+    }
+    let late: Vec<usize> = (data_at..png.chunks().len())
+        .filter(|&i| matches!(&code(png, i), b"tEXt" | b"zTXt" | b"iTXt" | b"tIME"))
+        .collect();
+    // Backwards, so indices stay valid.
+    let moved: Vec<Chunk> = late
+        .into_iter()
+        .rev()
+        .map(|index| png.remove_chunk_at(index))
+        .collect();
+    for (i, chunk) in moved.into_iter().rev().enumerate() {
+        png.insert_chunk_at(at + i, chunk);
+    }
+}
+
+/// Replaces the `IDAT` chunks of `png` with their data in chunks of `len`
+/// bytes, where the first was, and drops `iDOT`.
+#[cfg(feature = "std")]
+fn resplit_data(png: &mut Png, len: usize) {
+    let is_data = |chunk: &Chunk| chunk.chunk_type().bytes() == *b"IDAT";
+    let Some(at) = png.chunks().iter().position(is_data) else {
+        return;
+    };
+    let data: Vec<u8> = png
+        .chunks()
+        .iter()
+        .filter(|chunk| is_data(chunk))
+        .flat_map(|chunk| chunk.data().iter().copied())
+        .collect();
+    let mut at = at;
+    for index in (0..png.chunks().len()).rev() {
+        let chunk = &png.chunks()[index];
+        if is_data(chunk) || chunk.chunk_type().bytes() == *b"iDOT" {
+            png.remove_chunk_at(index);
+            at -= usize::from(index < at);
+        }
+    }
+    for (i, data) in data.chunks(len).enumerate() {
+        png.insert_chunk_at(at + i, chunk("IDAT", data));
+    }
+}
+
+/// A text chunk, decoded.
+#[cfg(feature = "std")]
+struct Text {
+    keyword: Vec<u8>,
+    compressed: bool,
+    language: Vec<u8>,
+    translated: Vec<u8>,
+    text: String,
+}
+
+#[cfg(feature = "std")]
+impl Text {
+    fn read(chunk: &Chunk) -> Option<Self> {
+        let data = chunk.data();
+        let keyword_len = data.iter().position(|&b| b == 0)?;
+        let (keyword, rest) = (data[..keyword_len].to_vec(), &data[keyword_len + 1..]);
+        let latin1 = |bytes: &[u8]| bytes.iter().map(|&b| char::from(b)).collect();
+        let text = |keyword, compressed, text| Self {
+            keyword,
+            compressed,
+            language: Vec::new(),
+            translated: Vec::new(),
+            text,
+        };
+        match &chunk.chunk_type().bytes() {
+            b"tEXt" => Some(text(keyword, false, latin1(rest))),
+            b"zTXt" => Some(text(
+                keyword,
+                true,
+                latin1(&inflate(rest.strip_prefix(&[0])?)?),
+            )),
+            b"iTXt" => {
+                let (&flag, rest) = rest.split_first()?;
+                let mut fields = rest.get(1..)?.splitn(3, |&b| b == 0);
+                let (language, translated) = (fields.next()?, fields.next()?);
+                let text = fields.next()?;
+                let text = match flag {
+                    0 => text.to_vec(),
+                    _ => inflate(text)?,
+                };
+                Some(Self {
+                    keyword,
+                    compressed: flag != 0,
+                    language: language.to_vec(),
+                    translated: translated.to_vec(),
+                    text: String::from_utf8(text).ok()?,
+                })
+            }
+            _ => None,
+        }
+    }
+    /// The text in a chunk of `kind`, if it fits.
+    fn write(&self, kind: TextChunks) -> Option<Chunk> {
+        let latin1 = || -> Option<Vec<u8>> {
+            if !self.language.is_empty() || !self.translated.is_empty() {
+                return None;
+            }
+            self.text.chars().map(|c| u8::try_from(c).ok()).collect()
+        };
+        let deflate = |bytes: &[u8]| Compression::new(Algorithm::Deflate).compress(bytes);
+        let mut data = self.keyword.clone();
+        data.push(0);
+        match kind {
+            TextChunks::Keep => None,
+            TextChunks::Plain => {
+                data.extend(latin1()?);
+                Some(chunk("tEXt", &data))
+            }
+            TextChunks::Compressed => {
+                data.push(0);
+                data.extend(deflate(&latin1()?));
+                Some(chunk("zTXt", &data))
+            }
+            TextChunks::International => {
+                data.extend([u8::from(self.compressed), 0]);
+                data.extend(&self.language);
+                data.push(0);
+                data.extend(&self.translated);
+                data.push(0);
+                match self.compressed {
+                    true => data.extend(deflate(self.text.as_bytes())),
+                    false => data.extend(self.text.as_bytes()),
+                }
+                Some(chunk("iTXt", &data))
+            }
+        }
+    }
+}
+
+/// `chunk` as a text chunk of `kind`, if it is a text chunk of another
+/// kind, or why its text can't be moved.
+#[cfg(feature = "std")]
+fn convert_text(chunk: &Chunk, kind: TextChunks) -> Result<Option<Chunk>, String> {
+    let target = match kind {
+        TextChunks::Keep => return Ok(None),
+        TextChunks::Plain => "tEXt",
+        TextChunks::Compressed => "zTXt",
+        TextChunks::International => "iTXt",
+    };
+    let code = chunk.chunk_type().bytes();
+    if code == *target.as_bytes() || !matches!(&code, b"tEXt" | b"zTXt" | b"iTXt") {
+        return Ok(None);
+    }
+    let text = Text::read(chunk).ok_or("its text doesn't decode")?;
+    match text.write(kind) {
+        Some(converted) => Ok(Some(converted)),
+        None => Err(format!("its text doesn't fit in {}", target)),
+    }
+}
+
+/// The zlib stream `data`, inflated as far as the limits allow.
+#[cfg(feature = "std")]
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let max = limits::get().max_decompressed_len;
+    let out = Algorithm::Deflate.decompress_at_most(data, max).ok()?;
+    (out.len() <= max).then_some(out)
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::chunk::{Chunk, ChunkError};
+    use crate::chunk_type::ChunkType;
+    use crate::lazy::LazyPng;
+    use crate::limits::{Limit, LimitExceeded};
+    use crate::png::{Png, PngError};
+    use std::io::Cursor;
+    use std::str::FromStr;
+
+    fn testing_png() -> Vec<u8> {
+        let ihdr = [0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0];
+        let chunks = [
+            ("IHDR", &ihdr[..]),
+            ("ruSt", b"hello"),
+            ("RUST", b"critical"),
+            ("IEND", b""),
+        ];
+        Png::from_chunks(
+            chunks
+                .iter()
+                .map(|(code, data)| Chunk::new(ChunkType::from_str(code).unwrap(), data))
+                .collect(),
+        )
+        .as_bytes()
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let mut bytes = testing_png();
+        // The last byte of the ruSt data.
+        bytes[33 + 8 + 4] ^= 1;
+        assert!(matches!(
+            Png::parse_with(&bytes, &ParseOptions::new()),
+            Err(PngError::BadChunk {
+                source: ChunkError::ChecksumError,
+                ..
+            })
+        ));
+        let keep = ParseOptions::new().with_crc_policy(CrcPolicy::Keep);
+        assert!(!Png::parse_with(&bytes, &keep).unwrap().chunks()[1].has_valid_crc());
+
+        let bytes = testing_png();
+        let quarantine = ParseOptions::new().with_critical_policy(CriticalPolicy::Quarantine);
+        assert_eq!(
+            Png::parse_with(&bytes, &quarantine)
+                .unwrap()
+                .quarantined()
+                .len(),
+            1
+        );
+        let limited = ParseOptions::new().with_limits(Limits {
+            max_chunks: 2,
+            ..Limits::UNLIMITED
+        });
+        assert!(matches!(
+            Png::parse_with(&bytes, &limited),
+            Err(PngError::LimitExceeded(LimitExceeded {
+                limit: Limit::Chunks,
+                ..
+            }))
+        ));
+
+        // IHDR says 8-bit grayscale, but there is no IDAT.
+        let strict = quarantine.with_profile(Profile::Third);
+        assert!(matches!(
+            Png::parse_with(&bytes, &strict),
+            Err(PngError::Nonconforming { lint, .. }) if lint.rule == Rule::MissingData
+        ));
+        assert!(matches!(
+            LazyPng::open_with(Cursor::new(&bytes), &strict.with_lazy(true)),
+            Err(PngError::Nonconforming { .. })
+        ));
+
+        let lazy = LazyPng::open_with(Cursor::new(&bytes), &quarantine.with_lazy(true)).unwrap();
+        assert_eq!(lazy.headers().len(), 3);
+        assert_eq!(lazy.quarantined()[0].chunk_type.to_string(), "RUST");
+        assert_eq!(lazy.trailer_len(), 0);
+        let png = lazy.into_png().unwrap();
+        assert_eq!(
+            png.as_bytes(),
+            Png::parse_with(&bytes, &quarantine).unwrap().as_bytes()
+        );
+        assert_eq!(png.quarantined().len(), 1);
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_write_options() {
+        let chunk = |code: &str, data: &[u8]| Chunk::new(ChunkType::from_str(code).unwrap(), data);
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("iDOT", &[0; 28]),
+            chunk("IDAT", b"abcde"),
+            chunk("tEXt", b"Title\0caf\xe9"),
+            chunk("IDAT", b"fgh"),
+            chunk("iTXt", b"Author\0\0\0ja\0\0name"),
+            chunk("tIME", &[0; 7]),
+            chunk("gAMA", &[0, 0, 1, 0]),
+            chunk("IEND", &[]),
+        ]);
+        png.set_trailer(b"trailer".to_vec());
+        assert_eq!(png.as_bytes_with(&WriteOptions::new()), png.as_bytes());
+
+        let options = WriteOptions::new()
+            .with_idat_len(3)
+            .with_chunk_order(ChunkOrder::MetadataFirst)
+            .with_trailer(false)
+            .with_text_chunks(TextChunks::International);
+        let written = Png::try_from(png.as_bytes_with(&options).as_slice()).unwrap();
+        assert_eq!(
+            types(&written),
+            ["IHDR", "iTXt", "iTXt", "tIME", "IDAT", "IDAT", "IDAT", "gAMA", "IEND"]
+        );
+        assert_eq!(written.chunks()[1].data(), "Title\0\0\0\0\0café".as_bytes());
+        let data: Vec<&[u8]> = written.chunks()[4..7].iter().map(Chunk::data).collect();
+        assert_eq!(data, [&b"abc"[..], b"def", b"gh"]);
+        assert!(written.trailer().is_empty());
+
+        // A language tag doesn't fit in zTXt.
+        let options = WriteOptions::new().with_text_chunks(TextChunks::Compressed);
+        let written = options.apply_with_warnings(&png);
+        assert_eq!(written.warnings.len(), 1);
+        assert_eq!(
+            written.warnings[0].to_string(),
+            "chunk 5: iTXt stays as it is: its text doesn't fit in zTXt"
+        );
+        let written = written.value;
+        assert_eq!(types(&written)[3..6], ["zTXt", "IDAT", "iTXt"]);
+        let plain = WriteOptions::new().with_text_chunks(TextChunks::Plain);
+        assert_eq!(plain.apply(&written).chunks()[3].data(), b"Title\0caf\xe9");
+
+        let canonical = WriteOptions::new().with_chunk_order(ChunkOrder::Canonical);
+        assert_eq!(types(&canonical.apply(&png))[1], "gAMA");
+        assert_eq!(canonical.apply(&png).trailer(), b"trailer");
+    }
+}
@@ -0,0 +1,83 @@
+//! Spreading independent work, like CRC checks over many chunks or files,
+//! across threads.
+//!
+//! Without the `parallel` feature everything runs on the calling thread, in
+//! order. With it, [`map`] starts one scoped thread per core, and each takes
+//! the next unclaimed item until none are left, so a few large items don't
+//! hold up the rest.
+
+use alloc::vec::Vec;
+#[cfg(feature = "parallel")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "parallel")]
+use std::thread;
+
+/// `f` applied to every item, results in the order of `items`.
+pub fn map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    #[cfg(feature = "parallel")]
+    {
+        let threads = thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(items.len());
+        if threads > 1 {
+            return threaded(items, &f, threads);
+        }
+    }
+    items.iter().map(f).collect()
+}
+
+#[cfg(feature = "parallel")]
+fn threaded<T: Sync, R: Send>(
+    items: &[T],
+    f: &(impl Fn(&T) -> R + Sync),
+    threads: usize,
+) -> Vec<R> {
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<Option<R>> = items.iter().map(|_| None).collect();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut done = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(index) else {
+                            break;
+                        };
+                        done.push((index, f(item)));
+                    }
+                    done
+                })
+            })
+            .collect();
+        for worker in workers {
+            let done = worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+            for (index, result) in done {
+                results[index] = Some(result);
+            }
+        }
+    });
+    results
+        .into_iter()
+        .map(|result| result.expect("every item is claimed once"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_keeps_order() {
+        let items: Vec<u64> = (0..1000).collect();
+        let squares = map(&items, |n| n * n);
+        assert_eq!(squares.len(), 1000);
+        assert!(squares
+            .iter()
+            .enumerate()
+            .all(|(n, &s)| s == (n * n) as u64));
+        assert!(map(&[] as &[u64], |n| *n).is_empty());
+    }
+}
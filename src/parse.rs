@@ -0,0 +1,226 @@
+//! Byte-level parsers for the formats pngme reads, built from nom
+//! combinators.
+//!
+//! Every parser names what it reads with `context`, so a failure can say
+//! which field ran out of input and where, as a [`Span`] into the whole
+//! input, rather than just that something was short.
+
+use core::fmt::{self, Display, Formatter};
+use nom::bytes::complete::take;
+#[cfg(feature = "crypto")]
+use nom::combinator::flat_map;
+use nom::combinator::map;
+use nom::error::{context, ContextError, ErrorKind, ParseError};
+use nom::number::complete::be_u32;
+#[cfg(feature = "crypto")]
+use nom::number::complete::{be_u16, be_u64, u8 as byte};
+#[cfg(feature = "crypto")]
+use nom::sequence::tuple;
+#[cfg(feature = "crypto")]
+use nom::ToUsize;
+
+/// Where parsing stopped: the offset of the field that didn't fit, from
+/// the start of the input, and its name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub offset: usize,
+    pub what: &'static str,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.what, self.offset)
+    }
+}
+
+/// The error of the parsers below: the input left where the innermost
+/// named parser failed, and its name.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Error<'a> {
+    input: &'a [u8],
+    what: Option<&'static str>,
+}
+
+impl<'a> ParseError<&'a [u8]> for Error<'a> {
+    fn from_error_kind(input: &'a [u8], _: ErrorKind) -> Self {
+        Self { input, what: None }
+    }
+    fn append(_: &'a [u8], _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<'a> ContextError<&'a [u8]> for Error<'a> {
+    fn add_context(_: &'a [u8], what: &'static str, other: Self) -> Self {
+        Self {
+            what: other.what.or(Some(what)),
+            ..other
+        }
+    }
+}
+
+pub(crate) type IResult<'a, T> = nom::IResult<&'a [u8], T, Error<'a>>;
+
+/// Turns the failure of a parser run on part of `whole` into a [`Span`].
+pub(crate) fn span(whole: &[u8], err: nom::Err<Error<'_>>) -> Span {
+    match err {
+        nom::Err::Error(e) | nom::Err::Failure(e) => Span {
+            offset: whole.len() - e.input.len(),
+            what: e.what.unwrap_or("input"),
+        },
+        nom::Err::Incomplete(_) => unreachable!("complete parsers only"),
+    }
+}
+
+/// `N` bytes, named `what`.
+pub(crate) fn array<'a, const N: usize>(
+    what: &'static str,
+) -> impl FnMut(&'a [u8]) -> IResult<'a, [u8; N]> {
+    context(
+        what,
+        map(take(N), |bytes: &[u8]| bytes.try_into().expect("N bytes")),
+    )
+}
+
+/// As many bytes as the length `length` reads first says. Unlike
+/// `nom::multi::length_data`, runs out as an error rather than waiting for
+/// more input.
+#[cfg(feature = "crypto")]
+fn length_data<'a, N: ToUsize>(
+    length: impl FnMut(&'a [u8]) -> IResult<'a, N>,
+) -> impl FnMut(&'a [u8]) -> IResult<'a, &'a [u8]> {
+    flat_map(length, take)
+}
+
+/// The parts of a chunk as stored in a file.
+pub(crate) struct RawChunk<'a> {
+    pub chunk_type: [u8; 4],
+    pub data: &'a [u8],
+    pub crc: u32,
+}
+
+/// A chunk: the data length, type, data and CRC.
+pub(crate) fn chunk(input: &[u8]) -> IResult<'_, RawChunk<'_>> {
+    let (input, length) = context("chunk length", be_u32)(input)?;
+    let (input, chunk_type) = array("chunk type")(input)?;
+    let (input, data) = context("chunk data", take(length))(input)?;
+    let (input, crc) = context("chunk CRC", be_u32)(input)?;
+    Ok((
+        input,
+        RawChunk {
+            chunk_type,
+            data,
+            crc,
+        },
+    ))
+}
+
+/// The magic bytes and version that open an envelope.
+#[cfg(feature = "crypto")]
+pub(crate) fn envelope_header<const N: usize>(input: &[u8]) -> IResult<'_, ([u8; N], u8)> {
+    tuple((array("magic"), context("version", byte)))(input)
+}
+
+/// An envelope field: a tag, then unless it is `end` a 4-byte length and
+/// that many bytes of value.
+#[cfg(feature = "crypto")]
+pub(crate) fn field(end: u8) -> impl FnMut(&[u8]) -> IResult<'_, (u8, &[u8])> {
+    move |input| {
+        let (input, tag) = context("field tag", byte)(input)?;
+        if tag == end {
+            return Ok((input, (tag, &[][..])));
+        }
+        let (input, value) = context("field value", length_data(be_u32))(input)?;
+        Ok((input, (tag, value)))
+    }
+}
+
+/// One entry of a log payload: flags, seconds and nanoseconds since the
+/// epoch, then the length-prefixed text.
+#[cfg(feature = "crypto")]
+pub(crate) fn log_entry(input: &[u8]) -> IResult<'_, (u8, u64, u32, &[u8])> {
+    tuple((
+        context("log entry flags", byte),
+        context("log entry time", be_u64),
+        context("log entry time", be_u32),
+        context("log entry text", length_data(be_u32)),
+    ))(input)
+}
+
+/// The version and entry count that open a payload index.
+#[cfg(feature = "crypto")]
+pub(crate) fn index_header(input: &[u8]) -> IResult<'_, (u8, u32)> {
+    tuple((context("version", byte), context("entry count", be_u32)))(input)
+}
+
+/// One entry of a payload index, as stored.
+#[cfg(feature = "crypto")]
+pub(crate) struct RawIndexEntry<'a> {
+    pub chunk_index: u32,
+    pub offset: u64,
+    pub chunk_type: [u8; 4],
+    pub flags: u8,
+    pub size: u64,
+    pub name: &'a [u8],
+}
+
+#[cfg(feature = "crypto")]
+pub(crate) fn index_entry(input: &[u8]) -> IResult<'_, RawIndexEntry<'_>> {
+    map(
+        tuple((
+            context("chunk index", be_u32),
+            context("chunk offset", be_u64),
+            array("chunk type"),
+            context("flags", byte),
+            context("payload size", be_u64),
+            context("payload name", length_data(be_u16)),
+        )),
+        |(chunk_index, offset, chunk_type, flags, size, name)| RawIndexEntry {
+            chunk_index,
+            offset,
+            chunk_type,
+            flags,
+            size,
+            name,
+        },
+    )(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk() {
+        let bytes = [0, 0, 0, 2, b'r', b'u', b'S', b't', 7, 7, 1, 2, 3, 4, 99];
+        let (rest, raw) = chunk(&bytes).unwrap();
+        assert_eq!(&raw.chunk_type, b"ruSt");
+        assert_eq!(raw.data, [7, 7]);
+        assert_eq!(raw.crc, 0x0102_0304);
+        assert_eq!(rest, [99]);
+
+        let cut = &bytes[..9];
+        let err = chunk(cut).err().unwrap();
+        assert_eq!(
+            span(cut, err),
+            Span {
+                offset: 8,
+                what: "chunk data"
+            }
+        );
+        let err = chunk(&bytes[..2]).err().unwrap();
+        assert_eq!(span(&bytes[..2], err).to_string(), "chunk length at byte 0");
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn test_field() {
+        let bytes = [1, 0, 0, 0, 2, 5, 6, 0];
+        let (rest, (tag, value)) = field(0)(&bytes).unwrap();
+        assert_eq!((tag, value), (1, &[5, 6][..]));
+        assert_eq!(field(0)(rest).unwrap().1, (0, &[][..]));
+        let cut = &bytes[..6];
+        let err = field(0)(cut).err().unwrap();
+        assert_eq!(span(cut, err).to_string(), "field value at byte 5");
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+use pngme::envelope::EnvelopeError;
+
+/// Environment variable consulted when no password option is given.
+pub const PASSWORD_ENV: &str = "PNGME_PASSWORD";
+/// Like [`PASSWORD_ENV`], for the password of a decoy payload.
+pub const DECOY_PASSWORD_ENV: &str = "PNGME_DECOY_PASSWORD";
+/// Like [`PASSWORD_ENV`], for the password `reencrypt` switches to.
+pub const NEW_PASSWORD_ENV: &str = "PNGME_NEW_PASSWORD";
+
+#[derive(Debug, Args)]
+pub struct PasswordArgs {
+    /// Password for encrypted payloads (visible to other local users; prefer
+    /// --password-file, $PNGME_PASSWORD or the interactive prompt)
+    #[arg(long, conflicts_with = "password_file")]
+    pub password: Option<String>,
+    /// Read the password from the first line of this file
+    #[arg(long, value_name = "PATH")]
+    pub password_file: Option<PathBuf>,
+    /// Use the password (and age identity) stored in the OS keychain under
+    /// this profile, see `pngme keychain`
+    #[cfg(feature = "keychain")]
+    #[arg(
+        long = "profile",
+        value_name = "PROFILE",
+        conflicts_with_all = ["password", "password_file"]
+    )]
+    pub keychain_profile: Option<String>,
+}
+
+impl PasswordArgs {
+    /// Returns the password from the options, the keychain, the environment
+    /// or an interactive prompt (asking twice when `confirm` is set), in that
+    /// order.
+    pub fn get(&self, confirm: bool) -> Result<Zeroizing<String>> {
+        self.get_or(std::env::var(PASSWORD_ENV).ok(), confirm)
+    }
+
+    /// Like [`PasswordArgs::get`], with `env` standing in for
+    /// `$PNGME_PASSWORD`.
+    pub(crate) fn get_or(&self, env: Option<String>, confirm: bool) -> Result<Zeroizing<String>> {
+        if let Some(password) = &self.password {
+            return Ok(Zeroizing::new(password.clone()));
+        }
+        if let Some(path) = &self.password_file {
+            return read_first_line(path);
+        }
+        #[cfg(feature = "keychain")]
+        if let Some(profile) = &self.keychain_profile {
+            return crate::keychain::password(profile);
+        }
+        if let Some(password) = env {
+            return Ok(Zeroizing::new(password));
+        }
+        prompt("password", confirm)
+    }
+    /// The keychain profile to take credentials from, if any.
+    pub fn profile(&self) -> Option<&str> {
+        #[cfg(feature = "keychain")]
+        return self.keychain_profile.as_deref();
+        #[cfg(not(feature = "keychain"))]
+        None
+    }
+}
+
+/// The password of a decoy payload: from `path`, `$PNGME_DECOY_PASSWORD` or
+/// a confirmed prompt, in that order.
+pub fn decoy_password(path: Option<&Path>) -> Result<Zeroizing<String>> {
+    second_password(path, DECOY_PASSWORD_ENV, "decoy password")
+}
+
+/// The password `reencrypt` switches to: from `path`,
+/// `$PNGME_NEW_PASSWORD` or a confirmed prompt, in that order.
+pub fn new_password(path: Option<&Path>) -> Result<Zeroizing<String>> {
+    second_password(path, NEW_PASSWORD_ENV, "new password")
+}
+
+fn second_password(path: Option<&Path>, env: &str, what: &str) -> Result<Zeroizing<String>> {
+    if let Some(path) = path {
+        return read_first_line(path);
+    }
+    if let Ok(password) = std::env::var(env) {
+        return Ok(Zeroizing::new(password));
+    }
+    prompt(what, true)
+}
+
+fn read_first_line(path: &Path) -> Result<Zeroizing<String>> {
+    let contents = Zeroizing::new(
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?,
+    );
+    let line = contents.lines().next().unwrap_or_default();
+    Ok(Zeroizing::new(line.to_string()))
+}
+
+/// Asks for `what` on the terminal, twice when `confirm` is set.
+fn prompt(what: &str, confirm: bool) -> Result<Zeroizing<String>> {
+    if !std::io::stdin().is_terminal() {
+        return Err(EnvelopeError::PasswordRequired.into());
+    }
+    let mut label = what.to_string();
+    label[..1].make_ascii_uppercase();
+    let password = Zeroizing::new(rpassword::prompt_password(format!("{}: ", label))?);
+    if confirm {
+        let again = Zeroizing::new(rpassword::prompt_password(format!("Confirm {}: ", what))?);
+        if *again != *password {
+            anyhow::bail!("Passwords do not match");
+        }
+    }
+    Ok(password)
+}
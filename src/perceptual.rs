@@ -0,0 +1,212 @@
+//! Perceptual hashes of the decoded image, for finding near-duplicates:
+//! the same picture re-encoded, converted to another pixel format or
+//! slightly resized hashes to the same or nearly the same 64 bits, unlike
+//! `hash::content_hash`, which any change to the pixels upsets.
+//!
+//! Both methods shrink the image's luminance (composited over white, so
+//! the color of transparent pixels doesn't count) to a small grid of cell
+//! averages. [`Method::Difference`] (dHash) sets a bit where a cell is
+//! brighter than the one to its left; [`Method::Dct`] (pHash) takes the
+//! discrete cosine transform of a 32 by 32 grid and sets a bit for each of
+//! the 64 lowest frequencies above their median, which copes better with
+//! changes of contrast and gamma. Hashes are compared by how many bits
+//! differ, with [`PerceptualHash::distance`].
+
+use std::f64::consts::PI;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::image::{Colors, ImageData, ImageError};
+use crate::png::Png;
+
+/// How a [`PerceptualHash`] is computed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Method {
+    /// pHash: the signs of the lowest frequencies of the image.
+    #[default]
+    Dct,
+    /// dHash: the direction of the gradient between neighboring cells.
+    Difference,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown perceptual hash {0:?} (expected dct or difference)")]
+pub struct UnknownMethod(String);
+
+impl FromStr for Method {
+    type Err = UnknownMethod;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dct" | "phash" => Ok(Self::Dct),
+            "difference" | "dhash" => Ok(Self::Difference),
+            _ => Err(UnknownMethod(s.to_string())),
+        }
+    }
+}
+
+/// 64 bits summing up what an image looks like.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PerceptualHash(pub u64);
+
+impl PerceptualHash {
+    /// Distance up to which two images are taken for the same by
+    /// [`PerceptualHash::is_similar`].
+    pub const SIMILAR: u32 = 10;
+
+    /// How many of the 64 bits differ: 0 for the same image, around 32 for
+    /// unrelated ones.
+    pub fn distance(self, other: Self) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+    pub fn is_similar(self, other: Self) -> bool {
+        self.distance(other) <= Self::SIMILAR
+    }
+}
+
+impl Display for PerceptualHash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// The perceptual hash of the image `png` holds, by `method`. Of an
+/// animation, only the default image is hashed.
+pub fn perceptual_hash(png: &Png, method: Method) -> Result<PerceptualHash, ImageError> {
+    let bits: Vec<bool> = match method {
+        Method::Difference => {
+            let grid = luminance_grid(png, 9, 8)?;
+            grid.chunks(9)
+                .flat_map(|row| row.windows(2).map(|pair| pair[1] > pair[0]))
+                .collect()
+        }
+        Method::Dct => {
+            let grid = luminance_grid(png, DCT_LEN, DCT_LEN)?;
+            let coefficients = low_frequencies(&grid);
+            let mut sorted = coefficients.clone();
+            sorted.sort_by(f64::total_cmp);
+            let median = (sorted[31] + sorted[32]) / 2.0;
+            coefficients.iter().map(|&c| c > median).collect()
+        }
+    };
+    Ok(PerceptualHash(
+        bits.into_iter().fold(0, |hash, bit| hash << 1 | bit as u64),
+    ))
+}
+
+/// Side of the grid [`Method::Dct`] transforms.
+const DCT_LEN: u32 = 32;
+
+/// The luminance of the image, from 0 to 1, averaged over `columns` by
+/// `rows` cells, row by row. Images smaller than the grid repeat pixels.
+fn luminance_grid(png: &Png, columns: u32, rows: u32) -> Result<Vec<f64>, ImageError> {
+    let image = ImageData::decode(png)?;
+    let header = *image.header();
+    let colors = Colors::of(png, &header);
+    // Every cell covers at least one pixel.
+    let span = |cell: u32, cells: u32, len: u32| {
+        let start = (cell as u64 * len as u64 / cells as u64) as u32;
+        let end = ((cell as u64 + 1) * len as u64 / cells as u64) as u32;
+        start..end.max(start + 1)
+    };
+    let mut grid = Vec::with_capacity((columns * rows) as usize);
+    for row in 0..rows {
+        let ys = span(row, rows, header.height);
+        for column in 0..columns {
+            let xs = span(column, columns, header.width);
+            let mut sum = 0.0;
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    let [r, g, b, a] = colors.rgba(&image, x, y).map(|s| s as f64 / 65535.0);
+                    sum += (0.299 * r + 0.587 * g + 0.114 * b) * a + (1.0 - a);
+                }
+            }
+            grid.push(sum / (ys.len() * xs.len()) as f64);
+        }
+    }
+    Ok(grid)
+}
+
+/// The 8 by 8 lowest-frequency coefficients of the two-dimensional DCT-II
+/// of `grid`, a [`DCT_LEN`] square, row by row.
+fn low_frequencies(grid: &[f64]) -> Vec<f64> {
+    let len = DCT_LEN as usize;
+    let cosines: Vec<f64> = (0..8 * len)
+        .map(|i| {
+            let (frequency, at) = (i / len, i % len);
+            (PI * (2 * at + 1) as f64 * frequency as f64 / (2 * len) as f64).cos()
+        })
+        .collect();
+    let transform = |samples: &mut dyn Iterator<Item = f64>| -> Vec<f64> {
+        let samples: Vec<f64> = samples.collect();
+        cosines
+            .chunks(len)
+            .map(|cosines| cosines.iter().zip(&samples).map(|(c, s)| c * s).sum())
+            .collect()
+    };
+    // Along the rows, then down the columns of the result.
+    let rows: Vec<Vec<f64>> = grid
+        .chunks(len)
+        .map(|row| transform(&mut row.iter().copied()))
+        .collect();
+    let columns: Vec<Vec<f64>> = (0..8)
+        .map(|u| transform(&mut rows.iter().map(|row| row[u])))
+        .collect();
+    (0..8)
+        .flat_map(|v| columns.iter().map(move |column| column[v]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::{ColorType, Header};
+
+    /// A gray image of smooth bands, `invert`ed or not.
+    fn testing_png(width: u32, height: u32, bit_depth: u8, invert: bool) -> Png {
+        let header = Header {
+            width,
+            height,
+            bit_depth,
+            color_type: ColorType::Grayscale,
+            interlaced: false,
+        };
+        let max = (1u32 << bit_depth) - 1;
+        let mut image =
+            ImageData::new(header, vec![0; header.row_len() * height as usize]).unwrap();
+        for y in 0..height {
+            for x in 0..width {
+                let (u, v) = (x as f64 / width as f64, y as f64 / height as f64);
+                let level = 0.5 + 0.4 * (6.0 * u).sin() * (4.0 * v + 1.0).cos();
+                let level = if invert { 1.0 - level } else { level };
+                image.set_pixel(x, y, &[(level * max as f64).round() as u16]);
+            }
+        }
+        image.to_png(&Default::default()).unwrap()
+    }
+
+    #[test]
+    fn test_perceptual_hash() {
+        for method in [Method::Dct, Method::Difference] {
+            let hash = |png: &Png| perceptual_hash(png, method).unwrap();
+            let original = hash(&testing_png(64, 64, 8, false));
+            let png = testing_png(64, 64, 8, false);
+            let options = crate::image::EncodeOptions {
+                interlace: Some(true),
+                ..Default::default()
+            };
+            let reencoded = ImageData::decode(&png).unwrap().to_png(&options).unwrap();
+            assert_eq!(hash(&reencoded), original);
+            assert!(hash(&testing_png(64, 64, 16, false)).distance(original) <= 2);
+            assert!(hash(&testing_png(61, 57, 8, false)).is_similar(original));
+            assert!(hash(&testing_png(48, 48, 8, false)).is_similar(original));
+            // Smaller than the grid.
+            hash(&testing_png(5, 4, 8, false));
+            assert!(hash(&testing_png(64, 64, 8, true)).distance(original) > 40);
+        }
+        assert_eq!(PerceptualHash(0xff).distance(PerceptualHash(0x1f0)), 5);
+        assert_eq!(PerceptualHash(0xab).to_string(), "00000000000000ab");
+        assert_eq!("dhash".parse::<Method>().unwrap(), Method::Difference);
+    }
+}
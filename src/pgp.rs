@@ -0,0 +1,155 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use sequoia_openpgp::Cert;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use pngme::envelope::Envelope;
+use pngme::openpgp;
+
+use crate::manifest::Manifest;
+use crate::password::PasswordArgs;
+
+#[derive(Debug, Args)]
+pub struct PgpEncodeArgs {
+    /// Encrypt the payload as a standard OpenPGP message to the certificate
+    /// in this key file (repeatable)
+    #[arg(
+        long = "pgp-recipient",
+        value_name = "CERT",
+        conflicts_with_all = ["encrypt", "recipients", "hmac", "compress"]
+    )]
+    pub pgp_recipients: Vec<PathBuf>,
+    /// Sign the payload as a standard OpenPGP message with the secret key in
+    /// this key file
+    #[arg(
+        long = "pgp-sign",
+        value_name = "KEY",
+        conflicts_with_all = ["encrypt", "recipients", "hmac", "compress"]
+    )]
+    pub pgp_signer: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct PgpDecodeArgs {
+    /// Secret key file to decrypt OpenPGP payloads with (repeatable)
+    #[arg(long = "pgp-key", value_name = "KEY")]
+    pub pgp_keys: Vec<PathBuf>,
+    /// Require a valid OpenPGP signature by the certificate in this key file
+    /// (repeatable: any one of them is enough)
+    #[arg(long = "pgp-signer", value_name = "CERT")]
+    pub pgp_signers: Vec<PathBuf>,
+}
+
+impl PgpEncodeArgs {
+    pub fn is_requested(&self) -> bool {
+        !self.pgp_recipients.is_empty() || self.pgp_signer.is_some()
+    }
+}
+
+fn read_certs(paths: &[PathBuf]) -> Result<Vec<Cert>> {
+    let mut certs = Vec::new();
+    for path in paths {
+        certs.extend(read_cert_file(path)?);
+    }
+    Ok(certs)
+}
+
+fn read_cert_file(path: &Path) -> Result<Vec<Cert>> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    openpgp::read_certs(&data).with_context(|| format!("Failed to load {}", path.display()))
+}
+
+/// Wraps the payload of `envelope` in an OpenPGP message when requested.
+pub fn protect(
+    envelope: Envelope,
+    args: &PgpEncodeArgs,
+    password: &PasswordArgs,
+) -> Result<Envelope> {
+    if !args.is_requested() {
+        return Ok(envelope);
+    }
+    let recipients = read_certs(&args.pgp_recipients)?;
+    let signer = match &args.pgp_signer {
+        Some(path) => read_cert_file(path)?.into_iter().next(),
+        None => None,
+    };
+    let signer_password = match &signer {
+        Some(cert) if openpgp::needs_password(std::slice::from_ref(cert)) => {
+            Some(password.get(false)?)
+        }
+        _ => None,
+    };
+    let message = openpgp::protect(
+        envelope.payload(),
+        envelope.file_meta().map(|m| m.name.as_str()),
+        &recipients,
+        signer
+            .as_ref()
+            .map(|c| (c, signer_password.as_deref().map(String::as_str))),
+    )?;
+    Ok(envelope.with_openpgp_message(message))
+}
+
+/// Opens an OpenPGP payload when keys or signers to check are given;
+/// otherwise the message is left as is, for `--output` and `gpg`.
+pub fn open(envelope: Envelope, args: &PgpDecodeArgs, password: &PasswordArgs) -> Result<Envelope> {
+    if !envelope.is_openpgp() || (args.pgp_keys.is_empty() && args.pgp_signers.is_empty()) {
+        return Ok(envelope);
+    }
+    let keys = read_certs(&args.pgp_keys)?;
+    let signers = read_certs(&args.pgp_signers)?;
+    let key_password = if openpgp::needs_password(&keys) {
+        Some(password.get(false)?)
+    } else {
+        None
+    };
+    let opened = openpgp::open(
+        envelope.payload(),
+        &keys,
+        key_password.as_deref().map(String::as_str),
+        &signers,
+    )?;
+    if let Some(fingerprint) = &opened.signed_by {
+        eprintln!("Good OpenPGP signature from {}", fingerprint);
+    }
+    Ok(envelope.with_openpgp_contents(opened.payload)?)
+}
+
+/// Signs `manifest` with the secret key in the key file `key`.
+pub fn sign_manifest(
+    mut manifest: Manifest,
+    key: &Path,
+    password: &PasswordArgs,
+) -> Result<Manifest> {
+    let signer = read_cert_file(key)?
+        .into_iter()
+        .next()
+        .expect("read_certs finds at least one");
+    let signer_password = match openpgp::needs_password(std::slice::from_ref(&signer)) {
+        true => Some(password.get(false)?),
+        false => None,
+    };
+    let signature = openpgp::sign_detached(
+        manifest.signed_data().as_bytes(),
+        (&signer, signer_password.as_deref().map(String::as_str)),
+    )?;
+    manifest.signature = Some(signature);
+    Ok(manifest)
+}
+
+/// Fails unless `manifest` is signed by one of the certificates in the key
+/// files `signers`.
+pub fn check_manifest(manifest: &Manifest, signers: &[PathBuf]) -> Result<()> {
+    let signers = read_certs(signers)?;
+    let signature = manifest.signature.as_ref().ok_or_else(|| {
+        openpgp::OpenPgpError::BadSignature("the manifest is not signed".to_string())
+    })?;
+    let fingerprint = openpgp::verify_detached(
+        manifest.signed_data().as_bytes(),
+        signature.as_bytes(),
+        &signers,
+    )?;
+    eprintln!("Good OpenPGP signature from {}", fingerprint);
+    Ok(())
+}
@@ -0,0 +1,352 @@
+//! Converting many PNG files in stages that run at the same time.
+//!
+//! A [`Pipeline`] reads and parses files on one thread, runs each of its
+//! transforms on a pool of workers of its own, and serializes and writes
+//! the results on another thread. The stages hand files on through bounded
+//! channels, so reading the next files, transforming some and writing
+//! others all overlap, and only a few files per stage are held in memory.
+
+use std::error::Error as StdError;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use thiserror::Error;
+
+use crate::batch::{Parser, Serializer};
+use crate::image::{EncodeOptions, ImageData};
+use crate::png::{Png, PngError};
+
+/// What a transform may fail with.
+pub type TransformError = Box<dyn StdError + Send + Sync>;
+
+type Transform = Arc<dyn Fn(&mut Png) -> Result<(), TransformError> + Send + Sync>;
+
+#[derive(Debug, Error)]
+pub enum PipelineError {
+    #[error("Failed to read {}: {source}", path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("Failed to parse {}: {source}", path.display())]
+    Parse { path: PathBuf, source: PngError },
+    #[error("Failed to transform {}: {source}", path.display())]
+    Transform {
+        path: PathBuf,
+        source: TransformError,
+    },
+    #[error("Failed to write {}: {source}", path.display())]
+    Write { path: PathBuf, source: io::Error },
+}
+
+/// A file that made it through every stage.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Converted {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    /// Bytes written to `output`.
+    pub len: usize,
+}
+
+/// A file between stages.
+struct Item {
+    input: PathBuf,
+    output: PathBuf,
+    png: Png,
+}
+
+#[derive(Clone)]
+pub struct Pipeline {
+    transforms: Vec<Transform>,
+    threads: usize,
+    depth: usize,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pipeline {
+    /// A pipeline that copies files as they are, until transforms are
+    /// added, with a worker per core for each transform.
+    pub fn new() -> Self {
+        Self {
+            transforms: Vec::new(),
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            depth: 4,
+        }
+    }
+    /// Runs each transform on `threads` workers (at least one).
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+    /// How many files may wait between two stages (at least one). Deeper
+    /// queues smooth over files that are slow to read or transform, at the
+    /// cost of holding more of them in memory.
+    pub fn with_depth(mut self, depth: usize) -> Self {
+        self.depth = depth.max(1);
+        self
+    }
+    /// Adds a stage that runs `transform` on every file, after the stages
+    /// before it. Payloads are sealed this way, with an envelope built from
+    /// the caller's keys.
+    pub fn then(
+        mut self,
+        transform: impl Fn(&mut Png) -> Result<(), TransformError> + Send + Sync + 'static,
+    ) -> Self {
+        self.transforms.push(Arc::new(transform));
+        self
+    }
+    /// Adds a stage that removes every chunk of `chunk_type`.
+    pub fn strip(self, chunk_type: &str) -> Self {
+        let chunk_type = chunk_type.to_string();
+        self.then(move |png| {
+            while png.remove_chunk(&chunk_type).is_ok() {}
+            Ok(())
+        })
+    }
+    /// Adds a stage that decodes the pixels and deflates them again with
+    /// `options`, replacing the `IDAT` chunks.
+    pub fn recompress(self, options: EncodeOptions) -> Self {
+        self.then(move |png| {
+            let image = ImageData::decode(png)?;
+            image.write_to(png, &options)?;
+            Ok(())
+        })
+    }
+    /// Converts each file `input` into `output`, overwriting it. Results
+    /// arrive in the order files are written, which with several workers
+    /// per transform needn't be the order of `jobs`.
+    pub fn run<P: Into<PathBuf>, Q: Into<PathBuf>>(
+        &self,
+        jobs: impl IntoIterator<Item = (P, Q)>,
+    ) -> Run {
+        let jobs: Vec<(PathBuf, PathBuf)> = jobs
+            .into_iter()
+            .map(|(input, output)| (input.into(), output.into()))
+            .collect();
+        let (results, received) = mpsc::channel();
+        let mut queue = self.read(jobs, &results);
+        for transform in &self.transforms {
+            queue = self.transform(queue, Arc::clone(transform), &results);
+        }
+        thread::spawn(move || write(queue, results));
+        Run { results: received }
+    }
+    /// Reads and parses the files of `jobs` on a thread of its own.
+    fn read(
+        &self,
+        jobs: Vec<(PathBuf, PathBuf)>,
+        results: &Sender<Result<Converted, PipelineError>>,
+    ) -> Receiver<Item> {
+        let (items, queue) = mpsc::sync_channel(self.depth);
+        let results = results.clone();
+        thread::spawn(move || {
+            let mut parser = Parser::new();
+            for (input, output) in jobs {
+                let item = read_one(&mut parser, &input).map(|png| Item { input, output, png });
+                let sent = match item {
+                    Ok(item) => items.send(item).is_ok(),
+                    Err(e) => results.send(Err(e)).is_ok(),
+                };
+                if !sent {
+                    return;
+                }
+            }
+        });
+        queue
+    }
+    /// Starts the workers of one transform stage, taking files from
+    /// `queue` and passing them on through the queue returned.
+    fn transform(
+        &self,
+        queue: Receiver<Item>,
+        transform: Transform,
+        results: &Sender<Result<Converted, PipelineError>>,
+    ) -> Receiver<Item> {
+        let (items, next) = mpsc::sync_channel(self.depth);
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..self.threads {
+            let queue = Arc::clone(&queue);
+            let transform = Arc::clone(&transform);
+            let items = items.clone();
+            let results = results.clone();
+            thread::spawn(move || loop {
+                let item = queue.lock().expect("no worker panics holding it").recv();
+                let Ok(mut item) = item else {
+                    return;
+                };
+                let sent = match transform(&mut item.png) {
+                    Ok(()) => items.send(item).is_ok(),
+                    Err(source) => results
+                        .send(Err(PipelineError::Transform {
+                            path: item.input,
+                            source,
+                        }))
+                        .is_ok(),
+                };
+                if !sent {
+                    return;
+                }
+            });
+        }
+        next
+    }
+}
+
+fn read_one(parser: &mut Parser, path: &Path) -> Result<Png, PipelineError> {
+    let file = File::open(path).map_err(|source| PipelineError::Read {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    parser.read(file).map_err(|source| PipelineError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+/// Serializes and writes what reaches the end of the pipeline.
+fn write(queue: Receiver<Item>, results: Sender<Result<Converted, PipelineError>>) {
+    let mut serializer = Serializer::new();
+    for item in queue {
+        let bytes = serializer.serialize(&item.png);
+        let result = match fs::write(&item.output, bytes) {
+            Ok(()) => Ok(Converted {
+                len: bytes.len(),
+                input: item.input,
+                output: item.output,
+            }),
+            Err(source) => Err(PipelineError::Write {
+                path: item.output,
+                source,
+            }),
+        };
+        if results.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+/// The results of a [`Pipeline::run`], as files are written. Dropping it
+/// stops the run after the files in progress.
+pub struct Run {
+    results: Receiver<Result<Converted, PipelineError>>,
+}
+
+impl Iterator for Run {
+    type Item = Result<Converted, PipelineError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.results.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::image::{ColorType, Header};
+    use std::str::FromStr;
+
+    fn testing_png(shade: u8) -> Png {
+        let header = Header {
+            width: 16,
+            height: 16,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        };
+        let mut png = ImageData::new(header, vec![shade; 16 * 16 * 3])
+            .unwrap()
+            .to_png(&EncodeOptions::default())
+            .unwrap();
+        let chunk = Chunk::new(ChunkType::from_str("teXt").unwrap(), b"comment");
+        png.insert_chunk_at(1, chunk.clone());
+        png.insert_chunk_at(1, chunk);
+        png
+    }
+
+    #[test]
+    fn test_run() {
+        let root = std::env::temp_dir().join(format!("pngme-pipeline-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let mut jobs = Vec::new();
+        for i in 0..20 {
+            let input = root.join(format!("{:02}.png", i));
+            fs::write(&input, testing_png(i).as_bytes()).unwrap();
+            jobs.push((input, root.join(format!("{:02}.out.png", i))));
+        }
+        fs::write(root.join("bad.png"), b"GIF89a").unwrap();
+        jobs.push((root.join("bad.png"), root.join("bad.out.png")));
+        jobs.push((root.join("missing.png"), root.join("missing.out.png")));
+
+        let pipeline = Pipeline::new()
+            .with_threads(3)
+            .with_depth(2)
+            .strip("teXt")
+            .recompress(EncodeOptions {
+                level: 9,
+                ..EncodeOptions::default()
+            })
+            .then(|png| {
+                let data = png.chunks()[0].data().to_vec();
+                let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), &data);
+                png.insert_chunk_at(1, chunk);
+                Ok(())
+            });
+        let (mut done, mut errors) = (Vec::new(), Vec::new());
+        for result in pipeline.run(jobs) {
+            match result {
+                Ok(converted) => done.push(converted),
+                Err(e) => errors.push(e),
+            }
+        }
+        done.sort_by(|a, b| a.input.cmp(&b.input));
+        let outputs: Vec<_> = done.iter().map(|c| fs::read(&c.output).unwrap()).collect();
+        let first = Png::try_from(outputs[0].as_slice()).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        // The bad and missing files get no further than reading.
+        assert_eq!(done.len(), 20);
+        assert_eq!(done[0].len, outputs[0].len());
+        assert!(first.chunk_by_type("teXt").is_none());
+        assert!(first.chunk_by_type("ruSt").is_some());
+        let image = ImageData::decode(&first).unwrap();
+        assert_eq!(image, ImageData::decode(&testing_png(0)).unwrap());
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().any(|e| matches!(
+            e,
+            PipelineError::Parse {
+                source: PngError::InvalidHeader,
+                ..
+            }
+        )));
+        assert!(errors
+            .iter()
+            .any(|e| matches!(e, PipelineError::Read { .. })));
+    }
+
+    #[test]
+    fn test_transform_errors() {
+        let root = std::env::temp_dir().join(format!("pngme-pipeline-err-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        let input = root.join("a.png");
+        fs::write(&input, testing_png(1).as_bytes()).unwrap();
+        let results: Vec<_> = Pipeline::new()
+            .then(|_| Err("no".into()))
+            .run([(&input, root.join("a.out.png"))])
+            .collect();
+        let written = root.join("a.out.png").exists();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert!(!written);
+        assert!(matches!(
+            &results[..],
+            [Err(PipelineError::Transform { path, .. })] if *path == input
+        ));
+    }
+}
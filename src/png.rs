@@ -0,0 +1,941 @@
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt::{self, Display, Formatter};
+use core::ops::Index;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io::{self, Write};
+
+use crate::chunk::{Chunk, ChunkError, ChunkRef};
+use crate::chunk_type::{ChunkType, ChunkTypeError};
+use crate::decoder::{CriticalPolicy, Decoder, Event};
+use crate::limits::{LimitExceeded, Limits};
+#[cfg(feature = "std")]
+use crate::lint::{Lint, Profile};
+#[cfg(feature = "std")]
+use crate::options::WriteOptions;
+use crate::options::{CrcPolicy, ParseOptions};
+use crate::parallel;
+use crate::trace;
+use crate::warning::{self, WithWarnings};
+
+#[derive(Debug)]
+pub enum PngError {
+    InvalidHeader,
+    BadChunk {
+        offset: usize,
+        source: ChunkError,
+    },
+    BadChunkType(ChunkTypeError),
+    ChunkNotFound(String),
+    UnknownCritical {
+        offset: usize,
+        chunk_type: ChunkType,
+    },
+    LimitExceeded(LimitExceeded),
+    TooLarge,
+    /// The first error linting found under
+    /// [`ParseOptions::with_profile`].
+    #[cfg(feature = "std")]
+    Nonconforming {
+        profile: Profile,
+        lint: Lint,
+    },
+}
+
+impl Display for PngError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHeader => write!(f, "Invalid PNG header"),
+            Self::BadChunk { offset, source } => {
+                write!(f, "Bad chunk at offset {}: {}", offset, source)
+            }
+            Self::BadChunkType(e) => write!(f, "Bad ChunkType: {}", e),
+            Self::ChunkNotFound(chunk_type) => write!(f, "Chunk not found: {}", chunk_type),
+            Self::UnknownCritical { offset, chunk_type } => write!(
+                f,
+                "Unknown critical chunk {} at offset {}",
+                chunk_type, offset
+            ),
+            Self::LimitExceeded(e) => e.fmt(f),
+            Self::TooLarge => write!(f, "File is too large to address on this platform"),
+            #[cfg(feature = "std")]
+            Self::Nonconforming { profile, lint } => {
+                write!(f, "File doesn't conform to {}: {}", profile, lint)
+            }
+        }
+    }
+}
+
+impl core::error::Error for PngError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::BadChunk { source, .. } => Some(source),
+            Self::BadChunkType(e) => Some(e),
+            #[cfg(feature = "std")]
+            Self::Nonconforming { lint, .. } => Some(lint),
+            _ => None,
+        }
+    }
+}
+
+impl From<ChunkTypeError> for PngError {
+    fn from(e: ChunkTypeError) -> Self {
+        Self::BadChunkType(e)
+    }
+}
+
+impl From<LimitExceeded> for PngError {
+    fn from(e: LimitExceeded) -> Self {
+        Self::LimitExceeded(e)
+    }
+}
+
+// A parsed file is meant to be shared between threads, typically behind an
+// `Arc<Png>`, and read through `&self` with no locking. Keep these types
+// free of `Rc` and cells.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Png>();
+    assert_send_sync::<Chunk>();
+    assert_send_sync::<ChunkType>();
+};
+
+/// A PNG file as its chunks. Chunk data and the trailer are shared between
+/// clones, so cloning copies only the list of chunks, and forking a file
+/// into variants duplicates just the chunks each one changes.
+#[derive(Clone, Debug)]
+pub struct Png {
+    chunks: Vec<Chunk>,
+    /// Whatever follows `IEND`, which decoders ignore.
+    trailer: Arc<Vec<u8>>,
+    /// Unknown critical chunks set aside under
+    /// [`CriticalPolicy::Quarantine`], which aren't part of the file.
+    quarantined: Arc<Vec<Chunk>>,
+}
+
+impl TryFrom<&[u8]> for Png {
+    type Error = PngError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        Self::parse(value, Decoder::new())
+    }
+}
+
+impl Png {
+    fn parse(value: &[u8], decoder: Decoder) -> Result<Self, PngError> {
+        trace::span!("png.parse", len = value.len());
+        let png = trace::failed(Self::decode(value, decoder))?;
+        trace::event!(
+            debug,
+            chunks = png.chunks.len(),
+            quarantined = png.quarantined.len(),
+            trailer_len = png.trailer.len(),
+            "parsed"
+        );
+        Ok(png)
+    }
+    fn decode(value: &[u8], mut decoder: Decoder) -> Result<Self, PngError> {
+        let mut chunks = Vec::new();
+        let mut trailer = Vec::new();
+        let mut quarantined = Vec::new();
+        for event in decoder.feed(value)? {
+            match event {
+                Event::Chunk { chunk, .. } => chunks.push(chunk),
+                Event::Trailer(bytes) => trailer = bytes,
+                Event::Quarantined { chunk, .. } => quarantined.push(chunk),
+            }
+        }
+        decoder.finish()?;
+        Ok(Self {
+            chunks,
+            trailer: Arc::new(trailer),
+            quarantined: Arc::new(quarantined),
+        })
+    }
+}
+
+/// The chunks of the PNG file in `bytes`, borrowed rather than copied, up to
+/// and including `IEND`. CRCs are left to the caller to check.
+pub fn chunk_refs(bytes: &[u8]) -> Result<ChunkRefs<'_>, PngError> {
+    if !bytes.starts_with(&Png::STANDARD_HEADER) {
+        return Err(PngError::InvalidHeader);
+    }
+    Ok(ChunkRefs {
+        bytes,
+        offset: Png::STANDARD_HEADER.len(),
+        done: false,
+    })
+}
+
+/// Iterator returned by [`chunk_refs`].
+pub struct ChunkRefs<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> ChunkRefs<'a> {
+    /// The bytes after the last chunk read so far: once iteration is over,
+    /// whatever follows `IEND`.
+    pub fn rest(&self) -> &'a [u8] {
+        &self.bytes[self.offset..]
+    }
+}
+
+impl<'a> Iterator for ChunkRefs<'a> {
+    type Item = Result<ChunkRef<'a>, PngError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset == self.bytes.len() {
+            return None;
+        }
+        let offset = self.offset;
+        match ChunkRef::parse(self.bytes, offset) {
+            Ok(chunk) => {
+                self.offset = chunk.end();
+                self.done = chunk.chunk_type().bytes() == *b"IEND";
+                Some(Ok(chunk))
+            }
+            Err(source) => {
+                self.done = true;
+                Some(Err(PngError::BadChunk { offset, source }))
+            }
+        }
+    }
+}
+
+/// Iterator returned by [`Png::try_chunks`].
+pub struct TryChunks<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl Iterator for TryChunks<'_> {
+    type Item = Result<Chunk, ChunkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let at = self.offset;
+        if self.done || at >= self.bytes.len() {
+            return None;
+        }
+        if let Some(end) = plausible(self.bytes, at) {
+            let chunk = Chunk::from_bytes_unchecked(&self.bytes[at..end]);
+            let chunk = chunk.expect("a plausible chunk parses");
+            self.offset = end;
+            self.done = chunk.chunk_type().bytes() == *b"IEND";
+            return Some(Ok(chunk));
+        }
+        // Past a chunk that only fails its CRC when another follows it as
+        // declared, or else to the next chunk that can be read.
+        let declared = header(self.bytes, at)
+            .and_then(|(_, length)| at.checked_add(Chunk::OVERHEAD + length as usize))
+            .filter(|&end| {
+                end == self.bytes.len()
+                    || (end < self.bytes.len() && header(self.bytes, end).is_some())
+            });
+        let err = match ChunkRef::parse(self.bytes, at) {
+            Ok(_) => ChunkError::ChecksumError,
+            Err(source) => source,
+        };
+        self.offset = match declared {
+            Some(end) => end,
+            None => find_next(self.bytes, at + Chunk::OVERHEAD).unwrap_or(self.bytes.len()),
+        };
+        Some(Err(err))
+    }
+}
+
+/// The end of the chunk at `at` if its header and CRC are right.
+pub(crate) fn plausible(bytes: &[u8], at: usize) -> Option<usize> {
+    let (chunk_type, length) = header(bytes, at)?;
+    let end = at.checked_add(Chunk::OVERHEAD + length as usize)?;
+    let data = bytes.get(at + 8..end.checked_sub(4)?)?;
+    let stored = u32::from_be_bytes(bytes.get(end - 4..end)?.try_into().ok()?);
+    (Chunk::checksum(&chunk_type, data) == stored).then_some(end)
+}
+
+/// The type and declared length of the chunk at `at`, if they could be a
+/// chunk's.
+pub(crate) fn header(bytes: &[u8], at: usize) -> Option<(ChunkType, u32)> {
+    let header = bytes.get(at..at.checked_add(8)?)?;
+    let length = u32::from_be_bytes(header[..4].try_into().expect("4 bytes"));
+    let code: [u8; 4] = header[4..].try_into().expect("4 bytes");
+    let chunk_type = ChunkType::try_from(code).ok()?;
+    (length <= Chunk::MAX_LENGTH).then_some((chunk_type, length))
+}
+
+/// The first plausible chunk from `from` on.
+pub(crate) fn find_next(bytes: &[u8], from: usize) -> Option<usize> {
+    (from..bytes.len().saturating_sub(Chunk::OVERHEAD - 1)).find(|&at| {
+        bytes[at + 4..at + 8].iter().all(u8::is_ascii_alphabetic) && plausible(bytes, at).is_some()
+    })
+}
+
+/// Checks the CRC of every chunk, on several threads with the `parallel`
+/// feature, and reports the first that doesn't match.
+pub fn check_crcs(chunks: &[ChunkRef<'_>]) -> Result<(), PngError> {
+    let valid = parallel::map(chunks, ChunkRef::has_valid_crc);
+    match valid.iter().position(|valid| !valid) {
+        Some(index) => Err(PngError::BadChunk {
+            offset: chunks[index].offset(),
+            source: ChunkError::ChecksumError,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Panics if there is no chunk of the type; see [`Png::get`].
+impl Index<&str> for Png {
+    type Output = Chunk;
+
+    fn index(&self, chunk_type: &str) -> &Chunk {
+        match self.chunk_by_type(chunk_type) {
+            Some(chunk) => chunk,
+            None => panic!("no {} chunk", chunk_type),
+        }
+    }
+}
+
+/// The first chunk of a type in a [`Png`], which may not be there yet;
+/// returned by [`Png::entry`].
+pub struct Entry<'a> {
+    png: &'a mut Png,
+    chunk_type: ChunkType,
+    /// Of the chunk, if there is one.
+    index: Option<usize>,
+}
+
+impl<'a> Entry<'a> {
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+    /// Whether the file has a chunk of the type.
+    pub fn is_occupied(&self) -> bool {
+        self.index.is_some()
+    }
+    /// Changes the chunk, if there is one, through `f`.
+    pub fn and_modify(self, f: impl FnOnce(&mut Chunk)) -> Self {
+        if let Some(index) = self.index {
+            f(&mut self.png.chunks[index]);
+        }
+        self
+    }
+    /// The chunk, appended with `data` if there is none.
+    pub fn or_insert(self, data: &[u8]) -> &'a mut Chunk {
+        self.or_insert_with(|| data.to_vec())
+    }
+    /// The chunk, appended with what `f` returns if there is none.
+    pub fn or_insert_with(self, f: impl FnOnce() -> Vec<u8>) -> &'a mut Chunk {
+        let index = match self.index {
+            Some(index) => index,
+            None => {
+                let index = self.png.append_index();
+                let chunk = Chunk::new(self.chunk_type, &f());
+                self.png.chunks.insert(index, chunk);
+                index
+            }
+        };
+        &mut self.png.chunks[index]
+    }
+    /// The chunk with its data replaced by `data`, or appended with it if
+    /// there is none.
+    pub fn set(self, data: &[u8]) -> &'a mut Chunk {
+        let occupied = self.is_occupied();
+        let chunk = self.or_insert(data);
+        if occupied {
+            chunk.modify_data(|old| {
+                old.clear();
+                old.extend_from_slice(data);
+            });
+        }
+        chunk
+    }
+}
+
+impl Display for Png {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for chunk in &self.chunks {
+            writeln!(
+                f,
+                "{} (length: {}, crc: {:#010x})",
+                chunk.chunk_type(),
+                chunk.length(),
+                chunk.crc()
+            )?;
+        }
+        if !self.trailer.is_empty() {
+            writeln!(f, "after IEND (length: {})", self.trailer.len())?;
+        }
+        for chunk in self.quarantined.iter() {
+            writeln!(
+                f,
+                "quarantined {} (length: {}, crc: {:#010x})",
+                chunk.chunk_type(),
+                chunk.length(),
+                chunk.crc()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Png {
+    pub const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+
+    pub fn from_chunks(chunks: Vec<Chunk>) -> Self {
+        Self {
+            chunks,
+            trailer: Arc::default(),
+            quarantined: Arc::default(),
+        }
+    }
+    /// Parses the PNG file in `value` as `options` say.
+    pub fn parse_with(value: &[u8], options: &ParseOptions) -> Result<Self, PngError> {
+        let png = Self::parse(value, Decoder::with_options(options))?;
+        #[cfg(feature = "std")]
+        options.check_conformance(&png)?;
+        Ok(png)
+    }
+    /// Like [`Png::parse_with`], with what the file gets away with (see
+    /// [`crate::warning`]).
+    pub fn parse_with_warnings(
+        value: &[u8],
+        options: &ParseOptions,
+    ) -> Result<WithWarnings<Self>, PngError> {
+        let png = Self::parse_with(value, options)?;
+        Ok(WithWarnings {
+            warnings: warning::check(&png),
+            value: png,
+        })
+    }
+    /// Like `try_from`, but keeps chunks whose CRC doesn't match (see
+    /// [`Chunk::has_valid_crc`]) instead of rejecting the file: shorthand
+    /// for [`CrcPolicy::Keep`].
+    pub fn from_bytes_unchecked(value: &[u8]) -> Result<Self, PngError> {
+        Self::parse_with(value, &ParseOptions::new().with_crc_policy(CrcPolicy::Keep))
+    }
+    /// The chunks of `bytes`, a PNG file or chunks without the signature,
+    /// up to and including `IEND`, carrying on past those that fail to
+    /// parse. A chunk that only fails its CRC is skipped as declared when
+    /// another chunk follows it there; otherwise, its length can't be
+    /// trusted either, and reading resumes at the next chunk whose CRC
+    /// matches. Each place reading fails is one error.
+    pub fn try_chunks(bytes: &[u8]) -> TryChunks<'_> {
+        let offset = match bytes.starts_with(&Self::STANDARD_HEADER) {
+            true => Self::STANDARD_HEADER.len(),
+            false => 0,
+        };
+        TryChunks {
+            bytes,
+            offset,
+            done: false,
+        }
+    }
+    /// Like `try_from`, with unknown critical chunks handled by `policy`;
+    /// shorthand for [`Png::parse_with`].
+    pub fn from_bytes_with_policy(value: &[u8], policy: CriticalPolicy) -> Result<Self, PngError> {
+        Self::parse_with(value, &ParseOptions::new().with_critical_policy(policy))
+    }
+    /// Like `try_from`, under `limits` rather than the process-wide ones;
+    /// shorthand for [`Png::parse_with`].
+    pub fn from_bytes_with_limits(value: &[u8], limits: Limits) -> Result<Self, PngError> {
+        Self::parse_with(value, &ParseOptions::new().with_limits(limits))
+    }
+    /// Appends a chunk, keeping `IEND` as the last chunk if present.
+    pub fn append_chunk(&mut self, chunk: Chunk) {
+        let index = self.append_index();
+        self.chunks.insert(index, chunk);
+    }
+    /// Where [`Png::append_chunk`] puts a chunk.
+    fn append_index(&self) -> usize {
+        match self.chunks.last() {
+            Some(last) if last.chunk_type().bytes() == *b"IEND" => self.chunks.len() - 1,
+            _ => self.chunks.len(),
+        }
+    }
+    /// The first chunk of `chunk_type`, like `png["tEXt"]` without the
+    /// panic.
+    pub fn get(&self, chunk_type: &ChunkType) -> Option<&Chunk> {
+        self.chunks.iter().find(|c| c.chunk_type() == chunk_type)
+    }
+    /// The first chunk of `chunk_type`, to change with
+    /// [`Chunk::modify_data`].
+    pub fn get_mut(&mut self, chunk_type: &ChunkType) -> Option<&mut Chunk> {
+        self.chunks
+            .iter_mut()
+            .find(|c| c.chunk_type() == chunk_type)
+    }
+    /// The first chunk of `chunk_type`, or where [`Png::append_chunk`]
+    /// would put one, to fetch or create it in one go.
+    pub fn entry(&mut self, chunk_type: ChunkType) -> Entry<'_> {
+        let index = self
+            .chunks
+            .iter()
+            .position(|c| *c.chunk_type() == chunk_type);
+        Entry {
+            png: self,
+            chunk_type,
+            index,
+        }
+    }
+    /// Inserts a chunk before the one at `index` (at the end if `index` is
+    /// the number of chunks).
+    pub fn insert_chunk_at(&mut self, index: usize, chunk: Chunk) {
+        self.chunks.insert(index, chunk);
+    }
+    /// Removes the first chunk matching `chunk_type`.
+    pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk, PngError> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        let index = self
+            .chunks
+            .iter()
+            .position(|c| *c.chunk_type() == chunk_type)
+            .ok_or_else(|| PngError::ChunkNotFound(chunk_type.to_string()))?;
+        Ok(self.chunks.remove(index))
+    }
+    /// Removes the chunk at `index`, as found through [`Png::chunks`].
+    pub fn remove_chunk_at(&mut self, index: usize) -> Chunk {
+        self.chunks.remove(index)
+    }
+    /// Replaces the chunk at `index`, returning the previous one.
+    pub fn replace_chunk_at(&mut self, index: usize, chunk: Chunk) -> Chunk {
+        core::mem::replace(&mut self.chunks[index], chunk)
+    }
+    pub fn header(&self) -> &[u8; 8] {
+        &Self::STANDARD_HEADER
+    }
+    pub fn chunks(&self) -> &[Chunk] {
+        &self.chunks
+    }
+    /// The chunks, to change in place with [`Chunk::modify_data`]. Clones
+    /// of the file keep the data they had.
+    pub fn chunks_mut(&mut self) -> &mut [Chunk] {
+        &mut self.chunks
+    }
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
+        self.chunks
+            .iter()
+            .find(|c| c.chunk_type().to_string() == chunk_type)
+    }
+    /// Every chunk of type `chunk_type`, in file order.
+    pub fn chunks_by_type<'a>(&'a self, chunk_type: &'a str) -> impl Iterator<Item = &'a Chunk> {
+        self.chunks
+            .iter()
+            .filter(move |c| c.chunk_type().to_string() == chunk_type)
+    }
+    /// The bytes after the `IEND` chunk, if the file goes on past it.
+    /// The chunks [`CriticalPolicy::Quarantine`] took out of the file.
+    pub fn quarantined(&self) -> &[Chunk] {
+        &self.quarantined
+    }
+    pub fn trailer(&self) -> &[u8] {
+        &self.trailer
+    }
+    pub fn set_trailer(&mut self, trailer: Vec<u8>) {
+        self.trailer = Arc::new(trailer);
+    }
+    #[cfg(feature = "std")]
+    pub(crate) fn set_quarantined(&mut self, quarantined: Vec<Chunk>) {
+        self.quarantined = Arc::new(quarantined);
+    }
+    /// The length of [`Png::as_bytes`], without serializing anything.
+    /// Counted in `u64`, since clones share chunk data and a file can be
+    /// longer than the memory it takes up.
+    pub fn encoded_len(&self) -> u64 {
+        let chunks: u64 = self
+            .chunks
+            .iter()
+            .map(|c| (Chunk::OVERHEAD + c.data().len()) as u64)
+            .sum();
+        (Self::STANDARD_HEADER.len() + self.trailer.len()) as u64 + chunks
+    }
+    /// # Panics
+    ///
+    /// If the file is longer than the address space; see
+    /// [`Png::write_into`].
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.extend_bytes(&mut bytes);
+        bytes
+    }
+    /// Appends the file to `bytes`, growing it at most once.
+    pub fn extend_bytes(&self, bytes: &mut Vec<u8>) {
+        let len = usize::try_from(self.encoded_len()).expect("file too large for memory");
+        bytes.reserve(len);
+        bytes.extend_from_slice(self.header());
+        for chunk in &self.chunks {
+            chunk.extend_bytes(bytes);
+        }
+        bytes.extend_from_slice(&self.trailer);
+    }
+    /// Writes the file chunk by chunk, without building it in memory first.
+    #[cfg(feature = "std")]
+    pub fn write_into(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(self.header())?;
+        for chunk in &self.chunks {
+            chunk.write_into(writer)?;
+        }
+        writer.write_all(&self.trailer)
+    }
+    /// The file as `options` say to write it.
+    #[cfg(feature = "std")]
+    pub fn as_bytes_with(&self, options: &WriteOptions) -> Vec<u8> {
+        options.apply(self).as_bytes()
+    }
+    /// Like [`Png::write_into`], as `options` say.
+    #[cfg(feature = "std")]
+    pub fn write_into_with(
+        &self,
+        writer: &mut impl Write,
+        options: &WriteOptions,
+    ) -> io::Result<()> {
+        options.apply(self).write_into(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::convert::TryFrom;
+    use std::str::FromStr;
+
+    fn testing_chunks() -> Vec<Chunk> {
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(testing_chunks())
+    }
+
+    fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk, PngError> {
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        let data: Vec<u8> = data.bytes().collect();
+        Ok(Chunk::new(chunk_type, &data))
+    }
+
+    #[test]
+    fn test_from_chunks() {
+        let png = Png::from_chunks(testing_chunks());
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_valid_from_bytes() {
+        let chunk_bytes: Vec<u8> = testing_chunks()
+            .into_iter()
+            .flat_map(|chunk| chunk.as_bytes())
+            .collect();
+
+        let bytes: Vec<u8> = Png::STANDARD_HEADER
+            .iter()
+            .chain(chunk_bytes.iter())
+            .copied()
+            .collect();
+
+        let png = Png::try_from(bytes.as_ref());
+        assert!(png.is_ok());
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let chunk_bytes: Vec<u8> = testing_chunks()
+            .into_iter()
+            .flat_map(|chunk| chunk.as_bytes())
+            .collect();
+
+        let bytes: Vec<u8> = [13, 80, 78, 71, 13, 10, 26, 10]
+            .iter()
+            .chain(chunk_bytes.iter())
+            .copied()
+            .collect();
+
+        let png = Png::try_from(bytes.as_ref());
+        assert!(png.is_err());
+    }
+
+    #[test]
+    fn test_invalid_chunk() {
+        let mut chunk_bytes: Vec<u8> = testing_chunks()
+            .into_iter()
+            .flat_map(|chunk| chunk.as_bytes())
+            .collect();
+
+        #[rustfmt::skip]
+        let mut bad_chunk = vec![
+            0, 0, 0, 5,         // length
+            32, 117, 83, 116,   // Chunk Type (bad)
+            65, 64, 65, 66, 67, // Data
+            1, 2, 3, 4, 5       // CRC (bad)
+        ];
+
+        chunk_bytes.append(&mut bad_chunk);
+
+        let png = Png::try_from(chunk_bytes.as_ref());
+        assert!(png.is_err());
+    }
+
+    #[test]
+    fn test_list_chunks() {
+        let png = testing_png();
+        let chunks = png.chunks();
+        assert_eq!(chunks.len(), 3);
+    }
+
+    #[test]
+    fn test_chunk_by_type() {
+        let png = testing_png();
+        let chunk = png.chunk_by_type("FrSt").unwrap();
+        assert_eq!(&chunk.chunk_type().to_string(), "FrSt");
+        assert_eq!(&chunk.data_as_string().unwrap(), "I am the first chunk");
+    }
+
+    #[test]
+    fn test_append_chunk() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let chunk = png.chunk_by_type("TeSt").unwrap();
+        assert_eq!(&chunk.chunk_type().to_string(), "TeSt");
+        assert_eq!(&chunk.data_as_string().unwrap(), "Message");
+    }
+
+    #[test]
+    fn test_append_chunk_before_iend() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("IEND", "").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        let last = png.chunks().last().unwrap();
+        assert_eq!(&last.chunk_type().to_string(), "IEND");
+    }
+
+    #[test]
+    fn test_entry() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("IEND", "").unwrap());
+        assert_eq!(png["miDl"].data(), b"I am another chunk");
+        let rust = ChunkType::from_str("ruSt").unwrap();
+        assert!(png.get(&rust).is_none());
+
+        assert_eq!(png.entry(rust.clone()).or_insert(b"first").data(), b"first");
+        assert_eq!(
+            png.entry(rust.clone()).or_insert(b"second").data(),
+            b"first"
+        );
+        assert_eq!(png.chunks()[3].chunk_type(), &rust);
+        assert_eq!(png.chunks().len(), 5);
+
+        png.entry(rust.clone())
+            .and_modify(|chunk| chunk.modify_data(|data| data.push(b'!')))
+            .or_insert(b"unused");
+        assert_eq!(png[&*rust.to_string()].data(), b"first!");
+        let chunk = png.entry(rust.clone()).set(b"replaced");
+        assert!(chunk.has_valid_crc());
+        png.get_mut(&rust)
+            .unwrap()
+            .modify_data(|data| data.truncate(3));
+        assert_eq!(png.get(&rust).unwrap().data(), b"rep");
+        assert_eq!(png.chunks().len(), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "no teSt chunk")]
+    fn test_index_missing() {
+        let _ = &testing_png()["teSt"];
+    }
+
+    #[test]
+    fn test_remove_chunk() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        png.remove_chunk("TeSt").unwrap();
+        let chunk = png.chunk_by_type("TeSt");
+        assert!(chunk.is_none());
+    }
+
+    #[test]
+    fn test_png_from_image_file() {
+        let png = Png::try_from(&PNG_FILE[..]);
+        assert!(png.is_ok());
+    }
+
+    #[test]
+    fn test_as_bytes() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let actual = png.as_bytes();
+        let expected: Vec<u8> = PNG_FILE.to_vec();
+        assert_eq!(actual, expected);
+        assert_eq!(actual.capacity(), expected.len());
+        assert_eq!(png.encoded_len(), expected.len() as u64);
+        #[cfg(feature = "std")]
+        {
+            let mut written = Vec::new();
+            png.write_into(&mut written).unwrap();
+            assert_eq!(written, expected);
+        }
+    }
+
+    #[test]
+    fn test_chunk_refs() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("IEND", "").unwrap());
+        png.set_trailer(b"trailing".to_vec());
+        let bytes = png.as_bytes();
+        let mut refs = chunk_refs(&bytes).unwrap();
+        let chunks: Vec<ChunkRef> = refs.by_ref().map(Result::unwrap).collect();
+        assert_eq!(chunks.len(), png.chunks().len());
+        for (chunk, expected) in chunks.iter().zip(png.chunks()) {
+            assert_eq!(chunk.to_chunk().as_bytes(), expected.as_bytes());
+            assert_eq!(&bytes[chunk.offset()..chunk.end()], expected.as_bytes());
+        }
+        assert_eq!(refs.rest(), b"trailing");
+        assert!(chunk_refs(&bytes[1..]).is_err());
+        let truncated = &bytes[..bytes.len() - 25];
+        assert!(chunk_refs(truncated).unwrap().any(|c| c.is_err()));
+    }
+
+    #[test]
+    fn test_try_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("IEND", "").unwrap());
+        png.set_trailer(b"trailing".to_vec());
+        let bytes = png.as_bytes();
+        let read = |bytes: &[u8]| -> Vec<Result<String, ChunkError>> {
+            Png::try_chunks(bytes)
+                .map(|chunk| chunk.map(|c| c.chunk_type().to_string()))
+                .collect()
+        };
+        let types = |results: &[Result<String, ChunkError>]| -> Vec<String> {
+            results
+                .iter()
+                .map(|r| r.as_ref().map_or("error".to_string(), String::clone))
+                .collect()
+        };
+        assert_eq!(types(&read(&bytes)), ["FrSt", "miDl", "LASt", "IEND"]);
+        assert_eq!(types(&read(&bytes[8..])), ["FrSt", "miDl", "LASt", "IEND"]);
+
+        // miDl starts at 40: damaged data, length and type.
+        for (at, value) in [(48, b'i'), (43, 200), (45, b'1')] {
+            let mut damaged = bytes.clone();
+            damaged[at] = value;
+            let results = read(&damaged);
+            assert_eq!(types(&results), ["FrSt", "error", "LASt", "IEND"]);
+            assert!(matches!(
+                (at, &results[1]),
+                (48, Err(ChunkError::ChecksumError))
+                    | (43, Err(ChunkError::Truncated(_)))
+                    | (45, Err(ChunkError::BadChunkType(_)))
+            ));
+        }
+
+        let results = read(&bytes[..90]);
+        assert_eq!(types(&results), ["FrSt", "miDl", "error"]);
+        assert!(matches!(results[2], Err(ChunkError::Truncated(_))));
+
+        // Cut inside the trailer, then inside IEND's CRC.
+        let iend = bytes.len() - b"trailing".len();
+        for cut in 1..=3 {
+            let results = read(&bytes[..bytes.len() - cut]);
+            assert_eq!(types(&results), ["FrSt", "miDl", "LASt", "IEND"]);
+            let results = read(&bytes[..iend - cut]);
+            assert_eq!(types(&results), ["FrSt", "miDl", "LASt", "error"]);
+            assert!(matches!(results[3], Err(ChunkError::Truncated(_))));
+        }
+    }
+
+    #[test]
+    fn test_check_crcs() {
+        let mut bytes = testing_png().as_bytes();
+        let chunks: Vec<ChunkRef> = chunk_refs(&bytes).unwrap().map(Result::unwrap).collect();
+        check_crcs(&chunks).unwrap();
+        let second = chunks[1].offset();
+        bytes[second + 8] ^= 1;
+        let chunks: Vec<ChunkRef> = chunk_refs(&bytes).unwrap().map(Result::unwrap).collect();
+        assert!(matches!(
+            check_crcs(&chunks),
+            Err(PngError::BadChunk {
+                offset,
+                source: ChunkError::ChecksumError
+            }) if offset == second
+        ));
+    }
+
+    #[test]
+    fn test_clone_shares_data() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut variant = png.clone();
+        for (a, b) in png.chunks().iter().zip(variant.chunks()) {
+            assert_eq!(a.data().as_ptr(), b.data().as_ptr());
+        }
+        variant.chunks_mut()[1].modify_data(|data| data[0] ^= 1);
+        assert_ne!(variant.chunks()[1].data(), png.chunks()[1].data());
+        assert_eq!(png.as_bytes(), PNG_FILE);
+        assert!(Png::try_from(variant.as_bytes().as_slice()).is_ok());
+        assert_eq!(
+            variant.chunks()[0].data().as_ptr(),
+            png.chunks()[0].data().as_ptr()
+        );
+    }
+
+    #[test]
+    fn test_shared_between_threads() {
+        let png = Arc::new(testing_png());
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let png = Arc::clone(&png);
+                std::thread::spawn(move || {
+                    let chunk = png.chunk_by_type("FrSt").unwrap();
+                    (png.chunks_by_type("miDl").count(), chunk.shared_data())
+                })
+            })
+            .collect();
+        for handle in handles {
+            let (count, data) = handle.join().unwrap();
+            assert_eq!(count, 1);
+            assert_eq!(data.as_slice(), png.chunk_by_type("FrSt").unwrap().data());
+        }
+    }
+
+    #[test]
+    fn test_bytes_after_iend() {
+        let mut bytes = PNG_FILE.to_vec();
+        bytes.extend_from_slice(b"not a chunk");
+        let png = Png::try_from(bytes.as_ref()).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.trailer(), b"not a chunk");
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_png_trait_impls() {
+        let chunk_bytes: Vec<u8> = testing_chunks()
+            .into_iter()
+            .flat_map(|chunk| chunk.as_bytes())
+            .collect();
+
+        let bytes: Vec<u8> = Png::STANDARD_HEADER
+            .iter()
+            .chain(chunk_bytes.iter())
+            .copied()
+            .collect();
+
+        let png: Png = TryFrom::try_from(bytes.as_ref()).unwrap();
+
+        let _png_string = format!("{}", png);
+    }
+
+    // A 1x1 RGBA image.
+    const PNG_FILE: [u8; 70] = [
+        137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 1, 0, 0, 0, 1, 8, 6,
+        0, 0, 0, 31, 21, 196, 137, 0, 0, 0, 13, 73, 68, 65, 84, 120, 218, 99, 248, 207, 192, 240,
+        31, 0, 5, 0, 1, 255, 86, 199, 47, 13, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96, 130,
+    ];
+}
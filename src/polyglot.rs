@@ -0,0 +1,471 @@
+//! PNG/ZIP polyglots: a ZIP archive after `IEND` leaves a file that image
+//! viewers show as a PNG and that unzip extracts as an archive.
+//!
+//! ZIP readers find the archive from its end-of-central-directory record at
+//! the end of the file and locate everything else by absolute offsets, so
+//! appending an archive means shifting those offsets by the size of the PNG
+//! in front of it. ZIP64 archives are not supported.
+
+use std::io::Read;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+use crate::limits::{self, Limit, LimitExceeded, Limits};
+use crate::png::Png;
+use crate::timestamp::days_from_civil;
+
+#[derive(Debug, Error)]
+pub enum PolyglotError {
+    #[error("Not a ZIP archive")]
+    NotZip,
+    #[error("ZIP64 archives are not supported")]
+    Zip64,
+    #[error("Malformed ZIP archive: {0}")]
+    Malformed(&'static str),
+    #[error("The file already has {0} bytes after IEND")]
+    Trailer(usize),
+    #[error("ZIP entry {0} is encrypted or uses an unsupported compression method")]
+    Unsupported(String),
+    #[error(transparent)]
+    LimitExceeded(#[from] LimitExceeded),
+}
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+const END_LEN: usize = 22;
+const CENTRAL_LEN: usize = 46;
+const LOCAL_LEN: usize = 30;
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+/// The end record is followed by a comment of at most this many bytes.
+const MAX_COMMENT: usize = u16::MAX as usize;
+
+/// A ZIP archive found in a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Archive {
+    /// Where the archive's first byte is in the file.
+    pub start: usize,
+    /// Names of the entries, in central directory order.
+    pub entries: Vec<String>,
+    /// Offset of the end-of-central-directory record.
+    end: usize,
+    /// Offset of each central directory entry's local header offset field.
+    offset_fields: Vec<usize>,
+    /// How much the offsets stored in the archive are off from where
+    /// things actually are (non-zero when it was appended untouched).
+    shift: usize,
+    /// What the central directory says of each entry.
+    files: Vec<File>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct File {
+    flags: u16,
+    method: u16,
+    /// MS-DOS time and date, in local time.
+    time: u16,
+    date: u16,
+    crc: u32,
+    compressed_len: u32,
+    len: u32,
+    /// Where the local header is in the file.
+    local: usize,
+}
+
+impl Archive {
+    /// Finds the ZIP archive that ends `bytes`, as ZIP readers do.
+    pub fn find(bytes: &[u8]) -> Result<Self, PolyglotError> {
+        if bytes.len() < END_LEN {
+            return Err(PolyglotError::NotZip);
+        }
+        let lowest = bytes.len().saturating_sub(END_LEN + MAX_COMMENT);
+        let end = (lowest..=bytes.len() - END_LEN)
+            .rev()
+            .find(|&i| {
+                read_u32(bytes, i) == Some(END_OF_DIRECTORY)
+                    && read_u16(bytes, i + 20).map(usize::from) == Some(bytes.len() - i - END_LEN)
+            })
+            .ok_or(PolyglotError::NotZip)?;
+        let count = read_u16(bytes, end + 10).expect("inside the end record");
+        let size = read_u32(bytes, end + 12).expect("inside the end record");
+        let offset = read_u32(bytes, end + 16).expect("inside the end record");
+        if count == u16::MAX || size == u32::MAX || offset == u32::MAX {
+            return Err(PolyglotError::Zip64);
+        }
+        // The central directory sits right before the end record, whatever
+        // its stored offset says.
+        let directory = end
+            .checked_sub(size as usize)
+            .ok_or(PolyglotError::Malformed("central directory out of bounds"))?;
+        let shift = directory
+            .checked_sub(offset as usize)
+            .ok_or(PolyglotError::Malformed(
+                "central directory offset past its end",
+            ))?;
+
+        let mut entries = Vec::with_capacity(count.into());
+        let mut offset_fields = Vec::with_capacity(count.into());
+        let mut files = Vec::with_capacity(count.into());
+        let mut start = directory;
+        let mut position = directory;
+        for _ in 0..count {
+            if position + CENTRAL_LEN > end || read_u32(bytes, position) != Some(CENTRAL_HEADER) {
+                return Err(PolyglotError::Malformed("bad central directory entry"));
+            }
+            let field = |at| read_u16(bytes, position + at).expect("inside the entry") as usize;
+            let (name_len, extra_len, comment_len) = (field(28), field(30), field(32));
+            let local = read_u32(bytes, position + 42).expect("inside the entry");
+            if local == u32::MAX {
+                return Err(PolyglotError::Zip64);
+            }
+            let local = local as usize + shift;
+            if read_u32(bytes, local) != Some(LOCAL_HEADER) {
+                return Err(PolyglotError::Malformed("bad local file header offset"));
+            }
+            let name = bytes
+                .get(position + CENTRAL_LEN..position + CENTRAL_LEN + name_len)
+                .ok_or(PolyglotError::Malformed("entry name out of bounds"))?;
+            entries.push(String::from_utf8_lossy(name).into_owned());
+            offset_fields.push(position + 42);
+            let word = |at| read_u32(bytes, position + at).expect("inside the entry");
+            files.push(File {
+                flags: field(8) as u16,
+                method: field(10) as u16,
+                time: field(12) as u16,
+                date: field(14) as u16,
+                crc: word(16),
+                compressed_len: word(20),
+                len: word(24),
+                local,
+            });
+            start = start.min(local);
+            position += CENTRAL_LEN + name_len + extra_len + comment_len;
+        }
+        Ok(Self {
+            start,
+            entries,
+            end,
+            offset_fields,
+            shift,
+            files,
+        })
+    }
+    /// The uncompressed size of entry `index`.
+    pub fn entry_len(&self, index: usize) -> u64 {
+        self.files[index].len.into()
+    }
+    /// When entry `index` was last modified, taking its MS-DOS local time
+    /// for UTC, since ZIP archives don't record the time zone.
+    pub fn modified(&self, index: usize) -> SystemTime {
+        let File { time, date, .. } = self.files[index];
+        let (month, day) = (u32::from(date >> 5 & 0xf), u32::from(date & 0x1f));
+        if !(1..=12).contains(&month) || day == 0 {
+            return UNIX_EPOCH;
+        }
+        let days = days_from_civil(1980 + i64::from(date >> 9), month, day);
+        let secs = u64::from(time >> 11) * 3600 + u64::from(time >> 5 & 0x3f) * 60;
+        let secs = secs + u64::from(time & 0x1f) * 2;
+        UNIX_EPOCH + Duration::from_secs(days as u64 * 86_400 + secs)
+    }
+    /// The contents of entry `index`, given the `bytes` the archive was
+    /// found in. Stored and deflated entries are supported, and their CRC
+    /// is checked.
+    pub fn extract(&self, bytes: &[u8], index: usize) -> Result<Vec<u8>, PolyglotError> {
+        self.extract_with_limits(bytes, index, &limits::get())
+    }
+    /// [`Archive::extract`] under `limits` rather than the process-wide
+    /// ones. The size the archive claims for the entry is checked against
+    /// the file size limit, and for deflated entries the decompressed size
+    /// limit, before anything is inflated.
+    pub fn extract_with_limits(
+        &self,
+        bytes: &[u8],
+        index: usize,
+        limits: &Limits,
+    ) -> Result<Vec<u8>, PolyglotError> {
+        let file = &self.files[index];
+        let name = &self.entries[index];
+        // Bit 0 marks encrypted entries.
+        if file.flags & 1 != 0 || !matches!(file.method, STORED | DEFLATED) {
+            return Err(PolyglotError::Unsupported(name.clone()));
+        }
+        let field = |at| read_u16(bytes, file.local + at).map(usize::from);
+        let (Some(name_len), Some(extra_len)) = (field(26), field(28)) else {
+            return Err(PolyglotError::Malformed("local file header out of bounds"));
+        };
+        let len = file.len as usize;
+        limits.check(Limit::FileLen, len)?;
+        let start = file.local + LOCAL_LEN + name_len + extra_len;
+        let data = bytes
+            .get(start..start + file.compressed_len as usize)
+            .ok_or(PolyglotError::Malformed("entry data out of bounds"))?;
+        let contents = match file.method {
+            STORED => data.to_vec(),
+            _ => {
+                limits.check(Limit::DecompressedLen, len)?;
+                let mut contents = Vec::new();
+                flate2::read::DeflateDecoder::new(data)
+                    .take(u64::from(file.len) + 1)
+                    .read_to_end(&mut contents)
+                    .map_err(|_| PolyglotError::Malformed("bad deflate stream"))?;
+                contents
+            }
+        };
+        if contents.len() != len || crc32fast::hash(&contents) != file.crc {
+            return Err(PolyglotError::Malformed("entry checksum mismatch"));
+        }
+        Ok(contents)
+    }
+    /// Whether the offsets stored in the archive are where things are in the
+    /// file; archives merely appended to another file need fixing up.
+    pub fn is_aligned(&self) -> bool {
+        self.shift == 0
+    }
+}
+
+/// Appends the ZIP archive `zip` after `IEND`, shifting its offsets so the
+/// result is a valid archive as well as a PNG.
+pub fn create(png: &mut Png, zip: &[u8]) -> Result<(), PolyglotError> {
+    if !png.trailer().is_empty() {
+        return Err(PolyglotError::Trailer(png.trailer().len()));
+    }
+    let archive = Archive::find(zip)?;
+    let zip = &zip[archive.start..];
+    let prefix = png.encoded_len();
+    // Offsets need to move from where they point now to `prefix` plus
+    // their position within `zip`.
+    let moved = |stored: u32| -> Result<u32, PolyglotError> {
+        (u64::from(stored) + archive.shift as u64 - archive.start as u64 + prefix)
+            .try_into()
+            .map_err(|_| PolyglotError::Zip64)
+    };
+    let mut trailer = zip.to_vec();
+    for &field in &archive.offset_fields {
+        let field = field - archive.start;
+        let stored = read_u32(&trailer, field).expect("inside the entry");
+        trailer[field..field + 4].copy_from_slice(&moved(stored)?.to_le_bytes());
+    }
+    let field = archive.end - archive.start + 16;
+    let stored = read_u32(&trailer, field).expect("inside the end record");
+    trailer[field..field + 4].copy_from_slice(&moved(stored)?.to_le_bytes());
+    png.set_trailer(trailer);
+    Ok(())
+}
+
+/// The ZIP archive after `IEND`, if the file is a PNG/ZIP polyglot.
+pub fn detect(png: &Png) -> Option<Archive> {
+    if png.trailer().is_empty() {
+        return None;
+    }
+    let bytes = png.as_bytes();
+    detect_in(&bytes, bytes.len() - png.trailer().len())
+}
+
+/// Like [`detect`], given the bytes of the whole file and where `IEND`
+/// ends in it.
+pub fn detect_in(file: &[u8], trailer_start: usize) -> Option<Archive> {
+    if trailer_start >= file.len() {
+        return None;
+    }
+    let archive = Archive::find(file).ok()?;
+    (archive.start >= trailer_start).then_some(archive)
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    /// An archive of stored (uncompressed) files.
+    fn zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+        zip_of(files, false)
+    }
+
+    fn zip_of(files: &[(&str, &[u8])], deflate: bool) -> Vec<u8> {
+        let mut zip = Vec::new();
+        let mut directory = Vec::new();
+        for &(name, contents) in files {
+            let crc = crc32fast::hash(contents);
+            let data = match deflate {
+                true => {
+                    let mut encoder = flate2::write::DeflateEncoder::new(
+                        Vec::new(),
+                        flate2::Compression::default(),
+                    );
+                    std::io::Write::write_all(&mut encoder, contents).unwrap();
+                    encoder.finish().unwrap()
+                }
+                false => contents.to_vec(),
+            };
+            let method = if deflate { DEFLATED } else { STORED };
+            let mut common = Vec::new();
+            common.extend_from_slice(&[20, 0, 0, 0]);
+            common.extend_from_slice(&method.to_le_bytes());
+            // 12:30:00 on 2022-05-01.
+            common.extend_from_slice(&(12u16 << 11 | 30 << 5).to_le_bytes());
+            common.extend_from_slice(&(42u16 << 9 | 5 << 5 | 1).to_le_bytes());
+            common.extend_from_slice(&crc.to_le_bytes());
+            common.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(contents.len() as u32).to_le_bytes());
+            common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            common.extend_from_slice(&[0, 0]);
+
+            directory.extend_from_slice(&CENTRAL_HEADER.to_le_bytes());
+            directory.extend_from_slice(&[20, 0]);
+            directory.extend_from_slice(&common);
+            // Comment length, disk, internal and external attributes.
+            directory.extend_from_slice(&[0; 10]);
+            directory.extend_from_slice(&(zip.len() as u32).to_le_bytes());
+            directory.extend_from_slice(name.as_bytes());
+
+            zip.extend_from_slice(&LOCAL_HEADER.to_le_bytes());
+            zip.extend_from_slice(&common);
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(&data);
+        }
+        let offset = zip.len() as u32;
+        zip.extend_from_slice(&directory);
+        zip.extend_from_slice(&END_OF_DIRECTORY.to_le_bytes());
+        zip.extend_from_slice(&[0; 4]);
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(files.len() as u16).to_le_bytes());
+        zip.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&offset.to_le_bytes());
+        zip.extend_from_slice(&[0, 0]);
+        zip
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), &[0; 13]),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), &[]),
+        ])
+    }
+
+    #[test]
+    fn test_create_and_detect() {
+        let zip = zip(&[("a.txt", b"hello"), ("b/c.txt", b"world")]);
+        let archive = Archive::find(&zip).unwrap();
+        assert_eq!(archive.start, 0);
+        assert_eq!(archive.entries, ["a.txt", "b/c.txt"]);
+        assert!(archive.is_aligned());
+
+        let mut png = testing_png();
+        assert_eq!(detect(&png), None);
+        create(&mut png, &zip).unwrap();
+        let bytes = png.as_bytes();
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.chunks().len(), 2);
+        let archive = detect(&png).unwrap();
+        assert_eq!(archive.start, testing_png().as_bytes().len());
+        assert_eq!(archive.entries, ["a.txt", "b/c.txt"]);
+        assert!(archive.is_aligned());
+        assert!(matches!(
+            create(&mut png.clone(), &zip),
+            Err(PolyglotError::Trailer(_))
+        ));
+    }
+
+    #[test]
+    fn test_extract() {
+        let mut png = testing_png();
+        create(
+            &mut png,
+            &zip(&[("a.txt", b"hello"), ("b/c.txt", b"world")]),
+        )
+        .unwrap();
+        let bytes = png.as_bytes();
+        let archive = detect(&png).unwrap();
+        assert_eq!(archive.entry_len(1), 5);
+        assert_eq!(archive.extract(&bytes, 0).unwrap(), b"hello");
+        assert_eq!(archive.extract(&bytes, 1).unwrap(), b"world");
+        // 2022-05-01 12:30:00, as the test archive's entries are dated.
+        assert_eq!(
+            archive.modified(0),
+            UNIX_EPOCH + Duration::from_secs(1_651_408_200)
+        );
+
+        let mut damaged = bytes.clone();
+        let at = archive.files[0].local + LOCAL_LEN + 5;
+        damaged[at] ^= 1;
+        assert!(matches!(
+            archive.extract(&damaged, 0),
+            Err(PolyglotError::Malformed(_))
+        ));
+        let mut unsupported = archive.clone();
+        unsupported.files[0].method = 12;
+        assert!(matches!(
+            unsupported.extract(&bytes, 0),
+            Err(PolyglotError::Unsupported(_))
+        ));
+
+        let text = b"hello hello hello hello".repeat(10);
+        let zip = zip_of(&[("a.txt", &text)], true);
+        let archive = Archive::find(&zip).unwrap();
+        assert!(zip.len() < text.len());
+        assert_eq!(archive.extract(&zip, 0).unwrap(), text);
+    }
+
+    #[test]
+    fn test_extract_claimed_len() {
+        let zip = zip_of(&[("a.txt", &b"hello ".repeat(20))], true);
+        let mut archive = Archive::find(&zip).unwrap();
+        // A crafted header claiming 4 GiB.
+        archive.files[0].len = u32::MAX;
+        assert!(matches!(
+            archive.extract_with_limits(&zip, 0, &Limits::UNLIMITED),
+            Err(PolyglotError::Malformed("entry checksum mismatch"))
+        ));
+        let limits = Limits {
+            max_file_len: 1024,
+            ..Limits::UNLIMITED
+        };
+        assert!(matches!(
+            archive.extract_with_limits(&zip, 0, &limits),
+            Err(PolyglotError::LimitExceeded(LimitExceeded {
+                limit: Limit::FileLen,
+                ..
+            }))
+        ));
+        let limits = Limits {
+            max_decompressed_len: 1024,
+            ..Limits::UNLIMITED
+        };
+        assert!(matches!(
+            archive.extract_with_limits(&zip, 0, &limits),
+            Err(PolyglotError::LimitExceeded(LimitExceeded {
+                limit: Limit::DecompressedLen,
+                ..
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_detect_unaligned() {
+        // Plain concatenation, as `cat image.png archive.zip` does.
+        let mut png = testing_png();
+        png.set_trailer(zip(&[("a.txt", b"hello")]));
+        let archive = detect(&png).unwrap();
+        assert!(!archive.is_aligned());
+        assert_eq!(archive.entries, ["a.txt"]);
+
+        let mut png = testing_png();
+        png.set_trailer(b"not an archive".to_vec());
+        assert_eq!(detect(&png), None);
+        assert!(matches!(
+            create(&mut testing_png(), b"PK\x05\x06"),
+            Err(PolyglotError::NotZip)
+        ));
+    }
+}
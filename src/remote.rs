@@ -0,0 +1,222 @@
+//! Reading PNG files straight from `https://` URLs.
+//!
+//! Downloads go through the system's `curl`, which ships with every
+//! platform pngme targets (Windows 10 and later included), so the `http`
+//! feature adds no TLS stack to the build. The body streams through a
+//! [`Download`] as it arrives, and a download is cut off as soon as it
+//! goes past its size cap, whatever the server claims its length is.
+
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RemoteError {
+    #[error("Only https:// URLs can be downloaded: {0}")]
+    NotHttps(String),
+    #[error("Failed to run curl: {0}")]
+    Spawn(io::Error),
+    #[error("Failed to download {url}: {message}")]
+    Failed { url: String, message: String },
+    #[error("{url} is larger than {max_len} bytes")]
+    TooLarge { url: String, max_len: usize },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// curl's exit code when `--max-filesize` refuses a download.
+const CURL_FILESIZE_EXCEEDED: i32 = 63;
+
+/// Whether `input` names something to download rather than a file.
+pub fn is_url(input: &str) -> bool {
+    input.starts_with("https://")
+}
+
+/// Starts downloading `url`, following redirects as long as they stay on
+/// `https`. Reading fails once more than `max_len` bytes arrive.
+pub fn open(url: &str, max_len: usize) -> Result<Download, RemoteError> {
+    if !is_url(url) {
+        return Err(RemoteError::NotHttps(url.to_string()));
+    }
+    let mut curl = Command::new("curl");
+    curl.args(["--silent", "--show-error", "--fail", "--location"])
+        .args(["--proto", "=https", "--proto-redir", "=https"]);
+    // Lets curl refuse a body whose announced length is too large before
+    // it starts; curl takes no cap past `i64::MAX`, so no cap at all.
+    if i64::try_from(max_len).is_ok() {
+        curl.arg("--max-filesize").arg(max_len.to_string());
+    }
+    curl.arg("--").arg(url);
+    Download::spawn(curl, url, max_len, &[])
+}
+
+/// The whole of `url`, at most `max_len` bytes of it.
+pub fn fetch(url: &str, max_len: usize) -> Result<Vec<u8>, RemoteError> {
+    open(url, max_len)?.read_all()
+}
+
+/// The body of a download, as it arrives. Dropping it stops the download.
+pub struct Download {
+    child: Child,
+    stdout: ChildStdout,
+    url: String,
+    max_len: usize,
+    read: usize,
+    /// Why reading failed, when it wasn't the pipe.
+    error: Option<RemoteError>,
+}
+
+impl Download {
+    /// Runs `command`, which writes the body of `url` to its standard
+    /// output, with `input` for its standard input (a `--config -` that
+    /// keeps secrets out of the process list).
+    pub(crate) fn spawn(
+        mut command: Command,
+        url: &str,
+        max_len: usize,
+        input: &[u8],
+    ) -> Result<Self, RemoteError> {
+        let stdin = match input.is_empty() {
+            true => Stdio::null(),
+            false => Stdio::piped(),
+        };
+        let mut child = command
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(RemoteError::Spawn)?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input).map_err(RemoteError::Spawn)?;
+        }
+        let stdout = child.stdout.take().expect("stdout is piped");
+        Ok(Self {
+            child,
+            stdout,
+            url: url.to_string(),
+            max_len,
+            read: 0,
+            error: None,
+        })
+    }
+    pub(crate) fn read_all(mut self) -> Result<Vec<u8>, RemoteError> {
+        let mut bytes = Vec::new();
+        match self.read_to_end(&mut bytes) {
+            Ok(_) => Ok(bytes),
+            Err(e) => Err(self.error.take().unwrap_or(RemoteError::Io(e))),
+        }
+    }
+    fn fail(&mut self, err: RemoteError) -> io::Error {
+        let io = io::Error::other(err.to_string());
+        self.error = Some(err);
+        io
+    }
+    fn too_large(&self) -> RemoteError {
+        RemoteError::TooLarge {
+            url: self.url.clone(),
+            max_len: self.max_len,
+        }
+    }
+    /// Checks how curl exited, once the body has ended.
+    fn finish(&mut self) -> io::Result<()> {
+        let status = self.child.wait()?;
+        if status.success() {
+            return Ok(());
+        }
+        let err = if status.code() == Some(CURL_FILESIZE_EXCEEDED) {
+            self.too_large()
+        } else {
+            let mut message = String::new();
+            if let Some(stderr) = self.child.stderr.as_mut() {
+                stderr.read_to_string(&mut message)?;
+            }
+            // curl's first line says what went wrong; the rest is advice.
+            let line = message.lines().next().unwrap_or_default().trim();
+            let message = match line.strip_prefix("curl: ") {
+                Some(line) => line.to_string(),
+                None if line.is_empty() => status.to_string(),
+                None => line.to_string(),
+            };
+            RemoteError::Failed {
+                url: self.url.clone(),
+                message,
+            }
+        };
+        Err(self.fail(err))
+    }
+}
+
+impl Read for Download {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Ask for one byte past the cap, to tell a body of exactly
+        // `max_len` bytes from a longer one.
+        let room = self.max_len.saturating_sub(self.read).saturating_add(1);
+        let len = buf.len().min(room);
+        let read = self.stdout.read(&mut buf[..len])?;
+        if read == 0 && len > 0 {
+            self.finish()?;
+            return Ok(0);
+        }
+        self.read += read;
+        if self.read > self.max_len {
+            let _ = self.child.kill();
+            let err = self.too_large();
+            return Err(self.fail(err));
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str, max_len: usize) -> Result<Vec<u8>, RemoteError> {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        Download::spawn(command, "https://example.com/a.png", max_len, &[])?.read_all()
+    }
+
+    #[test]
+    fn test_is_url() {
+        assert!(is_url("https://example.com/a.png"));
+        assert!(!is_url("http://example.com/a.png"));
+        assert!(!is_url("a.png"));
+        assert!(matches!(
+            fetch("file:///etc/passwd", 100),
+            Err(RemoteError::NotHttps(_))
+        ));
+    }
+
+    #[test]
+    fn test_download() {
+        assert_eq!(sh("printf 0123456789", 10).unwrap(), b"0123456789");
+        assert!(matches!(
+            sh("printf 0123456789", 9),
+            Err(RemoteError::TooLarge { max_len: 9, .. })
+        ));
+        // Cut off even when the body never ends.
+        assert!(matches!(sh("yes", 1000), Err(RemoteError::TooLarge { .. })));
+        assert!(matches!(
+            sh("exit 63", 10),
+            Err(RemoteError::TooLarge { .. })
+        ));
+        let err = sh(
+            "echo 'curl: (22) The requested URL returned error: 404' >&2; exit 22",
+            10,
+        );
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "Failed to download https://example.com/a.png: (22) The requested URL returned error: 404"
+        );
+    }
+}
@@ -0,0 +1,721 @@
+//! Forensic reports on one PNG file, to attach to an investigation or a
+//! bug ticket: [`report`] puts together a summary, what linting and
+//! scanning found, a map of the chunks with their offsets, CRCs and
+//! entropy, the text and timestamps the file carries, an entropy profile
+//! of the whole file and a hex preview of each chunk.
+//!
+//! Reports come as HTML, one self-contained page with its style inline
+//! and charts drawn with CSS, or as Markdown, with the charts in block
+//! characters. Nothing in them depends on when they were made, so the
+//! same file always gives the same report.
+
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+use thiserror::Error;
+
+use crate::analysis;
+use crate::census;
+use crate::chunk::Chunk;
+#[cfg(feature = "crypto")]
+use crate::hash;
+use crate::image::{ColorType, Header, ImageData};
+use crate::lint::{self, Profile};
+use crate::png::{Png, PngError};
+use crate::scan::{self, Finding};
+use crate::timeline;
+use crate::timestamp;
+use crate::vendor;
+
+/// Bytes of data shown per chunk in the hex previews.
+pub const PREVIEW_LEN: usize = 64;
+/// Characters of a text chunk shown in the metadata table.
+const TEXT_LEN: usize = 500;
+/// Most windows the entropy profile divides the file into…
+const PROFILE_WINDOWS: usize = 64;
+/// …each of at least this many bytes.
+const PROFILE_MIN_WINDOW: usize = 256;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Html,
+    Markdown,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown report format {0:?} (expected html or markdown)")]
+pub struct UnknownFormat(String);
+
+impl FromStr for Format {
+    type Err = UnknownFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "html" => Ok(Self::Html),
+            "markdown" | "md" => Ok(Self::Markdown),
+            _ => Err(UnknownFormat(s.to_string())),
+        }
+    }
+}
+
+impl Format {
+    /// The format a file named `path` is for, by its extension: Markdown
+    /// for `.md` and `.markdown`, HTML otherwise.
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("md") || e.eq_ignore_ascii_case("markdown") => {
+                Self::Markdown
+            }
+            _ => Self::Html,
+        }
+    }
+}
+
+/// The report on the PNG file `bytes`, called `name` in it. Chunks that
+/// fail their CRC are reported rather than refused.
+pub fn report(name: &str, bytes: &[u8], format: Format) -> Result<String, PngError> {
+    let png = Png::from_bytes_unchecked(bytes)?;
+    let offsets = chunk_offsets(&png);
+    let sections = [
+        summary(name, bytes, &png),
+        findings(bytes, &png),
+        chunk_map(&png, &offsets),
+        metadata(&png),
+        entropy_profile(bytes, &offsets),
+        previews(&png, &offsets),
+    ];
+    Ok(match format {
+        Format::Html => html(name, &sections),
+        Format::Markdown => markdown(name, &sections),
+    })
+}
+
+/// The offset in the file of each chunk, and of the data after `IEND`.
+fn chunk_offsets(png: &Png) -> Vec<usize> {
+    let mut at = Png::STANDARD_HEADER.len();
+    let mut offsets = Vec::with_capacity(png.chunks().len() + 1);
+    for chunk in png.chunks() {
+        offsets.push(at);
+        at += Chunk::OVERHEAD + chunk.data().len();
+    }
+    offsets.push(at);
+    offsets
+}
+
+struct Section {
+    title: &'static str,
+    blocks: Vec<Block>,
+}
+
+enum Block {
+    Table {
+        headers: &'static [&'static str],
+        rows: Vec<Vec<Cell>>,
+    },
+    /// Preformatted text under a heading of its own.
+    Dump {
+        title: String,
+        text: String,
+    },
+    Note(String),
+}
+
+enum Cell {
+    Text(String),
+    Code(String),
+    /// A bar `value / max` of the way across, labelled.
+    Bar {
+        value: f64,
+        max: f64,
+        label: String,
+    },
+}
+
+fn text(s: impl Into<String>) -> Cell {
+    Cell::Text(s.into())
+}
+
+fn summary(name: &str, bytes: &[u8], png: &Png) -> Section {
+    let mut rows = vec![
+        vec![text("File"), Cell::Code(name.to_string())],
+        vec![text("Size"), text(format!("{} bytes", bytes.len()))],
+    ];
+    let image = match Header::from_png(png) {
+        Ok(header) => format!(
+            "{}×{}, {}-bit {}{}",
+            header.width,
+            header.height,
+            header.bit_depth,
+            color_name(header.color_type),
+            if header.interlaced {
+                ", interlaced"
+            } else {
+                ""
+            }
+        ),
+        Err(e) => e.to_string(),
+    };
+    rows.push(vec![text("Image"), text(image)]);
+    let pixels = match ImageData::decode(png) {
+        Ok(_) => "decode cleanly".to_string(),
+        Err(e) => format!("fail to decode: {}", e),
+    };
+    rows.push(vec![text("Pixels"), text(pixels)]);
+    let bad = png.chunks().iter().filter(|c| !c.has_valid_crc()).count();
+    rows.push(vec![
+        text("Chunks"),
+        text(format!("{}, {} failing their CRC", png.chunks().len(), bad)),
+    ]);
+    rows.push(vec![
+        text("After IEND"),
+        text(format!("{} bytes", png.trailer().len())),
+    ]);
+    #[cfg(feature = "crypto")]
+    rows.push(vec![
+        text("Content hash"),
+        Cell::Code(hash::to_hex(&hash::content_hash(png))),
+    ]);
+    Section {
+        title: "Summary",
+        blocks: vec![Block::Table {
+            headers: &["Property", "Value"],
+            rows,
+        }],
+    }
+}
+
+fn color_name(color_type: ColorType) -> &'static str {
+    match color_type {
+        ColorType::Grayscale => "grayscale",
+        ColorType::Rgb => "RGB",
+        ColorType::Indexed => "indexed",
+        ColorType::GrayscaleAlpha => "grayscale with alpha",
+        ColorType::Rgba => "RGBA",
+    }
+}
+
+fn findings(bytes: &[u8], png: &Png) -> Section {
+    let lints = lint::lint_with_profile(png, Profile::Third);
+    let mut blocks = vec![match lints.is_empty() {
+        true => Block::Note("Linting found no problems.".to_string()),
+        false => Block::Table {
+            headers: &["Lint", "Severity", "Chunk", "Message"],
+            rows: lints
+                .iter()
+                .map(|lint| {
+                    vec![
+                        Cell::Code(lint.rule.id().to_string()),
+                        text(lint.severity().as_str()),
+                        text(chunk_name(png, lint.chunk)),
+                        text(&lint.message),
+                    ]
+                })
+                .collect(),
+        },
+    }];
+    blocks.push(match scan::inspect(bytes) {
+        Ok((_, findings)) if findings.is_empty() => {
+            Block::Note("Scanning found nothing hidden.".to_string())
+        }
+        Ok((_, findings)) => Block::Table {
+            headers: &["Where", "Found"],
+            rows: findings.iter().map(finding).collect(),
+        },
+        Err(e) => Block::Note(format!("Not scanned: {}", e)),
+    });
+    Section {
+        title: "Findings",
+        blocks,
+    }
+}
+
+fn chunk_name(png: &Png, index: Option<usize>) -> String {
+    match index {
+        Some(index) => format!("#{} {}", index, png.chunks()[index].chunk_type()),
+        None => "file".to_string(),
+    }
+}
+
+fn finding(finding: &Finding) -> Vec<Cell> {
+    let (place, found) = match finding {
+        Finding::Payload { chunk_type, name } => (
+            chunk_type.to_string(),
+            match name {
+                Some(name) => format!("pngme payload {:?}", name),
+                None => "pngme payload".to_string(),
+            },
+        ),
+        Finding::TrailerPayload { len } => (
+            "after IEND".to_string(),
+            format!("pngme payload ({} bytes)", len),
+        ),
+        Finding::Polyglot { entries, aligned } => (
+            "after IEND".to_string(),
+            format!(
+                "ZIP archive of {} entries: PNG/ZIP polyglot{}",
+                entries,
+                if *aligned {
+                    ""
+                } else {
+                    " (offsets not adjusted)"
+                }
+            ),
+        ),
+        Finding::UnknownTrailer { len } => {
+            ("after IEND".to_string(), format!("{} unknown bytes", len))
+        }
+        Finding::HighEntropy(stats) => (
+            format!("#{} {}", stats.index, stats.chunk_type),
+            format!(
+                "high entropy: {:.2} bits/byte, deflates to {:.0}%",
+                stats.entropy,
+                stats.deflate_ratio * 100.0
+            ),
+        ),
+        Finding::Vendor {
+            chunk_type,
+            vendor,
+            description,
+        } => (
+            chunk_type.to_string(),
+            format!("{} chunk: {}", vendor, description),
+        ),
+    };
+    vec![text(place), text(found)]
+}
+
+fn chunk_map(png: &Png, offsets: &[usize]) -> Section {
+    let rows = analysis::chunk_stats(png)
+        .into_iter()
+        .zip(png.chunks())
+        .map(|(stats, chunk)| {
+            let code = chunk.chunk_type().bytes();
+            let class = match (chunk.chunk_type().is_critical(), vendor::lookup(&code)) {
+                (true, _) => "critical".to_string(),
+                (false, _) if census::is_registered(&code) => "ancillary".to_string(),
+                (false, Some(known)) => format!("ancillary, {}", known.vendor),
+                (false, None) => "ancillary, unregistered".to_string(),
+            };
+            vec![
+                text(stats.index.to_string()),
+                Cell::Code(format!("{:#x}", offsets[stats.index])),
+                Cell::Code(chunk.chunk_type().to_string()),
+                text(stats.len.to_string()),
+                text(match chunk.has_valid_crc() {
+                    true => "ok",
+                    false => "bad",
+                }),
+                text(class),
+                Cell::Bar {
+                    value: stats.entropy,
+                    max: 8.0,
+                    label: format!("{:.2}", stats.entropy),
+                },
+                text(match stats.is_suspicious() {
+                    true => format!("{:.0}%, suspicious", stats.deflate_ratio * 100.0),
+                    false => format!("{:.0}%", stats.deflate_ratio * 100.0),
+                }),
+            ]
+        })
+        .collect();
+    Section {
+        title: "Chunks",
+        blocks: vec![Block::Table {
+            headers: &[
+                "#",
+                "Offset",
+                "Type",
+                "Length",
+                "CRC",
+                "Class",
+                "Entropy (bits/byte)",
+                "Deflates to",
+            ],
+            rows,
+        }],
+    }
+}
+
+fn metadata(png: &Png) -> Section {
+    let texts: Vec<Vec<Cell>> = png
+        .chunks()
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| matches!(&c.chunk_type().bytes(), b"tEXt" | b"zTXt" | b"iTXt"))
+        .map(|(index, chunk)| {
+            let (keyword, value) = timeline::text(chunk)
+                .unwrap_or_else(|| ("-".to_string(), "(malformed)".to_string()));
+            let value = match value.char_indices().nth(TEXT_LEN) {
+                Some((end, _)) => format!("{}…", &value[..end]),
+                None => value,
+            };
+            vec![
+                text(format!("#{} {}", index, chunk.chunk_type())),
+                text(keyword),
+                text(value),
+            ]
+        })
+        .collect();
+    let times: Vec<Vec<Cell>> = timeline::timeline(png)
+        .into_iter()
+        .map(|event| {
+            vec![
+                text(time(event.time, event.zoned)),
+                text(event.source.to_string()),
+                text(match event.chunk {
+                    Some(index) => format!("#{}", index),
+                    None => "after IEND".to_string(),
+                }),
+                text(event.detail),
+            ]
+        })
+        .collect();
+    let blocks = vec![
+        match texts.is_empty() {
+            true => Block::Note("No text chunks.".to_string()),
+            false => Block::Table {
+                headers: &["Chunk", "Keyword", "Text"],
+                rows: texts,
+            },
+        },
+        match times.is_empty() {
+            true => Block::Note("No timestamps.".to_string()),
+            false => Block::Table {
+                headers: &["Time", "Source", "Chunk", "Value"],
+                rows: times,
+            },
+        },
+    ];
+    Section {
+        title: "Metadata",
+        blocks,
+    }
+}
+
+fn time(time: Option<SystemTime>, zoned: bool) -> String {
+    match time {
+        Some(time) if zoned => timestamp::format_utc(time),
+        Some(time) => timestamp::format_utc(time)
+            .trim_end_matches('Z')
+            .to_string(),
+        None => "-".to_string(),
+    }
+}
+
+/// The entropy of the file window by window, with the chunk each starts
+/// in, so encrypted or compressed runs show wherever they are.
+fn entropy_profile(bytes: &[u8], offsets: &[usize]) -> Section {
+    let window = bytes
+        .len()
+        .div_ceil(PROFILE_WINDOWS)
+        .max(PROFILE_MIN_WINDOW);
+    let rows = bytes
+        .chunks(window)
+        .enumerate()
+        .map(|(i, data)| {
+            let at = i * window;
+            let entropy = analysis::entropy(data);
+            // The last offset is where the data after IEND starts.
+            let within = match offsets.partition_point(|&offset| offset <= at) {
+                0 => "signature".to_string(),
+                n if n == offsets.len() => "after IEND".to_string(),
+                n => format!("#{}", n - 1),
+            };
+            vec![
+                Cell::Code(format!("{:#x}", at)),
+                text(within),
+                Cell::Bar {
+                    value: entropy,
+                    max: 8.0,
+                    label: format!("{:.2}", entropy),
+                },
+            ]
+        })
+        .collect();
+    Section {
+        title: "Entropy profile",
+        blocks: vec![
+            Block::Note(format!("{}-byte windows, in bits per byte.", window)),
+            Block::Table {
+                headers: &["Offset", "Chunk", "Entropy"],
+                rows,
+            },
+        ],
+    }
+}
+
+/// The start of each chunk's data, but of runs of `IDAT` and `fdAT` only
+/// the first.
+fn previews(png: &Png, offsets: &[usize]) -> Section {
+    let mut blocks = Vec::new();
+    let mut previous: Option<[u8; 4]> = None;
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let code = chunk.chunk_type().bytes();
+        let repeated = previous == Some(code) && matches!(&code, b"IDAT" | b"fdAT");
+        previous = Some(code);
+        if chunk.data().is_empty() || repeated {
+            continue;
+        }
+        blocks.push(Block::Dump {
+            title: format!(
+                "#{} {}, {} bytes at {:#x}",
+                index,
+                chunk.chunk_type(),
+                chunk.data().len(),
+                offsets[index]
+            ),
+            text: hex_dump(chunk.data(), offsets[index] + 8),
+        });
+    }
+    if !png.trailer().is_empty() {
+        let at = offsets[offsets.len() - 1];
+        blocks.push(Block::Dump {
+            title: format!("After IEND, {} bytes at {:#x}", png.trailer().len(), at),
+            text: hex_dump(png.trailer(), at),
+        });
+    }
+    Section {
+        title: "Hex previews",
+        blocks,
+    }
+}
+
+/// The first [`PREVIEW_LEN`] bytes of `data`, 16 a line, with the file
+/// offsets from `at` and the printable ASCII characters.
+fn hex_dump(data: &[u8], at: usize) -> String {
+    let mut dump = String::new();
+    for (i, line) in data[..data.len().min(PREVIEW_LEN)].chunks(16).enumerate() {
+        let _ = write!(dump, "{:08x} ", at + i * 16);
+        for j in 0..16 {
+            match line.get(j) {
+                Some(byte) => write!(dump, " {:02x}", byte),
+                None => write!(dump, "   "),
+            }
+            .expect("writing to a String");
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| match b {
+                0x20..=0x7e => b as char,
+                _ => '.',
+            })
+            .collect();
+        let _ = writeln!(dump, "  |{}|", ascii);
+    }
+    if data.len() > PREVIEW_LEN {
+        let _ = writeln!(dump, "… {} more bytes", data.len() - PREVIEW_LEN);
+    }
+    dump
+}
+
+const STYLE: &str = "\
+body{font-family:system-ui,sans-serif;margin:2em auto;max-width:72em;padding:0 1em;color:#222}\
+table{border-collapse:collapse;margin:1em 0}\
+th,td{border:1px solid #ccc;padding:.25em .5em;text-align:left;vertical-align:top}\
+th{background:#f3f3f3}\
+pre{background:#f7f7f7;padding:.5em;overflow-x:auto}\
+.bar{display:inline-block;width:8em;height:.8em;background:#eee;margin-right:.5em}\
+.bar span{display:block;height:100%;background:#c0392b}\
+footer{color:#777;font-size:small}";
+
+fn html(name: &str, sections: &[Section]) -> String {
+    let mut page = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape(name),
+        STYLE,
+        escape(name)
+    );
+    for section in sections {
+        let _ = writeln!(page, "<section>\n<h2>{}</h2>", section.title);
+        for block in &section.blocks {
+            match block {
+                Block::Table { headers, rows } => {
+                    page.push_str("<table>\n<tr>");
+                    for header in headers.iter() {
+                        let _ = write!(page, "<th>{}</th>", escape(header));
+                    }
+                    page.push_str("</tr>\n");
+                    for row in rows {
+                        page.push_str("<tr>");
+                        for cell in row {
+                            let _ = write!(page, "<td>{}</td>", html_cell(cell));
+                        }
+                        page.push_str("</tr>\n");
+                    }
+                    page.push_str("</table>\n");
+                }
+                Block::Dump { title, text } => {
+                    let _ = write!(
+                        page,
+                        "<h3>{}</h3>\n<pre>{}</pre>\n",
+                        escape(title),
+                        escape(text)
+                    );
+                }
+                Block::Note(note) => {
+                    let _ = writeln!(page, "<p>{}</p>", escape(note));
+                }
+            }
+        }
+        page.push_str("</section>\n");
+    }
+    let _ = write!(
+        page,
+        "<footer>pngme {}</footer>\n</body>\n</html>\n",
+        env!("CARGO_PKG_VERSION")
+    );
+    page
+}
+
+fn html_cell(cell: &Cell) -> String {
+    match cell {
+        Cell::Text(text) => escape(text),
+        Cell::Code(code) => format!("<code>{}</code>", escape(code)),
+        Cell::Bar { value, max, label } => format!(
+            "<span class=\"bar\"><span style=\"width:{:.1}%\"></span></span>{}",
+            (value / max).clamp(0.0, 1.0) * 100.0,
+            escape(label)
+        ),
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn markdown(name: &str, sections: &[Section]) -> String {
+    let mut page = format!("# {}\n", markdown_text(name));
+    for section in sections {
+        let _ = write!(page, "\n## {}\n", section.title);
+        for block in &section.blocks {
+            match block {
+                Block::Table { headers, rows } => {
+                    page.push('\n');
+                    let header: Vec<String> = headers.iter().map(|h| markdown_text(h)).collect();
+                    let _ = writeln!(page, "| {} |", header.join(" | "));
+                    let _ = writeln!(page, "|{}", "---|".repeat(headers.len()));
+                    for row in rows {
+                        let cells: Vec<String> = row.iter().map(markdown_cell).collect();
+                        let _ = writeln!(page, "| {} |", cells.join(" | "));
+                    }
+                }
+                Block::Dump { title, text } => {
+                    let _ = write!(page, "\n### {}\n\n```\n{}```\n", markdown_text(title), text);
+                }
+                Block::Note(note) => {
+                    let _ = write!(page, "\n{}\n", markdown_text(note));
+                }
+            }
+        }
+    }
+    let _ = write!(page, "\n---\npngme {}\n", env!("CARGO_PKG_VERSION"));
+    page
+}
+
+fn markdown_cell(cell: &Cell) -> String {
+    match cell {
+        Cell::Text(text) => markdown_text(text),
+        Cell::Code(code) if !code.contains('`') => format!("`{}`", code.replace('|', "\\|")),
+        Cell::Code(code) => markdown_text(code),
+        Cell::Bar { value, max, label } => {
+            // Eighths of a block, over 16 blocks.
+            const WIDTH: usize = 16;
+            let eighths = ((value / max).clamp(0.0, 1.0) * (WIDTH * 8) as f64).round() as usize;
+            let mut bar = "█".repeat(eighths / 8);
+            if !eighths.is_multiple_of(8) {
+                bar.push([' ', '▏', '▎', '▍', '▌', '▋', '▊', '▉'][eighths % 8]);
+            }
+            let padding = WIDTH - bar.chars().count();
+            format!("`{}{}` {}", bar, "·".repeat(padding), markdown_text(label))
+        }
+    }
+}
+
+/// `s` on one line, with what Markdown would take for markup escaped.
+fn markdown_text(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    if s.starts_with('#') {
+        escaped.push('\\');
+    }
+    for c in s.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '|' | '<' | '>' | '[' | ']' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' | '\r' | '\t' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::image::EncodeOptions;
+
+    fn testing_png() -> Vec<u8> {
+        let header = Header {
+            width: 4,
+            height: 4,
+            bit_depth: 8,
+            color_type: ColorType::Grayscale,
+            interlaced: false,
+        };
+        let mut png = ImageData::new(header, vec![7; 16])
+            .unwrap()
+            .to_png(&EncodeOptions::default())
+            .unwrap();
+        let chunk = |code: &str, data: &[u8]| Chunk::new(ChunkType::from_str(code).unwrap(), data);
+        png.insert_chunk_at(1, chunk("tEXt", b"Title\0<script>|x|</script>"));
+        png.insert_chunk_at(2, chunk("tIME", &[7, 234, 10, 14, 12, 0, 0]));
+        png.set_trailer(vec![0xab; 100]);
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_html_report() {
+        let bytes = testing_png();
+        let page = report("cat <1>.png", &bytes, Format::Html).unwrap();
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("<h1>cat &lt;1&gt;.png</h1>"));
+        assert!(page.contains("&lt;script&gt;|x|&lt;/script&gt;"));
+        assert!(!page.contains("<script>"));
+        // The tEXt chunk follows the signature and the 25 bytes of IHDR.
+        assert!(page.contains("<td><code>0x21</code></td><td><code>tEXt</code></td>"));
+        assert!(page.contains("<td>tIME</td><td>#2</td><td>2026-10-14 12:00:00</td>"));
+        assert!(page.contains("100 unknown bytes"));
+        assert!(page.contains("00000029  54 69 74 6c 65 00 3c"));
+        assert!(page.contains("|Title.&lt;script&gt;|x|"));
+        assert_eq!(report("cat <1>.png", &bytes, Format::Html).unwrap(), page);
+    }
+
+    #[test]
+    fn test_markdown_report() {
+        let bytes = testing_png();
+        let page = report("cat.png", &bytes, Format::Markdown).unwrap();
+        assert!(page.starts_with("# cat.png\n"));
+        assert!(page.contains("\\<script\\>\\|x\\|\\</script\\>"));
+        assert!(page.contains("## Hex previews"));
+        assert!(page.contains("### After IEND, 100 bytes at "));
+        assert_eq!(Format::for_path(Path::new("out.MD")), Format::Markdown);
+        assert_eq!(Format::for_path(Path::new("out.html")), Format::Html);
+        assert!(report("x", b"GIF89a", Format::Markdown).is_err());
+    }
+}
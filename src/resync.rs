@@ -0,0 +1,546 @@
+//! Reading PNG files whose chunk lengths are corrupt, which other tools
+//! give up on at the first bad length and lose everything after.
+//!
+//! When a chunk's CRC doesn't match at its declared length, [`resync`]
+//! looks ahead for the next plausible chunk: a type of four letters and a
+//! data length that fit the file, with a CRC that matches. If the bytes in
+//! between match the damaged chunk's own CRC, its length alone was wrong
+//! and the chunk is recovered whole; otherwise they are skipped, and
+//! reading carries on from there.
+//!
+//! Chunks whose data was damaged in place, as flaky storage does a bit at
+//! a time, can then be fixed with [`bit_flips`]: CRC-32 is linear, so the
+//! one or two flipped bits that explain a mismatch are found without
+//! trying every combination, within the lengths where the CRC tells them
+//! apart.
+
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{find_next, header, plausible, Png, PngError};
+
+/// What [`resync`] changed to read a file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Repair {
+    /// The chunk at `offset` declared `declared` bytes of data, but its CRC
+    /// matches `actual`.
+    Length {
+        offset: usize,
+        chunk_type: ChunkType,
+        declared: u32,
+        actual: u32,
+    },
+    /// The chunk at `offset` fits its declared length but not its CRC; it
+    /// is kept as stored.
+    BadCrc {
+        offset: usize,
+        chunk_type: ChunkType,
+    },
+    /// `len` bytes from `offset` held no chunk that could be recovered.
+    Skipped { offset: usize, len: usize },
+    /// The chunk at `offset` failed its CRC until `flips` were undone.
+    BitFlips {
+        offset: usize,
+        chunk_type: ChunkType,
+        flips: Vec<Flip>,
+    },
+    /// The chunk at `offset` fails its CRC, and each of `candidates` would
+    /// fix it; it is kept as stored.
+    Candidates {
+        offset: usize,
+        chunk_type: ChunkType,
+        candidates: Vec<Vec<Flip>>,
+    },
+}
+
+impl Display for Repair {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Length {
+                offset,
+                chunk_type,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "{:#x}: {} declared {} bytes, fixed to {}",
+                offset, chunk_type, declared, actual
+            ),
+            Self::BadCrc { offset, chunk_type } => {
+                write!(f, "{:#x}: {} kept with a bad CRC", offset, chunk_type)
+            }
+            Self::Skipped { offset, len } => write!(f, "{:#x}: skipped {} bytes", offset, len),
+            Self::BitFlips {
+                offset,
+                chunk_type,
+                flips,
+            } => write!(
+                f,
+                "{:#x}: {} fixed by flipping {}",
+                offset,
+                chunk_type,
+                List(flips)
+            ),
+            Self::Candidates {
+                offset,
+                chunk_type,
+                candidates,
+            } => {
+                write!(
+                    f,
+                    "{:#x}: {} kept with a bad CRC; it could be",
+                    offset, chunk_type
+                )?;
+                for (i, flips) in candidates.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { ", or " };
+                    write!(f, "{}{}", separator, List(flips))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// A bit flipped in a chunk as stored; bit 0 is the lowest of its byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flip {
+    Type {
+        byte: usize,
+        bit: u8,
+    },
+    Data {
+        byte: usize,
+        bit: u8,
+    },
+    /// Bit `bit` of the CRC as a big-endian number.
+    Crc {
+        bit: u8,
+    },
+}
+
+impl Display for Flip {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Type { byte, bit } => write!(f, "bit {} of type byte {}", bit, byte),
+            Self::Data { byte, bit } => write!(f, "bit {} of data byte {}", bit, byte),
+            Self::Crc { bit } => write!(f, "bit {} of the CRC", bit),
+        }
+    }
+}
+
+struct List<'a>(&'a [Flip]);
+
+impl Display for List<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (i, flip) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " and ")?;
+            }
+            write!(f, "{}", flip)?;
+        }
+        Ok(())
+    }
+}
+
+/// A file read by [`resync`].
+#[derive(Clone, Debug)]
+pub struct Resynced {
+    pub png: Png,
+    /// In the order of the file; empty when it parsed as it was.
+    pub repairs: Vec<Repair>,
+}
+
+impl Resynced {
+    /// Fixes the chunks kept with a bad CRC that [`bit_flips`] finds a
+    /// single explanation for, and lists the candidates for the others.
+    pub fn fix_bit_flips(&mut self) {
+        let damaged = self
+            .png
+            .chunks()
+            .iter()
+            .enumerate()
+            .filter(|(_, chunk)| !chunk.has_valid_crc())
+            .map(|(index, _)| index);
+        let kept = self
+            .repairs
+            .iter_mut()
+            .filter(|repair| matches!(repair, Repair::BadCrc { .. }));
+        let mut fixes = Vec::new();
+        for (index, repair) in damaged.zip(kept) {
+            let Repair::BadCrc { offset, chunk_type } = repair else {
+                unreachable!("only BadCrc is kept");
+            };
+            let chunk = &self.png.chunks()[index];
+            let mut candidates = bit_flips(chunk);
+            let (offset, chunk_type) = (*offset, chunk_type.clone());
+            *repair = match candidates.len() {
+                0 => continue,
+                1 => {
+                    let flips = candidates.remove(0);
+                    let fixed = undo_flips(chunk, &flips).expect("candidates leave valid types");
+                    fixes.push((index, fixed));
+                    Repair::BitFlips {
+                        offset,
+                        chunk_type,
+                        flips,
+                    }
+                }
+                _ => Repair::Candidates {
+                    offset,
+                    chunk_type,
+                    candidates,
+                },
+            };
+        }
+        for (index, chunk) in fixes {
+            self.png.replace_chunk_at(index, chunk);
+        }
+    }
+}
+
+/// Reads `bytes` as a PNG file, recovering chunks with corrupt lengths and
+/// skipping what can't be recovered. Fails only without a PNG signature.
+pub fn resync(bytes: &[u8]) -> Result<Resynced, PngError> {
+    if !bytes.starts_with(&Png::STANDARD_HEADER) {
+        return Err(PngError::InvalidHeader);
+    }
+    let mut chunks = Vec::new();
+    let mut repairs = Vec::new();
+    let mut at = Png::STANDARD_HEADER.len();
+    let mut trailer = Vec::new();
+    while at < bytes.len() {
+        let (chunk, end) = match plausible(bytes, at) {
+            Some(end) => {
+                let chunk = Chunk::from_bytes_unchecked(&bytes[at..end]);
+                (chunk.expect("a plausible chunk parses"), end)
+            }
+            None => {
+                let next = find_next(bytes, at + Chunk::OVERHEAD);
+                match recover(bytes, at, next, &mut repairs) {
+                    Some(recovered) => recovered,
+                    None => {
+                        let end = next.unwrap_or(bytes.len());
+                        repairs.push(Repair::Skipped {
+                            offset: at,
+                            len: end - at,
+                        });
+                        at = end;
+                        continue;
+                    }
+                }
+            }
+        };
+        let iend = chunk.chunk_type().bytes() == *b"IEND";
+        chunks.push(chunk);
+        at = end;
+        if iend {
+            trailer = bytes[at..].to_vec();
+            break;
+        }
+    }
+    let mut png = Png::from_chunks(chunks);
+    png.set_trailer(trailer);
+    Ok(Resynced { png, repairs })
+}
+
+/// The chunk at `at`, which failed its CRC or doesn't fit the file, and
+/// where it ends: with its data up to `next`, the next plausible chunk,
+/// when they match its CRC, or as declared when a chunk header follows it
+/// there.
+fn recover(
+    bytes: &[u8],
+    at: usize,
+    next: Option<usize>,
+    repairs: &mut Vec<Repair>,
+) -> Option<(Chunk, usize)> {
+    let (chunk_type, declared) = header(bytes, at)?;
+    if let Some(next) = next {
+        let data = &bytes[at + 8..next - 4];
+        let stored = u32::from_be_bytes(bytes[next - 4..next].try_into().expect("4 bytes"));
+        if Chunk::checksum(&chunk_type, data) == stored {
+            repairs.push(Repair::Length {
+                offset: at,
+                chunk_type: chunk_type.clone(),
+                declared,
+                actual: data.len() as u32,
+            });
+            return Some((Chunk::new(chunk_type, data), next));
+        }
+    }
+    let end = at.checked_add(Chunk::OVERHEAD + declared as usize)?;
+    if end > bytes.len() || (end < bytes.len() && header(bytes, end).is_none()) {
+        return None;
+    }
+    repairs.push(Repair::BadCrc {
+        offset: at,
+        chunk_type,
+    });
+    Chunk::from_bytes_unchecked(&bytes[at..end])
+        .ok()
+        .map(|chunk| (chunk, end))
+}
+
+/// The longest type and data in which CRC-32 tells every single-bit error
+/// apart (Hamming distance 4 up to 91607 bits, per Koopman).
+pub const SINGLE_FLIP_LEN: usize = 11_450;
+/// The longest in which it tells double-bit errors apart too (distance 5
+/// up to 2974 bits).
+pub const DOUBLE_FLIP_LEN: usize = 371;
+
+/// The table of the reflected IEEE polynomial, by byte.
+const TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// The flips, one bit or two, that would make `chunk` match its CRC; the
+/// fewest that do, so a single bit is preferred over two. Empty when the
+/// CRC already matches, when nothing that small does, or when the chunk is
+/// too long for its errors to be told apart.
+pub fn bit_flips(chunk: &Chunk) -> Vec<Vec<Flip>> {
+    let len = 4 + chunk.data().len();
+    let syndrome = Chunk::checksum(chunk.chunk_type(), chunk.data()) ^ chunk.crc();
+    if syndrome == 0 || len > SINGLE_FLIP_LEN {
+        return Vec::new();
+    }
+    // How flipping each bit of the type and data changes the CRC: the CRC
+    // from zero of a message with only that bit set.
+    let mut deltas = vec![0; 8 * len];
+    let mut registers: [u32; 8] = core::array::from_fn(|bit| TABLE[1 << bit]);
+    for byte in (0..len).rev() {
+        deltas[8 * byte..8 * byte + 8].copy_from_slice(&registers);
+        for register in &mut registers {
+            *register = (*register >> 8) ^ TABLE[(*register & 0xff) as usize];
+        }
+    }
+    let by_delta: HashMap<u32, usize> = deltas
+        .iter()
+        .enumerate()
+        .map(|(position, &delta)| (delta, position))
+        .collect();
+    let crc_bit = |delta: u32| (delta.count_ones() == 1).then(|| delta.trailing_zeros() as u8);
+
+    let mut candidates = Vec::new();
+    if let Some(&position) = by_delta.get(&syndrome) {
+        candidates.push(vec![content_flip(position)]);
+    }
+    if let Some(bit) = crc_bit(syndrome) {
+        candidates.push(vec![Flip::Crc { bit }]);
+    }
+    if candidates.is_empty() && len <= DOUBLE_FLIP_LEN {
+        for (first, &delta) in deltas.iter().enumerate() {
+            let rest = syndrome ^ delta;
+            if let Some(&second) = by_delta.get(&rest).filter(|&&second| second > first) {
+                candidates.push(vec![content_flip(first), content_flip(second)]);
+            }
+            if let Some(bit) = crc_bit(rest) {
+                candidates.push(vec![content_flip(first), Flip::Crc { bit }]);
+            }
+        }
+        if syndrome.count_ones() == 2 {
+            let low = syndrome.trailing_zeros() as u8;
+            let high = 31 - syndrome.leading_zeros() as u8;
+            candidates.push(vec![Flip::Crc { bit: low }, Flip::Crc { bit: high }]);
+        }
+    }
+    candidates.retain(|flips| undo_flips(chunk, flips).is_some());
+    candidates
+}
+
+fn content_flip(position: usize) -> Flip {
+    let (byte, bit) = (position / 8, (position % 8) as u8);
+    match byte < 4 {
+        true => Flip::Type { byte, bit },
+        false => Flip::Data {
+            byte: byte - 4,
+            bit,
+        },
+    }
+}
+
+/// `chunk` with `flips` undone, and a CRC to match, unless they leave a
+/// type that isn't four letters.
+fn undo_flips(chunk: &Chunk, flips: &[Flip]) -> Option<Chunk> {
+    let mut code = chunk.chunk_type().bytes();
+    let mut data = chunk.data().to_vec();
+    for flip in flips {
+        match *flip {
+            Flip::Type { byte, bit } => code[byte] ^= 1 << bit,
+            Flip::Data { byte, bit } => data[byte] ^= 1 << bit,
+            Flip::Crc { .. } => {}
+        }
+    }
+    let chunk_type = ChunkType::try_from(code).ok()?;
+    Some(Chunk::new(chunk_type, &data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(code: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(code).unwrap(), data)
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Comment\0hello"),
+            chunk("IDAT", &[7; 300]),
+            chunk("IEND", &[]),
+        ])
+    }
+
+    #[test]
+    fn test_resync_length() {
+        let png = testing_png();
+        let mut bytes = png.as_bytes();
+        // tEXt's length, 13, becomes 0x0100000d.
+        bytes[33] = 1;
+        assert!(Png::try_from(bytes.as_slice()).is_err());
+
+        let resynced = resync(&bytes).unwrap();
+        assert_eq!(resynced.png.as_bytes(), png.as_bytes());
+        assert_eq!(
+            resynced.repairs,
+            [Repair::Length {
+                offset: 33,
+                chunk_type: ChunkType::from_str("tEXt").unwrap(),
+                declared: 0x0100_000d,
+                actual: 13,
+            }]
+        );
+        assert!(resync(&png.as_bytes()).unwrap().repairs.is_empty());
+    }
+
+    #[test]
+    fn test_resync_damage() {
+        let png = testing_png();
+        let mut bytes = png.as_bytes();
+        // A byte of the IDAT data.
+        bytes[8 + 25 + 25 + 8 + 100] ^= 1;
+        let resynced = resync(&bytes).unwrap();
+        assert_eq!(resynced.png.chunks().len(), 4);
+        assert!(!resynced.png.chunks()[2].has_valid_crc());
+        assert_eq!(
+            resynced.repairs,
+            [Repair::BadCrc {
+                offset: 58,
+                chunk_type: ChunkType::from_str("IDAT").unwrap()
+            }]
+        );
+
+        let mut bytes = png.as_bytes();
+        // tEXt's length, and a byte of its data, so it can't be recovered.
+        bytes[33..37].copy_from_slice(&[0, 0, 0, 5]);
+        bytes[45] = 0;
+        let resynced = resync(&bytes).unwrap();
+        let types: Vec<String> = resynced
+            .png
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+        assert_eq!(types, ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(
+            resynced.repairs,
+            [Repair::Skipped {
+                offset: 33,
+                len: 25
+            }]
+        );
+        assert!(matches!(resync(b"GIF89a"), Err(PngError::InvalidHeader)));
+    }
+
+    #[test]
+    fn test_resync_truncated_crc() {
+        let png = testing_png();
+        let bytes = png.as_bytes();
+        // Cut inside IEND's CRC, and inside IDAT's.
+        for cut in [1, 3, 12 + 1, 12 + 3] {
+            let truncated = &bytes[..bytes.len() - cut];
+            let resynced = resync(truncated).unwrap();
+            let types: Vec<String> = resynced
+                .png
+                .chunks()
+                .iter()
+                .map(|chunk| chunk.chunk_type().to_string())
+                .collect();
+            let kept = if cut < 12 { 3 } else { 2 };
+            assert_eq!(types, ["IHDR", "tEXt", "IDAT"][..kept]);
+            assert!(matches!(
+                resynced.repairs[..],
+                [Repair::Skipped { offset, len }] if offset + len == truncated.len()
+            ));
+        }
+    }
+
+    /// `chunk` as stored with the bits `flipped` flipped, positions counted
+    /// from the start of the type.
+    fn damaged(chunk: &Chunk, flipped: &[usize]) -> Chunk {
+        let mut bytes = chunk.as_bytes();
+        for &position in flipped {
+            bytes[4 + position / 8] ^= 1 << (position % 8);
+        }
+        Chunk::from_bytes_unchecked(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_bit_flips() {
+        let small = chunk("tEXt", b"Comment\0a short note about the picture");
+        assert!(bit_flips(&small).is_empty());
+        assert_eq!(
+            bit_flips(&damaged(&small, &[8 * 10 + 3])),
+            [[Flip::Data { byte: 6, bit: 3 }]]
+        );
+        let crc_bit = 8 * (4 + small.data().len()) + 24 + 5;
+        assert_eq!(
+            bit_flips(&damaged(&small, &[crc_bit])),
+            [[Flip::Crc { bit: 5 }]]
+        );
+        assert_eq!(
+            bit_flips(&damaged(&small, &[8 * 5, 8 * 30 + 7])),
+            [[
+                Flip::Data { byte: 1, bit: 0 },
+                Flip::Data { byte: 26, bit: 7 }
+            ]]
+        );
+
+        let large = chunk("IDAT", &[9; 5000]);
+        assert_eq!(bit_flips(&damaged(&large, &[8 * 4000 + 1])).len(), 1);
+        assert!(bit_flips(&damaged(&large, &[8 * 10, 8 * 4000 + 1])).is_empty());
+
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            small.clone(),
+            chunk("IEND", &[]),
+        ]);
+        let mut bytes = png.as_bytes();
+        bytes[33 + 8 + 20] ^= 0x40;
+        let mut resynced = resync(&bytes).unwrap();
+        resynced.fix_bit_flips();
+        assert_eq!(resynced.png.as_bytes(), png.as_bytes());
+        assert_eq!(
+            resynced.repairs[0].to_string(),
+            "0x21: tEXt fixed by flipping bit 6 of data byte 20"
+        );
+    }
+}
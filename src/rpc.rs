@@ -0,0 +1,468 @@
+//! `pngme rpc`: JSON-RPC 2.0 over standard input and output.
+//!
+//! Every line of standard input is a request, and every answer a line of
+//! standard output, in the order the requests came; notifications, which
+//! have no `id`, get none. Params are an object naming the image with
+//! `path`, a file to read, or `image`, the file itself in base64:
+//!
+//! - `inspect`: the chunks of the image and how many bytes follow `IEND`.
+//! - `encode`: adds `message` in a `chunk_type` chunk, under `name` and
+//!   encrypted under `password` if given. Writes the image to `output`, or
+//!   back to `path`, answering with where and how many bytes; without
+//!   either, answers with the image in base64.
+//! - `decode`: the payload of the `chunk_type` chunk or named `name`,
+//!   opened with `password`, as `text`, base64 `data`, or a file's `name`
+//!   and `data`.
+//! - `validate`: whether every chunk passes its CRC check, with how many
+//!   chunks, bytes and unknown critical chunks there are, or why not and
+//!   the exit code `verify` would give. `unknown_critical` takes the same
+//!   policies as the command's option.
+//!
+//! Images are read under [`api::limits_for`] `--max-image` MiB, so
+//! payloads can't decompress or derive keys without bound.
+//!
+//! A method that fails answers with error code -32602 for bad params and
+//! -32000 otherwise, with the exit code the command would have given in
+//! `data.exit_code`.
+
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+use pngme::chunk_type::ChunkType;
+use pngme::decoder::CriticalPolicy;
+use pngme::limits;
+use pngme::png::Png;
+use pngme::verify;
+
+use crate::api::{self, ApiError, Decoded};
+use crate::args::RpcArgs;
+use crate::commands::{read_input, write_file};
+use crate::exit;
+use crate::json::Value;
+
+#[derive(Debug, Error)]
+pub enum RpcError {
+    #[error("No method {0:?}: expected inspect, encode, decode or validate")]
+    NoMethod(String),
+}
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const SERVER_ERROR: i32 = -32000;
+
+pub fn run(args: RpcArgs) -> Result<()> {
+    limits::set(api::limits_for(args.max_image as usize * 1024 * 1024));
+    let mut stdout = io::stdout().lock();
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = respond(&line) {
+            writeln!(stdout, "{}", response)?;
+            stdout.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// The answer to the request on `line`, if it needs one.
+fn respond(line: &str) -> Option<Value> {
+    let Some(request) = Value::parse(line) else {
+        return Some(error_response(
+            Value::Null,
+            PARSE_ERROR,
+            "Parse error",
+            None,
+        ));
+    };
+    let id = request.get("id").cloned();
+    let method = request.get("method").and_then(Value::as_str);
+    let params = request
+        .get("params")
+        .cloned()
+        .unwrap_or(Value::Object(Vec::new()));
+    let valid_id = matches!(
+        id,
+        None | Some(Value::Null | Value::Number(_) | Value::String(_))
+    );
+    let (Some(method), true, true, Value::Object(_)) = (
+        method,
+        valid_id,
+        request.get("jsonrpc").and_then(Value::as_str) == Some("2.0"),
+        &params,
+    ) else {
+        let id = id.filter(|_| valid_id).unwrap_or(Value::Null);
+        return Some(error_response(id, INVALID_REQUEST, "Invalid request", None));
+    };
+    let result = call(method, &Params(&params));
+    let id = id?;
+    Some(match result {
+        Ok(result) => Value::object([
+            ("jsonrpc", Value::string("2.0")),
+            ("id", id),
+            ("result", result),
+        ]),
+        Err(err) => {
+            let exit_code = exit::code_for(&err);
+            let code = match exit_code {
+                _ if err.is::<RpcError>() => METHOD_NOT_FOUND,
+                exit::BAD_ARGUMENTS => INVALID_PARAMS,
+                _ => SERVER_ERROR,
+            };
+            let data = Value::object([("exit_code", Value::number(exit_code))]);
+            error_response(id, code, &format!("{:#}", err), Some(data))
+        }
+    })
+}
+
+fn error_response(id: Value, code: i32, message: &str, data: Option<Value>) -> Value {
+    let mut error = vec![
+        ("code", Value::Number(code.to_string())),
+        ("message", Value::string(message)),
+    ];
+    error.extend(data.map(|data| ("data", data)));
+    Value::object([
+        ("jsonrpc", Value::string("2.0")),
+        ("id", id),
+        ("error", Value::object(error)),
+    ])
+}
+
+fn call(method: &str, params: &Params) -> Result<Value> {
+    match method {
+        "inspect" => Ok(api::inspect(&params.png()?)),
+        "encode" => encode(params),
+        "decode" => decode(params),
+        "validate" => validate(params),
+        _ => Err(RpcError::NoMethod(method.to_string()).into()),
+    }
+}
+
+fn encode(params: &Params) -> Result<Value> {
+    let mut png = params.png()?;
+    let chunk_type = params
+        .chunk_type()?
+        .ok_or(ApiError::MissingField("chunk_type"))?;
+    let message = params
+        .str("message")?
+        .ok_or(ApiError::MissingField("message"))?;
+    let password = params.str("password")?.map(str::as_bytes);
+    api::encode(&mut png, chunk_type, message, params.str("name")?, password)?;
+    let bytes = png.as_bytes();
+    let output = match (params.str("output")?, params.str("image")?) {
+        (Some(output), _) => Some(output),
+        (None, None) => params.str("path")?,
+        (None, Some(_)) => None,
+    };
+    match output {
+        Some(output) => {
+            write_file(Path::new(output), &bytes)?;
+            Ok(Value::object([
+                ("path", Value::string(output)),
+                ("length", Value::number(bytes.len() as u64)),
+            ]))
+        }
+        None => Ok(Value::object([(
+            "image",
+            Value::string(BASE64.encode(&bytes)),
+        )])),
+    }
+}
+
+fn decode(params: &Params) -> Result<Value> {
+    let png = params.png()?;
+    let password = params.str("password")?.map(str::as_bytes);
+    let decoded = api::decode(
+        &png,
+        params.chunk_type()?.as_ref(),
+        params.str("name")?,
+        password,
+    )?;
+    Ok(match decoded {
+        Decoded::Text(text) => match String::from_utf8(text) {
+            Ok(text) => Value::object([("text", Value::String(text))]),
+            Err(e) => Value::object([("data", Value::string(BASE64.encode(e.as_bytes())))]),
+        },
+        Decoded::File { name, data } => Value::object([
+            ("name", Value::String(name)),
+            ("data", Value::string(BASE64.encode(&data))),
+        ]),
+    })
+}
+
+fn validate(params: &Params) -> Result<Value> {
+    let bytes = params.image()?;
+    let policy = match params.str("unknown_critical")? {
+        Some(policy) => {
+            CriticalPolicy::from_str(policy).map_err(|e| ApiError::Invalid(e.to_string()))?
+        }
+        None => CriticalPolicy::default(),
+    };
+    Ok(match verify::verify_with_policy(bytes.as_slice(), policy) {
+        Ok(verified) => Value::object([
+            ("valid", Value::Bool(true)),
+            ("chunks", Value::number(verified.chunks as u64)),
+            ("length", Value::number(verified.len)),
+            ("trailer_length", Value::number(verified.trailer_len)),
+            (
+                "unknown_critical",
+                Value::number(verified.unknown_critical as u64),
+            ),
+        ]),
+        Err(e) => Value::object([
+            ("valid", Value::Bool(false)),
+            ("error", Value::string(e.to_string())),
+            ("exit_code", Value::number(exit::code_for(&e.into()))),
+        ]),
+    })
+}
+
+struct Params<'a>(&'a Value);
+
+impl Params<'_> {
+    fn str(&self, key: &str) -> Result<Option<&str>> {
+        match self.0.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s)),
+            Some(_) => Err(ApiError::Invalid(format!("{:?} must be a string", key)).into()),
+        }
+    }
+    /// The bytes of the image, from `image` or the file at `path`.
+    fn image(&self) -> Result<Vec<u8>> {
+        match (self.str("image")?, self.str("path")?) {
+            (Some(image), _) => BASE64
+                .decode(image)
+                .map_err(|_| ApiError::Invalid("\"image\" is not valid base64".to_string()).into()),
+            (None, Some(path)) => read_input(Path::new(path)),
+            (None, None) => Err(ApiError::MissingField("path").into()),
+        }
+    }
+    fn png(&self) -> Result<Png> {
+        let bytes = self.image()?;
+        Png::try_from(bytes.as_slice()).context("Failed to parse the image")
+    }
+    fn chunk_type(&self) -> Result<Option<ChunkType>> {
+        Ok(self
+            .str("chunk_type")?
+            .map(ChunkType::from_str)
+            .transpose()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pngme::chunk::Chunk;
+    use pngme::compression::{Algorithm, Compression};
+    use pngme::crypto::{Encryption, Kdf};
+    use pngme::envelope::Envelope;
+
+    fn testing_png() -> Vec<u8> {
+        let chunk = |t: &str, data: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("IDAT", &[1; 20]),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes()
+    }
+
+    fn call(method: &str, params: &str) -> Value {
+        let line = format!(
+            r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{}}}"#,
+            method, params
+        );
+        respond(&line).unwrap()
+    }
+
+    fn error_code(response: &Value) -> Option<&Value> {
+        response.get("error")?.get("code")
+    }
+
+    #[test]
+    fn test_methods() {
+        let image = BASE64.encode(testing_png());
+        let inspected = call("inspect", &format!(r#"{{"image":"{}"}}"#, image));
+        let result = inspected.get("result").unwrap();
+        assert_eq!(result.get("trailer_length"), Some(&Value::number(0u8)));
+
+        let encoded = call(
+            "encode",
+            &format!(
+                r#"{{"image":"{}","chunk_type":"ruSt","message":"hi \"there\"","password":"pw"}}"#,
+                image
+            ),
+        );
+        let encoded = encoded.get("result").unwrap().get("image").unwrap();
+        let encoded = encoded.as_str().unwrap();
+        let decode = |password: &str| {
+            call(
+                "decode",
+                &format!(
+                    r#"{{"image":"{}","chunk_type":"ruSt"{}}}"#,
+                    encoded, password
+                ),
+            )
+        };
+        let decoded = decode(r#","password":"pw""#);
+        assert_eq!(
+            decoded.get("result").unwrap().get("text"),
+            Some(&Value::string("hi \"there\""))
+        );
+        let locked = decode("");
+        assert_eq!(error_code(&locked), Some(&Value::Number("-32000".into())));
+        assert_eq!(
+            locked.get("error").unwrap().get("data"),
+            Some(&Value::object([(
+                "exit_code",
+                Value::number(exit::AUTH_FAILURE)
+            )]))
+        );
+
+        let valid = call("validate", &format!(r#"{{"image":"{}"}}"#, encoded));
+        assert_eq!(
+            valid.get("result").unwrap().get("valid"),
+            Some(&Value::Bool(true))
+        );
+        let mut corrupt = testing_png();
+        corrupt[20] ^= 1;
+        let invalid = call(
+            "validate",
+            &format!(r#"{{"image":"{}"}}"#, BASE64.encode(corrupt)),
+        );
+        let result = invalid.get("result").unwrap();
+        assert_eq!(result.get("valid"), Some(&Value::Bool(false)));
+        assert_eq!(
+            result.get("exit_code"),
+            Some(&Value::number(exit::CRC_FAILURE))
+        );
+
+        let chunk = |t: &str, data: &[u8]| Chunk::new(ChunkType::from_str(t).unwrap(), data);
+        let unknown = BASE64.encode(
+            Png::from_chunks(vec![
+                chunk("IHDR", &[0; 13]),
+                chunk("TEST", b"critical"),
+                chunk("IDAT", &[1; 20]),
+                chunk("IEND", &[]),
+            ])
+            .as_bytes(),
+        );
+        let kept = call("validate", &format!(r#"{{"image":"{}"}}"#, unknown));
+        assert_eq!(
+            kept.get("result").unwrap().get("unknown_critical"),
+            Some(&Value::number(1u8))
+        );
+        let refused = call(
+            "validate",
+            &format!(r#"{{"image":"{}","unknown_critical":"error"}}"#, unknown),
+        );
+        let result = refused.get("result").unwrap();
+        assert_eq!(result.get("valid"), Some(&Value::Bool(false)));
+        assert_eq!(
+            result.get("exit_code"),
+            Some(&Value::number(exit::PARSE_ERROR))
+        );
+    }
+
+    #[test]
+    fn test_decompression_bomb() {
+        limits::set(api::limits_for(64 * 1024));
+        let bomb = Envelope::text(&"0".repeat(2 << 20))
+            .with_compression(Compression::new(Algorithm::Deflate))
+            .as_bytes();
+        let mut png = Png::try_from(testing_png().as_slice()).unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), &bomb));
+        let params = format!(
+            r#"{{"image":"{}","chunk_type":"ruSt"}}"#,
+            BASE64.encode(png.as_bytes())
+        );
+        let response = call("decode", &params);
+        assert_eq!(
+            error_code(&response),
+            Some(&Value::Number(SERVER_ERROR.to_string()))
+        );
+        let data = response.get("error").unwrap().get("data").unwrap();
+        assert_eq!(
+            data.get("exit_code"),
+            Some(&Value::number(exit::PARSE_ERROR))
+        );
+    }
+
+    #[test]
+    fn test_kdf_cost() {
+        limits::set(api::limits_for(64 * 1024));
+        let encryption = Encryption {
+            kdf: Kdf::Pbkdf2Sha256 { iterations: 1234 },
+            ..Encryption::default()
+        };
+        let mut sealed = Envelope::text("hi")
+            .encrypt(b"hunter2", &encryption)
+            .unwrap()
+            .as_bytes();
+        // Asks for ten million iterations; the key is never derived, so the
+        // tag needn't match.
+        let at = sealed
+            .windows(5)
+            .position(|w| w == [1, 0, 0, 0x04, 0xd2])
+            .unwrap();
+        sealed[at + 1..at + 5].copy_from_slice(&Kdf::MAX_PBKDF2_ITERATIONS.to_be_bytes());
+        let mut png = Png::try_from(testing_png().as_slice()).unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), &sealed));
+        let params = format!(
+            r#"{{"image":"{}","chunk_type":"ruSt","password":"hunter2"}}"#,
+            BASE64.encode(png.as_bytes())
+        );
+        let response = call("decode", &params);
+        let data = response.get("error").unwrap().get("data").unwrap();
+        assert_eq!(
+            data.get("exit_code"),
+            Some(&Value::number(exit::PARSE_ERROR))
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let code = |line: &str| error_code(&respond(line).unwrap()).cloned();
+        let number = |n: i32| Some(Value::Number(n.to_string()));
+        assert_eq!(code("{nope"), number(PARSE_ERROR));
+        assert_eq!(
+            code(r#"{"id":1,"method":"inspect"}"#),
+            number(INVALID_REQUEST)
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","id":1,"method":"inspect","params":[1]}"#),
+            number(INVALID_REQUEST)
+        );
+        assert_eq!(
+            code(r#"{"jsonrpc":"2.0","id":"a","method":"paint"}"#),
+            number(METHOD_NOT_FOUND)
+        );
+        assert_eq!(
+            error_code(&call("inspect", "{}")).cloned(),
+            number(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error_code(&call("inspect", r#"{"image":"!!"}"#)).cloned(),
+            number(INVALID_PARAMS)
+        );
+        assert_eq!(
+            error_code(&call(
+                "decode",
+                r#"{"image":"iVBORw0KGgo=","chunk_type":3}"#
+            ))
+            .cloned(),
+            number(INVALID_PARAMS)
+        );
+        assert!(respond(r#"{"jsonrpc":"2.0","method":"paint"}"#).is_none());
+        let response = respond(r#"{"jsonrpc":"2.0","id":"a","method":"paint"}"#).unwrap();
+        assert_eq!(response.get("id"), Some(&Value::string("a")));
+    }
+}
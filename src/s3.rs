@@ -0,0 +1,346 @@
+//! Buckets behind the S3 API, for [`crate::storage`].
+//!
+//! AWS, Google Cloud Storage (through its XML API, with an HMAC key) and
+//! MinIO all serve the S3 API. Like [`crate::remote`], requests go through
+//! the system's `curl`, which signs them (SigV4) with credentials it reads
+//! from its standard input rather than its command line, so they don't
+//! show up in the process list.
+
+use std::fs;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::remote::{Download, RemoteError};
+use crate::storage::{Object, Storage, StorageError};
+
+/// Listings come in pages of at most this many objects.
+const PAGE_LEN: usize = 1000;
+
+/// Room for the three digits of the HTTP status after a body.
+const STATUS_LEN: usize = 3;
+
+#[derive(Clone)]
+pub struct S3 {
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+}
+
+impl S3 {
+    /// Where Google Cloud Storage serves the S3 API.
+    pub const GCS_ENDPOINT: &'static str = "https://storage.googleapis.com";
+
+    /// `bucket` on AWS, in `region`.
+    pub fn new(bucket: &str, region: &str, access_key: &str, secret_key: &str) -> Self {
+        Self {
+            endpoint: format!("https://s3.{}.amazonaws.com", region),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+            session_token: None,
+        }
+    }
+    /// `bucket` with the credentials in `AWS_ACCESS_KEY_ID`,
+    /// `AWS_SECRET_ACCESS_KEY` and `AWS_SESSION_TOKEN`, in `AWS_REGION` (or
+    /// `AWS_DEFAULT_REGION`, or us-east-1), at `AWS_ENDPOINT_URL` if set.
+    pub fn from_env(bucket: &str) -> Result<Self, StorageError> {
+        let var = |name| std::env::var(name).ok().filter(|value| !value.is_empty());
+        let access_key = var("AWS_ACCESS_KEY_ID")
+            .ok_or(StorageError::MissingCredentials("AWS_ACCESS_KEY_ID"))?;
+        let secret_key = var("AWS_SECRET_ACCESS_KEY")
+            .ok_or(StorageError::MissingCredentials("AWS_SECRET_ACCESS_KEY"))?;
+        let region = var("AWS_REGION")
+            .or_else(|| var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| "us-east-1".to_string());
+        let mut store = Self::new(bucket, &region, &access_key, &secret_key);
+        if let Some(endpoint) = var("AWS_ENDPOINT_URL") {
+            store = store.with_endpoint(&endpoint);
+        }
+        store.session_token = var("AWS_SESSION_TOKEN");
+        Ok(store)
+    }
+    /// Talks to another S3 server, such as MinIO or GCS. Buckets are
+    /// addressed by path (`ENDPOINT/BUCKET/KEY`), which they all take.
+    pub fn with_endpoint(self, endpoint: &str) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            ..self
+        }
+    }
+    /// Signs requests for `region`.
+    pub fn with_region(self, region: &str) -> Self {
+        Self {
+            region: region.to_string(),
+            ..self
+        }
+    }
+    pub fn with_session_token(self, token: &str) -> Self {
+        Self {
+            session_token: Some(token.to_string()),
+            ..self
+        }
+    }
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}/{}", self.endpoint, self.bucket, encode(key, "/"))
+    }
+    /// The secrets, as a curl config.
+    fn config(&self) -> String {
+        let mut config = format!(
+            "user = \"{}:{}\"\naws-sigv4 = \"aws:amz:{}:s3\"\n",
+            quote(&self.access_key),
+            quote(&self.secret_key),
+            quote(&self.region)
+        );
+        if let Some(token) = &self.session_token {
+            config += &format!("header = \"x-amz-security-token: {}\"\n", quote(token));
+        }
+        config
+    }
+    /// Sends a request to `url`, with the options `curl` adds, giving back
+    /// the status and at most `max_len` bytes of body.
+    fn request(
+        &self,
+        url: &str,
+        curl: impl FnOnce(&mut Command),
+        max_len: usize,
+    ) -> Result<(u16, Vec<u8>), StorageError> {
+        let mut command = Command::new("curl");
+        command
+            .args(["--silent", "--show-error", "--config", "-"])
+            .args(["--proto-redir", "=https", "--write-out", "%{http_code}"]);
+        curl(&mut command);
+        command.arg("--").arg(url);
+        let config = self.config();
+        let download = Download::spawn(
+            command,
+            url,
+            max_len.saturating_add(STATUS_LEN),
+            config.as_bytes(),
+        )?;
+        let mut body = match download.read_all() {
+            Err(RemoteError::TooLarge { .. }) => {
+                return Err(StorageError::TooLarge {
+                    name: url.to_string(),
+                    max_len,
+                })
+            }
+            result => result?,
+        };
+        let status = body
+            .len()
+            .checked_sub(STATUS_LEN)
+            .and_then(|at| std::str::from_utf8(&body[at..]).ok())
+            .and_then(|status| status.parse().ok())
+            .ok_or_else(|| StorageError::BadResponse(url.to_string()))?;
+        body.truncate(body.len() - STATUS_LEN);
+        Ok((status, body))
+    }
+    fn status_error(&self, key: &str, status: u16, body: &[u8]) -> StorageError {
+        let body = String::from_utf8_lossy(body);
+        let name = self.name(key);
+        match status {
+            404 => StorageError::NotFound(name),
+            // 409 is S3's answer when another conditional write is racing.
+            409 | 412 => StorageError::Changed(name),
+            _ => StorageError::Status {
+                name,
+                status,
+                message: tag(&body, "Message")
+                    .map(unescape)
+                    .unwrap_or_else(|| "no message".to_string()),
+            },
+        }
+    }
+}
+
+impl Storage for S3 {
+    fn list(&self, prefix: &str) -> Result<Vec<Object>, StorageError> {
+        let bad = || StorageError::BadResponse(self.name(prefix));
+        let mut objects = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut url = format!(
+                "{}/{}?list-type=2&max-keys={}&prefix={}",
+                self.endpoint,
+                self.bucket,
+                PAGE_LEN,
+                encode(prefix, "")
+            );
+            if let Some(token) = &token {
+                url += &format!("&continuation-token={}", encode(token, ""));
+            }
+            // Generous for a page: a kilobyte per object.
+            let (status, body) = self.request(&url, |_| {}, PAGE_LEN * 1024)?;
+            if status != 200 {
+                return Err(self.status_error(prefix, status, &body));
+            }
+            let listing = String::from_utf8(body).map_err(|_| bad())?;
+            for contents in blocks(&listing, "Contents") {
+                let object = (|| {
+                    Some(Object {
+                        key: unescape(tag(contents, "Key")?),
+                        len: tag(contents, "Size")?.parse().ok()?,
+                        etag: unescape(tag(contents, "ETag")?),
+                    })
+                })();
+                objects.push(object.ok_or_else(bad)?);
+            }
+            match tag(&listing, "NextContinuationToken") {
+                Some(next) if tag(&listing, "IsTruncated") == Some("true") => {
+                    token = Some(unescape(next));
+                }
+                _ => return Ok(objects),
+            }
+        }
+    }
+    fn get(&self, key: &str, max_len: usize) -> Result<Vec<u8>, StorageError> {
+        let (status, body) = match self.request(&self.url(key), |_| {}, max_len) {
+            Err(StorageError::TooLarge { max_len, .. }) => {
+                return Err(StorageError::TooLarge {
+                    name: self.name(key),
+                    max_len,
+                })
+            }
+            result => result?,
+        };
+        match status {
+            200 => Ok(body),
+            _ => Err(self.status_error(key, status, &body)),
+        }
+    }
+    fn put(&self, key: &str, bytes: &[u8], etag: Option<&str>) -> Result<(), StorageError> {
+        // curl reads its config from standard input, so the body goes
+        // through a file.
+        static UPLOADS: AtomicUsize = AtomicUsize::new(0);
+        let upload = std::env::temp_dir().join(format!(
+            ".pngme-upload-{}-{}",
+            process::id(),
+            UPLOADS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&upload, bytes)?;
+        let result = self.request(
+            &self.url(key),
+            |curl| {
+                curl.arg("--upload-file").arg(&upload);
+                if let Some(etag) = etag {
+                    curl.arg("--header").arg(format!("If-Match: {}", etag));
+                }
+            },
+            64 * 1024,
+        );
+        let _ = fs::remove_file(&upload);
+        let (status, body) = result?;
+        match status {
+            200 => Ok(()),
+            _ => Err(self.status_error(key, status, &body)),
+        }
+    }
+    fn name(&self, key: &str) -> String {
+        let scheme = match self.endpoint == Self::GCS_ENDPOINT {
+            true => "gs",
+            false => "s3",
+        };
+        format!("{}://{}/{}", scheme, self.bucket, key)
+    }
+}
+
+/// Percent-encodes `text` for a URL, leaving alone unreserved characters
+/// and those in `keep`.
+fn encode(text: &str, keep: &str) -> String {
+    let mut encoded = String::new();
+    for byte in text.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ if keep.as_bytes().contains(&byte) => encoded.push(byte as char),
+            _ => encoded += &format!("%{:02X}", byte),
+        }
+    }
+    encoded
+}
+
+/// `text` inside double quotes in a curl config.
+fn quote(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// The contents of the first `<name>` element in `xml`.
+fn tag<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    blocks(xml, name).next()
+}
+
+/// The contents of each `<name>` element in `xml`. Listings are flat
+/// enough that this is all the XML parsing they need.
+fn blocks<'a>(xml: &'a str, name: &str) -> impl Iterator<Item = &'a str> {
+    let (open, close) = (format!("<{}>", name), format!("</{}>", name));
+    let mut rest = xml;
+    std::iter::from_fn(move || {
+        let start = rest.find(&open)? + open.len();
+        let len = rest[start..].find(&close)?;
+        let contents = &rest[start..start + len];
+        rest = &rest[start + len + close.len()..];
+        Some(contents)
+    })
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listing() {
+        let listing = "<?xml version=\"1.0\"?><ListBucketResult><IsTruncated>true</IsTruncated>\
+            <Contents><Key>cats/a&amp;b.png</Key><ETag>&quot;9b2c&quot;</ETag><Size>120</Size>\
+            </Contents><Contents><Key>cats/c.png</Key><Size>7</Size><ETag>\"00\"</ETag>\
+            </Contents><NextContinuationToken>1/x=</NextContinuationToken></ListBucketResult>";
+        let keys: Vec<_> = blocks(listing, "Contents")
+            .map(|c| unescape(tag(c, "Key").unwrap()))
+            .collect();
+        assert_eq!(keys, ["cats/a&b.png", "cats/c.png"]);
+        let first = blocks(listing, "Contents").next().unwrap();
+        assert_eq!(unescape(tag(first, "ETag").unwrap()), "\"9b2c\"");
+        assert_eq!(tag(listing, "NextContinuationToken"), Some("1/x="));
+        assert_eq!(tag(listing, "Missing"), None);
+
+        assert_eq!(encode("cats/a b+c.png", "/"), "cats/a%20b%2Bc.png");
+        assert_eq!(encode("1/x=", ""), "1%2Fx%3D");
+        assert_eq!(quote("a\"b\\c"), "a\\\"b\\\\c");
+    }
+
+    #[test]
+    fn test_addressing() {
+        let store = S3::new("bucket", "eu-west-1", "AK", "SK");
+        assert_eq!(
+            store.url("cats/a.png"),
+            "https://s3.eu-west-1.amazonaws.com/bucket/cats/a.png"
+        );
+        assert_eq!(store.name("cats/a.png"), "s3://bucket/cats/a.png");
+        let store = store
+            .with_endpoint("https://storage.googleapis.com/")
+            .with_region("auto")
+            .with_session_token("to\"ken");
+        assert_eq!(
+            store.url("cats/a b.png"),
+            "https://storage.googleapis.com/bucket/cats/a%20b.png"
+        );
+        assert_eq!(store.name("cats/a.png"), "gs://bucket/cats/a.png");
+        assert_eq!(
+            store.config(),
+            "user = \"AK:SK\"\naws-sigv4 = \"aws:amz:auto:s3\"\n\
+             header = \"x-amz-security-token: to\\\"ken\"\n"
+        );
+    }
+}
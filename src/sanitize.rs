@@ -0,0 +1,232 @@
+//! Laundering PNG files from untrusted sources: [`sanitize`] rebuilds a
+//! file from its decoded pixels, keeping only the chunks it is given, so
+//! whatever else the file carried (payloads, malformed chunks aimed at
+//! decoders, data after `IEND`) is gone.
+//!
+//! The image data is decoded and encoded again rather than copied, which
+//! leaves nothing of the original zlib stream, and the padding bits at the
+//! end of rows of small pixels are cleared. `PLTE` and `tRNS` are checked
+//! against the header and kept once, before the image data, with entries
+//! past the end of the palette cut off; other chunks kept are copied as
+//! they are.
+
+use std::str::FromStr;
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::image::{ColorType, EncodeOptions, Header, ImageData, ImageError};
+use crate::png::Png;
+
+/// The chunks [`sanitize`] keeps unless told otherwise: those the image
+/// can't be shown without.
+pub const KEEP: [&str; 5] = ["IHDR", "PLTE", "tRNS", "IDAT", "IEND"];
+
+#[derive(Debug, Error)]
+pub enum SanitizeError {
+    #[error(transparent)]
+    Image(#[from] ImageError),
+    #[error("Indexed image has no valid PLTE chunk to keep")]
+    MissingPalette,
+}
+
+/// A file rebuilt by [`sanitize`], and what was left out of it.
+#[derive(Clone, Debug)]
+pub struct Sanitized {
+    pub png: Png,
+    /// How many chunks of each type were dropped, in the order of the file,
+    /// quarantined ones included.
+    pub dropped: Vec<(ChunkType, usize)>,
+    /// Bytes dropped from after `IEND`.
+    pub trailer: usize,
+}
+
+impl Sanitized {
+    /// Chunks dropped, of all types.
+    pub fn count(&self) -> usize {
+        self.dropped.iter().map(|(_, count)| count).sum()
+    }
+}
+
+/// The chunk types of [`KEEP`].
+pub fn default_keep() -> Vec<ChunkType> {
+    KEEP.iter()
+        .map(|code| ChunkType::from_str(code).expect("valid chunk type"))
+        .collect()
+}
+
+/// `png` rebuilt from scratch with only the chunks of the types in `keep`
+/// (`IHDR`, `IDAT` and `IEND` always), its image data encoded anew.
+pub fn sanitize(png: &Png, keep: &[ChunkType]) -> Result<Sanitized, SanitizeError> {
+    let mut image = ImageData::decode(png)?;
+    clear_padding(&mut image);
+    let header = *image.header();
+    let mut rebuilt = image.to_png(&EncodeOptions::default())?;
+    let mut sanitized = Sanitized {
+        png: Png::from_chunks(Vec::new()),
+        dropped: Vec::new(),
+        trailer: png.trailer().len(),
+    };
+    let (mut before, mut after) = (Vec::new(), Vec::new());
+    let mut palette: Option<usize> = None;
+    let (mut transparency, mut data_seen) = (false, false);
+    for chunk in png.chunks() {
+        let kept = match &chunk.chunk_type().bytes() {
+            b"IHDR" | b"IEND" => continue,
+            b"IDAT" => {
+                data_seen = true;
+                continue;
+            }
+            _ if !keep.contains(chunk.chunk_type()) => None,
+            b"PLTE" if data_seen || palette.is_some() => None,
+            b"PLTE" => palette_entries(chunk, &header).map(|entries| {
+                palette = Some(entries);
+                chunk.clone()
+            }),
+            b"tRNS" if data_seen || transparency => None,
+            b"tRNS" => vet_transparency(chunk, &header, palette).inspect(|_| transparency = true),
+            _ => Some(chunk.clone()),
+        };
+        match kept {
+            Some(chunk) if data_seen => after.push(chunk),
+            Some(chunk) => before.push(chunk),
+            None => count(&mut sanitized.dropped, chunk.chunk_type()),
+        }
+    }
+    for chunk in png.quarantined() {
+        count(&mut sanitized.dropped, chunk.chunk_type());
+    }
+    if header.color_type == ColorType::Indexed && palette.is_none() {
+        return Err(SanitizeError::MissingPalette);
+    }
+    // The new file is IHDR, IDAT... and IEND.
+    for (i, chunk) in before.into_iter().enumerate() {
+        rebuilt.insert_chunk_at(1 + i, chunk);
+    }
+    for chunk in after {
+        rebuilt.append_chunk(chunk);
+    }
+    sanitized.png = rebuilt;
+    Ok(sanitized)
+}
+
+fn count(dropped: &mut Vec<(ChunkType, usize)>, chunk_type: &ChunkType) {
+    match dropped.iter_mut().find(|(t, _)| t == chunk_type) {
+        Some((_, count)) => *count += 1,
+        None => dropped.push((chunk_type.clone(), 1)),
+    }
+}
+
+/// The bits after the last pixel of each row, which decoders ignore.
+fn clear_padding(image: &mut ImageData) {
+    let header = *image.header();
+    let used = header.width as usize * header.bits_per_pixel() % 8;
+    if used == 0 {
+        return;
+    }
+    let mask = 0xffu8 << (8 - used);
+    for y in 0..header.height {
+        if let Some(last) = image.row_mut(y).last_mut() {
+            *last &= mask;
+        }
+    }
+}
+
+/// The entries of a well-formed `PLTE`: 1 to 256 colors, no more than an
+/// indexed image's bit depth can address, and none for grayscale images.
+fn palette_entries(chunk: &Chunk, header: &Header) -> Option<usize> {
+    let len = chunk.data().len();
+    let entries = len / 3;
+    let max = match header.color_type {
+        ColorType::Grayscale | ColorType::GrayscaleAlpha => return None,
+        ColorType::Indexed => 1 << header.bit_depth,
+        ColorType::Rgb | ColorType::Rgba => 256,
+    };
+    (len.is_multiple_of(3) && (1..=max).contains(&entries)).then_some(entries)
+}
+
+/// `tRNS` as the color type allows it, cut to the palette for indexed
+/// images.
+fn vet_transparency(chunk: &Chunk, header: &Header, palette: Option<usize>) -> Option<Chunk> {
+    let data = chunk.data();
+    match header.color_type {
+        ColorType::Grayscale if data.len() == 2 => Some(chunk.clone()),
+        ColorType::Rgb if data.len() == 6 => Some(chunk.clone()),
+        ColorType::Indexed => {
+            let entries = palette?;
+            Some(match data.len() > entries {
+                true => Chunk::new(chunk.chunk_type().clone(), &data[..entries]),
+                false => chunk.clone(),
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(code: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(code).unwrap(), data)
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_sanitize() {
+        // Three 2-bit pixels a row, with the last two bits set.
+        let header = Header {
+            width: 3,
+            height: 2,
+            bit_depth: 2,
+            color_type: ColorType::Indexed,
+            interlaced: false,
+        };
+        let image = ImageData::new(header, vec![0x17, 0x07]).unwrap();
+        let mut png = image.to_png(&Default::default()).unwrap();
+        for (i, c) in [
+            chunk("tEXt", b"Comment\0hi"),
+            chunk("PLTE", &[255, 0, 0, 0, 0, 255]),
+            chunk("tRNS", &[255, 128, 7, 7]),
+            chunk("ruSt", b"payload"),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            png.insert_chunk_at(1 + i, c);
+        }
+        png.append_chunk(chunk("zTXt", b"after"));
+        png.append_chunk(chunk("PLTE", &[0, 0, 0]));
+        png.set_trailer(b"hidden".to_vec());
+
+        let mut keep = default_keep();
+        keep.push(ChunkType::from_str("zTXt").unwrap());
+        let sanitized = sanitize(&png, &keep).unwrap();
+        assert_eq!(
+            types(&sanitized.png),
+            ["IHDR", "PLTE", "tRNS", "IDAT", "zTXt", "IEND"]
+        );
+        assert_eq!(
+            sanitized.png.chunk_by_type("tRNS").unwrap().data(),
+            [255, 128]
+        );
+        assert!(sanitized.png.trailer().is_empty());
+        assert_eq!((sanitized.count(), sanitized.trailer), (3, 6));
+        assert_eq!(sanitized.dropped[0].0.to_string(), "tEXt");
+        assert!(sanitized.png.pixels_equal(&png).unwrap());
+        let decoded = ImageData::decode(&sanitized.png).unwrap();
+        assert_eq!(decoded.as_bytes(), [0x14, 0x04]);
+
+        keep.retain(|t| t.to_string() != "PLTE");
+        assert!(matches!(
+            sanitize(&png, &keep),
+            Err(SanitizeError::MissingPalette)
+        ));
+    }
+}
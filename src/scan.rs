@@ -0,0 +1,655 @@
+//! Finding what PNG files hide, file by file or across directory trees.
+//!
+//! [`inspect`] reports on one file held in memory. With the `fs` feature, a
+//! `Scanner` walks the paths it is given on one thread while a pool of
+//! workers reads and inspects the PNG files found, handing back a `Report`
+//! per file as soon as it is ready, so a frontend can show progress on a
+//! large tree.
+
+#[cfg(feature = "fs")]
+use std::fs::{self, File};
+#[cfg(feature = "fs")]
+use std::io::{self, BufReader, Read};
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "fs")]
+use std::sync::mpsc::{self, Receiver, Sender};
+#[cfg(feature = "fs")]
+use std::sync::{Arc, Mutex};
+#[cfg(feature = "fs")]
+use std::thread;
+#[cfg(feature = "fs")]
+use thiserror::Error;
+
+use crate::analysis::{self, ChunkStats};
+#[cfg(feature = "fs")]
+use crate::census;
+use crate::chunk_type::ChunkType;
+#[cfg(feature = "fs")]
+use crate::decoder::CriticalPolicy;
+#[cfg(all(feature = "fs", feature = "crypto"))]
+use crate::detect::{self, Detected};
+#[cfg(feature = "crypto")]
+use crate::envelope::Envelope;
+#[cfg(feature = "fs")]
+use crate::image::ImageError;
+use crate::limits::{self, Limit};
+#[cfg(feature = "fs")]
+use crate::perceptual::{self, Method, PerceptualHash};
+use crate::png::{self, PngError};
+use crate::polyglot;
+use crate::trailer;
+#[cfg(all(feature = "fs", feature = "uring", target_os = "linux"))]
+use crate::uring;
+use crate::vendor;
+#[cfg(feature = "fs")]
+use crate::verify::{self, Verified};
+
+#[cfg(feature = "fs")]
+#[derive(Debug, Error)]
+pub enum ScanError {
+    #[error("Failed to read {}: {source}", path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error("Failed to parse {}: {source}", path.display())]
+    Parse { path: PathBuf, source: PngError },
+    #[error("Failed to decode {}: {source}", path.display())]
+    Decode { path: PathBuf, source: ImageError },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Finding {
+    /// A pngme payload in a chunk, with its name if it has one. Needs the
+    /// `crypto` feature, which envelopes are part of.
+    Payload {
+        chunk_type: ChunkType,
+        name: Option<String>,
+    },
+    /// A pngme payload of `len` bytes stored after `IEND`.
+    TrailerPayload { len: usize },
+    /// A ZIP archive after `IEND`, making the file a polyglot. `aligned`
+    /// is whether its offsets were adjusted to count from the file's start.
+    Polyglot { entries: usize, aligned: bool },
+    /// `len` bytes after `IEND` that are none of the above.
+    UnknownTrailer { len: usize },
+    /// An ancillary chunk whose data looks encrypted, see
+    /// [`ChunkStats::is_suspicious`]. pngme payloads are reported as such
+    /// rather than this.
+    HighEntropy(ChunkStats),
+    /// A private chunk of a tool pngme knows, from [`vendor::KNOWN`].
+    Vendor {
+        chunk_type: ChunkType,
+        vendor: &'static str,
+        description: &'static str,
+    },
+}
+
+/// What [`inspect`] learned parsing a file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Summary {
+    /// Bytes in the file.
+    pub len: usize,
+    /// Chunks, `IEND` included.
+    pub chunks: usize,
+    /// Bytes after `IEND`.
+    pub trailer_len: usize,
+}
+
+#[cfg(feature = "fs")]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub path: PathBuf,
+    pub summary: Summary,
+    /// Empty if the file hides nothing pngme knows of.
+    pub findings: Vec<Finding>,
+}
+
+/// Reports what the PNG file in `bytes` hides, walking its chunks in place
+/// and checking their CRCs.
+pub fn inspect(bytes: &[u8]) -> Result<(Summary, Vec<Finding>), PngError> {
+    let limits = limits::get();
+    limits.check(Limit::FileLen, bytes.len())?;
+    let mut refs = png::chunk_refs(bytes)?;
+    let chunks = refs.by_ref().collect::<Result<Vec<_>, _>>()?;
+    limits.check(Limit::Chunks, chunks.len())?;
+    png::check_crcs(&chunks)?;
+    let mut findings = Vec::new();
+    for chunk in &chunks {
+        if let Some(known) = vendor::lookup(&chunk.chunk_type().bytes()) {
+            findings.push(Finding::Vendor {
+                chunk_type: chunk.chunk_type().clone(),
+                vendor: known.vendor,
+                description: known.description,
+            });
+        }
+    }
+    #[cfg(feature = "crypto")]
+    for chunk in &chunks {
+        if !Envelope::is_envelope(chunk.data()) {
+            continue;
+        }
+        let Ok(envelope) = Envelope::try_from(chunk.data()) else {
+            continue;
+        };
+        findings.push(Finding::Payload {
+            chunk_type: chunk.chunk_type().clone(),
+            name: envelope.name().map(str::to_string),
+        });
+    }
+    for (index, chunk) in chunks.iter().enumerate() {
+        #[cfg(feature = "crypto")]
+        if Envelope::is_envelope(chunk.data()) {
+            continue;
+        }
+        if chunk.chunk_type().is_critical() || chunk.data().len() < analysis::MIN_FLAGGED_LEN {
+            continue;
+        }
+        let stats = analysis::measure(index, chunk.chunk_type(), chunk.data());
+        if stats.is_suspicious() {
+            findings.push(Finding::HighEntropy(stats));
+        }
+    }
+    let trailer = refs.rest();
+    if let Ok(data) = trailer::find_in(trailer) {
+        findings.push(Finding::TrailerPayload { len: data.len() });
+    } else if let Some(archive) = polyglot::detect_in(bytes, bytes.len() - trailer.len()) {
+        findings.push(Finding::Polyglot {
+            entries: archive.entries.len(),
+            aligned: archive.is_aligned(),
+        });
+    } else if !trailer.is_empty() {
+        findings.push(Finding::UnknownTrailer { len: trailer.len() });
+    }
+    let summary = Summary {
+        len: bytes.len(),
+        chunks: chunks.len(),
+        trailer_len: trailer.len(),
+    };
+    Ok((summary, findings))
+}
+
+/// Reads the file at `path`, up to the file size limit, and inspects it.
+#[cfg(feature = "fs")]
+pub fn inspect_file(path: &Path) -> Result<Report, ScanError> {
+    report(path, read_capped(path))
+}
+
+/// The bytes of the file at `path`, stopping one byte past the file size
+/// limit: enough for `inspect` to refuse it.
+#[cfg(feature = "fs")]
+fn read_capped(path: &Path) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(read_limit() as u64).read_to_end(&mut bytes))
+        .map(|_| bytes)
+}
+
+#[cfg(feature = "fs")]
+fn read_limit() -> usize {
+    limits::get().max_file_len.saturating_add(1)
+}
+
+/// Inspects the file at `path`, given what reading it returned.
+#[cfg(feature = "fs")]
+fn report(path: &Path, bytes: io::Result<Vec<u8>>) -> Result<Report, ScanError> {
+    let bytes = bytes.map_err(|source| ScanError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let (summary, findings) = inspect(&bytes).map_err(|source| ScanError::Parse {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(Report {
+        path: path.to_path_buf(),
+        summary,
+        findings,
+    })
+}
+
+#[cfg(feature = "fs")]
+pub struct Scanner {
+    threads: usize,
+    policy: CriticalPolicy,
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    uring: bool,
+}
+
+#[cfg(feature = "fs")]
+impl Default for Scanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Scanner {
+    /// A scanner with a worker per core.
+    pub fn new() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            policy: CriticalPolicy::default(),
+            #[cfg(all(feature = "uring", target_os = "linux"))]
+            uring: false,
+        }
+    }
+    /// A scanner with `threads` workers (at least one).
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+    /// What [`Scanner::verify`] does with unknown critical chunks (see
+    /// [`verify::verify_with_policy`]).
+    pub fn with_critical_policy(mut self, policy: CriticalPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+    /// Whether [`Scanner::scan`] reads files in batches through io_uring,
+    /// on a thread of its own, rather than one at a time on the workers.
+    /// Where the kernel refuses io_uring, files are read as usual.
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    pub fn with_io_uring(mut self, enabled: bool) -> Self {
+        self.uring = enabled;
+        self
+    }
+    /// Scans `roots`: files are inspected whatever their name, directories
+    /// searched recursively for files ending in `.png` (symbolic links to
+    /// directories aren't followed). Reports arrive in the order files are
+    /// finished, not found.
+    pub fn scan<P: Into<PathBuf>>(&self, roots: impl IntoIterator<Item = P>) -> Scan {
+        #[cfg(all(feature = "uring", target_os = "linux"))]
+        if self.uring {
+            return self.scan_batched(roots);
+        }
+        let (results, received) = mpsc::channel();
+        let paths = self.walk_roots(roots, self.threads * 4, &results);
+        self.work(paths, results, |path: PathBuf| inspect_file(&path));
+        Scan { results: received }
+    }
+    /// Like [`Scanner::scan`], but only checks the CRCs of each file with
+    /// [`verify::verify`], streaming it rather than reading it into memory.
+    pub fn verify<P: Into<PathBuf>>(
+        &self,
+        roots: impl IntoIterator<Item = P>,
+    ) -> Scan<(PathBuf, Verified)> {
+        let (results, received) = mpsc::channel();
+        let paths = self.walk_roots(roots, self.threads * 4, &results);
+        let policy = self.policy;
+        self.work(paths, results, move |path: PathBuf| {
+            let file = File::open(&path).map_err(|source| ScanError::Io {
+                path: path.clone(),
+                source,
+            })?;
+            let reader = BufReader::with_capacity(verify::BUFFER_LEN, file);
+            match verify::verify_with_policy(reader, policy) {
+                Ok(verified) => Ok((path, verified)),
+                Err(source) => Err(ScanError::Parse { path, source }),
+            }
+        });
+        Scan { results: received }
+    }
+    /// Like [`Scanner::verify`], but looks for pngme payloads with
+    /// [`detect::detect`], seeking past the image data instead of checking
+    /// it. Files without payloads are reported too, with none.
+    #[cfg(feature = "crypto")]
+    pub fn detect<P: Into<PathBuf>>(
+        &self,
+        roots: impl IntoIterator<Item = P>,
+    ) -> Scan<(PathBuf, Vec<Detected>)> {
+        let (results, received) = mpsc::channel();
+        let paths = self.walk_roots(roots, self.threads * 4, &results);
+        self.work(paths, results, |path: PathBuf| {
+            let file = File::open(&path).map_err(|source| ScanError::Io {
+                path: path.clone(),
+                source,
+            })?;
+            match detect::detect(BufReader::new(file)) {
+                Ok(detected) => Ok((path, detected)),
+                Err(source) => Err(ScanError::Parse { path, source }),
+            }
+        });
+        Scan { results: received }
+    }
+    /// Like [`Scanner::verify`], but only lists the chunks of each file with
+    /// [`census::chunk_headers`], for a [`census::Census`] of them.
+    pub fn census<P: Into<PathBuf>>(
+        &self,
+        roots: impl IntoIterator<Item = P>,
+    ) -> Scan<(PathBuf, Vec<(ChunkType, u32)>)> {
+        let (results, received) = mpsc::channel();
+        let paths = self.walk_roots(roots, self.threads * 4, &results);
+        self.work(paths, results, |path: PathBuf| {
+            let file = File::open(&path).map_err(|source| ScanError::Io {
+                path: path.clone(),
+                source,
+            })?;
+            match census::chunk_headers(BufReader::new(file)) {
+                Ok(headers) => Ok((path, headers)),
+                Err(source) => Err(ScanError::Parse { path, source }),
+            }
+        });
+        Scan { results: received }
+    }
+    /// Like [`Scanner::scan`], but decodes each file for its
+    /// [`perceptual::perceptual_hash`] by `method`, to find near-duplicates.
+    pub fn perceptual_hashes<P: Into<PathBuf>>(
+        &self,
+        roots: impl IntoIterator<Item = P>,
+        method: Method,
+    ) -> Scan<(PathBuf, PerceptualHash)> {
+        let (results, received) = mpsc::channel();
+        let paths = self.walk_roots(roots, self.threads * 4, &results);
+        self.work(paths, results, move |path: PathBuf| {
+            let bytes = read_capped(&path).map_err(|source| ScanError::Io {
+                path: path.clone(),
+                source,
+            })?;
+            let png = match png::Png::try_from(bytes.as_slice()) {
+                Ok(png) => png,
+                Err(source) => return Err(ScanError::Parse { path, source }),
+            };
+            match perceptual::perceptual_hash(&png, method) {
+                Ok(hash) => Ok((path, hash)),
+                Err(source) => Err(ScanError::Decode { path, source }),
+            }
+        });
+        Scan { results: received }
+    }
+    /// Hands a whole queue of paths at a time to a [`uring::Reader`], and
+    /// the files read to the workers.
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    fn scan_batched<P: Into<PathBuf>>(&self, roots: impl IntoIterator<Item = P>) -> Scan {
+        let (results, received) = mpsc::channel();
+        let depth = uring::Reader::DEFAULT_DEPTH as usize;
+        let paths = self.walk_roots(roots, depth, &results);
+        let (files, read) = mpsc::sync_channel(self.threads * 4);
+        thread::spawn(move || {
+            let mut reader = uring::Reader::new(depth as u32).ok();
+            let limit = read_limit();
+            while let Ok(first) = paths.recv() {
+                let mut batch = vec![first];
+                batch.extend(paths.try_iter().take(depth - 1));
+                let bytes = match reader.as_mut().map(|r| r.read_files(&batch, limit)) {
+                    Some(Ok(bytes)) => bytes,
+                    // No ring, or a broken one: read file by file from now on.
+                    _ => {
+                        reader = None;
+                        batch.iter().map(|path| read_capped(path)).collect()
+                    }
+                };
+                for file in batch.into_iter().zip(bytes) {
+                    if files.send(file).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        self.work(
+            read,
+            results,
+            |(path, bytes): (PathBuf, io::Result<Vec<u8>>)| report(&path, bytes),
+        );
+        Scan { results: received }
+    }
+    /// Walks `roots` on a thread of its own, queueing up to `queued` files
+    /// found; failures to list a directory go to `results`.
+    fn walk_roots<T: Send + 'static, P: Into<PathBuf>>(
+        &self,
+        roots: impl IntoIterator<Item = P>,
+        queued: usize,
+        results: &Sender<Result<T, ScanError>>,
+    ) -> Receiver<PathBuf> {
+        let roots: Vec<PathBuf> = roots.into_iter().map(Into::into).collect();
+        // Bounded, so the walk stays only a little ahead of the workers.
+        let (paths, queue) = mpsc::sync_channel(queued);
+        let results = results.clone();
+        thread::spawn(move || {
+            for root in roots {
+                let is_dir = fs::metadata(&root).is_ok_and(|m| m.is_dir());
+                if !is_dir {
+                    if paths.send(root).is_err() {
+                        return;
+                    }
+                } else if !walk(&root, &paths, &results) {
+                    return;
+                }
+            }
+        });
+        queue
+    }
+    /// Starts the workers, each running `job` on what it takes from `queue`
+    /// until the queue runs dry or nobody wants the results.
+    fn work<I, T, F>(&self, queue: Receiver<I>, results: Sender<Result<T, ScanError>>, job: F)
+    where
+        I: Send + 'static,
+        T: Send + 'static,
+        F: Fn(I) -> Result<T, ScanError> + Clone + Send + 'static,
+    {
+        let queue = Arc::new(Mutex::new(queue));
+        for _ in 0..self.threads {
+            let queue = Arc::clone(&queue);
+            let results = results.clone();
+            let job = job.clone();
+            thread::spawn(move || loop {
+                let item = queue.lock().expect("no worker panics holding it").recv();
+                let Ok(item) = item else {
+                    return;
+                };
+                if results.send(job(item)).is_err() {
+                    return;
+                }
+            });
+        }
+    }
+}
+
+/// Sends the PNG files under `dir` to `paths`, in name order, and failures
+/// to list a directory to `results`. Returns false once nobody is
+/// listening.
+#[cfg(feature = "fs")]
+fn walk<T>(
+    dir: &Path,
+    paths: &mpsc::SyncSender<PathBuf>,
+    results: &Sender<Result<T, ScanError>>,
+) -> bool {
+    let entries = fs::read_dir(dir).and_then(|entries| entries.collect::<io::Result<Vec<_>>>());
+    let mut entries = match entries {
+        Ok(entries) => entries,
+        Err(source) => {
+            let error = ScanError::Io {
+                path: dir.to_path_buf(),
+                source,
+            };
+            return results.send(Err(error)).is_ok();
+        }
+    };
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        let keep_going = if is_dir {
+            walk(&path, paths, results)
+        } else if is_png_name(&path) && path.is_file() {
+            paths.send(path).is_ok()
+        } else {
+            true
+        };
+        if !keep_going {
+            return false;
+        }
+    }
+    true
+}
+
+#[cfg(feature = "fs")]
+fn is_png_name(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+}
+
+/// The reports of a [`Scanner::scan`] (or [`Scanner::verify`]), as the
+/// workers finish them. Dropping it stops the scan after the files in
+/// progress.
+#[cfg(feature = "fs")]
+pub struct Scan<T = Report> {
+    results: Receiver<Result<T, ScanError>>,
+}
+
+#[cfg(feature = "fs")]
+impl<T> Iterator for Scan<T> {
+    type Item = Result<T, ScanError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.results.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::png::Png;
+    use std::str::FromStr;
+
+    fn testing_png(trailer: &[u8]) -> Vec<u8> {
+        let mut png = Png::from_chunks(vec![Chunk::new(ChunkType::from_str("IEND").unwrap(), &[])]);
+        png.set_trailer(trailer.to_vec());
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_inspect() {
+        let (summary, findings) = inspect(&testing_png(b"")).unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                len: 20,
+                chunks: 1,
+                trailer_len: 0
+            }
+        );
+        assert!(findings.is_empty());
+        let (_, findings) = inspect(&testing_png(b"junk")).unwrap();
+        assert_eq!(findings, [Finding::UnknownTrailer { len: 4 }]);
+        assert!(matches!(inspect(b"GIF89a"), Err(PngError::InvalidHeader)));
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_scan_tree() {
+        let root = std::env::temp_dir().join(format!("pngme-scan-{}", std::process::id()));
+        fs::create_dir_all(root.join("nested")).unwrap();
+        fs::write(root.join("a.png"), testing_png(b"")).unwrap();
+        fs::write(root.join("nested/b.PNG"), testing_png(b"junk")).unwrap();
+        fs::write(root.join("nested/bad.png"), b"GIF89a").unwrap();
+        fs::write(root.join("notes.txt"), b"not scanned").unwrap();
+        let extra = root.join("notes.txt");
+
+        let mut reports = Vec::new();
+        let mut errors = Vec::new();
+        for result in Scanner::new().with_threads(2).scan([root.clone(), extra]) {
+            match result {
+                Ok(report) => reports.push(report),
+                Err(e) => errors.push(e),
+            }
+        }
+        fs::remove_dir_all(&root).unwrap();
+
+        reports.sort_by(|a, b| a.path.cmp(&b.path));
+        let found: Vec<_> = reports.iter().map(|r| r.findings.len()).collect();
+        assert_eq!(reports[0].path, root.join("a.png"));
+        assert_eq!(reports[1].path, root.join("nested/b.PNG"));
+        assert_eq!(found, [0, 1]);
+        // The bad PNG, and the text file named explicitly.
+        assert_eq!(errors.len(), 2);
+        assert!(errors.iter().all(|e| matches!(
+            e,
+            ScanError::Parse {
+                source: PngError::InvalidHeader,
+                ..
+            }
+        )));
+    }
+
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    #[test]
+    fn test_scan_with_io_uring() {
+        let root = std::env::temp_dir().join(format!("pngme-scan-uring-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        for i in 0..300 {
+            let trailer: &[u8] = if i % 3 == 0 { b"junk" } else { b"" };
+            fs::write(root.join(format!("{:03}.png", i)), testing_png(trailer)).unwrap();
+        }
+        fs::write(root.join("bad.png"), b"GIF89a").unwrap();
+
+        let scan = |scanner: Scanner| {
+            let mut reports: Vec<_> = scanner.scan([&root]).map(|r| r.ok()).collect();
+            reports.sort_by(|a, b| {
+                a.as_ref()
+                    .map(|r| &r.path)
+                    .cmp(&b.as_ref().map(|r| &r.path))
+            });
+            reports
+        };
+        let batched = scan(Scanner::new().with_io_uring(true));
+        let plain = scan(Scanner::new());
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(batched.len(), 301);
+        assert_eq!(batched, plain);
+        assert_eq!(
+            batched
+                .iter()
+                .flatten()
+                .filter(|r| !r.findings.is_empty())
+                .count(),
+            100
+        );
+    }
+
+    #[cfg(feature = "fs")]
+    #[test]
+    fn test_verify_tree() {
+        let root = std::env::temp_dir().join(format!("pngme-verify-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.png"), testing_png(b"junk")).unwrap();
+        let mut damaged = testing_png(b"");
+        damaged[8 + 8] ^= 1;
+        fs::write(root.join("b.png"), damaged).unwrap();
+
+        let mut results: Vec<_> = Scanner::new().verify([&root]).collect();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(results.len(), 2);
+        results.sort_by_key(|result| result.is_err());
+        let (path, verified) = results[0].as_ref().unwrap();
+        assert_eq!(*path, root.join("a.png"));
+        assert_eq!(verified.trailer_len, 4);
+        assert!(matches!(
+            results[1],
+            Err(ScanError::Parse {
+                source: PngError::BadChunk { offset: 8, .. },
+                ..
+            })
+        ));
+    }
+
+    #[cfg(all(feature = "fs", feature = "crypto"))]
+    #[test]
+    fn test_detect_tree() {
+        let root = std::env::temp_dir().join(format!("pngme-detect-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("a.png"), testing_png(b"junk")).unwrap();
+        let payload = Envelope::text("hello").as_bytes();
+        let mut png = Png::try_from(testing_png(b"").as_slice()).unwrap();
+        trailer::attach(&mut png, &payload).unwrap();
+        fs::write(root.join("b.png"), png.as_bytes()).unwrap();
+
+        let mut results: Vec<_> = Scanner::new().detect([&root]).map(Result::unwrap).collect();
+        fs::remove_dir_all(&root).unwrap();
+
+        results.sort_by(|a, b| a.0.cmp(&b.0));
+        assert!(results[0].1.is_empty());
+        assert_eq!(results[1].1.len(), 1);
+        assert_eq!(results[1].1[0].len, payload.len());
+    }
+}
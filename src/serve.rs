@@ -0,0 +1,834 @@
+//! `pngme serve`: inspect, encode, decode and strip over HTTP.
+//!
+//! The server speaks just enough HTTP/1.1 for API clients behind a trusted
+//! network or a TLS-terminating proxy: one request per connection, bodies
+//! with a `Content-Length` of at most `--max-upload`, and `POST` forms in
+//! `multipart/form-data` with the image in an `image` field. Images are
+//! read under [`api::limits_for`] the upload size, so payloads can't
+//! decompress or derive keys without bound. Every endpoint but `/health`
+//! needs the API key, which is checked before the body is read, and each
+//! connection has [`TIMEOUT`] to send its request and read the response.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use pngme::chunk_type::ChunkType;
+use pngme::limits;
+use pngme::png::Png;
+
+use crate::api::{self, ApiError, Decoded};
+use crate::args::ServeArgs;
+use crate::commands::{strip_chunks, strippable};
+use crate::exit;
+use crate::json::{self, Value};
+
+#[derive(Debug, Error)]
+pub enum ServeError {
+    #[error("Form field {0:?} is not UTF-8 text")]
+    NotText(String),
+}
+
+/// Connections served at once; more are turned away with a 503.
+const MAX_CONNECTIONS: usize = 16;
+/// Longest request line and headers, together.
+const MAX_HEAD_LEN: u64 = 64 * 1024;
+/// How long a connection may take, from the first byte of its request to
+/// the last of the response, however slowly it sends or reads them.
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+struct Config {
+    api_key: Option<String>,
+    max_upload: usize,
+}
+
+pub fn run(args: ServeArgs) -> Result<()> {
+    let api_key = match (&args.api_key_file, args.no_auth) {
+        (_, true) => None,
+        (Some(path), false) => {
+            let contents = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            Some(contents.lines().next().unwrap_or_default().to_string())
+        }
+        (None, false) => match std::env::var("PNGME_API_KEY") {
+            Ok(key) => Some(key),
+            Err(_) => {
+                bail!("Set PNGME_API_KEY or pass --api-key-file, or --no-auth to serve anyone")
+            }
+        },
+    };
+    if api_key.as_deref() == Some("") {
+        bail!("The API key is empty");
+    }
+    let config = Arc::new(Config {
+        api_key,
+        max_upload: args.max_upload as usize * 1024 * 1024,
+    });
+    limits::set(api::limits_for(config.max_upload));
+    let listener = TcpListener::bind(args.listen)
+        .with_context(|| format!("Failed to listen on {}", args.listen))?;
+    eprintln!("Listening on http://{}", listener.local_addr()?);
+    let active = Arc::new(AtomicUsize::new(0));
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        if active.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+            active.fetch_sub(1, Ordering::SeqCst);
+            let busy = Response::error(503, "Too many requests at once; try again later");
+            let _ = busy.write_to(&stream);
+            continue;
+        }
+        let (config, active) = (Arc::clone(&config), Arc::clone(&active));
+        thread::spawn(move || {
+            serve_connection(stream, &config);
+            active.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+    Ok(())
+}
+
+fn serve_connection(stream: TcpStream, config: &Config) {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "-".to_string(), |addr| addr.to_string());
+    let mut connection = Deadline {
+        stream: &stream,
+        until: Instant::now() + TIMEOUT,
+    };
+    let (line, response) = match read_request(&mut BufReader::new(&mut connection), config) {
+        Ok(request) => {
+            let line = format!("{} {}", request.method, request.path);
+            (line, handle(&request, config))
+        }
+        Err(err) => ("-".to_string(), err),
+    };
+    eprintln!("{} {} {}", peer, line, response.status);
+    let _ = response.write_to(connection);
+}
+
+/// A connection whose reads and writes fail once `until` has passed.
+struct Deadline<'a> {
+    stream: &'a TcpStream,
+    until: Instant,
+}
+
+impl Deadline<'_> {
+    /// Time left, or an error once there's none.
+    fn remaining(&self) -> io::Result<Duration> {
+        let left = self.until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        Ok(left)
+    }
+}
+
+impl Read for Deadline<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(Some(self.remaining()?))?;
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Deadline<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(Some(self.remaining()?))?;
+        self.stream.write(buf)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status: 200,
+            content_type,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+    fn error(status: u16, message: &str) -> Self {
+        let body = format!("{}\n", Value::object([("error", Value::string(message))]));
+        Self {
+            status,
+            ..Self::new("application/json", body)
+        }
+    }
+    fn with_header(mut self, name: &'static str, value: String) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+    fn write_to(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            self.status,
+            reason(self.status),
+            self.content_type,
+            self.body.len()
+        );
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        writer.write_all(head.as_bytes())?;
+        writer.write_all(&self.body)?;
+        writer.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        411 => "Length Required",
+        413 => "Content Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Content",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Reads a request, failing with the response to send instead. Requests
+/// without the API key fail before their body is read.
+fn read_request(reader: &mut impl BufRead, config: &Config) -> Result<Request, Response> {
+    let mut head = reader.take(MAX_HEAD_LEN);
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        match head.read_line(&mut line) {
+            Ok(0) if head.limit() == 0 => {
+                return Err(Response::error(431, "Request headers are too large"))
+            }
+            Ok(0) => return Err(Response::error(400, "Incomplete request")),
+            Ok(_) if !line.ends_with('\n') && head.limit() == 0 => {
+                return Err(Response::error(431, "Request headers are too large"))
+            }
+            Ok(_) => {}
+            Err(_) => return Err(Response::error(400, "Malformed request")),
+        }
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    let reader = head.into_inner();
+    let mut lines = lines.into_iter();
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(path), Some(version)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(Response::error(400, "Malformed request line"));
+    };
+    if !version.starts_with("HTTP/1.") {
+        return Err(Response::error(400, "Only HTTP/1.x is supported"));
+    }
+    let mut headers = Vec::new();
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(Response::error(400, "Malformed header"));
+        };
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.split('?').next().unwrap_or_default().to_string(),
+        headers,
+        body: Vec::new(),
+    };
+    authorize(&request, config)?;
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(Response::error(411, "Send the body with a Content-Length"));
+    }
+    let len = match request.header("Content-Length") {
+        Some(len) => len
+            .parse::<usize>()
+            .map_err(|_| Response::error(400, "Malformed Content-Length"))?,
+        None if request.method == "POST" => {
+            return Err(Response::error(411, "Send the body with a Content-Length"))
+        }
+        None => 0,
+    };
+    if len > config.max_upload {
+        let message = format!("The body is larger than {} bytes", config.max_upload);
+        return Err(Response::error(413, &message));
+    }
+    request.body = vec![0; len];
+    reader
+        .read_exact(&mut request.body)
+        .map_err(|_| Response::error(400, "Incomplete body"))?;
+    Ok(request)
+}
+
+fn handle(request: &Request, config: &Config) -> Response {
+    let route: fn(&Form) -> Result<Response> = match request.path.as_str() {
+        "/health" => return Response::new("text/plain", "ok\n"),
+        "/inspect" => inspect,
+        "/encode" => encode,
+        "/decode" => decode,
+        "/strip" => strip,
+        _ => return Response::error(404, "No such endpoint"),
+    };
+    if let Err(response) = authorize(request, config) {
+        return response;
+    }
+    if request.method != "POST" {
+        return Response::error(405, "Use POST").with_header("Allow", "POST".to_string());
+    }
+    let form = match request.header("Content-Type").and_then(boundary) {
+        Some(boundary) => match Form::parse(&request.body, boundary) {
+            Some(form) => form,
+            None => return Response::error(400, "Malformed multipart/form-data body"),
+        },
+        None => return Response::error(415, "Send a multipart/form-data form"),
+    };
+    match route(&form) {
+        Ok(response) => response,
+        Err(err) => Response::error(status_for(&err), &format!("{:#}", err)),
+    }
+}
+
+/// Fails with a 401 unless `request` is for `/health` or carries the API
+/// key, if one is set.
+fn authorize(request: &Request, config: &Config) -> Result<(), Response> {
+    let Some(key) = &config.api_key else {
+        return Ok(());
+    };
+    if request.path == "/health" {
+        return Ok(());
+    }
+    let given = request
+        .header("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or_else(|| request.header("X-Api-Key"));
+    if !given.is_some_and(|given| constant_time_eq(given.as_bytes(), key.as_bytes())) {
+        return Err(Response::error(401, "Missing or wrong API key")
+            .with_header("WWW-Authenticate", "Bearer".to_string()));
+    }
+    Ok(())
+}
+
+/// The HTTP status for an error, from the exit code the command would have
+/// failed with.
+fn status_for(err: &anyhow::Error) -> u16 {
+    match exit::code_for(err) {
+        exit::BAD_ARGUMENTS => 400,
+        exit::NOT_FOUND => 404,
+        exit::PARSE_ERROR | exit::CRC_FAILURE => 422,
+        exit::AUTH_FAILURE => 403,
+        _ => 500,
+    }
+}
+
+/// Compares without stopping at the first difference, so the time taken
+/// doesn't tell how much of a guessed key is right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn boundary(content_type: &str) -> Option<&str> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    param(params, "boundary").filter(|boundary| !boundary.is_empty())
+}
+
+/// The value of `name` in `key=value; key="value"` parameters.
+fn param<'a>(params: &'a str, name: &str) -> Option<&'a str> {
+    params.split(';').find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// The fields of a `multipart/form-data` body, in order.
+struct Form(Vec<(String, Vec<u8>)>);
+
+impl Form {
+    fn parse(body: &[u8], boundary: &str) -> Option<Self> {
+        let delimiter = format!("--{}", boundary);
+        let start = find(body, delimiter.as_bytes())?;
+        let mut rest = &body[start + delimiter.len()..];
+        let delimiter = format!("\r\n{}", delimiter);
+        let mut fields = Vec::new();
+        while !rest.starts_with(b"--") {
+            rest = rest.strip_prefix(b"\r\n")?;
+            let end = find(rest, b"\r\n\r\n")?;
+            let headers = std::str::from_utf8(&rest[..end]).ok()?;
+            let name = headers.split("\r\n").find_map(|header| {
+                let (key, value) = header.split_once(':')?;
+                key.eq_ignore_ascii_case("Content-Disposition")
+                    .then(|| param(value, "name"))
+                    .flatten()
+            })?;
+            rest = &rest[end + 4..];
+            let end = find(rest, delimiter.as_bytes())?;
+            fields.push((name.to_string(), rest[..end].to_vec()));
+            rest = &rest[end + delimiter.len()..];
+        }
+        Some(Self(fields))
+    }
+    fn bytes(&self, name: &str) -> Option<&[u8]> {
+        self.0
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_slice())
+    }
+    fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a [u8]> + 'a {
+        self.0
+            .iter()
+            .filter(move |(field, _)| field == name)
+            .map(|(_, value)| value.as_slice())
+    }
+    fn text(&self, name: &str) -> Result<Option<String>> {
+        self.bytes(name)
+            .map(|bytes| String::from_utf8(bytes.to_vec()))
+            .transpose()
+            .map_err(|_| ServeError::NotText(name.to_string()).into())
+    }
+    fn image(&self) -> Result<Png> {
+        let bytes = self.bytes("image").ok_or(ApiError::MissingField("image"))?;
+        Png::try_from(bytes).context("Failed to parse the image")
+    }
+    fn chunk_type(&self) -> Result<Option<ChunkType>> {
+        Ok(self
+            .text("chunk_type")?
+            .map(|chunk_type| ChunkType::from_str(&chunk_type))
+            .transpose()?)
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// `image`: the chunks of the image and how many bytes follow `IEND`.
+fn inspect(form: &Form) -> Result<Response> {
+    let png = form.image()?;
+    let body = format!("{}\n", api::inspect(&png));
+    Ok(Response::new("application/json", body))
+}
+
+/// `image`, `chunk_type`, `message` and optionally `name` and `password`
+/// (which encrypts the message with the default settings): the image with
+/// the message added, as `encode` does.
+fn encode(form: &Form) -> Result<Response> {
+    let mut png = form.image()?;
+    let chunk_type = form
+        .chunk_type()?
+        .ok_or(ApiError::MissingField("chunk_type"))?;
+    let message = form
+        .text("message")?
+        .ok_or(ApiError::MissingField("message"))?;
+    let name = form.text("name")?;
+    api::encode(
+        &mut png,
+        chunk_type,
+        &message,
+        name.as_deref(),
+        form.bytes("password"),
+    )?;
+    Ok(Response::new("image/png", png.as_bytes()))
+}
+
+/// `image`, `chunk_type` or `name`, and `password` for protected payloads:
+/// the text or file stored, as `decode` gives it.
+fn decode(form: &Form) -> Result<Response> {
+    let png = form.image()?;
+    let chunk_type = form.chunk_type()?;
+    let name = form.text("name")?;
+    let decoded = api::decode(
+        &png,
+        chunk_type.as_ref(),
+        name.as_deref(),
+        form.bytes("password"),
+    )?;
+    Ok(match decoded {
+        Decoded::Text(text) => Response::new("text/plain; charset=utf-8", text),
+        Decoded::File { name, data } => {
+            let disposition = format!("attachment; filename={}", json::string(&name));
+            Response::new("application/octet-stream", data)
+                .with_header("Content-Disposition", disposition)
+        }
+    })
+}
+
+/// `image` and one or more `chunk_type`: the image without the chunks of
+/// those types, with how many there were in `X-Pngme-Removed`.
+fn strip(form: &Form) -> Result<Response> {
+    let mut png = form.image()?;
+    let chunk_types = form
+        .all("chunk_type")
+        .map(|chunk_type| String::from_utf8_lossy(chunk_type).into_owned())
+        .collect::<Vec<_>>();
+    if chunk_types.is_empty() {
+        return Err(ApiError::MissingField("chunk_type").into());
+    }
+    let chunk_types =
+        strippable(&chunk_types).map_err(|e| ApiError::Invalid(format!("{:#}", e)))?;
+    let removed = strip_chunks(&mut png, &chunk_types);
+    Ok(Response::new("image/png", png.as_bytes())
+        .with_header("X-Pngme-Removed", removed.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pngme::chunk::Chunk;
+    use pngme::compression::{Algorithm, Compression};
+    use pngme::crypto::{Encryption, Kdf};
+    use pngme::envelope::Envelope;
+
+    const BOUNDARY: &str = "XyZ";
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn testing_png() -> Vec<u8> {
+        Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Comment\0hi"),
+            chunk("IDAT", &[1; 20]),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes()
+    }
+
+    fn multipart(fields: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut body = b"preamble\r\n".to_vec();
+        for (name, value) in fields {
+            body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+            let disposition = format!(
+                "Content-Disposition: form-data; name=\"{}\"; filename=\"x\"\r\n\r\n",
+                name
+            );
+            body.extend_from_slice(disposition.as_bytes());
+            body.extend_from_slice(value);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn post(path: &str, fields: &[(&str, &[u8])]) -> Request {
+        Request {
+            method: "POST".to_string(),
+            path: path.to_string(),
+            headers: vec![
+                (
+                    "content-type".to_string(),
+                    format!("multipart/form-data; boundary=\"{}\"", BOUNDARY),
+                ),
+                ("Authorization".to_string(), "Bearer secret".to_string()),
+            ],
+            body: multipart(fields),
+        }
+    }
+
+    fn config() -> Config {
+        Config {
+            api_key: Some("secret".to_string()),
+            max_upload: 1024,
+        }
+    }
+
+    #[test]
+    fn test_read_request() {
+        let open = |max_upload| Config {
+            api_key: None,
+            max_upload,
+        };
+        let raw = b"POST /strip?x=1 HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello";
+        let request = read_request(&mut &raw[..], &open(1024)).ok().unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.path.as_str()),
+            ("POST", "/strip")
+        );
+        assert_eq!(request.header("host"), Some("a"));
+        assert_eq!(request.body, b"hello");
+
+        let status = |raw: &[u8], max| {
+            let config = open(max);
+            read_request(&mut &raw[..], &config).err().unwrap().status
+        };
+        assert_eq!(status(raw, 4), 413);
+        assert_eq!(status(b"POST / HTTP/1.1\r\n\r\n", 10), 411);
+        assert_eq!(
+            status(b"POST / HTTP/1.1\r\nContent-Length: 9\r\n\r\nhi", 10),
+            400
+        );
+        assert_eq!(status(b"GARBAGE\r\n\r\n", 10), 400);
+        let long = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(70_000));
+        assert_eq!(status(long.as_bytes(), 10), 431);
+
+        // Without the key, the body isn't waited for.
+        let unauthorized = b"POST /decode HTTP/1.1\r\nContent-Length: 1000\r\n\r\n";
+        let response = read_request(&mut &unauthorized[..], &config());
+        assert_eq!(response.err().unwrap().status, 401);
+        let keyed = b"POST /decode HTTP/1.1\r\nX-Api-Key: secret\r\nContent-Length: 1000\r\n\r\n";
+        let response = read_request(&mut &keyed[..], &config());
+        assert_eq!(response.err().unwrap().status, 400);
+        let health = b"GET /health HTTP/1.1\r\n\r\n";
+        assert!(read_request(&mut &health[..], &config()).is_ok());
+    }
+
+    #[test]
+    fn test_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let start = Instant::now();
+        let mut slow = Deadline {
+            stream: &stream,
+            until: start + Duration::from_millis(100),
+        };
+        assert!(slow.read(&mut [0; 1]).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        (&client).write_all(b"late").unwrap();
+        let mut late = Deadline {
+            stream: &stream,
+            until: Instant::now(),
+        };
+        let err = late.read(&mut [0; 4]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(
+            late.write(b"x").unwrap_err().kind(),
+            io::ErrorKind::TimedOut
+        );
+    }
+
+    #[test]
+    fn test_form() {
+        let body = multipart(&[("image", b"\r\n--Xy\r\n"), ("chunk_type", b"tEXt")]);
+        let form = Form::parse(&body, BOUNDARY).unwrap();
+        assert_eq!(form.bytes("image"), Some(&b"\r\n--Xy\r\n"[..]));
+        assert_eq!(form.text("chunk_type").unwrap().as_deref(), Some("tEXt"));
+        assert!(form.bytes("message").is_none());
+        assert!(Form::parse(b"--XyZ\r\nno end", BOUNDARY).is_none());
+        assert_eq!(
+            boundary("multipart/form-data; charset=utf-8; boundary=abc"),
+            Some("abc")
+        );
+        assert_eq!(boundary("application/json"), None);
+    }
+
+    #[test]
+    fn test_auth() {
+        let mut request = post("/inspect", &[("image", &testing_png())]);
+        assert_eq!(handle(&request, &config()).status, 200);
+        request.headers[1].1 = "Bearer wrong!".to_string();
+        assert_eq!(handle(&request, &config()).status, 401);
+        request.headers[1] = ("X-Api-Key".to_string(), "secret".to_string());
+        assert_eq!(handle(&request, &config()).status, 200);
+        request.headers.pop();
+        assert_eq!(handle(&request, &config()).status, 401);
+        let open = Config {
+            api_key: None,
+            ..config()
+        };
+        assert_eq!(handle(&request, &open).status, 200);
+        request.path = "/health".to_string();
+        assert_eq!(handle(&request, &config()).status, 200);
+        request.path = "/nope".to_string();
+        assert_eq!(handle(&request, &config()).status, 404);
+    }
+
+    #[test]
+    fn test_endpoints() {
+        let image = testing_png();
+        let response = handle(&post("/inspect", &[("image", &image)]), &config());
+        let body = String::from_utf8(response.body).unwrap();
+        assert!(body.starts_with("{\"chunks\":[{\"type\":\"IHDR\",\"length\":13,"));
+        assert!(body.ends_with("\"trailer_length\":0}\n"));
+
+        let fields: [(&str, &[u8]); 5] = [
+            ("image", &image),
+            ("chunk_type", b"ruSt"),
+            ("message", b"hello"),
+            ("name", b"greeting"),
+            ("password", b"hunter2"),
+        ];
+        let encoded = handle(&post("/encode", &fields), &config());
+        assert_eq!((encoded.status, encoded.content_type), (200, "image/png"));
+        let decode = |fields: &[(&str, &[u8])]| handle(&post("/decode", fields), &config());
+        let decoded = decode(&[
+            ("image", &encoded.body),
+            ("name", b"greeting"),
+            ("password", b"hunter2"),
+        ]);
+        assert_eq!(
+            (decoded.status, decoded.body.as_slice()),
+            (200, &b"hello"[..])
+        );
+        let missing = decode(&[("image", &encoded.body), ("name", b"greeting")]);
+        assert_eq!(missing.status, 403);
+        let wrong = decode(&[
+            ("image", &encoded.body),
+            ("name", b"greeting"),
+            ("password", b"hunter3"),
+        ]);
+        assert_eq!(wrong.status, 403);
+        let raw = decode(&[("image", &image), ("chunk_type", b"tEXt")]);
+        assert_eq!(raw.body, b"Comment\0hi");
+        assert_eq!(
+            decode(&[("image", &image), ("chunk_type", b"ruSt")]).status,
+            404
+        );
+        assert_eq!(decode(&[("image", &image)]).status, 400);
+        assert_eq!(
+            decode(&[("image", b"GIF89a"), ("chunk_type", b"ruSt")]).status,
+            422
+        );
+
+        let stripped = handle(
+            &post(
+                "/strip",
+                &[
+                    ("image", &encoded.body),
+                    ("chunk_type", b"ruSt"),
+                    ("chunk_type", b"tEXt"),
+                ],
+            ),
+            &config(),
+        );
+        assert_eq!(stripped.status, 200);
+        assert_eq!(stripped.headers, [("X-Pngme-Removed", "2".to_string())]);
+        let png = Png::try_from(stripped.body.as_slice()).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        let critical = handle(
+            &post("/strip", &[("image", &image), ("chunk_type", b"IDAT")]),
+            &config(),
+        );
+        assert_eq!(critical.status, 400);
+    }
+
+    #[test]
+    fn test_decompression_bomb() {
+        limits::set(api::limits_for(64 * 1024));
+        let bomb = Envelope::text(&"0".repeat(2 << 20))
+            .with_compression(Compression::new(Algorithm::Deflate))
+            .as_bytes();
+        let mut png = Png::try_from(testing_png().as_slice()).unwrap();
+        png.append_chunk(chunk("ruSt", &bomb));
+        let image = png.as_bytes();
+        assert!(image.len() < 64 * 1024);
+        let fields: [(&str, &[u8]); 2] = [("image", &image), ("chunk_type", b"ruSt")];
+        assert_eq!(handle(&post("/decode", &fields), &config()).status, 422);
+    }
+
+    /// The bytes `kdf` is stored as.
+    fn kdf_bytes(kdf: Kdf) -> Vec<u8> {
+        match kdf {
+            Kdf::Pbkdf2Sha256 { iterations } => [&[1][..], &iterations.to_be_bytes()].concat(),
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => [
+                &[2][..],
+                &memory_kib.to_be_bytes(),
+                &iterations.to_be_bytes(),
+                &parallelism.to_be_bytes(),
+            ]
+            .concat(),
+        }
+    }
+
+    #[test]
+    fn test_kdf_cost() {
+        limits::set(api::limits_for(64 * 1024));
+        let argon2id = |memory_kib| Kdf::Argon2id {
+            memory_kib,
+            iterations: 1,
+            parallelism: 1,
+        };
+        let pbkdf2 = |iterations| Kdf::Pbkdf2Sha256 { iterations };
+        // Payloads sealed cheaply, then made to ask for far more: the key
+        // is never derived, so the tag needn't match.
+        for (cheap, costly) in [
+            (argon2id(8), argon2id(Kdf::MAX_MEMORY_KIB)),
+            (pbkdf2(1234), pbkdf2(Kdf::MAX_PBKDF2_ITERATIONS)),
+        ] {
+            let encryption = Encryption {
+                kdf: cheap,
+                ..Encryption::default()
+            };
+            let mut sealed = Envelope::text("hi")
+                .encrypt(b"hunter2", &encryption)
+                .unwrap()
+                .as_bytes();
+            let (from, to) = (kdf_bytes(cheap), kdf_bytes(costly));
+            let at = sealed.windows(from.len()).position(|w| w == from).unwrap();
+            sealed[at..at + to.len()].copy_from_slice(&to);
+            let mut png = Png::try_from(testing_png().as_slice()).unwrap();
+            png.append_chunk(chunk("ruSt", &sealed));
+            let image = png.as_bytes();
+            let fields: [(&str, &[u8]); 3] = [
+                ("image", &image),
+                ("chunk_type", b"ruSt"),
+                ("password", b"hunter2"),
+            ];
+            let response = handle(&post("/decode", &fields), &config());
+            let body = String::from_utf8(response.body).unwrap();
+            assert_eq!(response.status, 422, "{}", body);
+            assert!(body.contains("key derivation"), "{}", body);
+        }
+    }
+
+    #[test]
+    fn test_response() {
+        let mut written = Vec::new();
+        Response::error(404, "No \"such\" thing")
+            .write_to(&mut written)
+            .unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(written.ends_with("\r\n\r\n{\"error\":\"No \\\"such\\\" thing\"}\n"));
+    }
+}
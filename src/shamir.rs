@@ -0,0 +1,176 @@
+//! Shamir secret sharing over GF(256): a secret is split into `n` shares so
+//! that any `k` of them recover it, while fewer reveal nothing about it.
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ShamirError {
+    #[error("Invalid threshold {threshold} for {shares} shares (expected 2 <= threshold <= shares <= 255)")]
+    InvalidThreshold { threshold: u8, shares: u8 },
+    #[error("Need at least {needed} shares, got {got}")]
+    NotEnoughShares { needed: u8, got: usize },
+    #[error("Share {0} was given more than once")]
+    DuplicateShare(u8),
+    #[error("Shares have different lengths")]
+    LengthMismatch,
+}
+
+/// One share: the value at `x` of a random polynomial per secret byte,
+/// whose constant term is that byte.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Share {
+    pub x: u8,
+    pub y: Vec<u8>,
+}
+
+/// Splits `secret` into `shares` shares, any `threshold` of which recover
+/// it with [`combine`].
+pub fn split(secret: &[u8], threshold: u8, shares: u8) -> Result<Vec<Share>, ShamirError> {
+    if threshold < 2 || threshold > shares {
+        return Err(ShamirError::InvalidThreshold { threshold, shares });
+    }
+    let mut result: Vec<Share> = (1..=shares)
+        .map(|x| Share {
+            x,
+            y: Vec::with_capacity(secret.len()),
+        })
+        .collect();
+    let mut coefficients = vec![0u8; threshold as usize];
+    for &byte in secret {
+        coefficients[0] = byte;
+        OsRng.fill_bytes(&mut coefficients[1..]);
+        for share in &mut result {
+            // Horner's rule, highest degree first.
+            let y = coefficients
+                .iter()
+                .rev()
+                .fold(0, |acc, &c| gf_mul(acc, share.x) ^ c);
+            share.y.push(y);
+        }
+    }
+    Ok(result)
+}
+
+/// Recovers the secret from `threshold` or more distinct shares. With fewer
+/// (or unrelated) shares the result is garbage rather than an error, which
+/// is inherent to the scheme.
+pub fn combine(shares: &[Share], threshold: u8) -> Result<Vec<u8>, ShamirError> {
+    if shares.len() < threshold as usize {
+        return Err(ShamirError::NotEnoughShares {
+            needed: threshold,
+            got: shares.len(),
+        });
+    }
+    let shares = &shares[..threshold as usize];
+    for (i, share) in shares.iter().enumerate() {
+        if share.x == 0 || shares[..i].iter().any(|s| s.x == share.x) {
+            return Err(ShamirError::DuplicateShare(share.x));
+        }
+    }
+    let len = shares[0].y.len();
+    if shares.iter().any(|s| s.y.len() != len) {
+        return Err(ShamirError::LengthMismatch);
+    }
+    // Lagrange basis polynomials evaluated at x = 0.
+    let basis: Vec<u8> = shares
+        .iter()
+        .map(|si| {
+            shares
+                .iter()
+                .filter(|sj| sj.x != si.x)
+                .fold(1, |acc, sj| gf_mul(acc, gf_div(sj.x, sj.x ^ si.x)))
+        })
+        .collect();
+    Ok((0..len)
+        .map(|i| {
+            shares
+                .iter()
+                .zip(&basis)
+                .fold(0, |acc, (s, &b)| acc ^ gf_mul(s.y[i], b))
+        })
+        .collect())
+}
+
+/// Multiplication in GF(256) with the AES polynomial x^8 + x^4 + x^3 + x + 1.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+fn gf_inv(a: u8) -> u8 {
+    // a^254 = a^-1, since the multiplicative group has order 255.
+    let mut result = 1;
+    let mut base = a;
+    let mut exponent = 254u8;
+    while exponent != 0 {
+        if exponent & 1 != 0 {
+            result = gf_mul(result, base);
+        }
+        base = gf_mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+fn gf_div(a: u8, b: u8) -> u8 {
+    gf_mul(a, gf_inv(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field() {
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+        for a in 1..=255 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+
+    #[test]
+    fn test_any_threshold_subset_recovers() {
+        let secret = b"This is where your secret message will be!";
+        let shares = split(secret, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+        for subset in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let picked: Vec<Share> = subset.iter().map(|&i| shares[i].clone()).collect();
+            assert_eq!(combine(&picked, 3).unwrap(), secret);
+        }
+    }
+
+    #[test]
+    fn test_too_few_shares() {
+        let shares = split(b"secret", 3, 5).unwrap();
+        assert!(matches!(
+            combine(&shares[..2], 3),
+            Err(ShamirError::NotEnoughShares { needed: 3, got: 2 })
+        ));
+        let wrong = combine(&[shares[0].clone(), shares[1].clone()], 2).unwrap();
+        assert_ne!(wrong, b"secret");
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        assert!(split(b"s", 1, 3).is_err());
+        assert!(split(b"s", 4, 3).is_err());
+        let shares = split(b"s", 2, 2).unwrap();
+        assert!(matches!(
+            combine(&[shares[0].clone(), shares[0].clone()], 2),
+            Err(ShamirError::DuplicateShare(1))
+        ));
+    }
+}
@@ -0,0 +1,182 @@
+//! Payloads hidden without any fixed marker: the chunk type, its position
+//! and the encryption key are all derived from the password, and the chunk
+//! data is indistinguishable from random bytes. Without the password there
+//! is nothing telling the chunk apart from any other private chunk.
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::crypto::{Cipher, CryptoError, Kdf};
+use crate::png::Png;
+
+/// Everything derived from a stealth password.
+pub struct StealthKey {
+    chunk_type: ChunkType,
+    position: u64,
+    key: Zeroizing<[u8; 32]>,
+}
+
+impl StealthKey {
+    /// Nothing derived from the password can be stored, so the salt is
+    /// fixed: the password alone must be enough to find the payload again.
+    const SALT: &'static [u8; 16] = b"pngme stealth v1";
+    const CIPHER: Cipher = Cipher::Aes256Gcm;
+
+    pub fn derive(password: &[u8], kdf: Kdf) -> Result<Self, CryptoError> {
+        let master = kdf.derive(password, Self::SALT)?;
+        let chunk_type = {
+            let b = Self::expand(&master, b"chunk type");
+            // Ancillary, private, reserved bit clear, safe to copy: the
+            // shape of any application's private chunk, e.g. "abCd".
+            let code = [
+                b'a' + b[0] % 26,
+                b'a' + b[1] % 26,
+                b'A' + b[2] % 26,
+                b'a' + b[3] % 26,
+            ];
+            ChunkType::try_from(code).expect("letters only")
+        };
+        let position = {
+            let b = Self::expand(&master, b"position");
+            u64::from_be_bytes(b[..8].try_into().expect("8 bytes"))
+        };
+        Ok(Self {
+            chunk_type,
+            position,
+            key: Zeroizing::new(Self::expand(&master, b"key")),
+        })
+    }
+    fn expand(master: &[u8; 32], label: &[u8]) -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(master).expect("any key length");
+        mac.update(label);
+        mac.finalize().into_bytes().into()
+    }
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+    /// `nonce | ciphertext`, with no readable header.
+    pub fn seal(&self, data: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; Cipher::NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&Self::CIPHER.encrypt(&self.key, &nonce, &[], data));
+        sealed
+    }
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if sealed.len() < Cipher::NONCE_LEN {
+            return Err(CryptoError::DecryptionFailed);
+        }
+        let (nonce, ciphertext) = sealed.split_at(Cipher::NONCE_LEN);
+        Self::CIPHER.decrypt(&self.key, nonce, &[], ciphertext)
+    }
+    /// Stores `sealed` in the derived chunk type, replacing an earlier
+    /// payload hidden with the same password (but not other chunks that
+    /// happen to have that type), at a derived position among
+    /// those where an ancillary chunk is valid: after `IHDR` and not between
+    /// two `IDAT` chunks.
+    pub fn insert(&self, png: &mut Png, sealed: &[u8]) {
+        while let Some(i) = self.position_in(png) {
+            png.remove_chunk_at(i);
+        }
+        let chunks = png.chunks();
+        let is_idat = |i: usize| chunks[i].chunk_type().to_string() == "IDAT";
+        let end = match chunks.last() {
+            Some(last) if last.chunk_type().to_string() == "IEND" => chunks.len() - 1,
+            _ => chunks.len(),
+        };
+        let slots: Vec<usize> = (1.min(end)..=end)
+            .filter(|&i| !(i > 0 && i < chunks.len() && is_idat(i - 1) && is_idat(i)))
+            .collect();
+        let index = slots[(self.position % slots.len() as u64) as usize];
+        png.insert_chunk_at(index, Chunk::new(self.chunk_type.clone(), sealed));
+    }
+    /// The hidden chunk, if this password hid one in `png`.
+    pub fn find<'a>(&self, png: &'a Png) -> Option<&'a Chunk> {
+        self.position_in(png).map(|i| &png.chunks()[i])
+    }
+    /// The first chunk of the derived type that opens under the key.
+    fn position_in(&self, png: &Png) -> Option<usize> {
+        png.chunks()
+            .iter()
+            .position(|c| *c.chunk_type() == self.chunk_type && self.open(c.data()).is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_kdf() -> Kdf {
+        Kdf::Pbkdf2Sha256 { iterations: 10 }
+    }
+
+    fn testing_png() -> Png {
+        let chunk = |t: &str| Chunk::new(ChunkType::from_str(t).unwrap(), &[1, 2, 3]);
+        Png::from_chunks(["IHDR", "IDAT", "IDAT", "IDAT", "IEND"].map(chunk).to_vec())
+    }
+
+    #[test]
+    fn test_derivation_is_deterministic() {
+        let a = StealthKey::derive(b"hunter2", testing_kdf()).unwrap();
+        let b = StealthKey::derive(b"hunter2", testing_kdf()).unwrap();
+        let c = StealthKey::derive(b"letmein", testing_kdf()).unwrap();
+        assert_eq!(a.chunk_type(), b.chunk_type());
+        assert_ne!(a.key.as_ref(), c.key.as_ref());
+        let chunk_type = a.chunk_type();
+        assert!(!chunk_type.is_critical() && !chunk_type.is_public());
+        assert!(chunk_type.is_reserved_bit_valid() && chunk_type.is_safe_to_copy());
+    }
+
+    #[test]
+    fn test_hide_and_find() {
+        let key = StealthKey::derive(b"hunter2", testing_kdf()).unwrap();
+        let mut png = testing_png();
+        let sealed = key.seal(b"secret");
+        assert!(!sealed.windows(6).any(|w| w == b"secret"));
+        key.insert(&mut png, &sealed);
+        key.insert(&mut png, &key.seal(b"newer secret"));
+        assert_eq!(png.chunks().len(), 6);
+        let types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        let at = types
+            .iter()
+            .position(|t| t == &key.chunk_type().to_string())
+            .unwrap();
+        assert!(at == 1 || at == 4, "{:?}", types);
+
+        let found = key.find(&png).unwrap();
+        assert_eq!(key.open(found.data()).unwrap(), b"newer secret");
+        let other = StealthKey::derive(b"letmein", testing_kdf()).unwrap();
+        assert!(other.open(found.data()).is_err());
+    }
+
+    #[test]
+    fn test_insert_keeps_foreign_chunks() {
+        let key = StealthKey::derive(b"hunter2", testing_kdf()).unwrap();
+        let mut png = testing_png();
+        let foreign = b"some application's data";
+        png.insert_chunk_at(1, Chunk::new(key.chunk_type().clone(), foreign));
+        key.insert(&mut png, &key.seal(b"secret"));
+        key.insert(&mut png, &key.seal(b"newer secret"));
+        assert_eq!(png.chunks().len(), 7);
+        let same_type: Vec<&[u8]> = png
+            .chunks()
+            .iter()
+            .filter(|c| c.chunk_type() == key.chunk_type())
+            .map(Chunk::data)
+            .collect();
+        assert_eq!(same_type.len(), 2);
+        assert!(same_type.contains(&&foreign[..]));
+        let found = key.find(&png).unwrap();
+        assert_eq!(key.open(found.data()).unwrap(), b"newer secret");
+    }
+}
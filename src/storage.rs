@@ -0,0 +1,254 @@
+//! Listing, reading and rewriting PNG files where large corpora live.
+//!
+//! [`Storage`] is what batch commands need from a store: listing the
+//! objects under a prefix, reading one, and writing one back only if
+//! nobody changed it in the meantime. [`Directory`] serves a local
+//! directory; with the `s3` feature, `s3::S3` serves buckets, so the same
+//! code runs against either.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use thiserror::Error;
+
+#[cfg(feature = "s3")]
+use crate::remote::RemoteError;
+#[cfg(feature = "s3")]
+use crate::s3::S3;
+
+#[derive(Debug, Error)]
+pub enum StorageError {
+    #[error(
+        "Unsupported storage location {0}: expected s3://BUCKET/PREFIX or gs://BUCKET/PREFIX \
+         (with the s3 feature) or a directory"
+    )]
+    BadLocation(String),
+    #[error("{0} is not set")]
+    MissingCredentials(&'static str),
+    #[error("{0} not found")]
+    NotFound(String),
+    #[error("{0} was changed by someone else since it was read")]
+    Changed(String),
+    #[error("{name} is larger than {max_len} bytes")]
+    TooLarge { name: String, max_len: usize },
+    #[error("{name}: the store answered {status}: {message}")]
+    Status {
+        name: String,
+        status: u16,
+        message: String,
+    },
+    #[error("Unexpected answer from the store for {0}")]
+    BadResponse(String),
+    #[cfg(feature = "s3")]
+    #[error(transparent)]
+    Remote(#[from] RemoteError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// An object in a store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Object {
+    pub key: String,
+    pub len: u64,
+    /// Changes whenever the contents do; [`Storage::put`] takes it to
+    /// refuse overwriting a newer version.
+    pub etag: String,
+}
+
+pub trait Storage: Send + Sync {
+    /// The objects whose keys start with `prefix`, in key order.
+    fn list(&self, prefix: &str) -> Result<Vec<Object>, StorageError>;
+    /// The contents of `key`, failing on more than `max_len` bytes.
+    fn get(&self, key: &str, max_len: usize) -> Result<Vec<u8>, StorageError>;
+    /// Replaces the contents of `key` with `bytes`. With `etag`, fails
+    /// with [`StorageError::Changed`] unless the object still has it.
+    fn put(&self, key: &str, bytes: &[u8], etag: Option<&str>) -> Result<(), StorageError>;
+    /// `key` as users know it, for messages.
+    fn name(&self, key: &str) -> String;
+}
+
+/// Whether `location` names a bucket rather than a local path.
+pub fn is_location(location: &str) -> bool {
+    location.starts_with("s3://") || location.starts_with("gs://")
+}
+
+/// The store `location` names and the prefix in it: with the `s3` feature,
+/// `s3://BUCKET/PREFIX` and `gs://BUCKET/PREFIX` with credentials from the
+/// environment (see `S3::from_env`); anything else a local directory.
+pub fn open(location: &str) -> Result<(Box<dyn Storage>, String), StorageError> {
+    let Some((scheme, rest)) = location.split_once("://") else {
+        return Ok((Box::new(Directory::new(location)), String::new()));
+    };
+    let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+    if bucket.is_empty() {
+        return Err(StorageError::BadLocation(location.to_string()));
+    }
+    match bucket_store(scheme, bucket)? {
+        Some(store) => Ok((store, prefix.to_string())),
+        None => Err(StorageError::BadLocation(location.to_string())),
+    }
+}
+
+#[cfg(feature = "s3")]
+fn bucket_store(scheme: &str, bucket: &str) -> Result<Option<Box<dyn Storage>>, StorageError> {
+    Ok(Some(match scheme {
+        "s3" => Box::new(S3::from_env(bucket)?),
+        "gs" => Box::new(
+            S3::from_env(bucket)?
+                .with_endpoint(S3::GCS_ENDPOINT)
+                .with_region("auto"),
+        ),
+        _ => return Ok(None),
+    }))
+}
+
+#[cfg(not(feature = "s3"))]
+fn bucket_store(_scheme: &str, _bucket: &str) -> Result<Option<Box<dyn Storage>>, StorageError> {
+    Ok(None)
+}
+
+/// A local directory, with keys the `/`-separated paths of the files in
+/// it.
+#[derive(Clone, Debug)]
+pub struct Directory {
+    root: PathBuf,
+}
+
+impl Directory {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+    fn walk(&self, dir: &Path, key: &str, objects: &mut Vec<Object>) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name();
+            let key = match key {
+                "" => name.to_string_lossy().into_owned(),
+                _ => format!("{}/{}", key, name.to_string_lossy()),
+            };
+            // Symbolic links to directories aren't followed, as in scans.
+            let meta = entry.metadata()?;
+            if meta.is_dir() {
+                self.walk(&entry.path(), &key, objects)?;
+            } else if meta.is_file() {
+                objects.push(Object {
+                    key,
+                    len: meta.len(),
+                    etag: etag(&meta),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Files have no tags; their length and modification time stand in.
+fn etag(meta: &fs::Metadata) -> String {
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("{:x}-{:x}", meta.len(), modified.as_nanos())
+}
+
+impl Storage for Directory {
+    fn list(&self, prefix: &str) -> Result<Vec<Object>, StorageError> {
+        let mut objects = Vec::new();
+        self.walk(&self.root, "", &mut objects)?;
+        objects.retain(|object| object.key.starts_with(prefix));
+        Ok(objects)
+    }
+    fn get(&self, key: &str, max_len: usize) -> Result<Vec<u8>, StorageError> {
+        let bytes = match fs::read(self.path(key)) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(StorageError::NotFound(self.name(key)))
+            }
+            result => result?,
+        };
+        if bytes.len() > max_len {
+            return Err(StorageError::TooLarge {
+                name: self.name(key),
+                max_len,
+            });
+        }
+        Ok(bytes)
+    }
+    fn put(&self, key: &str, bytes: &[u8], etag: Option<&str>) -> Result<(), StorageError> {
+        let path = self.path(key);
+        if let Some(expected) = etag {
+            let current = fs::metadata(&path).map(|meta| self::etag(&meta));
+            if current.ok().as_deref() != Some(expected) {
+                return Err(StorageError::Changed(self.name(key)));
+            }
+        }
+        // A crash leaves either the old file or the new one.
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!(".{}.pngme-tmp", name));
+        fs::write(&temp, bytes)
+            .and_then(|()| fs::rename(&temp, &path))
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&temp);
+            })?;
+        Ok(())
+    }
+    fn name(&self, key: &str) -> String {
+        self.path(key).display().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open() {
+        assert!(is_location("s3://bucket/cats/"));
+        assert!(is_location("gs://bucket"));
+        assert!(!is_location("photos/"));
+        assert!(matches!(
+            open("ftp://bucket/x"),
+            Err(StorageError::BadLocation(_))
+        ));
+        assert!(matches!(open("s3:///x"), Err(StorageError::BadLocation(_))));
+        let (store, prefix) = open("photos").unwrap();
+        let path = Path::new("photos").join("a.png");
+        assert_eq!(store.name("a.png"), path.display().to_string());
+        assert_eq!(prefix, "");
+    }
+
+    #[test]
+    fn test_directory() {
+        let root = std::env::temp_dir().join(format!("pngme-storage-{}", std::process::id()));
+        fs::create_dir_all(root.join("cats")).unwrap();
+        fs::write(root.join("cats/a.png"), b"one").unwrap();
+        fs::write(root.join("dog.png"), b"two").unwrap();
+        let store = Directory::new(&root);
+
+        let keys: Vec<_> = store.list("").unwrap().into_iter().map(|o| o.key).collect();
+        let cats = store.list("cats/").unwrap();
+        let read = store.get("cats/a.png", 3);
+        let too_large = store.get("cats/a.png", 2);
+        let missing = store.get("cats/b.png", 3);
+        let put = store.put("cats/a.png", b"three", Some(&cats[0].etag));
+        let changed = store.put("cats/a.png", b"four", Some(&cats[0].etag));
+        let contents = fs::read(root.join("cats/a.png")).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(keys, ["cats/a.png", "dog.png"]);
+        assert_eq!(cats.len(), 1);
+        assert_eq!(cats[0].len, 3);
+        assert_eq!(read.unwrap(), b"one");
+        assert!(matches!(too_large, Err(StorageError::TooLarge { .. })));
+        assert!(matches!(missing, Err(StorageError::NotFound(_))));
+        put.unwrap();
+        assert!(matches!(changed, Err(StorageError::Changed(_))));
+        assert_eq!(contents, b"three");
+    }
+}
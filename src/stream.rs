@@ -0,0 +1,269 @@
+//! Reading and adding chunks in PNG streams another crate decodes or
+//! encodes.
+//!
+//! Apps that get their pixels from the `png` crate can leave it at that
+//! and use this crate for chunks. The `png` crate's `Decoder` reads from
+//! any `io::Read` and its `Encoder` writes to any `io::Write`, so it works
+//! with the adapters here without either crate knowing about the other:
+//! [`Tap`] goes between a file and a decoder and keeps the ancillary chunks
+//! that pass by, and [`Inject`] goes between an encoder and a file and
+//! slips chunks into what it writes.
+
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::decoder::{Decoder, Event};
+use crate::png::{Png, PngError};
+
+type Filter = Box<dyn Fn(&ChunkType) -> bool + Send>;
+
+fn invalid(err: PngError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+/// A reader that passes a PNG stream on unchanged, keeping copies of some
+/// of its chunks. A stream that isn't a PNG file fails to read.
+pub struct Tap<R> {
+    reader: R,
+    decoder: Decoder,
+    filter: Filter,
+    chunks: Tapped,
+}
+
+impl<R: Read> Tap<R> {
+    /// Keeps the ancillary chunks: everything but `IHDR`, `PLTE`, `IDAT`
+    /// and `IEND`.
+    pub fn new(reader: R) -> Self {
+        Self::with_filter(reader, |chunk_type| !chunk_type.is_critical())
+    }
+    /// Keeps the chunks whose type `filter` accepts.
+    pub fn with_filter(reader: R, filter: impl Fn(&ChunkType) -> bool + Send + 'static) -> Self {
+        Self {
+            reader,
+            decoder: Decoder::new(),
+            filter: Box::new(filter),
+            chunks: Tapped::default(),
+        }
+    }
+    /// A handle on the chunks kept, which stays usable after the tap has
+    /// been handed to a decoder.
+    pub fn chunks(&self) -> Tapped {
+        self.chunks.clone()
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for Tap<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if self.decoder.is_done() {
+            return Ok(read);
+        }
+        if read == 0 {
+            self.decoder.finish().map_err(invalid)?;
+            return Ok(0);
+        }
+        let events = self.decoder.feed(&buf[..read]).map_err(invalid)?;
+        let mut chunks = self.chunks.0.lock().expect("no thread panics holding it");
+        for event in events {
+            if let Event::Chunk { chunk, .. } = event {
+                if (self.filter)(chunk.chunk_type()) {
+                    chunks.push(chunk);
+                }
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// The chunks a [`Tap`] has kept so far.
+#[derive(Clone, Debug, Default)]
+pub struct Tapped(Arc<Mutex<Vec<Chunk>>>);
+
+impl Tapped {
+    /// The chunks kept since the last call, in file order.
+    pub fn take(&self) -> Vec<Chunk> {
+        mem::take(&mut *self.0.lock().expect("no thread panics holding it"))
+    }
+}
+
+/// Where [`Inject`] puts its chunks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Placement {
+    /// Before `IEND`, where [`Png::append_chunk`] puts them.
+    #[default]
+    BeforeIend,
+    /// Before the first `IDAT`, for chunks that must precede the image
+    /// data, such as `iCCP` or `pHYs`.
+    BeforeIdat,
+}
+
+/// A writer that passes a PNG stream on, adding chunks to it. It holds back
+/// at most the chunk being written, so the stream still goes out as the
+/// encoder produces it.
+pub struct Inject<W: Write> {
+    writer: W,
+    decoder: Decoder,
+    /// Waiting to be written; emptied once they are.
+    chunks: Vec<Chunk>,
+    placement: Placement,
+    signature_written: bool,
+}
+
+impl<W: Write> Inject<W> {
+    /// Adds `chunks` before `IEND`.
+    pub fn new(writer: W, chunks: Vec<Chunk>) -> Self {
+        Self {
+            writer,
+            decoder: Decoder::new(),
+            chunks,
+            placement: Placement::default(),
+            signature_written: false,
+        }
+    }
+    pub fn with_placement(self, placement: Placement) -> Self {
+        Self { placement, ..self }
+    }
+    /// Checks that the stream ended after a whole chunk and that the chunks
+    /// were added, then gives back the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.decoder.finish().map_err(invalid)?;
+        if !self.chunks.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "the stream ended before the chunks could be added",
+            ));
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+    fn due(&self, chunk_type: &ChunkType) -> bool {
+        match self.placement {
+            Placement::BeforeIend => chunk_type.bytes() == *b"IEND",
+            Placement::BeforeIdat => matches!(&chunk_type.bytes(), b"IDAT" | b"IEND"),
+        }
+    }
+}
+
+impl<W: Write> Write for Inject<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let events = self.decoder.feed(buf).map_err(invalid)?;
+        if !self.signature_written && self.decoder.position() >= Png::STANDARD_HEADER.len() {
+            self.writer.write_all(&Png::STANDARD_HEADER)?;
+            self.signature_written = true;
+        }
+        for event in events {
+            match event {
+                Event::Chunk { chunk, .. } => {
+                    if !self.chunks.is_empty() && self.due(chunk.chunk_type()) {
+                        for injected in mem::take(&mut self.chunks) {
+                            injected.write_into(&mut self.writer)?;
+                        }
+                    }
+                    chunk.write_into(&mut self.writer)?;
+                }
+                Event::Trailer(trailer) => self.writer.write_all(&trailer)?,
+                // Out of the file, like it is out of a parsed one.
+                Event::Quarantined { .. } => {}
+            }
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn testing_png() -> Png {
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tEXt", b"Comment\0hi"),
+            chunk("IDAT", &[1; 300]),
+            chunk("IDAT", &[2; 300]),
+            chunk("ruSt", b"hidden"),
+            chunk("IEND", &[]),
+        ]);
+        png.set_trailer(b"after".to_vec());
+        png
+    }
+
+    /// Reads in pieces of at most `.1` bytes, as decoders do.
+    struct Pieces<R>(R, usize);
+
+    impl<R: Read> Read for Pieces<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    #[test]
+    fn test_tap() {
+        let bytes = testing_png().as_bytes();
+        let tap = Tap::new(Pieces(bytes.as_slice(), 7));
+        let tapped = tap.chunks();
+        let mut read = Vec::new();
+        Pieces(tap, 7).read_to_end(&mut read).unwrap();
+        assert_eq!(read, bytes);
+        let chunks = tapped.take();
+        let types: Vec<_> = chunks.iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["tEXt", "ruSt"]);
+        assert_eq!(chunks[1].data(), b"hidden");
+        assert!(tapped.take().is_empty());
+
+        let tap = Tap::with_filter(bytes.as_slice(), |t| t.bytes() == *b"IDAT");
+        let tapped = tap.chunks();
+        let mut tap = tap;
+        io::copy(&mut tap, &mut io::sink()).unwrap();
+        assert_eq!(tapped.take().len(), 2);
+
+        let mut tap = Tap::new(&b"GIF89a"[..]);
+        let err = tap.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let mut tap = Tap::new(&bytes[..40]);
+        assert!(tap.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_inject() {
+        let bytes = testing_png().as_bytes();
+        let added = vec![chunk("ruSt", b"one"), chunk("ruSt", b"two")];
+        let write = |placement| {
+            let mut inject = Inject::new(Vec::new(), added.clone()).with_placement(placement);
+            for piece in bytes.chunks(5) {
+                inject.write_all(piece).unwrap();
+            }
+            inject.finish().unwrap()
+        };
+
+        let mut expected = testing_png();
+        for c in &added {
+            expected.append_chunk(c.clone());
+        }
+        assert_eq!(write(Placement::BeforeIend), expected.as_bytes());
+        let mut expected = testing_png();
+        expected.insert_chunk_at(2, added[1].clone());
+        expected.insert_chunk_at(2, added[0].clone());
+        assert_eq!(write(Placement::BeforeIdat), expected.as_bytes());
+
+        let mut inject = Inject::new(Vec::new(), added);
+        inject.write_all(&bytes[..50]).unwrap();
+        assert!(inject.finish().is_err());
+        let mut inject = Inject::new(Vec::new(), Vec::new());
+        assert!(inject.write_all(b"GIF89a").is_err());
+    }
+}
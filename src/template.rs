@@ -0,0 +1,190 @@
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum TemplateError {
+    #[error("Unknown placeholder in output template: {{{0}}}")]
+    UnknownPlaceholder(String),
+    #[error("Unclosed '{{' in output template")]
+    UnclosedPlaceholder,
+    #[error("Unmatched '}}' in output template")]
+    UnmatchedBrace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Placeholder {
+    Dir,
+    File,
+    Stem,
+    Ext,
+    Chunk,
+    Index,
+    Count,
+}
+
+impl FromStr for Placeholder {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dir" => Ok(Self::Dir),
+            "file" => Ok(Self::File),
+            "stem" => Ok(Self::Stem),
+            "ext" => Ok(Self::Ext),
+            "chunk" => Ok(Self::Chunk),
+            "index" => Ok(Self::Index),
+            "n" => Ok(Self::Count),
+            _ => Err(TemplateError::UnknownPlaceholder(s.to_string())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// Values substituted into an output filename [`Template`].
+pub struct TemplateContext<'a> {
+    pub input: &'a Path,
+    pub chunk_type: &'a str,
+    pub index: usize,
+    pub count: usize,
+}
+
+/// An output filename template such as `{stem}_{chunk}_{index}.bin`.
+#[derive(Clone, Debug)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError::UnclosedPlaceholder),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(name.parse()?));
+                }
+                '}' => return Err(TemplateError::UnmatchedBrace),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self { parts })
+    }
+}
+
+impl Template {
+    pub fn render(&self, ctx: &TemplateContext) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Placeholder(p) => out.push_str(&Self::value(*p, ctx)),
+            }
+        }
+        out
+    }
+
+    fn value(placeholder: Placeholder, ctx: &TemplateContext) -> String {
+        let lossy = |s: Option<&std::ffi::OsStr>| {
+            s.map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        match placeholder {
+            Placeholder::Dir => match ctx.input.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p.to_string_lossy().into_owned(),
+                _ => ".".to_string(),
+            },
+            Placeholder::File => lossy(ctx.input.file_name()),
+            Placeholder::Stem => lossy(ctx.input.file_stem()),
+            Placeholder::Ext => lossy(ctx.input.extension()),
+            Placeholder::Chunk => ctx.chunk_type.to_string(),
+            Placeholder::Index => ctx.index.to_string(),
+            Placeholder::Count => ctx.count.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(input: &str) -> TemplateContext<'_> {
+        TemplateContext {
+            input: Path::new(input),
+            chunk_type: "ruSt",
+            index: 2,
+            count: 7,
+        }
+    }
+
+    #[test]
+    fn test_render_default_template() {
+        let template = Template::from_str("{stem}_{chunk}_{index}.bin").unwrap();
+        assert_eq!(
+            template.render(&context("images/cat.png")),
+            "cat_ruSt_2.bin"
+        );
+    }
+
+    #[test]
+    fn test_render_path_placeholders() {
+        let template = Template::from_str("{dir}/{file}.{ext}.{n}").unwrap();
+        assert_eq!(
+            template.render(&context("images/cat.png")),
+            "images/cat.png.png.7"
+        );
+        assert_eq!(template.render(&context("cat.png")), "./cat.png.png.7");
+    }
+
+    #[test]
+    fn test_escaped_braces() {
+        let template = Template::from_str("{{{stem}}}").unwrap();
+        assert_eq!(template.render(&context("cat.png")), "{cat}");
+    }
+
+    #[test]
+    fn test_invalid_templates() {
+        assert!(matches!(
+            Template::from_str("{nope}"),
+            Err(TemplateError::UnknownPlaceholder(_))
+        ));
+        assert!(matches!(
+            Template::from_str("{stem"),
+            Err(TemplateError::UnclosedPlaceholder)
+        ));
+        assert!(matches!(
+            Template::from_str("stem}"),
+            Err(TemplateError::UnmatchedBrace)
+        ));
+    }
+}
@@ -0,0 +1,747 @@
+//! Every timestamp a PNG file carries, in the order they say things
+//! happened, for reconstructing when an image was made and changed.
+//!
+//! [`timeline`] reads the `tIME` chunk, text chunks holding dates, the
+//! date tags of `eXIf` (GPS included), the dates of an XMP packet (its
+//! edit history included) and, with `crypto`, when the files and log
+//! entries of unencrypted envelopes were stamped. Values that don't parse
+//! as dates are kept, undated, so nothing a file claims is lost.
+
+use flate2::read::ZlibDecoder;
+use std::fmt::{self, Display, Formatter};
+use std::io::Read;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::chunk::Chunk;
+#[cfg(feature = "crypto")]
+use crate::envelope::{Envelope, PayloadKind};
+use crate::png::Png;
+use crate::timestamp::days_from_civil;
+#[cfg(feature = "crypto")]
+use crate::trailer;
+
+/// Text is inflated up to this many bytes; an XMP packet is rarely more
+/// than a few kilobytes.
+const MAX_TEXT_LEN: usize = 1024 * 1024;
+
+/// Text chunk keywords whose values are dates.
+const DATE_KEYWORDS: &[&str] = &[
+    "Creation Time",
+    "Modification Time",
+    "date:create",
+    "date:modify",
+    "date:timestamp",
+];
+
+const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+/// XMP properties holding dates; `stEvt:when` dates each step of the edit
+/// history.
+const XMP_DATES: &[&str] = &[
+    "xmp:CreateDate",
+    "xmp:ModifyDate",
+    "xmp:MetadataDate",
+    "photoshop:DateCreated",
+    "exif:DateTimeOriginal",
+    "exif:DateTimeDigitized",
+    "tiff:DateTime",
+    "stEvt:when",
+];
+
+/// Where a timestamp was found.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The `tIME` chunk: when the image was last changed.
+    Time,
+    /// A text chunk, by keyword.
+    Text(String),
+    /// An `eXIf` tag, by name.
+    Exif(&'static str),
+    /// An XMP property, by name.
+    Xmp(String),
+    /// The modification time of a file in an envelope.
+    #[cfg(feature = "crypto")]
+    FileModified,
+    /// An entry of a log envelope.
+    #[cfg(feature = "crypto")]
+    LogEntry,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Time => write!(f, "tIME"),
+            Self::Text(keyword) => write!(f, "text {}", keyword),
+            Self::Exif(tag) => write!(f, "EXIF {}", tag),
+            Self::Xmp(property) => write!(f, "XMP {}", property),
+            #[cfg(feature = "crypto")]
+            Self::FileModified => write!(f, "envelope file modified"),
+            #[cfg(feature = "crypto")]
+            Self::LogEntry => write!(f, "envelope log entry"),
+        }
+    }
+}
+
+/// One timestamp of a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    /// `None` when the value isn't a date this module reads.
+    pub time: Option<SystemTime>,
+    /// Whether `time` had a zone. EXIF and many text dates are in a local
+    /// time that isn't recorded; they are read as UTC.
+    pub zoned: bool,
+    pub source: Source,
+    /// The index of the chunk it is in, or `None` after `IEND`.
+    pub chunk: Option<usize>,
+    /// The value as stored, or for envelopes the name of the file or the
+    /// text of the log entry.
+    pub detail: String,
+}
+
+impl Event {
+    fn parsed(source: Source, chunk: Option<usize>, detail: String) -> Self {
+        let (time, zoned) = match parse_date(&detail) {
+            Some((time, zoned)) => (Some(time), zoned),
+            None => (None, false),
+        };
+        Self {
+            time,
+            zoned,
+            source,
+            chunk,
+            detail,
+        }
+    }
+}
+
+/// The timestamps of `png`, earliest first; those that don't parse come
+/// last, in the order of the file.
+pub fn timeline(png: &Png) -> Vec<Event> {
+    let mut events = Vec::new();
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        match &chunk.chunk_type().bytes() {
+            b"tIME" => events.extend(time_chunk(chunk.data(), index)),
+            b"eXIf" => events.extend(exif(chunk.data(), index)),
+            b"tEXt" | b"zTXt" | b"iTXt" => events.extend(text_events(chunk, index)),
+            _ => {}
+        }
+        #[cfg(feature = "crypto")]
+        if Envelope::is_envelope(chunk.data()) {
+            events.extend(envelope(chunk.data(), Some(index)));
+        }
+    }
+    #[cfg(feature = "crypto")]
+    if let Ok(data) = trailer::find(png) {
+        events.extend(envelope(data, None));
+    }
+    events.sort_by_key(|event| (event.time.is_none(), event.time));
+    events
+}
+
+fn time_chunk(data: &[u8], index: usize) -> Option<Event> {
+    let &[year_high, year_low, month, day, hour, minute, second] = data else {
+        return None;
+    };
+    let year = u16::from_be_bytes([year_high, year_low]);
+    let time = civil(
+        year.into(),
+        month.into(),
+        day.into(),
+        hour.into(),
+        minute.into(),
+        second.into(),
+        0,
+    );
+    Some(Event {
+        time,
+        zoned: time.is_some(),
+        source: Source::Time,
+        chunk: Some(index),
+        detail: format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            year, month, day, hour, minute, second
+        ),
+    })
+}
+
+/// The keyword and text of a text chunk, inflated when compressed.
+pub(crate) fn text(chunk: &Chunk) -> Option<(String, String)> {
+    let data = chunk.data();
+    let keyword_len = data.iter().position(|&b| b == 0)?;
+    let keyword = latin1(&data[..keyword_len]);
+    let rest = &data[keyword_len + 1..];
+    let text = match &chunk.chunk_type().bytes() {
+        b"tEXt" => latin1(rest),
+        b"zTXt" => latin1(&inflate(rest.strip_prefix(&[0])?)?),
+        _ => {
+            let (&flag, rest) = rest.split_first()?;
+            let mut fields = rest.get(1..)?.splitn(3, |&b| b == 0);
+            let (_language, _translated) = (fields.next()?, fields.next()?);
+            let text = fields.next()?;
+            match flag {
+                0 => String::from_utf8_lossy(text).into_owned(),
+                _ => String::from_utf8_lossy(&inflate(text)?).into_owned(),
+            }
+        }
+    };
+    Some((keyword, text))
+}
+
+fn latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| char::from(b)).collect()
+}
+
+fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    ZlibDecoder::new(data)
+        .take(MAX_TEXT_LEN as u64)
+        .read_to_end(&mut out)
+        .ok()?;
+    Some(out)
+}
+
+fn text_events(chunk: &Chunk, index: usize) -> Vec<Event> {
+    let Some((keyword, text)) = text(chunk) else {
+        return Vec::new();
+    };
+    if keyword == XMP_KEYWORD {
+        return xmp(&text, index);
+    }
+    let known = DATE_KEYWORDS.contains(&keyword.as_str());
+    let lower = keyword.to_ascii_lowercase();
+    let dated = (lower.contains("date") || lower.contains("time")) && parse_date(&text).is_some();
+    match known || dated {
+        true => vec![Event::parsed(
+            Source::Text(keyword),
+            Some(index),
+            text.trim().to_string(),
+        )],
+        false => Vec::new(),
+    }
+}
+
+/// The values of the date properties of an XMP packet, as attributes
+/// (`xmp:CreateDate="…"`) or elements (`<xmp:CreateDate>…</…>`).
+fn xmp(packet: &str, index: usize) -> Vec<Event> {
+    let mut events = Vec::new();
+    for property in XMP_DATES {
+        let mut from = 0;
+        while let Some(at) = packet[from..].find(property) {
+            let start = from + at;
+            from = start + property.len();
+            let after = &packet[from..];
+            let value = if let Some(quoted) = after.strip_prefix('=') {
+                let Some(quote) = quoted.chars().next().filter(|c| *c == '"' || *c == '\'') else {
+                    continue;
+                };
+                quoted[1..].split(quote).next()
+            } else if packet[..start].ends_with('<') {
+                after
+                    .strip_prefix('>')
+                    .and_then(|rest| rest.split('<').next())
+            } else {
+                None
+            };
+            if let Some(value) = value.map(str::trim).filter(|value| !value.is_empty()) {
+                events.push(Event::parsed(
+                    Source::Xmp(property.to_string()),
+                    Some(index),
+                    value.to_string(),
+                ));
+            }
+        }
+    }
+    events
+}
+
+/// The date tags of a TIFF structure, as `eXIf` holds: `DateTime` in the
+/// first directory, the original and digitized dates with their offsets
+/// in the EXIF one, and the UTC date and time in the GPS one.
+fn exif(data: &[u8], index: usize) -> Vec<Event> {
+    let big_endian = match data.get(..4) {
+        Some(b"MM\0*") => true,
+        Some(b"II*\0") => false,
+        _ => return Vec::new(),
+    };
+    let tiff = Tiff { data, big_endian };
+    let mut events = Vec::new();
+    let Some(first) = tiff.u32(4).and_then(|at| tiff.directory(at)) else {
+        return events;
+    };
+    let sub = |tag| {
+        first
+            .iter()
+            .find(|entry| entry.tag == tag)
+            .and_then(|entry| tiff.u32(entry.value))
+            .and_then(|at| tiff.directory(at))
+            .unwrap_or_default()
+    };
+    let exif = sub(0x8769);
+    let ascii = |entries: &[TiffEntry], tag| {
+        entries
+            .iter()
+            .find(|entry| entry.tag == tag)
+            .and_then(|entry| tiff.ascii(entry))
+    };
+    let dates = [
+        ("DateTime", ascii(&first, 0x0132), ascii(&exif, 0x9010)),
+        (
+            "DateTimeOriginal",
+            ascii(&exif, 0x9003),
+            ascii(&exif, 0x9011),
+        ),
+        (
+            "DateTimeDigitized",
+            ascii(&exif, 0x9004),
+            ascii(&exif, 0x9012),
+        ),
+    ];
+    for (name, value, zone) in dates {
+        let Some(value) = value else { continue };
+        let offset = zone.as_deref().and_then(parse_offset);
+        let time = parse_exif(&value, offset.unwrap_or(0));
+        events.push(Event {
+            time,
+            zoned: time.is_some() && offset.is_some(),
+            source: Source::Exif(name),
+            chunk: Some(index),
+            detail: match zone {
+                Some(zone) => format!("{} {}", value, zone),
+                None => value,
+            },
+        });
+    }
+    let gps = sub(0x8825);
+    if let Some(date) = ascii(&gps, 0x001D) {
+        let clock = gps
+            .iter()
+            .find(|entry| entry.tag == 0x0007 && entry.kind == 5 && entry.count == 3)
+            .and_then(|entry| tiff.rationals(entry));
+        let (time, detail) = match clock {
+            Some([hour, minute, second]) => {
+                let value = format!("{} {:02}:{:02}:{:02}", date, hour, minute, second);
+                (parse_exif(&value, 0), value)
+            }
+            None => (None, date),
+        };
+        events.push(Event {
+            time,
+            zoned: time.is_some(),
+            source: Source::Exif("GPSDateStamp"),
+            chunk: Some(index),
+            detail,
+        });
+    }
+    events
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+struct TiffEntry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    /// Where the value, or the offset of a value over four bytes, is.
+    value: usize,
+}
+
+impl Tiff<'_> {
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.data.get(at..at.checked_add(2)?)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    }
+    fn u32(&self, at: usize) -> Option<usize> {
+        let bytes = self.data.get(at..at.checked_add(4)?)?.try_into().ok()?;
+        Some(match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        } as usize)
+    }
+    fn directory(&self, at: usize) -> Option<Vec<TiffEntry>> {
+        let count = self.u16(at)? as usize;
+        (0..count)
+            .map(|i| {
+                let entry = at + 2 + 12 * i;
+                Some(TiffEntry {
+                    tag: self.u16(entry)?,
+                    kind: self.u16(entry + 2)?,
+                    count: self.u32(entry + 4)? as u32,
+                    value: entry + 8,
+                })
+            })
+            .collect()
+    }
+    /// Where the `len` bytes of the value of `entry` are.
+    fn value(&self, entry: &TiffEntry, len: usize) -> Option<&[u8]> {
+        let at = match len <= 4 {
+            true => entry.value,
+            false => self.u32(entry.value)?,
+        };
+        self.data.get(at..at.checked_add(len)?)
+    }
+    fn ascii(&self, entry: &TiffEntry) -> Option<String> {
+        if entry.kind != 2 {
+            return None;
+        }
+        let value = self.value(entry, entry.count as usize)?;
+        let value = value.split(|&b| b == 0).next().unwrap_or_default();
+        Some(String::from_utf8_lossy(value).trim().to_string())
+    }
+    /// The whole parts of three unsigned rationals.
+    fn rationals(&self, entry: &TiffEntry) -> Option<[u32; 3]> {
+        let value = self.value(entry, 24)?;
+        let tiff = Tiff {
+            data: value,
+            big_endian: self.big_endian,
+        };
+        let part = |i: usize| {
+            let denominator = tiff.u32(8 * i + 4).filter(|d| *d != 0)?;
+            Some((tiff.u32(8 * i)? / denominator) as u32)
+        };
+        Some([part(0)?, part(1)?, part(2)?])
+    }
+}
+
+#[cfg(feature = "crypto")]
+fn envelope(data: &[u8], chunk: Option<usize>) -> Vec<Event> {
+    let Ok(envelope) = Envelope::try_from(data) else {
+        return Vec::new();
+    };
+    let mut events = Vec::new();
+    if let Some(meta) = envelope.file_meta() {
+        events.extend(meta.modified.map(|time| Event {
+            time: Some(time),
+            zoned: true,
+            source: Source::FileModified,
+            chunk,
+            detail: meta.name.clone(),
+        }));
+    }
+    if envelope.kind() == PayloadKind::Log {
+        for entry in envelope.log_entries().unwrap_or_default() {
+            events.extend(entry.time.map(|time| Event {
+                time: Some(time),
+                zoned: true,
+                source: Source::LogEntry,
+                chunk,
+                detail: entry.text,
+            }));
+        }
+    }
+    events
+}
+
+/// A civil date and time with the zone `offset` seconds east of UTC.
+fn civil(
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    offset: i64,
+) -> Option<SystemTime> {
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    if hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let secs = days_from_civil(year, month, day) * 86_400
+        + i64::from(hour * 3600 + minute * 60 + second)
+        - offset;
+    match secs >= 0 {
+        true => UNIX_EPOCH.checked_add(Duration::from_secs(secs as u64)),
+        false => UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs())),
+    }
+}
+
+/// The numeric fields of `value` split at `separators`, up to the first
+/// that isn't one.
+fn numbers(value: &str, separators: &[char]) -> Vec<u32> {
+    value
+        .split(|c| separators.contains(&c))
+        .map_while(|field| field.parse().ok())
+        .collect()
+}
+
+/// `YYYY:MM:DD HH:MM:SS`, the EXIF format, in a zone `offset` seconds
+/// east of UTC.
+fn parse_exif(value: &str, offset: i64) -> Option<SystemTime> {
+    let (date, time) = value.split_once(' ')?;
+    let date = numbers(date, &[':']);
+    let time = numbers(time, &[':']);
+    match (date.as_slice(), time.as_slice()) {
+        (&[year, month, day], &[hour, minute, second]) if year > 0 => {
+            civil(year.into(), month, day, hour, minute, second, offset)
+        }
+        _ => None,
+    }
+}
+
+/// `Z`, `+HH:MM`, `-HHMM` or `+HH`, in seconds east of UTC.
+fn parse_offset(value: &str) -> Option<i64> {
+    let value = value.trim();
+    if value == "Z" || value == "UT" || value == "UTC" || value == "GMT" {
+        return Some(0);
+    }
+    let sign = match value.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits: String = value[1..].chars().filter(|c| *c != ':').collect();
+    if !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (hours, minutes) = match digits.len() {
+        2 => (digits.parse::<i64>().ok()?, 0),
+        4 => (digits[..2].parse().ok()?, digits[2..].parse::<i64>().ok()?),
+        _ => return None,
+    };
+    (hours < 24 && minutes < 60).then_some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// ISO 8601 as XMP writes it, `YYYY-MM-DDThh:mm:ss.sss±hh:mm` with
+/// everything after the year optional; a missing zone is reported.
+fn parse_iso(value: &str) -> Option<(SystemTime, bool)> {
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    if date.len() < 4 || !date.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    let parts = numbers(date, &['-']);
+    if parts.len() != date.split('-').count() {
+        return None;
+    }
+    let (year, month, day) = match *parts.as_slice() {
+        [year] => (year, 1, 1),
+        [year, month] => (year, month, 1),
+        [year, month, day] => (year, month, day),
+        _ => return None,
+    };
+    let Some(time) = time else {
+        return Some((civil(year.into(), month, day, 0, 0, 0, 0)?, false));
+    };
+    let zone_at = time.find(['Z', '+', '-']).unwrap_or(time.len());
+    let (clock, zone) = time.split_at(zone_at);
+    let offset = match zone {
+        "" => None,
+        zone => Some(parse_offset(zone)?),
+    };
+    let clock = clock.split('.').next().unwrap_or_default();
+    let fields = numbers(clock, &[':']);
+    if fields.len() != clock.split(':').count() {
+        return None;
+    }
+    let (hour, minute, second) = match *fields.as_slice() {
+        [hour, minute] => (hour, minute, 0),
+        [hour, minute, second] => (hour, minute, second),
+        _ => return None,
+    };
+    let time = civil(
+        year.into(),
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        offset.unwrap_or(0),
+    )?;
+    Some((time, offset.is_some()))
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// RFC 1123, `Sun, 01 May 2022 12:30:00 +0000`, which the specification
+/// recommends for `Creation Time`; the weekday is optional.
+fn parse_rfc1123(value: &str) -> Option<(SystemTime, bool)> {
+    let value = value.split_once(", ").map_or(value, |(_, rest)| rest);
+    let mut fields = value.split_whitespace();
+    let (day, month, year, clock) = (
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+    );
+    let zone = fields.next();
+    let month = MONTHS
+        .iter()
+        .position(|name| month.eq_ignore_ascii_case(name))? as u32
+        + 1;
+    let clock = numbers(clock, &[':']);
+    let &[hour, minute, second] = clock.as_slice() else {
+        return None;
+    };
+    let offset = match zone {
+        Some(zone) => Some(parse_offset(zone)?),
+        None => None,
+    };
+    let time = civil(
+        year.parse().ok()?,
+        month,
+        day.parse().ok()?,
+        hour,
+        minute,
+        second,
+        offset.unwrap_or(0),
+    )?;
+    Some((time, offset.is_some()))
+}
+
+/// A date in any of the formats PNG writers use in text: ISO 8601, RFC
+/// 1123 or the EXIF format. The flag tells whether it had a zone.
+fn parse_date(value: &str) -> Option<(SystemTime, bool)> {
+    let value = value.trim();
+    parse_iso(value)
+        .or_else(|| parse_rfc1123(value))
+        .or_else(|| parse_exif(value, 0).map(|time| (time, false)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use crate::timestamp::format_utc;
+    use std::str::FromStr;
+
+    fn chunk(code: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(code).unwrap(), data)
+    }
+
+    /// A little-endian TIFF with `DateTime` and an EXIF directory holding
+    /// `DateTimeOriginal` and its offset.
+    fn testing_exif() -> Vec<u8> {
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend(8u32.to_le_bytes());
+        let entry = |tag: u16, kind: u16, count: u32, value: u32| {
+            [
+                &tag.to_le_bytes()[..],
+                &kind.to_le_bytes(),
+                &count.to_le_bytes(),
+                &value.to_le_bytes(),
+            ]
+            .concat()
+        };
+        // First directory at 8, EXIF directory at 38, strings from 68.
+        tiff.extend(2u16.to_le_bytes());
+        tiff.extend(entry(0x0132, 2, 20, 68));
+        tiff.extend(entry(0x8769, 4, 1, 38));
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(2u16.to_le_bytes());
+        tiff.extend(entry(0x9003, 2, 20, 88));
+        tiff.extend(entry(0x9011, 2, 7, 108));
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(b"2022:05:03 08:00:00\0");
+        tiff.extend(b"2022:05:01 14:30:00\0");
+        tiff.extend(b"+02:00\0");
+        tiff
+    }
+
+    #[test]
+    fn test_timeline() {
+        let xmp = b"XML:com.adobe.xmp\0\0\0\0\0<rdf:Description \
+            xmp:CreateDate=\"2022-05-01T12:00:00Z\"><xmpMM:History><rdf:li \
+            stEvt:when=\"2022-05-02T10:00:00+01:00\"/></xmpMM:History>\
+            <xmp:ModifyDate>soon</xmp:ModifyDate></rdf:Description>";
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("tIME", &[7, 230, 5, 4, 9, 0, 0]),
+            chunk("eXIf", &testing_exif()),
+            chunk("tEXt", b"Creation Time\0Sun, 01 May 2022 11:00:00 +0000"),
+            chunk("iTXt", xmp),
+            chunk("tEXt", b"Comment\0Made on 2022-05-01"),
+            chunk("IEND", &[]),
+        ]);
+        let events = timeline(&png);
+        let summary: Vec<(String, String, bool)> = events
+            .iter()
+            .map(|event| {
+                let time = event.time.map(format_utc).unwrap_or_default();
+                (event.source.to_string(), time, event.zoned)
+            })
+            .collect();
+        let expected = [
+            ("text Creation Time", "2022-05-01T11:00:00Z", true),
+            ("XMP xmp:CreateDate", "2022-05-01T12:00:00Z", true),
+            ("EXIF DateTimeOriginal", "2022-05-01T12:30:00Z", true),
+            ("XMP stEvt:when", "2022-05-02T09:00:00Z", true),
+            ("EXIF DateTime", "2022-05-03T08:00:00Z", false),
+            ("tIME", "2022-05-04T09:00:00Z", true),
+            ("XMP xmp:ModifyDate", "", false),
+        ];
+        let expected: Vec<(String, String, bool)> = expected
+            .iter()
+            .map(|&(source, time, zoned)| (source.to_string(), time.to_string(), zoned))
+            .collect();
+        assert_eq!(summary, expected);
+        assert_eq!(events.last().unwrap().detail, "soon");
+        assert_eq!(events[0].chunk, Some(3));
+    }
+
+    #[cfg(feature = "crypto")]
+    #[test]
+    fn test_envelope_times() {
+        use crate::envelope::LogEntry;
+
+        let entry = |secs, text: &str| LogEntry {
+            time: Some(UNIX_EPOCH + Duration::from_secs(secs)),
+            text: text.to_string(),
+        };
+        let log = Envelope::log(&[entry(200, "second"), entry(100, "first")]);
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("ruSt", &log.as_bytes()),
+            chunk("IEND", &[]),
+        ]);
+        trailer::attach(&mut png, &log.as_bytes()).unwrap();
+        let events = timeline(&png);
+        let details: Vec<(&str, Option<usize>)> = events
+            .iter()
+            .map(|event| (event.detail.as_str(), event.chunk))
+            .collect();
+        assert_eq!(
+            details,
+            [
+                ("first", Some(1)),
+                ("first", None),
+                ("second", Some(1)),
+                ("second", None)
+            ]
+        );
+        assert_eq!(events[0].source, Source::LogEntry);
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let utc = |value| parse_date(value).map(|(time, zoned)| (format_utc(time), zoned));
+        assert_eq!(
+            utc("2022-05-01T12:30:00.250-05:30"),
+            Some(("2022-05-01T18:00:00Z".to_string(), true))
+        );
+        assert_eq!(
+            utc("2022-05"),
+            Some(("2022-05-01T00:00:00Z".to_string(), false))
+        );
+        assert_eq!(
+            utc("1 May 2022 12:30:00 GMT"),
+            Some(("2022-05-01T12:30:00Z".to_string(), true))
+        );
+        assert_eq!(
+            utc("2022:05:01 12:30:00"),
+            Some(("2022-05-01T12:30:00Z".to_string(), false))
+        );
+        assert_eq!(utc("2022-13-01"), None);
+        assert_eq!(utc("yesterday"), None);
+    }
+}
@@ -0,0 +1,97 @@
+//! Instrumentation for services that embed the crate, with the `tracing`
+//! feature.
+//!
+//! Parsing, validation, compression, pixel coding and encryption each run
+//! in a `tracing` span at debug level named after the stage (`png.parse`,
+//! `verify`, `lint`, `compress`, `decompress`, `image.decode`,
+//! `image.encode`, `kdf.derive`, `encrypt`, `decrypt`), with the sizes,
+//! chunk types and algorithms involved as fields; how long each took is
+//! the span's own, as subscribers report it when it closes. Stages end
+//! with a debug event of what they produced, failures are warn events in
+//! the span of the stage they end, and every chunk the decoder reads is a
+//! trace event.
+//!
+//! Without the feature the macros here expand to nothing and their
+//! arguments aren't evaluated, so instrumenting costs nothing.
+
+/// Enters a debug span until the end of the enclosing block.
+macro_rules! span {
+    ($name:literal $(, $($field:tt)*)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($name $(, $($field)*)?).entered();
+    };
+}
+
+/// Emits an event at `$level` (`trace`, `debug`, ...).
+macro_rules! event {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::$level!($($arg)*);
+    };
+}
+
+pub(crate) use {event, span};
+
+/// `result`, with a warn event in the current span when it is an error.
+pub(crate) fn failed<T, E: core::fmt::Display>(result: Result<T, E>) -> Result<T, E> {
+    #[cfg(feature = "tracing")]
+    if let Err(e) = &result {
+        tracing::warn!(error = %e, "failed");
+    }
+    result
+}
+
+#[cfg(all(test, feature = "tracing", feature = "std"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::png::Png;
+    use core::str::FromStr;
+
+    /// Names the spans entered and the events emitted, in order.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut names = self.0.lock().unwrap();
+            names.push(span.metadata().name().to_string());
+            Id::from_u64(names.len() as u64)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let level = event.metadata().level().to_string();
+            self.0.lock().unwrap().push(level.to_lowercase());
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_spans() {
+        let png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), &[0; 13]),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), &[]),
+        ]);
+        let mut bytes = png.as_bytes();
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            Png::try_from(bytes.as_slice()).unwrap();
+            bytes[20] ^= 1;
+            assert!(Png::try_from(bytes.as_slice()).is_err());
+        });
+        let names = recorder.0.lock().unwrap();
+        assert_eq!(
+            *names,
+            ["png.parse", "trace", "trace", "debug", "png.parse", "warn"]
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Payloads stored after the `IEND` chunk, where decoders stop reading.
+//!
+//! Some pipelines drop every chunk they don't know but copy the file's
+//! trailing bytes along. The payload is framed by a footer so it can be
+//! found from the end of the file, after any other trailing data:
+//!
+//! ```text
+//! payload | length (u32 BE) | "pmTR"
+//! ```
+
+use thiserror::Error;
+
+use crate::png::Png;
+
+#[derive(Debug, Error)]
+pub enum TrailerError {
+    #[error("No payload after IEND")]
+    NotFound,
+    #[error("The payload after IEND claims {0} bytes, more than the file has")]
+    Truncated(u32),
+    #[error("A payload of {0} bytes is too large to store after IEND")]
+    TooLong(usize),
+}
+
+pub const FOOTER: &[u8; 4] = b"pmTR";
+const FOOTER_LEN: usize = 4 + FOOTER.len();
+
+/// Where the framed payload starts in the trailer, and how long it is.
+fn locate(trailer: &[u8]) -> Result<(usize, usize), TrailerError> {
+    if trailer.len() < FOOTER_LEN || !trailer.ends_with(FOOTER) {
+        return Err(TrailerError::NotFound);
+    }
+    let end = trailer.len() - FOOTER_LEN;
+    let len = u32::from_be_bytes(trailer[end..end + 4].try_into().expect("4 bytes"));
+    let start = end
+        .checked_sub(len as usize)
+        .ok_or(TrailerError::Truncated(len))?;
+    Ok((start, len as usize))
+}
+
+/// The payload stored after `IEND` by [`attach`].
+pub fn find(png: &Png) -> Result<&[u8], TrailerError> {
+    find_in(png.trailer())
+}
+
+/// Like [`find`], given the bytes after `IEND`.
+pub fn find_in(trailer: &[u8]) -> Result<&[u8], TrailerError> {
+    let (start, len) = locate(trailer)?;
+    Ok(&trailer[start..start + len])
+}
+
+/// Stores `data` after `IEND`, replacing a payload attached before and
+/// keeping any other trailing bytes in front of it.
+pub fn attach(png: &mut Png, data: &[u8]) -> Result<(), TrailerError> {
+    let len = u32::try_from(data.len()).map_err(|_| TrailerError::TooLong(data.len()))?;
+    let mut trailer = png.trailer().to_vec();
+    if let Ok((start, _)) = locate(&trailer) {
+        trailer.truncate(start);
+    }
+    trailer.extend_from_slice(data);
+    trailer.extend_from_slice(&len.to_be_bytes());
+    trailer.extend_from_slice(FOOTER);
+    png.set_trailer(trailer);
+    Ok(())
+}
+
+/// Removes the payload attached after `IEND`, returning it.
+pub fn detach(png: &mut Png) -> Result<Vec<u8>, TrailerError> {
+    let mut trailer = png.trailer().to_vec();
+    let (start, len) = locate(&trailer)?;
+    let data = trailer[start..start + len].to_vec();
+    trailer.truncate(start);
+    png.set_trailer(trailer);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), &[0; 13]),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), &[]),
+        ]);
+        png.set_trailer(b"other tool's data".to_vec());
+        png
+    }
+
+    #[test]
+    fn test_attach_and_detach() {
+        let mut png = testing_png();
+        assert!(matches!(find(&png), Err(TrailerError::NotFound)));
+        attach(&mut png, b"first").unwrap();
+        attach(&mut png, b"second").unwrap();
+        let parsed = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert_eq!(find(&parsed).unwrap(), b"second");
+        assert_eq!(parsed.chunks().len(), 2);
+
+        assert_eq!(detach(&mut png).unwrap(), b"second");
+        assert_eq!(png.trailer(), b"other tool's data");
+        assert!(matches!(detach(&mut png), Err(TrailerError::NotFound)));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let mut png = testing_png();
+        png.set_trailer([&1000u32.to_be_bytes()[..], FOOTER].concat());
+        assert!(matches!(find(&png), Err(TrailerError::Truncated(1000))));
+    }
+}
@@ -0,0 +1,497 @@
+//! Batched file reads through io_uring (Linux 5.6 and later).
+//!
+//! Reading thousands of small files one at a time costs an open, a read or
+//! two and a close per file, each a blocking system call. A [`Reader`]
+//! queues those operations for a whole batch of files in a ring shared
+//! with the kernel and submits them together, so a batch costs a few system
+//! calls however many files it holds.
+
+use std::ffi::CString;
+use std::io;
+use std::mem::{self, size_of};
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+// From <linux/io_uring.h>.
+const IORING_OFF_SQ_RING: libc::off_t = 0;
+const IORING_OFF_CQ_RING: libc::off_t = 0x800_0000;
+const IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_OP_OPENAT: u8 = 18;
+const IORING_OP_CLOSE: u8 = 19;
+const IORING_OP_READ: u8 = 22;
+
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+/// A submission queue entry: one operation for the kernel.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    op_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    addr3: u64,
+    pad: u64,
+}
+
+/// A completion queue entry: the result of one operation.
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+const _: () = assert!(size_of::<Params>() == 120);
+const _: () = assert!(size_of::<Sqe>() == 64);
+const _: () = assert!(size_of::<Cqe>() == 16);
+
+/// The first read of each file, enough for most PNG files in one go.
+const INITIAL_READ_LEN: usize = 64 * 1024;
+/// Reads are capped below `u32::MAX`, the most one entry can ask for.
+const MAX_READ_LEN: usize = 1 << 30;
+
+/// A region of the ring mapped from the kernel.
+struct Map {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+impl Map {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> io::Result<Self> {
+        // SAFETY: a fresh shared mapping of the ring behind `fd`; the kernel
+        // picks the address.
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED | libc::MAP_POPULATE,
+                fd,
+                offset,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: NonNull::new(ptr.cast()).expect("mmap never maps at address 0"),
+            len,
+        })
+    }
+    /// A pointer `offset` bytes into the map.
+    fn at<T>(&self, offset: u32) -> *mut T {
+        debug_assert!(offset as usize + size_of::<T>() <= self.len);
+        // SAFETY: the kernel's offsets all fall inside the map.
+        unsafe { self.ptr.as_ptr().add(offset as usize).cast() }
+    }
+    /// The ring index `offset` bytes into the map, shared with the kernel.
+    fn index(&self, offset: u32) -> &AtomicU32 {
+        // SAFETY: ring indices are aligned `u32`s live as long as the map,
+        // and the kernel only ever accesses them atomically.
+        unsafe { &*self.at::<AtomicU32>(offset) }
+    }
+}
+
+impl Drop for Map {
+    fn drop(&mut self) {
+        // SAFETY: unmapping exactly what `new` mapped, once.
+        unsafe {
+            libc::munmap(self.ptr.as_ptr().cast(), self.len);
+        }
+    }
+}
+
+/// An io_uring instance.
+struct Ring {
+    sq: Map,
+    /// `None` if the kernel maps both queues in one region, `sq`.
+    cq: Option<Map>,
+    sqes: Map,
+    params: Params,
+    fd: OwnedFd,
+}
+
+// The maps are only touched through `&mut self`, and the kernel doesn't
+// care which thread submits.
+unsafe impl Send for Ring {}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Self> {
+        let mut params = Params::default();
+        // SAFETY: `params` is a valid `io_uring_params` for the kernel to
+        // fill in.
+        let fd = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_setup,
+                entries,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: a new descriptor that nothing else owns.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+        let single = params.features & IORING_FEAT_SINGLE_MMAP != 0;
+        let sq = Map::new(
+            fd.as_raw_fd(),
+            if single { sq_len.max(cq_len) } else { sq_len },
+            IORING_OFF_SQ_RING,
+        )?;
+        let cq = if single {
+            None
+        } else {
+            Some(Map::new(fd.as_raw_fd(), cq_len, IORING_OFF_CQ_RING)?)
+        };
+        let sqes = Map::new(
+            fd.as_raw_fd(),
+            params.sq_entries as usize * size_of::<Sqe>(),
+            IORING_OFF_SQES,
+        )?;
+        Ok(Self {
+            sq,
+            cq,
+            sqes,
+            params,
+            fd,
+        })
+    }
+    /// How many operations [`Ring::run`] takes at once.
+    fn depth(&self) -> usize {
+        self.params.sq_entries as usize
+    }
+    /// Submits `sqes` and waits for all of them, returning each one's
+    /// result: non-negative on success, a negated `errno` on failure.
+    ///
+    /// # Safety
+    ///
+    /// The memory each entry points to must stay valid until it completes.
+    /// If this fails, some entries may still be in flight, so that memory
+    /// must never be freed.
+    unsafe fn run(&mut self, sqes: &[Sqe]) -> io::Result<Vec<i32>> {
+        assert!(sqes.len() <= self.depth());
+        let sq_off = &self.params.sq_off;
+        let mask = *self.sq.at::<u32>(sq_off.ring_mask);
+        let array = self.sq.at::<u32>(sq_off.array);
+        let entries = self.sqes.at::<Sqe>(0);
+        let tail = self.sq.index(sq_off.tail);
+        // Only this side moves the submission tail.
+        let mut next = tail.load(Ordering::Relaxed);
+        for (i, sqe) in sqes.iter().enumerate() {
+            let slot = (next & mask) as usize;
+            entries.add(slot).write(Sqe {
+                user_data: i as u64,
+                ..*sqe
+            });
+            array.add(slot).write(slot as u32);
+            next = next.wrapping_add(1);
+        }
+        tail.store(next, Ordering::Release);
+
+        let cq = self.cq.as_ref().unwrap_or(&self.sq);
+        let cq_off = &self.params.cq_off;
+        let cq_mask = *cq.at::<u32>(cq_off.ring_mask);
+        let cqes = cq.at::<Cqe>(cq_off.cqes);
+        let mut results = vec![None; sqes.len()];
+        let (mut submitted, mut completed) = (0, 0);
+        while completed < sqes.len() {
+            let ret = libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.fd.as_raw_fd(),
+                (sqes.len() - submitted) as u32,
+                (sqes.len() - completed) as u32,
+                IORING_ENTER_GETEVENTS,
+                std::ptr::null::<libc::sigset_t>(),
+                0,
+            );
+            if ret < 0 {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EINTR | libc::EAGAIN | libc::EBUSY) => {}
+                    _ => return Err(err),
+                }
+            } else {
+                submitted += ret as usize;
+            }
+            let head = cq.index(cq_off.head);
+            let end = cq.index(cq_off.tail).load(Ordering::Acquire);
+            let mut at = head.load(Ordering::Relaxed);
+            while at != end {
+                let cqe = &*cqes.add((at & cq_mask) as usize);
+                results[cqe.user_data as usize] = Some(cqe.res);
+                at = at.wrapping_add(1);
+                completed += 1;
+            }
+            head.store(at, Ordering::Release);
+        }
+        Ok(results
+            .into_iter()
+            .map(|res| res.expect("every entry completed"))
+            .collect())
+    }
+}
+
+/// Reads whole files in batches through one ring. Use one per thread.
+pub struct Reader {
+    ring: Ring,
+}
+
+impl Reader {
+    /// Operations submitted at once by [`Reader::default_depth`].
+    pub const DEFAULT_DEPTH: u32 = 256;
+
+    /// A reader submitting up to `depth` operations at a time (the kernel
+    /// may round it up). Fails where io_uring is unavailable: before Linux
+    /// 5.1, or where a seccomp filter or `kernel.io_uring_disabled` blocks
+    /// it. Before 5.6, every file fails to open.
+    pub fn new(depth: u32) -> io::Result<Self> {
+        Ok(Self {
+            ring: Ring::new(depth)?,
+        })
+    }
+    pub fn default_depth() -> io::Result<Self> {
+        Self::new(Self::DEFAULT_DEPTH)
+    }
+    /// Reads the first `limit` bytes (or all, if shorter) of each of
+    /// `paths`, in order, failing file by file as `fs::read` would. Fails
+    /// as a whole only if the ring does, in which case the memory given to
+    /// the kernel for the batch in progress is leaked rather than risk it
+    /// being written to after it is freed.
+    pub fn read_files<P: AsRef<Path>>(
+        &mut self,
+        paths: &[P],
+        limit: usize,
+    ) -> io::Result<Vec<io::Result<Vec<u8>>>> {
+        let mut files = Vec::with_capacity(paths.len());
+        // A file takes one slot at a time: opening, reading, then closing.
+        for batch in paths.chunks(self.ring.depth()) {
+            files.extend(self.read_batch(batch, limit)?);
+        }
+        Ok(files)
+    }
+    fn read_batch<P: AsRef<Path>>(
+        &mut self,
+        paths: &[P],
+        limit: usize,
+    ) -> io::Result<Vec<io::Result<Vec<u8>>>> {
+        let names: Vec<io::Result<CString>> = paths
+            .iter()
+            .map(|path| {
+                CString::new(path.as_ref().as_os_str().as_bytes()).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte")
+                })
+            })
+            .collect();
+        let opens: Vec<Sqe> = names
+            .iter()
+            .flatten()
+            .map(|name| Sqe {
+                opcode: IORING_OP_OPENAT,
+                fd: libc::AT_FDCWD,
+                addr: name.as_ptr() as u64,
+                op_flags: (libc::O_RDONLY | libc::O_CLOEXEC) as u32,
+                ..Sqe::default()
+            })
+            .collect();
+        // SAFETY: the names outlive the operations, or are leaked.
+        let opened = match unsafe { self.ring.run(&opens) } {
+            Ok(opened) => opened,
+            Err(e) => {
+                mem::forget(names);
+                return Err(e);
+            }
+        };
+        let mut opened = opened.into_iter();
+        let mut fds: Vec<Option<OwnedFd>> = Vec::with_capacity(paths.len());
+        let mut failed: Vec<Option<io::Error>> = Vec::with_capacity(paths.len());
+        for name in names {
+            let fd = match name {
+                Ok(_) => opened.next().expect("one result per open"),
+                Err(e) => {
+                    fds.push(None);
+                    failed.push(Some(e));
+                    continue;
+                }
+            };
+            if fd < 0 {
+                fds.push(None);
+                failed.push(Some(io::Error::from_raw_os_error(-fd)));
+            } else {
+                // SAFETY: a descriptor the kernel just opened for us.
+                fds.push(Some(unsafe { OwnedFd::from_raw_fd(fd) }));
+                failed.push(None);
+            }
+        }
+
+        let mut data: Vec<Vec<u8>> = vec![Vec::new(); paths.len()];
+        let mut pending: Vec<usize> = (0..paths.len())
+            .filter(|&i| fds[i].is_some() && limit > 0)
+            .collect();
+        // A short read doesn't mean the end of a file, so each one reads
+        // until a read comes back empty.
+        while !pending.is_empty() {
+            let reads: Vec<Sqe> = pending
+                .iter()
+                .map(|&i| {
+                    let buf = &mut data[i];
+                    if buf.len() == buf.capacity() {
+                        let grow = buf.capacity().max(INITIAL_READ_LEN);
+                        buf.reserve_exact(grow.min(limit - buf.len()));
+                    }
+                    let len = (buf.capacity() - buf.len())
+                        .min(limit - buf.len())
+                        .min(MAX_READ_LEN);
+                    let fd = fds[i].as_ref().expect("only open files read");
+                    Sqe {
+                        opcode: IORING_OP_READ,
+                        fd: fd.as_raw_fd(),
+                        off: buf.len() as u64,
+                        addr: buf.spare_capacity_mut().as_mut_ptr() as u64,
+                        len: len as u32,
+                        ..Sqe::default()
+                    }
+                })
+                .collect();
+            // SAFETY: the buffers aren't touched until the reads complete,
+            // and are leaked if they might not.
+            let read = match unsafe { self.ring.run(&reads) } {
+                Ok(read) => read,
+                Err(e) => {
+                    mem::forget(data);
+                    return Err(e);
+                }
+            };
+            let mut still_pending = Vec::new();
+            for (&i, read) in pending.iter().zip(read) {
+                if read < 0 {
+                    failed[i] = Some(io::Error::from_raw_os_error(-read));
+                } else if read > 0 {
+                    // SAFETY: the kernel wrote `read` bytes into the spare
+                    // capacity.
+                    let buf = &mut data[i];
+                    unsafe { buf.set_len(buf.len() + read as usize) };
+                    if buf.len() < limit {
+                        still_pending.push(i);
+                    }
+                }
+            }
+            pending = still_pending;
+        }
+
+        let closes: Vec<Sqe> = fds
+            .into_iter()
+            .flatten()
+            .map(|fd| Sqe {
+                opcode: IORING_OP_CLOSE,
+                fd: fd.into_raw_fd(),
+                ..Sqe::default()
+            })
+            .collect();
+        // SAFETY: closing points at no memory. If it fails, only the
+        // descriptors are lost.
+        unsafe { self.ring.run(&closes) }?;
+
+        Ok(data
+            .into_iter()
+            .zip(failed)
+            .map(|(data, failed)| match failed {
+                Some(e) => Err(e),
+                None => Ok(data),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_read_files() {
+        let Ok(mut reader) = Reader::new(4) else {
+            // io_uring is blocked here; there is nothing to test.
+            return;
+        };
+        let dir = std::env::temp_dir().join(format!("pngme-uring-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let large: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let paths: Vec<_> = (0..10).map(|i| dir.join(format!("{}.bin", i))).collect();
+        for (i, path) in paths.iter().enumerate() {
+            fs::write(path, if i == 3 { &large[..] } else { b"small" }).unwrap();
+        }
+        let mut asked = paths.clone();
+        asked.push(dir.join("missing.bin"));
+
+        let read = reader.read_files(&asked, usize::MAX).unwrap();
+        let capped = reader.read_files(&paths[3..4], 1000).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(read.len(), 11);
+        assert_eq!(read[0].as_ref().unwrap(), b"small");
+        assert_eq!(read[3].as_ref().unwrap(), &large);
+        assert_eq!(read[9].as_ref().unwrap(), b"small");
+        assert_eq!(
+            read[10].as_ref().unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        assert_eq!(capped[0].as_ref().unwrap(), &large[..1000]);
+    }
+}
@@ -0,0 +1,357 @@
+//! Private chunks that tools outside the specification write often enough
+//! to be worth telling apart from payloads and junk: Apple's, Microsoft's,
+//! Adobe Fireworks', Android's and ImageMagick's.
+//!
+//! They are read and written back byte for byte like any other chunk; only
+//! `strip` removes them, and only when asked to. The exception is `iDOT`,
+//! which holds offsets into the image data: [`crate::image::ImageData`]
+//! drops it when it rewrites the pixels, as the specification asks of a
+//! chunk that is unsafe to copy.
+
+use thiserror::Error;
+
+use crate::chunk::Chunk;
+use crate::png::Png;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum VendorError {
+    #[error("{chunk_type} is {actual} bytes, expected {expected}")]
+    Length {
+        chunk_type: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("{0}")]
+    Invalid(&'static str),
+}
+
+/// A vendor chunk this crate knows of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Known {
+    pub chunk_type: &'static [u8; 4],
+    pub vendor: &'static str,
+    pub description: &'static str,
+}
+
+pub const KNOWN: &[Known] = &[
+    Known {
+        chunk_type: b"iDOT",
+        vendor: "Apple",
+        description: "image data split for parallel decoding",
+    },
+    Known {
+        chunk_type: b"CgBI",
+        vendor: "Apple",
+        description: "CgBI image marker",
+    },
+    Known {
+        chunk_type: b"msOG",
+        vendor: "Microsoft Office",
+        description: "original GIF image",
+    },
+    Known {
+        chunk_type: b"mkBF",
+        vendor: "Adobe Fireworks",
+        description: "editable document",
+    },
+    Known {
+        chunk_type: b"mkBS",
+        vendor: "Adobe Fireworks",
+        description: "editable document",
+    },
+    Known {
+        chunk_type: b"mkBT",
+        vendor: "Adobe Fireworks",
+        description: "editable document",
+    },
+    Known {
+        chunk_type: b"mkTS",
+        vendor: "Adobe Fireworks",
+        description: "editable document",
+    },
+    Known {
+        chunk_type: b"prVW",
+        vendor: "Adobe Fireworks",
+        description: "preview image",
+    },
+    Known {
+        chunk_type: b"npTc",
+        vendor: "Android",
+        description: "nine-patch stretch regions",
+    },
+    Known {
+        chunk_type: b"npLb",
+        vendor: "Android",
+        description: "nine-patch layout bounds",
+    },
+    Known {
+        chunk_type: b"npOl",
+        vendor: "Android",
+        description: "nine-patch outline",
+    },
+    Known {
+        chunk_type: b"vpAg",
+        vendor: "ImageMagick",
+        description: "virtual page size",
+    },
+    Known {
+        chunk_type: b"caNv",
+        vendor: "ImageMagick",
+        description: "canvas size and offset",
+    },
+    Known {
+        chunk_type: b"orNT",
+        vendor: "ImageMagick",
+        description: "orientation",
+    },
+];
+
+/// The registry entry for chunks of type `code`.
+pub fn lookup(code: &[u8; 4]) -> Option<&'static Known> {
+    KNOWN.iter().find(|known| known.chunk_type == code)
+}
+
+/// One run of rows of an `iDOT` image, stored in the `IDAT` chunks from
+/// `offset`, counted from the start of the `iDOT` chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub first_row: u32,
+    pub rows: u32,
+    pub offset: u32,
+}
+
+/// Android's `Res_png_9patch`: where the image stretches, its content
+/// padding (left, right, top, bottom) and the colors of its regions.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NinePatch {
+    pub x_divs: Vec<i32>,
+    pub y_divs: Vec<i32>,
+    pub padding: [i32; 4],
+    pub colors: Vec<u32>,
+}
+
+/// The data of a vendor chunk with a known layout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VendorChunk {
+    IDot(Vec<Segment>),
+    /// `msOG`: an Office version string, then `len` bytes of the image.
+    OfficeImage {
+        version: String,
+        len: usize,
+    },
+    NinePatch(NinePatch),
+    VirtualPage {
+        width: u32,
+        height: u32,
+        unit: u8,
+    },
+    Canvas {
+        width: u32,
+        height: u32,
+        x: i32,
+        y: i32,
+    },
+    Orientation(u8),
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().expect("4 bytes"))
+}
+
+fn length(chunk_type: &'static str, data: &[u8], expected: usize) -> Result<(), VendorError> {
+    match data.len() == expected {
+        true => Ok(()),
+        false => Err(VendorError::Length {
+            chunk_type,
+            expected,
+            actual: data.len(),
+        }),
+    }
+}
+
+/// Parses `chunk`, or gives `None` for chunks whose layout isn't known.
+pub fn parse(chunk: &Chunk) -> Option<Result<VendorChunk, VendorError>> {
+    let data = chunk.data();
+    Some(match &chunk.chunk_type().bytes() {
+        b"iDOT" => parse_idot(data),
+        b"msOG" => parse_office_image(data),
+        b"npTc" => parse_nine_patch(data),
+        b"vpAg" => length("vpAg", data, 9).and_then(|()| match data[8] {
+            0 | 1 => Ok(VendorChunk::VirtualPage {
+                width: u32_at(data, 0),
+                height: u32_at(data, 4),
+                unit: data[8],
+            }),
+            _ => Err(VendorError::Invalid("vpAg has an unknown unit")),
+        }),
+        b"caNv" => length("caNv", data, 16).map(|()| VendorChunk::Canvas {
+            width: u32_at(data, 0),
+            height: u32_at(data, 4),
+            x: u32_at(data, 8) as i32,
+            y: u32_at(data, 12) as i32,
+        }),
+        b"orNT" => length("orNT", data, 1).and_then(|()| match data[0] {
+            1..=8 => Ok(VendorChunk::Orientation(data[0])),
+            _ => Err(VendorError::Invalid("orNT has an orientation outside 1-8")),
+        }),
+        _ => return None,
+    })
+}
+
+fn parse_idot(data: &[u8]) -> Result<VendorChunk, VendorError> {
+    if data.len() < 4 {
+        return Err(VendorError::Invalid("iDOT is too short to hold a count"));
+    }
+    let count = u32_at(data, 0) as usize;
+    let expected = count.saturating_mul(12).saturating_add(4);
+    length("iDOT", data, expected)?;
+    let segments = data[4..]
+        .chunks_exact(12)
+        .map(|entry| Segment {
+            first_row: u32_at(entry, 0),
+            rows: u32_at(entry, 4),
+            offset: u32_at(entry, 8),
+        })
+        .collect();
+    Ok(VendorChunk::IDot(segments))
+}
+
+fn parse_office_image(data: &[u8]) -> Result<VendorChunk, VendorError> {
+    let rest = data
+        .strip_prefix(b"MSOFFICE")
+        .ok_or(VendorError::Invalid("msOG doesn't start with MSOFFICE"))?;
+    let version_len = rest
+        .iter()
+        .position(|b| !(b.is_ascii_digit() || *b == b'.'))
+        .unwrap_or(rest.len());
+    let version = String::from_utf8_lossy(&rest[..version_len]).into_owned();
+    Ok(VendorChunk::OfficeImage {
+        version,
+        len: rest.len() - version_len,
+    })
+}
+
+fn parse_nine_patch(data: &[u8]) -> Result<VendorChunk, VendorError> {
+    if data.len() < 32 {
+        return Err(VendorError::Length {
+            chunk_type: "npTc",
+            expected: 32,
+            actual: data.len(),
+        });
+    }
+    let counts = [data[1], data[2], data[3]].map(usize::from);
+    length("npTc", data, 32 + 4 * counts.iter().sum::<usize>())?;
+    let mut values = data[32..].chunks_exact(4).map(|v| u32_at(v, 0));
+    let x_divs = values.by_ref().take(counts[0]).map(|v| v as i32).collect();
+    let y_divs = values.by_ref().take(counts[1]).map(|v| v as i32).collect();
+    let padding = [12, 16, 20, 24].map(|at| u32_at(data, at) as i32);
+    Ok(VendorChunk::NinePatch(NinePatch {
+        x_divs,
+        y_divs,
+        padding,
+        colors: values.collect(),
+    }))
+}
+
+/// The offsets of the `iDOT` chunk at `index` of `png` that don't point at
+/// the start of an `IDAT` chunk.
+pub fn stale_idot_offsets(png: &Png, index: usize) -> Vec<u32> {
+    let Some(Ok(VendorChunk::IDot(segments))) = png.chunks().get(index).and_then(parse) else {
+        return Vec::new();
+    };
+    let mut starts = Vec::new();
+    let mut offset = 0u64;
+    for chunk in &png.chunks()[index..] {
+        if chunk.chunk_type().bytes() == *b"IDAT" {
+            starts.push(offset);
+        }
+        offset += 12 + chunk.length() as u64;
+    }
+    segments
+        .iter()
+        .map(|segment| segment.offset)
+        .filter(|offset| !starts.contains(&(*offset as u64)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(code: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(code).unwrap(), data)
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn test_idot() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("iDOT", &words(&[2, 0, 50, 40, 50, 50, 60])),
+            chunk("IDAT", &[0; 8]),
+            chunk("IDAT", &[0; 8]),
+            chunk("IEND", &[]),
+        ]);
+        let parsed = parse(&png.chunks()[1]).unwrap().unwrap();
+        assert_eq!(
+            parsed,
+            VendorChunk::IDot(vec![
+                Segment {
+                    first_row: 0,
+                    rows: 50,
+                    offset: 40
+                },
+                Segment {
+                    first_row: 50,
+                    rows: 50,
+                    offset: 60
+                },
+            ])
+        );
+        assert!(stale_idot_offsets(&png, 1).is_empty());
+        assert_eq!(lookup(b"iDOT").unwrap().vendor, "Apple");
+
+        let bytes = png.as_bytes();
+        assert_eq!(Png::try_from(bytes.as_slice()).unwrap().as_bytes(), bytes);
+
+        let mut moved = png.clone();
+        moved.insert_chunk_at(2, chunk("tEXt", b"a\0b"));
+        assert_eq!(stale_idot_offsets(&moved, 1), [40, 60]);
+        assert!(matches!(
+            parse(&chunk("iDOT", &words(&[3, 0]))),
+            Some(Err(VendorError::Length { expected: 40, .. }))
+        ));
+    }
+
+    #[test]
+    fn test_other_layouts() {
+        let mut nine_patch = vec![1, 2, 2, 1];
+        nine_patch.extend(words(&[0, 0, 1, 2, 3, 4, 0, 5, 10, 6, 12, 1]));
+        assert_eq!(
+            parse(&chunk("npTc", &nine_patch)).unwrap().unwrap(),
+            VendorChunk::NinePatch(NinePatch {
+                x_divs: vec![5, 10],
+                y_divs: vec![6, 12],
+                padding: [1, 2, 3, 4],
+                colors: vec![1],
+            })
+        );
+        assert_eq!(
+            parse(&chunk("msOG", b"MSOFFICE9.0GIF89a")).unwrap(),
+            Ok(VendorChunk::OfficeImage {
+                version: "9.0".to_string(),
+                len: 6
+            })
+        );
+        assert_eq!(
+            parse(&chunk("orNT", &[9])).unwrap(),
+            Err(VendorError::Invalid("orNT has an orientation outside 1-8"))
+        );
+        assert!(parse(&chunk("mkBF", b"fireworks")).is_none());
+    }
+}
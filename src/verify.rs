@@ -0,0 +1,198 @@
+//! Checking the CRCs of a PNG file as it streams past.
+//!
+//! [`verify`] hands each chunk's data straight from the reader's buffer to
+//! the CRC digest: nothing is copied, no [`crate::chunk::Chunk`] is built,
+//! and memory use doesn't depend on the size of the file, so it runs about
+//! as fast as the file can be read. Limits don't apply, since nothing is
+//! kept.
+
+use std::io::{self, BufRead};
+
+use crate::chunk::ChunkError;
+use crate::crc;
+use crate::decoder::{self, CriticalPolicy};
+use crate::png::PngError;
+use crate::trace;
+use crate::walk::{bad, Walker};
+
+/// The capacity to give a `BufReader` for [`verify`]: reads large enough
+/// for the CRC to run at full speed between them.
+pub const BUFFER_LEN: usize = 256 * 1024;
+
+/// What [`verify`] read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Verified {
+    /// Bytes in the file.
+    pub len: u64,
+    /// Chunks, `IEND` included.
+    pub chunks: usize,
+    /// Bytes after `IEND`.
+    pub trailer_len: u64,
+    /// Critical chunks other than `IHDR`, `PLTE`, `IDAT` and `IEND`, which
+    /// most decoders refuse.
+    pub unknown_critical: usize,
+}
+
+/// Reads the PNG file in `reader` to the end, checking the CRC of every
+/// chunk, and reports the first one that is damaged. Like
+/// [`crate::decoder::Decoder`], accepts a file that ends without `IEND`,
+/// and counts unknown critical chunks.
+pub fn verify(reader: impl BufRead) -> Result<Verified, PngError> {
+    verify_with_policy(reader, CriticalPolicy::Keep)
+}
+
+/// Like [`verify`], but fails on the first unknown critical chunk under
+/// [`CriticalPolicy::Error`]. Nothing is kept, so the other policies only
+/// count them.
+pub fn verify_with_policy(
+    reader: impl BufRead,
+    policy: CriticalPolicy,
+) -> Result<Verified, PngError> {
+    trace::span!("verify", ?policy);
+    let verified = trace::failed(read_to_end(reader, policy))?;
+    trace::event!(
+        debug,
+        len = verified.len,
+        chunks = verified.chunks,
+        trailer_len = verified.trailer_len,
+        unknown_critical = verified.unknown_critical,
+        "verified"
+    );
+    Ok(verified)
+}
+
+fn read_to_end(reader: impl BufRead, policy: CriticalPolicy) -> Result<Verified, PngError> {
+    let mut walker = Walker::new(reader)?;
+    let mut chunks = 0;
+    let mut unknown_critical = 0;
+    while let Some(header) = walker.next_header()? {
+        if decoder::is_unknown_critical(&header.chunk_type) {
+            if policy == CriticalPolicy::Error {
+                return Err(match usize::try_from(header.offset) {
+                    Ok(offset) => PngError::UnknownCritical {
+                        offset,
+                        chunk_type: header.chunk_type,
+                    },
+                    Err(_) => PngError::TooLarge,
+                });
+            }
+            unknown_critical += 1;
+        }
+        let reader = walker.reader();
+        let mut hasher = crc::Hasher::new();
+        hasher.update(&header.chunk_type.bytes());
+        let mut crc = [0; 4];
+        digest(reader, &mut hasher, header.length as usize)
+            .and_then(|_| reader.read_exact(&mut crc))
+            .map_err(|e| header.bad(e))?;
+        if u32::from_be_bytes(crc) != hasher.finalize() {
+            return Err(header.bad(ChunkError::ChecksumError));
+        }
+        chunks += 1;
+        if header.is_end() {
+            let offset = walker.offset();
+            let trailer_len =
+                io::copy(walker.reader(), &mut io::sink()).map_err(|e| bad(offset, e.into()))?;
+            return Ok(Verified {
+                len: offset + trailer_len,
+                chunks,
+                trailer_len,
+                unknown_critical,
+            });
+        }
+    }
+    Ok(Verified {
+        len: walker.offset(),
+        chunks,
+        trailer_len: 0,
+        unknown_critical,
+    })
+}
+
+/// Feeds the next `len` bytes of `reader` to `hasher`, from its buffer.
+fn digest(reader: &mut impl BufRead, hasher: &mut crc::Hasher, mut len: usize) -> io::Result<()> {
+    while len > 0 {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let n = buf.len().min(len);
+        hasher.update(&buf[..n]);
+        reader.consume(n);
+        len -= n;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::png::Png;
+    use std::io::BufReader;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), &[0; 13]),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), &[7; 100_000]),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), &[]),
+        ]);
+        png.set_trailer(b"after".to_vec());
+        png.as_bytes()
+    }
+
+    #[test]
+    fn test_verify() {
+        let bytes = testing_bytes();
+        let expected = Verified {
+            len: bytes.len() as u64,
+            chunks: 3,
+            trailer_len: 5,
+            unknown_critical: 0,
+        };
+        assert_eq!(verify(bytes.as_slice()).unwrap(), expected);
+        // Chunks straddling many small reads.
+        let small = BufReader::with_capacity(7, bytes.as_slice());
+        assert_eq!(verify(small).unwrap(), expected);
+        // No IEND.
+        let end = bytes.len() - 5 - Chunk::OVERHEAD;
+        assert_eq!(verify(&bytes[..end]).unwrap().chunks, 2);
+    }
+
+    #[test]
+    fn test_verify_errors() {
+        let mut bytes = testing_bytes();
+        let idat = 8 + 25;
+        bytes[idat + 8 + 500] ^= 1;
+        assert!(matches!(
+            verify(bytes.as_slice()),
+            Err(PngError::BadChunk {
+                offset,
+                source: ChunkError::ChecksumError
+            }) if offset == idat
+        ));
+        bytes.truncate(idat + 1000);
+        assert!(matches!(
+            verify(bytes.as_slice()),
+            Err(PngError::BadChunk { offset, .. }) if offset == idat
+        ));
+        assert!(matches!(
+            verify(&b"GIF89a"[..]),
+            Err(PngError::InvalidHeader)
+        ));
+    }
+
+    #[test]
+    fn test_unknown_critical() {
+        let mut png = Png::try_from(testing_bytes().as_slice()).unwrap();
+        png.insert_chunk_at(1, Chunk::new(ChunkType::from_str("ABCD").unwrap(), &[]));
+        let bytes = png.as_bytes();
+        assert_eq!(verify(bytes.as_slice()).unwrap().unknown_critical, 1);
+        assert!(matches!(
+            verify_with_policy(bytes.as_slice(), CriticalPolicy::Error),
+            Err(PngError::UnknownCritical { offset: 33, .. })
+        ));
+    }
+}
@@ -0,0 +1,177 @@
+//! Walking the chunk headers of a PNG file as it streams past, for the
+//! readers that never build a [`crate::chunk::Chunk`]: [`crate::verify`],
+//! [`crate::detect`] and [`crate::census`].
+//!
+//! [`Walker`] checks the signature and reads each eight-byte header; what
+//! happens to the data after it is up to the caller, which reads it, hands
+//! it to a digest or [`skip`]s it. Offsets in errors are those of the
+//! chunk's length field, as [`PngError::BadChunk`] reports everywhere else.
+
+use std::io::{self, BufRead, Seek, SeekFrom};
+
+use crate::chunk::{Chunk, ChunkError};
+use crate::chunk_type::ChunkType;
+use crate::png::{Png, PngError};
+
+/// A chunk's header, with the reader at the start of its data.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Header {
+    /// Where the chunk starts in the file.
+    pub offset: u64,
+    /// Data bytes, header and CRC left out.
+    pub length: u32,
+    pub chunk_type: ChunkType,
+}
+
+impl Header {
+    pub fn is_end(&self) -> bool {
+        self.chunk_type.bytes() == *b"IEND"
+    }
+
+    /// The error for this chunk.
+    pub fn bad(&self, source: impl Into<ChunkError>) -> PngError {
+        bad(self.offset, source.into())
+    }
+}
+
+/// Reads the chunk headers of a PNG file, one [`Self::next_header`] at a
+/// time. Between two calls the caller moves the reader past the data and
+/// the CRC of the chunk.
+pub(crate) struct Walker<R> {
+    reader: R,
+    offset: u64,
+}
+
+impl<R: BufRead> Walker<R> {
+    /// Reads and checks the signature.
+    pub fn new(mut reader: R) -> Result<Self, PngError> {
+        let mut signature = [0; 8];
+        reader
+            .read_exact(&mut signature)
+            .map_err(|_| PngError::InvalidHeader)?;
+        if signature != Png::STANDARD_HEADER {
+            return Err(PngError::InvalidHeader);
+        }
+        Ok(Self {
+            reader,
+            offset: Png::STANDARD_HEADER.len() as u64,
+        })
+    }
+
+    /// The header of the next chunk, or `None` when the file ends where a
+    /// chunk would start.
+    pub fn next_header(&mut self) -> Result<Option<Header>, PngError> {
+        let offset = self.offset;
+        if self
+            .reader
+            .fill_buf()
+            .map_err(|e| bad(offset, e.into()))?
+            .is_empty()
+        {
+            return Ok(None);
+        }
+        let mut header = [0; 8];
+        self.reader
+            .read_exact(&mut header)
+            .map_err(|e| bad(offset, e.into()))?;
+        let length = u32::from_be_bytes(header[..4].try_into().expect("4 bytes"));
+        let chunk_type = ChunkType::try_from(<[u8; 4]>::try_from(&header[4..]).expect("4 bytes"))
+            .map_err(|e| bad(offset, e.into()))?;
+        if length > Chunk::MAX_LENGTH {
+            return Err(bad(offset, ChunkError::TooLong(length as usize)));
+        }
+        self.offset += (Chunk::OVERHEAD + length as usize) as u64;
+        Ok(Some(Header {
+            offset,
+            length,
+            chunk_type,
+        }))
+    }
+
+    /// Where the chunk after the last header read starts: past the end of
+    /// the walk, the length of what was walked.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn reader(&mut self) -> &mut R {
+        &mut self.reader
+    }
+}
+
+/// Moves `reader` on by `len` bytes, from its buffer when they are there.
+/// Fails with `UnexpectedEof`, like a read would, when fewer are left:
+/// seeking past the end succeeds, and would leave a file truncated in the
+/// middle of a chunk looking as if it ended between two.
+pub(crate) fn skip(reader: &mut (impl BufRead + Seek), len: u64) -> io::Result<()> {
+    let buffered = reader.fill_buf()?.len() as u64;
+    if len <= buffered {
+        reader.consume(len as usize);
+        return Ok(());
+    }
+    reader.consume(buffered as usize);
+    let position = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    if end.saturating_sub(position) < len - buffered {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    reader.seek(SeekFrom::Start(position + len - buffered))?;
+    Ok(())
+}
+
+pub(crate) fn bad(offset: u64, source: ChunkError) -> PngError {
+    match usize::try_from(offset) {
+        Ok(offset) => PngError::BadChunk { offset, source },
+        Err(_) => PngError::TooLarge,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor, Read};
+    use std::str::FromStr;
+
+    #[test]
+    fn test_walker() {
+        let chunk = |code: &str, data: &[u8]| Chunk::new(ChunkType::from_str(code).unwrap(), data);
+        let bytes = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("IDAT", &[0; 1000]),
+            chunk("IEND", &[]),
+        ])
+        .as_bytes();
+        let mut walker = Walker::new(BufReader::with_capacity(16, Cursor::new(&bytes))).unwrap();
+        let mut headers = Vec::new();
+        while let Some(header) = walker.next_header().unwrap() {
+            skip(walker.reader(), header.length as u64 + 4).unwrap();
+            headers.push((
+                header.offset,
+                header.chunk_type.to_string(),
+                header.is_end(),
+            ));
+        }
+        assert_eq!(
+            headers,
+            [
+                (8, "IHDR".to_string(), false),
+                (33, "IDAT".to_string(), false),
+                (1045, "IEND".to_string(), true),
+            ]
+        );
+        assert_eq!(walker.offset(), bytes.len() as u64);
+
+        let mut walker = Walker::new(&bytes[..40]).unwrap();
+        walker.next_header().unwrap();
+        let mut rest = [0; 17];
+        walker.reader().read_exact(&mut rest).unwrap();
+        assert!(matches!(
+            walker.next_header(),
+            Err(PngError::BadChunk { offset: 33, .. })
+        ));
+        assert!(matches!(
+            Walker::new(&b"GIF89a"[..]),
+            Err(PngError::InvalidHeader)
+        ));
+    }
+}
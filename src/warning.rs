@@ -0,0 +1,221 @@
+//! Findings that don't stop a file from being read or written.
+//!
+//! Parsing and writing fail only on what they can't get past. What they do
+//! get past, but a caller may want to hear about (a deprecated chunk,
+//! metadata after the pixels, text longer than libpng reads), comes back
+//! as [`Warning`]s next to the result, in a [`WithWarnings`] from
+//! [`Png::parse_with_warnings`] and, with `std`,
+//! `WriteOptions::apply_with_warnings`. [`crate::lint`] checks a great
+//! deal more, when asked.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+
+use crate::decoder;
+use crate::png::Png;
+
+/// Chunks the PNG extensions register but deprecate.
+pub const DEPRECATED: &[&[u8; 4]] = &[b"gIFt"];
+
+/// Largest ancillary chunk libpng reads by default (its
+/// `PNG_USER_CHUNK_MALLOC_MAX`); longer text is dropped by most decoders
+/// built on it.
+pub const MAX_TEXT_LEN: usize = 8_000_000;
+
+/// Ancillary chunks the specification puts before `PLTE`.
+const BEFORE_PALETTE: &[&[u8; 4]] = &[
+    b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCV", b"cLLI",
+];
+/// Ancillary chunks the specification puts before `IDAT`.
+const BEFORE_DATA: &[&[u8; 4]] = &[
+    b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCV", b"cLLI", b"bKGD", b"hIST",
+    b"tRNS", b"pHYs", b"sPLT", b"eXIf", b"oFFs", b"pCAL", b"sCAL", b"acTL",
+];
+
+/// What a [`Warning`] is about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WarningKind {
+    DeprecatedChunk,
+    /// A chunk after the one the specification puts it before, which
+    /// decoders may ignore.
+    Misordered,
+    /// `IDAT` chunks with others between them.
+    SplitData,
+    /// Text longer than [`MAX_TEXT_LEN`].
+    OversizedText,
+    /// An unknown critical chunk kept under
+    /// [`decoder::CriticalPolicy::Keep`].
+    UnknownCritical,
+    /// Bytes after `IEND`.
+    TrailingData,
+    /// A text chunk written as it was, rather than as the kind asked for.
+    TextKept,
+}
+
+/// A finding, and the chunk it is about, if it is about one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    /// The index of the chunk in the file read, or in the [`Png`] written.
+    pub chunk: Option<usize>,
+    pub message: String,
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.chunk {
+            Some(chunk) => write!(f, "chunk {}: {}", chunk, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// A result and the warnings met on the way to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WithWarnings<T> {
+    pub value: T,
+    pub warnings: Vec<Warning>,
+}
+
+impl<T> WithWarnings<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            warnings: Vec::new(),
+        }
+    }
+    /// The value, ignoring the warnings.
+    pub fn into_value(self) -> T {
+        self.value
+    }
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> WithWarnings<U> {
+        WithWarnings {
+            value: f(self.value),
+            warnings: self.warnings,
+        }
+    }
+    pub(crate) fn warn(&mut self, kind: WarningKind, chunk: Option<usize>, message: String) {
+        self.warnings.push(Warning {
+            kind,
+            chunk,
+            message,
+        });
+    }
+}
+
+/// The warnings of a parsed file.
+pub(crate) fn check(png: &Png) -> Vec<Warning> {
+    let mut found = WithWarnings::new(());
+    let (mut palette_seen, mut data_seen, mut data_ended) = (false, false, false);
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let chunk_type = chunk.chunk_type();
+        let code = chunk_type.bytes();
+        let mut warn = |kind, message| found.warn(kind, Some(index), message);
+        if DEPRECATED.contains(&&code) {
+            warn(
+                WarningKind::DeprecatedChunk,
+                format!("{} is deprecated", chunk_type),
+            );
+        }
+        if data_seen && BEFORE_DATA.contains(&&code) {
+            warn(
+                WarningKind::Misordered,
+                format!("{} comes after the image data", chunk_type),
+            );
+        } else if palette_seen && BEFORE_PALETTE.contains(&&code) {
+            warn(
+                WarningKind::Misordered,
+                format!("{} comes after PLTE", chunk_type),
+            );
+        }
+        if code == *b"IDAT" && data_ended {
+            warn(
+                WarningKind::SplitData,
+                "IDAT chunks aren't consecutive".into(),
+            );
+        }
+        let text = matches!(&code, b"tEXt" | b"zTXt" | b"iTXt");
+        if text && chunk.data().len() > MAX_TEXT_LEN {
+            warn(
+                WarningKind::OversizedText,
+                format!(
+                    "{} is {} bytes, more than libpng reads ({})",
+                    chunk_type,
+                    chunk.data().len(),
+                    MAX_TEXT_LEN
+                ),
+            );
+        }
+        if decoder::is_unknown_critical(chunk_type) {
+            warn(
+                WarningKind::UnknownCritical,
+                format!("{} is critical, and unknown to most decoders", chunk_type),
+            );
+        }
+        palette_seen |= code == *b"PLTE";
+        data_ended |= data_seen && code != *b"IDAT";
+        data_seen |= code == *b"IDAT";
+    }
+    if !png.trailer().is_empty() {
+        found.warn(
+            WarningKind::TrailingData,
+            None,
+            format!("{} bytes follow IEND", png.trailer().len()),
+        );
+    }
+    found.warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::options::ParseOptions;
+    use core::str::FromStr;
+
+    #[test]
+    fn test_warnings() {
+        let chunk = |code: &str, data: &[u8]| Chunk::new(ChunkType::from_str(code).unwrap(), data);
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &[0; 13]),
+            chunk("PLTE", &[0; 3]),
+            chunk("gAMA", &[0, 0, 1, 0]),
+            chunk("IDAT", &[1]),
+            chunk("gIFt", b"text"),
+            chunk("IDAT", &[2]),
+            chunk("pHYs", &[0; 9]),
+            chunk("RUST", b"critical"),
+            chunk("IEND", &[]),
+        ]);
+        png.set_trailer(b"trailer".to_vec());
+        let parsed = Png::parse_with_warnings(&png.as_bytes(), &ParseOptions::new()).unwrap();
+        assert_eq!(parsed.value.chunks().len(), 9);
+        let kinds: Vec<(WarningKind, Option<usize>)> = parsed
+            .warnings
+            .iter()
+            .map(|warning| (warning.kind, warning.chunk))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (WarningKind::Misordered, Some(2)),
+                (WarningKind::DeprecatedChunk, Some(4)),
+                (WarningKind::SplitData, Some(5)),
+                (WarningKind::Misordered, Some(6)),
+                (WarningKind::UnknownCritical, Some(7)),
+                (WarningKind::TrailingData, None),
+            ]
+        );
+        assert_eq!(
+            parsed.warnings[0].to_string(),
+            "chunk 2: gAMA comes after PLTE"
+        );
+        assert_eq!(parsed.warnings[5].to_string(), "7 bytes follow IEND");
+
+        let clean = Png::from_chunks(vec![chunk("IHDR", &[0; 13]), chunk("IEND", &[])]);
+        assert!(check(&clean).is_empty());
+    }
+}
@@ -0,0 +1,278 @@
+//! Robust watermarks: a mark to prove where an image came from rather than
+//! a payload to read back, built to survive what images go through.
+//!
+//! A key-dependent pattern of 32x32 bits is tiled over the image and set as
+//! the least significant bit of every color sample. Lossless re-encoding
+//! and stripping metadata keep the pixels, and cropping keeps the tiling, so
+//! detection folds the image onto a single tile, correlates it with the
+//! pattern at every offset and reports the best match. The same pattern bit
+//! in every channel and every tile makes the mark redundant enough to
+//! survive partial damage.
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::image::{ColorType, EncodeOptions, ImageData, ImageError};
+use crate::png::Png;
+
+#[derive(Debug, Error)]
+pub enum WatermarkError {
+    #[error(transparent)]
+    Image(#[from] ImageError),
+    #[error("Can't watermark {0}")]
+    Unsupported(&'static str),
+    #[error("No watermark found (strength {0:.3})")]
+    NotFound(f64),
+}
+
+/// Side of the tile the pattern repeats in.
+pub const TILE: usize = 32;
+/// Correlation above which a watermark counts as present. An unmarked
+/// image stays near 0; a marked one starts at 1 and fades with damage.
+pub const THRESHOLD: f64 = 0.5;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watermark {
+    pattern: Vec<bool>,
+}
+
+/// How well an image matches a watermark.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Detection {
+    /// Correlation with the pattern, from -1 to 1.
+    pub strength: f64,
+    /// Where the image's top left pixel sits in the tile, which moves when
+    /// the image is cropped.
+    pub offset: (usize, usize),
+}
+
+impl Detection {
+    pub fn is_present(&self) -> bool {
+        self.strength >= THRESHOLD
+    }
+}
+
+impl Watermark {
+    /// The watermark of `key`, e.g. an owner's name or a secret.
+    pub fn new(key: &[u8]) -> Self {
+        let mut pattern = Vec::with_capacity(TILE * TILE);
+        for counter in 0u32.. {
+            if pattern.len() == TILE * TILE {
+                break;
+            }
+            let digest = Sha256::new()
+                .chain_update(b"pngme watermark v1")
+                .chain_update((key.len() as u64).to_be_bytes())
+                .chain_update(key)
+                .chain_update(counter.to_be_bytes())
+                .finalize();
+            pattern.extend(
+                digest
+                    .iter()
+                    .flat_map(|b| (0..8).map(move |i| b >> i & 1 == 1)),
+            );
+        }
+        Self { pattern }
+    }
+    fn bit(&self, x: usize, y: usize) -> bool {
+        self.pattern[y % TILE * TILE + x % TILE]
+    }
+    /// Marks the pixels of `png` and writes the image back with `options`.
+    pub fn apply(&self, png: &mut Png, options: &EncodeOptions) -> Result<(), WatermarkError> {
+        let mut image = ImageData::decode(png)?;
+        let samples = Samples::new(&image)?;
+        let header = *image.header();
+        for y in 0..header.height as usize {
+            let row = image.row_mut(y as u32);
+            for x in 0..header.width as usize {
+                let bit = self.bit(x, y) as u8;
+                for offset in samples.offsets(x) {
+                    row[offset] = (row[offset] & !1) | bit;
+                }
+            }
+        }
+        image.write_to(png, options)?;
+        Ok(())
+    }
+    /// Looks for the watermark in `png`, wherever the tiling starts.
+    pub fn detect(&self, png: &Png) -> Result<Detection, WatermarkError> {
+        let image = ImageData::decode(png)?;
+        let samples = Samples::new(&image)?;
+        let header = image.header();
+        // Fold the image onto one tile: +1 for each set low bit, -1 for
+        // each clear one.
+        let mut folded = vec![0i64; TILE * TILE];
+        let mut count = 0;
+        for y in 0..header.height as usize {
+            let row = image.row(y as u32);
+            for x in 0..header.width as usize {
+                for offset in samples.offsets(x) {
+                    folded[y % TILE * TILE + x % TILE] += if row[offset] & 1 == 1 { 1 } else { -1 };
+                    count += 1;
+                }
+            }
+        }
+        let mut best = Detection {
+            strength: f64::MIN,
+            offset: (0, 0),
+        };
+        for dy in 0..TILE {
+            for dx in 0..TILE {
+                let correlation: i64 = (0..TILE * TILE)
+                    .map(|i| {
+                        let sign = if self.bit(i % TILE + dx, i / TILE + dy) {
+                            1
+                        } else {
+                            -1
+                        };
+                        folded[i] * sign
+                    })
+                    .sum();
+                let strength = correlation as f64 / count.max(1) as f64;
+                if strength > best.strength {
+                    best = Detection {
+                        strength,
+                        offset: (dx, dy),
+                    };
+                }
+            }
+        }
+        Ok(best)
+    }
+    /// Like [`Watermark::detect`], but fails unless the watermark is present.
+    pub fn verify(&self, png: &Png) -> Result<Detection, WatermarkError> {
+        let detection = self.detect(png)?;
+        if !detection.is_present() {
+            return Err(WatermarkError::NotFound(detection.strength));
+        }
+        Ok(detection)
+    }
+}
+
+/// Where the low bytes of a pixel's color samples are in its scanline.
+struct Samples {
+    /// Bytes per pixel.
+    pixel_len: usize,
+    /// Offsets of the color samples' low bytes within a pixel.
+    color: Vec<usize>,
+}
+
+impl Samples {
+    fn new(image: &ImageData) -> Result<Self, WatermarkError> {
+        let header = image.header();
+        if header.color_type == ColorType::Indexed {
+            return Err(WatermarkError::Unsupported("indexed images"));
+        }
+        if header.bit_depth < 8 {
+            return Err(WatermarkError::Unsupported("grayscale images below 8 bits"));
+        }
+        let sample_len = header.bit_depth as usize / 8;
+        let colors = match header.color_type {
+            ColorType::Rgb | ColorType::Rgba => 3,
+            _ => 1,
+        };
+        Ok(Self {
+            pixel_len: header.color_type.channels() * sample_len,
+            color: (0..colors)
+                .map(|c| c * sample_len + sample_len - 1)
+                .collect(),
+        })
+    }
+    fn offsets(&self, x: usize) -> impl Iterator<Item = usize> + '_ {
+        self.color.iter().map(move |c| x * self.pixel_len + c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::image::{FilterStrategy, Header};
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    fn rgb(width: u32, height: u32) -> Header {
+        Header {
+            width,
+            height,
+            bit_depth: 8,
+            color_type: ColorType::Rgb,
+            interlaced: false,
+        }
+    }
+
+    fn testing_png(image: &ImageData) -> Png {
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", &image.header().as_bytes()),
+            chunk("tEXt", b"Comment\0made by pngme"),
+            chunk("IEND", &[]),
+        ]);
+        image.write_to(&mut png, &EncodeOptions::default()).unwrap();
+        png
+    }
+
+    fn testing_image() -> ImageData {
+        let pixels = (0..100 * 90 * 3u32).map(|i| (i * i % 251) as u8).collect();
+        ImageData::new(rgb(100, 90), pixels).unwrap()
+    }
+
+    #[test]
+    fn test_survives_reencoding_and_stripping() {
+        let watermark = Watermark::new(b"alice");
+        let mut png = testing_png(&testing_image());
+        assert!(!watermark.detect(&png).unwrap().is_present());
+        watermark
+            .apply(&mut png, &EncodeOptions::default())
+            .unwrap();
+        let detection = watermark.verify(&png).unwrap();
+        assert_eq!(detection.strength, 1.0);
+        assert_eq!(detection.offset, (0, 0));
+
+        let image = ImageData::decode(&png).unwrap();
+        let options = EncodeOptions {
+            filter: FilterStrategy::from_str("paeth").unwrap(),
+            level: 1,
+            interlace: Some(true),
+        };
+        let mut stripped = Png::from_chunks(vec![
+            chunk("IHDR", &image.header().as_bytes()),
+            chunk("IEND", &[]),
+        ]);
+        image.write_to(&mut stripped, &options).unwrap();
+        assert_eq!(watermark.verify(&stripped).unwrap().strength, 1.0);
+        assert!(matches!(
+            Watermark::new(b"mallory").verify(&stripped),
+            Err(WatermarkError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_survives_cropping_and_damage() {
+        let watermark = Watermark::new(b"alice");
+        let mut png = testing_png(&testing_image());
+        watermark
+            .apply(&mut png, &EncodeOptions::default())
+            .unwrap();
+        let image = ImageData::decode(&png).unwrap();
+
+        // Drop 5 columns and 9 rows from the top left, and 20 more from
+        // the bottom right.
+        let (width, height) = (75, 61);
+        let mut pixels = Vec::new();
+        for y in 9..9 + height {
+            pixels.extend_from_slice(&image.row(y)[5 * 3..(5 + width as usize) * 3]);
+        }
+        let mut cropped = ImageData::new(rgb(width, height), pixels).unwrap();
+        // Scribble over a third of it.
+        for byte in &mut cropped.as_bytes_mut()[..width as usize * 3 * 20] {
+            *byte = byte.wrapping_mul(7);
+        }
+        let detection = watermark.verify(&testing_png(&cropped)).unwrap();
+        assert_eq!(detection.offset, (5, 9));
+        assert!(detection.strength > 0.6, "{:?}", detection);
+    }
+}
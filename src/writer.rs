@@ -0,0 +1,83 @@
+//! Writing a PNG file chunk by chunk, as the chunks are produced.
+//!
+//! [`PngWriter`] writes the signature as soon as it is created and each
+//! chunk when it is given, so a pipeline can emit output while it is still
+//! reading input, and nothing but the chunk at hand is held in memory.
+//! [`PngWriter::finish`] ends the file with `IEND`.
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+
+pub struct PngWriter<W: Write> {
+    writer: W,
+    /// Bytes written so far.
+    written: usize,
+}
+
+impl<W: Write> PngWriter<W> {
+    /// Writes the signature to `writer`.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&Png::STANDARD_HEADER)?;
+        Ok(Self {
+            writer,
+            written: Png::STANDARD_HEADER.len(),
+        })
+    }
+    /// Writes `chunk`. `IEND` is refused, since [`PngWriter::finish`]
+    /// writes it.
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> io::Result<()> {
+        if chunk.chunk_type().bytes() == *b"IEND" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "IEND is written by finish",
+            ));
+        }
+        chunk.write_into(&mut self.writer)?;
+        self.written += Chunk::OVERHEAD + chunk.data().len();
+        Ok(())
+    }
+    /// How many bytes have been written, the signature included.
+    pub fn written(&self) -> usize {
+        self.written
+    }
+    /// Writes `IEND` and flushes, giving back the writer so that a trailer
+    /// can follow.
+    pub fn finish(mut self) -> io::Result<W> {
+        let iend = ChunkType::from_str("IEND").expect("valid chunk type");
+        Chunk::new(iend, &[]).write_into(&mut self.writer)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+    }
+
+    #[test]
+    fn test_write_as_produced() {
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        assert_eq!(writer.written(), 8);
+        let chunks = [chunk("IHDR", &[0; 13]), chunk("ruSt", b"hello")];
+        for c in &chunks {
+            writer.write_chunk(c).unwrap();
+        }
+        assert_eq!(writer.written(), 8 + 25 + 17);
+        assert!(writer.write_chunk(&chunk("IEND", &[])).is_err());
+        let bytes = writer.finish().unwrap();
+
+        let mut expected = Png::from_chunks(chunks.to_vec());
+        expected.append_chunk(chunk("IEND", &[]));
+        assert_eq!(bytes, expected.as_bytes());
+        let png = Png::try_from(bytes.as_slice()).unwrap();
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"hello");
+    }
+}
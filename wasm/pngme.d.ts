@@ -0,0 +1,19 @@
+// Types of the module wasm-pack builds from this crate, for bundlers that
+// don't read the generated definitions. Every function throws an Error
+// with pngme's message when the bytes aren't a PNG file or a chunk type is
+// invalid.
+
+/** The types of the chunks in `png`, in file order. */
+export function parse(png: Uint8Array): string[];
+
+/** `png` with `message` in a new chunk of `chunkType`, before `IEND`. */
+export function encode(png: Uint8Array, chunkType: string, message: string): Uint8Array;
+
+/** The message in the first chunk of `chunkType`, or `undefined`. */
+export function decode(png: Uint8Array, chunkType: string): string | undefined;
+
+/** `png` without any chunk of `chunkType`. */
+export function strip(png: Uint8Array, chunkType: string): Uint8Array;
+
+/** Loads the WebAssembly module; call once before the functions above. */
+export default function init(module?: RequestInfo | URL | BufferSource): Promise<void>;
@@ -0,0 +1,116 @@
+//! JavaScript bindings for pngme, so web apps can hide and extract messages
+//! in PNG files without them leaving the browser.
+//!
+//! Every function takes the bytes of a PNG file as a `Uint8Array` and
+//! returns new bytes rather than changing them. Messages are stored bare,
+//! as `pngme encode` stores a plain message, so files move freely between
+//! the page and the command line; envelopes (encrypted, compressed or
+//! named payloads) need the full CLI. Errors are thrown as `Error`s with
+//! the same messages the CLI prints.
+
+use std::str::FromStr;
+
+use pngme::chunk::Chunk;
+use pngme::chunk_type::ChunkType;
+use pngme::png::Png;
+use wasm_bindgen::prelude::*;
+
+/// The types of the chunks in `png`, in file order.
+#[wasm_bindgen]
+pub fn parse(png: &[u8]) -> Result<Vec<String>, JsError> {
+    chunk_types(png).map_err(|e| JsError::new(&e))
+}
+
+/// `png` with `message` in a new chunk of `chunk_type`, before `IEND`.
+#[wasm_bindgen]
+pub fn encode(png: &[u8], chunk_type: &str, message: &str) -> Result<Vec<u8>, JsError> {
+    append(png, chunk_type, message).map_err(|e| JsError::new(&e))
+}
+
+/// The message in the first chunk of `chunk_type`, or `undefined`.
+#[wasm_bindgen]
+pub fn decode(png: &[u8], chunk_type: &str) -> Result<Option<String>, JsError> {
+    find(png, chunk_type).map_err(|e| JsError::new(&e))
+}
+
+/// `png` without any chunk of `chunk_type`.
+#[wasm_bindgen]
+pub fn strip(png: &[u8], chunk_type: &str) -> Result<Vec<u8>, JsError> {
+    remove_all(png, chunk_type).map_err(|e| JsError::new(&e))
+}
+
+// `JsError` only exists inside a JavaScript host, so the work is done by
+// these, which tests can call anywhere, failing with the message to throw.
+
+fn chunk_types(png: &[u8]) -> Result<Vec<String>, String> {
+    let png = Png::try_from(png).map_err(|e| e.to_string())?;
+    Ok(png
+        .chunks()
+        .iter()
+        .map(|chunk| chunk.chunk_type().to_string())
+        .collect())
+}
+
+fn append(png: &[u8], chunk_type: &str, message: &str) -> Result<Vec<u8>, String> {
+    let mut png = Png::try_from(png).map_err(|e| e.to_string())?;
+    let chunk_type = ChunkType::from_str(chunk_type).map_err(|e| e.to_string())?;
+    let chunk = Chunk::try_new(chunk_type, message.as_bytes()).map_err(|e| e.to_string())?;
+    png.append_chunk(chunk);
+    Ok(png.as_bytes())
+}
+
+fn find(png: &[u8], chunk_type: &str) -> Result<Option<String>, String> {
+    let chunk_type = ChunkType::from_str(chunk_type).map_err(|e| e.to_string())?;
+    let png = Png::try_from(png).map_err(|e| e.to_string())?;
+    let Some(chunk) = png.chunks().iter().find(|c| *c.chunk_type() == chunk_type) else {
+        return Ok(None);
+    };
+    chunk.data_as_string().map(Some).map_err(|e| e.to_string())
+}
+
+fn remove_all(png: &[u8], chunk_type: &str) -> Result<Vec<u8>, String> {
+    let chunk_type = ChunkType::from_str(chunk_type).map_err(|e| e.to_string())?;
+    let png = Png::try_from(png).map_err(|e| e.to_string())?;
+    let mut chunks = png.chunks().to_vec();
+    chunks.retain(|chunk| *chunk.chunk_type() != chunk_type);
+    let mut stripped = Png::from_chunks(chunks);
+    stripped.set_trailer(png.trailer().to_vec());
+    Ok(stripped.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_png() -> Vec<u8> {
+        let chunks = ["IHDR", "IDAT", "IEND"]
+            .iter()
+            .map(|t| Chunk::new(ChunkType::from_str(t).unwrap(), &[0; 4]))
+            .collect();
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let png = append(&testing_png(), "ruSt", "hidden").unwrap();
+        let png = append(&png, "ruSt", "again").unwrap();
+        assert_eq!(
+            chunk_types(&png).unwrap(),
+            ["IHDR", "IDAT", "ruSt", "ruSt", "IEND"]
+        );
+        assert_eq!(find(&png, "ruSt").unwrap().as_deref(), Some("hidden"));
+        assert_eq!(find(&png, "teSt").unwrap(), None);
+        let stripped = remove_all(&png, "ruSt").unwrap();
+        assert_eq!(stripped, testing_png());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            chunk_types(b"GIF89a").unwrap_err(),
+            pngme::png::PngError::InvalidHeader.to_string()
+        );
+        assert!(append(&testing_png(), "bad!", "x").is_err());
+        assert!(find(&testing_png(), "ru").is_err());
+    }
+}